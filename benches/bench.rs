@@ -7,10 +7,10 @@ use std::io::{empty, BufReader, Cursor, Read as _};
 
 static SCRIPT: &str = "return true";
 
-/// evaluation
+// evaluation
 
 fn lmb_evaluate(bencher: &mut Bencher) {
-    let e = EvaluationBuilder::new(SCRIPT, empty()).build();
+    let e = EvaluationBuilder::new(SCRIPT, empty()).build().unwrap();
     bencher.iter(|| e.evaluate().unwrap());
 }
 
@@ -32,17 +32,18 @@ fn mlua_sandbox_eval(bencher: &mut Bencher) {
     bencher.iter(|| vm.load(SCRIPT).eval::<bool>());
 }
 
-/// store
+// store
 
 fn lmb_no_store(bencher: &mut Bencher) {
-    let e = EvaluationBuilder::new(SCRIPT, empty()).build();
+    let e = EvaluationBuilder::new(SCRIPT, empty()).build().unwrap();
     bencher.iter(|| e.evaluate().unwrap());
 }
 
 fn lmb_default_store(bencher: &mut Bencher) {
     let e = EvaluationBuilder::new(SCRIPT, empty())
         .default_store()
-        .build();
+        .build()
+        .unwrap();
     bencher.iter(|| e.evaluate().unwrap());
 }
 
@@ -54,16 +55,19 @@ fn lmb_update(bencher: &mut Bencher) {
     "#;
     let e = EvaluationBuilder::new(script, empty())
         .default_store()
-        .build();
+        .build()
+        .unwrap();
     bencher.iter(|| e.evaluate().unwrap());
 }
 
-/// read
+// read
 
 fn lmb_read_all(bencher: &mut Bencher) {
     let input = "1";
     let script = "return io.read('*a')";
-    let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+    let e = EvaluationBuilder::new(script, input.as_bytes())
+        .build()
+        .unwrap();
     bencher.iter(|| {
         e.set_input(&b"0"[..]);
         e.evaluate().unwrap()
@@ -73,7 +77,9 @@ fn lmb_read_all(bencher: &mut Bencher) {
 fn lmb_read_line(bencher: &mut Bencher) {
     let input = "1";
     let script = "return io.read('*l')";
-    let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+    let e = EvaluationBuilder::new(script, input.as_bytes())
+        .build()
+        .unwrap();
     bencher.iter(|| {
         e.set_input(&b"0"[..]);
         e.evaluate().unwrap()
@@ -83,7 +89,9 @@ fn lmb_read_line(bencher: &mut Bencher) {
 fn lmb_read_number(bencher: &mut Bencher) {
     let input = "1";
     let script = "return io.read('*n')";
-    let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+    let e = EvaluationBuilder::new(script, input.as_bytes())
+        .build()
+        .unwrap();
     bencher.iter(|| {
         e.set_input(&b"0"[..]);
         e.evaluate().unwrap()
@@ -93,7 +101,9 @@ fn lmb_read_number(bencher: &mut Bencher) {
 fn lmb_read_unicode(bencher: &mut Bencher) {
     let input = "1";
     let script = "return require('@lmb'):read_unicode(1)";
-    let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+    let e = EvaluationBuilder::new(script, input.as_bytes())
+        .build()
+        .unwrap();
     bencher.iter(|| {
         e.set_input(&b"0"[..]);
         e.evaluate().unwrap()
@@ -0,0 +1 @@
+ALTER TABLE store ADD COLUMN key_id TEXT;
@@ -0,0 +1 @@
+ALTER TABLE store ADD COLUMN accessed_at TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE store DROP COLUMN chunks;
+DROP TABLE store_blobs;
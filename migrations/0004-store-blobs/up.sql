@@ -0,0 +1,6 @@
+CREATE TABLE store_blobs (
+  hash BLOB NOT NULL PRIMARY KEY,
+  data BLOB NOT NULL,
+  refs INTEGER NOT NULL
+) STRICT;
+ALTER TABLE store ADD COLUMN chunks BLOB;
@@ -0,0 +1,9 @@
+CREATE TABLE history (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL,
+  started_at TEXT NOT NULL,
+  duration_us INTEGER NOT NULL,
+  used_memory INTEGER NOT NULL,
+  result TEXT,
+  error TEXT
+) STRICT;
@@ -0,0 +1,4 @@
+ALTER TABLE history DROP COLUMN env;
+ALTER TABLE history DROP COLUMN state;
+ALTER TABLE history DROP COLUMN input;
+ALTER TABLE history DROP COLUMN script;
@@ -0,0 +1,4 @@
+ALTER TABLE history ADD COLUMN script TEXT;
+ALTER TABLE history ADD COLUMN input BLOB;
+ALTER TABLE history ADD COLUMN state TEXT;
+ALTER TABLE history ADD COLUMN env TEXT;
@@ -0,0 +1,25 @@
+CREATE TABLE history_old (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL,
+  started_at TEXT NOT NULL,
+  duration_us INTEGER NOT NULL,
+  used_memory INTEGER NOT NULL,
+  result TEXT,
+  error TEXT,
+  script TEXT,
+  input BLOB,
+  state TEXT,
+  env TEXT
+) STRICT;
+-- encrypted columns can't be kept in plaintext, so they are dropped
+INSERT INTO history_old (
+  id, name, started_at, duration_us, used_memory, result, error, script, input, state, env
+)
+SELECT
+  id, name, started_at, duration_us, used_memory, result, error, script,
+  CASE WHEN key_id IS NULL THEN input END,
+  CASE WHEN key_id IS NULL THEN CAST(state AS TEXT) END,
+  CASE WHEN key_id IS NULL THEN CAST(env AS TEXT) END
+FROM history;
+DROP TABLE history;
+ALTER TABLE history_old RENAME TO history;
@@ -0,0 +1,23 @@
+CREATE TABLE history_new (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL,
+  started_at TEXT NOT NULL,
+  duration_us INTEGER NOT NULL,
+  used_memory INTEGER NOT NULL,
+  result TEXT,
+  error TEXT,
+  script TEXT,
+  input BLOB,
+  state BLOB,
+  env BLOB,
+  key_id TEXT
+) STRICT;
+INSERT INTO history_new (
+  id, name, started_at, duration_us, used_memory, result, error, script, input, state, env
+)
+SELECT
+  id, name, started_at, duration_us, used_memory, result, error, script, input,
+  CAST(state AS BLOB), CAST(env AS BLOB)
+FROM history;
+DROP TABLE history;
+ALTER TABLE history_new RENAME TO history;
@@ -0,0 +1,177 @@
+use comfy_table::{presets, Table};
+use lmb::{Evaluation, Result};
+use std::{
+    fmt::Display,
+    io::Cursor,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Input of evaluations, rewound before each iteration.
+pub type BenchInput = Cursor<Vec<u8>>;
+
+/// Options of benchmark.
+#[derive(Clone, Debug)]
+pub struct BenchOptions {
+    concurrency: usize,
+    input: Vec<u8>,
+    iterations: usize,
+}
+
+/// Report of benchmark.
+#[derive(Debug)]
+pub struct BenchReport {
+    durations: Vec<Duration>,
+    elapsed: Duration,
+    errors: usize,
+    peak_memory: usize,
+}
+
+impl BenchOptions {
+    /// Create options. Concurrency is at least one.
+    pub fn new(iterations: usize, concurrency: usize, input: Vec<u8>) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            input,
+            iterations,
+        }
+    }
+}
+
+impl BenchReport {
+    /// Get the latency at the percentile, which is between 0 and 100.
+    pub fn percentile(&self, p: usize) -> Duration {
+        if self.durations.is_empty() {
+            return Duration::ZERO;
+        }
+        // nearest-rank method
+        let rank = (p * self.durations.len()).div_ceil(100).max(1);
+        self.durations[rank.min(self.durations.len()) - 1]
+    }
+
+    /// Get successful iterations per second.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let count = self.durations.len() as f64;
+        count / secs
+    }
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut table = Table::new();
+        table.load_preset(presets::NOTHING);
+        table.add_row(["iterations", &self.durations.len().to_string()]);
+        table.add_row(["errors", &self.errors.to_string()]);
+        table.add_row(["elapsed", &format!("{:?}", self.elapsed)]);
+        table.add_row(["throughput", &format!("{:.2}/s", self.throughput())]);
+        for p in [50, 95, 99] {
+            table.add_row([format!("p{p}"), format!("{:?}", self.percentile(p))]);
+        }
+        table.add_row(["peak memory", &format!("{} bytes", self.peak_memory)]);
+        write!(f, "{table}")
+    }
+}
+
+/// Evaluate the script repeatedly with the input rewound before each iteration.
+/// Each worker builds its own evaluation with `build` and reuses it across iterations.
+/// Fail if any worker fails to build the evaluation.
+pub fn bench<F>(options: &BenchOptions, build: F) -> Result<BenchReport>
+where
+    F: Fn(BenchInput) -> Result<Arc<Evaluation<BenchInput>>> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let start = Instant::now();
+    let results = thread::scope(|s| {
+        let workers = (0..options.concurrency)
+            .map(|_| {
+                s.spawn(|| {
+                    let e = build(Cursor::new(options.input.clone()))?;
+                    let mut results = vec![];
+                    while next.fetch_add(1, Ordering::Relaxed) < options.iterations {
+                        e.set_input(Cursor::new(options.input.clone()));
+                        match e.evaluate() {
+                            Ok(s) => results.push(Some((s.duration(), s.max_memory_usage()))),
+                            Err(err) => {
+                                warn!(?err, "failed to evaluate");
+                                results.push(None);
+                            }
+                        }
+                    }
+                    Ok(results)
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|w| w.join().expect("benchmark worker panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let elapsed = start.elapsed();
+
+    let results = results.into_iter().flatten().collect::<Vec<_>>();
+    let errors = results.iter().filter(|r| r.is_none()).count();
+    let (mut durations, memory): (Vec<_>, Vec<_>) = results.into_iter().flatten().unzip();
+    durations.sort_unstable();
+    Ok(BenchReport {
+        durations,
+        elapsed,
+        errors,
+        peak_memory: memory.into_iter().max().unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use lmb::EvaluationBuilder;
+    use std::time::Duration;
+
+    use super::{bench, BenchOptions};
+
+    #[test]
+    fn bench_rewinds_input() {
+        let options = BenchOptions::new(10, 4, b"1".to_vec());
+        let script = "assert(io.read('*n') == 1); return true";
+        let report = bench(&options, |input| {
+            EvaluationBuilder::new(script, input).build()
+        })
+        .unwrap();
+        assert_eq!(10, report.durations.len());
+        assert_eq!(0, report.errors);
+        assert!(report.peak_memory > 0);
+    }
+
+    #[test]
+    fn bench_errors() {
+        let options = BenchOptions::new(3, 1, vec![]);
+        let report = bench(&options, |input| {
+            EvaluationBuilder::new("error('oops')", input).build()
+        })
+        .unwrap();
+        assert!(report.durations.is_empty());
+        assert_eq!(3, report.errors);
+        assert_eq!(Duration::ZERO, report.percentile(99));
+    }
+
+    #[test]
+    fn percentile() {
+        let options = BenchOptions::new(100, 1, vec![]);
+        let mut report = bench(&options, |input| {
+            EvaluationBuilder::new("return true", input).build()
+        })
+        .unwrap();
+        report.durations = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(Duration::from_millis(50), report.percentile(50));
+        assert_eq!(Duration::from_millis(95), report.percentile(95));
+        assert_eq!(Duration::from_millis(99), report.percentile(99));
+    }
+}
@@ -0,0 +1,176 @@
+use mlua::{prelude::*, Compiler};
+use once_cell::sync::Lazy;
+
+use crate::{strip_shebang, Error, Result, SourceMap};
+
+/// Magic bytes at the beginning of precompiled scripts.
+const MAGIC: &[u8] = b"\x1bLMB";
+
+/// Version of the header, bumped when the layout of precompiled scripts changes.
+const HEADER_VERSION: u8 = 2;
+
+/// Version of Luau bytecode produced by the bundled compiler.
+/// Luau puts the version in the first byte, and zero indicates a compile error.
+static BYTECODE_VERSION: Lazy<u8> = Lazy::new(|| Compiler::new().compile("")[0]);
+
+/// Compile the script into Luau bytecode with a version header,
+/// which can be evaluated with [`crate::EvaluationBuilder::from_precompiled`].
+///
+/// ```rust
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let bytecode = compile("return 1")?;
+/// assert!(EvaluationBuilder::from_precompiled(&bytecode, std::io::empty()).is_ok());
+/// assert!(compile("return +").is_err());
+/// # Ok(())
+/// # }
+/// ```
+pub fn compile<S>(script: S) -> Result<Vec<u8>>
+where
+    S: AsRef<[u8]>,
+{
+    compile_with_source_map(script, &SourceMap::default())
+}
+
+/// Compile the script like [`compile`], and embed the source map,
+/// so errors of the precompiled script are located in the original files.
+///
+/// ```rust
+/// # use std::io::empty;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let mut map = SourceMap::new();
+/// map.add("a.lua", 1, 1, 1);
+/// let bytecode = compile_with_source_map("return nil+1", &map)?;
+/// let e = EvaluationBuilder::from_precompiled(&bytecode, empty())?.name("a.luac").build()?;
+/// let err = e.evaluate().unwrap_err();
+/// assert!(err.to_string().contains("[string \"a.lua\"]:1:"));
+/// # Ok(())
+/// # }
+/// ```
+pub fn compile_with_source_map<S>(script: S, source_map: &SourceMap) -> Result<Vec<u8>>
+where
+    S: AsRef<[u8]>,
+{
+    let compiled = Compiler::new().compile(strip_shebang(script.as_ref()));
+    if let Some((0, message)) = compiled.split_first() {
+        return Err(Error::Lua(LuaError::SyntaxError {
+            message: String::from_utf8_lossy(message).to_string(),
+            incomplete_input: false,
+        }));
+    }
+    let source_map = serde_json::to_vec(source_map)?;
+    let Ok(len) = u32::try_from(source_map.len()) else {
+        return Err(Error::InvalidBytecode(
+            "source map is too large".to_string(),
+        ));
+    };
+    let mut res = MAGIC.to_vec();
+    res.push(HEADER_VERSION);
+    res.extend(len.to_le_bytes());
+    res.extend(source_map);
+    res.extend(compiled);
+    Ok(res)
+}
+
+/// Verify the header of a precompiled script, and return the bytecode without it and the source map.
+pub(crate) fn verify_precompiled(bytes: &[u8]) -> Result<(&[u8], SourceMap)> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Err(Error::InvalidBytecode("missing magic bytes".to_string()));
+    };
+    let Some((&version, rest)) = rest.split_first() else {
+        return Err(Error::InvalidBytecode("missing header version".to_string()));
+    };
+    if version != HEADER_VERSION {
+        return Err(Error::InvalidBytecode(format!(
+            "unsupported header version {version}, expected {HEADER_VERSION}"
+        )));
+    }
+    let Some(len) = rest.get(..4).and_then(|b| <[u8; 4]>::try_from(b).ok()) else {
+        return Err(Error::InvalidBytecode("missing source map".to_string()));
+    };
+    let rest = &rest[4..];
+    let Some((source_map, bytecode)) = usize::try_from(u32::from_le_bytes(len))
+        .ok()
+        .filter(|len| *len <= rest.len())
+        .map(|len| rest.split_at(len))
+    else {
+        return Err(Error::InvalidBytecode("truncated source map".to_string()));
+    };
+    let source_map = serde_json::from_slice(source_map)
+        .map_err(|e| Error::InvalidBytecode(format!("invalid source map: {e}")))?;
+    match bytecode.first() {
+        Some(v) if *v == *BYTECODE_VERSION => Ok((bytecode, source_map)),
+        Some(v) => Err(Error::InvalidBytecode(format!(
+            "unsupported bytecode version {v}, expected {}",
+            *BYTECODE_VERSION
+        ))),
+        None => Err(Error::InvalidBytecode("missing bytecode".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::Compiler;
+    use serde_json::json;
+    use std::io::empty;
+
+    use super::{compile, compile_with_source_map, verify_precompiled, HEADER_VERSION, MAGIC};
+    use crate::{Error, EvaluationBuilder, SourceMap};
+
+    #[test]
+    fn from_precompiled() {
+        let bytecode = compile("return 1+1").unwrap();
+        let e = EvaluationBuilder::from_precompiled(&bytecode, empty())
+            .unwrap()
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(2), res.payload());
+    }
+
+    #[test]
+    fn from_precompiled_with_source_map() {
+        let mut map = SourceMap::new();
+        map.add("a.lua", 1, 2, 1);
+        let bytecode = compile_with_source_map("local a = nil\nreturn a.b", &map).unwrap();
+        let e = EvaluationBuilder::from_precompiled(&bytecode, empty())
+            .unwrap()
+            .name("a.luac")
+            .build()
+            .unwrap();
+        assert_eq!(Some(&map), e.source_map());
+        let err = e.evaluate().unwrap_err();
+        let report = err.report(&e);
+        assert_eq!("a.lua", report.file);
+        assert_eq!(Some(2), report.line);
+        assert_eq!(None, report.span);
+        assert!(err.to_string().contains("[string \"a.lua\"]:2:"), "{err}");
+    }
+
+    #[test]
+    fn invalid_header() {
+        assert!(matches!(
+            verify_precompiled(b"return 1"),
+            Err(Error::InvalidBytecode(_))
+        ));
+
+        let mut bytecode = compile("return 1").unwrap();
+        bytecode[MAGIC.len()] = HEADER_VERSION + 1;
+        assert!(matches!(
+            verify_precompiled(&bytecode),
+            Err(Error::InvalidBytecode(_))
+        ));
+
+        let mut bytecode = compile("return 1").unwrap();
+        // the bytecode follows the header
+        let offset = bytecode.len() - Compiler::new().compile("return 1").len();
+        bytecode[offset] = u8::MAX;
+        assert!(matches!(
+            verify_precompiled(&bytecode),
+            Err(Error::InvalidBytecode(_))
+        ));
+    }
+}
@@ -0,0 +1,109 @@
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+
+use crate::{Result, StoreBackend};
+
+/// Prefix of store keys holding cached responses, under [`crate::RESERVED_KEY_PREFIX`].
+pub const CACHE_KEY_PREFIX: &str = "__lmb:cache:";
+
+/// Key of a cached response, made of the path, method, query and hash of the body.
+/// Paths come first so responses of a path can be invalidated together.
+/// Spaces in paths are percent-encoded since they separate parts of the key.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let key = cache_key("GET", "/users", Some("page=1"), b"");
+/// assert!(key.starts_with("__lmb:cache:/users GET page=1 "));
+/// ```
+pub fn cache_key(method: &str, path: &str, query: Option<&str>, body: &[u8]) -> String {
+    let hash = Sha256::digest(body)
+        .iter()
+        .fold(String::new(), |mut output, b| {
+            let _ = write!(output, "{b:02x}");
+            output
+        });
+    format!(
+        "{CACHE_KEY_PREFIX}{} {method} {} {hash}",
+        encode_path(path),
+        query.unwrap_or_default()
+    )
+}
+
+fn encode_path(path: &str) -> String {
+    path.replace('%', "%25").replace(' ', "%20")
+}
+
+/// Delete cached responses of the path, or all cached responses if the path is omitted.
+/// Return number of deleted responses.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let store = MemoryStore::default();
+/// store.put(&cache_key("GET", "/a", None, b""), &true.into())?;
+/// store.put(&cache_key("GET", "/b", None, b""), &true.into())?;
+/// assert_eq!(1, invalidate_cache(&store, Some("/a"))?);
+/// assert_eq!(1, invalidate_cache(&store, None)?);
+/// # Ok(())
+/// # }
+/// ```
+pub fn invalidate_cache(store: &dyn StoreBackend, path: Option<&str>) -> Result<usize> {
+    let prefix = match path {
+        Some(path) => format!("{CACHE_KEY_PREFIX}{} ", encode_path(path)),
+        None => CACHE_KEY_PREFIX.to_string(),
+    };
+    let mut deleted = 0;
+    for metadata in store.list()? {
+        if metadata.name().starts_with(&prefix) {
+            deleted += store.delete(metadata.name())?;
+        }
+    }
+    Ok(deleted)
+}
+
+/// Delete values of which names start with the prefix and `expires_at` in UNIX timestamp
+/// is not later than now, e.g. cached responses. Values without `expires_at` are kept.
+/// Return number of deleted values.
+///
+/// ```rust
+/// use lmb::*;
+/// use serde_json::json;
+///
+/// # fn main() -> Result<()> {
+/// let store = MemoryStore::default();
+/// store.put("__lmb:cache:a", &json!({ "expires_at": 1 }))?;
+/// store.put("__lmb:cache:b", &json!({ "expires_at": 3 }))?;
+/// store.put("__lmb:cache:c", &json!({}))?;
+/// assert_eq!(1, expire_values(&store, CACHE_KEY_PREFIX, 2)?);
+/// assert_eq!(2, store.list()?.len());
+/// # Ok(())
+/// # }
+/// ```
+pub fn expire_values(store: &dyn StoreBackend, prefix: &str, now: i64) -> Result<usize> {
+    store.expire(prefix, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cache_key, invalidate_cache, MemoryStore, StoreBackend};
+
+    #[test]
+    fn invalidate_path_with_spaces() {
+        let store = MemoryStore::default();
+        store
+            .put(&cache_key("GET", "/a", None, b""), &true.into())
+            .unwrap();
+        store
+            .put(&cache_key("GET", "/a b", None, b""), &true.into())
+            .unwrap();
+        store
+            .put(&cache_key("GET", "/a%20b", None, b""), &true.into())
+            .unwrap();
+        assert_eq!(1, invalidate_cache(&store, Some("/a")).unwrap());
+        assert_eq!(1, invalidate_cache(&store, Some("/a b")).unwrap());
+        assert_eq!(1, invalidate_cache(&store, Some("/a%20b")).unwrap());
+        assert!(store.list().unwrap().is_empty());
+    }
+}
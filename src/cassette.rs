@@ -0,0 +1,272 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::Result;
+
+/// Request sent by `@lmb/http`. Headers are not recorded since they may contain credentials.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CassetteRequest {
+    /// Method
+    pub method: String,
+    /// URL
+    pub url: String,
+    /// Body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+/// Response received by `@lmb/http`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CassetteResponse {
+    /// Status code
+    pub status: u16,
+    /// Headers, whose names are in lowercase
+    #[serde(default)]
+    pub headers: BTreeMap<String, Vec<String>>,
+    /// Body, encoded in base64 if the encoding is set
+    #[serde(default)]
+    pub body: String,
+    /// Encoding of the body, which is set if the body is not valid UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<CassetteBodyEncoding>,
+}
+
+/// Encoding of the body of a recorded response.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteBodyEncoding {
+    /// Base64 with padding
+    Base64,
+}
+
+impl CassetteResponse {
+    /// Create a response. The body is encoded in base64 if it is not valid UTF-8.
+    pub fn new(status: u16, headers: BTreeMap<String, Vec<String>>, body: Vec<u8>) -> Self {
+        let (body, encoding) = match String::from_utf8(body) {
+            Ok(body) => (body, None),
+            Err(err) => (
+                STANDARD.encode(err.into_bytes()),
+                Some(CassetteBodyEncoding::Base64),
+            ),
+        };
+        Self {
+            status,
+            headers,
+            body,
+            encoding,
+        }
+    }
+
+    /// Decode the body into bytes.
+    pub fn decode_body(&self) -> Result<Vec<u8>> {
+        match self.encoding {
+            Some(CassetteBodyEncoding::Base64) => Ok(STANDARD.decode(&self.body)?),
+            None => Ok(self.body.clone().into_bytes()),
+        }
+    }
+}
+
+/// Request and the response to it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Interaction {
+    /// Request
+    pub request: CassetteRequest,
+    /// Response
+    pub response: CassetteResponse,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+/// Whether interactions are recorded to or replayed from the cassette.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Send requests and record interactions
+    Record,
+    /// Replay recorded interactions without sending requests
+    Replay,
+}
+
+#[derive(Debug)]
+struct Tape {
+    interactions: Vec<Interaction>,
+    played: Vec<bool>,
+}
+
+/// Cassette in YAML, where HTTP interactions of `@lmb/http` are recorded and replayed later,
+/// so scripts can be tested without network access.
+///
+/// In [`CassetteMode::Record`], requests are sent and the cassette file is rewritten after
+/// every interaction. In [`CassetteMode::Replay`], each request is answered with the first
+/// interaction not yet replayed with the same method and URL, and fails if there is none.
+///
+/// ```yaml
+/// interactions:
+///   - request:
+///       method: GET
+///       url: https://example.com/
+///     response:
+///       status: 200
+///       headers:
+///         content-type: [text/plain]
+///       body: ok
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    tape: Arc<Mutex<Tape>>,
+}
+
+impl Cassette {
+    /// Create an empty cassette to record interactions to the path.
+    pub fn record<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            mode: CassetteMode::Record,
+            path: path.as_ref().to_path_buf(),
+            tape: Arc::new(Mutex::new(Tape {
+                interactions: vec![],
+                played: vec![],
+            })),
+        }
+    }
+
+    /// Load the cassette from the path to replay interactions.
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = fs::read_to_string(path.as_ref())?;
+        let file: CassetteFile = serde_yaml::from_str(&content)?;
+        let played = vec![false; file.interactions.len()];
+        Ok(Self {
+            mode: CassetteMode::Replay,
+            path: path.as_ref().to_path_buf(),
+            tape: Arc::new(Mutex::new(Tape {
+                interactions: file.interactions,
+                played,
+            })),
+        })
+    }
+
+    /// Get interactions recorded or loaded.
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.tape.lock().interactions.clone()
+    }
+
+    /// Get mode.
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// Get path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append the interaction and save the cassette.
+    pub fn push(&self, interaction: Interaction) -> Result<()> {
+        let mut tape = self.tape.lock();
+        tape.interactions.push(interaction);
+        tape.played.push(true);
+        let file = CassetteFile {
+            interactions: tape.interactions.clone(),
+        };
+        fs::write(&self.path, serde_yaml::to_string(&file)?)?;
+        Ok(())
+    }
+
+    /// Take the response of the first interaction not yet replayed with the method and URL.
+    pub fn take(&self, method: &str, url: &str) -> Option<CassetteResponse> {
+        let mut tape = self.tape.lock();
+        let Tape {
+            interactions,
+            played,
+        } = &mut *tape;
+        let (i, interaction) = interactions.iter().enumerate().find(|(i, it)| {
+            !played[*i] && it.request.url == url && it.request.method.eq_ignore_ascii_case(method)
+        })?;
+        played[i] = true;
+        Some(interaction.response.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{
+        Cassette, CassetteBodyEncoding, CassetteMode, CassetteRequest, CassetteResponse,
+        Interaction,
+    };
+
+    fn interaction(url: &str, body: &str) -> Interaction {
+        Interaction {
+            request: CassetteRequest {
+                method: "GET".into(),
+                url: url.into(),
+                body: None,
+            },
+            response: CassetteResponse::new(200, BTreeMap::new(), body.into()),
+        }
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.yaml");
+        let cassette = Cassette::record(&path);
+        cassette.push(interaction("https://a/", "1")).unwrap();
+        cassette.push(interaction("https://a/", "2")).unwrap();
+        cassette.push(interaction("https://b/", "3")).unwrap();
+
+        let cassette = Cassette::replay(&path).unwrap();
+        assert_eq!(CassetteMode::Replay, cassette.mode());
+        assert_eq!(3, cassette.interactions().len());
+        let take = |url| cassette.take("get", url).map(|r| r.body);
+        assert_eq!(Some("3".to_string()), take("https://b/"));
+        assert_eq!(Some("1".to_string()), take("https://a/"));
+        assert_eq!(Some("2".to_string()), take("https://a/"));
+        assert_eq!(None, take("https://a/"));
+        assert!(cassette.take("POST", "https://c/").is_none());
+    }
+
+    #[test]
+    fn binary_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.yaml");
+        let body = vec![0xff, 0x00, 0xfe, b'a'];
+        let mut interaction = interaction("https://a/", "");
+        interaction.response = CassetteResponse::new(200, BTreeMap::new(), body.clone());
+        assert_eq!(
+            Some(CassetteBodyEncoding::Base64),
+            interaction.response.encoding
+        );
+        Cassette::record(&path).push(interaction).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("encoding: base64"));
+
+        let cassette = Cassette::replay(&path).unwrap();
+        let response = cassette.take("GET", "https://a/").unwrap();
+        assert_eq!(body, response.decode_body().unwrap());
+
+        let text = CassetteResponse::new(200, BTreeMap::new(), b"ok".to_vec());
+        assert_eq!(None, text.encoding);
+        assert_eq!(b"ok".to_vec(), text.decode_body().unwrap());
+    }
+
+    #[test]
+    fn replay_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.yaml");
+        std::fs::write(&path, "interactions: 1").unwrap();
+        assert!(Cassette::replay(&path).is_err());
+        assert!(Cassette::replay(dir.path().join("absent.yaml")).is_err());
+    }
+}
@@ -2,8 +2,11 @@ use ariadne::{CharSet, ColorGenerator, Config, Label, Report, ReportKind, Source
 use std::{
     fmt::Display,
     io::{Error as IoError, Write},
+    ops::Range,
 };
 
+use crate::{typing::check_types, ErrorReport, TypeError};
+
 /// Container for the script used for syntax checking.
 #[derive(Debug)]
 pub struct LuaCheck {
@@ -39,15 +42,81 @@ impl LuaCheck {
         full_moon::parse(self.script.as_ref())
     }
 
+    /// Check the script against type definitions of modules provided by lmb,
+    /// see [`crate::TYPE_DEFINITIONS`]. Unknown modules, unknown members,
+    /// and wrong numbers of arguments are reported.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the script contains syntax errors.
+    ///
+    /// ```rust
+    /// use lmb::LuaCheck;
+    ///
+    /// let check = LuaCheck::new("", "return require('@lmb'):gett('a')");
+    /// let errors = check.check_types().unwrap();
+    /// assert_eq!("gett is not a member of Lmb", errors[0].message());
+    /// ```
+    pub fn check_types(&self) -> Result<Vec<TypeError>, full_moon::Error> {
+        Ok(check_types(&self.check()?))
+    }
+
+    /// Render a type error to a writer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an [`std::io::Error`] if there is an issue writing the error to the provided writer.
+    pub fn write_type_error<W>(&self, f: W, err: &TypeError, no_color: bool) -> Result<(), IoError>
+    where
+        W: Write,
+    {
+        self.write_report(f, err.message(), err.span(), no_color)
+    }
+
     /// Render an error from [`full_moon`] to a writer.
     ///
     /// # Errors
     ///
     /// This function will return an [`std::io::Error`] if there is an issue writing the error to the provided writer.
-    pub fn write_error<W>(
+    pub fn write_error<W>(&self, f: W, err: full_moon::Error, no_color: bool) -> Result<(), IoError>
+    where
+        W: Write,
+    {
+        let Some((message, span)) = locate_error(&err) else {
+            return Ok(());
+        };
+        self.write_report(f, &message, span, no_color)
+    }
+
+    /// Build the report of an error from [`full_moon`].
+    ///
+    /// ```rust
+    /// use lmb::LuaCheck;
+    ///
+    /// let check = LuaCheck::new("a.lua", "return !true");
+    /// let report = check.report(&check.check().unwrap_err());
+    /// assert_eq!("unexpected character !", report.message);
+    /// assert_eq!(Some(8), report.column);
+    /// ```
+    pub fn report(&self, err: &full_moon::Error) -> ErrorReport {
+        match locate_error(err) {
+            Some((message, span)) => {
+                ErrorReport::new(&self.name, &self.script, message, Some(span))
+            }
+            None => ErrorReport::new(&self.name, &self.script, err.to_string(), None),
+        }
+    }
+
+    /// Build the report of a type error.
+    pub fn type_error_report(&self, err: &TypeError) -> ErrorReport {
+        ErrorReport::new(&self.name, &self.script, err.message(), Some(err.span()))
+    }
+
+    fn write_report<W>(
         &self,
         mut f: W,
-        err: full_moon::Error,
+        message: &str,
+        span: Range<usize>,
         no_color: bool,
     ) -> Result<(), IoError>
     where
@@ -56,28 +125,7 @@ impl LuaCheck {
         let mut colors = ColorGenerator::new();
         let color = colors.next();
         let name = &self.name;
-
-        let (message, start, end) = match err {
-            full_moon::Error::AstError(full_moon::ast::AstError::UnexpectedToken {
-                token,
-                additional,
-            }) => (
-                additional
-                    .as_ref()
-                    .map_or_else(String::new, |s| s.to_string()),
-                token.start_position().bytes(),
-                token.end_position().bytes(),
-            ),
-            full_moon::Error::AstError(_) => return Ok(()),
-            full_moon::Error::TokenizerError(e) => (
-                e.error().to_string(),
-                e.position().bytes(),
-                e.position().bytes() + 1,
-            ),
-        };
-
-        let span = start..end;
-        Report::build(ReportKind::Error, name, start)
+        Report::build(ReportKind::Error, name, span.start)
             .with_config(
                 Config::default()
                     .with_char_set(CharSet::Ascii)
@@ -87,15 +135,34 @@ impl LuaCheck {
             .with_label(
                 Label::new((name, span))
                     .with_color(color)
-                    .with_message(&message),
+                    .with_message(message),
             )
-            .with_message(&message)
+            .with_message(message)
             .finish()
             .write((name, Source::from(&self.script)), &mut f)?;
         Ok(())
     }
 }
 
+fn locate_error(err: &full_moon::Error) -> Option<(String, Range<usize>)> {
+    match err {
+        full_moon::Error::AstError(full_moon::ast::AstError::UnexpectedToken {
+            token,
+            additional,
+        }) => Some((
+            additional
+                .as_ref()
+                .map_or_else(String::new, |s| s.to_string()),
+            token.start_position().bytes()..token.end_position().bytes(),
+        )),
+        full_moon::Error::AstError(_) => None,
+        full_moon::Error::TokenizerError(e) => Some((
+            e.error().to_string(),
+            e.position().bytes()..e.position().bytes() + 1,
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::LuaCheck;
@@ -114,6 +181,16 @@ mod tests {
         assert!(check.check().is_ok());
     }
 
+    #[test]
+    fn type_error_report() {
+        let check = LuaCheck::new("a.lua", "local m = require('@lmb')\nreturn m:gett('a')");
+        let errors = check.check_types().unwrap();
+        let report = check.type_error_report(&errors[0]);
+        assert_eq!(Some(2), report.line);
+        assert_eq!(Some(10), report.column);
+        assert_eq!("gett is not a member of Lmb", report.message);
+    }
+
     #[test]
     fn syntax_error() {
         let script = "ret true";
@@ -122,4 +199,16 @@ mod tests {
         let mut buf = Vec::new();
         check.write_error(&mut buf, err, true).unwrap();
     }
+
+    #[test]
+    fn type_error() {
+        let script = "local m = require('@lmb')\nreturn m:get()";
+        let check = LuaCheck::new("", script);
+        let errors = check.check_types().unwrap();
+        assert_eq!(1, errors.len());
+        let mut buf = Vec::new();
+        check.write_type_error(&mut buf, &errors[0], true).unwrap();
+        let rendered = String::from_utf8(buf).unwrap();
+        assert!(rendered.contains("get expects 1 argument(s) but got 0"));
+    }
 }
@@ -0,0 +1,381 @@
+use clap::{Arg, ArgAction, Command, ValueEnum};
+use std::io::{self, Write};
+
+/// Shell to generate completions for.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Shell {
+    /// Bash
+    Bash,
+    /// Fish
+    Fish,
+    /// Zsh
+    Zsh,
+}
+
+/// Command with the subcommands leading to it, e.g. `store` and `get` of `store get`.
+struct Node<'a> {
+    command: &'a Command,
+    path: Vec<&'a Command>,
+}
+
+impl<'a> Node<'a> {
+    fn id(&self, bin: &str) -> String {
+        let mut id = bin.to_string();
+        for command in &self.path {
+            id.push_str("__");
+            id.push_str(&command.get_name().replace('-', "_"));
+        }
+        id
+    }
+
+    fn names(&self) -> Vec<&'a str> {
+        self.path.iter().map(|c| c.get_name()).collect()
+    }
+}
+
+fn collect<'a>(command: &'a Command, path: Vec<&'a Command>, nodes: &mut Vec<Node<'a>>) {
+    nodes.push(Node {
+        command,
+        path: path.clone(),
+    });
+    for sub in visible_subcommands(command) {
+        let mut path = path.clone();
+        path.push(sub);
+        collect(sub, path, nodes);
+    }
+}
+
+/// Name and aliases of the command.
+fn all_names(command: &Command) -> impl Iterator<Item = &str> {
+    std::iter::once(command.get_name()).chain(command.get_all_aliases())
+}
+
+fn visible_subcommands(command: &Command) -> impl Iterator<Item = &Command> {
+    command.get_subcommands().filter(|c| !c.is_hide_set())
+}
+
+fn visible_options(command: &Command) -> impl Iterator<Item = &Arg> {
+    command
+        .get_arguments()
+        .filter(|a| !a.is_hide_set() && !a.is_positional())
+}
+
+/// First line of the help of an argument, with the environment variable appended.
+fn describe_arg(arg: &Arg) -> String {
+    let help = arg
+        .get_help()
+        .map(|h| h.to_string())
+        .unwrap_or_default()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    match arg.get_env() {
+        Some(env) => format!("{help} [env: {}]", env.to_string_lossy()),
+        None => help,
+    }
+}
+
+fn describe_command(command: &Command) -> String {
+    command
+        .get_about()
+        .map(|h| h.to_string())
+        .unwrap_or_default()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| v.get_name().to_string())
+        .collect()
+}
+
+/// Write the completion script of the command for the shell.
+pub fn write_completions<W>(command: &mut Command, shell: Shell, w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    command.build();
+    let bin = command.get_name().to_string();
+    let mut nodes = Vec::new();
+    collect(command, vec![], &mut nodes);
+    nodes.sort_by(|a, b| a.names().cmp(&b.names()));
+    match shell {
+        Shell::Bash => write_bash(&bin, &nodes, w),
+        Shell::Fish => write_fish(&bin, &nodes, w),
+        Shell::Zsh => write_zsh(&bin, &nodes, w),
+    }
+}
+
+/// Write `case` arms which walk the subcommands typed so far into the `cmd` variable.
+fn write_transitions<W>(bin: &str, nodes: &[Node<'_>], w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    for node in nodes {
+        let id = node.id(bin);
+        for sub in visible_subcommands(node.command) {
+            let mut path = node.path.clone();
+            path.push(sub);
+            let target = Node { command: sub, path };
+            let patterns = all_names(sub)
+                .map(|name| format!("{id},{name}"))
+                .collect::<Vec<_>>()
+                .join("|");
+            writeln!(w, "            {patterns}) cmd=\"{}\" ;;", target.id(bin))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_bash<W>(bin: &str, nodes: &[Node<'_>], w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    let func = format!("_{}", bin.replace('-', "_"));
+    writeln!(w, "{func}() {{")?;
+    writeln!(w, "    local cur prev cmd opts i")?;
+    writeln!(w, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"")?;
+    writeln!(w, "    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+    writeln!(w, "    cmd=\"{bin}\"")?;
+    writeln!(w, "    for ((i = 1; i < COMP_CWORD; i++)); do")?;
+    writeln!(w, "        case \"${{cmd}},${{COMP_WORDS[i]}}\" in")?;
+    write_transitions(bin, nodes, w)?;
+    writeln!(w, "        esac")?;
+    writeln!(w, "    done")?;
+    writeln!(w, "    case \"${{cmd}}\" in")?;
+    for node in nodes {
+        let mut words = vec![];
+        let mut values = vec![];
+        for arg in visible_options(node.command) {
+            let mut flags = vec![];
+            if let Some(long) = arg.get_long() {
+                flags.push(format!("--{long}"));
+            }
+            if let Some(short) = arg.get_short() {
+                flags.push(format!("-{short}"));
+            }
+            let choices = possible_values(arg);
+            if takes_value(arg) && !choices.is_empty() {
+                values.push((flags.join("|"), choices.join(" ")));
+            }
+            words.extend(flags);
+        }
+        for sub in visible_subcommands(node.command) {
+            words.push(sub.get_name().to_string());
+        }
+        for arg in node.command.get_positionals() {
+            for choice in possible_values(arg) {
+                words.push(choice);
+            }
+        }
+        writeln!(w, "        {})", node.id(bin))?;
+        if !values.is_empty() {
+            writeln!(w, "            case \"${{prev}}\" in")?;
+            for (flags, choices) in values {
+                writeln!(w, "                {flags})")?;
+                writeln!(
+                    w,
+                    "                    COMPREPLY=($(compgen -W \"{choices}\" -- \"${{cur}}\"))"
+                )?;
+                writeln!(w, "                    return 0")?;
+                writeln!(w, "                    ;;")?;
+            }
+            writeln!(w, "            esac")?;
+        }
+        writeln!(w, "            opts=\"{}\"", words.join(" "))?;
+        writeln!(w, "            ;;")?;
+    }
+    writeln!(w, "    esac")?;
+    writeln!(
+        w,
+        "    COMPREPLY=($(compgen -W \"${{opts}}\" -- \"${{cur}}\"))"
+    )?;
+    writeln!(w, "}}")?;
+    writeln!(w)?;
+    writeln!(w, "complete -F {func} -o bashdefault -o default {bin}")?;
+    Ok(())
+}
+
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn write_fish<W>(bin: &str, nodes: &[Node<'_>], w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    for node in nodes {
+        let mut conditions = vec![];
+        if node.path.is_empty() {
+            conditions.push("__fish_use_subcommand".to_string());
+        }
+        for command in &node.path {
+            conditions.push(format!(
+                "__fish_seen_subcommand_from {}",
+                all_names(command).collect::<Vec<_>>().join(" ")
+            ));
+        }
+        let subcommands = visible_subcommands(node.command)
+            .flat_map(all_names)
+            .collect::<Vec<_>>();
+        if !node.path.is_empty() && !subcommands.is_empty() {
+            conditions.push(format!(
+                "not __fish_seen_subcommand_from {}",
+                subcommands.join(" ")
+            ));
+        }
+        let condition = fish_quote(&conditions.join("; and "));
+        for arg in visible_options(node.command) {
+            let mut line = format!("complete -c {bin} -n {condition}");
+            if let Some(short) = arg.get_short() {
+                line.push_str(&format!(" -s {short}"));
+            }
+            if let Some(long) = arg.get_long() {
+                line.push_str(&format!(" -l {long}"));
+            }
+            if takes_value(arg) {
+                line.push_str(" -r");
+                let choices = possible_values(arg);
+                if !choices.is_empty() {
+                    line.push_str(&format!(" -f -a {}", fish_quote(&choices.join(" "))));
+                }
+            }
+            let help = describe_arg(arg);
+            if !help.is_empty() {
+                line.push_str(&format!(" -d {}", fish_quote(&help)));
+            }
+            writeln!(w, "{line}")?;
+        }
+        for sub in visible_subcommands(node.command) {
+            let mut line = format!(
+                "complete -c {bin} -n {condition} -f -a {}",
+                fish_quote(sub.get_name())
+            );
+            let about = describe_command(sub);
+            if !about.is_empty() {
+                line.push_str(&format!(" -d {}", fish_quote(&about)));
+            }
+            writeln!(w, "{line}")?;
+        }
+    }
+    Ok(())
+}
+
+fn zsh_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn write_zsh<W>(bin: &str, nodes: &[Node<'_>], w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    let func = format!("_{}", bin.replace('-', "_"));
+    writeln!(w, "#compdef {bin}")?;
+    writeln!(w)?;
+    writeln!(w, "{func}() {{")?;
+    writeln!(w, "    local cmd=\"{bin}\" i")?;
+    writeln!(w, "    local -a commands")?;
+    writeln!(w, "    for ((i = 2; i < CURRENT; i++)); do")?;
+    writeln!(w, "        case \"${{cmd}},${{words[i]}}\" in")?;
+    write_transitions(bin, nodes, w)?;
+    writeln!(w, "        esac")?;
+    writeln!(w, "    done")?;
+    writeln!(w, "    case \"${{cmd}}\" in")?;
+    for node in nodes {
+        writeln!(w, "        {})", node.id(bin))?;
+        let subcommands = visible_subcommands(node.command).collect::<Vec<_>>();
+        if !subcommands.is_empty() {
+            writeln!(w, "            commands=(")?;
+            for sub in subcommands {
+                writeln!(
+                    w,
+                    "                '{}:{}'",
+                    sub.get_name(),
+                    zsh_escape(&describe_command(sub))
+                )?;
+            }
+            writeln!(w, "            )")?;
+            writeln!(w, "            _describe -t commands command commands")?;
+        }
+        writeln!(w, "            _arguments -s \\")?;
+        for arg in visible_options(node.command) {
+            let help = zsh_escape(&describe_arg(arg));
+            let value = if takes_value(arg) {
+                let choices = possible_values(arg);
+                if choices.is_empty() {
+                    ":value:".to_string()
+                } else {
+                    format!(":value:({})", choices.join(" "))
+                }
+            } else {
+                String::new()
+            };
+            let repeat = if matches!(arg.get_action(), ArgAction::Append) {
+                "*"
+            } else {
+                ""
+            };
+            if let Some(long) = arg.get_long() {
+                writeln!(w, "                '{repeat}--{long}[{help}]{value}' \\")?;
+            }
+            if let Some(short) = arg.get_short() {
+                writeln!(w, "                '{repeat}-{short}[{help}]{value}' \\")?;
+            }
+        }
+        writeln!(w, "                '*: :_files'")?;
+        writeln!(w, "            ;;")?;
+    }
+    writeln!(w, "    esac")?;
+    writeln!(w, "}}")?;
+    writeln!(w)?;
+    writeln!(w, "{func} \"$@\"")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use test_case::test_case;
+
+    use super::{write_completions, Shell};
+    use crate::Cli;
+
+    #[test_case(Shell::Bash, "complete -F _lmb")]
+    #[test_case(Shell::Fish, "-l allow-env -r -d 'Environment variable")]
+    #[test_case(Shell::Zsh, "#compdef lmb")]
+    fn completions(shell: Shell, expected: &str) {
+        let mut buf = Vec::new();
+        write_completions(&mut Cli::command(), shell, &mut buf).unwrap();
+        let script = String::from_utf8(buf).unwrap();
+        assert!(script.contains(expected), "{script}");
+        assert!(script.contains("store"));
+    }
+
+    #[test]
+    fn env_and_aliases() {
+        let mut buf = Vec::new();
+        write_completions(&mut Cli::command(), Shell::Fish, &mut buf).unwrap();
+        let script = String::from_utf8(buf).unwrap();
+        assert!(script.contains("[env: LMB_ALLOW_ENV]"));
+        assert!(script.contains("-l output -r -f -a 'raw json pretty'"));
+
+        let mut buf = Vec::new();
+        write_completions(&mut Cli::command(), Shell::Bash, &mut buf).unwrap();
+        let script = String::from_utf8(buf).unwrap();
+        assert!(script.contains("lmb,evaluate|lmb,eval) cmd=\"lmb__evaluate\" ;;"));
+    }
+}
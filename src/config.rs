@@ -0,0 +1,458 @@
+use anyhow::bail;
+use clap::{Arg, Command};
+use lmb::{
+    EnvPermissions, HttpLimits, HttpTimeouts, HttpTls, NetPermissions, Permissions, RunPermissions,
+    RESERVED_KEY_PREFIX,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{collections::HashSet, fs, path::Path, path::PathBuf, time::Duration};
+
+/// Options of serving which are reloaded from the config file on SIGHUP.
+/// Options in the table of `serve` take precedence over those at the top level.
+///
+/// ```toml
+/// allow_net = ["example.com"]
+/// http_max_requests = 10
+/// store_path = "db.sqlite3"
+/// timeout = 30
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    pub allow_env: Vec<String>,
+    pub allow_insecure_tls: bool,
+    pub allow_net: Vec<String>,
+    pub allow_run: Vec<String>,
+    pub disable_module: Vec<String>,
+    pub env_file: Option<PathBuf>,
+    pub http_ca_bundle: Option<PathBuf>,
+    pub http_client_cert: Option<PathBuf>,
+    pub http_client_key: Option<PathBuf>,
+    pub http_connect_timeout: Option<u64>,
+    pub http_max_bytes: Option<u64>,
+    pub http_max_concurrency: Option<usize>,
+    pub http_max_requests: Option<u64>,
+    pub http_read_timeout: Option<u64>,
+    pub http_timeout: Option<u64>,
+    pub store_path: Option<PathBuf>,
+    pub timeout: Option<u64>,
+}
+
+impl Config {
+    /// Load options of serving from the config file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut table = load_table(path)?;
+        let serve = match table.remove("serve") {
+            Some(Value::Object(serve)) => serve,
+            _ => Map::new(),
+        };
+        let table = table
+            .into_iter()
+            .chain(serve)
+            .map(|(k, v)| (arg_id(&k), v))
+            .collect();
+        Ok(serde_json::from_value(Value::Object(table))?)
+    }
+
+    /// Fill options which are unset with those of the other config,
+    /// so options of this config take precedence.
+    pub fn or(self, other: Config) -> Self {
+        let or_vec = |a: Vec<String>, b: Vec<String>| if a.is_empty() { b } else { a };
+        Self {
+            allow_env: or_vec(self.allow_env, other.allow_env),
+            allow_insecure_tls: self.allow_insecure_tls || other.allow_insecure_tls,
+            allow_net: or_vec(self.allow_net, other.allow_net),
+            allow_run: or_vec(self.allow_run, other.allow_run),
+            disable_module: or_vec(self.disable_module, other.disable_module),
+            env_file: self.env_file.or(other.env_file),
+            http_ca_bundle: self.http_ca_bundle.or(other.http_ca_bundle),
+            http_client_cert: self.http_client_cert.or(other.http_client_cert),
+            http_client_key: self.http_client_key.or(other.http_client_key),
+            http_connect_timeout: self.http_connect_timeout.or(other.http_connect_timeout),
+            http_max_bytes: self.http_max_bytes.or(other.http_max_bytes),
+            http_max_concurrency: self.http_max_concurrency.or(other.http_max_concurrency),
+            http_max_requests: self.http_max_requests.or(other.http_max_requests),
+            http_read_timeout: self.http_read_timeout.or(other.http_read_timeout),
+            http_timeout: self.http_timeout.or(other.http_timeout),
+            store_path: self.store_path.or(other.store_path),
+            timeout: self.timeout.or(other.timeout),
+        }
+    }
+
+    /// Build permissions, reading the `.env` file if any.
+    pub fn permissions(&self) -> anyhow::Result<Permissions> {
+        let mut permissions = Permissions::default();
+        if !self.allow_net.is_empty() {
+            permissions.set_net(NetPermissions::new(&self.allow_net));
+        }
+        permissions.set_run(RunPermissions::new(&self.allow_run));
+        permissions.set_disabled_modules(&self.disable_module);
+        let mut http_limits = HttpLimits::default();
+        http_limits
+            .set_max_bytes(self.http_max_bytes)
+            .set_max_concurrency(self.http_max_concurrency)
+            .set_max_requests(self.http_max_requests);
+        permissions.set_http_limits(http_limits);
+        let mut http_timeouts = HttpTimeouts::default();
+        http_timeouts
+            .set_connect(self.http_connect_timeout.map(Duration::from_millis))
+            .set_read(self.http_read_timeout.map(Duration::from_millis))
+            .set_total(self.http_timeout.map(Duration::from_millis));
+        permissions.set_http_timeouts(http_timeouts);
+        let read = |path: &Option<PathBuf>| path.as_ref().map(fs::read_to_string).transpose();
+        let mut http_tls = HttpTls::default();
+        http_tls
+            .set_allow_insecure(self.allow_insecure_tls)
+            .set_ca_bundle(read(&self.http_ca_bundle)?)
+            .set_client_cert(read(&self.http_client_cert)?)
+            .set_client_key(read(&self.http_client_key)?);
+        permissions.set_http_tls(http_tls);
+        let mut env = EnvPermissions::new(&self.allow_env);
+        if let Some(path) = &self.env_file {
+            env.load_env_file(&fs::read_to_string(path)?);
+        }
+        permissions.set_env(env);
+        Ok(permissions)
+    }
+
+    /// Get timeout.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+}
+
+/// Function served under a path prefix, with its own permissions, store namespace and timeout.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ManifestApp {
+    /// Path prefix e.g. `/hello`
+    pub prefix: String,
+    /// Script path, relative to the manifest
+    pub file: PathBuf,
+    /// Namespace of values in the store, defaults to the prefix. Colons are not allowed,
+    /// since they separate the namespace from names
+    #[serde(default)]
+    pub store_namespace: Option<String>,
+    /// Permissions, store path and timeout
+    #[serde(flatten)]
+    pub config: Config,
+    /// Script read from the file
+    #[serde(skip)]
+    pub script: String,
+}
+
+impl ManifestApp {
+    /// Get the namespace of values in the store.
+    pub fn store_namespace(&self) -> &str {
+        self.store_namespace.as_deref().unwrap_or(&self.prefix)
+    }
+}
+
+/// Manifest of functions served together, routed by path prefix.
+///
+/// ```toml
+/// [[app]]
+/// prefix = "/hello"
+/// file = "hello.lua"
+/// allow_net = ["example.com"]
+/// timeout = 5
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Manifest {
+    #[serde(default, rename = "app")]
+    pub apps: Vec<ManifestApp>,
+}
+
+impl Manifest {
+    /// Load the manifest and read scripts of functions.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut table = load_table(path)?;
+        // keys of functions can be written as arguments too e.g. allow-net
+        if let Some(Value::Array(apps)) = table.get_mut("app") {
+            for app in apps {
+                if let Value::Object(app) = app {
+                    *app = std::mem::take(app)
+                        .into_iter()
+                        .map(|(k, v)| (arg_id(&k), v))
+                        .collect();
+                }
+            }
+        }
+        let mut manifest: Self = serde_json::from_value(Value::Object(table))?;
+        if manifest.apps.is_empty() {
+            bail!("manifest should have at least one app");
+        }
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut prefixes = HashSet::new();
+        for app in &mut manifest.apps {
+            if !app.prefix.starts_with('/') {
+                bail!("prefix {} should start with a slash", app.prefix);
+            }
+            if app.prefix.len() > 1 {
+                app.prefix = app.prefix.trim_end_matches('/').to_string();
+            }
+            if !prefixes.insert(app.prefix.clone()) {
+                bail!("prefix {} is duplicated", app.prefix);
+            }
+            // namespaces with colons would overlap, e.g. names `b:c` of `a` and `c` of `a:b`
+            let namespace = app.store_namespace();
+            if namespace.contains(':') {
+                bail!("store namespace {namespace} should not contain colons");
+            }
+            if format!("{namespace}:") == RESERVED_KEY_PREFIX {
+                bail!("store namespace {namespace} is reserved");
+            }
+            app.file = dir.join(&app.file);
+            app.script = fs::read_to_string(&app.file)
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", app.file.display()))?;
+        }
+        Ok(manifest)
+    }
+}
+
+/// Load the config file in YAML if the extension is `.yaml` or `.yml`, otherwise in TOML.
+fn load_table(path: &Path) -> anyhow::Result<Map<String, Value>> {
+    let content = fs::read_to_string(path)?;
+    let is_yaml = path.extension().is_some_and(|e| e == "yaml" || e == "yml");
+    let value = if is_yaml {
+        #[cfg(not(feature = "yaml"))]
+        bail!("config files in YAML require the yaml feature");
+        #[cfg(feature = "yaml")]
+        serde_yaml::from_str(&content)?
+    } else {
+        serde_json::to_value(toml::from_str::<toml::Table>(&content)?)?
+    };
+    match value {
+        Value::Object(table) => Ok(table),
+        Value::Null => Ok(Map::new()),
+        _ => bail!("config file should be a table"),
+    }
+}
+
+// keys can be written as arguments on the command line e.g. store-path
+fn arg_id(key: &str) -> String {
+    key.replace('-', "_")
+}
+
+fn to_values(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.iter().flat_map(to_values).collect(),
+        Value::Null => vec![],
+        Value::String(s) => vec![s.clone()],
+        v => vec![v.to_string()],
+    }
+}
+
+fn has_arg(cmd: &Command, id: &str) -> bool {
+    cmd.get_arguments().any(|a| a.get_id() == id) || cmd.get_subcommands().any(|s| has_arg(s, id))
+}
+
+// values are validated even if the subcommand is not invoked
+fn validate(arg: &Arg) -> anyhow::Result<()> {
+    Command::new("lmb")
+        .arg(arg.clone().required(false))
+        .try_get_matches_from(["lmb"])?;
+    Ok(())
+}
+
+fn set_default(mut cmd: Command, id: &str, values: &[String]) -> anyhow::Result<Command> {
+    let arg = cmd.get_arguments().find(|a| a.get_id() == id).cloned();
+    if let Some(arg) = arg {
+        validate(&arg.default_values(values))?;
+        cmd = cmd.mut_arg(id, |a| a.default_values(values));
+    }
+    let names: Vec<String> = cmd
+        .get_subcommands()
+        .map(|s| s.get_name().to_string())
+        .collect();
+    for name in names {
+        let subcommand = cmd
+            .find_subcommand(&name)
+            .cloned()
+            .expect("subcommand should exist");
+        let subcommand = set_default(subcommand, id, values)?;
+        cmd = cmd.mut_subcommand(name, |_| subcommand);
+    }
+    Ok(cmd)
+}
+
+/// Apply options of the config file as default values of arguments, so arguments
+/// on the command line and environment variables take precedence over the file.
+/// An option applies to the command and any subcommand with the argument,
+/// while options in a table named after a subcommand only apply to it e.g. `[serve]`.
+pub fn apply_config(cmd: Command, path: &Path) -> anyhow::Result<Command> {
+    apply_table(cmd, &load_table(path)?)
+}
+
+fn apply_table(mut cmd: Command, table: &Map<String, Value>) -> anyhow::Result<Command> {
+    let mut subcommands = vec![];
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+        if let (Value::Object(table), Some(_)) = (value, cmd.find_subcommand(&name)) {
+            subcommands.push((name, table));
+            continue;
+        }
+        let id = arg_id(key);
+        if id == "config" || !has_arg(&cmd, &id) {
+            bail!("unknown option {key} in config file");
+        }
+        cmd = set_default(cmd, &id, &to_values(value))?;
+    }
+    // applied later, so they take precedence over options at the top level
+    for (name, table) in subcommands {
+        let subcommand = cmd
+            .find_subcommand(&name)
+            .cloned()
+            .expect("subcommand should exist");
+        let subcommand = apply_table(subcommand, table)?;
+        cmd = cmd.mut_subcommand(name, |_| subcommand);
+    }
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write as _, path::PathBuf};
+
+    use clap::{CommandFactory as _, FromArgMatches as _};
+
+    use super::{apply_config, Config};
+    use crate::{Cli, Commands};
+
+    fn parse(config: &str, args: &[&str]) -> anyhow::Result<Cli> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "{config}").unwrap();
+        let command = apply_config(Cli::command(), file.path())?;
+        Ok(Cli::from_arg_matches(&command.try_get_matches_from(args)?)?)
+    }
+
+    #[test]
+    fn load() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "allow_net = [\"example.com\"]\ntimeout = 5").unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(vec!["example.com".to_string()], config.allow_net);
+        assert_eq!(Some(5), config.timeout);
+        let permissions = config.permissions().unwrap();
+        assert!(permissions.net().is_allowed("example.com", 443));
+        assert!(!permissions.net().is_allowed("example.org", 443));
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn load_manifest() {
+        use super::Manifest;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.lua"), "return 1").unwrap();
+        let path = dir.path().join("apps.yaml");
+        let manifest =
+            "app:\n- prefix: /a/\n  file: a.lua\n  allow-net: [example.com]\n  timeout: 5\n";
+        std::fs::write(&path, manifest).unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        let app = manifest.apps.first().unwrap();
+        assert_eq!("/a", app.prefix);
+        assert_eq!("/a", app.store_namespace());
+        assert_eq!(dir.path().join("a.lua"), app.file);
+        assert_eq!("return 1", app.script);
+        assert_eq!(vec!["example.com".to_string()], app.config.allow_net);
+        assert_eq!(Some(5), app.config.timeout);
+
+        let duplicated = "app:\n- { prefix: /a, file: a.lua }\n- { prefix: /a/, file: a.lua }\n";
+        std::fs::write(&path, duplicated).unwrap();
+        let err = Manifest::load(&path).unwrap_err();
+        assert_eq!("prefix /a is duplicated", err.to_string());
+
+        std::fs::write(&path, "app:\n- { prefix: a, file: a.lua }\n").unwrap();
+        assert!(Manifest::load(&path).is_err());
+        std::fs::write(&path, "app:\n- { prefix: /b, file: b.lua }\n").unwrap();
+        assert!(Manifest::load(&path).is_err());
+
+        std::fs::write(&path, "app:\n- { prefix: /a:b, file: a.lua }\n").unwrap();
+        let err = Manifest::load(&path).unwrap_err();
+        assert_eq!(
+            "store namespace /a:b should not contain colons",
+            err.to_string()
+        );
+        let reserved = "app:\n- { prefix: /a, file: a.lua, store-namespace: __lmb }\n";
+        std::fs::write(&path, reserved).unwrap();
+        let err = Manifest::load(&path).unwrap_err();
+        assert_eq!("store namespace __lmb is reserved", err.to_string());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn load_serve_table() {
+        let mut file = tempfile::Builder::new().suffix(".yaml").tempfile().unwrap();
+        writeln!(
+            file,
+            "timeout: 5\nserve:\n  timeout: 10\n  store-path: db.sqlite3"
+        )
+        .unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(Some(10), config.timeout);
+        assert_eq!(Some(PathBuf::from("db.sqlite3")), config.store_path);
+    }
+
+    #[test]
+    fn precedence() {
+        let cli = Config {
+            allow_run: vec!["echo".to_string()],
+            store_path: Some(PathBuf::from("cli.sqlite3")),
+            ..Default::default()
+        };
+        let file = Config {
+            allow_net: vec!["example.com".to_string()],
+            allow_run: vec!["cat".to_string()],
+            store_path: Some(PathBuf::from("file.sqlite3")),
+            timeout: Some(5),
+            ..Default::default()
+        };
+        let expected = Config {
+            allow_net: vec!["example.com".to_string()],
+            allow_run: vec!["echo".to_string()],
+            store_path: Some(PathBuf::from("cli.sqlite3")),
+            timeout: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(expected, cli.or(file));
+    }
+
+    #[test]
+    fn apply() {
+        let config = r#"
+        allow-net = ["example.com", "example.org"]
+        json = true
+        timeout = 5
+
+        [serve]
+        bind = ["127.0.0.1:3000", "unix:/tmp/lmb.sock"]
+        "#;
+        let cli = parse(config, &["lmb", "serve", "--timeout", "10"]).unwrap();
+        assert_eq!(vec!["example.com", "example.org"], cli.allow_net);
+        assert!(cli.json);
+        let Some(Commands::Serve { bind, timeout, .. }) = cli.command else {
+            panic!("serve is expected");
+        };
+        assert_eq!(2, bind.len());
+        assert_eq!(Some(10), timeout);
+
+        let cli = parse(config, &["lmb", "--allow-net", "example.net", "serve"]).unwrap();
+        assert_eq!(vec!["example.net"], cli.allow_net);
+        let Some(Commands::Serve { timeout, .. }) = cli.command else {
+            panic!("serve is expected");
+        };
+        assert_eq!(Some(5), timeout);
+    }
+
+    #[test]
+    fn apply_invalid() {
+        let Err(err) = parse("unknown = 1", &["lmb", "list-themes"]) else {
+            panic!("unknown option is expected to be rejected");
+        };
+        assert_eq!("unknown option unknown in config file", err.to_string());
+        // values of subcommands which are not invoked are validated as well
+        assert!(parse("[schedule]\nmissed_runs = \"all\"", &["lmb", "list-themes"]).is_err());
+        assert!(parse("[serve]\nunknown = 1", &["lmb", "list-themes"]).is_err());
+    }
+}
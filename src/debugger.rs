@@ -0,0 +1,326 @@
+use mlua::prelude::*;
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{BufRead, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const HELP: &str = "\
+break <line>   b    set a breakpoint
+delete <line>  d    delete a breakpoint
+step           s    stop at the next line
+next           n    stop at the next line, stepping over function calls
+continue       c    run until a breakpoint
+backtrace      bt   show the stack
+list           l    show lines around the current line
+print <name>   p    print a global variable
+quit           q    stop the evaluation
+help           h    show this message";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StepMode {
+    Continue,
+    Step,
+    Next(usize),
+}
+
+struct DebuggerState {
+    breakpoints: BTreeSet<usize>,
+    input: Box<dyn BufRead + Send>,
+    mode: StepMode,
+    output: Box<dyn Write + Send>,
+    paused: Duration,
+    // line and stack depth of the last interrupt
+    previous: Option<(usize, usize)>,
+    source: Vec<String>,
+}
+
+/// Debugger, which pauses the evaluation on breakpoints and reads commands e.g. `step` from the input.
+///
+/// Luau checks for breakpoints on function calls, returns and loop iterations,
+/// so a breakpoint on a line without them is never hit.
+/// Local variables and upvalues cannot be inspected, only globals.
+///
+/// ```rust
+/// # use std::io::{empty, Cursor};
+/// # use serde_json::json;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let debugger = Debugger::new(Cursor::new("bt\ncontinue\n"), Vec::new());
+/// debugger.add_breakpoint(2);
+/// let e = EvaluationBuilder::new("local a = 1\nprint(a)\nreturn a", empty())
+///     .debugger(debugger)
+///     .build()?;
+/// assert_eq!(&json!(1), e.evaluate()?.payload());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Debugger {
+    state: Arc<Mutex<DebuggerState>>,
+}
+
+impl fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock();
+        f.debug_struct("Debugger")
+            .field("breakpoints", &state.breakpoints)
+            .field("mode", &state.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Debugger {
+    /// Create a debugger reading commands from the input and writing to the output.
+    /// It stops at the first line until breakpoints are added.
+    pub fn new<I, O>(input: I, output: O) -> Self
+    where
+        I: 'static + BufRead + Send,
+        O: 'static + Write + Send,
+    {
+        Self {
+            state: Arc::new(Mutex::new(DebuggerState {
+                breakpoints: BTreeSet::new(),
+                input: Box::new(input),
+                mode: StepMode::Step,
+                output: Box::new(output),
+                paused: Duration::ZERO,
+                previous: None,
+                source: vec![],
+            })),
+        }
+    }
+
+    /// Add a breakpoint on the line starting from 1, and run until it's hit.
+    pub fn add_breakpoint(&self, line: usize) {
+        let mut state = self.state.lock();
+        state.breakpoints.insert(line);
+        state.mode = StepMode::Continue;
+    }
+
+    /// Get breakpoints.
+    pub fn breakpoints(&self) -> Vec<usize> {
+        self.state.lock().breakpoints.iter().copied().collect()
+    }
+
+    /// Total duration paused by the debugger, which is excluded from the timeout.
+    pub fn paused(&self) -> Duration {
+        self.state.lock().paused
+    }
+
+    pub(crate) fn set_source(&self, source: &str) {
+        self.state.lock().source = source.lines().map(String::from).collect();
+    }
+
+    pub(crate) fn on_interrupt(&self, vm: &Lua) -> LuaResult<()> {
+        let Some(line) = vm
+            .inspect_stack(0)
+            .and_then(|d| usize::try_from(d.curr_line()).ok())
+        else {
+            return Ok(());
+        };
+        let depth = (0..).take_while(|l| vm.inspect_stack(*l).is_some()).count();
+        let mut state = self.state.lock();
+        let previous = state.previous.replace((line, depth));
+        // the same line may be interrupted multiple times e.g. on a call and the return
+        if previous == Some((line, depth)) {
+            return Ok(());
+        }
+        let stop = match state.mode {
+            StepMode::Step => true,
+            StepMode::Next(d) => depth <= d,
+            StepMode::Continue => false,
+        } || state.breakpoints.contains(&line);
+        if !stop {
+            return Ok(());
+        }
+        let start = Instant::now();
+        let res = state.prompt(vm, line, depth);
+        state.paused += start.elapsed();
+        res
+    }
+}
+
+impl DebuggerState {
+    fn prompt(&mut self, vm: &Lua, line: usize, depth: usize) -> LuaResult<()> {
+        let function = vm
+            .inspect_stack(0)
+            .and_then(|d| d.names().name.map(|n| format!(" in {n}")))
+            .unwrap_or_default();
+        writeln!(self.output, "stopped at line {line}{function}").into_lua_err()?;
+        self.list(line, 0)?;
+        loop {
+            write!(self.output, "(lmb) ").into_lua_err()?;
+            self.output.flush().into_lua_err()?;
+            let mut command = String::new();
+            // continue to the end when the input is closed
+            if self.input.read_line(&mut command).into_lua_err()? == 0 {
+                self.breakpoints.clear();
+                self.mode = StepMode::Continue;
+                return Ok(());
+            }
+            let mut parts = command.split_whitespace();
+            let (command, arg) = (parts.next().unwrap_or_default(), parts.next());
+            match command {
+                "b" | "break" | "d" | "delete" => {
+                    let Some(n) = arg.and_then(|a| a.parse::<usize>().ok()) else {
+                        writeln!(self.output, "line number is required").into_lua_err()?;
+                        continue;
+                    };
+                    if command.starts_with('b') {
+                        self.breakpoints.insert(n);
+                    } else {
+                        self.breakpoints.remove(&n);
+                    }
+                }
+                "s" | "step" => {
+                    self.mode = StepMode::Step;
+                    return Ok(());
+                }
+                "n" | "next" => {
+                    self.mode = StepMode::Next(depth);
+                    return Ok(());
+                }
+                "c" | "continue" => {
+                    self.mode = StepMode::Continue;
+                    return Ok(());
+                }
+                "bt" | "backtrace" => self.backtrace(vm)?,
+                "l" | "list" => self.list(line, 5)?,
+                "p" | "print" => {
+                    let Some(name) = arg else {
+                        writeln!(self.output, "name is required").into_lua_err()?;
+                        continue;
+                    };
+                    let value: LuaValue<'_> = vm.globals().get(name)?;
+                    let value =
+                        serde_json::to_string(&value).unwrap_or_else(|_| format!("{value:?}"));
+                    writeln!(self.output, "{name} = {value}").into_lua_err()?;
+                }
+                "q" | "quit" => return Err(LuaError::runtime("quit by debugger")),
+                "h" | "help" => writeln!(self.output, "{HELP}").into_lua_err()?,
+                "" => {}
+                _ => writeln!(
+                    self.output,
+                    "unknown command {command}, type help for commands"
+                )
+                .into_lua_err()?,
+            }
+        }
+    }
+
+    fn backtrace(&mut self, vm: &Lua) -> LuaResult<()> {
+        for (level, d) in (0..).map_while(|l| vm.inspect_stack(l).map(|d| (l, d))) {
+            let source = d.source().short_src.unwrap_or_default().to_string();
+            let name = d.names().name.unwrap_or("?".into()).to_string();
+            match d.curr_line() {
+                n if n > 0 => writeln!(self.output, "#{level} {source}:{n} in {name}"),
+                _ => writeln!(self.output, "#{level} {source} in {name}"),
+            }
+            .into_lua_err()?;
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, line: usize, context: usize) -> LuaResult<()> {
+        let start = line.saturating_sub(context).max(1);
+        for n in start..=line + context {
+            let Some(source) = self.source.get(n - 1) else {
+                break;
+            };
+            let marker = if n == line { '>' } else { ' ' };
+            writeln!(self.output, "{marker}{n:>4} | {source}").into_lua_err()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+    use serde_json::json;
+    use std::{
+        io::{empty, Cursor, Write},
+        sync::Arc,
+    };
+
+    use crate::{Debugger, EvaluationBuilder};
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock()).to_string()
+        }
+    }
+
+    const SCRIPT: &str = r#"a = 1
+local function f(x)
+  return x + 1
+end
+print(f(a))
+a = 2
+print(a)
+return a"#;
+
+    #[test]
+    fn breakpoint() {
+        let output = Output::default();
+        let debugger = Debugger::new(Cursor::new("p a\nbt\nc\n"), output.clone());
+        debugger.add_breakpoint(7);
+        let e = EvaluationBuilder::new(SCRIPT, empty())
+            .name("a.lua")
+            .debugger(debugger)
+            .build()
+            .unwrap();
+        assert_eq!(&json!(2), e.evaluate().unwrap().payload());
+        let text = output.text();
+        assert!(
+            text.contains("stopped at line 7\n>   7 | print(a)\n"),
+            "{text}"
+        );
+        assert!(text.contains("a = 2\n"), "{text}");
+        assert!(text.contains("#0 [string \"a.lua\"]:7 in ?"), "{text}");
+        assert_eq!(1, text.matches("stopped").count(), "{text}");
+    }
+
+    #[test]
+    fn step() {
+        let output = Output::default();
+        let debugger = Debugger::new(Cursor::new("s\nbt\nn\nc\n"), output.clone());
+        let e = EvaluationBuilder::new(SCRIPT, empty())
+            .debugger(debugger)
+            .build()
+            .unwrap();
+        assert_eq!(&json!(2), e.evaluate().unwrap().payload());
+        let text = output.text();
+        assert!(text.contains("stopped at line 3 in f\n"), "{text}");
+        assert!(text.contains("#1 [string \"\"]:5 in ?"), "{text}");
+    }
+
+    #[test]
+    fn quit() {
+        let debugger = Debugger::new(Cursor::new("q\n"), Vec::new());
+        let e = EvaluationBuilder::new(SCRIPT, empty())
+            .debugger(debugger)
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("quit by debugger"), "{err}");
+    }
+}
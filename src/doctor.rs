@@ -0,0 +1,291 @@
+use lmb::{NetPermissions, StoreOptions};
+use mlua::prelude::*;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    fs,
+    net::{TcpStream, ToSocketAddrs as _},
+    path::Path,
+    time::Duration,
+};
+
+/// Timeout of network checks.
+pub const DOCTOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Port of hosts allowed on any port, which are checked over HTTPS.
+const DEFAULT_PORT: u16 = 443;
+
+/// Status of a check.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    /// Passed
+    Ok,
+    /// Not checked, e.g. the feature is disabled
+    Skip,
+    /// Failed
+    Fail,
+}
+
+/// Result of a diagnostic check.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    name: String,
+    status: CheckStatus,
+    detail: String,
+}
+
+impl Check {
+    fn new<N, D>(name: N, status: CheckStatus, detail: D) -> Self
+    where
+        N: Display,
+        D: Display,
+    {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.to_string(),
+        }
+    }
+
+    fn from_result<N, D, E>(name: N, result: Result<D, E>) -> Self
+    where
+        N: Display,
+        D: Display,
+        E: Display,
+    {
+        match result {
+            Ok(detail) => Self::new(name, CheckStatus::Ok, detail),
+            Err(e) => Self::new(name, CheckStatus::Fail, e),
+        }
+    }
+
+    /// Name of the check.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Status of the check.
+    pub fn status(&self) -> CheckStatus {
+        self.status
+    }
+
+    /// What was found, or why the check failed.
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Skip => write!(f, "skip"),
+            Self::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// Diagnose versions, the store and the network allow-list.
+pub fn diagnose(version: &str, store: &StoreOptions, net: &NetPermissions) -> Vec<Check> {
+    let mut checks = vec![Check::new("lmb", CheckStatus::Ok, version)];
+    checks.push(Check::from_result("luau", luau_version()));
+    checks.push(Check::new("sqlite", CheckStatus::Ok, rusqlite::version()));
+    checks.push(Check::new(
+        "bindings",
+        CheckStatus::Ok,
+        bindings().join(", "),
+    ));
+    checks.push(Check::from_result("sqlite wal", check_wal()));
+    checks.push(check_store(store));
+    match net.hosts() {
+        None => checks.push(Check::new(
+            "network",
+            CheckStatus::Skip,
+            "any host is allowed, specify --allow-net to check hosts",
+        )),
+        Some(hosts) => {
+            for (host, port) in hosts {
+                let port = port.unwrap_or(DEFAULT_PORT);
+                checks.push(Check::from_result(
+                    format!("connect {host}:{port}"),
+                    check_connect(host, port),
+                ));
+                if port == DEFAULT_PORT {
+                    checks.push(check_tls(host));
+                }
+            }
+        }
+    }
+    checks
+}
+
+fn luau_version() -> LuaResult<String> {
+    Lua::new().globals().get::<_, String>("_VERSION")
+}
+
+/// Bindings enabled by features.
+fn bindings() -> Vec<&'static str> {
+    let features = [
+        ("cbor", cfg!(feature = "cbor")),
+        ("coroutine", cfg!(feature = "coroutine")),
+        ("crypto", cfg!(feature = "crypto")),
+        ("diff", cfg!(feature = "diff")),
+        ("dns", cfg!(feature = "dns")),
+        ("encoding", cfg!(feature = "encoding")),
+        ("http", cfg!(feature = "http")),
+        ("jq", cfg!(feature = "jq")),
+        ("json-path", cfg!(feature = "json-path")),
+        ("msgpack", cfg!(feature = "msgpack")),
+        ("protobuf", cfg!(feature = "protobuf")),
+        ("redis", cfg!(feature = "redis")),
+        ("toml", cfg!(feature = "toml")),
+        ("url", cfg!(feature = "url")),
+        ("yaml", cfg!(feature = "yaml")),
+    ];
+    features
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect()
+}
+
+/// Check the filesystem of temporary files supports write-ahead logging.
+fn check_wal() -> anyhow::Result<String> {
+    let dir = tempfile::tempdir()?;
+    let conn = Connection::open(dir.path().join("doctor.sqlite3"))?;
+    journal_mode(&conn)
+}
+
+fn journal_mode(conn: &Connection) -> anyhow::Result<String> {
+    let mode: String = conn.pragma_update_and_check(None, "journal_mode", "wal", |r| r.get(0))?;
+    if mode.eq_ignore_ascii_case("wal") {
+        Ok(format!("journal mode is {mode}"))
+    } else {
+        anyhow::bail!("journal mode is {mode} instead of wal")
+    }
+}
+
+fn check_store(options: &StoreOptions) -> Check {
+    if let Some(url) = options.store_url() {
+        let scheme = url.split_once("://").map_or(url, |(scheme, _)| scheme);
+        return Check::new(
+            "store",
+            CheckStatus::Skip,
+            format!("{scheme} store is not checked"),
+        );
+    }
+    let Some(path) = options.store_path() else {
+        return Check::new("store", CheckStatus::Skip, "store is in-memory");
+    };
+    Check::from_result(format!("store {}", path.display()), check_store_path(path))
+}
+
+/// Check the store is writable without creating it.
+fn check_store_path(path: &Path) -> anyhow::Result<String> {
+    if !path.exists() {
+        let dir = match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        tempfile::tempfile_in(dir)
+            .map_err(|e| anyhow::anyhow!("{} is not writable: {e}", dir.display()))?;
+        return Ok(format!(
+            "{} is writable, store will be created",
+            dir.display()
+        ));
+    }
+    if fs::metadata(path)?.permissions().readonly() {
+        anyhow::bail!("store is read-only");
+    }
+    let conn = Connection::open(path)?;
+    conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")?;
+    Ok(format!("writable, {}", journal_mode(&conn)?))
+}
+
+fn check_connect(host: &str, port: u16) -> anyhow::Result<String> {
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, DOCTOR_TIMEOUT) {
+            Ok(_) => return Ok(format!("connected to {addr}")),
+            Err(e) => last_error = Some(anyhow::anyhow!("{addr}: {e}")),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("{host} has no address")))
+}
+
+/// Check TLS roots by a handshake with the host. Any HTTP response passes.
+#[cfg(feature = "http")]
+fn check_tls(host: &str) -> Check {
+    let authority = if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    };
+    let agent = ureq::AgentBuilder::new().timeout(DOCTOR_TIMEOUT).build();
+    let result = match agent.head(&format!("https://{authority}/")).call() {
+        Ok(res) | Err(ureq::Error::Status(_, res)) => Ok(format!("HTTP {}", res.status())),
+        Err(e) => Err(e),
+    };
+    Check::from_result(format!("tls {host}"), result)
+}
+
+#[cfg(not(feature = "http"))]
+fn check_tls(host: &str) -> Check {
+    Check::new(
+        format!("tls {host}"),
+        CheckStatus::Skip,
+        "built without the http feature",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use lmb::{NetPermissions, StoreOptions};
+    use std::net::TcpListener;
+    use tempfile::tempdir;
+
+    use super::{diagnose, CheckStatus};
+
+    #[test]
+    fn diagnose_store_and_network() {
+        let dir = tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let store = StoreOptions::new(Some(dir.path().join("db.sqlite3")), false);
+        let net = NetPermissions::new([format!("127.0.0.1:{port}")]);
+        let checks = diagnose("dev", &store, &net);
+        for check in &checks {
+            assert_eq!(CheckStatus::Ok, check.status(), "{check:?}");
+        }
+        assert!(checks.iter().any(|c| c.name() == "sqlite"));
+        assert!(checks
+            .iter()
+            .any(|c| c.name() == format!("connect 127.0.0.1:{port}")));
+        assert!(!dir.path().join("db.sqlite3").exists());
+    }
+
+    #[test]
+    fn diagnose_failures() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let store = StoreOptions::new(Some("/nonexistent/db.sqlite3".into()), false);
+        let net = NetPermissions::new([format!("127.0.0.1:{port}")]);
+        let checks = diagnose("dev", &store, &net);
+        let failed = checks
+            .iter()
+            .filter(|c| c.status() == CheckStatus::Fail)
+            .map(|c| c.name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "store /nonexistent/db.sqlite3".to_string(),
+                format!("connect 127.0.0.1:{port}")
+            ],
+            failed
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Write},
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    BufferedTransaction, Result, StoreBackend, StoreValueMetadata, TransactionFn, UpdateFn,
+};
+
+/// Kind of a side effect attempted by the script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SideEffectKind {
+    /// Read an environment variable
+    Env,
+    /// Send an HTTP request
+    Http,
+    /// Run a subprocess
+    Run,
+    /// Connect a TCP or UDP socket
+    Socket,
+    /// Write to the store
+    Store,
+}
+
+impl Display for SideEffectKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Env => "env",
+            Self::Http => "http",
+            Self::Run => "run",
+            Self::Socket => "socket",
+            Self::Store => "store",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Side effect attempted by the script, which is recorded instead of executed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SideEffect {
+    /// Kind
+    pub kind: SideEffectKind,
+    /// Target e.g. the URL of the request or the name of the value
+    pub target: String,
+    /// Detail e.g. the method of the request or the value written to the store
+    pub detail: Value,
+}
+
+/// Canned response of HTTP requests in dry run.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct HttpFixture {
+    /// Method of the request. Any method matches if omitted
+    #[serde(default)]
+    pub method: Option<String>,
+    /// URL of the request
+    pub url: String,
+    /// Status code
+    #[serde(default = "default_status")]
+    pub status: u16,
+    /// Headers
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Body
+    #[serde(default)]
+    pub body: String,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// Fixtures of dry run in JSON, providing canned environment variables and HTTP responses.
+///
+/// ```json
+/// {
+///   "env": { "TOKEN": "dummy" },
+///   "http": [{ "method": "GET", "url": "https://example.com/", "status": 200, "body": "ok" }]
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DryRunFixtures {
+    /// Environment variables, which take precedence over the permitted ones
+    pub env: BTreeMap<String, String>,
+    /// HTTP responses
+    pub http: Vec<HttpFixture>,
+}
+
+impl DryRunFixtures {
+    /// Load fixtures from a JSON file.
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Find the HTTP response of the request.
+    pub fn find_http(&self, method: &str, url: &str) -> Option<&HttpFixture> {
+        self.http.iter().find(|f| {
+            f.url == url
+                && f.method
+                    .as_deref()
+                    .map_or(true, |m| m.eq_ignore_ascii_case(method))
+        })
+    }
+}
+
+/// Dry run, where side effects of bindings are recorded instead of executed.
+/// HTTP requests are answered with fixtures or an empty response, subprocesses are not run,
+/// sockets are not connected, and writes to the store are only visible to the evaluation.
+///
+/// ```rust
+/// # use std::io::empty;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let dry_run = DryRun::default();
+/// let store = MemoryStore::default();
+/// let e = EvaluationBuilder::new("local m = require('@lmb'); m:put('a', 1); return m:get('a')", empty())
+///     .dry_run(dry_run.clone())
+///     .store(store)
+///     .build()?;
+/// assert_eq!(&serde_json::json!(1), e.evaluate()?.payload());
+/// let effects = dry_run.effects();
+/// assert_eq!(1, effects.len());
+/// assert_eq!(SideEffectKind::Store, effects[0].kind);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DryRun {
+    effects: Arc<Mutex<Vec<SideEffect>>>,
+    fixtures: Arc<DryRunFixtures>,
+}
+
+impl DryRun {
+    /// Create a dry run with fixtures.
+    pub fn new(fixtures: DryRunFixtures) -> Self {
+        Self {
+            effects: Arc::default(),
+            fixtures: Arc::new(fixtures),
+        }
+    }
+
+    /// Get side effects recorded so far.
+    pub fn effects(&self) -> Vec<SideEffect> {
+        self.effects.lock().clone()
+    }
+
+    /// Get fixtures.
+    pub fn fixtures(&self) -> &DryRunFixtures {
+        &self.fixtures
+    }
+
+    /// Record a side effect.
+    pub fn record<S: Into<String>>(&self, kind: SideEffectKind, target: S, detail: Value) {
+        self.effects.lock().push(SideEffect {
+            kind,
+            target: target.into(),
+            detail,
+        });
+    }
+
+    /// Write the report of side effects, one per line or in JSON.
+    pub fn write_report<W: Write>(&self, mut f: W, json: bool) -> Result<()> {
+        let effects = self.effects();
+        if json {
+            writeln!(f, "{}", serde_json::to_string(&effects)?)?;
+            return Ok(());
+        }
+        writeln!(f, "dry run: {} side effect(s) attempted", effects.len())?;
+        for effect in effects {
+            write!(f, "{}\t{}", effect.kind, effect.target)?;
+            if !effect.detail.is_null() {
+                write!(f, "\t{}", effect.detail)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Store which records writes instead of applying them to the underlying store.
+/// Values written are kept in memory, so the evaluation reads its own writes.
+#[derive(Debug)]
+pub(crate) struct DryRunStore {
+    dry_run: DryRun,
+    inner: Arc<dyn StoreBackend>,
+    // none means the value is deleted
+    written: Mutex<HashMap<String, Option<Value>>>,
+}
+
+impl DryRunStore {
+    pub(crate) fn new(inner: Arc<dyn StoreBackend>, dry_run: DryRun) -> Self {
+        Self {
+            dry_run,
+            inner,
+            written: Mutex::default(),
+        }
+    }
+}
+
+impl StoreBackend for DryRunStore {
+    fn delete(&self, name: &str) -> Result<usize> {
+        let existed = !self.get(name)?.is_null();
+        self.dry_run
+            .record(SideEffectKind::Store, name, Value::from("delete"));
+        self.written.lock().insert(name.to_string(), None);
+        Ok(usize::from(existed))
+    }
+
+    fn get(&self, name: &str) -> Result<Value> {
+        if let Some(value) = self.written.lock().get(name) {
+            return Ok(value.clone().unwrap_or(Value::Null));
+        }
+        self.inner.get(name)
+    }
+
+    // values written in dry run are not listed
+    fn list(&self) -> Result<Vec<StoreValueMetadata>> {
+        self.inner.list()
+    }
+
+    fn put(&self, name: &str, value: &Value) -> Result<usize> {
+        self.dry_run
+            .record(SideEffectKind::Store, name, value.clone());
+        self.written
+            .lock()
+            .insert(name.to_string(), Some(value.clone()));
+        Ok(1)
+    }
+
+    fn transaction(&self, mut f: TransactionFn<'_>) -> Result<()> {
+        let written = {
+            let mut tx = BufferedTransaction::new(|name| self.get(name));
+            f(&mut tx)?;
+            tx.into_written()
+        };
+        for (name, value) in written {
+            self.put(&name, &value)?;
+        }
+        Ok(())
+    }
+
+    fn update(&self, name: &str, mut f: UpdateFn<'_>, default_v: Option<Value>) -> Result<Value> {
+        let mut value = match self.get(name)? {
+            Value::Null => default_v.unwrap_or(Value::Null),
+            value => value,
+        };
+        if f(&mut value).is_err() {
+            return Ok(value);
+        }
+        self.put(name, &value)?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{io::empty, sync::Arc};
+
+    use super::{DryRun, DryRunFixtures, DryRunStore, SideEffectKind};
+    use crate::{EvaluationBuilder, MemoryStore, Permissions, RunPermissions, StoreBackend};
+
+    #[test]
+    fn store() {
+        let inner = Arc::new(MemoryStore::default());
+        inner.put("a", &json!(1)).unwrap();
+        let dry_run = DryRun::default();
+        let store = DryRunStore::new(inner.clone(), dry_run.clone());
+        store.put("a", &json!(2)).unwrap();
+        assert_eq!(json!(2), store.get("a").unwrap());
+        assert_eq!(1, store.delete("a").unwrap());
+        assert_eq!(json!(null), store.get("a").unwrap());
+        let value = store
+            .update(
+                "b",
+                Box::new(|v| {
+                    *v = json!(v.as_i64().unwrap_or_default() + 1);
+                    Ok(())
+                }),
+                Some(json!(1)),
+            )
+            .unwrap();
+        assert_eq!(json!(2), value);
+        assert_eq!(json!(1), inner.get("a").unwrap());
+        assert_eq!(json!(null), inner.get("b").unwrap());
+        let effects = dry_run.effects();
+        assert_eq!(3, effects.len());
+        assert!(effects.iter().all(|e| e.kind == SideEffectKind::Store));
+    }
+
+    #[test]
+    fn env() {
+        let mut fixtures = DryRunFixtures::default();
+        fixtures.env.insert("TOKEN".into(), "dummy".into());
+        let dry_run = DryRun::new(fixtures);
+        let script = "local m = require('@lmb'); return m.env.TOKEN";
+        let e = EvaluationBuilder::new(script, empty())
+            .dry_run(dry_run.clone())
+            .build()
+            .unwrap();
+        assert_eq!(&json!("dummy"), e.evaluate().unwrap().payload());
+        let effects = dry_run.effects();
+        assert_eq!(SideEffectKind::Env, effects[0].kind);
+        assert_eq!("TOKEN", effects[0].target);
+    }
+
+    #[test]
+    fn shell() {
+        let dry_run = DryRun::default();
+        let mut permissions = Permissions::default();
+        permissions.set_run(RunPermissions::new(["echo"]));
+        let script = r#"
+        local shell = require('@lmb/shell')
+        local res = shell:exec('echo', {'hello'})
+        local ok = pcall(function() return shell:exec('rm', {'-rf', '/'}) end)
+        return { res.ok, res.stdout, ok }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .dry_run(dry_run.clone())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        assert_eq!(&json!([true, "", false]), e.evaluate().unwrap().payload());
+        let effects = dry_run.effects();
+        assert_eq!(1, effects.len());
+        assert_eq!(SideEffectKind::Run, effects[0].kind);
+        assert_eq!("echo", effects[0].target);
+        assert_eq!(json!(["hello"]), effects[0].detail);
+    }
+
+    #[test]
+    fn socket() {
+        let dry_run = DryRun::default();
+        let script = r#"
+        local ok = pcall(function() return require('@lmb/tcp'):connect('127.0.0.1', 1) end)
+        return ok
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .dry_run(dry_run.clone())
+            .build()
+            .unwrap();
+        assert_eq!(&json!(false), e.evaluate().unwrap().payload());
+        let effects = dry_run.effects();
+        assert_eq!(SideEffectKind::Socket, effects[0].kind);
+        assert_eq!("127.0.0.1:1", effects[0].target);
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn http() {
+        use super::HttpFixture;
+
+        let fixtures = DryRunFixtures {
+            http: vec![HttpFixture {
+                method: Some("get".into()),
+                url: "https://example.com/".into(),
+                status: 201,
+                headers: [("content-type".into(), "application/json".into())].into(),
+                body: r#"{"a":1}"#.into(),
+            }],
+            ..Default::default()
+        };
+        let dry_run = DryRun::new(fixtures);
+        let script = r#"
+        local http = require('@lmb/http')
+        local res = http:fetch('https://example.com/')
+        local other = http:fetch('https://example.org/', { method = 'POST', body = 'x' })
+        return { res.status_code, res:json(), other.status_code, other:read('*a') or '' }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .dry_run(dry_run.clone())
+            .build()
+            .unwrap();
+        assert_eq!(
+            &json!([201, { "a": 1 }, 200, ""]),
+            e.evaluate().unwrap().payload()
+        );
+        let effects = dry_run.effects();
+        assert_eq!(2, effects.len());
+        assert_eq!("https://example.com/", effects[0].target);
+        assert_eq!(json!({ "method": "GET" }), effects[0].detail);
+        assert_eq!(json!({ "method": "POST", "body": "x" }), effects[1].detail);
+    }
+
+    #[test]
+    fn report() {
+        let dry_run = DryRun::default();
+        dry_run.record(SideEffectKind::Store, "a", json!(1));
+        dry_run.record(SideEffectKind::Env, "TOKEN", json!(null));
+        let mut buf = String::new();
+        dry_run.write_report(&mut buf, false).unwrap();
+        assert_eq!(
+            "dry run: 2 side effect(s) attempted\nstore\ta\t1\nenv\tTOKEN\n",
+            buf
+        );
+        let mut buf = String::new();
+        dry_run.write_report(&mut buf, true).unwrap();
+        assert!(buf.starts_with(r#"[{"kind":"store","target":"a","detail":1}"#));
+    }
+}
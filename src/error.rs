@@ -1,51 +1,536 @@
-use std::{fmt::Write, io::Read};
+use serde::Serialize;
+use std::{
+    fmt::{Display, Write},
+    io::Read,
+    ops::Range,
+};
 
 use ariadne::{CharSet, ColorGenerator, Label, Report, ReportKind, Source};
-use lazy_regex::{lazy_regex, Regex};
+use lazy_regex::{lazy_regex, Captures, Regex};
 use mlua::prelude::*;
 use once_cell::sync::Lazy;
 use thiserror::Error;
 
 use crate::{Evaluation, Result};
 
-static LUA_ERROR_REGEX: Lazy<Regex> = lazy_regex!(r"\[[^\]]+\]:(\d+):(.+)");
+static LUA_ERROR_REGEX: Lazy<Regex> = lazy_regex!(r#"\[(?:string "([^"]*)"|[^\]]+)\]:(\d+):(.+)"#);
+static STACK_FRAME_REGEX: Lazy<Regex> = lazy_regex!(
+    r#"^\s*(\[string "([^"]*)"\]|\[[^\]]+\])(?::(\d+))?: in (?:function '([^']*)'|\?)"#
+);
 
 /// Custom error type for handling various error scenarios.
 #[derive(Debug, Error)]
 pub enum Error {
+    /// Error when decoding base64
+    #[error("base64 decode error: {0}")]
+    Base64Decode(#[from] base64::DecodeError),
     /// Error from the [`bat`] library
     #[error("bat error: {0}")]
     Bat(#[from] bat::error::Error),
+    /// The script exceeds the instruction budget, see [`crate::EvaluationBuilder::max_instructions`]
+    #[error("instruction budget of {limit} exceeded")]
+    BudgetExceeded {
+        /// Max instructions
+        limit: u64,
+    },
     /// Error from the `SQLite` database
     #[error("sqlite error: {0}")]
     Database(#[from] rusqlite::Error),
     /// Error from database migration
     #[error("migration error: {0}")]
     DatabaseMigration(#[from] rusqlite_migration::Error),
+    /// Error encrypting or decrypting values of the store
+    #[error("encryption error: {0}")]
+    Encryption(String),
     /// Error in formatting output
     #[error("format error: {0}")]
     Format(#[from] std::fmt::Error),
+    /// Invalid header or version of a precompiled script
+    #[error("invalid bytecode: {0}")]
+    InvalidBytecode(String),
+    /// Message catalog which is not an object of strings, see [`crate::Catalog::load_dir`]
+    #[error("invalid catalog: {0}")]
+    InvalidCatalog(String),
+    /// Unknown eviction policy of the store
+    #[error("invalid eviction policy: {0}")]
+    InvalidEvictionPolicy(String),
+    /// Unknown trigger, timezone or missed run policy of a schedule
+    #[error("invalid schedule: {0}")]
+    InvalidSchedule(String),
+    /// Unknown signal, which is neither "int" nor "term"
+    #[error("invalid signal: {0}")]
+    InvalidSignal(String),
     /// Invalid key length for HMAC
     #[error("invalid length: {0}")]
     InvalidLength(#[from] crypto_common::InvalidLength),
+    /// Payload doesn't match the type it is deserialized into,
+    /// or is rejected by [`crate::PayloadValidator`]
+    #[error("invalid payload at {path}: {message}")]
+    InvalidPayload {
+        /// Path to the mismatched field e.g. `items[0].name`
+        path: String,
+        /// Why the field doesn't match
+        message: String,
+    },
     /// IO error
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     /// Error from the Lua engine
     #[error("lua error: {0}")]
     Lua(#[from] LuaError),
+    /// The Lua virtual machine exceeds the memory limit,
+    /// see [`crate::EvaluationBuilder::memory_limit`]
+    #[error("memory limit of {limit} bytes exceeded")]
+    MemoryLimitExceeded {
+        /// Max bytes allocated by the Lua virtual machine
+        limit: usize,
+    },
+    /// Chunk of a value stored in the blob table is missing
+    #[error("chunk of value {0} is missing")]
+    MissingChunk(String),
+    /// Invalid descriptors of protobuf messages, see [`crate::ProtoDescriptors`]
+    #[cfg(feature = "protobuf")]
+    #[error("invalid protobuf descriptor: {0}")]
+    ProtoDescriptor(#[from] prost_reflect::DescriptorError),
+    /// Custom module named after modules of lmb, see [`crate::EvaluationBuilder::module`]
+    #[error("module {0} is reserved by lmb")]
+    ReservedModule(String),
     /// Error decoding value from `MessagePack` format
     #[error("RMP decode error: {0}")]
     RMPDecode(#[from] rmp_serde::decode::Error),
     /// Error encoding value to `MessagePack` format
     #[error("RMP encode error: {0}")]
     RMPEncode(#[from] rmp_serde::encode::Error),
+    /// Error from Redis
+    #[cfg(feature = "redis")]
+    #[error("redis error: {0}")]
+    Redis(#[from] redis::RedisError),
     /// Error from [`serde_json`] library
     #[error("serde JSON error: {0}")]
     SerdeJSONError(#[from] serde_json::Error),
+    /// Error from [`serde_yaml`] library
+    #[cfg(any(feature = "http", feature = "yaml"))]
+    #[error("serde YAML error: {0}")]
+    SerdeYAMLError(#[from] serde_yaml::Error),
+    /// The store is full and no value can be evicted
+    #[error("store is full: {size} bytes exceeds the limit of {limit} bytes")]
+    StoreFull {
+        /// Total size after the value is written
+        size: usize,
+        /// Max total size
+        limit: usize,
+    },
+    /// Stored value is prefixed with a format unknown to this version
+    #[error("unknown format {0} of the stored value")]
+    UnknownValueFormat(u8),
+    /// Value exceeds the max size of a single value
+    #[error("value of {name} is too large: {size} bytes exceeds the limit of {limit} bytes")]
+    ValueTooLarge {
+        /// Name of the value
+        name: String,
+        /// Size of the value
+        size: usize,
+        /// Max size of a single value
+        limit: usize,
+    },
+}
+
+/// Error raised by scripts with `http_error` to respond with the status code when serving HTTP requests.
+#[derive(Clone, Debug, Error)]
+#[error("HTTP error {status}: {message}")]
+pub struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    /// Create an HTTP error.
+    pub fn new<S: Into<String>>(status: u16, message: S) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// Status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Error raised by `@lmb/http` when the invocation exceeds a limit of [`crate::HttpLimits`].
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum HttpLimitError {
+    /// Too many bytes downloaded
+    #[error("limit of {0} bytes downloaded exceeded")]
+    Bytes(u64),
+    /// Too many requests in flight
+    #[error("limit of {0} concurrent HTTP requests exceeded")]
+    Concurrency(usize),
+    /// Too many requests
+    #[error("limit of {0} HTTP requests exceeded")]
+    Requests(u64),
+}
+
+/// Error raised when a single read of the input exceeds
+/// [`crate::EvaluationBuilder::max_input_bytes`].
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[error("read exceeds the limit of {limit} input bytes")]
+pub struct InputLimitError {
+    /// Max bytes of a single read
+    pub limit: usize,
+}
+
+/// Error raised when the store is called in the function of `update`, which would deadlock
+/// since the store is locked until the update finishes.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[error("store is being updated by {name}, call the store outside of the function of update")]
+pub struct NestedUpdateError {
+    /// Name of the value being updated
+    pub name: String,
+}
+
+/// Severity of an [`ErrorReport`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Error
+    Error,
+    /// Warning
+    Warning,
+}
+
+/// Diagnostic of an error, which can be serialized as JSON
+/// for editor integrations and CI annotators.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let report = ErrorReport::new("a.lua", "local a = 1\nreturn b", "unknown global", Some(19..20));
+/// assert_eq!(Some(2), report.line);
+/// assert_eq!(Some(8), report.column);
+/// let json = serde_json::to_value(&report).unwrap();
+/// assert_eq!("error", json["severity"]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ErrorReport {
+    /// Message
+    pub message: String,
+    /// Name of the script
+    pub file: String,
+    /// Line number starting from 1
+    pub line: Option<usize>,
+    /// Column number in characters starting from 1
+    pub column: Option<usize>,
+    /// Span in bytes
+    pub span: Option<Range<usize>>,
+    /// Label of the span
+    pub label: Option<String>,
+    /// Severity
+    pub severity: Severity,
+    /// Stack traceback from the innermost frame, see [`Error::traceback`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub traceback: Vec<StackFrame>,
+}
+
+impl ErrorReport {
+    /// Create an error report. The line and column are located with the span in the script.
+    pub fn new<S, T>(file: S, script: &str, message: T, span: Option<Range<usize>>) -> Self
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let message = message.into();
+        let location = span.as_ref().and_then(|span| {
+            let before = script.get(..span.start)?;
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            let line = before.matches('\n').count() + 1;
+            Some((line, before[line_start..].chars().count() + 1))
+        });
+        Self {
+            label: location.map(|_| message.clone()),
+            message,
+            file: file.into(),
+            line: location.map(|(line, _)| line),
+            column: location.map(|(_, column)| column),
+            span: location.and(span),
+            severity: Severity::Error,
+            traceback: vec![],
+        }
+    }
+}
+
+/// Frame in the stack traceback of a Lua error.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let frame = StackFrame { file: "a.lua".to_string(), line: Some(2), function: Some("f".to_string()) };
+/// assert_eq!("a.lua:2: in function 'f'", frame.to_string());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StackFrame {
+    /// Name of the script, or `[C]` for native functions
+    pub file: String,
+    /// Line number starting from 1
+    pub line: Option<usize>,
+    /// Name of the function
+    pub function: Option<String>,
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        match &self.function {
+            Some(function) => write!(f, ": in function '{function}'"),
+            None => write!(f, ": in ?"),
+        }
+    }
+}
+
+fn parse_traceback(traceback: &str) -> Vec<StackFrame> {
+    traceback
+        .lines()
+        .filter_map(|line| STACK_FRAME_REGEX.captures(line))
+        .map(|c| StackFrame {
+            file: c.get(2).or(c.get(1)).map_or("", |m| m.as_str()).to_string(),
+            line: c.get(3).and_then(|n| n.as_str().parse().ok()),
+            function: c.get(4).map(|m| m.as_str().to_string()),
+        })
+        .collect()
+}
+
+// file of the error, which is rewritten by the source map, otherwise the script itself
+fn error_file<'a, R>(e: &'a Evaluation<R>, captures: &Captures<'a>) -> &'a str
+where
+    for<'lua> R: 'lua + Read + Send,
+{
+    match (e.source_map(), captures.get(1)) {
+        (Some(_), Some(file)) => file.as_str(),
+        _ => e.name(),
+    }
+}
+
+pub(crate) fn find_external<T>(err: &LuaError) -> Option<&T>
+where
+    T: std::error::Error + 'static,
+{
+    match err {
+        LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => {
+            find_external(cause)
+        }
+        // errors may be raised while reading e.g. the response body
+        LuaError::ExternalError(err) => err.downcast_ref().or_else(|| {
+            err.downcast_ref::<std::io::Error>()
+                .and_then(|e| e.get_ref())
+                .and_then(|e| e.downcast_ref())
+        }),
+        _ => None,
+    }
 }
 
 impl Error {
+    /// Get the HTTP error raised by the script, if any.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// let e = EvaluationBuilder::new("require('@lmb'):http_error(404, 'not found')", std::io::empty()).build().unwrap();
+    /// let err = e.evaluate().unwrap_err();
+    /// let http_error = err.http_error().unwrap();
+    /// assert_eq!(404, http_error.status());
+    /// assert_eq!("not found", http_error.message());
+    /// ```
+    pub fn http_error(&self) -> Option<&HttpError> {
+        match self {
+            Self::Lua(err) => find_external(err),
+            _ => None,
+        }
+    }
+
+    /// Get the error raised when the script exceeds a limit of [`crate::HttpLimits`], if any.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// # #[cfg(feature = "http")] {
+    /// let mut limits = HttpLimits::default();
+    /// limits.set_max_requests(Some(0));
+    /// let mut permissions = Permissions::default();
+    /// permissions.set_http_limits(limits);
+    /// let script = "require('@lmb/http'):fetch('http://localhost')";
+    /// let e = EvaluationBuilder::new(script, std::io::empty())
+    ///     .permissions(permissions)
+    ///     .build().unwrap();
+    /// let err = e.evaluate().unwrap_err();
+    /// assert_eq!(Some(&HttpLimitError::Requests(0)), err.http_limit());
+    /// # }
+    /// ```
+    pub fn http_limit(&self) -> Option<&HttpLimitError> {
+        match self {
+            Self::Lua(err) => find_external(err),
+            _ => None,
+        }
+    }
+
+    /// Get the error raised when a read of the input exceeds the limit, if any.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// let e = EvaluationBuilder::new("return io.read('*a')", "hello".as_bytes())
+    ///     .max_input_bytes(Some(4))
+    ///     .build().unwrap();
+    /// let err = e.evaluate().unwrap_err();
+    /// assert_eq!(Some(&InputLimitError { limit: 4 }), err.input_limit());
+    /// ```
+    pub fn input_limit(&self) -> Option<&InputLimitError> {
+        match self {
+            Self::Lua(err) => find_external(err),
+            _ => None,
+        }
+    }
+
+    /// Get the error raised when the store is called in the function of `update`, if any.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    ///
+    /// let script = r#"
+    /// local m = require('@lmb')
+    /// m:update('a', function() return m:get('b') end)
+    /// "#;
+    /// let e = EvaluationBuilder::new(script, empty()).store(Store::default()).build().unwrap();
+    /// let err = e.evaluate().unwrap_err();
+    /// assert_eq!("a", err.nested_update().unwrap().name);
+    /// ```
+    pub fn nested_update(&self) -> Option<&NestedUpdateError> {
+        match self {
+            Self::Lua(err) => find_external(err),
+            _ => None,
+        }
+    }
+
+    /// Build the report of the error. Lua runtime and syntax errors are located in the script.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    ///
+    /// let e = EvaluationBuilder::new("return nil+1", empty()).name("a.lua").build().unwrap();
+    /// let err = e.evaluate().unwrap_err();
+    /// let report = err.report(&e);
+    /// assert_eq!("a.lua", report.file);
+    /// assert_eq!(Some(1), report.line);
+    /// ```
+    pub fn report<R>(&self, e: &Evaluation<R>) -> ErrorReport
+    where
+        for<'lua> R: 'lua + Read + Send,
+    {
+        if let Some((file, message, line_number)) = self.locate_lua_error(e) {
+            // the source is unavailable for precompiled scripts
+            let Some(script) = e
+                .source(&file)
+                .filter(|s| s.lines().nth(line_number - 1).is_some())
+            else {
+                let mut report = ErrorReport::new(file, "", message, None);
+                report.line = Some(line_number);
+                return report;
+            };
+            let start = script
+                .split_inclusive('\n')
+                .take(line_number - 1)
+                .map(str::len)
+                .sum::<usize>();
+            let len = script[start..].split('\n').next().map_or(0, str::len);
+            return ErrorReport::new(file, script, message, Some(start..start + len));
+        }
+        let message = match self {
+            Self::Lua(LuaError::RuntimeError(m) | LuaError::SyntaxError { message: m, .. }) => {
+                m.lines().next().unwrap_or_default().to_string()
+            }
+            _ => self.to_string(),
+        };
+        ErrorReport::new(e.name(), e.script(), message, None)
+    }
+
+    // file, message and line number of a Lua runtime or syntax error
+    fn locate_lua_error<R>(&self, e: &Evaluation<R>) -> Option<(String, String, usize)>
+    where
+        for<'lua> R: 'lua + Read + Send,
+    {
+        let message = match self {
+            Self::Lua(LuaError::RuntimeError(message) | LuaError::SyntaxError { message, .. }) => {
+                message
+            }
+            _ => return None,
+        };
+        let first_line = message.lines().next().unwrap_or_default();
+        let captures = LUA_ERROR_REGEX.captures(first_line)?;
+        let file = error_file(e, &captures);
+        let line_number = captures
+            .get(2)
+            .and_then(|n| n.as_str().parse::<usize>().ok())
+            .filter(|n| *n > 0)?;
+        let message = captures.get(3).map_or(first_line, |s| s.as_str().trim());
+        Some((file.to_string(), message.to_string(), line_number))
+    }
+
+    /// Get the stack traceback of a Lua error from the innermost frame.
+    /// Locations are rewritten by the source map, see [`crate::SourceMap`].
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// let script = "local function f()\n  error('boom')\nend\nf()";
+    /// let e = EvaluationBuilder::new(script, std::io::empty()).name("a.lua").build().unwrap();
+    /// let err = e.evaluate().unwrap_err();
+    /// let traceback = err.traceback();
+    /// let frame = traceback.iter().find(|f| f.file == "a.lua").unwrap();
+    /// assert_eq!("a.lua:2: in function 'f'", frame.to_string());
+    /// ```
+    pub fn traceback(&self) -> Vec<StackFrame> {
+        fn find_traceback(err: &LuaError) -> Option<&str> {
+            match err {
+                LuaError::RuntimeError(message) => message
+                    .split_once("stack traceback:")
+                    .map(|(_, traceback)| traceback),
+                LuaError::CallbackError { traceback, .. } => Some(traceback),
+                LuaError::WithContext { cause, .. } => find_traceback(cause),
+                _ => None,
+            }
+        }
+        match self {
+            Self::Lua(err) => find_traceback(err).map(parse_traceback).unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    /// Render the stack traceback of a Lua error, see [`Error::traceback`].
+    pub fn write_traceback<W>(&self, mut f: W) -> Result<()>
+    where
+        W: Write,
+    {
+        let traceback = self.traceback();
+        if traceback.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "stack traceback:")?;
+        for frame in traceback {
+            writeln!(f, "  {frame}")?;
+        }
+        Ok(())
+    }
+
     /// Render a Lua runtime or syntax error.
     pub fn write_lua_error<R, W>(&self, mut f: W, e: &Evaluation<R>, no_color: bool) -> Result<()>
     where
@@ -65,7 +550,7 @@ impl Error {
         };
 
         let Some(line_number) = captures
-            .get(1)
+            .get(2)
             .and_then(|n| n.as_str().parse::<usize>().ok())
         else {
             return Ok(write!(f, "{}", first_line)?);
@@ -73,15 +558,19 @@ impl Error {
 
         let mut colors = ColorGenerator::new();
 
-        let source = Source::from(e.script());
-        let line = source
-            .line(line_number - 1) // index, not line number
-            .expect("cannot find line in source");
+        let file = error_file(e, &captures);
+        // the source is unavailable for precompiled scripts
+        let Some(source) = e.source(file).map(Source::from) else {
+            return Ok(write!(f, "{}", first_line)?);
+        };
+        let Some(line) = source.line(line_number - 1) else {
+            return Ok(write!(f, "{}", first_line)?);
+        };
         let span = line.span();
 
-        let message = captures.get(2).map_or(first_line, |s| s.as_str().trim());
+        let message = captures.get(3).map_or(first_line, |s| s.as_str().trim());
         let mut buf = Vec::new();
-        Report::build(ReportKind::Error, e.name(), span.start)
+        Report::build(ReportKind::Error, file, span.start)
             .with_config(
                 ariadne::Config::default()
                     .with_char_set(CharSet::Ascii)
@@ -89,13 +578,13 @@ impl Error {
                     .with_color(!no_color),
             )
             .with_label(
-                Label::new((e.name(), span))
+                Label::new((file, span))
                     .with_color(colors.next())
                     .with_message(message),
             )
             .with_message(message)
             .finish()
-            .write((e.name(), source), &mut buf)?;
+            .write((file, source), &mut buf)?;
         write!(f, "{}", String::from_utf8_lossy(&buf))?;
         Ok(())
     }
@@ -107,10 +596,83 @@ mod tests {
 
     use crate::EvaluationBuilder;
 
+    #[test]
+    fn report() {
+        let script = "local a = 1\nreturn a + nil";
+        let e = EvaluationBuilder::new(script, empty())
+            .name("a.lua")
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        let report = err.report(&e);
+        assert_eq!("a.lua", report.file);
+        assert_eq!(Some(2), report.line);
+        assert_eq!(Some(1), report.column);
+        assert_eq!(Some(12..26), report.span);
+        assert!(report.message.contains("attempt to perform arithmetic"));
+        assert_eq!(Some(&report.message), report.label.as_ref());
+    }
+
+    #[test]
+    fn traceback() {
+        let script = "local function f()\n  error('boom')\nend\nlocal function g() f() end\ng()";
+        let e = EvaluationBuilder::new(script, empty())
+            .name("a.lua")
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        let frames = err
+            .traceback()
+            .into_iter()
+            .filter(|f| f.file == "a.lua")
+            .map(|f| (f.line, f.function))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Some(2), Some("f".to_string())),
+                (Some(4), Some("g".to_string())),
+                (Some(5), None)
+            ],
+            frames
+        );
+
+        let mut buf = String::new();
+        err.write_traceback(&mut buf).unwrap();
+        assert!(buf.starts_with("stack traceback:\n"));
+        assert!(buf.contains("  a.lua:4: in function 'g'\n"));
+    }
+
+    #[test]
+    fn traceback_of_callback() {
+        let script = "local function f()\n  require('@lmb'):http_error(500, 'x')\nend\nf()";
+        let e = EvaluationBuilder::new(script, empty())
+            .name("a.lua")
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        let frame = err.traceback().into_iter().find(|f| f.file == "a.lua");
+        assert_eq!(Some(Some(2)), frame.map(|f| f.line));
+        assert!(super::Error::Encryption(String::new())
+            .traceback()
+            .is_empty());
+    }
+
+    #[test]
+    fn report_without_location() {
+        let e = EvaluationBuilder::new("error({})", empty())
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        let report = err.report(&e);
+        assert_eq!(None, report.line);
+        assert_eq!(None, report.span);
+        assert_eq!(None, report.label);
+    }
+
     #[test]
     fn write_error() {
         let script = "return nil+1";
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let Err(err) = e.evaluate() else {
             panic!("expect error");
         };
@@ -8,22 +8,62 @@ use chrono::Utc;
 use console::Term;
 use mlua::{prelude::*, Compiler};
 use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 use std::{
-    fmt::{Display, Write},
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt::{Debug, Display, Write},
     io::{stdout, BufReader, IsTerminal as _, Read},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
-    thread,
     time::{Duration, Instant},
 };
-use tracing::{debug, error, trace_span, warn};
+use tracing::{debug, error, info, trace_span, warn};
 
+#[cfg(feature = "http")]
+use crate::Cassette;
 use crate::{
-    Input, LuaBinding, PrintOptions, Result, ScheduleOptions, State, Store, DEFAULT_TIMEOUT,
+    is_interrupted, register_app_state, register_args, register_assert, register_catalog,
+    register_deterministic, register_globals, register_metrics, register_modules,
+    register_permitted_modules, reset_state, sleep_until, verify_precompiled, Catalog, Deadline,
+    Debugger, DryRun, DryRunStore, Error, FrozenTime, GcOptions, Input, InputCopy, Invocation,
+    InvocationState, LuaBinding, MaxInputBytes, MemoryLimit, Metrics, MissedRunPolicy,
+    ModuleProvider, Modules, Permissions, PrintOptions, Profiler, Result, ScheduleOptions,
+    ScratchDir, Snapshots, SourceMap, Started, Store, StoreBackend, DEFAULT_TIMEOUT,
+    NONDETERMINISTIC_MODULES,
 };
+#[cfg(feature = "coroutine")]
+use crate::{register_coroutine, reset_coroutines};
+#[cfg(feature = "protobuf")]
+use crate::{register_proto_descriptors, ProtoDescriptors};
+
+/// Blank the leading `#!` line, so scripts can be executable with `#!/usr/bin/env lmb`.
+/// The line is kept empty to preserve line numbers.
+pub(crate) fn strip_shebang(script: &[u8]) -> Cow<'_, [u8]> {
+    if !script.starts_with(b"#!") {
+        return Cow::Borrowed(script);
+    }
+    let rest = script
+        .iter()
+        .position(|b| *b == b'\n')
+        .map_or(&[][..], |i| &script[i..]);
+    Cow::Owned(rest.to_vec())
+}
+
+/// Check whether the error is raised by an allocation exceeding the memory limit,
+/// including one raised in a callback.
+fn is_memory_error(err: &LuaError) -> bool {
+    match err {
+        LuaError::MemoryError(_) => true,
+        LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => {
+            is_memory_error(cause)
+        }
+        _ => false,
+    }
+}
 
 /// Evaluation builder.
 #[derive(Debug)]
@@ -31,10 +71,37 @@ pub struct EvaluationBuilder<R>
 where
     R: Read,
 {
-    input: Arc<Mutex<BufReader<R>>>,
+    app_state: Option<Value>,
+    args: Vec<String>,
+    #[cfg(feature = "http")]
+    cassette: Option<Cassette>,
+    catalog: Catalog,
+    compiled: Option<Vec<u8>>,
+    debugger: Option<Debugger>,
+    deterministic: bool,
+    dry_run: Option<DryRun>,
+    gc: GcOptions,
+    globals: Vec<(String, Value)>,
+    history: Option<Store>,
+    history_input: Option<InputCopy>,
+    input: Option<Input<R>>,
+    input_buffer_size: Option<usize>,
+    max_input_bytes: Option<usize>,
+    max_instructions: Option<u64>,
+    memory_limit: Option<usize>,
+    metrics: Metrics,
+    modules: Modules,
     name: Option<String>,
+    named_args: Vec<(String, String)>,
+    payload_validator: Option<PayloadValidator>,
+    permissions: Permissions,
+    profiler: Option<Profiler>,
+    #[cfg(feature = "protobuf")]
+    proto_descriptors: ProtoDescriptors,
     script: String,
-    store: Option<Store>,
+    snapshots: Option<Snapshots>,
+    source_map: Option<SourceMap>,
+    store: Option<Arc<dyn StoreBackend>>,
     timeout: Option<Duration>,
 }
 
@@ -53,14 +120,7 @@ where
     where
         S: Display,
     {
-        let input = Arc::new(Mutex::new(BufReader::new(input)));
-        Self {
-            input,
-            name: None,
-            script: script.to_string(),
-            store: None,
-            timeout: None,
-        }
+        Self::with_reader(script, Arc::new(Mutex::new(BufReader::new(input))))
     }
 
     /// Build the evaluation with a [`std::io::BufReader`].
@@ -77,14 +137,131 @@ where
         S: Display,
     {
         Self {
-            input,
+            app_state: None,
+            args: vec![],
+            #[cfg(feature = "http")]
+            cassette: None,
+            catalog: Catalog::default(),
+            compiled: None,
+            debugger: None,
+            deterministic: false,
+            dry_run: None,
+            gc: GcOptions::default(),
+            globals: vec![],
+            history: None,
+            history_input: None,
+            input: Some(input),
+            input_buffer_size: None,
+            max_input_bytes: None,
+            max_instructions: None,
+            memory_limit: None,
+            metrics: Metrics::default(),
+            modules: Modules::new(),
             name: None,
+            named_args: vec![],
+            payload_validator: None,
+            permissions: Permissions::default(),
+            profiler: None,
+            #[cfg(feature = "protobuf")]
+            proto_descriptors: ProtoDescriptors::default(),
             script: script.to_string(),
+            snapshots: None,
+            source_map: None,
             store: None,
             timeout: None,
         }
     }
 
+    /// Create a builder from a script precompiled by [`crate::compile`],
+    /// which skips parsing and compiling the script.
+    /// Fail if the version header doesn't match the bundled compiler.
+    ///
+    /// <div class="warning">The source is unavailable, so errors cannot be rendered
+    /// with the script and [`Evaluation::script`] is empty.</div>
+    ///
+    /// <div class="warning">Bytecode is not verified beyond the header, and malformed bytecode
+    /// may crash the process, so it must come from a trusted source.</div>
+    ///
+    /// The source map embedded by [`crate::compile_with_source_map`] is applied.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let bytecode = compile("return 1")?;
+    /// let e = EvaluationBuilder::from_precompiled(&bytecode, empty())?.build()?;
+    /// let res = e.evaluate()?;
+    /// assert_eq!(&json!(1), res.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_precompiled(bytecode: &[u8], input: R) -> Result<Self> {
+        let (bytecode, source_map) = verify_precompiled(bytecode)?;
+        let mut builder = Self::new("", input);
+        builder.compiled = Some(bytecode.to_vec());
+        builder.source_map = (!source_map.is_empty()).then_some(source_map);
+        Ok(builder)
+    }
+
+    /// Set the application state. Invocations share it read-only as `app_state` of `@lmb`,
+    /// and each invocation gets a copy as `state`, which can be mutated without affecting others.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let script = "local m = require('@lmb'); m.state.n += 1; return m.state.n + m.app_state.n";
+    /// let e = EvaluationBuilder::new(script, empty())
+    ///     .app_state(Some(json!({ "n": 1 })))
+    ///     .build()?;
+    /// assert_eq!(&json!(3), e.evaluate()?.payload());
+    /// assert_eq!(&json!(3), e.evaluate()?.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn app_state(&mut self, app_state: Option<Value>) -> &mut Self {
+        self.app_state = app_state;
+        self
+    }
+
+    /// Record HTTP interactions to or replay them from the cassette, see [`Cassette`].
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let _ = EvaluationBuilder::new("", empty()).cassette(Cassette::record("cassette.yaml"));
+    /// ```
+    #[cfg(feature = "http")]
+    pub fn cassette(&mut self, cassette: Cassette) -> &mut Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Translate messages of `@lmb/i18n` with the catalog, see [`Catalog`].
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let mut catalog = Catalog::new("fr");
+    /// catalog.add("fr", [("hello", "bonjour, {name}")]);
+    /// let script = "return require('@lmb/i18n'):t('hello', { name = 'lmb' })";
+    /// let e = EvaluationBuilder::new(script, empty()).catalog(catalog).build()?;
+    /// assert_eq!(&json!("bonjour, lmb"), e.evaluate()?.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn catalog(&mut self, catalog: Catalog) -> &mut Self {
+        self.catalog = catalog;
+        self
+    }
+
     /// Attach an in-memory store.
     /// <div class="warning">Data will be lost after the program finishes.</div>
     ///
@@ -94,7 +271,198 @@ where
     /// let _ = EvaluationBuilder::new("", empty()).default_store();
     /// ```
     pub fn default_store(&mut self) -> &mut Self {
-        self.store = Some(Store::default());
+        self.store = Some(Arc::new(Store::default()));
+        self
+    }
+
+    /// Pause the evaluation on breakpoints and read commands, see [`Debugger`].
+    /// Time paused by the debugger is excluded from the timeout.
+    ///
+    /// ```rust
+    /// # use std::io::{empty, stderr, stdin, BufReader};
+    /// use lmb::*;
+    /// let debugger = Debugger::new(BufReader::new(stdin()), stderr());
+    /// let _ = EvaluationBuilder::new("", empty()).debugger(debugger);
+    /// ```
+    pub fn debugger(&mut self, debugger: Debugger) -> &mut Self {
+        self.debugger = Some(debugger);
+        self
+    }
+
+    /// Make evaluations deterministic, so the same script, input and state yield the same
+    /// payload. `math.random` is seeded with 0, `os.time` is frozen at the start of each
+    /// evaluation, `tmpdir` of `@lmb` is nil, and modules reaching the network, running
+    /// processes or waiting for signals are disabled.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let script = "return { math.random(), os.time() == os.time() }";
+    /// let e = EvaluationBuilder::new(script, empty()).deterministic(true).build()?;
+    /// assert_eq!(e.evaluate()?.payload(), e.evaluate()?.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn deterministic(&mut self, deterministic: bool) -> &mut Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Record side effects of bindings instead of executing them, see [`DryRun`].
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let _ = EvaluationBuilder::new("", empty()).dry_run(DryRun::default());
+    /// ```
+    pub fn dry_run(&mut self, dry_run: DryRun) -> &mut Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Tune the garbage collector, see [`GcOptions`].
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let mut gc = GcOptions::default();
+    /// gc.set_step_size(Some(16));
+    /// let _ = EvaluationBuilder::new("", empty()).gc(gc);
+    /// ```
+    pub fn gc(&mut self, gc: GcOptions) -> &mut Self {
+        self.gc = gc;
+        self
+    }
+
+    /// Set or unset the store where each invocation is appended to the history,
+    /// with its duration, used memory, and the result or the error truncated.
+    /// The script, the state and permitted environment variables are kept to replay it.
+    /// Failing to record is logged without failing the evaluation. See [`Store::invocations`].
+    pub fn history(&mut self, history: Option<Store>) -> &mut Self {
+        self.history = history;
+        self
+    }
+
+    /// Set or unset the copy of the input, whose bytes read during each invocation
+    /// are recorded in the history, see [`InputCopy`].
+    pub fn history_input(&mut self, history_input: Option<InputCopy>) -> &mut Self {
+        self.history_input = history_input;
+        self
+    }
+
+    /// Set a global variable e.g. configuration before the script is loaded.
+    /// Tables are read-only, so the value is shared by evaluations without being tampered with.
+    ///
+    /// Names of built-in globals e.g. `io` are reserved, and globals set with these names are ignored.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let e = EvaluationBuilder::new("return CONFIG.name", empty())
+    ///     .global("CONFIG", json!({ "name": "lmb" }))
+    ///     .build()?;
+    /// let res = e.evaluate()?;
+    /// assert_eq!(&json!("lmb"), res.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn global<S>(&mut self, name: S, value: Value) -> &mut Self
+    where
+        S: Display,
+    {
+        self.globals.push((name.to_string(), value));
+        self
+    }
+
+    /// Register a custom module which can be loaded with `require` in Lua.
+    ///
+    /// Names of built-in modules i.e. `@lmb` and those under `@lmb/` are reserved,
+    /// and [`EvaluationBuilder::build`] fails if a module is registered with them.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use mlua::prelude::*;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// #[derive(Clone, Debug)]
+    /// struct Greeter;
+    ///
+    /// impl LuaUserData for Greeter {
+    ///     fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method("greet", |_, _, name: String| Ok(format!("hello, {name}")));
+    ///     }
+    /// }
+    ///
+    /// # fn main() -> Result<()> {
+    /// let script = "return require('@myco/greeter'):greet('world')";
+    /// let e = EvaluationBuilder::new(script, empty())
+    ///     .module("@myco/greeter", Greeter)
+    ///     .build()?;
+    /// let res = e.evaluate()?;
+    /// assert_eq!(&json!("hello, world"), res.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn module<S, M>(&mut self, name: S, module: M) -> &mut Self
+    where
+        S: Display,
+        M: 'static + ModuleProvider,
+    {
+        self.modules.push((name.to_string(), Arc::new(module)));
+        self
+    }
+
+    /// Set a named argument of the script, which is read by `args` of `@lmb` by the key.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let e = EvaluationBuilder::new("return require('@lmb').args.name", empty())
+    ///     .arg("name", "lmb")
+    ///     .build()?;
+    /// assert_eq!(&json!("lmb"), e.evaluate()?.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn arg<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: Display,
+        V: Display,
+    {
+        self.named_args.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set positional arguments of the script, which are read by `args` of `@lmb` by the index.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let e = EvaluationBuilder::new("return require('@lmb').args[2]", empty())
+    ///     .args(["a", "b"])
+    ///     .build()?;
+    /// assert_eq!(&json!("b"), e.evaluate()?.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Display,
+    {
+        self.args = args.into_iter().map(|a| a.to_string()).collect();
         self
     }
 
@@ -113,16 +481,203 @@ where
         self
     }
 
-    /// Attach a store to the function.
+    /// Grant permissions to the function, see [`Permissions`] for defaults.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let mut permissions = Permissions::default();
+    /// permissions.set_run(RunPermissions::new(["echo"]));
+    /// let script = "return require('@lmb/shell'):exec('echo', { 'hi' }).stdout";
+    /// let e = EvaluationBuilder::new(script, empty())
+    ///     .permissions(permissions)
+    ///     .build()?;
+    /// let res = e.evaluate()?;
+    /// assert_eq!(&json!("hi\n"), res.payload());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn permissions(&mut self, permissions: Permissions) -> &mut Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Validate the payload after each evaluation with the validator, so scripts feeding
+    /// typed systems fail instead of returning values of unexpected shapes.
     ///
     /// ```rust
     /// # use std::io::empty;
     /// use lmb::*;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct User {
+    ///     name: String,
+    /// }
+    ///
+    /// let e = EvaluationBuilder::new("return { name = 1 }", empty())
+    ///     .payload_validator(PayloadValidator::of::<User>())
+    ///     .build()
+    ///     .unwrap();
+    /// let err = e.evaluate().unwrap_err();
+    /// assert!(matches!(err, Error::InvalidPayload { path, .. } if path == "name"));
+    /// ```
+    pub fn payload_validator(&mut self, validator: PayloadValidator) -> &mut Self {
+        self.payload_validator = Some(validator);
+        self
+    }
+
+    /// Sample the Lua call stack during evaluations, see [`Profiler`].
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let _ = EvaluationBuilder::new("", empty()).profiler(Profiler::new());
+    /// ```
+    pub fn profiler(&mut self, profiler: Profiler) -> &mut Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Encode and decode messages of `@lmb/protobuf` with the descriptors,
+    /// see [`ProtoDescriptors`].
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let _ = EvaluationBuilder::new("", empty()).proto_descriptors(ProtoDescriptors::new());
+    /// ```
+    #[cfg(feature = "protobuf")]
+    pub fn proto_descriptors(&mut self, descriptors: ProtoDescriptors) -> &mut Self {
+        self.proto_descriptors = descriptors;
+        self
+    }
+
+    /// Write and compare snapshots of `@lmb/assert` under the directory, see [`Snapshots`].
+    pub fn snapshots(&mut self, snapshots: Option<Snapshots>) -> &mut Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Map lines of a wrapped or generated script to the original files, see [`SourceMap`].
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let mut map = SourceMap::new();
+    /// map.add("a.lua", 2, 1, 1);
+    /// let _ = EvaluationBuilder::new("return (function()\nreturn 1\nend)()", empty()).source_map(map);
+    /// ```
+    pub fn source_map(&mut self, source_map: SourceMap) -> &mut Self {
+        self.source_map = Some(source_map);
+        self
+    }
+
+    /// Attach a store to the function. Any [`StoreBackend`] can be attached,
+    /// including a shared `Arc<dyn StoreBackend>`.
+    ///
+    /// ```rust
+    /// # use std::{io::empty, sync::Arc};
+    /// use lmb::*;
     /// let store = Store::default();
     /// let _ = EvaluationBuilder::new("", empty()).store(store);
+    /// let store: Arc<dyn StoreBackend> = Arc::new(MemoryStore::default());
+    /// let _ = EvaluationBuilder::new("", empty()).store(store);
+    /// ```
+    pub fn store<B>(&mut self, store: B) -> &mut Self
+    where
+        B: 'static + StoreBackend,
+    {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Set the capacity of the buffer of the input in bytes, 8 KiB by default.
+    /// Inputs replaced by [`Evaluation::set_input`] get the same capacity.
+    /// The input shared by [`EvaluationBuilder::with_reader`] keeps its buffer.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let _ = EvaluationBuilder::new("", empty()).input_buffer_size(64 * 1024);
+    /// ```
+    pub fn input_buffer_size(&mut self, capacity: usize) -> &mut Self {
+        self.input_buffer_size = Some(capacity);
+        if let Some(input) = self.input.take() {
+            // nothing is read before the evaluation is built, unless the input is shared
+            let input = match Arc::try_unwrap(input) {
+                Ok(reader) => {
+                    let reader = reader.into_inner();
+                    if reader.buffer().is_empty() {
+                        Arc::new(Mutex::new(BufReader::with_capacity(
+                            capacity,
+                            reader.into_inner(),
+                        )))
+                    } else {
+                        Arc::new(Mutex::new(reader))
+                    }
+                }
+                Err(shared) => shared,
+            };
+            self.input = Some(input);
+        }
+        self
+    }
+
+    /// Set or unset the max bytes of a single read of the input, e.g. `io.read('*a')`,
+    /// so a script cannot buffer a huge input in memory.
+    /// The read fails with [`crate::InputLimitError`] once it would exceed the limit.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let _ = EvaluationBuilder::new("", empty()).max_input_bytes(Some(1024 * 1024));
+    /// ```
+    pub fn max_input_bytes(&mut self, max_input_bytes: Option<usize>) -> &mut Self {
+        self.max_input_bytes = max_input_bytes;
+        self
+    }
+
+    /// Set or unset the instruction budget, which bounds the script without relying on the clock.
+    /// Luau has no hook on every instruction, so instructions are counted on function calls,
+    /// returns and loop iterations, where Luau interrupts the script.
+    /// The evaluation fails with [`crate::Error::BudgetExceeded`] even if the script catches the error.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let e = EvaluationBuilder::new("while true do end", empty()).max_instructions(Some(100)).build().unwrap();
+    /// assert!(matches!(e.evaluate(), Err(Error::BudgetExceeded { limit: 100 })));
+    /// ```
+    pub fn max_instructions(&mut self, max_instructions: Option<u64>) -> &mut Self {
+        self.max_instructions = max_instructions;
+        self
+    }
+
+    /// Set or unset the max bytes allocated by the Lua virtual machine, including bindings.
+    /// The evaluation fails with [`crate::Error::MemoryLimitExceeded`] when an allocation
+    /// exceeds it, and garbage is collected, so the evaluation can be reused.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// let script = "local t = {} for i = 1, 1e7 do t[i] = i end";
+    /// let limit = 4 * 1024 * 1024;
+    /// let e = EvaluationBuilder::new(script, empty()).memory_limit(Some(limit)).build().unwrap();
+    /// assert!(matches!(e.evaluate(), Err(Error::MemoryLimitExceeded { .. })));
     /// ```
-    pub fn store(&mut self, store: Store) -> &mut Self {
-        self.store = Some(store);
+    pub fn memory_limit(&mut self, memory_limit: Option<usize>) -> &mut Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    /// Set metrics which `@lmb/prometheus` defines and updates, see [`Metrics`].
+    /// By default, each build has its own metrics.
+    pub fn metrics(&mut self, metrics: Metrics) -> &mut Self {
+        self.metrics = metrics;
         self
     }
 
@@ -152,32 +707,101 @@ where
     /// use lmb::*;
     ///
     /// # fn main() -> Result<()> {
-    /// let e = EvaluationBuilder::new("return true", empty()).build();
+    /// let e = EvaluationBuilder::new("return true", empty()).build()?;
     /// let res = e.evaluate()?;
     /// assert_eq!(&json!(true), res.payload());
     /// # Ok(())
     /// # }
     /// ```
-    pub fn build(&self) -> Arc<Evaluation<R>> {
+    ///
+    /// Fail if a custom module fails to be provided, or is named after modules of lmb.
+    pub fn build(&self) -> Result<Arc<Evaluation<R>>> {
         let vm = Lua::new();
-        vm.sandbox(true).expect("failed to enable sandbox");
+        vm.sandbox(true)?;
+        // zero keeps the parameter unchanged
+        let gc_param = |p: Option<u32>| p.and_then(|p| i32::try_from(p).ok()).unwrap_or(0);
+        vm.gc_inc(
+            gc_param(self.gc.pause()),
+            gc_param(self.gc.step_multiplier()),
+            gc_param(self.gc.step_size()),
+        );
 
-        let compiled = {
+        let compiled = self.compiled.clone().unwrap_or_else(|| {
             let compiler = Compiler::new();
             let _s = trace_span!("compile_script").entered();
-            compiler.compile(&self.script)
+            compiler.compile(strip_shebang(self.script.as_bytes()))
+        });
+        register_modules(&vm, &self.modules)?;
+        let mut permissions = self.permissions.clone();
+        if self.deterministic {
+            let mut disabled = permissions.disabled_modules().to_vec();
+            disabled.extend(NONDETERMINISTIC_MODULES.map(String::from));
+            permissions.set_disabled_modules(disabled);
+            register_deterministic(&vm)?;
+        }
+        register_permitted_modules(
+            &vm,
+            &permissions,
+            self.dry_run.as_ref(),
+            #[cfg(feature = "http")]
+            self.cassette.as_ref(),
+        )?;
+        let store = match (&self.store, &self.dry_run) {
+            (Some(store), Some(dry_run)) => {
+                Some(Arc::new(DryRunStore::new(store.clone(), dry_run.clone()))
+                    as Arc<dyn StoreBackend>)
+            }
+            (store, _) => store.clone(),
         };
-        LuaBinding::register(&vm, self.input.clone(), self.store.clone(), None)
-            .expect("failed to initalize the binding");
-        Arc::new(Evaluation {
+        let input = self.input.clone().expect("input is always set");
+        LuaBinding::register(&vm, input.clone(), store.clone(), None)?;
+        if let Some(max) = self.max_input_bytes {
+            vm.set_app_data(MaxInputBytes(max));
+        }
+        register_globals(&vm, &self.globals)?;
+        register_args(&vm, &self.args, &self.named_args)?;
+        register_app_state(&vm, self.app_state.as_ref())?;
+        register_catalog(&vm, &self.catalog)?;
+        #[cfg(feature = "protobuf")]
+        register_proto_descriptors(&vm, &self.proto_descriptors)?;
+        register_metrics(&vm, &self.metrics)?;
+        #[cfg(feature = "coroutine")]
+        register_coroutine(&vm)?;
+        register_assert(&vm, self.snapshots.as_ref())?;
+        if let Some(debugger) = &self.debugger {
+            debugger.set_source(&self.script);
+        }
+        // bindings are registered beforehand, so they don't fail under a tight limit
+        if let Some(limit) = self.memory_limit {
+            vm.set_memory_limit(limit)?;
+            vm.set_app_data(MemoryLimit(limit));
+        }
+        Ok(Arc::new(Evaluation {
+            app_state: self.app_state.clone(),
             compiled,
-            input: self.input.clone(),
+            debugger: self.debugger.clone(),
+            deterministic: self.deterministic,
+            // the snapshot is only kept to replay invocations
+            env: match self.history {
+                Some(_) => permissions.env().vars().clone(),
+                None => BTreeMap::new(),
+            },
+            full_collect: self.gc.full_collect(),
+            history: self.history.clone(),
+            history_input: self.history_input.clone(),
+            input,
+            input_buffer_size: self.input_buffer_size,
+            max_instructions: self.max_instructions,
+            memory_limit: self.memory_limit,
             name: self.name.clone().unwrap_or_default(),
+            payload_validator: self.payload_validator.clone(),
+            profiler: self.profiler.clone(),
             script: self.script.clone(),
-            store: self.store.clone(),
+            source_map: self.source_map.clone(),
+            store,
             timeout: self.timeout.unwrap_or(DEFAULT_TIMEOUT),
             vm,
-        })
+        }))
     }
 }
 
@@ -191,6 +815,8 @@ where
     evaluation: Arc<Evaluation<R>>,
     max_memory_usage: usize,
     payload: Value,
+    results: Vec<Value>,
+    used_memory: usize,
 }
 
 impl<R> Solution<R>
@@ -212,25 +838,254 @@ where
         self.max_memory_usage
     }
 
-    /// Get evaluated payload.
+    /// Get memory used by the Lua virtual machine in bytes after evaluation,
+    /// sampled after a full garbage-collection cycle if enabled by [`GcOptions`].
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    /// Get evaluated payload, which is the first value returned by the function.
     pub fn payload(&self) -> &Value {
         &self.payload
     }
 
+    /// Get all values returned by the function.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let e = EvaluationBuilder::new("return 'created', 201", empty()).build()?;
+    /// let res = e.evaluate()?;
+    /// assert_eq!(&json!("created"), res.payload());
+    /// assert_eq!(&[json!("created"), json!(201)], res.results());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn results(&self) -> &[Value] {
+        &self.results
+    }
+
     /// Render the solution.
-    pub fn write<W>(&self, mut f: W, json: bool) -> Result<()>
+    pub fn write<W>(&self, f: W, json: bool) -> Result<()>
+    where
+        W: Write,
+    {
+        Self::write_value(f, &self.payload, json)
+    }
+
+    /// Render all values returned by the function, one value per line.
+    pub fn write_results<W>(&self, mut f: W, json: bool) -> Result<()>
+    where
+        W: Write,
+    {
+        for (idx, value) in self.results.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            Self::write_value(&mut f, value, json)?;
+        }
+        Ok(())
+    }
+
+    /// Render the solution indented like JSON, with colors unless disabled by the options
+    /// or standard output is not a terminal.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let e = EvaluationBuilder::new("return { a = { 1, true } }", empty()).build()?;
+    /// let mut buf = String::new();
+    /// e.evaluate()?.write_pretty(&mut buf, &PrintOptions::no_color())?;
+    /// assert_eq!("{\n  \"a\": [\n    1,\n    true\n  ]\n}", buf);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_pretty<W>(&self, mut f: W, options: &PrintOptions) -> Result<()>
+    where
+        W: Write,
+    {
+        let colored = !options.no_color && console::colors_enabled();
+        write_pretty_value(&mut f, &self.payload, 0, colored)
+    }
+
+    /// Render all values returned by the function like [`Solution::write_pretty`], one value per line.
+    pub fn write_results_pretty<W>(&self, mut f: W, options: &PrintOptions) -> Result<()>
+    where
+        W: Write,
+    {
+        let colored = !options.no_color && console::colors_enabled();
+        for (idx, value) in self.results.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            write_pretty_value(&mut f, value, 0, colored)?;
+        }
+        Ok(())
+    }
+
+    fn write_value<W>(mut f: W, value: &Value, json: bool) -> Result<()>
     where
         W: Write,
     {
         if json {
-            let res = serde_json::to_string(&self.payload)?;
+            let res = serde_json::to_string(value)?;
             Ok(write!(f, "{}", res)?)
         } else {
-            match &self.payload {
+            match value {
                 Value::String(s) => Ok(write!(f, "{}", s)?),
-                _ => Ok(write!(f, "{}", self.payload)?),
+                _ => Ok(write!(f, "{}", value)?),
+            }
+        }
+    }
+}
+
+fn write_pretty_value<W>(f: &mut W, value: &Value, indent: usize, colored: bool) -> Result<()>
+where
+    W: Write,
+{
+    let paint = |text: String, style: console::Style| {
+        if colored {
+            style.force_styling(true).apply_to(text).to_string()
+        } else {
+            text
+        }
+    };
+    let pad = "  ".repeat(indent + 1);
+    let end = "  ".repeat(indent);
+    match value {
+        Value::Array(values) if !values.is_empty() => {
+            writeln!(f, "[")?;
+            for (idx, v) in values.iter().enumerate() {
+                write!(f, "{pad}")?;
+                write_pretty_value(f, v, indent + 1, colored)?;
+                writeln!(f, "{}", if idx + 1 < values.len() { "," } else { "" })?;
             }
+            write!(f, "{end}]")?;
         }
+        Value::Object(map) if !map.is_empty() => {
+            writeln!(f, "{{")?;
+            for (idx, (k, v)) in map.iter().enumerate() {
+                let key = paint(serde_json::to_string(k)?, console::Style::new().blue());
+                write!(f, "{pad}{key}: ")?;
+                write_pretty_value(f, v, indent + 1, colored)?;
+                writeln!(f, "{}", if idx + 1 < map.len() { "," } else { "" })?;
+            }
+            write!(f, "{end}}}")?;
+        }
+        Value::String(_) => {
+            let text = paint(serde_json::to_string(value)?, console::Style::new().green());
+            write!(f, "{text}")?;
+        }
+        Value::Number(_) => write!(
+            f,
+            "{}",
+            paint(value.to_string(), console::Style::new().yellow())
+        )?,
+        _ => write!(
+            f,
+            "{}",
+            paint(value.to_string(), console::Style::new().magenta())
+        )?,
+    }
+    Ok(())
+}
+
+/// Deserialize the payload into the type, telling where it doesn't match.
+fn deserialize_payload<T>(payload: &Value) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    serde_path_to_error::deserialize(payload).map_err(|e| Error::InvalidPayload {
+        path: e.path().to_string(),
+        message: e.into_inner().to_string(),
+    })
+}
+
+type ValidateFn = dyn Fn(&Value) -> Result<()> + Send + Sync;
+
+/// Validator of the payload, see [`EvaluationBuilder::payload_validator`].
+#[derive(Clone)]
+pub struct PayloadValidator(Arc<ValidateFn>);
+
+impl PayloadValidator {
+    /// Create a validator with the function, which returns [`Error::InvalidPayload`]
+    /// when the payload is invalid.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    ///
+    /// let validator = PayloadValidator::new(|payload| match payload.as_i64() {
+    ///     Some(n) if n > 0 => Ok(()),
+    ///     _ => Err(Error::InvalidPayload {
+    ///         path: ".".to_string(),
+    ///         message: "positive integer expected".to_string(),
+    ///     }),
+    /// });
+    /// let e = EvaluationBuilder::new("return -1", empty())
+    ///     .payload_validator(validator)
+    ///     .build().unwrap();
+    /// assert!(e.evaluate().is_err());
+    /// ```
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Value) -> Result<()> + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    /// Create a validator checking the payload can be deserialized into the type.
+    pub fn of<T>() -> Self
+    where
+        T: DeserializeOwned,
+    {
+        Self::new(|payload| deserialize_payload::<T>(payload).map(|_| ()))
+    }
+
+    /// Validate the payload.
+    pub fn validate(&self, payload: &Value) -> Result<()> {
+        (self.0)(payload)
+    }
+}
+
+impl Debug for PayloadValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PayloadValidator").finish_non_exhaustive()
+    }
+}
+
+/// Solution of [`Evaluation::evaluate_typed`] with the payload deserialized into the type.
+#[derive(Debug)]
+pub struct TypedSolution<T, R>
+where
+    for<'lua> R: 'lua + Read,
+{
+    payload: T,
+    solution: Solution<R>,
+}
+
+impl<T, R> TypedSolution<T, R>
+where
+    for<'lua> R: 'lua + Read,
+{
+    /// Get the deserialized payload.
+    pub fn payload(&self) -> &T {
+        &self.payload
+    }
+
+    /// Take the deserialized payload.
+    pub fn into_payload(self) -> T {
+        self.payload
+    }
+
+    /// Get the solution, e.g. for duration and memory usage.
+    pub fn solution(&self) -> &Solution<R> {
+        &self.solution
     }
 }
 
@@ -240,11 +1095,24 @@ pub struct Evaluation<R>
 where
     for<'lua> R: 'lua + Read,
 {
+    app_state: Option<Value>,
     compiled: Vec<u8>,
+    debugger: Option<Debugger>,
+    deterministic: bool,
+    env: BTreeMap<String, String>,
+    full_collect: bool,
+    history: Option<Store>,
+    history_input: Option<InputCopy>,
     input: Input<R>,
+    input_buffer_size: Option<usize>,
+    max_instructions: Option<u64>,
+    memory_limit: Option<usize>,
     name: String,
+    payload_validator: Option<PayloadValidator>,
+    profiler: Option<Profiler>,
     script: String,
-    store: Option<Store>,
+    source_map: Option<SourceMap>,
+    store: Option<Arc<dyn StoreBackend>>,
     timeout: Duration,
     vm: Lua,
 }
@@ -261,14 +1129,14 @@ where
     /// use lmb::*;
     ///
     /// # fn main() -> Result<()> {
-    /// let e = EvaluationBuilder::new("return 1+1", empty()).build();
+    /// let e = EvaluationBuilder::new("return 1+1", empty()).build()?;
     /// let res = e.evaluate()?;
     /// assert_eq!(&json!(2), res.payload());
     /// # Ok(())
     /// # }
     /// ```
     pub fn evaluate(self: &Arc<Self>) -> Result<Solution<R>> {
-        self.do_evaluate(None)
+        self.do_evaluate(None, None)
     }
 
     /// Evaluate the function with a state.
@@ -279,16 +1147,67 @@ where
     /// use lmb::*;
     ///
     /// # fn main() -> Result<()> {
-    /// let e = EvaluationBuilder::new("return 1+1", empty()).build();
-    /// let state = Arc::new(State::new());
+    /// let e = EvaluationBuilder::new("return 1+1", empty()).build()?;
+    /// let state = Arc::new(InvocationState::new());
     /// state.insert(StateKey::from("bool"), true.into());
     /// let res = e.evaluate_with_state(state)?;
     /// assert_eq!(&json!(2), res.payload());
     /// # Ok(())
     /// # }
     /// ```
-    pub fn evaluate_with_state(self: &Arc<Self>, state: Arc<State>) -> Result<Solution<R>> {
-        self.do_evaluate(Some(state))
+    pub fn evaluate_with_state(
+        self: &Arc<Self>,
+        state: Arc<InvocationState>,
+    ) -> Result<Solution<R>> {
+        self.do_evaluate(Some(state), None)
+    }
+
+    /// Evaluate the function with the state serialized into `state` of `@lmb`
+    /// in place of the application state, and deserialize the payload into the type.
+    /// [`Error::InvalidPayload`] tells where the payload doesn't match the type.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Serialize)]
+    /// struct Order {
+    ///     items: Vec<u32>,
+    /// }
+    ///
+    /// #[derive(Debug, Deserialize, PartialEq)]
+    /// struct Total {
+    ///     count: usize,
+    ///     sum: u32,
+    /// }
+    ///
+    /// # fn main() -> Result<()> {
+    /// let script = r#"
+    /// local m = require('@lmb')
+    /// local sum = 0
+    /// for _, n in ipairs(m.state.items) do sum += n end
+    /// return { count = #m.state.items, sum = sum }
+    /// "#;
+    /// let e = EvaluationBuilder::new(script, empty()).build()?;
+    /// let res = e.evaluate_typed::<Total, _>(&Order { items: vec![1, 2, 3] })?;
+    /// assert_eq!(&Total { count: 3, sum: 6 }, res.payload());
+    ///
+    /// let e = EvaluationBuilder::new("return { count = 1, sum = 'a' }", empty()).build()?;
+    /// let err = e.evaluate_typed::<Total, _>(&()).unwrap_err();
+    /// assert!(err.to_string().starts_with("invalid payload at sum: invalid type"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn evaluate_typed<T, S>(self: &Arc<Self>, state: &S) -> Result<TypedSolution<T, R>>
+    where
+        T: DeserializeOwned,
+        S: Serialize + ?Sized,
+    {
+        let state = serde_json::to_value(state)?;
+        let solution = self.do_evaluate(None, Some(&state))?;
+        let payload = deserialize_payload(&solution.payload)?;
+        Ok(TypedSolution { payload, solution })
     }
 
     /// Get name.
@@ -296,29 +1215,41 @@ where
         &self.name
     }
 
-    /// Schedule the script.
+    /// Schedule the script until the schedule ends, errors reach the bail threshold,
+    /// or SIGINT or SIGTERM is received by scripts listening to signals, see [`crate::listen_signals`].
     pub fn schedule(self: Arc<Self>, options: &ScheduleOptions) {
         let bail = options.bail();
         debug!(bail, "script scheduled");
         let mut error_count = 0usize;
-        loop {
+        let mut last = Utc::now();
+        while let Some(next) = options.next_after(&last) {
+            debug!(%next, "next run");
+            if !sleep_until(next) {
+                info!("stop because signal received");
+                break;
+            }
             let now = Utc::now();
-            if let Some(next) = options.schedule().upcoming(Utc).take(1).next() {
-                debug!(%next, "next run");
-                let elapsed = next - now;
-                thread::sleep(elapsed.to_std().expect("failed to fetch next schedule"));
-                if let Err(err) = self.evaluate() {
-                    warn!(?err, "failed to evaluate");
-                    if bail > 0 {
-                        debug!(bail, error_count, "check bail threshold");
-                        error_count += 1;
-                        if error_count == bail {
-                            error!("bail because threshold reached");
-                            break;
-                        }
+            let missed = options.next_after(&next).is_some_and(|n| n <= now);
+            last = if missed { now } else { next };
+            if missed && options.missed_runs() == MissedRunPolicy::Skip {
+                warn!(%next, "skip missed runs");
+                continue;
+            }
+            if let Err(err) = self.evaluate() {
+                warn!(?err, "failed to evaluate");
+                if bail > 0 {
+                    debug!(bail, error_count, "check bail threshold");
+                    error_count += 1;
+                    if error_count == bail {
+                        error!("bail because threshold reached");
+                        break;
                     }
                 }
             }
+            if is_interrupted() {
+                info!("stop because signal received");
+                break;
+            }
         }
     }
 
@@ -327,6 +1258,19 @@ where
         &self.script
     }
 
+    /// Get the source of the file in errors. It's the script itself without a source map.
+    pub fn source(&self, file: &str) -> Option<&str> {
+        match &self.source_map {
+            Some(source_map) => source_map.source(file),
+            None => (file == self.name).then_some(self.script.as_str()),
+        }
+    }
+
+    /// Get the source map.
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.source_map.as_ref()
+    }
+
     /// Replace the function input after the container is built.
     ///
     /// ```rust
@@ -336,7 +1280,7 @@ where
     ///
     /// # fn main() -> Result<()> {
     /// let script = "return io.read('*a')";
-    /// let mut e = EvaluationBuilder::new(script, Cursor::new("1")).build();
+    /// let mut e = EvaluationBuilder::new(script, Cursor::new("1")).build()?;
     ///
     /// let r = e.evaluate()?;
     /// assert_eq!(&json!("1"), r.payload());
@@ -349,7 +1293,10 @@ where
     /// # }
     /// ```
     pub fn set_input(self: &Arc<Self>, input: R) {
-        *self.input.lock() = BufReader::new(input);
+        *self.input.lock() = match self.input_buffer_size {
+            Some(capacity) => BufReader::with_capacity(capacity, input),
+            None => BufReader::new(input),
+        };
     }
 
     /// Render the script.
@@ -360,7 +1307,7 @@ where
     ///
     /// # fn main() -> Result<()> {
     /// let script = "return 1";
-    /// let e = EvaluationBuilder::new(script, empty()).build();
+    /// let e = EvaluationBuilder::new(script, empty()).build()?;
     ///
     /// let mut buf = String::new();
     /// e.write_script(&mut buf, &PrintOptions::no_color())?;
@@ -397,8 +1344,46 @@ where
         Ok(controller.run(inputs, Some(&mut f))?)
     }
 
-    fn do_evaluate(self: &Arc<Self>, state: Option<Arc<State>>) -> Result<Solution<R>> {
+    fn do_evaluate(
+        self: &Arc<Self>,
+        state: Option<Arc<InvocationState>>,
+        initial_state: Option<&Value>,
+    ) -> Result<Solution<R>> {
+        let Some(history) = &self.history else {
+            return self.run(state, initial_state);
+        };
+        let started_at = Utc::now();
+        let start = Instant::now();
+        let res = self.run(state, initial_state);
+        let (duration, used_memory, outcome) = match &res {
+            Ok(s) => (s.duration, s.used_memory, Ok(s.payload.to_string())),
+            Err(err) => (start.elapsed(), self.vm.used_memory(), Err(err.to_string())),
+        };
+        let input = self
+            .history_input
+            .as_ref()
+            .map(InputCopy::take)
+            .unwrap_or_default();
+        let state = initial_state.or(self.app_state.as_ref()).cloned();
+        let invocation = Invocation::new(&self.name, started_at, duration, used_memory, outcome)
+            .with_replay(&self.script, input, state, self.env.clone());
+        if let Err(err) = history.record_invocation(&invocation) {
+            warn!(?err, "failed to record the invocation");
+        }
+        res
+    }
+
+    fn run(
+        self: &Arc<Self>,
+        state: Option<Arc<InvocationState>>,
+        initial_state: Option<&Value>,
+    ) -> Result<Solution<R>> {
         let vm = &self.vm;
+        #[cfg(feature = "http")]
+        match state.as_ref().and_then(|s| s.request_id()) {
+            Some(id) => vm.set_app_data(crate::RequestId(id)),
+            None => vm.remove_app_data::<crate::RequestId>(),
+        };
         if state.is_some() {
             LuaBinding::register(vm, self.input.clone(), self.store.clone(), state)?;
         }
@@ -407,12 +1392,45 @@ where
         let timeout = self.timeout;
 
         let start = Instant::now();
+        vm.set_app_data(Started(start));
+        // a timeout too long to be represented is no deadline
+        match start.checked_add(timeout) {
+            Some(deadline) => vm.set_app_data(Deadline(deadline)),
+            None => vm.remove_app_data::<Deadline>(),
+        };
+        reset_state(vm, initial_state.or(self.app_state.as_ref()))?;
+        #[cfg(feature = "coroutine")]
+        reset_coroutines(vm)?;
+        #[cfg(feature = "http")]
+        vm.set_app_data(crate::HttpUsage::default());
+        if let Some(profiler) = &self.profiler {
+            profiler.start();
+        }
+        let instructions = Arc::new(AtomicU64::new(0));
+        let max_instructions = self.max_instructions;
         self.vm.set_interrupt({
+            let debugger = self.debugger.clone();
+            let instructions = Arc::clone(&instructions);
             let max_memory = Arc::clone(&max_memory);
+            let profiler = self.profiler.clone();
             move |vm| {
                 let used_memory = vm.used_memory();
                 max_memory.fetch_max(used_memory, Ordering::Relaxed);
-                if start.elapsed() > timeout {
+                // keep failing after the budget is exceeded, so the script cannot recover with pcall
+                if let Some(limit) = max_instructions {
+                    if instructions.fetch_add(1, Ordering::Relaxed) >= limit {
+                        return Err(Error::BudgetExceeded { limit }.into_lua_err());
+                    }
+                }
+                if let Some(profiler) = &profiler {
+                    profiler.sample(vm);
+                }
+                let mut elapsed = start.elapsed();
+                if let Some(debugger) = &debugger {
+                    debugger.on_interrupt(vm)?;
+                    elapsed = elapsed.saturating_sub(debugger.paused());
+                }
+                if elapsed > timeout {
                     vm.remove_interrupt();
                     return Err(mlua::Error::runtime("timeout"));
                 }
@@ -424,38 +1442,87 @@ where
         let chunk = vm.load(&self.compiled).set_name(script_name);
 
         let _s = trace_span!("evaluate").entered();
-        let result = vm.from_value(chunk.eval()?)?;
+        if self.deterministic {
+            vm.set_app_data(FrozenTime(Utc::now().timestamp()));
+            let randomseed = vm
+                .globals()
+                .get::<_, LuaTable<'_>>("math")?
+                .get::<_, LuaFunction<'_>>("randomseed")?;
+            randomseed.call::<_, ()>(0)?;
+        } else {
+            // scripts write files to the scratch directory, which deterministic mode forbids
+            vm.set_app_data(ScratchDir::default());
+        }
+        let result = chunk.eval::<LuaMultiValue<'_>>();
+        // delete the scratch directory even if the evaluation fails
+        vm.remove_app_data::<ScratchDir>();
+        if let Some(limit) = max_instructions {
+            if instructions.load(Ordering::Acquire) > limit {
+                return Err(Error::BudgetExceeded { limit });
+            }
+        }
+        if let (Some(limit), Err(err)) = (self.memory_limit, &result) {
+            if is_memory_error(err) {
+                // release what the script allocated, so the virtual machine can be reused
+                vm.gc_collect()?;
+                return Err(Error::MemoryLimitExceeded { limit });
+            }
+        }
+        let results = result
+            .map_err(|err| match &self.source_map {
+                Some(source_map) => source_map.rewrite_error(script_name, err),
+                None => err,
+            })?
+            .into_iter()
+            .map(|v| vm.from_value(v))
+            .collect::<LuaResult<Vec<Value>>>()?;
+        let payload = results.first().cloned().unwrap_or(Value::Null);
+        if let Some(validator) = &self.payload_validator {
+            validator.validate(&payload)?;
+        }
 
         let duration = start.elapsed();
         let max_memory = max_memory.load(Ordering::Acquire);
-        debug!(?duration, %script_name, ?max_memory, "script evaluated");
+        if self.full_collect {
+            let _s = trace_span!("full_collect").entered();
+            vm.gc_collect()?;
+        }
+        let used_memory = vm.used_memory();
+        debug!(?duration, %script_name, ?max_memory, ?used_memory, "script evaluated");
         Ok(Solution {
             duration,
             evaluation: self.clone(),
             max_memory_usage: max_memory,
-            payload: result,
+            payload,
+            results,
+            used_memory,
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use mlua::prelude::*;
     use parking_lot::Mutex;
+    use serde::Deserialize;
     use serde_json::{json, Value};
     use std::{
         fs,
-        io::{empty, BufReader},
+        io::{empty, BufReader, Cursor},
         sync::Arc,
         time::{Duration, Instant},
     };
     use test_case::test_case;
 
-    use crate::{EvaluationBuilder, State, StateKey};
+    use crate::{
+        Error, EvaluationBuilder, GcOptions, InvocationState, ModuleProvider, PayloadValidator,
+        PrintOptions, StateKey,
+    };
 
     #[test_case("./lua-examples/error.lua")]
     fn error_in_script(path: &str) {
         let script = fs::read_to_string(path).unwrap();
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         assert!(e.evaluate().is_err());
     }
 
@@ -470,11 +1537,141 @@ mod tests {
         let script = fs::read_to_string(format!("./lua-examples/{filename}")).unwrap();
         let e = EvaluationBuilder::new(&script, input.as_bytes())
             .default_store()
-            .build();
+            .build()
+            .unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(expected, res.payload);
     }
 
+    #[derive(Clone, Debug)]
+    struct Counter(i64);
+
+    impl LuaUserData for Counter {
+        fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("value", |_, this| Ok(this.0));
+        }
+    }
+
+    #[test]
+    fn custom_module() {
+        let script = "return require('@test/counter').value";
+        let e = EvaluationBuilder::new(script, empty())
+            .module("@test/counter", Counter(1))
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(json!(1), res.payload);
+    }
+
+    #[test_case("@lmb")]
+    #[test_case("@lmb/json")]
+    fn custom_module_reserved_name(name: &str) {
+        let err = EvaluationBuilder::new("return 1", empty())
+            .module(name, Counter(1))
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, Error::ReservedModule(n) if n == name));
+    }
+
+    #[derive(Debug)]
+    struct Broken;
+
+    impl ModuleProvider for Broken {
+        fn provide<'lua>(&self, _vm: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+            Err(LuaError::runtime("broken module"))
+        }
+    }
+
+    #[test]
+    fn custom_module_error() {
+        let err = EvaluationBuilder::new("return 1", empty())
+            .module("@test/broken", Broken)
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("broken module"), "{err}");
+    }
+
+    #[test]
+    fn evaluate_typed() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Item {
+            name: String,
+        }
+
+        let script = "return { { name = 'a' }, { name = require('@lmb').state.name } }";
+        let e = EvaluationBuilder::new(script, empty())
+            .app_state(Some(json!({ "name": "app" })))
+            .build()
+            .unwrap();
+        let res = e
+            .evaluate_typed::<Vec<Item>, _>(&json!({ "name": "b" }))
+            .unwrap();
+        let names: Vec<_> = res.into_payload().into_iter().map(|i| i.name).collect();
+        assert_eq!(vec!["a", "b"], names);
+
+        // the application state is restored for other evaluations
+        let res = e.evaluate().unwrap();
+        assert_eq!(json!([{ "name": "a" }, { "name": "app" }]), res.payload);
+
+        let err = e.evaluate_typed::<Vec<Item>, _>(&json!({})).unwrap_err();
+        let Error::InvalidPayload { path, message } = err else {
+            panic!("unexpected error {err:?}");
+        };
+        assert_eq!("[1]", path);
+        assert_eq!("missing field `name`", message);
+    }
+
+    #[test]
+    fn payload_validator() {
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Order {
+            items: Vec<u32>,
+        }
+
+        let e = EvaluationBuilder::new("return { items = { 1, 'a' } }", empty())
+            .payload_validator(PayloadValidator::of::<Order>())
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        let Error::InvalidPayload { path, .. } = err else {
+            panic!("unexpected error {err:?}");
+        };
+        assert_eq!("items[1]", path);
+
+        let e = EvaluationBuilder::new("return { items = { 1, 2 } }", empty())
+            .payload_validator(PayloadValidator::of::<Order>())
+            .build()
+            .unwrap();
+        assert_eq!(json!({ "items": [1, 2] }), e.evaluate().unwrap().payload);
+    }
+
+    #[test]
+    fn deterministic() {
+        let script = r#"
+        local m = require('@lmb')
+        local now = os.time()
+        return {
+          random = { math.random(), math.random(1, 100) },
+          frozen = now == os.time() and os.clock() == 0,
+          date = os.date('!%Y', now) == os.date('!%Y'),
+          http = pcall(require, '@lmb/http'),
+          tmpdir = m.tmpdir,
+        }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .deterministic(true)
+            .build()
+            .unwrap();
+        let first = e.evaluate().unwrap();
+        let payload = first.payload();
+        assert_eq!(json!(true), payload["frozen"]);
+        assert_eq!(json!(true), payload["date"]);
+        assert_eq!(json!(false), payload["http"]);
+        assert_eq!(Value::Null, payload["tmpdir"]);
+        assert_eq!(payload["random"], e.evaluate().unwrap().payload()["random"]);
+    }
+
     #[test]
     fn evaluate_infinite_loop() {
         let timer = Instant::now();
@@ -482,7 +1679,8 @@ mod tests {
 
         let e = EvaluationBuilder::new(r#"while true do end"#, empty())
             .timeout(Some(timeout))
-            .build();
+            .build()
+            .unwrap();
         let res = e.evaluate();
         assert!(res.is_err());
 
@@ -490,20 +1688,132 @@ mod tests {
         assert!(elapsed < 500, "actual elapsed {elapsed:?}"); // 500% error
     }
 
+    #[test]
+    fn evaluate_long_timeout() {
+        // a timeout too long to be represented is no deadline instead of a panic
+        let script = "return require('@lmb').runtime.stats().remaining == nil";
+        let e = EvaluationBuilder::new(script, empty())
+            .timeout(Some(Duration::from_secs(u64::MAX)))
+            .build()
+            .unwrap();
+        assert_eq!(json!(true), e.evaluate().unwrap().payload);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let script = "local t = {} for i = 1, 1e7 do t[i] = string.rep('a', 64) .. i end";
+        let limit = 4 * 1024 * 1024;
+        let e = EvaluationBuilder::new(script, empty())
+            .memory_limit(Some(limit))
+            .build()
+            .unwrap();
+        for _ in 0..2 {
+            let err = e.evaluate().unwrap_err();
+            assert!(matches!(err, Error::MemoryLimitExceeded { limit: l } if l == limit));
+        }
+        assert!(e.vm.used_memory() < limit);
+    }
+
+    #[test]
+    fn max_instructions() {
+        let e = EvaluationBuilder::new("while true do end", empty())
+            .max_instructions(Some(1_000))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            e.evaluate(),
+            Err(Error::BudgetExceeded { limit: 1_000 })
+        ));
+
+        // the error cannot be caught by the script
+        let script = "pcall(function() while true do end end) return 1";
+        let e = EvaluationBuilder::new(script, empty())
+            .max_instructions(Some(1_000))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            e.evaluate(),
+            Err(Error::BudgetExceeded { limit: 1_000 })
+        ));
+
+        let script = "local s = 0 for i = 1, 10 do s = s + i end return s";
+        let e = EvaluationBuilder::new(script, empty())
+            .max_instructions(Some(1_000))
+            .build()
+            .unwrap();
+        assert_eq!(&json!(55), e.evaluate().unwrap().payload());
+    }
+
+    #[test]
+    fn full_collect() {
+        let script = r#"
+        local t = {}
+        for i = 1, 100000 do
+          t[i] = tostring(i)
+        end
+        return #t
+        "#;
+        let mut gc = GcOptions::default();
+        gc.set_full_collect(true);
+        let e = EvaluationBuilder::new(script, empty())
+            .gc(gc)
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(100000), res.payload());
+        assert!(res.used_memory() < res.max_memory_usage());
+    }
+
     #[test_case("return 1+1", json!(2))]
     #[test_case("return 'a'..1", json!("a1"))]
     #[test_case("return require('@lmb')._VERSION", json!(env!("APP_VERSION")))]
     fn evaluate_scripts(script: &str, expected: Value) {
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(expected, res.payload);
     }
 
+    #[test]
+    fn shebang() {
+        let script = "#!/usr/bin/env lmb\nreturn require('@lmb').args";
+        let e = EvaluationBuilder::new(script, empty())
+            .args(["a", "b"])
+            .build()
+            .unwrap();
+        assert_eq!(&json!(["a", "b"]), e.evaluate().unwrap().payload());
+
+        let e = EvaluationBuilder::new("#!/usr/bin/env lmb\nreturn nil + 1", empty())
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains(":2:"), "{err}");
+    }
+
+    #[test]
+    fn globals() {
+        let script = r#"
+        assert(not pcall(function() CONFIG.nested.a = 2 end), 'nested table is read-only')
+        assert(type(io.read) == 'function', 'built-in global is reserved')
+        COUNTER = (COUNTER or 0) + 1
+        return { CONFIG.nested.a, CONFIG.list[2], NAME, COUNTER }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .global("CONFIG", json!({ "nested": { "a": 1 }, "list": [1, 2] }))
+            .global("NAME", json!("lmb"))
+            .global("io", json!(null))
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(json!([1, 2, "lmb", 1]), res.payload);
+    }
+
     #[test]
     fn reevaluate() {
         let input = "foo\nbar";
         let script = "return io.read('*l')";
-        let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
 
         let res = e.evaluate().unwrap();
         assert_eq!(json!("foo"), res.payload);
@@ -515,7 +1825,7 @@ mod tests {
     #[test]
     fn replace_input() {
         let script = "return io.read('*a')";
-        let e = EvaluationBuilder::new(script, &b"0"[..]).build();
+        let e = EvaluationBuilder::new(script, &b"0"[..]).build().unwrap();
 
         let res = e.evaluate().unwrap();
         assert_eq!(json!("0"), res.payload);
@@ -529,14 +1839,16 @@ mod tests {
     #[test]
     fn syntax_error() {
         let script = "ret true"; // code with syntax error
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         assert!(e.evaluate().is_err());
     }
 
     #[test]
     fn with_bufreader() {
         let input = Arc::new(Mutex::new(BufReader::new(empty())));
-        let e = EvaluationBuilder::with_reader("return nil", input.clone()).build();
+        let e = EvaluationBuilder::with_reader("return nil", input.clone())
+            .build()
+            .unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(json!(null), res.payload);
         let _input = input;
@@ -544,8 +1856,10 @@ mod tests {
 
     #[test]
     fn with_state() {
-        let e = EvaluationBuilder::new(r#"return require("@lmb").request"#, empty()).build();
-        let state = Arc::new(State::new());
+        let e = EvaluationBuilder::new(r#"return require("@lmb").request"#, empty())
+            .build()
+            .unwrap();
+        let state = Arc::new(InvocationState::new());
         state.insert(StateKey::Request, 1.into());
         {
             let res = e.evaluate_with_state(state.clone()).unwrap();
@@ -561,10 +1875,48 @@ mod tests {
     #[test]
     fn write_solution() {
         let script = "return 1+1";
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let solution = e.evaluate().unwrap();
         let mut buf = String::new();
         solution.write(&mut buf, false).unwrap();
         assert_eq!("2", buf);
     }
+
+    #[test]
+    fn write_results() {
+        let script = "return 1+1, 'a', { b = true }";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let solution = e.evaluate().unwrap();
+        assert_eq!(json!(2), solution.payload);
+        let mut buf = String::new();
+        solution.write_results(&mut buf, true).unwrap();
+        assert_eq!("2\n\"a\"\n{\"b\":true}", buf);
+    }
+
+    #[test]
+    fn input_buffer_size() {
+        let script = "return io.read('*a')";
+        let e = EvaluationBuilder::new(script, Cursor::new("1"))
+            .input_buffer_size(1)
+            .build()
+            .unwrap();
+        assert_eq!(1, e.input.lock().capacity());
+        assert_eq!(&json!("1"), e.evaluate().unwrap().payload());
+
+        e.set_input(Cursor::new("23"));
+        assert_eq!(1, e.input.lock().capacity());
+        assert_eq!(&json!("23"), e.evaluate().unwrap().payload());
+    }
+
+    #[test]
+    fn write_results_pretty() {
+        let script = "return { a = {}, b = { 'x' } }, 1";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let solution = e.evaluate().unwrap();
+        let mut buf = String::new();
+        solution
+            .write_results_pretty(&mut buf, &PrintOptions::no_color())
+            .unwrap();
+        assert_eq!("{\n  \"a\": {},\n  \"b\": [\n    \"x\"\n  ]\n}\n1", buf);
+    }
 }
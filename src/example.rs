@@ -49,7 +49,7 @@ impl Visitor for Example {
         let Value::String(description) = &parsed["description"] else {
             return;
         };
-        self.description = description.to_string();
+        self.description.clone_from(description);
         self.done = true;
     }
 }
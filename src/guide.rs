@@ -44,20 +44,16 @@ pub static GUIDES: Lazy<Vec<Guide>> = Lazy::new(|| {
         let mut is_heading = false;
         for event in parser {
             match event {
-                Event::Start(Tag::Heading { level, .. }) => {
-                    if !is_heading && level == HeadingLevel::H1 {
-                        is_heading = true;
-                    }
+                Event::Start(Tag::Heading { level, .. })
+                    if !is_heading && level == HeadingLevel::H1 =>
+                {
+                    is_heading = true;
                 }
-                Event::End(TagEnd::Heading(_)) => {
-                    if is_heading {
-                        is_heading = false;
-                    }
+                Event::End(TagEnd::Heading(_)) if is_heading => {
+                    is_heading = false;
                 }
-                Event::Text(s) => {
-                    if is_heading {
-                        title.push_str(&s);
-                    }
+                Event::Text(s) if is_heading => {
+                    title.push_str(&s);
                 }
                 _ => {}
             }
@@ -0,0 +1,324 @@
+use serde_json::Value;
+use std::{collections::HashMap, fs, path::Path, result::Result as StdResult, sync::Arc};
+
+use crate::{Error, Result};
+
+/// Default locale, which every lookup falls back to.
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Messages of lmb itself, so errors and reports of the command line can be localized.
+const BUILTIN_MESSAGES: &[(&str, &[(&str, &str)])] = &[
+    (
+        "en",
+        &[
+            (
+                "config.not_specified",
+                "config file is not specified, please specify it with --config",
+            ),
+            ("config.valid", "{path} is valid"),
+            (
+                "debug.stdin",
+                "standard input is for commands, please specify the script with --file",
+            ),
+            ("doctor.check", "check"),
+            ("doctor.detail", "detail"),
+            ("doctor.failed", "{failed} of {total} checks failed"),
+            ("doctor.status", "status"),
+            (
+                "script.required",
+                "a script or a subcommand is required, see --help for usage",
+            ),
+        ],
+    ),
+    (
+        "zh-TW",
+        &[
+            ("config.not_specified", "未指定設定檔，請以 --config 指定"),
+            ("config.valid", "{path} 有效"),
+            ("debug.stdin", "標準輸入用於指令，請以 --file 指定腳本"),
+            ("doctor.check", "檢查"),
+            ("doctor.detail", "詳細"),
+            ("doctor.failed", "{total} 項檢查中有 {failed} 項失敗"),
+            ("doctor.status", "狀態"),
+            ("script.required", "需要腳本或子命令，用法請見 --help"),
+        ],
+    ),
+];
+
+/// Messages of a locale, mapping keys to templates e.g. `hello, {name}`.
+pub type Messages = HashMap<String, String>;
+
+/// Normalize the locale e.g. `zh_TW.UTF-8` into `zh-TW`.
+pub fn normalize_locale(locale: &str) -> String {
+    let locale = locale.split(['.', '@']).next().unwrap_or_default();
+    let mut parts = locale.split(['_', '-']).filter(|p| !p.is_empty());
+    let Some(language) = parts.next() else {
+        return String::new();
+    };
+    let mut normalized = language.to_ascii_lowercase();
+    for part in parts {
+        normalized.push('-');
+        // regions are in uppercase, and scripts e.g. Hant are in title case
+        if part.len() == 2 {
+            normalized.push_str(&part.to_ascii_uppercase());
+        } else {
+            normalized.push_str(part);
+        }
+    }
+    normalized
+}
+
+/// Locale of the environment from `LC_ALL`, `LC_MESSAGES` or `LANG`, or [`DEFAULT_LOCALE`].
+pub fn locale_from_env() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .map(|l| normalize_locale(&l))
+        // the C locale has no language
+        .find(|l| !l.is_empty() && l != "c" && l != "posix")
+        .unwrap_or_else(|| DEFAULT_LOCALE.to_string())
+}
+
+/// Locales to look up in order, e.g. `zh-Hant-TW`, `zh-Hant`, `zh` and then [`DEFAULT_LOCALE`].
+pub fn fallback_locales(locale: &str) -> Vec<String> {
+    let mut locales = vec![];
+    let mut locale = normalize_locale(locale);
+    while !locale.is_empty() {
+        locales.push(locale.clone());
+        locale = locale
+            .rsplit_once('-')
+            .map(|(l, _)| l.to_string())
+            .unwrap_or_default();
+    }
+    if !locales.iter().any(|l| l == DEFAULT_LOCALE) {
+        locales.push(DEFAULT_LOCALE.to_string());
+    }
+    locales
+}
+
+/// Replace placeholders e.g. `{name}` in the template with variables.
+/// Placeholders without variables are kept.
+pub fn interpolate(template: &str, vars: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find('}')
+            .map(|end| (&after[..end], &after[end + 1..]))
+        {
+            Some((name, tail)) if vars.contains_key(name) => {
+                out.push_str(&vars[name]);
+                rest = tail;
+            }
+            _ => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Flatten nested objects into dotted keys e.g. `{"greeting":{"hello":"hi"}}` into `greeting.hello`.
+fn flatten(prefix: &str, value: &Value, messages: &mut Messages) -> StdResult<(), String> {
+    match value {
+        Value::String(s) => {
+            messages.insert(prefix.to_string(), s.clone());
+        }
+        Value::Object(o) => {
+            for (k, v) in o {
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{prefix}.{k}")
+                };
+                flatten(&key, v, messages)?;
+            }
+        }
+        _ => return Err(format!("message {prefix} should be a string")),
+    }
+    Ok(())
+}
+
+/// Message catalogs of locales, and the locale to translate into.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let mut catalog = Catalog::new("zh-TW");
+/// catalog.add("zh", [("hello", "你好，{name}")]);
+/// assert_eq!("你好，lmb", catalog.translate("hello", &[("name", "lmb")]));
+/// assert_eq!("missing", catalog.translate("missing", &[]));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Catalog {
+    locale: String,
+    catalogs: Arc<HashMap<String, Messages>>,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOCALE)
+    }
+}
+
+impl Catalog {
+    /// Create an empty catalog translating into the locale.
+    pub fn new<S: AsRef<str>>(locale: S) -> Self {
+        Self {
+            locale: normalize_locale(locale.as_ref()),
+            catalogs: Arc::default(),
+        }
+    }
+
+    /// Create a catalog of messages of lmb itself.
+    pub fn builtin<S: AsRef<str>>(locale: S) -> Self {
+        let mut catalog = Self::new(locale);
+        for (locale, messages) in BUILTIN_MESSAGES {
+            catalog.add(locale, messages.iter().copied());
+        }
+        catalog
+    }
+
+    /// Add messages of the locale, which replace existing ones with the same keys.
+    pub fn add<S, I, K, V>(&mut self, locale: S, messages: I) -> &mut Self
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        Arc::make_mut(&mut self.catalogs)
+            .entry(normalize_locale(locale.as_ref()))
+            .or_default()
+            .extend(messages.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Load catalogs from JSON files named after their locales e.g. `zh-TW.json`.
+    /// Nested objects are flattened into dotted keys.
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<&mut Self> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(locale) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let value: Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            let mut messages = Messages::new();
+            flatten("", &value, &mut messages)
+                .map_err(|e| Error::InvalidCatalog(format!("{}: {e}", path.display())))?;
+            self.add(locale, messages);
+        }
+        Ok(self)
+    }
+
+    /// Locale to translate into.
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// Set the locale to translate into.
+    pub fn set_locale<S: AsRef<str>>(&mut self, locale: S) -> &mut Self {
+        self.locale = normalize_locale(locale.as_ref());
+        self
+    }
+
+    /// Template of the key in the locale or its fallback locales.
+    pub fn lookup(&self, locale: &str, key: &str) -> Option<&str> {
+        fallback_locales(locale)
+            .iter()
+            .find_map(|l| self.catalogs.get(l)?.get(key))
+            .map(String::as_str)
+    }
+
+    /// Translate the key with variables. The key itself is returned when it is missing.
+    pub fn translate(&self, key: &str, vars: &[(&str, &str)]) -> String {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect();
+        interpolate(self.lookup(&self.locale, key).unwrap_or(key), &vars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+    use test_case::test_case;
+
+    use super::{fallback_locales, interpolate, normalize_locale, Catalog, BUILTIN_MESSAGES};
+
+    #[test_case("zh_TW.UTF-8", "zh-TW")]
+    #[test_case("en_US@euro", "en-US")]
+    #[test_case("zh-hant-tw", "zh-hant-TW")]
+    #[test_case("", "")]
+    fn normalize(locale: &str, expected: &str) {
+        assert_eq!(expected, normalize_locale(locale));
+    }
+
+    #[test]
+    fn fallback() {
+        assert_eq!(vec!["zh-TW", "zh", "en"], fallback_locales("zh_TW"));
+        assert_eq!(vec!["en-US", "en"], fallback_locales("en-US"));
+    }
+
+    #[test_case("hello, {name}", "hello, lmb")]
+    #[test_case("{missing} {name}", "{missing} lmb")]
+    #[test_case("{ {name}", "{ lmb")]
+    #[test_case("{name", "{name")]
+    fn interpolate_vars(template: &str, expected: &str) {
+        let vars = HashMap::from([("name".to_string(), "lmb".to_string())]);
+        assert_eq!(expected, interpolate(template, &vars));
+    }
+
+    #[test]
+    fn builtin_messages_complete() {
+        let (_, en) = BUILTIN_MESSAGES[0];
+        for (_, messages) in BUILTIN_MESSAGES {
+            let mut keys: Vec<_> = messages.iter().map(|(k, _)| k).collect();
+            keys.sort();
+            assert_eq!(en.iter().map(|(k, _)| k).collect::<Vec<_>>(), keys);
+        }
+        let catalog = Catalog::builtin("zh-TW");
+        assert_eq!(
+            "3 項檢查中有 1 項失敗",
+            catalog.translate("doctor.failed", &[("failed", "1"), ("total", "3")])
+        );
+    }
+
+    #[test]
+    fn load_dir() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("en.json"),
+            r#"{"greeting":{"hello":"hello"}}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("fr.json"),
+            r#"{"greeting":{"hello":"bonjour"}}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("README.md"), "ignored").unwrap();
+        let mut catalog = Catalog::new("fr-CA");
+        catalog.load_dir(dir.path()).unwrap();
+        assert_eq!("bonjour", catalog.translate("greeting.hello", &[]));
+        catalog.set_locale("de");
+        assert_eq!("hello", catalog.translate("greeting.hello", &[]));
+    }
+
+    #[test]
+    fn load_dir_invalid() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("en.json"), r#"{"n":1}"#).unwrap();
+        let err = Catalog::default().load_dir(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("message n should be a string"));
+    }
+}
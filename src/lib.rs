@@ -1,31 +1,87 @@
 #![deny(missing_debug_implementations, missing_docs)]
 
 //! A Lua function runner.
+//!
+//! Evaluation is blocking and doesn't require an async runtime,
+//! so it can be embedded in synchronous code directly:
+//!
+//! ```rust
+//! # use std::io::empty;
+//! # use serde_json::json;
+//! use lmb::*;
+//!
+//! # fn main() -> Result<()> {
+//! let e = EvaluationBuilder::new("return 1+1", empty()).build()?;
+//! let res = e.evaluate()?;
+//! assert_eq!(&json!(2), res.payload());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! In async code, evaluate on a thread where blocking is acceptable,
+//! e.g. with `tokio::task::spawn_blocking`, to avoid stalling the executor.
 
 use dashmap::DashMap;
 use include_dir::{include_dir, Dir};
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use rusqlite_migration::Migrations;
+use serde::{Deserialize, Serialize};
 use std::{fmt::Display, io::BufReader, result::Result as StdResult, sync::Arc, time::Duration};
 
+pub use bytecode::*;
+pub use cache::*;
+#[cfg(feature = "http")]
+pub use cassette::*;
 pub use check::*;
+pub use debugger::*;
+pub use dry_run::*;
 pub use error::*;
 pub use eval::*;
 pub use example::*;
 pub use guide::*;
+pub use i18n::*;
+pub use lock::*;
 pub use lua_binding::*;
+pub use metrics::*;
+pub use permissions::*;
+pub use profiler::*;
+#[cfg(feature = "protobuf")]
+pub use protobuf::*;
 pub use schedule::*;
+pub use signal::*;
+pub use source_map::*;
 pub use store::*;
+pub use typing::*;
 
+mod bytecode;
+mod cache;
+#[cfg(feature = "http")]
+mod cassette;
 mod check;
+mod debugger;
+mod dry_run;
 mod error;
 mod eval;
 mod example;
 mod guide;
+mod i18n;
+mod lock;
 mod lua_binding;
+mod metrics;
+mod permissions;
+mod profiler;
+#[cfg(feature = "protobuf")]
+mod protobuf;
 mod schedule;
+mod signal;
+mod source_map;
 mod store;
+pub mod testing;
+mod typing;
+
+/// Header of the ID identifying the HTTP request across services, see [`InvocationState::request_id`].
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
 /// Default timeout for evaluation in seconds.
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
@@ -46,12 +102,14 @@ pub type Input<R> = Arc<Mutex<BufReader<R>>>;
 pub type Result<T> = StdResult<T, Error>;
 
 /// Enum representing different state keys.
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum StateKey {
     /// HTTP request object
     Request,
     /// HTTP response object
     Response,
+    /// Session of the HTTP client
+    Session,
     /// Plain string key
     String(String),
 }
@@ -66,10 +124,178 @@ where
     }
 }
 
-/// State of each evaluation, using a [`dashmap::DashMap`].
-pub type State = DashMap<StateKey, serde_json::Value>;
+/// State of each invocation shared with bindings, e.g. the HTTP request read by `ctx.request`
+/// and the response written to `ctx.response` when serving HTTP requests.
+///
+/// ```rust
+/// # use std::{io::empty, sync::Arc};
+/// # use serde_json::json;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let state = Arc::new(InvocationState::new());
+/// state.set_request(json!({ "method": "GET" }));
+/// let script = "local m = require('@lmb'); m.response = { status_code = 201 }; return m.request.method";
+/// let e = EvaluationBuilder::new(script, empty()).build()?;
+/// let res = e.evaluate_with_state(state.clone())?;
+/// assert_eq!(&json!("GET"), res.payload());
+/// assert_eq!(Some(json!({ "status_code": 201 })), state.response());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct InvocationState(DashMap<StateKey, serde_json::Value>);
+
+impl InvocationState {
+    /// Create an empty state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the value of the key.
+    pub fn get(&self, key: &StateKey) -> Option<serde_json::Value> {
+        self.0.get(key).map(|v| v.clone())
+    }
+
+    /// Set the value of the key.
+    pub fn insert(&self, key: StateKey, value: serde_json::Value) {
+        self.0.insert(key, value);
+    }
+
+    /// Remove the key, and return the value.
+    pub fn remove(&self, key: &StateKey) -> Option<serde_json::Value> {
+        self.0.remove(key).map(|(_, v)| v)
+    }
+
+    /// HTTP request, which is read by `ctx.request`.
+    pub fn request(&self) -> Option<serde_json::Value> {
+        self.get(&StateKey::Request)
+    }
+
+    /// Set the HTTP request, which is read by `ctx.request`.
+    pub fn set_request(&self, request: serde_json::Value) {
+        self.insert(StateKey::Request, request);
+    }
+
+    /// ID of the HTTP request, which is read by `ctx.request.id`. `@lmb/http` sends it
+    /// in the [`REQUEST_ID_HEADER`] header of outbound requests, unless the script sets one.
+    pub fn request_id(&self) -> Option<String> {
+        let request = self.request()?;
+        request.get("id")?.as_str().map(ToString::to_string)
+    }
+
+    /// HTTP response written by `ctx.response`, with the status code and headers.
+    pub fn response(&self) -> Option<serde_json::Value> {
+        self.get(&StateKey::Response)
+    }
+
+    /// Set the HTTP response, which is read and written by `ctx.response`.
+    pub fn set_response(&self, response: serde_json::Value) {
+        self.insert(StateKey::Response, response);
+    }
+
+    /// Session of the HTTP client, which is read and written by `ctx.session`.
+    pub fn session(&self) -> Option<serde_json::Value> {
+        self.get(&StateKey::Session)
+    }
+
+    /// Set the session of the HTTP client, which is read and written by `ctx.session`.
+    pub fn set_session(&self, session: serde_json::Value) {
+        self.insert(StateKey::Session, session);
+    }
+}
+
+impl FromIterator<(StateKey, serde_json::Value)> for InvocationState {
+    fn from_iter<T: IntoIterator<Item = (StateKey, serde_json::Value)>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for InvocationState {
+    type Item = (StateKey, serde_json::Value);
+    type IntoIter = dashmap::iter::OwningIter<StateKey, serde_json::Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// State of each evaluation.
+#[deprecated(note = "use InvocationState instead")]
+pub type State = InvocationState;
+
+/// Options for tuning the incremental garbage collector of Lua.
+/// Parameters left unset keep the defaults of Luau.
+///
+/// ```rust
+/// # use std::io::empty;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let mut gc = GcOptions::default();
+/// gc.set_pause(Some(200)).set_full_collect(true);
+/// let e = EvaluationBuilder::new("return 1", empty()).gc(gc).build()?;
+/// let res = e.evaluate()?;
+/// assert!(res.used_memory() > 0);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GcOptions {
+    full_collect: bool,
+    pause: Option<u32>,
+    step_multiplier: Option<u32>,
+    step_size: Option<u32>,
+}
+
+impl GcOptions {
+    /// Get whether a full garbage-collection cycle runs after each evaluation.
+    pub fn full_collect(&self) -> bool {
+        self.full_collect
+    }
+
+    /// Get pause, which is the heap size goal in percent in Luau.
+    pub fn pause(&self) -> Option<u32> {
+        self.pause
+    }
+
+    /// Get step multiplier in percent.
+    pub fn step_multiplier(&self) -> Option<u32> {
+        self.step_multiplier
+    }
+
+    /// Get step size in kilobytes.
+    pub fn step_size(&self) -> Option<u32> {
+        self.step_size
+    }
+
+    /// Run a full garbage-collection cycle after each evaluation, so long-lived
+    /// evaluations don't accumulate garbage across invocations.
+    pub fn set_full_collect(&mut self, yes: bool) -> &mut Self {
+        self.full_collect = yes;
+        self
+    }
+
+    /// Set or unset pause, which is the heap size goal in percent in Luau.
+    pub fn set_pause(&mut self, pause: Option<u32>) -> &mut Self {
+        self.pause = pause;
+        self
+    }
 
-/// Options for printing scripts.
+    /// Set or unset step multiplier in percent.
+    pub fn set_step_multiplier(&mut self, step_multiplier: Option<u32>) -> &mut Self {
+        self.step_multiplier = step_multiplier;
+        self
+    }
+
+    /// Set or unset step size in kilobytes.
+    pub fn set_step_size(&mut self, step_size: Option<u32>) -> &mut Self {
+        self.step_size = step_size;
+        self
+    }
+}
+
+/// Options for printing scripts and results.
 #[derive(Debug, Default)]
 pub struct PrintOptions {
     no_color: bool,
@@ -100,13 +326,31 @@ impl PrintOptions {
 
 #[cfg(test)]
 mod tests {
-    use crate::{EvaluationBuilder, StateKey, Store, MIGRATIONS};
-    use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
-    use serde_json::json;
-    use std::io::empty;
+    use crate::{StateKey, MIGRATIONS};
 
+    // the guide covers every binding
+    #[cfg(all(
+        feature = "cbor",
+        feature = "coroutine",
+        feature = "crypto",
+        feature = "diff",
+        feature = "dns",
+        feature = "encoding",
+        feature = "http",
+        feature = "jq",
+        feature = "json-path",
+        feature = "msgpack",
+        feature = "toml",
+        feature = "url",
+        feature = "yaml"
+    ))]
     #[test]
     fn test_evaluation() {
+        use crate::{EvaluationBuilder, Permissions, RunPermissions, Store};
+        use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+        use serde_json::json;
+        use std::io::empty;
+
         let markdown = include_str!("../guides/lua.md");
         let blocks = {
             let mut blocks = Vec::new();
@@ -116,22 +360,18 @@ mod tests {
 
             for event in parser {
                 match event {
-                    Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang))) => {
-                        if lang.to_string() == "lua" {
-                            is_code = true;
-                        }
+                    Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang)))
+                        if lang.to_string() == "lua" =>
+                    {
+                        is_code = true;
                     }
-                    Event::Text(t) => {
-                        if is_code {
-                            text.push_str(&t);
-                        }
+                    Event::Text(t) if is_code => {
+                        text.push_str(&t);
                     }
-                    Event::End(TagEnd::CodeBlock) => {
-                        if is_code {
-                            blocks.push(text.clone());
-                            text.clear();
-                            is_code = false;
-                        }
+                    Event::End(TagEnd::CodeBlock) if is_code => {
+                        blocks.push(text.clone());
+                        text.clear();
+                        is_code = false;
                     }
                     _ => {}
                 }
@@ -141,6 +381,12 @@ mod tests {
 
         let mut server = mockito::Server::new();
 
+        let get_mock = server
+            .mock("GET", "/get")
+            .with_status(200)
+            .expect(2)
+            .create();
+
         let headers_mock = server
             .mock("GET", "/headers")
             .with_status(200)
@@ -163,15 +409,44 @@ mod tests {
             )
             .create();
 
+        let signed_mock = server
+            .mock("POST", "/anything")
+            .with_status(200)
+            .match_header(
+                "X-Hub-Signature",
+                "4f4bb3a54e99c4a20e243485229f9b08c66e09104ba6f79c23ce647242a4ce84",
+            )
+            .create();
+
+        let stream_mock = server
+            .mock("GET", "/stream/3")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                (0..3)
+                    .map(|id| format!("{}\n", json!({ "id": id })))
+                    .collect::<String>(),
+            )
+            .create();
+
         for block in blocks {
             let block = block.replace("https://httpbin.org", &server.url());
             let store = Store::default();
-            let e = EvaluationBuilder::new(&block, empty()).store(store).build();
+            let mut permissions = Permissions::default();
+            permissions.set_run(RunPermissions::new(["echo"]));
+            let e = EvaluationBuilder::new(&block, empty())
+                .permissions(permissions)
+                .store(store)
+                .build()
+                .unwrap();
             e.evaluate().unwrap();
         }
 
+        get_mock.assert();
         post_mock.assert();
         headers_mock.assert();
+        signed_mock.assert();
+        stream_mock.assert();
     }
 
     #[test]
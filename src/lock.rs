@@ -0,0 +1,150 @@
+use aes_gcm::aead::{rand_core::RngCore as _, OsRng};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::{fmt::Write as _, time::Duration};
+
+use crate::{Result, StoreBackend};
+
+/// Prefix of store keys holding locks, under [`crate::RESERVED_KEY_PREFIX`].
+pub const LOCK_KEY_PREFIX: &str = "__lmb:lock:";
+
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn is_held(value: &Value, now: i64) -> bool {
+    value.get("token").is_some_and(Value::is_string)
+        && value
+            .get("expires_at")
+            .and_then(Value::as_i64)
+            .is_some_and(|t| t > now)
+}
+
+/// Acquire the advisory lock of the key, which expires after the time-to-live.
+/// Return a token to release the lock, or `None` if the lock is held by others.
+///
+/// Locks are persisted in the store, so processes sharing the store
+/// e.g. on multiple machines can coordinate with each other.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let store = MemoryStore::default();
+/// let token = acquire_lock(&store, "leader", Duration::from_secs(60))?.unwrap();
+/// assert!(acquire_lock(&store, "leader", Duration::from_secs(60))?.is_none());
+/// assert!(release_lock(&store, &token)?);
+/// assert!(acquire_lock(&store, "leader", Duration::from_secs(60))?.is_some());
+/// # Ok(())
+/// # }
+/// ```
+pub fn acquire_lock(store: &dyn StoreBackend, key: &str, ttl: Duration) -> Result<Option<String>> {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    let mut token = bytes.iter().fold(String::new(), |mut output, b| {
+        let _ = write!(output, "{b:02x}");
+        output
+    });
+    token.push(':');
+    token.push_str(key);
+
+    let ttl = i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX);
+    let update_fn = |value: &mut Value| -> mlua::Result<()> {
+        let now = now_millis();
+        if is_held(value, now) {
+            return Err(mlua::Error::runtime("lock is held"));
+        }
+        *value = json!({ "token": token, "expires_at": now.saturating_add(ttl) });
+        Ok(())
+    };
+    let value = store.update(
+        &format!("{LOCK_KEY_PREFIX}{key}"),
+        Box::new(update_fn),
+        None,
+    )?;
+    let acquired = value.get("token").and_then(Value::as_str) == Some(token.as_str());
+    Ok(acquired.then_some(token))
+}
+
+/// Release the lock acquired by [`acquire_lock`] with the token.
+/// Return `false` if the lock has expired or is held by others.
+pub fn release_lock(store: &dyn StoreBackend, token: &str) -> Result<bool> {
+    let Some((_, key)) = token.split_once(':') else {
+        return Ok(false);
+    };
+    let mut released = false;
+    let update_fn = |value: &mut Value| -> mlua::Result<()> {
+        // backends with optimistic concurrency control may call the function more than once
+        let owned = value.get("token").and_then(Value::as_str) == Some(token);
+        released = owned && is_held(value, now_millis());
+        if !released {
+            return Err(mlua::Error::runtime("lock is not held"));
+        }
+        // keep the row, so the lock is released without racing with the next holder
+        *value = json!({ "token": null, "expires_at": 0 });
+        Ok(())
+    };
+    store.update(
+        &format!("{LOCK_KEY_PREFIX}{key}"),
+        Box::new(update_fn),
+        None,
+    )?;
+    Ok(released)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use super::{acquire_lock, release_lock};
+    use crate::{MemoryStore, Store, StoreBackend};
+
+    #[test]
+    fn expired() {
+        let store = MemoryStore::default();
+        let token = acquire_lock(&store, "a", Duration::ZERO).unwrap().unwrap();
+        let other = acquire_lock(&store, "a", Duration::from_secs(60)).unwrap();
+        assert!(other.is_some());
+        assert!(!release_lock(&store, &token).unwrap());
+    }
+
+    #[test]
+    fn invalid_token() {
+        let store = MemoryStore::default();
+        let _token = acquire_lock(&store, "a", Duration::from_secs(60)).unwrap();
+        assert!(!release_lock(&store, "invalid").unwrap());
+        assert!(!release_lock(&store, "0:a").unwrap());
+        assert!(!release_lock(&store, "0:b").unwrap());
+    }
+
+    #[test]
+    fn mutual_exclusion() {
+        let store: Arc<dyn StoreBackend> = Arc::new(Store::default());
+        let acquired = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    acquire_lock(store.as_ref(), "a", Duration::from_secs(60)).unwrap()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .filter_map(|t| t.join().unwrap())
+            .count();
+        assert_eq!(1, acquired);
+    }
+
+    #[test]
+    fn release() {
+        let store = MemoryStore::default();
+        let token = acquire_lock(&store, "a", Duration::from_secs(60))
+            .unwrap()
+            .unwrap();
+        assert!(acquire_lock(&store, "b", Duration::from_secs(60))
+            .unwrap()
+            .is_some());
+        assert!(release_lock(&store, &token).unwrap());
+        assert!(!release_lock(&store, &token).unwrap());
+    }
+}
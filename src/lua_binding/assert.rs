@@ -0,0 +1,267 @@
+use mlua::prelude::*;
+use serde_json::Value;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::debug;
+
+#[cfg(feature = "diff")]
+use super::diff::{unified_diff, DEFAULT_CONTEXT};
+
+/// Directory of snapshots written and compared by `snapshot` of `@lmb/assert`.
+///
+/// ```rust
+/// # use std::io::empty;
+/// use lmb::*;
+///
+/// let mut snapshots = Snapshots::new("tests/snapshots");
+/// snapshots.set_update(true);
+/// let _ = EvaluationBuilder::new("", empty()).snapshots(Some(snapshots));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Snapshots {
+    dir: PathBuf,
+    update: bool,
+}
+
+impl Snapshots {
+    /// Create snapshots under the directory, which is created on the first write.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            update: false,
+        }
+    }
+
+    /// Get the directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Overwrite mismatched snapshots instead of failing.
+    pub fn set_update(&mut self, update: bool) -> &mut Self {
+        self.update = update;
+        self
+    }
+
+    /// Check whether mismatched snapshots are overwritten.
+    pub fn update(&self) -> bool {
+        self.update
+    }
+
+    /// Path of the snapshot. Names are restricted, so snapshots stay in the directory.
+    fn path(&self, name: &str) -> LuaResult<PathBuf> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(LuaError::runtime(format!("invalid snapshot name {name:?}")));
+        }
+        Ok(self.dir.join(format!("{name}.json")))
+    }
+}
+
+/// Render the value in pretty JSON, or with `tostring` if it cannot be serialized.
+fn render(vm: &Lua, value: &LuaValue<'_>) -> LuaResult<String> {
+    match vm.from_value::<Value>(value.clone()) {
+        Ok(v) => Ok(serde_json::to_string_pretty(&v).into_lua_err()?),
+        Err(_) => Ok(value.to_string()?),
+    }
+}
+
+fn failure(message: Option<String>, reason: String) -> LuaError {
+    match message {
+        Some(message) => LuaError::runtime(format!("{message}: {reason}")),
+        None => LuaError::runtime(reason),
+    }
+}
+
+/// Describe the difference between the rendered values.
+fn difference(expected: &str, actual: &str) -> String {
+    #[cfg(feature = "diff")]
+    {
+        unified_diff(
+            &format!("{expected}\n"),
+            &format!("{actual}\n"),
+            DEFAULT_CONTEXT,
+            ("expected", "actual"),
+        )
+    }
+    #[cfg(not(feature = "diff"))]
+    {
+        format!("expected {expected} but got {actual}")
+    }
+}
+
+/// Assertion module
+pub struct LuaModAssert {
+    snapshots: Option<Snapshots>,
+}
+
+impl LuaModAssert {
+    pub fn new(snapshots: Option<Snapshots>) -> Self {
+        Self { snapshots }
+    }
+
+    fn snapshot(&self, vm: &Lua, name: &str, value: &LuaValue<'_>) -> LuaResult<()> {
+        let Some(snapshots) = &self.snapshots else {
+            return Err(LuaError::runtime("snapshot directory is not specified"));
+        };
+        let path = snapshots.path(name)?;
+        let actual = format!("{}\n", render(vm, value)?);
+        let expected = match fs::read_to_string(&path) {
+            Ok(expected) => Some(expected),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into_lua_err()),
+        };
+        match expected {
+            Some(expected) if expected == actual => return Ok(()),
+            Some(expected) if !snapshots.update => {
+                let reason = difference(expected.trim_end(), actual.trim_end());
+                return Err(LuaError::runtime(format!(
+                    "snapshot {name} mismatched\n{reason}"
+                )));
+            }
+            _ => {}
+        }
+        debug!(?path, "write snapshot");
+        fs::create_dir_all(&snapshots.dir)?;
+        fs::write(&path, actual)?;
+        Ok(())
+    }
+}
+
+impl LuaUserData for LuaModAssert {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "deep_equal",
+            |vm,
+             _,
+             (actual, expected, message): (LuaValue<'lua>, LuaValue<'lua>, Option<String>)| {
+                let (actual, expected) = (render(vm, &actual)?, render(vm, &expected)?);
+                if actual != expected {
+                    return Err(failure(message, difference(&expected, &actual)));
+                }
+                Ok(())
+            },
+        );
+        methods.add_method(
+            "equal",
+            |vm,
+             _,
+             (actual, expected, message): (LuaValue<'lua>, LuaValue<'lua>, Option<String>)| {
+                if !actual.equals(&expected)? {
+                    let reason = format!(
+                        "expected {} but got {}",
+                        render(vm, &expected)?,
+                        render(vm, &actual)?
+                    );
+                    return Err(failure(message, reason));
+                }
+                Ok(())
+            },
+        );
+        // patterns are the same as `string.find`
+        methods.add_method(
+            "matches",
+            |vm, _, (s, pattern, message): (LuaString<'lua>, LuaString<'lua>, Option<String>)| {
+                let string = vm.globals().get::<_, LuaTable<'_>>("string")?;
+                let find = string.get::<_, LuaFunction<'_>>("find")?;
+                let found = find.call::<_, LuaValue<'_>>((s.clone(), pattern.clone()))?;
+                if found.is_nil() {
+                    let reason = format!(
+                        "{:?} does not match {:?}",
+                        s.to_string_lossy(),
+                        pattern.to_string_lossy()
+                    );
+                    return Err(failure(message, reason));
+                }
+                Ok(())
+            },
+        );
+        methods.add_method(
+            "snapshot",
+            |vm, this, (name, value): (String, LuaValue<'lua>)| this.snapshot(vm, &name, &value),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+    use std::{fs, io::empty};
+
+    use crate::{EvaluationBuilder, Snapshots};
+
+    #[test]
+    fn assert() {
+        let script = r#"
+        local t = require('@lmb/assert')
+        t:equal(1, 1)
+        t:deep_equal({ a = { 1, 2 } }, { a = { 1, 2 } })
+        t:matches('hello, world', '^hello')
+        local results = {}
+        for _, f in ipairs({
+          function() t:equal(1, 2, 'numbers') end,
+          function() t:deep_equal({ 1 }, { 2 }) end,
+          function() t:matches('hello', '^world') end,
+        }) do
+          local ok, err = pcall(f)
+          assert(not ok)
+          table.insert(results, tostring(err))
+        end
+        return results
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let errors = res.payload().as_array().unwrap();
+        assert!(errors[0]
+            .as_str()
+            .unwrap()
+            .contains("numbers: expected 2 but got 1"));
+        // values are diffed only with the diff feature
+        if cfg!(feature = "diff") {
+            assert!(errors[1].as_str().unwrap().contains("-  2\n+  1"));
+        }
+        assert!(errors[2]
+            .as_str()
+            .unwrap()
+            .contains(r#""hello" does not match "^world""#));
+    }
+
+    #[test]
+    fn snapshot() {
+        let dir = TempDir::new().unwrap();
+        let script = "require('@lmb/assert'):snapshot('value', { a = io.read('*n') })";
+        let evaluate = |input: &'static str, update: bool| {
+            let mut snapshots = Snapshots::new(dir.path());
+            snapshots.set_update(update);
+            EvaluationBuilder::new(script, input.as_bytes())
+                .snapshots(Some(snapshots))
+                .build()
+                .unwrap()
+                .evaluate()
+        };
+
+        // the snapshot is written when absent
+        evaluate("1", false).unwrap();
+        let path = dir.path().join("value.json");
+        assert_eq!("{\n  \"a\": 1\n}\n", fs::read_to_string(&path).unwrap());
+        evaluate("1", false).unwrap();
+
+        let err = evaluate("2", false).unwrap_err();
+        assert!(err.to_string().contains("snapshot value mismatched"));
+        evaluate("2", true).unwrap();
+        assert_eq!("{\n  \"a\": 2\n}\n", fs::read_to_string(&path).unwrap());
+
+        let script = "require('@lmb/assert'):snapshot('../escape', 1)";
+        let e = EvaluationBuilder::new(script, empty())
+            .snapshots(Some(Snapshots::new(dir.path())))
+            .build()
+            .unwrap();
+        assert!(e.evaluate().is_err());
+    }
+}
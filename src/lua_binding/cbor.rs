@@ -0,0 +1,361 @@
+use mlua::prelude::*;
+use serde_value::Value;
+use std::collections::BTreeMap;
+
+use super::decoded_into_lua;
+
+// Nested arrays and maps deeper than this are rejected to protect the stack.
+const MAX_DEPTH: usize = 128;
+
+fn write_head(buf: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => buf.push(major | n as u8),
+        24..=0xff => buf.extend([major | 24, n as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend((n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend((n as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend(n.to_be_bytes());
+        }
+    }
+}
+
+fn write_signed(buf: &mut Vec<u8>, n: i64) {
+    match u64::try_from(n) {
+        Ok(n) => write_head(buf, 0, n),
+        // -1 - n is non-negative for negative n
+        Err(_) => write_head(buf, 1, !n as u64),
+    }
+}
+
+fn encode(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Bool(false) => buf.push(0xf4),
+        Value::Bool(true) => buf.push(0xf5),
+        Value::Unit | Value::Option(None) => buf.push(0xf6),
+        Value::U8(n) => write_head(buf, 0, (*n).into()),
+        Value::U16(n) => write_head(buf, 0, (*n).into()),
+        Value::U32(n) => write_head(buf, 0, (*n).into()),
+        Value::U64(n) => write_head(buf, 0, *n),
+        Value::I8(n) => write_signed(buf, (*n).into()),
+        Value::I16(n) => write_signed(buf, (*n).into()),
+        Value::I32(n) => write_signed(buf, (*n).into()),
+        Value::I64(n) => write_signed(buf, *n),
+        Value::F32(n) => {
+            buf.push(0xfa);
+            buf.extend(n.to_be_bytes());
+        }
+        Value::F64(n) => {
+            buf.push(0xfb);
+            buf.extend(n.to_be_bytes());
+        }
+        Value::Char(c) => encode(buf, &Value::String(c.to_string())),
+        Value::String(s) => {
+            write_head(buf, 3, s.len() as u64);
+            buf.extend(s.as_bytes());
+        }
+        Value::Bytes(b) => {
+            write_head(buf, 2, b.len() as u64);
+            buf.extend(b);
+        }
+        Value::Option(Some(v)) | Value::Newtype(v) => encode(buf, v),
+        Value::Seq(items) => {
+            write_head(buf, 4, items.len() as u64);
+            for item in items {
+                encode(buf, item);
+            }
+        }
+        Value::Map(entries) => {
+            write_head(buf, 5, entries.len() as u64);
+            for (k, v) in entries {
+                encode(buf, k);
+                encode(buf, v);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> LuaResult<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| LuaError::runtime("unexpected end of CBOR"))?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> LuaResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Read the argument of the head. `None` stands for indefinite length.
+    fn argument(&mut self, info: u8) -> LuaResult<Option<u64>> {
+        let n = match info {
+            0..=23 => info.into(),
+            24 => self.byte()?.into(),
+            25 => u16::from_be_bytes(self.take(2)?.try_into().into_lua_err()?).into(),
+            26 => u32::from_be_bytes(self.take(4)?.try_into().into_lua_err()?).into(),
+            27 => u64::from_be_bytes(self.take(8)?.try_into().into_lua_err()?),
+            31 => return Ok(None),
+            _ => return Err(LuaError::runtime(format!("invalid CBOR argument {info}"))),
+        };
+        Ok(Some(n))
+    }
+
+    fn length(&mut self, info: u8) -> LuaResult<Option<usize>> {
+        self.argument(info)?
+            .map(|n| usize::try_from(n).into_lua_err())
+            .transpose()
+    }
+
+    fn is_break(&mut self) -> bool {
+        if self.bytes.get(self.pos) == Some(&0xff) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn chunks(&mut self, major: u8, info: u8) -> LuaResult<Vec<u8>> {
+        if let Some(len) = self.length(info)? {
+            return Ok(self.take(len)?.to_vec());
+        }
+        let mut buf = vec![];
+        while !self.is_break() {
+            let head = self.byte()?;
+            if head >> 5 != major {
+                return Err(LuaError::runtime("invalid chunk of CBOR string"));
+            }
+            let len = self
+                .length(head & 0x1f)?
+                .ok_or_else(|| LuaError::runtime("nested indefinite CBOR string"))?;
+            buf.extend(self.take(len)?);
+        }
+        Ok(buf)
+    }
+
+    fn decode(&mut self, depth: usize) -> LuaResult<Value> {
+        if depth > MAX_DEPTH {
+            return Err(LuaError::runtime("CBOR is nested too deeply"));
+        }
+        let head = self.byte()?;
+        let (major, info) = (head >> 5, head & 0x1f);
+        Ok(match major {
+            0 => Value::U64(self.argument(info)?.unwrap_or_default()),
+            1 => {
+                let n = self.argument(info)?.unwrap_or_default();
+                match i64::try_from(n) {
+                    Ok(n) => Value::I64(-1 - n),
+                    #[allow(clippy::cast_precision_loss)]
+                    Err(_) => Value::F64(-1.0 - n as f64),
+                }
+            }
+            2 => Value::Bytes(self.chunks(major, info)?),
+            3 => Value::String(String::from_utf8(self.chunks(major, info)?).into_lua_err()?),
+            4 => {
+                let mut items = vec![];
+                match self.length(info)? {
+                    Some(len) => {
+                        for _ in 0..len {
+                            items.push(self.decode(depth + 1)?);
+                        }
+                    }
+                    None => {
+                        while !self.is_break() {
+                            items.push(self.decode(depth + 1)?);
+                        }
+                    }
+                }
+                Value::Seq(items)
+            }
+            5 => {
+                let mut entries = BTreeMap::new();
+                match self.length(info)? {
+                    Some(len) => {
+                        for _ in 0..len {
+                            let k = self.decode(depth + 1)?;
+                            entries.insert(k, self.decode(depth + 1)?);
+                        }
+                    }
+                    None => {
+                        while !self.is_break() {
+                            let k = self.decode(depth + 1)?;
+                            entries.insert(k, self.decode(depth + 1)?);
+                        }
+                    }
+                }
+                Value::Map(entries)
+            }
+            // tags are dropped
+            6 => {
+                self.argument(info)?;
+                self.decode(depth + 1)?
+            }
+            _ => match info {
+                20 => Value::Bool(false),
+                21 => Value::Bool(true),
+                22 | 23 => Value::Unit,
+                25 => {
+                    let half = u16::from_be_bytes(self.take(2)?.try_into().into_lua_err()?);
+                    Value::F64(f16_to_f64(half))
+                }
+                26 => Value::F32(f32::from_be_bytes(self.take(4)?.try_into().into_lua_err()?)),
+                27 => Value::F64(f64::from_be_bytes(self.take(8)?.try_into().into_lua_err()?)),
+                _ => {
+                    return Err(LuaError::runtime(format!(
+                        "unsupported CBOR value {head:#x}"
+                    )))
+                }
+            },
+        })
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc8949.html#name-half-precision
+fn f16_to_f64(half: u16) -> f64 {
+    let exp = (half >> 10) & 0x1f;
+    let mant = f64::from(half & 0x3ff);
+    let value = match exp {
+        0 => mant * 2f64.powi(-24),
+        31 if mant == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mant + 1024.0) * 2f64.powi(i32::from(exp) - 25),
+    };
+    if half & 0x8000 == 0 {
+        value
+    } else {
+        -value
+    }
+}
+
+/// CBOR module
+pub struct LuaModCBOR {}
+
+impl LuaUserData for LuaModCBOR {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("decode", |vm, _, value: LuaString<'lua>| {
+            let mut decoder = Decoder {
+                bytes: value.as_bytes(),
+                pos: 0,
+            };
+            let decoded = decoder.decode(0)?;
+            if decoder.pos != decoder.bytes.len() {
+                return Err(LuaError::runtime("trailing bytes after CBOR"));
+            }
+            decoded_into_lua(vm, decoded)
+        });
+        // strings which are not valid UTF-8 are encoded as byte strings
+        methods.add_method("encode", |vm, _, value: LuaValue<'lua>| {
+            let value = serde_value::to_value(&value).into_lua_err()?;
+            let mut buf = vec![];
+            encode(&mut buf, &value);
+            vm.create_string(buf)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use std::io::empty;
+    use test_case::test_case;
+
+    use super::f16_to_f64;
+    use crate::EvaluationBuilder;
+
+    // https://www.rfc-editor.org/rfc/rfc8949.html#name-examples-of-encoded-cbor-da
+    #[test_case("return m:encode(true)", json!([0xf5]))]
+    #[test_case("return m:encode(m:decode('\\xf6'))", json!([0xf6]))]
+    #[test_case("return m:encode(23)", json!([0x17]))]
+    #[test_case("return m:encode(1000)", json!([0x19, 0x03, 0xe8]))]
+    #[test_case("return m:encode(-1000)", json!([0x39, 0x03, 0xe7]))]
+    #[test_case("return m:encode(1.5)", json!([0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]))]
+    #[test_case("return m:encode('a')", json!([0x61, 0x61]))]
+    #[test_case("return m:encode('\\xff')", json!([0x41, 0xff]))]
+    #[test_case("return m:encode({ 1, 2 })", json!([0x82, 0x01, 0x02]))]
+    #[test_case("return m:encode({ a = 1 })", json!([0xa1, 0x61, 0x61, 0x01]))]
+    fn cbor_encode(body: &str, expected: Value) {
+        let script = format!(
+            r#"
+            local m = require('@lmb/cbor')
+            local s = (function() {body} end)()
+            return {{ string.byte(s, 1, -1) }}
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test_case("\\x00", json!(0))]
+    #[test_case("\\x20", json!(-1))]
+    #[test_case("\\x1b\\x00\\x00\\x00\\xe8\\xd4\\xa5\\x10\\x00", json!(1e12))]
+    #[test_case("\\xf9\\x3c\\x00", json!(1); "half precision")]
+    #[test_case("\\xfa\\x47\\xc3\\x50\\x00", json!(100_000); "single precision")]
+    #[test_case("\\x7f\\x62\\x73\\x74\\x61\\x72\\xff", json!("str"); "indefinite string")]
+    #[test_case("\\x9f\\x01\\x82\\x02\\x03\\xff", json!([1, [2, 3]]); "indefinite array")]
+    #[test_case("\\xbf\\x61\\x61\\x01\\xff", json!({ "a": 1 }); "indefinite map")]
+    #[test_case("\\xc1\\x1a\\x51\\x4b\\x67\\xb0", json!(1_363_896_240); "tagged")]
+    fn cbor_decode(input: &str, expected: Value) {
+        let script = format!("return require('@lmb/cbor'):decode('{input}')");
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test_case("\\x18"; "truncated")]
+    #[test_case("\\x00\\x00"; "trailing")]
+    #[test_case("\\x1c"; "invalid argument")]
+    #[test_case("\\x62\\xff\\xfe"; "invalid utf-8")]
+    fn cbor_decode_invalid(input: &str) {
+        let script = format!("return require('@lmb/cbor'):decode('{input}')");
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        assert!(e.evaluate().is_err());
+    }
+
+    #[test]
+    fn cbor_decode_deeply_nested() {
+        let script = "return require('@lmb/cbor'):decode(string.rep('\\x81', 1000) .. '\\x00')";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("nested too deeply"));
+    }
+
+    #[test]
+    fn cbor_decode_encode() {
+        let script = r#"
+        local m = require('@lmb/cbor')
+        local value = { a = { 1, -2.5, 'b', false }, bin = '\xff\x00' }
+        local decoded = m:decode(m:encode(value))
+        assert(decoded.bin == '\xff\x00', 'binary is preserved')
+        return decoded.a
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([1, -2.5, "b", false]), res.payload());
+    }
+
+    #[test_case(0x0000, 0.0)]
+    #[test_case(0x3c00, 1.0)]
+    #[test_case(0xc400, -4.0)]
+    #[test_case(0x7bff, 65504.0)]
+    #[test_case(0x0001, 5.960_464_477_539_063e-8)]
+    fn half_precision(half: u16, expected: f64) {
+        assert!((f16_to_f64(half) - expected).abs() < f64::EPSILON);
+    }
+}
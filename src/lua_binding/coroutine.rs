@@ -0,0 +1,165 @@
+use mlua::prelude::*;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{bound_timeout, K_LOADED};
+use crate::Result;
+
+const K_COROUTINE_RESET: &str = "lmb_coroutine_reset";
+
+/// Scheduler and channels of `@lmb/coroutine` are written in Luau,
+/// since functions of the host cannot yield.
+const SOURCE: &str = include_str!("coroutine.luau");
+
+/// Register `@lmb/coroutine`, which extends `coroutine` with channels, `select`,
+/// and tasks spawned in scopes.
+pub(crate) fn register_coroutine(vm: &Lua) -> Result<()> {
+    let epoch = Instant::now();
+    let clock = vm.create_function(move |_, ()| Ok(epoch.elapsed().as_secs_f64()))?;
+    // the scheduler sleeps until the earliest timeout when every coroutine is blocked
+    let sleep = vm.create_function(|vm, seconds: f64| {
+        let duration = Duration::try_from_secs_f64(seconds).into_lua_err()?;
+        if let Some(duration) = bound_timeout(vm, Some(duration))? {
+            thread::sleep(duration);
+        }
+        Ok(())
+    })?;
+    let (module, reset) = vm
+        .load(SOURCE)
+        .set_name("=@lmb/coroutine")
+        .call::<_, (LuaTable<'_>, LuaFunction<'_>)>((clock, sleep))?;
+    vm.set_named_registry_value(K_COROUTINE_RESET, reset)?;
+    let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
+    loaded.set("@lmb/coroutine", module)?;
+    vm.set_named_registry_value(K_LOADED, loaded)?;
+    Ok(())
+}
+
+/// Drop tasks left by the previous invocation, e.g. when it timed out.
+pub(crate) fn reset_coroutines(vm: &Lua) -> Result<()> {
+    let reset = vm.named_registry_value::<Option<LuaFunction<'_>>>(K_COROUTINE_RESET)?;
+    if let Some(reset) = reset {
+        reset.call::<_, ()>(())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{io::empty, time::Duration};
+    use test_case::test_case;
+
+    use crate::EvaluationBuilder;
+
+    #[test]
+    fn coroutine_channel() {
+        let script = r#"
+        local coroutine = require('@lmb/coroutine')
+        return coroutine.scope(function()
+          local lines = coroutine.channel(2)
+          local results = coroutine.channel()
+          coroutine.spawn(function()
+            for line in io.lines() do
+              lines:send(line)
+            end
+            lines:close()
+          end)
+          for _ = 1, 2 do
+            coroutine.spawn(function()
+              while true do
+                local line, ok = lines:recv()
+                if not ok then
+                  break
+                end
+                results:send(tonumber(line) * 2)
+              end
+            end)
+          end
+          local sum = 0
+          for _ = 1, 5 do
+            sum += results:recv()
+          end
+          return sum
+        end)
+        "#;
+        let e = EvaluationBuilder::new(script, &b"1\n2\n3\n4\n5\n"[..])
+            .build()
+            .unwrap();
+        assert_eq!(&json!(30), e.evaluate().unwrap().payload());
+    }
+
+    #[test]
+    fn coroutine_select() {
+        let script = r#"
+        local coroutine = require('@lmb/coroutine')
+        return coroutine.scope(function()
+          local a, b = coroutine.channel(1), coroutine.channel(1)
+          local received = {}
+          coroutine.spawn(function()
+            b:send('b')
+            coroutine.select({}, 0.02)
+            a:send('a')
+          end)
+          for _ = 1, 2 do
+            local idx, value = coroutine.select({ a, b }, 1)
+            table.insert(received, { idx, value })
+          end
+          table.insert(received, coroutine.select({ a, b }, 0.01) or 'timeout')
+          return received
+        end)
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        assert_eq!(
+            &json!([[2, "b"], [1, "a"], "timeout"]),
+            e.evaluate().unwrap().payload()
+        );
+    }
+
+    #[test]
+    fn coroutine_scope_error() {
+        let script = r#"
+        local coroutine = require('@lmb/coroutine')
+        local c = coroutine.channel()
+        local ok, err = pcall(coroutine.scope, function()
+          coroutine.spawn(function() c:recv() end)
+          coroutine.spawn(function() error('boom') end)
+          c:recv()
+        end)
+        return { ok, string.find(err, 'boom') ~= nil }
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        assert_eq!(&json!([false, true]), e.evaluate().unwrap().payload());
+    }
+
+    #[test_case("local c = require('@lmb/coroutine'); c.channel():recv()", "deadlock")]
+    #[test_case("require('@lmb/coroutine').spawn(print)", "spawn outside")]
+    #[test_case(
+        "local c = require('@lmb/coroutine').channel(); c:close(); c:send(1)",
+        "closed"
+    )]
+    fn coroutine_invalid(script: &str, expected: &str) {
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err().to_string();
+        assert!(err.contains(expected), "{err}");
+    }
+
+    #[test]
+    fn coroutine_timeout() {
+        let script = r#"
+        local coroutine = require('@lmb/coroutine')
+        coroutine.scope(function()
+          local c = coroutine.channel()
+          coroutine.spawn(function() coroutine.select({ c }, 10) end)
+          return c:recv()
+        end)
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .timeout(Some(Duration::from_millis(50)))
+            .build()
+            .unwrap();
+        assert!(e.evaluate().is_err());
+    }
+}
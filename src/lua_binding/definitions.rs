@@ -0,0 +1,926 @@
+use std::fmt::Write as _;
+
+/// Declaration of a Luau type, describing the value returned by `require` for a module
+/// or values created by modules.
+#[derive(Debug)]
+pub(crate) struct TypeDeclaration {
+    /// Members and their types in Luau syntax. Methods take `self` as the first argument.
+    pub(crate) members: &'static [(&'static str, &'static str)],
+    /// Name passed to `require`, if the type is of a module.
+    pub(crate) module: Option<&'static str>,
+    /// Name of the type.
+    pub(crate) name: &'static str,
+}
+
+/// Registry of types of modules provided by lmb,
+/// from which type definitions are generated and scripts are checked.
+/// Keep in sync with methods and fields added to modules, which is verified by tests.
+pub(crate) static DECLARATIONS: &[TypeDeclaration] = &[
+    TypeDeclaration {
+        module: None,
+        name: "StoreTransaction",
+        members: &[
+            ("get", "(self: StoreTransaction, key: string) -> any"),
+            (
+                "put",
+                "(self: StoreTransaction, key: string, value: any) -> any",
+            ),
+            (
+                "transaction",
+                "(self: StoreTransaction, f: (StoreTransaction) -> any) -> any",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "RuntimeStats",
+        members: &[
+            ("elapsed", "number?"),
+            ("gc_running", "boolean"),
+            ("memory_kb", "number"),
+            ("memory_limit", "number?"),
+            ("remaining", "number?"),
+            ("used_memory", "number"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "Runtime",
+        members: &[("stats", "() -> RuntimeStats")],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "TimerOptions",
+        members: &[("jitter", "number?"), ("times", "number?")],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "Timer",
+        members: &[
+            (
+                "every",
+                "(interval: number, f: () -> boolean?, options: TimerOptions?) -> number",
+            ),
+            (
+                "sleep_until",
+                "(timestamp: number, options: TimerOptions?) -> boolean",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb"),
+        name: "Lmb",
+        members: &[
+            ("_VERSION", "string"),
+            ("app_state", "any"),
+            ("args", "{ [number | string]: string }"),
+            ("env", "{ [string]: string }?"),
+            ("request", "any"),
+            ("response", "any"),
+            ("runtime", "Runtime"),
+            ("session", "any"),
+            ("state", "any"),
+            ("stdin_is_tty", "boolean"),
+            ("stdout_is_tty", "boolean"),
+            ("timer", "Timer"),
+            ("tmpdir", "string?"),
+            ("count", "(self: Lmb, prefix: string?) -> number"),
+            ("get", "(self: Lmb, key: string) -> any"),
+            (
+                "http_error",
+                "(self: Lmb, status: number, message: string) -> never",
+            ),
+            ("invalidate_cache", "(self: Lmb, path: string?) -> number"),
+            ("lock", "(self: Lmb, key: string, ttl: number) -> string?"),
+            ("put", "(self: Lmb, key: string, value: any) -> any"),
+            ("read_bytes", "(self: Lmb, n: number?) -> string?"),
+            (
+                "read_unicode",
+                r#"(self: Lmb, f: number | "*a" | "*l") -> string?"#,
+            ),
+            (
+                "stat",
+                "(self: Lmb, key: string) -> { size: number, type_hint: string, created_at: number, updated_at: number }?",
+            ),
+            (
+                "transaction",
+                "(self: Lmb, f: (StoreTransaction) -> any) -> any",
+            ),
+            ("unlock", "(self: Lmb, token: string) -> boolean"),
+            (
+                "update",
+                "(self: Lmb, key: string, f: (any) -> any, default: any?) -> any",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/assert"),
+        name: "Assert",
+        members: &[
+            (
+                "deep_equal",
+                "(self: Assert, actual: any, expected: any, message: string?) -> ()",
+            ),
+            (
+                "equal",
+                "(self: Assert, actual: any, expected: any, message: string?) -> ()",
+            ),
+            (
+                "matches",
+                "(self: Assert, s: string, pattern: string, message: string?) -> ()",
+            ),
+            ("snapshot", "(self: Assert, name: string, value: any) -> ()"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "Channel",
+        members: &[
+            ("close", "(self: Channel) -> ()"),
+            ("recv", "(self: Channel) -> (any, boolean)"),
+            ("send", "(self: Channel, value: any) -> ()"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/coroutine"),
+        name: "Coroutine",
+        members: &[
+            ("channel", "(capacity: number?) -> Channel"),
+            ("close", "(co: thread) -> (boolean, any)"),
+            ("create", "(f: (...any) -> ...any) -> thread"),
+            ("isyieldable", "() -> boolean"),
+            ("resume", "(co: thread, ...any) -> (boolean, ...any)"),
+            ("running", "() -> thread?"),
+            ("scope", "(f: (...any) -> ...any, ...any) -> ...any"),
+            (
+                "select",
+                "(channels: { Channel }, timeout: number?) -> (number?, any, boolean?)",
+            ),
+            ("spawn", "(f: (...any) -> ...any, ...any) -> thread"),
+            ("status", "(co: thread) -> string"),
+            ("wrap", "(f: (...any) -> ...any) -> (...any) -> ...any"),
+            ("yield", "(...any) -> ...any"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/crypto"),
+        name: "Crypto",
+        members: &[
+            (
+                "hmac",
+                r#"(self: Crypto, algorithm: "sha256", payload: string, secret: string) -> string"#,
+            ),
+            ("sha256", "(self: Crypto, payload: string) -> string"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "DiffOptions",
+        members: &[
+            ("context", "number?"),
+            ("old_name", "string?"),
+            ("new_name", "string?"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/diff"),
+        name: "Diff",
+        members: &[
+            (
+                "diff",
+                "(self: Diff, old: string, new: string, options: DiffOptions?) -> string",
+            ),
+            ("git_blob_hash", "(self: Diff, content: string) -> string"),
+            (
+                "patch",
+                "(self: Diff, text: string, diff: string) -> string",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "DnsLookupOptions",
+        members: &[("server", "string?"), ("timeout", "number?")],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/dns"),
+        name: "Dns",
+        members: &[(
+            "lookup",
+            r#"(self: Dns, name: string, record_type: ("A" | "AAAA" | "MX" | "SRV" | "TXT")?, options: DnsLookupOptions?) -> { any }"#,
+        )],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/encoding"),
+        name: "Encoding",
+        members: &[
+            (
+                "convert",
+                "(self: Encoding, data: string, from: string, to: string) -> string",
+            ),
+            ("detect_bom", "(self: Encoding, data: string) -> string?"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/i18n"),
+        name: "I18n",
+        members: &[
+            ("locale", "string"),
+            (
+                "t",
+                "(self: I18n, key: string, vars: { [string]: any }?, locale: string?) -> string",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "JsonEncodeOptions",
+        members: &[("pretty", "boolean?"), ("sort_keys", "boolean?")],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/jq"),
+        name: "Jq",
+        members: &[("run", "(program: string, value: any) -> { any }")],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/json"),
+        name: "Json",
+        members: &[
+            ("decode", "(self: Json, value: string) -> any"),
+            (
+                "decode_stream",
+                "(self: Json, reader: () -> string?) -> () -> any",
+            ),
+            (
+                "encode",
+                "(self: Json, value: any, options: JsonEncodeOptions?) -> string",
+            ),
+            (
+                "encode_stream",
+                "(self: Json, value: any, writer: (string) -> ()) -> ()",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "JsonPathQuery",
+        members: &[
+            ("delete", "(self: JsonPathQuery, doc: any) -> any"),
+            ("get", "(self: JsonPathQuery, doc: any) -> any"),
+            ("set", "(self: JsonPathQuery, doc: any, value: any) -> any"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/json-path"),
+        name: "JsonPath",
+        members: &[
+            ("compile", "(self: JsonPath, path: string) -> JsonPathQuery"),
+            ("delete", "(self: JsonPath, doc: any, path: string) -> any"),
+            ("get", "(self: JsonPath, doc: any, path: string) -> any"),
+            (
+                "set",
+                "(self: JsonPath, doc: any, path: string, value: any) -> any",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/msgpack"),
+        name: "MsgPack",
+        members: &[
+            ("decode", "(self: MsgPack, value: string) -> any"),
+            ("encode", "(self: MsgPack, value: any) -> string"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/cbor"),
+        name: "Cbor",
+        members: &[
+            ("decode", "(self: Cbor, value: string) -> any"),
+            ("encode", "(self: Cbor, value: any) -> string"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "Metric",
+        members: &[
+            (
+                "inc",
+                "(self: Metric, delta: number?, labels: { [string]: string }?) -> ()",
+            ),
+            (
+                "dec",
+                "(self: Metric, delta: number?, labels: { [string]: string }?) -> ()",
+            ),
+            (
+                "set",
+                "(self: Metric, value: number, labels: { [string]: string }?) -> ()",
+            ),
+            (
+                "observe",
+                "(self: Metric, value: number, labels: { [string]: string }?) -> ()",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "MetricOptions",
+        members: &[("help", "string?"), ("buckets", "{ number }?")],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/prometheus"),
+        name: "Prometheus",
+        members: &[
+            (
+                "counter",
+                "(self: Prometheus, name: string, options: MetricOptions?) -> Metric",
+            ),
+            (
+                "gauge",
+                "(self: Prometheus, name: string, options: MetricOptions?) -> Metric",
+            ),
+            (
+                "histogram",
+                "(self: Prometheus, name: string, options: MetricOptions?) -> Metric",
+            ),
+            ("render", "(self: Prometheus) -> string"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/protobuf"),
+        name: "Protobuf",
+        members: &[
+            ("decode", "(self: Protobuf, name: string, data: string) -> any"),
+            ("encode", "(self: Protobuf, name: string, value: any) -> string"),
+            ("load", "(self: Protobuf, descriptor_set: string) -> Protobuf"),
+            ("messages", "(self: Protobuf) -> { string }"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "FetchOptions",
+        members: &[
+            ("method", "string?"),
+            ("headers", "{ [string]: string }?"),
+            ("body", "string?"),
+            ("retries", "number?"),
+            ("backoff", r#"("constant" | "exponential")?"#),
+            ("retry_delay", "number?"),
+            ("retry_on", "{ number }?"),
+            ("connect_timeout", "number?"),
+            ("read_timeout", "number?"),
+            ("timeout", "number?"),
+            ("prefer_family", r#"("ipv4" | "ipv6")?"#),
+            ("interleave", "boolean?"),
+            ("sign", "FetchSignOptions?"),
+            ("tls", "FetchTlsOptions?"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "FetchSignOptions",
+        members: &[
+            ("algo", r#""hmac-sha256"?"#),
+            ("secret", "string"),
+            ("header", "string?"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "FetchTlsOptions",
+        members: &[
+            ("ca", "string?"),
+            ("cert", "string?"),
+            ("key", "string?"),
+            ("insecure_skip_verify", "boolean?"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "HttpResponse",
+        members: &[
+            ("charset", "string"),
+            ("content_type", "string"),
+            ("headers", "{ [string]: { string } }"),
+            ("ok", "boolean"),
+            ("status_code", "number"),
+            ("json", "(self: HttpResponse) -> any"),
+            ("pipe", "(self: HttpResponse) -> HttpReader"),
+            (
+                "read",
+                r#"(self: HttpResponse, ...(number | "*a" | "*l" | "*L" | "*n" | "a" | "l" | "L" | "n")) -> ...(string | number)?"#,
+            ),
+            (
+                "read_unicode",
+                r#"(self: HttpResponse, f: number | "*a" | "*l") -> string?"#,
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "HttpReader",
+        members: &[
+            (
+                "lines",
+                r#"(self: HttpReader, ...(number | "*a" | "*l" | "*L" | "*n" | "a" | "l" | "L" | "n")) -> () -> ...(string | number)?"#,
+            ),
+            (
+                "read",
+                r#"(self: HttpReader, ...(number | "*a" | "*l" | "*L" | "*n" | "a" | "l" | "L" | "n")) -> ...(string | number)?"#,
+            ),
+            (
+                "read_unicode",
+                r#"(self: HttpReader, f: number | "*a" | "*l") -> string?"#,
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/http"),
+        name: "Http",
+        members: &[(
+            "fetch",
+            "(self: Http, uri: string, options: FetchOptions?) -> HttpResponse",
+        )],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "ParsedUrl",
+        members: &[
+            ("fragment", "string?"),
+            ("host", "string?"),
+            ("href", "string"),
+            ("origin", "string?"),
+            ("password", "string?"),
+            ("path", "string"),
+            ("port", "number?"),
+            ("query", "string?"),
+            ("scheme", "string"),
+            ("username", "string"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/url"),
+        name: "Url",
+        members: &[
+            (
+                "decode_query",
+                "(self: Url, query: string) -> { [string]: string | { string } }",
+            ),
+            ("domain_to_ascii", "(self: Url, domain: string) -> string"),
+            ("domain_to_unicode", "(self: Url, domain: string) -> string"),
+            (
+                "encode_query",
+                "(self: Url, params: { [string]: any }) -> string",
+            ),
+            ("join", "(self: Url, base: string, input: string) -> string"),
+            ("parse", "(self: Url, url: string) -> ParsedUrl"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "YamlDecodeOptions",
+        members: &[("merge", "boolean?")],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/yaml"),
+        name: "Yaml",
+        members: &[
+            (
+                "decode",
+                "(self: Yaml, value: string, options: YamlDecodeOptions?) -> any",
+            ),
+            (
+                "decode_all",
+                "(self: Yaml, value: string, options: YamlDecodeOptions?) -> { any }",
+            ),
+            ("encode", "(self: Yaml, value: any) -> string"),
+            ("encode_all", "(self: Yaml, docs: { any }) -> string"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "TomlDocument",
+        members: &[
+            (
+                "get",
+                "(self: TomlDocument, path: string | { string }) -> any",
+            ),
+            (
+                "remove",
+                "(self: TomlDocument, path: string | { string }) -> any",
+            ),
+            (
+                "set",
+                "(self: TomlDocument, path: string | { string }, value: any) -> ()",
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/toml"),
+        name: "Toml",
+        members: &[
+            ("decode", "(self: Toml, value: string) -> any"),
+            ("edit", "(self: Toml, value: string) -> TomlDocument"),
+            ("encode", "(self: Toml, value: { [string]: any }) -> string"),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "ExecOptions",
+        members: &[("stdin", "string?"), ("timeout", "number?")],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "ExecResult",
+        members: &[
+            ("ok", "boolean"),
+            ("status", "number?"),
+            ("stdout", "string"),
+            ("stderr", "string"),
+            ("timed_out", "boolean"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/shell"),
+        name: "Shell",
+        members: &[(
+            "exec",
+            "(self: Shell, cmd: string, args: { string }?, options: ExecOptions?) -> ExecResult",
+        )],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/signal"),
+        name: "Signal",
+        members: &[
+            (
+                "received",
+                r#"(self: Signal, name: "int" | "term") -> boolean"#,
+            ),
+            (
+                "wait",
+                r#"(self: Signal, name: "int" | "term", timeout: number?) -> boolean"#,
+            ),
+        ],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "SocketOptions",
+        members: &[("timeout", "number?")],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "TcpStream",
+        members: &[
+            ("close", "(self: TcpStream) -> ()"),
+            (
+                "receive",
+                r#"(self: TcpStream, pattern: (number | "*a" | "*l")?) -> string"#,
+            ),
+            ("send", "(self: TcpStream, data: string) -> number"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/tcp"),
+        name: "Tcp",
+        members: &[(
+            "connect",
+            "(self: Tcp, host: string, port: number, options: SocketOptions?) -> TcpStream",
+        )],
+    },
+    TypeDeclaration {
+        module: None,
+        name: "UdpSocket",
+        members: &[
+            ("close", "(self: UdpSocket) -> ()"),
+            ("receive", "(self: UdpSocket, size: number?) -> string"),
+            ("send", "(self: UdpSocket, data: string) -> number"),
+        ],
+    },
+    TypeDeclaration {
+        module: Some("@lmb/udp"),
+        name: "Udp",
+        members: &[(
+            "connect",
+            "(self: Udp, host: string, port: number, options: SocketOptions?) -> UdpSocket",
+        )],
+    },
+];
+
+/// Generate Luau type definitions from [`DECLARATIONS`].
+pub(crate) fn generate_definitions() -> String {
+    let mut res = String::new();
+    res.push_str("--!strict\n");
+    res.push_str("-- Type definitions of modules provided by lmb, generated by `lmb defs`.\n");
+    for declaration in DECLARATIONS {
+        res.push('\n');
+        if let Some(module) = declaration.module {
+            let _ = writeln!(res, "-- require('{module}')");
+        }
+        let _ = writeln!(res, "export type {} = {{", declaration.name);
+        for (name, type_info) in declaration.members {
+            let _ = writeln!(res, "\t{name}: {type_info},");
+        }
+        res.push_str("}\n");
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::prelude::*;
+    use serde_json::json;
+    use std::{
+        collections::BTreeSet,
+        io::{empty, Empty},
+    };
+
+    use super::{super::*, DECLARATIONS};
+    use crate::{EvaluationBuilder, ModuleProvider};
+
+    // modules always registered, regardless of features
+    const CORE: &[&str] = &[
+        "@lmb",
+        "@lmb/assert",
+        "@lmb/i18n",
+        "@lmb/json",
+        "@lmb/prometheus",
+        "@lmb/shell",
+        "@lmb/signal",
+        "@lmb/tcp",
+        "@lmb/udp",
+    ];
+
+    // modules of disabled features are declared but not registered
+    const FEATURES: &[(&str, bool)] = &[
+        ("@lmb/cbor", cfg!(feature = "cbor")),
+        ("@lmb/coroutine", cfg!(feature = "coroutine")),
+        ("@lmb/crypto", cfg!(feature = "crypto")),
+        ("@lmb/diff", cfg!(feature = "diff")),
+        ("@lmb/dns", cfg!(feature = "dns")),
+        ("@lmb/encoding", cfg!(feature = "encoding")),
+        ("@lmb/http", cfg!(feature = "http")),
+        ("@lmb/jq", cfg!(feature = "jq")),
+        ("@lmb/json-path", cfg!(feature = "json-path")),
+        ("@lmb/msgpack", cfg!(feature = "msgpack")),
+        ("@lmb/protobuf", cfg!(feature = "protobuf")),
+        ("@lmb/toml", cfg!(feature = "toml")),
+        ("@lmb/url", cfg!(feature = "url")),
+        ("@lmb/yaml", cfg!(feature = "yaml")),
+    ];
+
+    fn is_enabled(module: &str) -> bool {
+        FEATURES
+            .iter()
+            .find(|(m, _)| *m == module)
+            .map_or(true, |(_, enabled)| *enabled)
+    }
+
+    #[test]
+    fn declared_modules_gated() {
+        for declaration in DECLARATIONS {
+            let Some(module) = declaration.module else {
+                continue;
+            };
+            let gated = FEATURES.iter().any(|(m, _)| *m == module);
+            assert!(
+                CORE.contains(&module) != gated,
+                "{module} should be either a core module or gated behind a feature"
+            );
+        }
+    }
+
+    #[test]
+    fn declared_members_exist() {
+        for declaration in DECLARATIONS {
+            let Some(module) = declaration.module else {
+                continue;
+            };
+            for (name, type_info) in declaration.members {
+                let expected = if type_info.starts_with('(') && type_info.contains(") ->") {
+                    "function"
+                } else {
+                    "field"
+                };
+                let script = format!(
+                    r#"
+                    local m = require('{module}')
+                    if type(m.{name}) == 'function' then return 'function' end
+                    return 'field'
+                    "#
+                );
+                let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+                let res = e.evaluate();
+                if !is_enabled(module) {
+                    continue;
+                }
+                let res = res.unwrap_or_else(|e| panic!("{module} {name}: {e}"));
+                assert_eq!(&json!(expected), res.payload(), "{module} {name}");
+            }
+        }
+    }
+
+    /// Names of fields and methods registered by a userdata type, collected without running Lua.
+    /// Metamethods are not members, so they are ignored.
+    #[derive(Default)]
+    struct Members(BTreeSet<String>);
+
+    impl<'lua, T> LuaUserDataFields<'lua, T> for Members {
+        fn add_field<V>(&mut self, name: impl AsRef<str>, _value: V) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_field_method_get<M, R>(&mut self, name: impl AsRef<str>, _method: M) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_field_method_set<M, A>(&mut self, name: impl AsRef<str>, _method: M) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_field_function_get<F, R>(&mut self, name: impl AsRef<str>, _function: F) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_field_function_set<F, A>(&mut self, name: impl AsRef<str>, _function: F) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_meta_field<V>(&mut self, _name: impl AsRef<str>, _value: V) {}
+
+        fn add_meta_field_with<F, R>(&mut self, _name: impl AsRef<str>, _f: F) {}
+    }
+
+    impl<'lua, T> LuaUserDataMethods<'lua, T> for Members {
+        fn add_method<M, A, R>(&mut self, name: impl AsRef<str>, _method: M) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_method_mut<M, A, R>(&mut self, name: impl AsRef<str>, _method: M) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_function<F, A, R>(&mut self, name: impl AsRef<str>, _function: F) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_function_mut<F, A, R>(&mut self, name: impl AsRef<str>, _function: F) {
+            self.0.insert(name.as_ref().to_string());
+        }
+
+        fn add_meta_method<M, A, R>(&mut self, _name: impl AsRef<str>, _method: M) {}
+
+        fn add_meta_method_mut<M, A, R>(&mut self, _name: impl AsRef<str>, _method: M) {}
+
+        fn add_meta_function<F, A, R>(&mut self, _name: impl AsRef<str>, _function: F) {}
+
+        fn add_meta_function_mut<F, A, R>(&mut self, _name: impl AsRef<str>, _function: F) {}
+    }
+
+    fn members<T: LuaUserData>() -> BTreeSet<String> {
+        let mut members = Members::default();
+        T::add_fields(&mut members);
+        T::add_methods(&mut members);
+        members.0
+    }
+
+    fn declared(name: &str) -> BTreeSet<&'static str> {
+        let declaration = DECLARATIONS
+            .iter()
+            .find(|d| d.name == name)
+            .unwrap_or_else(|| panic!("{name} is not declared"));
+        declaration.members.iter().map(|(n, _)| *n).collect()
+    }
+
+    #[test]
+    fn registered_members_declared() {
+        // types of enabled features are added below
+        #[allow(unused_mut)]
+        let mut types = vec![
+            ("Assert", members::<LuaModAssert>()),
+            ("I18n", members::<LuaModI18n>()),
+            ("Json", members::<LuaModJSON>()),
+            ("Lmb", members::<LuaBinding<Empty>>()),
+            ("Metric", members::<LuaMetric>()),
+            ("Prometheus", members::<LuaModPrometheus>()),
+            ("Runtime", members::<LuaRuntime>()),
+            ("Shell", members::<LuaModShell>()),
+            ("Signal", members::<LuaModSignal>()),
+            ("Tcp", members::<LuaModTCP>()),
+            ("TcpStream", members::<LuaTCPStream>()),
+            ("Timer", members::<LuaTimer>()),
+            ("Udp", members::<LuaModUDP>()),
+            ("UdpSocket", members::<LuaUDPSocket>()),
+        ];
+        #[cfg(feature = "cbor")]
+        types.push(("Cbor", members::<LuaModCBOR>()));
+        #[cfg(feature = "crypto")]
+        types.push(("Crypto", members::<LuaModCrypto>()));
+        #[cfg(feature = "diff")]
+        types.push(("Diff", members::<LuaModDiff>()));
+        #[cfg(feature = "dns")]
+        types.push(("Dns", members::<LuaModDNS>()));
+        #[cfg(feature = "encoding")]
+        types.push(("Encoding", members::<LuaModEncoding>()));
+        #[cfg(feature = "http")]
+        types.extend([
+            ("Http", members::<LuaModHTTP>()),
+            ("HttpReader", members::<LuaModHTTPReader>()),
+            ("HttpResponse", members::<LuaModHTTPResponse>()),
+        ]);
+        #[cfg(feature = "jq")]
+        types.push(("Jq", members::<LuaModJq>()));
+        #[cfg(feature = "json-path")]
+        types.extend([
+            ("JsonPath", members::<LuaModJSONPath>()),
+            ("JsonPathQuery", members::<LuaJsonPath>()),
+        ]);
+        #[cfg(feature = "msgpack")]
+        types.push(("MsgPack", members::<LuaModMsgPack>()));
+        #[cfg(feature = "protobuf")]
+        types.push(("Protobuf", members::<LuaModProtobuf>()));
+        #[cfg(feature = "toml")]
+        types.extend([
+            ("Toml", members::<LuaModTOML>()),
+            ("TomlDocument", members::<LuaTomlDocument>()),
+        ]);
+        #[cfg(feature = "url")]
+        types.push(("Url", members::<LuaModURL>()));
+        #[cfg(feature = "yaml")]
+        types.push(("Yaml", members::<LuaModYAML>()));
+        for (name, registered) in types {
+            let declared = declared(name);
+            for member in registered {
+                assert!(
+                    declared.contains(member.as_str()),
+                    "{name}.{member} is not declared"
+                );
+            }
+        }
+    }
+
+    /// Functions of modules implemented as Lua tables, keyed by module names,
+    /// and an empty list for other modules.
+    #[derive(Debug)]
+    struct LoadedFunctions;
+
+    impl ModuleProvider for LoadedFunctions {
+        fn provide<'lua>(&self, vm: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+            let f = vm.create_function(|vm, ()| {
+                let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
+                let res = vm.create_table()?;
+                for pair in loaded.pairs::<String, LuaValue<'_>>() {
+                    let (name, module) = pair?;
+                    let mut functions = vec![];
+                    if let LuaValue::Table(t) = module {
+                        for pair in t.pairs::<String, LuaValue<'_>>() {
+                            let (key, value) = pair?;
+                            if value.is_function() {
+                                functions.push(key);
+                            }
+                        }
+                    }
+                    res.set(name, functions)?;
+                }
+                Ok(res)
+            })?;
+            Ok(LuaValue::Function(f))
+        }
+    }
+
+    #[test]
+    fn registered_modules_declared() {
+        let script = "return require('@test/loaded')()";
+        let e = EvaluationBuilder::new(script, empty())
+            .module("@test/loaded", LoadedFunctions)
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        let loaded = res.payload().as_object().unwrap();
+        for declaration in DECLARATIONS {
+            let Some(module) = declaration.module else {
+                continue;
+            };
+            assert_eq!(
+                is_enabled(module),
+                loaded.contains_key(module),
+                "{module} should be registered if and only if its feature is enabled"
+            );
+        }
+        for (module, functions) in loaded {
+            if module.starts_with("@test/") {
+                continue;
+            }
+            let declaration = DECLARATIONS
+                .iter()
+                .find(|d| d.module == Some(module.as_str()))
+                .unwrap_or_else(|| panic!("{module} is not declared"));
+            let declared = declared(declaration.name);
+            for function in functions.as_array().into_iter().flatten() {
+                let function = function.as_str().unwrap();
+                assert!(
+                    declared.contains(function),
+                    "{module}.{function} is not declared"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use mlua::prelude::*;
+use similar::TextDiff;
+use std::fmt::Write as _;
+
+/// Lines of context around changes by default, the same as `diff -u`.
+pub(crate) const DEFAULT_CONTEXT: usize = 3;
+
+const NO_NEWLINE: &str = "\\ No newline at end of file";
+
+/// Render the unified diff. Unlike the formatter of `similar`, the missing newline is always
+/// marked, so the diff can be applied exactly.
+pub(crate) fn unified_diff(old: &str, new: &str, context: usize, header: (&str, &str)) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    let mut out = String::new();
+    for (i, hunk) in diff
+        .unified_diff()
+        .context_radius(context)
+        .iter_hunks()
+        .enumerate()
+    {
+        if i == 0 {
+            let _ = writeln!(out, "--- {}\n+++ {}", header.0, header.1);
+        }
+        let _ = writeln!(out, "{}", hunk.header());
+        for change in hunk.iter_changes() {
+            let value = change.value();
+            let _ = write!(out, "{}{value}", change.tag());
+            if !value.ends_with('\n') {
+                let _ = writeln!(out, "\n{NO_NEWLINE}");
+            }
+        }
+    }
+    out
+}
+
+/// Line of a hunk, with its newline unless it is marked missing.
+enum HunkLine<'a> {
+    Context(String),
+    Delete(String),
+    Insert(&'a str),
+}
+
+struct Hunk<'a> {
+    old_start: usize,
+    lines: Vec<HunkLine<'a>>,
+}
+
+/// Parse the start line of the old file in a hunk header e.g. `@@ -1,3 +1,4 @@`.
+fn parse_old_start(header: &str) -> LuaResult<usize> {
+    let range = header
+        .strip_prefix("@@ -")
+        .and_then(|h| h.split_whitespace().next());
+    let (start, len) = range
+        .map(|r| r.split_once(',').unwrap_or((r, "1")))
+        .and_then(|(start, len)| Some((start.parse::<usize>().ok()?, len.parse::<usize>().ok()?)))
+        .ok_or_else(|| LuaError::runtime(format!("invalid hunk header {}", header.trim_end())))?;
+    // the start of an empty range is the line before it
+    Ok(if len == 0 { start + 1 } else { start.max(1) })
+}
+
+fn parse_hunks(diff: &str) -> LuaResult<Vec<Hunk<'_>>> {
+    let mut hunks: Vec<Hunk<'_>> = vec![];
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("@@ ") {
+            hunks.push(Hunk {
+                old_start: parse_old_start(line)?,
+                lines: vec![],
+            });
+            continue;
+        }
+        // headers and other lines before the first hunk are skipped
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        if line.trim_end_matches('\n') == NO_NEWLINE {
+            match hunk.lines.last_mut() {
+                Some(HunkLine::Context(s) | HunkLine::Delete(s)) => {
+                    s.pop();
+                }
+                Some(HunkLine::Insert(s)) => *s = s.strip_suffix('\n').unwrap_or(s),
+                None => {}
+            }
+            continue;
+        }
+        let (tag, value) = line.split_at(line.len().min(1));
+        match tag {
+            " " => hunk.lines.push(HunkLine::Context(value.to_string())),
+            "-" => hunk.lines.push(HunkLine::Delete(value.to_string())),
+            "+" => hunk.lines.push(HunkLine::Insert(value)),
+            // an empty context line may lose its leading space
+            "\n" => hunk.lines.push(HunkLine::Context("\n".to_string())),
+            _ => {
+                return Err(LuaError::runtime(format!(
+                    "invalid line in diff: {}",
+                    line.trim_end()
+                )))
+            }
+        }
+    }
+    Ok(hunks)
+}
+
+/// Apply the unified diff. Context and deleted lines must match exactly.
+fn apply_patch(text: &str, diff: &str) -> LuaResult<String> {
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut cursor = 0;
+    for (i, hunk) in parse_hunks(diff)?.into_iter().enumerate() {
+        let start = hunk.old_start - 1;
+        if start < cursor || start > lines.len() {
+            return Err(LuaError::runtime(format!(
+                "hunk {} is out of order or out of range",
+                i + 1
+            )));
+        }
+        out.extend(lines[cursor..start].iter().copied());
+        cursor = start;
+        for line in hunk.lines {
+            match line {
+                HunkLine::Context(expected) | HunkLine::Delete(expected)
+                    if lines.get(cursor).copied() != Some(expected.as_str()) =>
+                {
+                    return Err(LuaError::runtime(format!(
+                        "hunk {} does not apply at line {}",
+                        i + 1,
+                        cursor + 1
+                    )));
+                }
+                HunkLine::Context(expected) => {
+                    out.push_str(&expected);
+                    cursor += 1;
+                }
+                HunkLine::Delete(_) => cursor += 1,
+                HunkLine::Insert(value) => out.push_str(value),
+            }
+        }
+    }
+    out.extend(lines[cursor..].iter().copied());
+    Ok(out)
+}
+
+// https://www.rfc-editor.org/rfc/rfc3174
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((bytes.len() as u64).wrapping_mul(8).to_be_bytes());
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (chunk, h) in digest.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+/// Hash of the content as a blob object of Git, the same as `git hash-object`.
+fn git_blob_hash(content: &[u8]) -> String {
+    let mut object = format!("blob {}\0", content.len()).into_bytes();
+    object.extend(content);
+    sha1(&object).iter().fold(String::new(), |mut output, b| {
+        let _ = write!(output, "{b:02x}");
+        output
+    })
+}
+
+/// Diff module
+pub struct LuaModDiff {}
+
+impl LuaUserData for LuaModDiff {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "diff",
+            |_, _, (old, new, options): (String, String, Option<LuaTable<'lua>>)| {
+                let (context, old_name, new_name) = match options {
+                    Some(options) => (
+                        options.get::<_, Option<usize>>("context")?,
+                        options.get::<_, Option<String>>("old_name")?,
+                        options.get::<_, Option<String>>("new_name")?,
+                    ),
+                    None => (None, None, None),
+                };
+                let old_name = old_name.unwrap_or_else(|| "a".to_string());
+                let new_name = new_name.unwrap_or_else(|| "b".to_string());
+                Ok(unified_diff(
+                    &old,
+                    &new,
+                    context.unwrap_or(DEFAULT_CONTEXT),
+                    (&old_name, &new_name),
+                ))
+            },
+        );
+        methods.add_method("git_blob_hash", |_, _, content: LuaString<'lua>| {
+            Ok(git_blob_hash(content.as_bytes()))
+        });
+        methods.add_method("patch", |_, _, (text, diff): (String, String)| {
+            apply_patch(&text, &diff)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::io::empty;
+    use test_case::test_case;
+
+    use super::{apply_patch, git_blob_hash, unified_diff};
+    use crate::EvaluationBuilder;
+
+    #[test]
+    fn diff() {
+        let script = r#"
+        local m = require('@lmb/diff')
+        return m:diff('a\nb\nc\n', 'a\nB\nc\n', { old_name = 'old.txt', new_name = 'new.txt' })
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = "--- old.txt\n+++ new.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n";
+        assert_eq!(&json!(expected), res.payload());
+    }
+
+    #[test]
+    fn diff_equal() {
+        assert_eq!("", unified_diff("a\n", "a\n", 3, ("a", "b")));
+    }
+
+    #[test_case("a\nb\nc\n", "a\nB\nc\n")]
+    #[test_case("a", "b"; "without newline")]
+    #[test_case("a\nb", "a\nb\n"; "newline added")]
+    #[test_case("", "a\nb\n"; "from empty")]
+    #[test_case("a\nb\n", ""; "to empty")]
+    #[test_case("1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n", "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n"; "multiple hunks")]
+    fn diff_patch(old: &str, new: &str) {
+        let diff = unified_diff(old, new, 1, ("a", "b"));
+        assert_eq!(new, apply_patch(old, &diff).unwrap(), "{diff}");
+    }
+
+    #[test]
+    fn patch_conflict() {
+        let script = r#"
+        local m = require('@lmb/diff')
+        local d = m:diff('a\nb\n', 'a\nc\n')
+        return m:patch('a\nx\n', d)
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("hunk 1 does not apply at line 2"));
+    }
+
+    // echo -n ... | git hash-object --stdin
+    #[test_case("", "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391")]
+    #[test_case("hello world\n", "3b18e512dba79e4c8300dd08aeb37f8e728b8dad")]
+    fn blob_hash(content: &str, expected: &str) {
+        assert_eq!(expected, git_blob_hash(content.as_bytes()));
+    }
+
+    #[test]
+    fn blob_hash_binding() {
+        let script = "return require('@lmb/diff'):git_blob_hash(string.rep('x', 100))";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = "f6be7cae2045aac11912ea642bf7f9d5d261f63b";
+        assert_eq!(&json!(expected), res.payload());
+    }
+}
@@ -0,0 +1,286 @@
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::ResolveErrorKind,
+    proto::rr::{RData, RecordType},
+    system_conf::read_system_conf,
+    TokioAsyncResolver,
+};
+use mlua::prelude::*;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tracing::trace_span;
+
+use super::bound_timeout;
+use crate::NetPermissions;
+
+/// Default timeout of a lookup.
+const DEFAULT_DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Port of DNS, against which names are checked with permissions.
+const DNS_PORT: u16 = 53;
+
+/// DNS module
+#[derive(Clone, Debug)]
+pub struct LuaModDNS {
+    permissions: NetPermissions,
+}
+
+impl LuaModDNS {
+    /// Create DNS module with permissions.
+    pub fn new(permissions: NetPermissions) -> Self {
+        Self { permissions }
+    }
+}
+
+fn record_type(name: &str) -> LuaResult<RecordType> {
+    match name.to_ascii_uppercase().as_str() {
+        "A" => Ok(RecordType::A),
+        "AAAA" => Ok(RecordType::AAAA),
+        "MX" => Ok(RecordType::MX),
+        "SRV" => Ok(RecordType::SRV),
+        "TXT" => Ok(RecordType::TXT),
+        _ => Err(LuaError::runtime(format!("unsupported record type {name}"))),
+    }
+}
+
+/// Parse the name server in either `ip` or `ip:port`, where IPv6 addresses with ports
+/// are in brackets.
+fn name_server(server: &str) -> LuaResult<SocketAddr> {
+    if let Ok(addr) = server.parse() {
+        return Ok(addr);
+    }
+    match server
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => Ok(SocketAddr::new(ip, DNS_PORT)),
+        Err(_) => Err(LuaError::runtime(format!("invalid name server {server}"))),
+    }
+}
+
+fn lua_dns_lookup<'lua>(
+    vm: &'lua Lua,
+    this: &LuaModDNS,
+    (name, kind, options): (String, Option<String>, Option<LuaTable<'lua>>),
+) -> LuaResult<LuaTable<'lua>> {
+    let record_type = record_type(kind.as_deref().unwrap_or("A"))?;
+    let host = name.trim_end_matches('.');
+    if !this.permissions.is_allowed(host, DNS_PORT) {
+        return Err(LuaError::runtime(format!(
+            "{host} is not allowed to resolve"
+        )));
+    }
+    let (server, timeout) = match options {
+        Some(options) => (
+            options.get::<_, Option<String>>("server")?,
+            options.get::<_, Option<f64>>("timeout")?,
+        ),
+        None => (None, None),
+    };
+    let timeout = match timeout {
+        Some(timeout) => Duration::try_from_secs_f64(timeout).into_lua_err()?,
+        None => DEFAULT_DNS_TIMEOUT,
+    };
+    let timeout = bound_timeout(vm, Some(timeout))?.unwrap_or(timeout);
+    let (config, mut opts) = match server {
+        Some(server) => {
+            let addr = name_server(&server)?;
+            if !this
+                .permissions
+                .is_allowed(&addr.ip().to_string(), addr.port())
+            {
+                return Err(LuaError::runtime(format!(
+                    "{addr} is not allowed to connect"
+                )));
+            }
+            let servers = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+            let config = ResolverConfig::from_parts(None, vec![], servers);
+            (config, ResolverOpts::default())
+        }
+        None => read_system_conf().into_lua_err()?,
+    };
+    opts.timeout = timeout;
+
+    let _s = trace_span!("dns_lookup", name, %record_type).entered();
+    // the resolver and its runtime are dropped with the lookup, outside of any runtime
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let res = runtime.block_on(async {
+        let resolver = TokioAsyncResolver::tokio(config, opts);
+        tokio::time::timeout(timeout, resolver.lookup(name.as_str(), record_type)).await
+    });
+    let lookup = match res {
+        Ok(Ok(lookup)) => lookup,
+        Ok(Err(err)) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+            return vm.create_table();
+        }
+        Ok(Err(err)) => return Err(err.into_lua_err()),
+        Err(_) => return Err(LuaError::runtime("timeout")),
+    };
+
+    let records = vm.create_table()?;
+    for rdata in lookup.iter() {
+        let value = match rdata {
+            RData::A(a) => a.to_string().into_lua(vm)?,
+            RData::AAAA(aaaa) => aaaa.to_string().into_lua(vm)?,
+            RData::TXT(txt) => {
+                let data = txt.iter().flat_map(|s| s.iter().copied());
+                vm.create_string(data.collect::<Vec<_>>())?.into_lua(vm)?
+            }
+            RData::MX(mx) => {
+                let t = vm.create_table()?;
+                t.set("preference", mx.preference())?;
+                t.set("exchange", mx.exchange().to_string())?;
+                t.into_lua(vm)?
+            }
+            RData::SRV(srv) => {
+                let t = vm.create_table()?;
+                t.set("priority", srv.priority())?;
+                t.set("weight", srv.weight())?;
+                t.set("port", srv.port())?;
+                t.set("target", srv.target().to_string())?;
+                t.into_lua(vm)?
+            }
+            // e.g. CNAME records followed to the answer
+            _ => continue,
+        };
+        records.push(value)?;
+    }
+    Ok(records)
+}
+
+impl LuaUserData for LuaModDNS {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("lookup", lua_dns_lookup);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_resolver::proto::{
+        op::{Message, MessageType, ResponseCode},
+        rr::{
+            rdata::{A, MX, SRV, TXT},
+            Name, RData, Record, RecordType,
+        },
+        serialize::binary::{BinDecodable as _, BinEncodable as _},
+    };
+    use serde_json::json;
+    use std::{io::empty, net::UdpSocket, thread};
+
+    use crate::{EvaluationBuilder, NetPermissions, Permissions};
+
+    /// Serve the queries with fixed records over UDP, and return the port.
+    fn serve_dns(queries: usize) -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            for _ in 0..queries {
+                let (read, addr) = socket.recv_from(&mut buf).unwrap();
+                let query = Message::from_bytes(&buf[..read]).unwrap();
+                let q = query.queries()[0].clone();
+                let name = q.name().clone();
+                let target = || Name::from_ascii("mail.example.com.").unwrap();
+                let rdata = match q.query_type() {
+                    RecordType::A => Some(RData::A(A::new(127, 0, 0, 1))),
+                    RecordType::MX => Some(RData::MX(MX::new(10, target()))),
+                    RecordType::SRV => Some(RData::SRV(SRV::new(1, 2, 25, target()))),
+                    RecordType::TXT => Some(RData::TXT(TXT::new(vec!["v=spf1 -all".into()]))),
+                    _ => None,
+                };
+                let mut res = Message::new();
+                res.set_id(query.id())
+                    .set_message_type(MessageType::Response)
+                    .set_recursion_desired(query.recursion_desired())
+                    .set_recursion_available(true)
+                    .add_query(q);
+                match rdata {
+                    Some(rdata) => {
+                        res.add_answer(Record::from_rdata(name, 60, rdata));
+                    }
+                    None => {
+                        res.set_response_code(ResponseCode::NXDomain);
+                    }
+                }
+                socket.send_to(&res.to_bytes().unwrap(), addr).unwrap();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn lookup() {
+        let port = serve_dns(5);
+        let script = format!(
+            r#"
+            local dns = require('@lmb/dns')
+            local options = {{ server = '127.0.0.1:{port}', timeout = 5 }}
+            return {{
+              a = dns:lookup('example.com.', nil, options),
+              mx = dns:lookup('example.com.', 'mx', options),
+              srv = dns:lookup('_smtp._tcp.example.com.', 'SRV', options),
+              txt = dns:lookup('example.com.', 'TXT', options),
+              aaaa = #dns:lookup('example.com.', 'AAAA', options),
+            }}
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = json!({
+            "a": ["127.0.0.1"],
+            "mx": [{ "preference": 10, "exchange": "mail.example.com." }],
+            "srv": [{ "priority": 1, "weight": 2, "port": 25, "target": "mail.example.com." }],
+            "txt": ["v=spf1 -all"],
+            "aaaa": 0,
+        });
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test]
+    fn lookup_not_allowed() {
+        let mut permissions = Permissions::default();
+        permissions.set_net(NetPermissions::new(["example.com", "127.0.0.1:53"]));
+
+        let script = "return require('@lmb/dns'):lookup('example.org')";
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions.clone())
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("example.org is not allowed to resolve"));
+
+        let script =
+            "return require('@lmb/dns'):lookup('example.com', 'A', { server = '1.1.1.1' })";
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("1.1.1.1:53 is not allowed to connect"));
+    }
+
+    #[test]
+    fn lookup_unsupported_type() {
+        let script = "return require('@lmb/dns'):lookup('example.com', 'PTR')";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("unsupported record type PTR"));
+    }
+
+    #[test]
+    fn lookup_negative_timeout() {
+        let script = "return require('@lmb/dns'):lookup('example.com', nil, { timeout = -1 })";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("negative"), "{err}");
+    }
+}
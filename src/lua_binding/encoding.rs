@@ -0,0 +1,129 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use mlua::prelude::*;
+use std::borrow::Cow;
+
+/// Look up the encoding by a label of the WHATWG Encoding Standard e.g. `shift_jis`.
+/// Note that `latin1` and `iso-8859-1` are labels of `windows-1252`.
+fn encoding_for_label(label: &str) -> LuaResult<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| LuaError::runtime(format!("unknown encoding {label}")))
+}
+
+/// Decode the bytes strictly. The byte order mark of the same encoding is removed.
+fn decode<'a>(data: &'a [u8], from: &'static Encoding) -> LuaResult<Cow<'a, str>> {
+    let data = match Encoding::for_bom(data) {
+        Some((encoding, len)) if encoding == from => &data[len..],
+        _ => data,
+    };
+    from.decode_without_bom_handling_and_without_replacement(data)
+        .ok_or_else(|| LuaError::runtime(format!("data is not valid {}", from.name())))
+}
+
+/// Encode the text strictly. Encoders of UTF-16 output UTF-8, so UTF-16 is encoded here.
+fn encode(text: &str, to: &'static Encoding) -> LuaResult<Vec<u8>> {
+    if to == UTF_16LE {
+        return Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect());
+    }
+    if to == UTF_16BE {
+        return Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect());
+    }
+    if to.output_encoding() != to {
+        return Err(LuaError::runtime(format!(
+            "cannot encode into {}",
+            to.name()
+        )));
+    }
+    let (encoded, _, had_errors) = to.encode(text);
+    if had_errors {
+        return Err(LuaError::runtime(format!(
+            "text cannot be represented in {}",
+            to.name()
+        )));
+    }
+    Ok(encoded.into_owned())
+}
+
+/// Encoding module
+pub struct LuaModEncoding {}
+
+impl LuaUserData for LuaModEncoding {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "convert",
+            |vm, _, (data, from, to): (LuaString<'lua>, String, String)| {
+                let text = decode(data.as_bytes(), encoding_for_label(&from)?)?;
+                vm.create_string(encode(&text, encoding_for_label(&to)?)?)
+            },
+        );
+        // Name of the encoding indicated by the byte order mark, or nil without one.
+        methods.add_method("detect_bom", |_, _, data: LuaString<'lua>| {
+            Ok(Encoding::for_bom(data.as_bytes()).map(|(encoding, _)| encoding.name()))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::io::empty;
+    use test_case::test_case;
+
+    use super::{decode, encode, encoding_for_label};
+    use crate::EvaluationBuilder;
+
+    #[test_case("shift_jis", b"\x82\xb1\x82\xf1\x82\xc9\x82\xbf\x82\xcd")]
+    #[test_case("utf-16le", b"\x53\x30\x93\x30\x6b\x30\x61\x30\x6f\x30")]
+    #[test_case("utf-16be", b"\x30\x53\x30\x93\x30\x6b\x30\x61\x30\x6f")]
+    #[test_case("euc-jp", b"\xa4\xb3\xa4\xf3\xa4\xcb\xa4\xc1\xa4\xcf")]
+    fn round_trip(label: &str, bytes: &[u8]) {
+        let encoding = encoding_for_label(label).unwrap();
+        assert_eq!("こんにちは", decode(bytes, encoding).unwrap());
+        assert_eq!(bytes, encode("こんにちは", encoding).unwrap());
+    }
+
+    #[test]
+    fn decode_removes_bom() {
+        let encoding = encoding_for_label("utf-16le").unwrap();
+        assert_eq!("a", decode(b"\xff\xfea\x00", encoding).unwrap());
+    }
+
+    #[test]
+    fn convert() {
+        let script = r"
+        local m = require('@lmb/encoding')
+        local latin1 = m:convert('café', 'utf-8', 'latin1')
+        return { #latin1, m:convert(latin1, 'latin1', 'utf-8') }
+        ";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([4, "café"]), res.payload());
+    }
+
+    #[test_case(
+        "return require('@lmb/encoding'):convert('\\xff', 'utf-8', 'utf-16le')",
+        "data is not valid UTF-8"
+    )]
+    #[test_case(
+        "return require('@lmb/encoding'):convert('日本', 'utf-8', 'latin1')",
+        "text cannot be represented in windows-1252"
+    )]
+    #[test_case(
+        "return require('@lmb/encoding'):convert('a', 'utf-8', 'unknown')",
+        "unknown encoding unknown"
+    )]
+    fn convert_error(script: &str, message: &str) {
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains(message), "{err}");
+    }
+
+    #[test_case("'\\xef\\xbb\\xbfa'", json!("UTF-8"))]
+    #[test_case("'\\xfe\\xff\\x00a'", json!("UTF-16BE"))]
+    #[test_case("'a'", json!(null))]
+    fn detect_bom(data: &str, expected: serde_json::Value) {
+        let script = format!("return require('@lmb/encoding'):detect_bom({data})");
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+}
@@ -1,22 +1,516 @@
 use std::{
     collections::HashMap,
-    io::{BufReader, Cursor, Read},
-    sync::Arc,
+    fmt::Write as _,
+    io::{self, BufReader, Cursor, Read},
+    net::{SocketAddr, ToSocketAddrs as _},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac as _};
 use http::{Method, StatusCode};
 use mlua::prelude::*;
 use parking_lot::Mutex;
+use rand::Rng as _;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
 use serde_json::Value;
+use sha2::Sha256;
 use tracing::{trace, trace_span, warn};
 use ureq::Request;
 use url::Url;
 
-use super::{lua_lmb_read, lua_lmb_read_unicode};
-use crate::Input;
+use super::{bound_timeout, lua_lmb_read, lua_lmb_read_unicode, HttpUsage, ReadFormat, RequestId};
+use crate::{
+    Cassette, CassetteMode, CassetteRequest, CassetteResponse, DryRun, HttpLimitError, HttpLimits,
+    HttpTimeouts, HttpTls, Input, Interaction, NetPermissions, SideEffectKind, REQUEST_ID_HEADER,
+};
+
+/// Default delay before the first retry.
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Status codes that are retried when `retry_on` is omitted.
+const DEFAULT_RETRY_ON: [u16; 4] = [429, 502, 503, 504];
+
+/// Upper bound of the delay between two attempts, including `Retry-After`.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Header of the signature when the `sign` option omits it.
+const DEFAULT_SIGNATURE_HEADER: &str = "X-Signature";
 
 /// HTTP module
-pub struct LuaModHTTP {}
+pub struct LuaModHTTP {
+    cassette: Option<Cassette>,
+    dry_run: Option<DryRun>,
+    limits: HttpLimits,
+    permissions: NetPermissions,
+    timeouts: HttpTimeouts,
+    tls: HttpTls,
+}
+
+impl LuaModHTTP {
+    /// Create HTTP module with permissions.
+    pub fn new(permissions: NetPermissions) -> Self {
+        Self {
+            cassette: None,
+            dry_run: None,
+            limits: HttpLimits::default(),
+            permissions,
+            timeouts: HttpTimeouts::default(),
+            tls: HttpTls::default(),
+        }
+    }
+
+    /// Record interactions to or replay them from the cassette.
+    pub fn set_cassette(&mut self, cassette: Cassette) -> &mut Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Limit requests and downloaded bytes.
+    pub fn set_limits(&mut self, limits: HttpLimits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Answer requests with fixtures of the dry run instead of sending them.
+    pub fn set_dry_run(&mut self, dry_run: DryRun) -> &mut Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Set timeouts of connecting, reading and the whole request.
+    pub fn set_timeouts(&mut self, timeouts: HttpTimeouts) -> &mut Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Set TLS options e.g. the client certificate.
+    pub fn set_tls(&mut self, tls: HttpTls) -> &mut Self {
+        self.tls = tls;
+        self
+    }
+}
+
+/// Timeouts of a request in milliseconds. Those in the options of the request take precedence.
+#[derive(Debug)]
+struct Timeouts {
+    connect: Option<Duration>,
+    read: Option<Duration>,
+    total: Option<Duration>,
+}
+
+impl Timeouts {
+    fn new(timeouts: &HttpTimeouts, options: Option<&LuaTable<'_>>) -> LuaResult<Self> {
+        let get = |key: &str| -> LuaResult<Option<Duration>> {
+            let millis = match options {
+                Some(options) => options.get::<_, Option<u64>>(key)?,
+                None => None,
+            };
+            Ok(millis.map(Duration::from_millis))
+        };
+        Ok(Self {
+            connect: get("connect_timeout")?.or(timeouts.connect()),
+            read: get("read_timeout")?.or(timeouts.read()),
+            total: get("timeout")?.or(timeouts.total()),
+        })
+    }
+}
+
+/// TLS options of a request. Those in the `tls` option of the request take precedence.
+#[derive(Debug, Default, PartialEq)]
+struct TlsOptions {
+    ca: Option<String>,
+    cert: Option<String>,
+    insecure_skip_verify: bool,
+    key: Option<String>,
+}
+
+impl TlsOptions {
+    fn new(tls: &HttpTls, options: Option<&LuaTable<'_>>) -> LuaResult<Self> {
+        let table = match options {
+            Some(options) => options.get::<_, Option<LuaTable<'_>>>("tls")?,
+            None => None,
+        };
+        let get = |key: &str| -> LuaResult<Option<String>> {
+            match &table {
+                Some(table) => table.get(key),
+                None => Ok(None),
+            }
+        };
+        let insecure_skip_verify = match &table {
+            Some(table) => table
+                .get::<_, Option<bool>>("insecure_skip_verify")?
+                .unwrap_or(false),
+            None => false,
+        };
+        if insecure_skip_verify && !tls.allow_insecure() {
+            return Err(LuaError::runtime(
+                "insecure_skip_verify is not allowed, specify --allow-insecure-tls",
+            ));
+        }
+        Ok(Self {
+            ca: get("ca")?.or_else(|| tls.ca_bundle().map(String::from)),
+            cert: get("cert")?.or_else(|| tls.client_cert().map(String::from)),
+            insecure_skip_verify,
+            key: get("key")?.or_else(|| tls.client_key().map(String::from)),
+        })
+    }
+
+    /// Build the client config, or none to use the default one.
+    fn client_config(&self) -> LuaResult<Option<Arc<ClientConfig>>> {
+        if *self == Self::default() {
+            return Ok(None);
+        }
+        let builder = ClientConfig::builder();
+        let builder = if self.insecure_skip_verify {
+            let algorithms =
+                rustls::crypto::ring::default_provider().signature_verification_algorithms;
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerifier(algorithms)))
+        } else {
+            let mut roots = RootCertStore::empty();
+            match &self.ca {
+                Some(ca) => {
+                    for cert in pem_certs(ca)? {
+                        roots.add(cert).into_lua_err()?;
+                    }
+                }
+                None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+            }
+            builder.with_root_certificates(roots)
+        };
+        let config = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(pem_certs(cert)?, pem_key(key)?)
+                .into_lua_err()?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(LuaError::runtime(
+                    "both certificate and key are required for client authentication",
+                ))
+            }
+        };
+        Ok(Some(Arc::new(config)))
+    }
+}
+
+/// Verifier accepting any server certificate, while signatures of the handshake are still verified.
+#[derive(Debug)]
+struct NoVerifier(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+/// Decode labelled blocks in PEM.
+fn pem_blocks(pem: &str) -> LuaResult<Vec<(String, Vec<u8>)>> {
+    let mut blocks = vec![];
+    let mut current: Option<(&str, String)> = None;
+    for line in pem.lines().map(str::trim) {
+        if let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|l| l.strip_suffix("-----"))
+        {
+            current = Some((label, String::new()));
+        } else if let Some(label) = line
+            .strip_prefix("-----END ")
+            .and_then(|l| l.strip_suffix("-----"))
+        {
+            match current.take() {
+                Some((begin, data)) if begin == label => {
+                    let der = STANDARD.decode(data).into_lua_err()?;
+                    blocks.push((label.to_string(), der));
+                }
+                _ => {
+                    return Err(LuaError::runtime(format!(
+                        "unexpected end of {label} in PEM"
+                    )))
+                }
+            }
+        } else if let Some((_, data)) = current.as_mut() {
+            data.push_str(line);
+        }
+    }
+    Ok(blocks)
+}
+
+fn pem_certs(pem: &str) -> LuaResult<Vec<CertificateDer<'static>>> {
+    let certs = pem_blocks(pem)?
+        .into_iter()
+        .filter(|(label, _)| label == "CERTIFICATE")
+        .map(|(_, der)| CertificateDer::from(der))
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(LuaError::runtime("no certificate found in PEM"));
+    }
+    Ok(certs)
+}
+
+/// Decode the first private key in PKCS#8, PKCS#1 or SEC1.
+fn pem_key(pem: &str) -> LuaResult<PrivateKeyDer<'static>> {
+    pem_blocks(pem)?
+        .into_iter()
+        .find_map(|(label, der)| match label.as_str() {
+            "PRIVATE KEY" => Some(PrivateKeyDer::Pkcs8(der.into())),
+            "RSA PRIVATE KEY" => Some(PrivateKeyDer::Pkcs1(der.into())),
+            "EC PRIVATE KEY" => Some(PrivateKeyDer::Sec1(der.into())),
+            _ => None,
+        })
+        .ok_or_else(|| LuaError::runtime("no private key found in PEM"))
+}
+
+/// Reader counting bytes of response bodies downloaded by the invocation.
+struct LimitedReader {
+    bytes: Arc<AtomicU64>,
+    inner: Box<dyn Read + Send + Sync>,
+    limit: Option<u64>,
+}
+
+impl Read for LimitedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let total = self.bytes.fetch_add(n as u64, Ordering::AcqRel) + n as u64;
+        match self.limit {
+            Some(limit) if total > limit => Err(io::Error::other(HttpLimitError::Bytes(limit))),
+            _ => Ok(n),
+        }
+    }
+}
+
+/// Backoff strategy between retries.
+#[derive(Debug, PartialEq)]
+enum Backoff {
+    Constant,
+    Exponential,
+}
+
+/// Retry policy parsed from the options of fetch.
+#[derive(Debug)]
+struct RetryPolicy {
+    backoff: Backoff,
+    delay: Duration,
+    retries: usize,
+    retry_on: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::Exponential,
+            delay: DEFAULT_RETRY_DELAY,
+            retries: 0,
+            retry_on: DEFAULT_RETRY_ON.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn from_options(options: Option<&LuaTable<'_>>) -> LuaResult<Self> {
+        let mut policy = Self::default();
+        let Some(t) = options else {
+            return Ok(policy);
+        };
+        if let Some(retries) = t.get::<_, Option<usize>>("retries")? {
+            policy.retries = retries;
+        }
+        policy.backoff = match t.get::<_, Option<String>>("backoff")?.as_deref() {
+            None | Some("exponential") => Backoff::Exponential,
+            Some("constant") => Backoff::Constant,
+            Some(b) => return Err(LuaError::runtime(format!("unsupported backoff {b}"))),
+        };
+        if let Some(delay) = t.get::<_, Option<u64>>("retry_delay")? {
+            policy.delay = Duration::from_millis(delay);
+        }
+        if let Some(retry_on) = t.get::<_, Option<Vec<u16>>>("retry_on")? {
+            policy.retry_on = retry_on;
+        }
+        Ok(policy)
+    }
+
+    /// Transport errors are always retried, responses only when the status code is listed.
+    fn should_retry(&self, attempt: usize, status: Option<u16>) -> bool {
+        attempt < self.retries && status.map_or(true, |s| self.retry_on.contains(&s))
+    }
+
+    /// Delay before the next attempt. `Retry-After` from the server takes precedence,
+    /// otherwise the backoff is computed with equal jitter.
+    fn delay(&self, attempt: usize, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(MAX_RETRY_DELAY);
+        }
+        let delay = match self.backoff {
+            Backoff::Constant => self.delay,
+            Backoff::Exponential => {
+                let exp = u32::try_from(attempt).unwrap_or(u32::MAX);
+                self.delay.saturating_mul(2u32.saturating_pow(exp))
+            }
+        }
+        .min(MAX_RETRY_DELAY);
+        let half = delay / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis());
+        half + Duration::from_millis(u64::try_from(jitter).unwrap_or_default())
+    }
+}
+
+/// Signer of the request body, parsed from the `sign` option of fetch.
+#[derive(Debug)]
+struct Signer {
+    header: String,
+    secret: String,
+}
+
+impl Signer {
+    fn from_options(options: Option<&LuaTable<'_>>) -> LuaResult<Option<Self>> {
+        let Some(t) = options else {
+            return Ok(None);
+        };
+        let Some(sign) = t.get::<_, Option<LuaTable<'_>>>("sign")? else {
+            return Ok(None);
+        };
+        match sign.get::<_, Option<String>>("algo")?.as_deref() {
+            None | Some("hmac-sha256") => {}
+            Some(a) => return Err(LuaError::runtime(format!("unsupported algorithm {a}"))),
+        }
+        let Some(secret) = sign.get::<_, Option<String>>("secret")? else {
+            return Err(LuaError::runtime("secret is required to sign the request"));
+        };
+        let header = sign
+            .get::<_, Option<String>>("header")?
+            .unwrap_or_else(|| DEFAULT_SIGNATURE_HEADER.to_string());
+        Ok(Some(Self { header, secret }))
+    }
+
+    /// Sign the body with HMAC-SHA256 in hex, the same as `hmac` of `@lmb/crypto`.
+    fn sign(&self, body: &[u8]) -> LuaResult<String> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).into_lua_err()?;
+        mac.update(body);
+        let signature = mac.finalize().into_bytes();
+        Ok(signature.iter().fold(String::new(), |mut output, b| {
+            let _ = write!(output, "{b:02x}");
+            output
+        }))
+    }
+}
+
+/// Order of addresses to connect to, parsed from the `prefer_family` and `interleave`
+/// options of fetch. Addresses are tried one by one, and each attempt takes at most half of
+/// the remaining connect timeout when there are several.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AddressOrder {
+    interleave: bool,
+    prefer_ipv6: Option<bool>,
+}
+
+impl Default for AddressOrder {
+    fn default() -> Self {
+        Self {
+            interleave: true,
+            prefer_ipv6: None,
+        }
+    }
+}
+
+impl AddressOrder {
+    fn from_options(options: Option<&LuaTable<'_>>) -> LuaResult<Self> {
+        let mut order = Self::default();
+        let Some(t) = options else {
+            return Ok(order);
+        };
+        order.prefer_ipv6 = match t.get::<_, Option<String>>("prefer_family")?.as_deref() {
+            None => None,
+            Some("ipv4") => Some(false),
+            Some("ipv6") => Some(true),
+            Some(f) => return Err(LuaError::runtime(format!("unsupported family {f}"))),
+        };
+        if let Some(interleave) = t.get::<_, Option<bool>>("interleave")? {
+            order.interleave = interleave;
+        }
+        Ok(order)
+    }
+
+    /// Put addresses of the preferred family first, which defaults to the family of the first
+    /// resolved address. Families are interleaved unless disabled, so a broken family
+    /// falls back to the other one quickly. Unlike RFC 8305, attempts are not made in parallel.
+    fn sort(&self, addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let Some(first) = addrs.first() else {
+            return addrs;
+        };
+        let prefer_ipv6 = self.prefer_ipv6.unwrap_or(first.is_ipv6());
+        let (preferred, others): (Vec<_>, Vec<_>) =
+            addrs.into_iter().partition(|a| a.is_ipv6() == prefer_ipv6);
+        if !self.interleave {
+            return preferred.into_iter().chain(others).collect();
+        }
+        let mut sorted = Vec::with_capacity(preferred.len() + others.len());
+        let (mut preferred, mut others) = (preferred.into_iter(), others.into_iter());
+        loop {
+            match (preferred.next(), others.next()) {
+                (None, None) => break,
+                (a, b) => sorted.extend(a.into_iter().chain(b)),
+            }
+        }
+        sorted
+    }
+}
+
+impl ureq::Resolver for AddressOrder {
+    fn resolve(&self, netloc: &str) -> io::Result<Vec<SocketAddr>> {
+        Ok(self.sort(netloc.to_socket_addrs()?.collect()))
+    }
+}
+
+/// Parse `Retry-After` header in either delay-seconds or HTTP-date format.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
 
 /// HTTP response
 pub struct LuaModHTTPResponse {
@@ -46,8 +540,35 @@ impl LuaUserData for LuaModHTTPResponse {
             let value = vm.to_value(&value)?;
             Ok(value)
         });
-        methods.add_method("read", |vm, this, f: Option<LuaValue<'lua>>| {
-            lua_lmb_read(vm, &this.reader, f)
+        methods.add_method("pipe", |_, this, ()| {
+            Ok(LuaModHTTPReader {
+                reader: this.reader.clone(),
+            })
+        });
+        methods.add_method("read", |vm, this, formats: LuaMultiValue<'lua>| {
+            lua_lmb_read(vm, &this.reader, &ReadFormat::from_lua_multi(formats)?)
+        });
+        methods.add_method("read_unicode", |vm, this, f: LuaValue<'lua>| {
+            lua_lmb_read_unicode(vm, &this.reader, f)
+        });
+    }
+}
+
+/// Reader of the HTTP response body, which reads the body as it arrives instead of
+/// buffering it, and shares the position with the response.
+pub struct LuaModHTTPReader {
+    reader: Input<Box<dyn Read + Send + Sync + 'static>>,
+}
+
+impl LuaUserData for LuaModHTTPReader {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("lines", |vm, this, formats: LuaMultiValue<'lua>| {
+            let formats = ReadFormat::from_lua_multi(formats)?;
+            let reader = this.reader.clone();
+            vm.create_function(move |vm, ()| lua_lmb_read(vm, &reader, &formats))
+        });
+        methods.add_method("read", |vm, this, formats: LuaMultiValue<'lua>| {
+            lua_lmb_read(vm, &this.reader, &ReadFormat::from_lua_multi(formats)?)
         });
         methods.add_method("read_unicode", |vm, this, f: LuaValue<'lua>| {
             lua_lmb_read_unicode(vm, &this.reader, f)
@@ -72,11 +593,28 @@ fn set_headers(req: Request, headers: &Value) -> Request {
 
 fn lua_lmb_fetch(
     vm: &Lua,
-    _: &LuaModHTTP,
+    this: &LuaModHTTP,
     (uri, options): (String, Option<LuaTable<'_>>),
 ) -> LuaResult<LuaModHTTPResponse> {
     let options = options.as_ref();
     let url: Url = uri.parse().into_lua_err()?;
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or_default();
+    if !this.permissions.is_url_allowed(&url) {
+        let path = url.path().trim_start_matches('/');
+        let target = if path.is_empty() {
+            format!("{host}:{port}")
+        } else {
+            format!("{host}:{port}/{path}")
+        };
+        return Err(LuaError::runtime(format!(
+            "{target} is not allowed to connect"
+        )));
+    }
+    let tls = TlsOptions::new(&this.tls, options)?;
+    let timeouts = Timeouts::new(&this.timeouts, options)?;
+    let signer = Signer::from_options(options)?;
+    let order = AddressOrder::from_options(options)?;
     let method: String = options
         .and_then(|t| t.get("method").ok().map(|s: String| s))
         .unwrap_or_else(|| "GET".to_string());
@@ -85,23 +623,111 @@ fn lua_lmb_fetch(
         .and_then(|t| t.get("headers").ok())
         .and_then(|m| vm.from_value(m).ok())
         .unwrap_or(Value::Null);
-    let _s = trace_span!("send_http_request", %method, %url, ?headers).entered();
-    let res = if method.is_safe() {
-        let req = ureq::request_url(method.as_str(), &url);
-        let req = set_headers(req, &headers);
-        req.call()
+    let body: Option<String> = if method.is_safe() {
+        None
     } else {
-        let body: String = options
-            .map(|t| t.get("body").unwrap_or_default())
-            .unwrap_or_default();
-        let req = ureq::request_url(method.as_str(), &url);
-        let req = set_headers(req, &headers);
-        req.send(Cursor::new(body))
+        Some(
+            options
+                .map(|t| t.get("body").unwrap_or_default())
+                .unwrap_or_default(),
+        )
+    };
+    let bytes = match vm.app_data_ref::<HttpUsage>() {
+        Some(usage) => {
+            let requests = usage.requests.fetch_add(1, Ordering::AcqRel);
+            if let Some(limit) = this.limits.max_requests().filter(|l| requests >= *l) {
+                return Err(HttpLimitError::Requests(limit).into_lua_err());
+            }
+            usage.bytes.clone()
+        }
+        None => Arc::default(),
+    };
+    if let Some(dry_run) = &this.dry_run {
+        return dry_run_fetch(dry_run, &method, &url, body);
+    }
+    let cassette = this.cassette.as_ref();
+    if let Some(cassette) = cassette.filter(|c| c.mode() == CassetteMode::Replay) {
+        return replay_fetch(cassette, &method, &url);
+    }
+    let tls_config = tls.client_config()?;
+    let new_builder = || {
+        let mut builder = ureq::AgentBuilder::new().resolver(order);
+        // redirects may lead to hosts which are not allowed
+        if this.permissions.is_restricted() {
+            builder = builder.redirects(0);
+        }
+        if let Some(config) = &tls_config {
+            builder = builder.tls_config(config.clone());
+        }
+        if let Some(timeout) = timeouts.connect {
+            builder = builder.timeout_connect(timeout);
+        }
+        builder
+    };
+    let policy = RetryPolicy::from_options(options)?;
+    let signature = match &signer {
+        Some(signer) => {
+            let body = body.as_deref().unwrap_or_default();
+            Some((&signer.header, signer.sign(body.as_bytes())?))
+        }
+        None => None,
+    };
+    let _s = trace_span!("send_http_request", %method, %url, ?headers).entered();
+    let guard = this.limits.acquire().into_lua_err()?;
+    let deadline = timeouts.total.map(|t| Instant::now() + t);
+    let mut attempt = 0;
+    let res = loop {
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let timeout = bound_timeout(vm, remaining)?;
+        // the overall timeout overrides the read timeout, so it bounds each read instead
+        let agent = match (timeouts.read, timeout) {
+            (Some(read), Some(timeout)) => new_builder().timeout_read(read.min(timeout)),
+            (Some(read), None) => new_builder().timeout_read(read),
+            (None, Some(timeout)) => new_builder().timeout(timeout),
+            (None, None) => new_builder(),
+        }
+        .build();
+        let req = agent.request_url(method.as_str(), &url);
+        let mut req = set_headers(req, &headers);
+        if let Some((header, signature)) = &signature {
+            req = req.set(header, signature);
+        }
+        if let Some(id) = vm.app_data_ref::<RequestId>() {
+            if req.header(REQUEST_ID_HEADER).is_none() {
+                req = req.set(REQUEST_ID_HEADER, &id.0);
+            }
+        }
+        let res = match &body {
+            None => req.call(),
+            Some(body) => req.send(Cursor::new(body.clone())),
+        };
+        let (status, retry_after) = match &res {
+            Ok(res) | Err(ureq::Error::Status(_, res)) => (
+                Some(res.status()),
+                res.header("retry-after").and_then(parse_retry_after),
+            ),
+            Err(_) => (None, None),
+        };
+        if res.is_err() && status.is_none() {
+            // the request may time out because of the deadline of the evaluation
+            bound_timeout(vm, None)?;
+        }
+        let delay = policy.delay(attempt, retry_after);
+        // the last result is returned when no time is left for the next attempt
+        let expired = deadline.is_some_and(|d| Instant::now() + delay >= d);
+        if !policy.should_retry(attempt, status) || expired {
+            break res;
+        }
+        let delay = bound_timeout(vm, Some(delay))?.unwrap_or(delay);
+        attempt += 1;
+        warn!(attempt, ?status, ?delay, "retry request");
+        thread::sleep(delay);
     };
     let res = match res {
         Ok(res) | Err(ureq::Error::Status(_, res)) => res,
         Err(e) => return Err(e.into_lua_err()),
     };
+    drop(guard);
     let charset = res.charset().to_string();
     let content_type = res.content_type().to_string();
     let headers = {
@@ -118,7 +744,34 @@ fn lua_lmb_fetch(
     };
     let status_code = StatusCode::from_u16(res.status()).into_lua_err()?;
     trace!(%status_code, charset, content_type, "response");
-    let reader = Arc::new(Mutex::new(BufReader::new(res.into_reader())));
+    let mut reader: Box<dyn Read + Send + Sync> = Box::new(LimitedReader {
+        bytes,
+        inner: res.into_reader(),
+        limit: this.limits.max_bytes(),
+    });
+    let reader: Box<dyn Read + Send + Sync> = match cassette {
+        Some(cassette) => {
+            // the body is read in advance to be recorded
+            let mut buf = vec![];
+            reader.read_to_end(&mut buf)?;
+            let request = CassetteRequest {
+                method: method.to_string(),
+                url: url.to_string(),
+                body,
+            };
+            let response = CassetteResponse::new(
+                status_code.as_u16(),
+                headers.clone().into_iter().collect(),
+                buf.clone(),
+            );
+            cassette
+                .push(Interaction { request, response })
+                .into_lua_err()?;
+            Box::new(Cursor::new(buf))
+        }
+        None => reader,
+    };
+    let reader = Arc::new(Mutex::new(BufReader::new(reader)));
     Ok(LuaModHTTPResponse {
         charset,
         content_type,
@@ -128,6 +781,80 @@ fn lua_lmb_fetch(
     })
 }
 
+// unmatched requests are answered with an empty response of 200 OK
+fn dry_run_fetch(
+    dry_run: &DryRun,
+    method: &Method,
+    url: &Url,
+    body: Option<String>,
+) -> LuaResult<LuaModHTTPResponse> {
+    let mut detail = serde_json::Map::new();
+    detail.insert("method".into(), method.as_str().into());
+    if let Some(body) = body {
+        detail.insert("body".into(), body.into());
+    }
+    dry_run.record(SideEffectKind::Http, url.as_str(), Value::Object(detail));
+    let fixture = dry_run.fixtures().find_http(method.as_str(), url.as_str());
+    let headers = fixture
+        .map(|f| {
+            f.headers
+                .iter()
+                .map(|(k, v)| (k.to_lowercase(), vec![v.clone()]))
+                .collect()
+        })
+        .unwrap_or_default();
+    let body = fixture.map(|f| f.body.clone()).unwrap_or_default();
+    canned_response(
+        fixture.map_or(200, |f| f.status),
+        headers,
+        body.into_bytes(),
+    )
+}
+
+// replayed requests are expected to be recorded with the same method and URL
+fn replay_fetch(cassette: &Cassette, method: &Method, url: &Url) -> LuaResult<LuaModHTTPResponse> {
+    let Some(res) = cassette.take(method.as_str(), url.as_str()) else {
+        return Err(LuaError::runtime(format!(
+            "no interaction of {method} {url} to replay"
+        )));
+    };
+    trace!(status = res.status, "replay response");
+    let body = res.decode_body().into_lua_err()?;
+    let headers = res.headers.into_iter().collect();
+    canned_response(res.status, headers, body)
+}
+
+fn canned_response(
+    status: u16,
+    headers: HashMap<String, Vec<String>>,
+    body: Vec<u8>,
+) -> LuaResult<LuaModHTTPResponse> {
+    let content_type = headers
+        .get("content-type")
+        .and_then(|v| v.first())
+        .map_or("text/plain", String::as_str);
+    let (content_type, charset) = match content_type.split_once(';') {
+        Some((t, params)) => (
+            t.trim().to_string(),
+            params
+                .trim()
+                .strip_prefix("charset=")
+                .unwrap_or("utf-8")
+                .to_string(),
+        ),
+        None => (content_type.to_string(), "utf-8".to_string()),
+    };
+    let status_code = StatusCode::from_u16(status).into_lua_err()?;
+    let reader: Box<dyn Read + Send + Sync> = Box::new(Cursor::new(body));
+    Ok(LuaModHTTPResponse {
+        charset,
+        content_type,
+        headers,
+        reader: Arc::new(Mutex::new(BufReader::new(reader))),
+        status_code,
+    })
+}
+
 impl LuaUserData for LuaModHTTP {
     fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_method("fetch", lua_lmb_fetch);
@@ -136,12 +863,146 @@ impl LuaUserData for LuaModHTTP {
 
 #[cfg(test)]
 mod tests {
-    use std::io::empty;
+    use std::{
+        io::{empty, Read as _, Write as _},
+        net::TcpListener,
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
 
     use mockito::Server;
+    use rustls::{
+        server::WebPkiClientVerifier, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+    };
     use serde_json::json;
+    use test_case::test_case;
 
-    use crate::EvaluationBuilder;
+    use super::{parse_retry_after, pem_certs, pem_key, AddressOrder, Backoff, RetryPolicy};
+    use crate::{
+        Cassette, EvaluationBuilder, HttpLimitError, HttpLimits, HttpTimeouts, HttpTls,
+        InvocationState, NetPermissions, Permissions,
+    };
+
+    const CA: &str = include_str!("../../tests/fixtures/tls/ca.pem");
+    const CLIENT_CERT: &str = include_str!("../../tests/fixtures/tls/client.pem");
+    const CLIENT_KEY: &str = include_str!("../../tests/fixtures/tls/client.key");
+    const SERVER_CERT: &str = include_str!("../../tests/fixtures/tls/server.pem");
+    const SERVER_KEY: &str = include_str!("../../tests/fixtures/tls/server.key");
+
+    /// Serve HTTPS requiring client certificates issued by the test CA, and return the port.
+    fn serve_mtls(connections: usize) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut roots = RootCertStore::empty();
+        roots.add(pem_certs(CA).unwrap().remove(0)).unwrap();
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+            .build()
+            .unwrap();
+        let config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                pem_certs(SERVER_CERT).unwrap(),
+                pem_key(SERVER_KEY).unwrap(),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let conn = ServerConnection::new(config.clone()).unwrap();
+                let mut tls = StreamOwned::new(conn, stream.unwrap());
+                let mut buf = [0; 1024];
+                // the handshake fails without a client certificate
+                if tls.read(&mut buf).is_ok() {
+                    let res =
+                        "HTTP/1.1 200 OK\r\ncontent-length: 5\r\nconnection: close\r\n\r\nhello";
+                    let _ = tls.write_all(res.as_bytes());
+                    tls.conn.send_close_notify();
+                    let _ = tls.flush();
+                }
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn http_mtls() {
+        let port = serve_mtls(2);
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            local tls = {{ cert = [[{CLIENT_CERT}]], key = [[{CLIENT_KEY}]] }}
+            local res = m:fetch('https://127.0.0.1:{port}/', {{ tls = tls }})
+            return res:read('*a')
+            "#
+        );
+        let mut tls = HttpTls::default();
+        tls.set_ca_bundle(Some(CA.to_string()));
+        let mut permissions = Permissions::default();
+        permissions.set_http_tls(tls);
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions.clone())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!("hello"), res.payload());
+
+        let script = format!("return require('@lmb/http'):fetch('https://127.0.0.1:{port}/')");
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        assert!(e.evaluate().is_err());
+    }
+
+    #[test]
+    fn http_tls_insecure_skip_verify() {
+        let port = serve_mtls(1);
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            local tls = {{ insecure_skip_verify = true }}
+            return m:fetch('https://127.0.0.1:{port}/', {{ tls = tls }}):read('*a')
+            "#
+        );
+        let e = EvaluationBuilder::new(&script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("insecure_skip_verify is not allowed"));
+
+        let mut tls = HttpTls::default();
+        tls.set_allow_insecure(true)
+            .set_client_cert(Some(CLIENT_CERT.to_string()))
+            .set_client_key(Some(CLIENT_KEY.to_string()));
+        let mut permissions = Permissions::default();
+        permissions.set_http_tls(tls);
+        let e = EvaluationBuilder::new(&script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!("hello"), res.payload());
+    }
+
+    #[test]
+    fn http_tls_cert_without_key() {
+        let script = format!(
+            "return require('@lmb/http'):fetch('https://127.0.0.1/', {{ tls = {{ cert = [[{CLIENT_CERT}]] }} }})"
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("both certificate and key are required"));
+    }
+
+    #[test_case(CLIENT_KEY, true)]
+    #[test_case(SERVER_KEY, true)]
+    #[test_case(CLIENT_CERT, false)]
+    fn pem_private_key(pem: &str, expected: bool) {
+        assert_eq!(expected, pem_key(pem).is_ok());
+    }
 
     #[test]
     fn http_get() {
@@ -162,13 +1023,166 @@ mod tests {
             return res:read('*a')
             "#
         );
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(body), res.payload());
 
         get_mock.assert();
     }
 
+    #[test]
+    fn http_limit_bytes() {
+        let mut server = Server::new();
+        let get_mock = server.mock("GET", "/").with_body("x".repeat(100)).create();
+
+        let url = server.url();
+        let script = format!("return require('@lmb/http'):fetch('{url}'):read('*a')");
+        let mut limits = HttpLimits::default();
+        limits.set_max_bytes(Some(10));
+        let mut permissions = Permissions::default();
+        permissions.set_http_limits(limits);
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert_eq!(Some(&HttpLimitError::Bytes(10)), err.http_limit(), "{err}");
+
+        get_mock.assert();
+    }
+
+    #[test]
+    fn http_limit_requests() {
+        let mut server = Server::new();
+        let get_mock = server.mock("GET", "/").expect(4).create();
+
+        let url = server.url();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            m:fetch('{url}')
+            m:fetch('{url}')
+            local ok = pcall(function() m:fetch('{url}') end)
+            return ok
+            "#
+        );
+        let mut limits = HttpLimits::default();
+        limits.set_max_requests(Some(2));
+        let mut permissions = Permissions::default();
+        permissions.set_http_limits(limits);
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions.clone())
+            .build()
+            .unwrap();
+        assert_eq!(&json!(false), e.evaluate().unwrap().payload());
+
+        // requests are counted per invocation
+        let script = format!("require('@lmb/http'):fetch('{url}'); return true");
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        e.evaluate().unwrap();
+        e.evaluate().unwrap();
+
+        get_mock.assert();
+    }
+
+    #[test]
+    fn http_get_not_allowed() {
+        let mut server = Server::new();
+        let get_mock = server.mock("GET", "/html").expect(0).create();
+
+        let url = server.url();
+        let script = format!("return require('@lmb/http'):fetch('{url}/html')");
+        let mut permissions = Permissions::default();
+        permissions.set_net(NetPermissions::new(["example.com"]));
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("is not allowed to connect"));
+
+        get_mock.assert();
+    }
+
+    #[test]
+    fn http_get_path_allowed() {
+        let mut server = Server::new();
+        let allowed_mock = server.mock("GET", "/api/a").create();
+        let denied_mock = server.mock("GET", "/other").expect(0).create();
+
+        let url = server.url();
+        let host = server.host_with_port();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            m:fetch('{url}/api/a')
+            return m:fetch('{url}/other')
+            "#
+        );
+        let mut permissions = Permissions::default();
+        permissions.set_net(NetPermissions::new([format!("{host}/api/*")]));
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        let expected = format!("{host}/other is not allowed to connect");
+        assert!(err.to_string().contains(&expected), "{err}");
+
+        allowed_mock.assert();
+        denied_mock.assert();
+    }
+
+    #[test]
+    fn http_get_evaluation_timeout() {
+        // the server accepts connections but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let script = format!("return require('@lmb/http'):fetch('http://127.0.0.1:{port}')");
+        let e = EvaluationBuilder::new(script, empty())
+            .timeout(Some(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("timeout"));
+        assert!(start.elapsed().as_secs() < 10);
+        drop(listener);
+    }
+
+    #[test_case("{ read_timeout = 100 }", HttpTimeouts::default(); "read timeout option")]
+    #[test_case("{ timeout = 100 }", HttpTimeouts::default(); "total timeout option")]
+    #[test_case("nil", {
+        let mut t = HttpTimeouts::default();
+        t.set_read(Some(Duration::from_millis(100)));
+        t
+    }; "read timeout")]
+    #[test_case("{ timeout = 100 }", {
+        let mut t = HttpTimeouts::default();
+        t.set_total(Some(Duration::from_secs(60)));
+        t
+    }; "option takes precedence")]
+    fn http_get_timeouts(options: &str, timeouts: HttpTimeouts) {
+        // the server accepts connections but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let script =
+            format!("return require('@lmb/http'):fetch('http://127.0.0.1:{port}', {options})");
+        let mut permissions = Permissions::default();
+        permissions.set_http_timeouts(timeouts);
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        assert!(e.evaluate().is_err());
+        assert!(start.elapsed().as_secs() < 10);
+        drop(listener);
+    }
+
     #[test]
     fn http_get_headers() {
         let mut server = Server::new();
@@ -189,13 +1203,43 @@ mod tests {
             return res:read('*a')
             "#
         );
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(body), res.payload());
 
         get_mock.assert();
     }
 
+    #[test]
+    fn http_get_request_id() {
+        let mut server = Server::new();
+
+        let propagated = server
+            .mock("GET", "/propagated")
+            .match_header("x-request-id", "abc")
+            .create();
+        let overridden = server
+            .mock("GET", "/overridden")
+            .match_header("x-request-id", "def")
+            .create();
+
+        let url = server.url();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            m:fetch('{url}/propagated')
+            m:fetch('{url}/overridden', {{ headers = {{ ['X-Request-Id'] = 'def' }} }})
+            "#
+        );
+        let state = Arc::new(InvocationState::new());
+        state.set_request(json!({ "id": "abc" }));
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        e.evaluate_with_state(state).unwrap();
+
+        propagated.assert();
+        overridden.assert();
+    }
+
     #[test]
     fn http_get_unicode() {
         let mut server = Server::new();
@@ -215,13 +1259,38 @@ mod tests {
             return res:read_unicode('*a')
             "#
         );
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(body), res.payload());
 
         get_mock.assert();
     }
 
+    #[test]
+    fn http_get_pipe() {
+        let mut server = Server::new();
+
+        let get_mock = server.mock("GET", "/lines").with_body("1\n2\n3\n").create();
+
+        let url = server.url();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            local reader = m:fetch('{url}/lines'):pipe()
+            local sum = reader:read('*n')
+            for line in reader:lines() do
+              sum = sum + (tonumber(line) or 0)
+            end
+            return sum
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(6), res.payload());
+
+        get_mock.assert();
+    }
+
     #[test]
     fn http_get_json() {
         let mut server = Server::new();
@@ -241,13 +1310,87 @@ mod tests {
             return res:json()
             "#
         );
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!({ "a": 1 }), res.payload());
 
         get_mock.assert();
     }
 
+    #[test]
+    fn http_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.yaml");
+        let mut server = Server::new();
+        let mock = server
+            .mock("POST", "/count")
+            .with_header("content-type", "application/json; charset=utf-8")
+            .with_body(r#"{"count":1}"#)
+            .create();
+        let url = server.url();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            local res = m:fetch('{url}/count', {{ method = 'POST', body = 'a' }})
+            return {{ res.status_code, res.content_type, res.charset, res:json() }}
+            "#
+        );
+        let expected = json!([200, "application/json", "utf-8", { "count": 1 }]);
+        let e = EvaluationBuilder::new(&script, empty())
+            .cassette(Cassette::record(&path))
+            .build()
+            .unwrap();
+        assert_eq!(&expected, e.evaluate().unwrap().payload());
+        mock.assert();
+        drop(server);
+
+        let cassette = Cassette::replay(&path).unwrap();
+        let interactions = cassette.interactions();
+        assert_eq!(1, interactions.len());
+        assert_eq!(Some("a"), interactions[0].request.body.as_deref());
+        let e = EvaluationBuilder::new(&script, empty())
+            .cassette(cassette)
+            .build()
+            .unwrap();
+        assert_eq!(&expected, e.evaluate().unwrap().payload());
+        // each interaction is replayed once
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("no interaction of POST"));
+    }
+
+    #[test]
+    fn http_record_and_replay_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.yaml");
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/bin")
+            .with_header("content-type", "application/octet-stream")
+            .with_body([0xff, 0x00, 0xfe])
+            .create();
+        let url = server.url();
+        let script = format!(
+            r#"
+            local res = require('@lmb/http'):fetch('{url}/bin')
+            return {{ string.byte(res:read('*a'), 1, -1) }}
+            "#
+        );
+        let expected = json!([0xff, 0x00, 0xfe]);
+        let e = EvaluationBuilder::new(&script, empty())
+            .cassette(Cassette::record(&path))
+            .build()
+            .unwrap();
+        assert_eq!(&expected, e.evaluate().unwrap().payload());
+        mock.assert();
+        drop(server);
+
+        let e = EvaluationBuilder::new(&script, empty())
+            .cassette(Cassette::replay(&path).unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(&expected, e.evaluate().unwrap().payload());
+    }
+
     #[test]
     fn http_post() {
         let mut server = Server::new();
@@ -270,10 +1413,223 @@ mod tests {
             return res:read('*a')
             "#
         );
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!("2"), res.payload());
 
         post_mock.assert();
     }
+
+    #[test]
+    fn http_get_address_order() {
+        let mut server = Server::new();
+
+        let get_mock = server.mock("GET", "/").with_status(200).expect(2).create();
+
+        // localhost may resolve to ::1 without listeners, which falls back to 127.0.0.1
+        let port = server.socket_address().port();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            local url = 'http://localhost:{port}/'
+            assert(m:fetch(url, {{ prefer_family = 'ipv6' }}).ok)
+            assert(m:fetch(url, {{ prefer_family = 'ipv4', interleave = false }}).ok)
+            m:fetch(url, {{ prefer_family = 'ipv5' }})
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("unsupported family ipv5"), "{err}");
+
+        get_mock.assert();
+    }
+
+    #[test]
+    fn http_post_signed() {
+        let mut server = Server::new();
+
+        let default_mock = server
+            .mock("POST", "/webhook")
+            .match_header(
+                "x-signature",
+                "4f4bb3a54e99c4a20e243485229f9b08c66e09104ba6f79c23ce647242a4ce84",
+            )
+            .create();
+        let custom_mock = server
+            .mock("GET", "/webhook")
+            .match_header(
+                "x-hub-signature",
+                "f9e66e179b6747ae54108f82f8ade8b3c25d76fd30afde6c395822c530196169",
+            )
+            .create();
+
+        let url = server.url();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            m:fetch('{url}/webhook', {{
+              method = 'POST',
+              body = '{{"event":"ping"}}',
+              sign = {{ secret = 'secret' }},
+            }})
+            m:fetch('{url}/webhook', {{
+              sign = {{ algo = 'hmac-sha256', secret = 'secret', header = 'X-Hub-Signature' }},
+            }})
+            local results = {{}}
+            for _, sign in ipairs({{ {{ algo = 'md5', secret = 'secret' }}, {{}} }}) do
+              local ok, err = pcall(m.fetch, m, '{url}/webhook', {{ sign = sign }})
+              assert(not ok)
+              table.insert(results, tostring(err))
+            end
+            return results
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let errors = res.payload().as_array().unwrap();
+        assert!(errors[0]
+            .as_str()
+            .unwrap()
+            .contains("unsupported algorithm md5"));
+        assert!(errors[1]
+            .as_str()
+            .unwrap()
+            .contains("secret is required to sign the request"));
+
+        default_mock.assert();
+        custom_mock.assert();
+    }
+
+    #[test]
+    fn http_retry() {
+        let mut server = Server::new();
+
+        let unavailable_mock = server
+            .mock("GET", "/retry")
+            .with_status(503)
+            .with_header("retry-after", "0")
+            .expect(2)
+            .create();
+        let ok_mock = server
+            .mock("GET", "/retry")
+            .with_header("content-type", "text/plain")
+            .with_body("ok")
+            .create();
+
+        let url = server.url();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            local res = m:fetch('{url}/retry', {{ retries = 3, retry_delay = 1 }})
+            return res:read('*a')
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!("ok"), res.payload());
+
+        unavailable_mock.assert();
+        ok_mock.assert();
+    }
+
+    #[test]
+    fn http_retry_exhausted() {
+        let mut server = Server::new();
+
+        let unavailable_mock = server
+            .mock("GET", "/retry")
+            .with_status(429)
+            .expect(3)
+            .create();
+
+        let url = server.url();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            local res = m:fetch('{url}/retry', {{
+              retries = 2,
+              retry_delay = 1,
+              backoff = 'constant',
+            }})
+            return res.status_code
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(429), res.payload());
+
+        unavailable_mock.assert();
+    }
+
+    #[test]
+    fn http_retry_not_listed() {
+        let mut server = Server::new();
+
+        let error_mock = server
+            .mock("GET", "/retry")
+            .with_status(500)
+            .expect(1)
+            .create();
+
+        let url = server.url();
+        let script = format!(
+            r#"
+            local m = require('@lmb/http')
+            local res = m:fetch('{url}/retry', {{ retries = 2, retry_on = {{ 503 }} }})
+            return res.status_code
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(500), res.payload());
+
+        error_mock.assert();
+    }
+
+    #[test_case(Backoff::Constant, 3, 50, 100)]
+    #[test_case(Backoff::Exponential, 0, 50, 100)]
+    #[test_case(Backoff::Exponential, 2, 200, 400)]
+    fn retry_delay(backoff: Backoff, attempt: usize, min: u64, max: u64) {
+        let policy = RetryPolicy {
+            backoff,
+            delay: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let delay = policy.delay(attempt, None);
+        assert!(delay >= Duration::from_millis(min), "{delay:?}");
+        assert!(delay <= Duration::from_millis(max), "{delay:?}");
+    }
+
+    #[test_case(true, None, "[::1]:80 127.0.0.1:80 [::2]:80 127.0.0.2:80 [::3]:80")]
+    #[test_case(
+        true,
+        Some(false),
+        "127.0.0.1:80 [::1]:80 127.0.0.2:80 [::2]:80 [::3]:80"
+    )]
+    #[test_case(false, None, "[::1]:80 [::2]:80 [::3]:80 127.0.0.1:80 127.0.0.2:80")]
+    #[test_case(
+        false,
+        Some(false),
+        "127.0.0.1:80 127.0.0.2:80 [::1]:80 [::2]:80 [::3]:80"
+    )]
+    fn address_order(interleave: bool, prefer_ipv6: Option<bool>, expected: &str) {
+        let addrs = "[::1]:80 [::2]:80 127.0.0.1:80 [::3]:80 127.0.0.2:80"
+            .split(' ')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let order = AddressOrder {
+            interleave,
+            prefer_ipv6,
+        };
+        let sorted = order.sort(addrs);
+        let sorted = sorted.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(expected, sorted.join(" "));
+    }
+
+    #[test_case("120", Some(Duration::from_secs(120)))]
+    #[test_case("Wed, 21 Oct 2015 07:28:00 GMT", None)]
+    #[test_case("soon", None)]
+    fn retry_after(value: &str, expected: Option<Duration>) {
+        assert_eq!(expected, parse_retry_after(value));
+    }
 }
@@ -0,0 +1,119 @@
+use mlua::prelude::*;
+use std::collections::HashMap;
+
+use super::K_STATE;
+use crate::{fallback_locales, interpolate, Catalog};
+
+/// Template of the key in catalogs of the state e.g. `state.i18n["zh-TW"].hello`.
+fn lookup_state(vm: &Lua, locale: &str, key: &str) -> LuaResult<Option<String>> {
+    let LuaValue::Table(state) = vm.named_registry_value::<LuaValue<'_>>(K_STATE)? else {
+        return Ok(None);
+    };
+    let Some(catalogs) = state.get::<_, Option<LuaTable<'_>>>("i18n")? else {
+        return Ok(None);
+    };
+    for locale in fallback_locales(locale) {
+        if let Some(messages) = catalogs.get::<_, Option<LuaTable<'_>>>(locale)? {
+            if let Some(template) = messages.get::<_, Option<String>>(key)? {
+                return Ok(Some(template));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Internationalization module
+pub struct LuaModI18n {
+    catalog: Catalog,
+}
+
+impl LuaModI18n {
+    pub fn new(catalog: Catalog) -> Self {
+        Self { catalog }
+    }
+}
+
+impl LuaUserData for LuaModI18n {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("locale", |_, this| Ok(this.catalog.locale().to_string()));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Catalogs of the state take precedence over catalogs of the directory.
+        methods.add_method(
+            "t",
+            |vm, this, (key, vars, locale): (String, Option<LuaTable<'lua>>, Option<String>)| {
+                let locale = locale.as_deref().unwrap_or(this.catalog.locale());
+                let template = match lookup_state(vm, locale, &key)? {
+                    Some(template) => template,
+                    None => this
+                        .catalog
+                        .lookup(locale, &key)
+                        .unwrap_or(&key)
+                        .to_string(),
+                };
+                let mut values = HashMap::new();
+                if let Some(vars) = vars {
+                    for pair in vars.pairs::<String, LuaValue<'lua>>() {
+                        let (k, v) = pair?;
+                        values.insert(k, v.to_string()?);
+                    }
+                }
+                Ok(interpolate(&template, &values))
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::io::empty;
+    use test_case::test_case;
+
+    use crate::{Catalog, EvaluationBuilder};
+
+    #[test_case("nil", "你好，lmb")]
+    #[test_case("'fr'", "bonjour, lmb")]
+    #[test_case("'de'", "hello, lmb")]
+    fn i18n_t(locale: &str, expected: &str) {
+        let mut catalog = Catalog::new("zh-TW");
+        catalog
+            .add("en", [("hello", "hello, {name}")])
+            .add("zh", [("hello", "你好，{name}")]);
+        let script =
+            format!("return require('@lmb/i18n'):t('hello', {{ name = 'lmb' }}, {locale})");
+        let e = EvaluationBuilder::new(script, empty())
+            .catalog(catalog)
+            .app_state(Some(
+                json!({ "i18n": { "fr": { "hello": "bonjour, {name}" } } }),
+            ))
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(expected), res.payload());
+    }
+
+    #[test]
+    fn i18n_state_takes_precedence() {
+        let mut catalog = Catalog::new("en");
+        catalog.add("en", [("n", "{n} from catalog")]);
+        let script = r"
+        local m = require('@lmb')
+        local i18n = require('@lmb/i18n')
+        local before = i18n:t('n', { n = 1 })
+        m.state.i18n = { en = { n = '{n} from state' } }
+        return { before, i18n:t('n', { n = 2 }), i18n:t('missing'), i18n.locale }
+        ";
+        let e = EvaluationBuilder::new(script, empty())
+            .catalog(catalog)
+            .app_state(Some(json!({})))
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(
+            &json!(["1 from catalog", "2 from state", "missing", "en"]),
+            res.payload()
+        );
+    }
+}
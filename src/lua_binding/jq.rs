@@ -0,0 +1,143 @@
+use jaq_core::{
+    load::{self, Arena, File, Loader},
+    Compiler, Ctx, RcIter,
+};
+use jaq_json::Val;
+use mlua::prelude::*;
+use serde_json::Value;
+
+use super::bound_timeout;
+
+// the rest of the program from where the error occurs is found
+fn expected(what: &str, found: &str) -> String {
+    if found.is_empty() {
+        return format!("expected {what} at the end");
+    }
+    let found = found.chars().take(16).collect::<String>();
+    format!("expected {what} at {found:?}")
+}
+
+fn load_error(error: load::Error<&str>) -> String {
+    match error {
+        load::Error::Io(errors) => errors
+            .into_iter()
+            .map(|(path, message)| format!("cannot load {path}: {message}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+        load::Error::Lex(errors) => errors
+            .into_iter()
+            .map(|(expect, found)| expected(expect.as_str(), found))
+            .collect::<Vec<_>>()
+            .join(", "),
+        load::Error::Parse(errors) => errors
+            .into_iter()
+            .map(|(expect, found)| expected(expect.as_str(), found))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+// outputs are collected in order, and the deadline of the evaluation is checked between them
+fn run(vm: &Lua, program: &str, value: Value) -> LuaResult<Vec<Value>> {
+    let invalid = |message: String| LuaError::runtime(format!("invalid jq program: {message}"));
+    let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+    let arena = Arena::default();
+    let file = File {
+        code: program,
+        path: (),
+    };
+    let modules = loader.load(&arena, file).map_err(|errors| {
+        invalid(
+            errors
+                .into_iter()
+                .map(|(_, e)| load_error(e))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    })?;
+    let filter = Compiler::default()
+        .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+        .compile(modules)
+        .map_err(|errors| {
+            invalid(
+                errors
+                    .into_iter()
+                    .flat_map(|(_, e)| e)
+                    .map(|(name, _)| format!("{name} is undefined"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )
+        })?;
+    let inputs = RcIter::new(core::iter::empty());
+    let mut outputs = vec![];
+    for output in filter.run((Ctx::new([], &inputs), Val::from(value))) {
+        bound_timeout(vm, None)?;
+        let output = output.map_err(|e| LuaError::runtime(e.to_string()))?;
+        outputs.push(Value::from(output));
+    }
+    Ok(outputs)
+}
+
+/// jq module, which runs jq programs on values with [jaq](https://github.com/01mf02/jaq).
+pub struct LuaModJq {}
+
+impl LuaUserData for LuaModJq {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // return all outputs of the program in a list
+        methods.add_function("run", |vm, (program, value): (String, LuaValue<'lua>)| {
+            let value: Value = vm.from_value(value)?;
+            vm.to_value(&run(vm, &program, value)?)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use std::io::empty;
+    use test_case::test_case;
+
+    use crate::EvaluationBuilder;
+
+    #[test_case(".a", json!([1]))]
+    #[test_case(".b[]", json!([2, 3]))]
+    #[test_case(".b | map(. * 10)", json!([[20, 30]]))]
+    #[test_case("{ sum: (.b | add), keys: keys }", json!([{ "sum": 5, "keys": ["a", "b"] }]))]
+    #[test_case(".b[] | select(. > 2) | tostring", json!(["3"]))]
+    #[test_case("empty", json!([]))]
+    fn jq_run(program: &str, expected: Value) {
+        let script = format!(
+            r#"
+            local jq = require('@lmb/jq')
+            return jq.run({program:?}, {{ a = 1, b = {{ 2, 3 }} }})
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test_case(".a |", "invalid jq program: expected term at the end")]
+    #[test_case("[.a", "invalid jq program: expected closing bracket at the end")]
+    #[test_case(".a | )", "invalid jq program: expected token at \")\"")]
+    #[test_case("nope", "nope is undefined")]
+    #[test_case("error(\"boom\")", "boom")]
+    #[test_case(".a + \"x\"", "cannot")]
+    fn jq_run_error(program: &str, expected: &str) {
+        let script = format!("return require('@lmb/jq').run({program:?}, {{ a = 1 }})");
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+
+    #[test]
+    fn jq_run_timeout() {
+        let script = "return require('@lmb/jq').run('repeat(.)', 1)";
+        let e = EvaluationBuilder::new(script, empty())
+            .timeout(Some(std::time::Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("timeout"), "{err}");
+    }
+}
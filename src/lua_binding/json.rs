@@ -1,5 +1,35 @@
 use mlua::prelude::*;
+use serde::Serialize as _;
 use serde_json::Value;
+use std::io::{self, BufWriter, Write};
+
+// Size of chunks passed to the writer function when encoding a stream.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+struct LuaWriter<'a, 'lua> {
+    vm: &'lua Lua,
+    f: &'a LuaFunction<'lua>,
+}
+
+impl Write for LuaWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk = self.vm.create_string(buf).map_err(io::Error::other)?;
+        self.f.call::<_, ()>(chunk).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn encode<T: serde::Serialize>(value: &T, pretty: bool) -> LuaResult<String> {
+    if pretty {
+        serde_json::to_string_pretty(value).into_lua_err()
+    } else {
+        serde_json::to_string(value).into_lua_err()
+    }
+}
 
 /// JSON module
 pub struct LuaModJSON {}
@@ -9,9 +39,70 @@ impl LuaUserData for LuaModJSON {
         methods.add_method("decode", |vm, _, value: String| {
             vm.to_value(&serde_json::from_str::<Value>(&value).into_lua_err()?)
         });
-        methods.add_method("encode", |_, _, value: LuaValue<'lua>| {
-            serde_json::to_string(&value).into_lua_err()
+        methods.add_method(
+            "encode",
+            |_, _, (value, options): (LuaValue<'lua>, Option<LuaTable<'lua>>)| {
+                let (pretty, sort_keys) = match options {
+                    Some(options) => (
+                        options
+                            .get::<_, Option<bool>>("pretty")?
+                            .unwrap_or_default(),
+                        options
+                            .get::<_, Option<bool>>("sort_keys")?
+                            .unwrap_or_default(),
+                    ),
+                    None => (false, false),
+                };
+                if sort_keys {
+                    // objects of serde_json are ordered by keys
+                    let value = serde_json::to_value(&value).into_lua_err()?;
+                    return encode(&value, pretty);
+                }
+                encode(&value, pretty)
+            },
+        );
+        // Decode values e.g. NDJSON from chunks returned by the reader function until it returns nil.
+        methods.add_method("decode_stream", |vm, _, reader: LuaFunction<'lua>| {
+            let reader = vm.create_registry_value(reader)?;
+            let mut buf: Vec<u8> = vec![];
+            let mut eof = false;
+            vm.create_function_mut(move |vm, ()| {
+                let reader = vm.registry_value::<LuaFunction<'_>>(&reader)?;
+                loop {
+                    let mut values =
+                        serde_json::Deserializer::from_slice(&buf).into_iter::<Value>();
+                    let next = values.next();
+                    let offset = values.byte_offset();
+                    // a number at the end of the buffer may continue in the next chunk
+                    let complete = eof || offset < buf.len();
+                    match next {
+                        Some(Ok(value)) if complete || !value.is_number() => {
+                            buf.drain(..offset);
+                            return vm.to_value(&value);
+                        }
+                        Some(Err(e)) if eof || !e.is_eof() => return Err(e.into_lua_err()),
+                        None if eof => return Ok(LuaNil),
+                        _ => {}
+                    }
+                    match reader.call::<_, Option<LuaString<'_>>>(())? {
+                        Some(chunk) => buf.extend_from_slice(chunk.as_bytes()),
+                        None => eof = true,
+                    }
+                }
+            })
         });
+        // Encode the value and pass chunks of the output to the writer function.
+        methods.add_method(
+            "encode_stream",
+            |vm, _, (value, writer): (LuaValue<'lua>, LuaFunction<'lua>)| {
+                let mut w = BufWriter::with_capacity(CHUNK_SIZE, LuaWriter { vm, f: &writer });
+                value
+                    .serialize(&mut serde_json::Serializer::new(&mut w))
+                    .into_lua_err()?;
+                w.flush()?;
+                Ok(())
+            },
+        );
     }
 }
 
@@ -20,6 +111,7 @@ mod tests {
     use crate::EvaluationBuilder;
     use serde_json::{json, Value};
     use std::io::empty;
+    use test_case::test_case;
 
     #[test]
     fn json_decode() {
@@ -27,7 +119,7 @@ mod tests {
         local m = require('@lmb/json');
         return m:decode('{"bool":true,"num":2,"str":"hello"}')
         "#;
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         let expected = &json!({ "bool": true, "num": 2, "str": "hello" });
         assert_eq!(expected, res.payload());
@@ -39,12 +131,90 @@ mod tests {
         local m = require('@lmb/json');
         return m:encode({ bool = true, num = 2, str = 'hello' })
         "#;
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         let actual: Value = serde_json::from_str(res.payload().as_str().unwrap()).unwrap();
         assert_eq!(json!({"bool":true,"num":2,"str":"hello"}), actual);
     }
 
+    #[test]
+    fn json_decode_stream() {
+        let script = r#"
+        local m = require('@lmb/json')
+        local chunks = { '{"a":', '1}\n[1,', '2]\n1', '2\n', 'null\n', '"s"' }
+        local i = 0
+        local reader = function()
+          i = i + 1
+          return chunks[i]
+        end
+        local values = {}
+        for v in m:decode_stream(reader) do
+          table.insert(values, m:encode(v))
+        end
+        return values
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = json!([r#"{"a":1}"#, "[1,2]", "12", "null", r#""s""#]);
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test]
+    fn json_decode_stream_invalid() {
+        let script = r#"
+        local m = require('@lmb/json')
+        local chunks = { '{"a":1}', '{"a":' }
+        local i = 0
+        local next = m:decode_stream(function()
+          i = i + 1
+          return chunks[i]
+        end)
+        assert(next().a == 1)
+        return next()
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("EOF while parsing"));
+    }
+
+    #[test]
+    fn json_encode_stream() {
+        let script = r#"
+        local m = require('@lmb/json')
+        local chunks = {}
+        m:encode_stream({ a = string.rep('a', 10000) }, function(chunk)
+          table.insert(chunks, chunk)
+        end)
+        return { #chunks > 1, table.concat(chunks) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = format!(r#"{{"a":"{}"}}"#, "a".repeat(10000));
+        assert_eq!(&json!([true, expected]), res.payload());
+    }
+
+    #[test_case("{}", r#"{"b":[1,{"d":1,"c":2}],"a":true}"#; "default")]
+    #[test_case("{ sort_keys = true }", r#"{"a":true,"b":[1,{"c":2,"d":1}]}"#; "sort keys")]
+    #[test_case("{ pretty = true, sort_keys = true }", "{\n  \"a\": true,\n  \"b\": [\n    1,\n    {\n      \"c\": 2,\n      \"d\": 1\n    }\n  ]\n}"; "pretty")]
+    fn json_encode_options(options: &str, expected: &str) {
+        let script = format!(
+            r#"
+            local m = require('@lmb/json')
+            local value = m:decode('{{"b":[1,{{"d":1,"c":2}}],"a":true}}')
+            return m:encode(value, {options})
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let actual = res.payload().as_str().unwrap();
+        if options == "{}" {
+            let actual: Value = serde_json::from_str(actual).unwrap();
+            assert_eq!(serde_json::from_str::<Value>(expected).unwrap(), actual);
+        } else {
+            assert_eq!(expected, actual);
+        }
+    }
+
     #[test]
     fn json_decode_encode() {
         // https://github.com/rxi/json.lua/issues/19
@@ -52,7 +222,7 @@ mod tests {
         local m = require('@lmb/json');
         return m:encode(m:decode('{"a":[{}]}'))
         "#;
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         let actual: Value = serde_json::from_str(res.payload().as_str().unwrap()).unwrap();
         assert_eq!(json!({"a":[{}]}), actual);
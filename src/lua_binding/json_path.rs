@@ -0,0 +1,256 @@
+use mlua::prelude::*;
+use serde_json::{Map, Value};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Index(usize),
+    Key(String),
+}
+
+fn parse_path(path: &str) -> LuaResult<Vec<Segment>> {
+    let invalid = || LuaError::runtime(format!("invalid path {path}"));
+    let rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut chars = rest.chars().peekable();
+    let mut segments = vec![];
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => {
+                let mut key = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
+                {
+                    key.push(c);
+                }
+                if key.is_empty() {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Key(key));
+            }
+            '[' => {
+                let segment = match chars.next() {
+                    Some(quote @ ('\'' | '"')) => {
+                        let mut key = String::new();
+                        loop {
+                            match chars.next() {
+                                Some(c) if c == quote => break,
+                                Some(c) => key.push(c),
+                                None => return Err(invalid()),
+                            }
+                        }
+                        Segment::Key(key)
+                    }
+                    Some(c) if c.is_ascii_digit() => {
+                        let mut index = c.to_string();
+                        while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                            index.push(c);
+                        }
+                        Segment::Index(index.parse().into_lua_err()?)
+                    }
+                    _ => return Err(invalid()),
+                };
+                if chars.next() != Some(']') {
+                    return Err(invalid());
+                }
+                segments.push(segment);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(segments)
+}
+
+fn get<'a>(value: &'a Value, segments: &[Segment]) -> Option<&'a Value> {
+    segments.iter().try_fold(value, |v, s| match s {
+        Segment::Index(i) => v.get(i),
+        Segment::Key(k) => v.get(k),
+    })
+}
+
+fn set(value: &mut Value, segments: &[Segment], new: Value) -> LuaResult<()> {
+    let Some((last, parents)) = segments.split_last() else {
+        *value = new;
+        return Ok(());
+    };
+    let mut current = value;
+    for segment in parents {
+        create_object(current, segment);
+        current = match (segment, current) {
+            (Segment::Index(i), Value::Array(a)) => a
+                .get_mut(*i)
+                .ok_or_else(|| LuaError::runtime(format!("index {i} is out of range")))?,
+            (Segment::Key(k), Value::Object(o)) => o.entry(k.clone()).or_insert(Value::Null),
+            (segment, _) => return Err(not_found(segment)),
+        };
+    }
+    create_object(current, last);
+    match (last, current) {
+        (Segment::Index(i), Value::Array(a)) if *i < a.len() => a[*i] = new,
+        (Segment::Index(i), Value::Array(a)) if *i == a.len() => a.push(new),
+        (Segment::Index(i), Value::Array(_)) => {
+            return Err(LuaError::runtime(format!("index {i} is out of range")));
+        }
+        (Segment::Key(k), Value::Object(o)) => {
+            o.insert(k.clone(), new);
+        }
+        (segment, _) => return Err(not_found(segment)),
+    }
+    Ok(())
+}
+
+// missing objects along the path are created
+fn create_object(value: &mut Value, segment: &Segment) {
+    if value.is_null() && matches!(segment, Segment::Key(_)) {
+        *value = Value::Object(Map::new());
+    }
+}
+
+fn delete(value: &mut Value, segments: &[Segment]) {
+    let Some((last, parents)) = segments.split_last() else {
+        return;
+    };
+    let parent = parents.iter().try_fold(value, |v, s| match s {
+        Segment::Index(i) => v.get_mut(i),
+        Segment::Key(k) => v.get_mut(k),
+    });
+    match (last, parent) {
+        (Segment::Index(i), Some(Value::Array(a))) if *i < a.len() => {
+            a.remove(*i);
+        }
+        (Segment::Key(k), Some(Value::Object(o))) => {
+            o.remove(k);
+        }
+        _ => {}
+    }
+}
+
+fn not_found(segment: &Segment) -> LuaError {
+    match segment {
+        Segment::Index(i) => LuaError::runtime(format!("index {i} of non-array")),
+        Segment::Key(k) => LuaError::runtime(format!("key {k} of non-object")),
+    }
+}
+
+/// Path compiled by `compile` of `@lmb/json-path`, which can be applied to many documents.
+#[derive(Clone, Debug)]
+pub struct LuaJsonPath {
+    segments: Vec<Segment>,
+}
+
+impl LuaUserData for LuaJsonPath {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("get", |vm, this, doc: LuaValue<'lua>| {
+            let doc: Value = vm.from_value(doc)?;
+            get(&doc, &this.segments).map_or(Ok(LuaNil), |v| vm.to_value(v))
+        });
+        methods.add_method(
+            "set",
+            |vm, this, (doc, value): (LuaValue<'lua>, LuaValue<'lua>)| {
+                let mut doc: Value = vm.from_value(doc)?;
+                set(&mut doc, &this.segments, vm.from_value(value)?)?;
+                vm.to_value(&doc)
+            },
+        );
+        methods.add_method("delete", |vm, this, doc: LuaValue<'lua>| {
+            let mut doc: Value = vm.from_value(doc)?;
+            delete(&mut doc, &this.segments);
+            vm.to_value(&doc)
+        });
+    }
+}
+
+/// JSON path module
+pub struct LuaModJSONPath {}
+
+impl LuaUserData for LuaModJSONPath {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("compile", |_, _, path: String| {
+            Ok(LuaJsonPath {
+                segments: parse_path(&path)?,
+            })
+        });
+        methods.add_method("get", |vm, _, (doc, path): (LuaValue<'lua>, String)| {
+            let doc: Value = vm.from_value(doc)?;
+            get(&doc, &parse_path(&path)?).map_or(Ok(LuaNil), |v| vm.to_value(v))
+        });
+        methods.add_method(
+            "set",
+            |vm, _, (doc, path, value): (LuaValue<'lua>, String, LuaValue<'lua>)| {
+                let mut doc: Value = vm.from_value(doc)?;
+                set(&mut doc, &parse_path(&path)?, vm.from_value(value)?)?;
+                vm.to_value(&doc)
+            },
+        );
+        methods.add_method("delete", |vm, _, (doc, path): (LuaValue<'lua>, String)| {
+            let mut doc: Value = vm.from_value(doc)?;
+            delete(&mut doc, &parse_path(&path)?);
+            vm.to_value(&doc)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use std::io::empty;
+    use test_case::test_case;
+
+    use super::{parse_path, Segment};
+    use crate::EvaluationBuilder;
+
+    #[test_case("$", vec![])]
+    #[test_case("$.a[0]['b c'][\"d\"]", vec![
+        Segment::Key("a".into()),
+        Segment::Index(0),
+        Segment::Key("b c".into()),
+        Segment::Key("d".into()),
+    ])]
+    fn parse(path: &str, expected: Vec<Segment>) {
+        assert_eq!(expected, parse_path(path).unwrap());
+    }
+
+    #[test_case("a"; "without root")]
+    #[test_case("$.")]
+    #[test_case("$[a]")]
+    #[test_case("$['a'"; "unclosed bracket")]
+    #[test_case("$[0")]
+    fn parse_invalid(path: &str) {
+        assert!(parse_path(path).is_err());
+    }
+
+    #[test_case("return m:get(doc, '$.a.b[1]')", json!(2))]
+    #[test_case("return m:get(doc, '$.x.y')", json!(null))]
+    #[test_case("return m:set(doc, '$.a.b[0]', 9)", json!({ "a": { "b": [9, 2] } }))]
+    #[test_case("return m:set(doc, '$.a.b[2]', 3)", json!({ "a": { "b": [1, 2, 3] } }))]
+    #[test_case("return m:set(doc, '$.x.y', true)", json!({ "a": { "b": [1, 2] }, "x": { "y": true } }))]
+    #[test_case("return m:delete(doc, '$.a.b[0]')", json!({ "a": { "b": [2] } }))]
+    #[test_case("return m:delete(doc, '$.a')", json!({}))]
+    #[test_case("return m:delete(doc, '$.x')", json!({ "a": { "b": [1, 2] } }))]
+    #[test_case("local p = m:compile('$.a.b'); return { p:get(doc), p:get(p:set(doc, 0)), p:delete(doc) }", json!([[1, 2], 0, { "a": {} }]))]
+    fn json_path(body: &str, expected: Value) {
+        let script = format!(
+            r#"
+            local m = require('@lmb/json-path')
+            local doc = {{ a = {{ b = {{ 1, 2 }} }} }}
+            {body}
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test_case("return m:set(doc, '$.a.b[3]', 1)", "index 3 is out of range")]
+    #[test_case("return m:set(doc, '$.a[0]', 1)", "index 0 of non-array")]
+    #[test_case("return m:get(doc, 'a')", "invalid path a")]
+    fn json_path_error(body: &str, expected: &str) {
+        let script = format!(
+            r#"
+            local m = require('@lmb/json-path')
+            local doc = {{ a = {{ b = {{ 1, 2 }} }} }}
+            {body}
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+}
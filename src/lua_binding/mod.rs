@@ -1,24 +1,515 @@
+use chrono::{DateTime, Utc};
 use mlua::prelude::*;
+use parking_lot::Mutex;
 use serde_json::Value;
 use std::{
-    io::{stderr, stdout, Read, Write as _},
+    cell::RefCell,
+    fmt::Debug,
+    io::{stderr, stdin, stdout, IsTerminal as _, Read, Write as _},
     sync::Arc,
+    time::{Duration, Instant},
 };
+use tempfile::TempDir;
 
-use crate::{Input, Result, State, StateKey, Store};
+#[cfg(feature = "http")]
+use crate::Cassette;
+#[cfg(feature = "protobuf")]
+use crate::ProtoDescriptors;
+use crate::{
+    acquire_lock, find_external, invalidate_cache, release_lock, Catalog, DryRun, Error, HttpError,
+    Input, InvocationState, Metrics, NestedUpdateError, Permissions, Result, SideEffectKind,
+    StoreBackend, StoreTransaction, RESERVED_KEY_PREFIX,
+};
 
+pub use assert::Snapshots;
+use assert::*;
+#[cfg(feature = "cbor")]
+use cbor::*;
+#[cfg(feature = "coroutine")]
+pub(crate) use coroutine::*;
+#[cfg(feature = "crypto")]
 use crypto::*;
+pub(crate) use definitions::*;
+#[cfg(feature = "diff")]
+use diff::*;
+#[cfg(feature = "dns")]
+use dns::*;
+#[cfg(feature = "encoding")]
+use encoding::*;
+#[cfg(feature = "http")]
 use http::*;
+use i18n::*;
+#[cfg(feature = "jq")]
+use jq::*;
 use json::*;
+#[cfg(feature = "json-path")]
+use json_path::*;
+#[cfg(feature = "msgpack")]
+use msgpack::*;
+use prometheus::*;
+#[cfg(feature = "protobuf")]
+use protobuf::*;
 use read::*;
+use runtime::*;
+pub use shell::*;
+use signal::*;
+pub use socket::*;
+use timer::*;
+#[cfg(feature = "toml")]
+use toml::*;
+#[cfg(feature = "url")]
+use url::*;
+#[cfg(feature = "yaml")]
+use yaml::*;
 
+mod assert;
+#[cfg(feature = "cbor")]
+mod cbor;
+#[cfg(feature = "coroutine")]
+mod coroutine;
+#[cfg(feature = "crypto")]
 mod crypto;
+mod definitions;
+#[cfg(feature = "diff")]
+mod diff;
+#[cfg(feature = "dns")]
+mod dns;
+#[cfg(feature = "encoding")]
+mod encoding;
+#[cfg(feature = "http")]
 mod http;
+mod i18n;
+#[cfg(feature = "jq")]
+mod jq;
 mod json;
+#[cfg(feature = "json-path")]
+mod json_path;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+mod prometheus;
+#[cfg(feature = "protobuf")]
+mod protobuf;
 mod read;
+mod runtime;
+mod shell;
+mod signal;
+mod socket;
+mod timer;
+#[cfg(feature = "toml")]
+mod toml;
+#[cfg(feature = "url")]
+mod url;
+#[cfg(feature = "yaml")]
+mod yaml;
 
 // ref: https://www.lua.org/pil/8.1.html
+const K_APP_STATE: &str = "lmb_app_state";
+const K_ARGS: &str = "lmb_args";
+const K_ENV: &str = "lmb_env";
 const K_LOADED: &str = "_LOADED";
+const K_STATE: &str = "lmb_state";
+
+/// Deadline of the running evaluation, kept in app data of the Lua virtual machine.
+pub(crate) struct Deadline(pub(crate) Instant);
+
+/// Usage of `@lmb/http` by the running evaluation, kept in app data of the Lua virtual machine.
+#[cfg(feature = "http")]
+#[derive(Default)]
+pub(crate) struct HttpUsage {
+    pub(crate) bytes: Arc<std::sync::atomic::AtomicU64>,
+    pub(crate) requests: std::sync::atomic::AtomicU64,
+}
+
+/// ID of the HTTP request being served, which `@lmb/http` propagates to outbound requests,
+/// kept in app data of the Lua virtual machine.
+#[cfg(feature = "http")]
+pub(crate) struct RequestId(pub(crate) String);
+
+/// Max bytes of a single read of the input, kept in app data of the Lua virtual machine.
+pub(crate) struct MaxInputBytes(pub(crate) usize);
+
+/// Memory limit of the evaluation in bytes, kept in app data of the Lua virtual machine.
+pub(crate) struct MemoryLimit(pub(crate) usize);
+
+/// Start of the running evaluation, kept in app data of the Lua virtual machine.
+pub(crate) struct Started(pub(crate) Instant);
+
+/// Time frozen at the start of the running evaluation in deterministic mode,
+/// kept in app data of the Lua virtual machine.
+pub(crate) struct FrozenTime(pub(crate) i64);
+
+/// Modules disabled in deterministic mode, which reach the network, run processes
+/// or wait for signals.
+pub(crate) const NONDETERMINISTIC_MODULES: [&str; 6] = [
+    "@lmb/dns",
+    "@lmb/http",
+    "@lmb/shell",
+    "@lmb/signal",
+    "@lmb/tcp",
+    "@lmb/udp",
+];
+
+/// Scratch directory of the running evaluation, kept in app data of the Lua virtual machine.
+/// The directory is created on first access and deleted after the evaluation.
+#[derive(Default)]
+pub(crate) struct ScratchDir(Option<TempDir>);
+
+/// Bound the timeout of a blocking host call by the deadline of the evaluation, since
+/// the interrupt only fires while Lua executes. Fail when the deadline has passed.
+pub(crate) fn bound_timeout(vm: &Lua, timeout: Option<Duration>) -> LuaResult<Option<Duration>> {
+    let Some(deadline) = vm.app_data_ref::<Deadline>() else {
+        return Ok(timeout);
+    };
+    match deadline.0.checked_duration_since(Instant::now()) {
+        Some(remaining) if !remaining.is_zero() => {
+            Ok(Some(timeout.map_or(remaining, |t| t.min(remaining))))
+        }
+        _ => Err(LuaError::runtime("timeout")),
+    }
+}
+
+/// Convert a decoded value into Lua, where bytes become Lua strings
+/// which may not be valid UTF-8, and arrays are marked to be encoded as arrays.
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+pub(crate) fn decoded_into_lua(vm: &Lua, value: serde_value::Value) -> LuaResult<LuaValue<'_>> {
+    use serde_value::Value as V;
+    Ok(match value {
+        V::Bool(b) => LuaValue::Boolean(b),
+        V::U8(n) => n.into_lua(vm)?,
+        V::U16(n) => n.into_lua(vm)?,
+        V::U32(n) => n.into_lua(vm)?,
+        V::U64(n) => n.into_lua(vm)?,
+        V::I8(n) => n.into_lua(vm)?,
+        V::I16(n) => n.into_lua(vm)?,
+        V::I32(n) => n.into_lua(vm)?,
+        V::I64(n) => n.into_lua(vm)?,
+        V::F32(n) => LuaValue::Number(n.into()),
+        V::F64(n) => LuaValue::Number(n),
+        V::Char(c) => c.to_string().into_lua(vm)?,
+        V::String(s) => s.into_lua(vm)?,
+        V::Bytes(b) => LuaValue::String(vm.create_string(b)?),
+        V::Unit | V::Option(None) => LuaValue::NULL,
+        V::Option(Some(v)) | V::Newtype(v) => decoded_into_lua(vm, *v)?,
+        V::Seq(items) => {
+            let t = vm.create_table_with_capacity(items.len(), 0)?;
+            for item in items {
+                t.raw_push(decoded_into_lua(vm, item)?)?;
+            }
+            t.set_metatable(Some(vm.array_metatable()));
+            LuaValue::Table(t)
+        }
+        V::Map(entries) => {
+            let t = vm.create_table_with_capacity(0, entries.len())?;
+            for (k, v) in entries {
+                t.raw_set(decoded_into_lua(vm, k)?, decoded_into_lua(vm, v)?)?;
+            }
+            LuaValue::Table(t)
+        }
+    })
+}
+
+/// Provider of a custom Lua module registered from host code,
+/// see [`crate::EvaluationBuilder::module`].
+///
+/// Any [`mlua::UserData`] that can be cloned is a module provider.
+pub trait ModuleProvider: Debug + Send + Sync {
+    /// Create the value returned by `require` in Lua.
+    fn provide<'lua>(&self, vm: &'lua Lua) -> LuaResult<LuaValue<'lua>>;
+}
+
+impl<T> ModuleProvider for T
+where
+    T: 'static + LuaUserData + Clone + Debug + Send + Sync,
+{
+    fn provide<'lua>(&self, vm: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+        Ok(LuaValue::UserData(vm.create_userdata(self.clone())?))
+    }
+}
+
+/// Custom modules keyed by the name passed to `require`.
+pub type Modules = Vec<(String, Arc<dyn ModuleProvider>)>;
+
+/// Register custom modules to a Lua virtual machine.
+/// Modules named `@lmb` or under `@lmb/` are reserved for built-in modules.
+pub(crate) fn register_modules(vm: &Lua, modules: &Modules) -> Result<()> {
+    let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
+    for (name, module) in modules {
+        if name == "@lmb" || name.starts_with("@lmb/") {
+            return Err(Error::ReservedModule(name.clone()));
+        }
+        loaded.set(name.as_str(), module.provide(vm)?)?;
+    }
+    vm.set_named_registry_value(K_LOADED, loaded)?;
+    Ok(())
+}
+
+fn freeze(value: &LuaValue<'_>) -> LuaResult<()> {
+    if let LuaValue::Table(t) = value {
+        for pair in t.clone().pairs::<LuaValue<'_>, LuaValue<'_>>() {
+            let (_, v) = pair?;
+            freeze(&v)?;
+        }
+        t.set_readonly(true);
+    }
+    Ok(())
+}
+
+/// Set global variables to a Lua virtual machine. Tables are made read-only.
+///
+/// Built-in globals take precedence, so globals named after them are ignored.
+pub(crate) fn register_globals(vm: &Lua, values: &[(String, Value)]) -> Result<()> {
+    let globals = vm.globals();
+    for (name, value) in values {
+        if !globals.get::<_, LuaValue<'_>>(name.as_str())?.is_nil() {
+            continue;
+        }
+        let value = vm.to_value(value)?;
+        freeze(&value)?;
+        globals.set(name.as_str(), value)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn register_args(vm: &Lua, args: &[String], named: &[(String, String)]) -> Result<()> {
+    let table = vm.create_sequence_from(args.iter().map(String::as_str))?;
+    for (key, value) in named {
+        table.set(key.as_str(), value.as_str())?;
+    }
+    let table = LuaValue::Table(table);
+    freeze(&table)?;
+    vm.set_named_registry_value(K_ARGS, table)?;
+    Ok(())
+}
+
+/// Set the application state, which is shared read-only by invocations as `app_state`.
+pub(crate) fn register_app_state(vm: &Lua, app_state: Option<&Value>) -> Result<()> {
+    let value = app_state.map_or(Ok(LuaNil), |v| vm.to_value(v))?;
+    freeze(&value)?;
+    vm.set_named_registry_value(K_APP_STATE, value)?;
+    Ok(())
+}
+
+/// Register `@lmb/assert`, which writes and compares snapshots under the directory.
+pub(crate) fn register_assert(vm: &Lua, snapshots: Option<&Snapshots>) -> Result<()> {
+    let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
+    loaded.set("@lmb/assert", LuaModAssert::new(snapshots.cloned()))?;
+    vm.set_named_registry_value(K_LOADED, loaded)?;
+    Ok(())
+}
+
+/// Register `@lmb/i18n`, which translates with the catalog.
+pub(crate) fn register_catalog(vm: &Lua, catalog: &Catalog) -> Result<()> {
+    let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
+    loaded.set("@lmb/i18n", LuaModI18n::new(catalog.clone()))?;
+    vm.set_named_registry_value(K_LOADED, loaded)?;
+    Ok(())
+}
+
+/// Register `@lmb/protobuf`, which encodes and decodes messages of the descriptors.
+#[cfg(feature = "protobuf")]
+pub(crate) fn register_proto_descriptors(vm: &Lua, descriptors: &ProtoDescriptors) -> Result<()> {
+    let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
+    loaded.set("@lmb/protobuf", LuaModProtobuf::new(descriptors.clone()))?;
+    vm.set_named_registry_value(K_LOADED, loaded)?;
+    Ok(())
+}
+
+/// Register `@lmb/prometheus`, which defines and updates the metrics.
+pub(crate) fn register_metrics(vm: &Lua, metrics: &Metrics) -> Result<()> {
+    let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
+    loaded.set("@lmb/prometheus", LuaModPrometheus::new(metrics.clone()))?;
+    vm.set_named_registry_value(K_LOADED, loaded)?;
+    Ok(())
+}
+
+/// Replace `os` with one reading the time frozen by [`FrozenTime`] instead of the clock.
+/// `os.clock` always returns 0, and `os.date` without a time formats the frozen time.
+pub(crate) fn register_deterministic(vm: &Lua) -> Result<()> {
+    let globals = vm.globals();
+    let os = globals.get::<_, LuaTable<'_>>("os")?;
+    let frozen = vm.create_table()?;
+    for pair in os.clone().pairs::<LuaValue<'_>, LuaValue<'_>>() {
+        let (k, v) = pair?;
+        frozen.set(k, v)?;
+    }
+    let date = vm.create_registry_value(os.get::<_, LuaFunction<'_>>("date")?)?;
+    let time = vm.create_registry_value(os.get::<_, LuaFunction<'_>>("time")?)?;
+    let now = |vm: &Lua| vm.app_data_ref::<FrozenTime>().map(|t| t.0);
+    frozen.set("clock", vm.create_function(|_, ()| Ok(0.0))?)?;
+    frozen.set(
+        "date",
+        vm.create_function(move |vm, (format, t): (LuaValue<'_>, Option<i64>)| {
+            let date = vm.registry_value::<LuaFunction<'_>>(&date)?;
+            date.call::<_, LuaValue<'_>>((format, t.or_else(|| now(vm))))
+        })?,
+    )?;
+    frozen.set(
+        "time",
+        vm.create_function(move |vm, t: Option<LuaTable<'_>>| match (t, now(vm)) {
+            (None, Some(now)) => Ok(LuaValue::Number(now as f64)),
+            (t, _) => vm
+                .registry_value::<LuaFunction<'_>>(&time)?
+                .call::<_, LuaValue<'_>>(t),
+        })?,
+    )?;
+    frozen.set_readonly(true);
+    globals.set("os", frozen)?;
+    Ok(())
+}
+
+/// Copy the application state into `state`, which the invocation is free to mutate
+/// without affecting other invocations.
+pub(crate) fn reset_state(vm: &Lua, app_state: Option<&Value>) -> Result<()> {
+    let value = app_state.map_or(Ok(LuaNil), |v| vm.to_value(v))?;
+    vm.set_named_registry_value(K_STATE, value)?;
+    Ok(())
+}
+
+/// Register modules which are only usable with [`Permissions`] granted.
+/// In [`DryRun`], side effects of the modules are recorded instead of executed,
+/// which takes precedence over the [`Cassette`] of HTTP interactions.
+pub(crate) fn register_permitted_modules(
+    vm: &Lua,
+    permissions: &Permissions,
+    dry_run: Option<&DryRun>,
+    #[cfg(feature = "http")] cassette: Option<&Cassette>,
+) -> Result<()> {
+    let env = match dry_run {
+        Some(dry_run) => LuaValue::Table(dry_run_env(vm, permissions, dry_run)?),
+        None => vm.to_value(permissions.env().vars())?,
+    };
+    freeze(&env)?;
+    vm.set_named_registry_value(K_ENV, env)?;
+    disable_modules(vm, permissions.disabled_modules())?;
+
+    let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
+    #[cfg(feature = "http")]
+    {
+        let mut http = LuaModHTTP::new(permissions.net().clone());
+        http.set_limits(permissions.http_limits().clone())
+            .set_timeouts(permissions.http_timeouts().clone())
+            .set_tls(permissions.http_tls().clone());
+        if let Some(cassette) = cassette {
+            http.set_cassette(cassette.clone());
+        }
+        if let Some(dry_run) = dry_run {
+            http.set_dry_run(dry_run.clone());
+        }
+        loaded.set("@lmb/http", http)?;
+    }
+    // lookups have no side effects, so they are not recorded in dry run
+    #[cfg(feature = "dns")]
+    loaded.set("@lmb/dns", LuaModDNS::new(permissions.net().clone()))?;
+    if let Some(dry_run) = dry_run {
+        loaded.set("@lmb/shell", dry_run_shell(vm, permissions, dry_run)?)?;
+        loaded.set("@lmb/tcp", dry_run_socket(vm, permissions, dry_run, "tcp")?)?;
+        loaded.set("@lmb/udp", dry_run_socket(vm, permissions, dry_run, "udp")?)?;
+    } else {
+        loaded.set("@lmb/shell", LuaModShell::new(permissions.run().clone()))?;
+        loaded.set("@lmb/tcp", LuaModTCP::new(permissions.net().clone()))?;
+        loaded.set("@lmb/udp", LuaModUDP::new(permissions.net().clone()))?;
+    }
+    vm.set_named_registry_value(K_LOADED, loaded)?;
+    Ok(())
+}
+
+/// Replace `require` with one failing for the disabled modules. Loaded modules are
+/// registered again by [`LuaBinding::register`], so they are rejected on `require` instead.
+fn disable_modules(vm: &Lua, disabled: &[String]) -> Result<()> {
+    if disabled.is_empty() {
+        return Ok(());
+    }
+    let globals = vm.globals();
+    let require = vm.create_registry_value(globals.get::<_, LuaFunction<'_>>("require")?)?;
+    let disabled = disabled.to_vec();
+    let f = vm.create_function(move |vm, name: String| {
+        if disabled.contains(&name) {
+            return Err(LuaError::runtime(format!(
+                "module {name} is disabled by policy"
+            )));
+        }
+        vm.registry_value::<LuaFunction<'_>>(&require)?
+            .call::<_, LuaValue<'_>>(name)
+    })?;
+    globals.set("require", f)?;
+    Ok(())
+}
+
+// environment variables in fixtures take precedence over permitted ones
+fn dry_run_env<'lua>(
+    vm: &'lua Lua,
+    permissions: &Permissions,
+    dry_run: &DryRun,
+) -> LuaResult<LuaTable<'lua>> {
+    let mut vars = permissions.env().vars().clone();
+    vars.extend(dry_run.fixtures().env.clone());
+    let dry_run = dry_run.clone();
+    let index = vm.create_function(move |_, (_, name): (LuaValue<'_>, String)| {
+        dry_run.record(SideEffectKind::Env, &name, Value::Null);
+        Ok(vars.get(&name).cloned())
+    })?;
+    let metatable = vm.create_table()?;
+    metatable.set("__index", index)?;
+    metatable.set_readonly(true);
+    let env = vm.create_table()?;
+    env.set_metatable(Some(metatable));
+    Ok(env)
+}
+
+fn dry_run_shell<'lua>(
+    vm: &'lua Lua,
+    permissions: &Permissions,
+    dry_run: &DryRun,
+) -> LuaResult<LuaTable<'lua>> {
+    let permissions = permissions.run().clone();
+    let dry_run = dry_run.clone();
+    let exec = vm.create_function(
+        move |vm, (_, cmd, args): (LuaValue<'_>, String, Option<Vec<String>>)| {
+            if !permissions.is_allowed(&cmd) {
+                return Err(LuaError::runtime(format!("{cmd} is not allowed to run")));
+            }
+            dry_run.record(SideEffectKind::Run, &cmd, args.unwrap_or_default().into());
+            let res = vm.create_table()?;
+            res.set("ok", true)?;
+            res.set("status", 0)?;
+            res.set("stdout", "")?;
+            res.set("stderr", "")?;
+            res.set("timed_out", false)?;
+            Ok(res)
+        },
+    )?;
+    let shell = vm.create_table()?;
+    shell.set("exec", exec)?;
+    Ok(shell)
+}
+
+fn dry_run_socket<'lua>(
+    vm: &'lua Lua,
+    permissions: &Permissions,
+    dry_run: &DryRun,
+    protocol: &'static str,
+) -> LuaResult<LuaTable<'lua>> {
+    let permissions = permissions.net().clone();
+    let dry_run = dry_run.clone();
+    let connect = vm.create_function(move |_, (_, host, port): (LuaValue<'_>, String, u16)| {
+        if !permissions.is_allowed(&host, port) {
+            return Err(LuaError::runtime(format!(
+                "{host}:{port} is not allowed to connect"
+            )));
+        }
+        dry_run.record(
+            SideEffectKind::Socket,
+            format!("{host}:{port}"),
+            protocol.into(),
+        );
+        Err::<(), _>(LuaError::runtime(format!(
+            "{host}:{port} is not connected in dry run"
+        )))
+    })?;
+    let socket = vm.create_table()?;
+    socket.set("connect", connect)?;
+    Ok(socket)
+}
 
 /// Interface between Lua and Rust.
 #[derive(Debug)]
@@ -27,15 +518,17 @@ where
     R: Read,
 {
     input: Input<R>,
-    state: Option<Arc<State>>,
-    store: Option<Store>,
+    state: Option<Arc<InvocationState>>,
+    store: Option<Arc<dyn StoreBackend>>,
+    // error raised by calls to the store while it is locked by update or transaction
+    locked: Mutex<Option<LuaError>>,
 }
 
 impl<R> LuaBinding<R>
 where
     for<'lua> R: 'lua + Read + Send,
 {
-    /// Create a new instance of interface with input [`Input`] and store [`Store`].
+    /// Create a new instance of interface with input [`Input`] and store [`StoreBackend`].
     ///
     /// <div class="warning">Export for benchmarking, but end-user should not directly use it.</div>
     ///
@@ -44,14 +537,19 @@ where
     /// # use parking_lot::Mutex;
     /// use lmb::*;
     /// let input = Arc::new(Mutex::new(BufReader::new(Cursor::new("0"))));
-    /// let store = Store::default();
+    /// let store = Arc::new(Store::default());
     /// let _ = LuaBinding::new(input, Some(store), None);
     /// ```
-    pub fn new(input: Input<R>, store: Option<Store>, state: Option<Arc<State>>) -> Self {
+    pub fn new(
+        input: Input<R>,
+        store: Option<Arc<dyn StoreBackend>>,
+        state: Option<Arc<InvocationState>>,
+    ) -> Self {
         Self {
             input,
             state,
             store,
+            locked: Mutex::new(None),
         }
     }
 
@@ -64,20 +562,37 @@ where
     /// use lmb::*;
     /// let vm = Lua::new();
     /// let input = Arc::new(Mutex::new(BufReader::new(Cursor::new("0"))));
-    /// let store = Store::default();
+    /// let store = Arc::new(Store::default());
     /// let _ = LuaBinding::register(&vm, input, Some(store), None);
     /// ```
     pub fn register(
         vm: &Lua,
         input: Input<R>,
-        store: Option<Store>,
-        state: Option<Arc<State>>,
+        store: Option<Arc<dyn StoreBackend>>,
+        state: Option<Arc<InvocationState>>,
     ) -> Result<()> {
         let io_table = vm.create_table()?;
 
+        let lines_fn = vm.create_function({
+            let input = input.clone();
+            move |vm, (name, formats): (Option<String>, LuaMultiValue<'_>)| {
+                if name.is_some() {
+                    return Err(LuaError::runtime(
+                        "io.lines only iterates standard input, files are not accessible",
+                    ));
+                }
+                let formats = ReadFormat::from_lua_multi(formats)?;
+                let input = input.clone();
+                vm.create_function(move |vm, ()| lua_lmb_read(vm, &input, &formats))
+            }
+        })?;
+        io_table.set("lines", lines_fn)?;
+
         let read_fn = vm.create_function({
             let input = input.clone();
-            move |vm, f: Option<LuaValue<'_>>| lua_lmb_read(vm, &input, f)
+            move |vm, formats: LuaMultiValue<'_>| {
+                lua_lmb_read(vm, &input, &ReadFormat::from_lua_multi(formats)?)
+            }
         })?;
         io_table.set("read", read_fn)?;
 
@@ -97,15 +612,48 @@ where
 
         let loaded = vm.named_registry_value::<LuaTable<'_>>(K_LOADED)?;
         loaded.set("@lmb", Self::new(input, store, state))?;
+        #[cfg(feature = "crypto")]
         loaded.set("@lmb/crypto", LuaModCrypto {})?;
-        loaded.set("@lmb/http", LuaModHTTP {})?;
+        #[cfg(feature = "diff")]
+        loaded.set("@lmb/diff", LuaModDiff {})?;
+        #[cfg(feature = "encoding")]
+        loaded.set("@lmb/encoding", LuaModEncoding {})?;
+        #[cfg(feature = "jq")]
+        loaded.set("@lmb/jq", LuaModJq {})?;
         loaded.set("@lmb/json", LuaModJSON {})?;
+        #[cfg(feature = "json-path")]
+        loaded.set("@lmb/json-path", LuaModJSONPath {})?;
+        #[cfg(feature = "msgpack")]
+        loaded.set("@lmb/msgpack", LuaModMsgPack {})?;
+        #[cfg(feature = "cbor")]
+        loaded.set("@lmb/cbor", LuaModCBOR {})?;
+        loaded.set("@lmb/signal", LuaModSignal {})?;
+        #[cfg(feature = "toml")]
+        loaded.set("@lmb/toml", LuaModTOML {})?;
+        #[cfg(feature = "url")]
+        loaded.set("@lmb/url", LuaModURL {})?;
+        #[cfg(feature = "yaml")]
+        loaded.set("@lmb/yaml", LuaModYAML {})?;
         vm.set_named_registry_value(K_LOADED, loaded)?;
 
         Ok(())
     }
 }
 
+impl<R> LuaBinding<R>
+where
+    R: Read,
+{
+    /// Store of the binding. Fail while the store is locked by `update` or `transaction`
+    /// until they finish, since calling it in their functions would deadlock.
+    fn store(&self) -> LuaResult<Option<&Arc<dyn StoreBackend>>> {
+        if let Some(e) = self.locked.lock().as_ref() {
+            return Err(e.clone());
+        }
+        Ok(self.store.as_ref())
+    }
+}
+
 struct LuaStderr {}
 
 impl LuaUserData for LuaStderr {
@@ -124,6 +672,14 @@ impl LuaUserData for LuaStderr {
     }
 }
 
+/// Fail if the key is reserved for records of lmb, see [`RESERVED_KEY_PREFIX`].
+fn check_key(key: &str) -> LuaResult<()> {
+    if key.starts_with(RESERVED_KEY_PREFIX) {
+        return Err(LuaError::runtime(format!("key {key} is reserved by lmb")));
+    }
+    Ok(())
+}
+
 fn lua_lmb_get<'lua, R>(
     vm: &'lua Lua,
     lmb: &LuaBinding<R>,
@@ -132,7 +688,8 @@ fn lua_lmb_get<'lua, R>(
 where
     R: Read,
 {
-    let Some(store) = &lmb.store else {
+    check_key(&key)?;
+    let Some(store) = lmb.store()? else {
         return Ok(LuaNil);
     };
     let value = store.get(key.as_str()).into_lua_err()?;
@@ -150,14 +707,40 @@ fn lua_lmb_put<'lua, R>(
 where
     R: Read,
 {
-    let Some(store) = &lmb.store else {
+    check_key(&key)?;
+    let Some(store) = lmb.store()? else {
         return Ok(LuaNil);
     };
     let serialized = serde_json::to_value(&value).into_lua_err()?;
-    store.put(key, &serialized).into_lua_err()?;
+    store.put(&key, &serialized).into_lua_err()?;
     vm.to_value(&value)
 }
 
+// timestamps are in seconds since the Unix epoch, comparable with `os.time()`
+fn lua_lmb_stat<'lua, R>(
+    vm: &'lua Lua,
+    lmb: &LuaBinding<R>,
+    key: String,
+) -> LuaResult<LuaValue<'lua>>
+where
+    R: Read,
+{
+    check_key(&key)?;
+    let Some(store) = lmb.store()? else {
+        return Ok(LuaNil);
+    };
+    let Some(metadata) = store.stat(&key).into_lua_err()? else {
+        return Ok(LuaNil);
+    };
+    let timestamp = |t: &DateTime<Utc>| t.timestamp_millis() as f64 / 1000.0;
+    let table = vm.create_table()?;
+    table.set("size", metadata.size())?;
+    table.set("type_hint", metadata.type_hint())?;
+    table.set("created_at", timestamp(metadata.created_at()))?;
+    table.set("updated_at", timestamp(metadata.updated_at()))?;
+    Ok(LuaValue::Table(table))
+}
+
 fn lua_lmb_update<'lua, R>(
     vm: &'lua Lua,
     lmb: &LuaBinding<R>,
@@ -166,12 +749,19 @@ fn lua_lmb_update<'lua, R>(
 where
     R: Read,
 {
-    let Some(store) = &lmb.store else {
+    check_key(&key)?;
+    let Some(store) = lmb.store()? else {
         return Ok(LuaNil);
     };
+    // errors of the function leave the value unchanged silently,
+    // except nested calls to the store which are mistakes of the script
+    let mut nested = None;
     let update_fn = |old: &mut Value| -> LuaResult<()> {
         let old_v = vm.to_value(old)?;
-        let new = f.call::<_, LuaValue<'_>>(old_v)?;
+        let new = f.call::<_, LuaValue<'_>>(old_v).map_err(|e| {
+            nested = find_external::<NestedUpdateError>(&e).cloned();
+            e
+        })?;
         *old = vm.from_value(new)?;
         Ok(())
     };
@@ -179,8 +769,94 @@ where
         Some(v) => Some(vm.from_value(v)?),
         None => None,
     };
-    let value = store.update(key, update_fn, default_v).into_lua_err()?;
-    vm.to_value(&value)
+    *lmb.locked.lock() = Some(LuaError::external(NestedUpdateError { name: key.clone() }));
+    let res = store.update(&key, Box::new(update_fn), default_v);
+    *lmb.locked.lock() = None;
+    if let Some(e) = nested {
+        return Err(LuaError::external(e));
+    }
+    vm.to_value(&res.into_lua_err()?)
+}
+
+// errors of the function are raised as they are
+fn transaction_error(e: Error) -> LuaError {
+    match e {
+        Error::Lua(e) => e,
+        e => LuaError::external(e),
+    }
+}
+
+// table passed to the function of `transaction`, which is valid until the function returns
+fn call_in_transaction<'lua>(
+    vm: &'lua Lua,
+    f: &LuaFunction<'lua>,
+    tx: &mut dyn StoreTransaction,
+) -> LuaResult<LuaValue<'lua>> {
+    let tx = RefCell::new(tx);
+    // the transaction is suspended while a nested savepoint is running
+    let borrow = || {
+        tx.try_borrow_mut()
+            .map_err(|_e| LuaError::runtime("transaction is suspended by the nested transaction"))
+    };
+    vm.scope(|scope| {
+        let table = vm.create_table()?;
+        let get = scope.create_function(|vm, (_, key): (LuaValue<'_>, String)| {
+            check_key(&key)?;
+            let value = borrow()?.get(&key).into_lua_err()?;
+            match value {
+                Value::Null => Ok(LuaNil),
+                _ => vm.to_value(&value),
+            }
+        })?;
+        table.set("get", get)?;
+        let put = scope.create_function(
+            |vm, (_, key, value): (LuaValue<'_>, String, LuaValue<'_>)| {
+                check_key(&key)?;
+                let serialized = serde_json::to_value(&value).into_lua_err()?;
+                borrow()?.put(&key, &serialized).into_lua_err()?;
+                vm.to_value(&value)
+            },
+        )?;
+        table.set("put", put)?;
+        let transaction =
+            scope.create_function(|vm, (_, g): (LuaValue<'_>, LuaFunction<'_>)| {
+                let mut returned = LuaNil;
+                borrow()?
+                    .savepoint(Box::new(|sp| {
+                        returned = call_in_transaction(vm, &g, sp)?;
+                        Ok(())
+                    }))
+                    .map_err(transaction_error)?;
+                Ok(returned)
+            })?;
+        table.set("transaction", transaction)?;
+        f.call(table)
+    })
+}
+
+fn lua_lmb_transaction<'lua, R>(
+    vm: &'lua Lua,
+    lmb: &LuaBinding<R>,
+    f: LuaFunction<'lua>,
+) -> LuaResult<LuaValue<'lua>>
+where
+    R: Read,
+{
+    let Some(store) = lmb.store()? else {
+        return Ok(LuaNil);
+    };
+    let mut returned = LuaNil;
+    let transaction_fn = |tx: &mut dyn StoreTransaction| -> LuaResult<()> {
+        returned = call_in_transaction(vm, &f, tx)?;
+        Ok(())
+    };
+    *lmb.locked.lock() = Some(LuaError::runtime(
+        "store is locked by the transaction, use the store passed to the function instead",
+    ));
+    let res = store.transaction(Box::new(transaction_fn));
+    *lmb.locked.lock() = None;
+    res.map_err(transaction_error)?;
+    Ok(returned)
 }
 
 impl<R> LuaUserData for LuaBinding<R>
@@ -189,32 +865,118 @@ where
 {
     fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
         fields.add_field("_VERSION", env!("APP_VERSION"));
+        fields.add_field_method_get("app_state", |vm, _| {
+            vm.named_registry_value::<LuaValue<'lua>>(K_APP_STATE)
+        });
+        fields.add_field_method_get("args", |vm, _| {
+            vm.named_registry_value::<LuaValue<'lua>>(K_ARGS)
+        });
+        fields.add_field_method_get("env", |vm, _| {
+            vm.named_registry_value::<LuaValue<'lua>>(K_ENV)
+        });
         fields.add_field_method_get("request", |vm, this| {
-            let Some(v) = this.state.as_ref().and_then(|m| m.get(&StateKey::Request)) else {
+            let Some(v) = this.state.as_ref().and_then(|s| s.request()) else {
                 return Ok(LuaNil);
             };
-            vm.to_value(&*v)
+            vm.to_value(&v)
         });
         fields.add_field_method_get("response", |vm, this| {
-            let Some(v) = this.state.as_ref().and_then(|m| m.get(&StateKey::Response)) else {
+            let Some(v) = this.state.as_ref().and_then(|s| s.response()) else {
                 return Ok(LuaNil);
             };
-            vm.to_value(&*v)
+            vm.to_value(&v)
         });
         fields.add_field_method_set("response", |vm, this, value: LuaValue<'lua>| {
             if let Some(v) = this.state.as_ref() {
-                v.insert(StateKey::Response, vm.from_value(value)?);
+                v.set_response(vm.from_value(value)?);
+            }
+            Ok(())
+        });
+        fields.add_field_method_get("state", |vm, _| {
+            vm.named_registry_value::<LuaValue<'lua>>(K_STATE)
+        });
+        fields.add_field_method_get("stdin_is_tty", |_, _| Ok(stdin().is_terminal()));
+        fields.add_field_method_get("stdout_is_tty", |_, _| Ok(stdout().is_terminal()));
+        fields.add_field("runtime", LuaRuntime {});
+        fields.add_field("timer", LuaTimer {});
+        fields.add_field_method_get("tmpdir", |vm, _| {
+            let Some(mut scratch) = vm.app_data_mut::<ScratchDir>() else {
+                return Ok(None);
+            };
+            let dir = match scratch.0.take() {
+                Some(dir) => dir,
+                None => tempfile::Builder::new().prefix("lmb-").tempdir()?,
+            };
+            let path = dir.path().to_string_lossy().to_string();
+            scratch.0 = Some(dir);
+            Ok(Some(path))
+        });
+        fields.add_field_method_get("session", |vm, this| {
+            let Some(v) = this.state.as_ref().and_then(|s| s.session()) else {
+                return Ok(LuaNil);
+            };
+            vm.to_value(&v)
+        });
+        fields.add_field_method_set("session", |vm, this, value: LuaValue<'lua>| {
+            if let Some(v) = this.state.as_ref() {
+                v.set_session(vm.from_value(value)?);
             }
             Ok(())
         });
     }
 
     fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("count", |_, this, prefix: Option<String>| {
+            let prefix = prefix.unwrap_or_default();
+            check_key(&prefix)?;
+            let Some(store) = this.store()? else {
+                return Ok(0);
+            };
+            let count = store.count(&prefix).into_lua_err()?;
+            // reserved keys are not counted
+            if RESERVED_KEY_PREFIX.starts_with(prefix.as_str()) {
+                let reserved = store.count(RESERVED_KEY_PREFIX).into_lua_err()?;
+                return Ok(count.saturating_sub(reserved));
+            }
+            Ok(count)
+        });
         methods.add_method("get", lua_lmb_get);
+        methods.add_method("http_error", |_, _, (status, message): (u16, String)| {
+            if !(400..600).contains(&status) {
+                return Err(LuaError::runtime(format!(
+                    "expect status code between 400 and 599, got {status}"
+                )));
+            }
+            Err::<(), _>(LuaError::external(HttpError::new(status, message)))
+        });
+        methods.add_method("invalidate_cache", |_, this, path: Option<String>| {
+            let Some(store) = this.store()? else {
+                return Ok(0);
+            };
+            invalidate_cache(store.as_ref(), path.as_deref()).into_lua_err()
+        });
+        methods.add_method("lock", |_, this, (key, ttl): (String, f64)| {
+            let Some(store) = this.store()? else {
+                return Ok(None);
+            };
+            let ttl = Duration::try_from_secs_f64(ttl).into_lua_err()?;
+            acquire_lock(store.as_ref(), &key, ttl).into_lua_err()
+        });
+        methods.add_method("read_bytes", |vm, this, n: Option<usize>| {
+            lua_lmb_read_bytes(vm, &this.input, n)
+        });
         methods.add_method("read_unicode", |vm, this, f| {
             lua_lmb_read_unicode(vm, &this.input, f)
         });
         methods.add_method("put", lua_lmb_put);
+        methods.add_method("stat", lua_lmb_stat);
+        methods.add_method("transaction", lua_lmb_transaction);
+        methods.add_method("unlock", |_, this, token: String| {
+            let Some(store) = this.store()? else {
+                return Ok(false);
+            };
+            release_lock(store.as_ref(), &token).into_lua_err()
+        });
         methods.add_method("update", lua_lmb_update);
     }
 }
@@ -222,10 +984,143 @@ where
 #[cfg(test)]
 mod tests {
     use serde_json::{json, Value};
-    use std::io::empty;
+    use std::{io::empty, path::Path, sync::Arc};
     use test_case::test_case;
 
-    use crate::EvaluationBuilder;
+    use crate::{
+        EnvPermissions, EvaluationBuilder, InputLimitError, InvocationState, Permissions,
+        RunPermissions,
+    };
+
+    #[test]
+    fn env() {
+        let script = r#"
+        local m = require('@lmb')
+        assert(not pcall(function() m.env.GREETING = 'hi' end), 'env is read-only')
+        return m.env
+        "#;
+        let mut env = EnvPermissions::new(["GREETING"]);
+        env.load_env_file("GREETING=hello\nOTHER=1");
+        let mut permissions = Permissions::default();
+        permissions.set_env(env);
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!({ "GREETING": "hello" }), res.payload());
+    }
+
+    #[test]
+    fn disabled_modules() {
+        let script = r#"
+        local ok, err = pcall(require, '@lmb/crypto')
+        assert(not ok)
+        return { err = tostring(err), json = require('@lmb/json'):encode({ 1 }) }
+        "#;
+        let mut permissions = Permissions::default();
+        permissions.set_disabled_modules(["crypto"]);
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        // modules are registered again with the state
+        let res = e
+            .evaluate_with_state(Arc::new(InvocationState::new()))
+            .unwrap();
+        let payload = res.payload();
+        assert!(payload["err"]
+            .as_str()
+            .unwrap()
+            .contains("module @lmb/crypto is disabled by policy"));
+        assert_eq!(json!("[1]"), payload["json"]);
+    }
+
+    #[test]
+    fn args() {
+        let script = r#"
+        local m = require('@lmb')
+        assert(not pcall(function() m.args.a = 'x' end), 'arguments are read-only')
+        return { m.args[1], m.args.a, #m.args, type(m.stdin_is_tty), type(m.stdout_is_tty) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .args(["x", "y"])
+            .arg("a", "1")
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(["x", "1", 2, "boolean", "boolean"]), res.payload());
+    }
+
+    #[test]
+    fn tmpdir() {
+        let script = r#"
+        local m = require('@lmb')
+        assert(m.tmpdir == m.tmpdir)
+        local res = require('@lmb/shell'):exec('touch', { m.tmpdir .. '/a' })
+        assert(res.ok)
+        return m.tmpdir
+        "#;
+        let mut permissions = Permissions::default();
+        permissions.set_run(RunPermissions::new(["touch"]));
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let first = e.evaluate().unwrap();
+        let first = first.payload().as_str().unwrap();
+        assert!(!Path::new(first).exists());
+
+        let second = e.evaluate().unwrap();
+        assert_ne!(first, second.payload().as_str().unwrap());
+    }
+
+    #[test]
+    fn lock_and_unlock() {
+        let script = r#"
+        local m = require('@lmb')
+        local token = m:lock('leader', 60)
+        local other = m:lock('leader', 60)
+        return { token ~= nil, other == nil, m:unlock(token), m:unlock(token) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .default_store()
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([true, true, true, false]), res.payload());
+    }
+
+    #[test]
+    fn reserved_keys() {
+        let script = r#"
+        local m = require('@lmb')
+        m:lock('leader', 60)
+        m:put('a', 1)
+        local denied = {}
+        for _, f in {
+          function() return m:get('__lmb:lock:leader') end,
+          function() return m:put('__lmb:lock:leader', {}) end,
+          function() return m:update('__lmb:lock:leader', function() return {} end) end,
+          function() return m:stat('__lmb:lock:leader') end,
+          function() return m:count('__lmb:') end,
+          function() return m:transaction(function(tx) return tx:get('__lmb:lock:leader') end) end,
+        } do
+          local ok, err = pcall(f)
+          table.insert(denied, not ok and string.find(tostring(err), 'reserved') ~= nil)
+        end
+        return { denied, m:count(), m:count('__') }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .default_store()
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(
+            &json!([[true, true, true, true, true, true], 1, 0]),
+            res.payload()
+        );
+    }
 
     #[test]
     fn read_binary() {
@@ -238,7 +1133,7 @@ mod tests {
         end
         return t
         "#;
-        let e = EvaluationBuilder::new(script, input).build();
+        let e = EvaluationBuilder::new(script, input).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!([1, 2, 3]), res.payload());
     }
@@ -249,11 +1144,61 @@ mod tests {
     #[test_case("assert(not io.read('*n'))")]
     #[test_case("assert(not io.read(1))")]
     fn read_empty(script: &'static str) {
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let _ = e.evaluate().unwrap();
     }
 
+    #[test_case("a\nb\n\nc", "for l in io.lines() do table.insert(t, l) end", json!(["a", "b", "", "c"]))]
+    #[test_case("a\nb\n", "for l in io.lines(nil, 'L') do table.insert(t, l) end", json!(["a\n", "b\n"]))]
+    #[test_case("1 2\n3", "for n in io.lines(nil, '*n') do table.insert(t, n) end", json!([1, 2, 3]))]
+    #[test_case("1 2\nfoo\n", "local a, b = io.read('n', '*n'); t = { a, b, io.read('l'), io.read('L') }", json!([1, 2, "", "foo\n"]))]
+    #[test_case("1 x 2", "t = { io.read('n', 'n', 'n') }", json!([1]))]
+    #[test_case("x", "t = { io.read(0), io.read(1), io.read(0) }", json!(["", "x"]))]
+    fn read_lines_and_formats(input: &'static str, body: &str, expected: Value) {
+        let script = format!("local t = {{}}; {body}; return t");
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test_case("return io.read('*a')", "1234", true)]
+    #[test_case("return io.read('*a')", "12345", false)]
+    #[test_case("return io.read('*l')", "1234\n", false)]
+    #[test_case("return io.read('*l')", "123\n45678", true)]
+    #[test_case("return io.read(4)", "12345", true)]
+    #[test_case("return io.read(5)", "", false)]
+    #[test_case("return require('@lmb'):read_bytes()", "12345", false)]
+    #[test_case("return require('@lmb'):read_unicode('*a')", "12345", false)]
+    fn read_max_input_bytes(script: &str, input: &'static str, ok: bool) {
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .max_input_bytes(Some(4))
+            .build()
+            .unwrap();
+        match e.evaluate() {
+            Ok(_) => assert!(ok),
+            Err(err) => {
+                assert!(!ok, "{err}");
+                assert_eq!(Some(&InputLimitError { limit: 4 }), err.input_limit());
+            }
+        }
+    }
+
+    #[test]
+    fn read_lines_file() {
+        let script = "return io.lines('/etc/passwd')";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(
+            err.to_string().contains("files are not accessible"),
+            "{err}"
+        );
+    }
+
     #[test_case("1", 1.into())]
+    #[test_case("  -1.5e2 rest", (-150).into())]
+    #[test_case("0x1F", 31.into())]
     #[test_case("1.2", 1.2.into())]
     #[test_case("1.23e-10", 0.000000000123.into())]
     #[test_case("", json!(null))]
@@ -261,7 +1206,9 @@ mod tests {
     #[test_case("1\n", 1.into())]
     fn read_number(input: &'static str, expected: Value) {
         let script = "return io.read('*n')";
-        let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&expected, res.payload());
     }
@@ -269,22 +1216,49 @@ mod tests {
     #[test_case("return io.read()", "foo".into())]
     #[test_case("return io.read('*a')", "foo\nbar".into())]
     #[test_case("return io.read('*l')", "foo".into())]
+    #[test_case("return io.read('l', 'a')", json!("foo"))]
+    #[test_case("return io.read('*L')", "foo\n".into())]
     #[test_case("return io.read(1)", "f".into())]
     #[test_case("return io.read(4)", "foo\n".into())]
     fn read_string(script: &str, expected: Value) {
         let input = "foo\nbar";
-        let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&expected, res.payload());
     }
 
+    #[test]
+    fn read_bytes() {
+        let input: &[u8] = &[0xf0, 0x28, 0x8c, 0xbc, 0xff];
+        let script = r#"
+        local m = require('@lmb')
+        local head = m:read_bytes(2)
+        local rest = m:read_bytes()
+        local t = {}
+        for b in (head .. rest):gmatch('.') do
+          table.insert(t, string.byte(b))
+        end
+        return { #head, t, m:read_bytes(1) == nil, m:read_bytes() == nil }
+        "#;
+        let e = EvaluationBuilder::new(script, input).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(
+            &json!([2, [0xf0, 0x28, 0x8c, 0xbc, 0xff], true, true]),
+            res.payload()
+        );
+    }
+
     #[test_case(1, "你")]
     #[test_case(2, "你好")]
     #[test_case(3, "你好")]
     fn read_unicode_cjk_characters(n: usize, expected: &str) {
         let script = format!("return require('@lmb'):read_unicode({n})");
         let input = "你好";
-        let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(expected), res.payload());
     }
@@ -294,7 +1268,9 @@ mod tests {
         let input = "你好";
         let script = "return require('@lmb'):read_unicode(1)";
 
-        let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
 
         let res = e.evaluate().unwrap();
         assert_eq!(&json!("你"), res.payload());
@@ -311,7 +1287,9 @@ mod tests {
     #[test_case("你好", "*a", "你好")]
     fn read_unicode_format(input: &'static str, f: &str, expected: &str) {
         let script = format!(r#"return require('@lmb'):read_unicode('{f}')"#);
-        let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(expected.to_string()), res.payload());
     }
@@ -321,7 +1299,7 @@ mod tests {
         // ref: https://www.php.net/manual/en/reference.pcre.pattern.modifiers.php#54805
         let input: &[u8] = &[0xf0, 0x28, 0x8c, 0xbc];
         let script = "return require('@lmb'):read_unicode(1)";
-        let e = EvaluationBuilder::new(script, input).build();
+        let e = EvaluationBuilder::new(script, input).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(null), res.payload());
     }
@@ -331,7 +1309,9 @@ mod tests {
         // mix CJK and non-CJK characters
         let input = r#"{"key":"你好"}"#;
         let script = "return require('@lmb'):read_unicode(12)";
-        let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(input), res.payload());
     }
@@ -342,7 +1322,9 @@ mod tests {
     fn read_unicode_non_cjk_characters(n: usize, expected: &str) {
         let input = "ab";
         let script = format!("return require('@lmb'):read_unicode({n})");
-        let e = EvaluationBuilder::new(script, input.as_bytes()).build();
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(expected), res.payload());
     }
@@ -350,12 +1332,12 @@ mod tests {
     #[test]
     fn write() {
         let script = "io.write('l', 'a', 'm'); return nil";
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(null), res.payload());
 
         let script = "io.stderr:write('err', 'or'); return nil";
-        let e = EvaluationBuilder::new(script, empty()).build();
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(null), res.payload());
     }
@@ -0,0 +1,72 @@
+use mlua::prelude::*;
+
+use super::decoded_into_lua;
+
+/// `MessagePack` module
+pub struct LuaModMsgPack {}
+
+impl LuaUserData for LuaModMsgPack {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("decode", |vm, _, value: LuaString<'lua>| {
+            let decoded = rmp_serde::from_slice(value.as_bytes()).into_lua_err()?;
+            decoded_into_lua(vm, decoded)
+        });
+        // strings which are not valid UTF-8 are encoded as binaries
+        methods.add_method("encode", |vm, _, value: LuaValue<'lua>| {
+            let encoded = rmp_serde::to_vec_named(&value).into_lua_err()?;
+            vm.create_string(encoded)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use std::io::empty;
+    use test_case::test_case;
+
+    use crate::EvaluationBuilder;
+
+    #[test_case("return m:encode(true)", json!([0xc3]))]
+    #[test_case("return m:encode(1)", json!([0x01]))]
+    #[test_case("return m:encode('a')", json!([0xa1, 0x61]))]
+    #[test_case("return m:encode('\\xff')", json!([0xc4, 0x01, 0xff]))]
+    #[test_case("return m:encode({ 1, 2 })", json!([0x92, 0x01, 0x02]))]
+    #[test_case("return m:encode({ a = 1 })", json!([0x81, 0xa1, 0x61, 0x01]))]
+    fn msgpack_encode(body: &str, expected: Value) {
+        let script = format!(
+            r#"
+            local m = require('@lmb/msgpack')
+            local s = (function() {body} end)()
+            return {{ string.byte(s, 1, -1) }}
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test]
+    fn msgpack_decode_encode() {
+        let script = r#"
+        local m = require('@lmb/msgpack')
+        local json = require('@lmb/json')
+        local value = { a = { 1, 2.5, 'b', true }, bin = '\xff\x00', empty = {} }
+        local decoded = m:decode(m:encode(value))
+        assert(decoded.bin == '\xff\x00', 'binary is preserved')
+        decoded.bin = nil
+        return json:encode(decoded)
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let actual: Value = serde_json::from_str(res.payload().as_str().unwrap()).unwrap();
+        assert_eq!(json!({ "a": [1, 2.5, "b", true], "empty": {} }), actual);
+    }
+
+    #[test]
+    fn msgpack_decode_invalid() {
+        let script = "return require('@lmb/msgpack'):decode('\\xc1')";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        assert!(e.evaluate().is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use mlua::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::{metrics::DEFAULT_BUCKETS, Labels, MetricKind, Metrics};
+
+/// Prometheus module
+pub struct LuaModPrometheus {
+    metrics: Metrics,
+}
+
+impl LuaModPrometheus {
+    pub fn new(metrics: Metrics) -> Self {
+        Self { metrics }
+    }
+
+    fn define(
+        &self,
+        name: String,
+        kind: MetricKind,
+        options: Option<&LuaTable<'_>>,
+    ) -> LuaResult<LuaMetric> {
+        let help = match options {
+            Some(options) => options.get::<_, Option<String>>("help")?,
+            None => None,
+        };
+        self.metrics
+            .define(&name, kind.clone(), help)
+            .map_err(LuaError::runtime)?;
+        Ok(LuaMetric {
+            kind,
+            metrics: self.metrics.clone(),
+            name,
+        })
+    }
+}
+
+impl LuaUserData for LuaModPrometheus {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "counter",
+            |_, this, (name, options): (String, Option<LuaTable<'lua>>)| {
+                this.define(name, MetricKind::Counter, options.as_ref())
+            },
+        );
+        methods.add_method(
+            "gauge",
+            |_, this, (name, options): (String, Option<LuaTable<'lua>>)| {
+                this.define(name, MetricKind::Gauge, options.as_ref())
+            },
+        );
+        methods.add_method(
+            "histogram",
+            |_, this, (name, options): (String, Option<LuaTable<'lua>>)| {
+                let buckets = match &options {
+                    Some(options) => options.get::<_, Option<Vec<f64>>>("buckets")?,
+                    None => None,
+                };
+                let buckets = buckets.unwrap_or_else(|| DEFAULT_BUCKETS.to_vec());
+                this.define(name, MetricKind::Histogram(buckets), options.as_ref())
+            },
+        );
+        // metrics are rendered in the text exposition format
+        methods.add_method("render", |_, this, ()| Ok(this.metrics.render()));
+    }
+}
+
+/// Metric defined by `@lmb/prometheus`, updated with optional labels.
+pub struct LuaMetric {
+    kind: MetricKind,
+    metrics: Metrics,
+    name: String,
+}
+
+impl LuaMetric {
+    fn expect(&self, kinds: &[&str], method: &str) -> LuaResult<()> {
+        if kinds.contains(&self.kind.name()) {
+            return Ok(());
+        }
+        Err(LuaError::runtime(format!(
+            "{method} is not supported by {} {}",
+            self.kind.name(),
+            self.name
+        )))
+    }
+}
+
+fn labels(labels: Option<BTreeMap<String, String>>) -> Labels {
+    labels.unwrap_or_default().into_iter().collect()
+}
+
+impl LuaUserData for LuaMetric {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "inc",
+            |_, this, (delta, l): (Option<f64>, Option<BTreeMap<String, String>>)| {
+                this.expect(&["counter", "gauge"], "inc")?;
+                let delta = delta.unwrap_or(1.0);
+                if this.kind == MetricKind::Counter && delta < 0.0 {
+                    return Err(LuaError::runtime("counters can only be increased"));
+                }
+                this.metrics
+                    .add(&this.name, labels(l), delta)
+                    .map_err(LuaError::runtime)
+            },
+        );
+        methods.add_method(
+            "dec",
+            |_, this, (delta, l): (Option<f64>, Option<BTreeMap<String, String>>)| {
+                this.expect(&["gauge"], "dec")?;
+                let delta = delta.unwrap_or(1.0);
+                this.metrics
+                    .add(&this.name, labels(l), -delta)
+                    .map_err(LuaError::runtime)
+            },
+        );
+        methods.add_method(
+            "set",
+            |_, this, (value, l): (f64, Option<BTreeMap<String, String>>)| {
+                this.expect(&["gauge"], "set")?;
+                this.metrics
+                    .set(&this.name, labels(l), value)
+                    .map_err(LuaError::runtime)
+            },
+        );
+        methods.add_method(
+            "observe",
+            |_, this, (value, l): (f64, Option<BTreeMap<String, String>>)| {
+                this.expect(&["histogram"], "observe")?;
+                this.metrics
+                    .observe(&this.name, labels(l), value)
+                    .map_err(LuaError::runtime)
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::empty;
+
+    use crate::{EvaluationBuilder, Metrics};
+
+    #[test]
+    fn prometheus() {
+        let script = r#"
+        local metrics = require('@lmb/prometheus')
+        local jobs = metrics:counter('jobs_done', { help = 'Jobs done' })
+        jobs:inc()
+        jobs:inc(2, { queue = 'high' })
+        local depth = metrics:gauge('queue_depth')
+        depth:set(3)
+        depth:dec()
+        metrics:histogram('job_seconds', { buckets = { 1, 10 } }):observe(5)
+        assert(not pcall(jobs.inc, jobs, -1))
+        assert(not pcall(jobs.set, jobs, 1))
+        assert(not pcall(metrics.gauge, metrics, 'jobs_done'))
+        return metrics:render()
+        "#;
+        let metrics = Metrics::new();
+        let e = EvaluationBuilder::new(script, empty())
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = r#"# TYPE job_seconds histogram
+job_seconds_bucket{le="1"} 0
+job_seconds_bucket{le="10"} 1
+job_seconds_bucket{le="+Inf"} 1
+job_seconds_sum 5
+job_seconds_count 1
+# HELP jobs_done Jobs done
+# TYPE jobs_done counter
+jobs_done 1
+jobs_done{queue="high"} 2
+# TYPE queue_depth gauge
+queue_depth 2
+"#;
+        assert_eq!(expected, res.payload().as_str().unwrap());
+        assert_eq!(expected, metrics.render());
+    }
+}
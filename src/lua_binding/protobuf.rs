@@ -0,0 +1,189 @@
+use mlua::prelude::*;
+use prost_reflect::{
+    prost::Message as _, DeserializeOptions, DynamicMessage, MessageDescriptor, SerializeOptions,
+};
+use serde_json::Value;
+
+use crate::ProtoDescriptors;
+
+// fields are named as in .proto files, and 64-bit integers are numbers instead of strings
+const SERIALIZE_OPTIONS: SerializeOptions = SerializeOptions::new()
+    .stringify_64_bit_integers(false)
+    .use_proto_field_name(true);
+
+/// Protobuf module
+pub struct LuaModProtobuf {
+    descriptors: ProtoDescriptors,
+}
+
+impl LuaModProtobuf {
+    pub fn new(descriptors: ProtoDescriptors) -> Self {
+        Self { descriptors }
+    }
+
+    fn message(&self, name: &str) -> LuaResult<MessageDescriptor> {
+        self.descriptors
+            .message(name)
+            .ok_or_else(|| LuaError::runtime(format!("message {name} is not found")))
+    }
+}
+
+impl LuaUserData for LuaModProtobuf {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "decode",
+            |vm, this, (name, data): (String, LuaString<'lua>)| {
+                let message =
+                    DynamicMessage::decode(this.message(&name)?, data.as_bytes()).into_lua_err()?;
+                let value = message
+                    .serialize_with_options(serde_json::value::Serializer, &SERIALIZE_OPTIONS)
+                    .into_lua_err()?;
+                vm.to_value(&value)
+            },
+        );
+        methods.add_method(
+            "encode",
+            |vm, this, (name, value): (String, LuaValue<'lua>)| {
+                let value: Value = vm.from_value(value)?;
+                let message = DynamicMessage::deserialize_with_options(
+                    this.message(&name)?,
+                    value,
+                    &DeserializeOptions::new(),
+                )
+                .into_lua_err()?;
+                vm.create_string(message.encode_to_vec())
+            },
+        );
+        // descriptors of the set are added to a copy, e.g. from the state or the store
+        methods.add_method("load", |_, this, descriptor_set: LuaString<'lua>| {
+            let mut descriptors = this.descriptors.clone();
+            descriptors.add(descriptor_set.as_bytes()).into_lua_err()?;
+            Ok(Self::new(descriptors))
+        });
+        methods.add_method("messages", |_, this, ()| {
+            Ok(this.descriptors.message_names())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_reflect::prost::Message as _;
+    use prost_reflect::prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+    };
+    use serde_json::json;
+    use std::io::empty;
+    use test_case::test_case;
+
+    use crate::{EvaluationBuilder, ProtoDescriptors};
+
+    fn field(name: &str, number: i32, r#type: Type, label: Label) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.into()),
+            number: Some(number),
+            r#type: Some(r#type.into()),
+            label: Some(label.into()),
+            ..Default::default()
+        }
+    }
+
+    // message Event { string name = 1; int64 count = 2; repeated string tags = 3; bytes data = 4; }
+    fn descriptor_set() -> Vec<u8> {
+        let file = FileDescriptorProto {
+            name: Some("events.proto".into()),
+            package: Some("events".into()),
+            syntax: Some("proto3".into()),
+            message_type: vec![DescriptorProto {
+                name: Some("Event".into()),
+                field: vec![
+                    field("name", 1, Type::String, Label::Optional),
+                    field("count", 2, Type::Int64, Label::Optional),
+                    field("tags", 3, Type::String, Label::Repeated),
+                    field("data", 4, Type::Bytes, Label::Optional),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        FileDescriptorSet { file: vec![file] }.encode_to_vec()
+    }
+
+    fn descriptors() -> ProtoDescriptors {
+        let mut descriptors = ProtoDescriptors::new();
+        descriptors.add(&descriptor_set()).unwrap();
+        descriptors
+    }
+
+    #[test]
+    fn protobuf_round_trip() {
+        let script = r#"
+        local pb = require('@lmb/protobuf')
+        local data = pb:encode('events.Event', { name = 'a', count = 2, tags = { 'x', 'y' } })
+        return { { string.byte(data, 1, -1) }, pb:decode('events.Event', data) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .proto_descriptors(descriptors())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = json!([
+            [0x0a, 0x01, b'a', 0x10, 0x02, 0x1a, 0x01, b'x', 0x1a, 0x01, b'y'],
+            { "name": "a", "count": 2, "tags": ["x", "y"] },
+        ]);
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test]
+    fn protobuf_load() {
+        let bytes = descriptor_set()
+            .iter()
+            .map(|b| format!("\\{b}"))
+            .collect::<String>();
+        let script = format!(
+            r#"
+            local pb = require('@lmb/protobuf')
+            local loaded = pb:load('{bytes}')
+            local data = loaded:encode('events.Event', {{ data = 'AQI=' }})
+            return {{ #pb:messages(), loaded:messages(), loaded:decode('events.Event', data) }}
+            "#
+        );
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = json!([0, ["events.Event"], { "data": "AQI=" }]);
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test_case(
+        "return pb:encode('events.Missing', {})",
+        "message events.Missing is not found"
+    )]
+    #[test_case("return pb:encode('events.Event', { count = 'a' })", "invalid digit")]
+    #[test_case("return pb:encode('events.Event', { unknown = 1 })", "unknown")]
+    #[test_case("return pb:decode('events.Event', '\\xff')", "failed to decode")]
+    #[test_case("return pb:load('\\xff')", "invalid")]
+    fn protobuf_error(body: &str, expected: &str) {
+        let script = format!("local pb = require('@lmb/protobuf')\n{body}");
+        let e = EvaluationBuilder::new(script, empty())
+            .proto_descriptors(descriptors())
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+
+    #[test]
+    fn protobuf_empty() {
+        let script = r#"
+        local pb = require('@lmb/protobuf')
+        return { #pb:encode('events.Event', {}), pb:decode('events.Event', '') }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .proto_descriptors(descriptors())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([0, {}]), res.payload());
+    }
+}
@@ -1,8 +1,50 @@
-use std::io::{BufRead as _, Read};
+use std::io::{BufRead as _, BufReader, Read};
 
 use mlua::prelude::*;
 
-use crate::Input;
+use super::MaxInputBytes;
+use crate::{Input, InputLimitError};
+
+/// Max length of a numeral read by `*n`, same as stock Lua.
+const MAX_NUMERAL_LEN: usize = 200;
+
+/// Format of `io.read`, with or without the leading `*` of Lua 5.1.
+/// <https://www.lua.org/manual/5.4/manual.html#pdf-file:read>
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ReadFormat {
+    All,
+    Bytes(usize),
+    Line,
+    LineWithNewline,
+    Number,
+}
+
+impl ReadFormat {
+    pub(crate) fn from_lua(f: &LuaValue<'_>) -> LuaResult<Self> {
+        if let Some(f) = f.as_str() {
+            return match f.strip_prefix('*').unwrap_or(f).chars().next() {
+                Some('a') => Ok(Self::All),
+                Some('l') => Ok(Self::Line),
+                Some('L') => Ok(Self::LineWithNewline),
+                Some('n') => Ok(Self::Number),
+                _ => Err(LuaError::runtime(format!("unexpected format {f}"))),
+            };
+        }
+        if let Some(i) = f.as_usize() {
+            return Ok(Self::Bytes(i));
+        }
+        let f = f.to_string()?;
+        Err(LuaError::runtime(format!("unexpected format {f}")))
+    }
+
+    /// Parse formats, where the default is a line.
+    pub(crate) fn from_lua_multi(formats: LuaMultiValue<'_>) -> LuaResult<Vec<Self>> {
+        if formats.is_empty() {
+            return Ok(vec![Self::Line]);
+        }
+        formats.iter().map(Self::from_lua).collect()
+    }
+}
 
 // This function intentionally uses Lua values instead of JSON values to pass bytes as partial,
 // invalid strings, allowing Lua to handle the bytes.
@@ -10,71 +52,169 @@ use crate::Input;
 pub(crate) fn lua_lmb_read<'lua, R>(
     vm: &'lua Lua,
     input: &Input<R>,
-    f: Option<LuaValue<'lua>>,
-) -> LuaResult<LuaValue<'lua>>
+    formats: &[ReadFormat],
+) -> LuaResult<LuaMultiValue<'lua>>
 where
     R: Read,
 {
-    let Some(f) = f else {
-        // This pattern is the default for read, so io.read() has the same effect as io.read("*line").
-        // https://www.lua.org/pil/21.1.html
-        let mut buf = String::new();
-        let count = input.lock().read_line(&mut buf)?;
-        if count == 0 {
-            return Ok(LuaNil);
+    let mut reader = input.lock();
+    let mut values = vec![];
+    for f in formats {
+        let value = read_format(vm, &mut reader, *f)?;
+        let is_nil = value.is_nil();
+        values.push(value);
+        // like stock Lua, stop at the first format which fails
+        if is_nil {
+            break;
         }
-        // in Lua, *l doesn't include newline character
-        return buf.trim().into_lua(vm);
-    };
+    }
+    Ok(LuaMultiValue::from_vec(values))
+}
 
-    if let Some(f) = f.as_str() {
-        // Assume that the input is a valid UTF-8 string,
-        // so we can easily convert it to a string in Lua.
-        // Otherwise, it will be a list of bytes.
-        let mut buf = String::new();
-        match f {
-            "*a" | "*all" => {
-                let count = input.lock().read_to_string(&mut buf)?;
-                if count == 0 {
-                    return Ok(LuaNil);
-                }
-                return buf.into_lua(vm);
+/// Read at most `n` bytes, or all bytes when `n` is omitted, as a binary-safe Lua string.
+pub(crate) fn lua_lmb_read_bytes<'lua, R>(
+    vm: &'lua Lua,
+    input: &Input<R>,
+    n: Option<usize>,
+) -> LuaResult<LuaValue<'lua>>
+where
+    R: Read,
+{
+    let f = n.map_or(ReadFormat::All, ReadFormat::Bytes);
+    read_format(vm, &mut input.lock(), f)
+}
+
+fn read_format<'lua, R>(
+    vm: &'lua Lua,
+    reader: &mut BufReader<R>,
+    f: ReadFormat,
+) -> LuaResult<LuaValue<'lua>>
+where
+    R: Read,
+{
+    // Assume that the input is a valid UTF-8 string for lines and numbers,
+    // so we can easily convert it to a string in Lua.
+    match f {
+        ReadFormat::All => {
+            let buf = read_bounded(vm, reader, None)?;
+            if buf.is_empty() {
+                return Ok(LuaNil);
             }
-            "*l" | "*line" => {
-                let count = input.lock().read_line(&mut buf)?;
-                if count == 0 {
-                    return Ok(LuaNil);
-                }
-                // in Lua, *l doesn't include newline character
-                return buf.trim().into_lua(vm);
+            Ok(LuaValue::String(vm.create_string(&buf)?))
+        }
+        ReadFormat::Bytes(0) => {
+            // in Lua, reading zero bytes tests for the end of file
+            if reader.fill_buf()?.is_empty() {
+                return Ok(LuaNil);
             }
-            "*n" | "*number" => {
-                let count = input.lock().read_to_string(&mut buf)?;
-                if count == 0 {
-                    return Ok(LuaNil);
-                }
-                // in Lua *n returns nil when number is invalid
-                let num = buf.trim().parse::<f64>().ok();
-                return num.into_lua(vm);
+            "".into_lua(vm)
+        }
+        ReadFormat::Bytes(i) => {
+            check_limit(vm, i)?;
+            let mut buf = vec![];
+            let count = reader.by_ref().take(i as u64).read_to_end(&mut buf)?;
+            if count == 0 {
+                return Ok(LuaNil);
             }
-            _ => {}
+            // Unlike Rust strings, Lua strings may not be valid UTF-8.
+            // We leverage this trait to give Lua the power to handle binary.
+            Ok(LuaValue::String(vm.create_string(&buf)?))
         }
+        ReadFormat::Line | ReadFormat::LineWithNewline => {
+            let mut buf = read_bounded(vm, reader, Some(b'\n'))?;
+            if buf.is_empty() {
+                return Ok(LuaNil);
+            }
+            // in Lua, *l doesn't include newline character
+            if matches!(f, ReadFormat::Line) && buf.last() == Some(&b'\n') {
+                buf.pop();
+            }
+            Ok(LuaValue::String(vm.create_string(&buf)?))
+        }
+        // in Lua *n returns nil when number is invalid
+        ReadFormat::Number => Ok(read_number(reader)?.map_or(LuaNil, LuaValue::Number)),
     }
+}
 
-    if let Some(i) = f.as_usize() {
-        let mut buf = vec![0; i];
-        let count = input.lock().read(&mut buf)?;
-        if count == 0 {
-            return Ok(LuaNil);
-        }
-        buf.truncate(count);
-        // Unlike Rust strings, Lua strings may not be valid UTF-8.
-        // We leverage this trait to give Lua the power to handle binary.
-        return Ok(mlua::Value::String(vm.create_string(&buf)?));
+fn max_input_bytes(vm: &Lua) -> Option<usize> {
+    vm.app_data_ref::<MaxInputBytes>().map(|m| m.0)
+}
+
+fn check_limit(vm: &Lua, size: usize) -> LuaResult<()> {
+    match max_input_bytes(vm) {
+        Some(limit) if size > limit => Err(LuaError::external(InputLimitError { limit })),
+        _ => Ok(()),
     }
+}
 
-    let f = f.to_string()?;
-    Err(LuaError::runtime(format!("unexpected format {f}")))
+/// Read until the delimiter, or to the end without one.
+/// Fail once more bytes than the limit would be read, without buffering the rest of the input.
+fn read_bounded<R>(vm: &Lua, reader: &mut BufReader<R>, delimiter: Option<u8>) -> LuaResult<Vec<u8>>
+where
+    R: Read,
+{
+    // read one more byte to tell whether the limit is exceeded
+    let bound = max_input_bytes(vm)
+        .and_then(|l| u64::try_from(l).ok())
+        .map_or(u64::MAX, |l| l.saturating_add(1));
+    let mut bounded = reader.by_ref().take(bound);
+    let mut buf = vec![];
+    match delimiter {
+        Some(d) => bounded.read_until(d, &mut buf)?,
+        None => bounded.read_to_end(&mut buf)?,
+    };
+    check_limit(vm, buf.len())?;
+    Ok(buf)
+}
+
+fn peek<R>(reader: &mut BufReader<R>) -> std::io::Result<Option<u8>>
+where
+    R: Read,
+{
+    Ok(reader.fill_buf()?.first().copied())
+}
+
+/// Read a decimal or hexadecimal numeral after whitespaces, leaving the rest of the input.
+fn read_number<R>(reader: &mut BufReader<R>) -> std::io::Result<Option<f64>>
+where
+    R: Read,
+{
+    while peek(reader)?.is_some_and(|b| b.is_ascii_whitespace()) {
+        reader.consume(1);
+    }
+    let mut buf = String::new();
+    while buf.len() < MAX_NUMERAL_LEN {
+        let Some(b) = peek(reader)? else {
+            break;
+        };
+        let digits = buf.trim_start_matches(['+', '-']);
+        let is_hex = digits.starts_with("0x") || digits.starts_with("0X");
+        let accepted = match b {
+            b'0'..=b'9' | b'.' => true,
+            b'+' | b'-' => buf.is_empty() || (!is_hex && buf.ends_with(['e', 'E'])),
+            b'x' | b'X' => digits == "0",
+            b'a'..=b'f' | b'A'..=b'F' if is_hex => true,
+            b'e' | b'E' => !digits.is_empty() && !buf.contains(['e', 'E']),
+            _ => false,
+        };
+        if !accepted {
+            break;
+        }
+        buf.push(char::from(b));
+        reader.consume(1);
+    }
+    let (sign, digits) = match buf.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, buf.strip_prefix('+').unwrap_or(&buf)),
+    };
+    let num = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok().map(|n| n as f64),
+        None => digits.parse::<f64>().ok(),
+    };
+    Ok(num.map(|n| sign * n))
 }
 
 pub(crate) fn lua_lmb_read_unicode<'lua, R>(
@@ -88,14 +228,14 @@ where
     if let Some(f) = f.as_str() {
         match f {
             "*a" | "*all" => {
-                let mut s = vec![];
-                input.lock().read_to_end(&mut s).into_lua_err()?;
+                let s = read_bounded(vm, &mut input.lock(), None)?;
                 return Ok(LuaValue::String(vm.create_string(s)?));
             }
             "*l" | "*line" => {
-                let mut s = String::new();
-                input.lock().read_line(&mut s).into_lua_err()?;
-                return Ok(LuaValue::String(vm.create_string(s.trim())?));
+                let s = read_bounded(vm, &mut input.lock(), Some(b'\n'))?;
+                return Ok(LuaValue::String(
+                    vm.create_string(std::str::from_utf8(&s).into_lua_err()?.trim())?,
+                ));
             }
             _ => {}
         }
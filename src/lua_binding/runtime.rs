@@ -0,0 +1,84 @@
+use mlua::prelude::*;
+use std::time::Instant;
+
+use super::{Deadline, MemoryLimit, Started};
+
+/// Runtime of `@lmb`, which reports statistics of the Lua virtual machine,
+/// so scripts can reduce their work or bail out early when near the limits.
+#[derive(Clone)]
+pub struct LuaRuntime {}
+
+impl LuaUserData for LuaRuntime {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // memory in bytes and time in seconds, the limits are absent when not set
+        methods.add_function("stats", |vm, ()| {
+            let now = Instant::now();
+            let used_memory = vm.used_memory();
+            let stats = vm.create_table()?;
+            stats.set("used_memory", used_memory)?;
+            stats.set(
+                "memory_limit",
+                vm.app_data_ref::<MemoryLimit>().map(|m| m.0),
+            )?;
+            // what `collectgarbage('count')` returns in Lua
+            stats.set("memory_kb", used_memory as f64 / 1024.0)?;
+            stats.set("gc_running", vm.gc_is_running())?;
+            let elapsed = vm
+                .app_data_ref::<Started>()
+                .map(|s| now.saturating_duration_since(s.0).as_secs_f64());
+            stats.set("elapsed", elapsed)?;
+            // no deadline is set when the timeout is too long to be represented
+            let remaining = vm
+                .app_data_ref::<Deadline>()
+                .map(|d| d.0.saturating_duration_since(now).as_secs_f64());
+            stats.set("remaining", remaining)?;
+            Ok(stats)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{io::empty, time::Duration};
+
+    use crate::EvaluationBuilder;
+
+    #[test]
+    fn runtime_stats() {
+        let script = r#"
+        local m = require('@lmb')
+        local stats = m.runtime.stats()
+        return {
+          stats.used_memory > 0,
+          stats.memory_limit,
+          stats.memory_kb * 1024 == stats.used_memory,
+          stats.gc_running,
+          stats.elapsed >= 0,
+          stats.remaining > 0 and stats.remaining <= 1,
+        }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .memory_limit(Some(10 * 1024 * 1024))
+            .timeout(Some(Duration::from_secs(1)))
+            .build()
+            .unwrap();
+        assert_eq!(
+            &json!([true, 10 * 1024 * 1024, true, true, true, true]),
+            e.evaluate().unwrap().payload()
+        );
+    }
+
+    #[test]
+    fn runtime_stats_without_limits() {
+        let script = r#"
+        local stats = require('@lmb').runtime.stats()
+        return { stats.memory_limit == nil, stats.remaining == nil }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .timeout(Some(Duration::MAX))
+            .build()
+            .unwrap();
+        assert_eq!(&json!([true, true]), e.evaluate().unwrap().payload());
+    }
+}
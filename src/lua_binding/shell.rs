@@ -0,0 +1,237 @@
+use mlua::prelude::*;
+use std::{
+    io::{Read, Write as _},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, trace_span};
+
+use super::bound_timeout;
+use crate::RunPermissions;
+
+/// Interval to check whether the subprocess exits.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Shell module, which runs subprocesses whose binaries are allowed by [`RunPermissions`].
+#[derive(Clone, Debug)]
+pub struct LuaModShell {
+    permissions: RunPermissions,
+}
+
+impl LuaModShell {
+    /// Create shell module with permissions.
+    pub fn new(permissions: RunPermissions) -> Self {
+        Self { permissions }
+    }
+}
+
+fn read_to_end<T>(pipe: Option<T>) -> thread::JoinHandle<Vec<u8>>
+where
+    T: 'static + Read + Send,
+{
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+fn wait(child: &mut Child, timeout: Option<Duration>) -> LuaResult<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return Ok(Some(child.wait()?));
+    };
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn lua_shell_exec<'lua>(
+    vm: &'lua Lua,
+    this: &LuaModShell,
+    (cmd, args, options): (String, Option<Vec<String>>, Option<LuaTable<'lua>>),
+) -> LuaResult<LuaTable<'lua>> {
+    if !this.permissions.is_allowed(&cmd) {
+        return Err(LuaError::runtime(format!("{cmd} is not allowed to run")));
+    }
+    let args = args.unwrap_or_default();
+    let (stdin, timeout) = match options {
+        Some(options) => (
+            options.get::<_, Option<LuaString<'_>>>("stdin")?,
+            options.get::<_, Option<f64>>("timeout")?,
+        ),
+        None => (None, None),
+    };
+    let timeout = timeout
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .into_lua_err()?;
+    let timeout = bound_timeout(vm, timeout)?;
+
+    let _s = trace_span!("shell_exec", cmd, ?args).entered();
+    let mut child = Command::new(&cmd)
+        .args(&args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let writer = match (stdin, child.stdin.take()) {
+        (Some(input), Some(mut pipe)) => {
+            let input = input.as_bytes().to_vec();
+            Some(thread::spawn(move || {
+                // the subprocess may exit without reading all of the input
+                let _ = pipe.write_all(&input);
+            }))
+        }
+        _ => None,
+    };
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+
+    let status = wait(&mut child, timeout)?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    if status.is_none() {
+        // the subprocess may be killed because of the deadline of the evaluation
+        bound_timeout(vm, None)?;
+    }
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    debug!(?status, "subprocess exited");
+
+    let res = vm.create_table()?;
+    res.set("ok", status.is_some_and(|s| s.success()))?;
+    res.set("status", status.and_then(|s| s.code()))?;
+    res.set("stdout", vm.create_string(&stdout)?)?;
+    res.set("stderr", vm.create_string(&stderr)?)?;
+    res.set("timed_out", status.is_none())?;
+    Ok(res)
+}
+
+impl LuaUserData for LuaModShell {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("exec", lua_shell_exec);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{
+        io::empty,
+        time::{Duration, Instant},
+    };
+
+    use crate::{EvaluationBuilder, Permissions, RunPermissions};
+
+    fn permissions(allowed: &[&str]) -> Permissions {
+        let mut permissions = Permissions::default();
+        permissions.set_run(RunPermissions::new(allowed));
+        permissions
+    }
+
+    #[test]
+    fn shell_exec() {
+        let script = r#"
+        local res = require('@lmb/shell'):exec('echo', { 'hello', 'world' })
+        return { res.ok, res.status, res.stdout, res.stderr, res.timed_out }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions(&["echo"]))
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([true, 0, "hello world\n", "", false]), res.payload());
+    }
+
+    #[test]
+    fn shell_exec_denied() {
+        let script = "return require('@lmb/shell'):exec('echo', { 'hello' })";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("echo is not allowed to run"));
+    }
+
+    #[test]
+    fn shell_exec_failure() {
+        let script = r#"
+        local res = require('@lmb/shell'):exec('sh', { '-c', 'echo oops >&2; exit 3' })
+        return { res.ok, res.status, res.stderr }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions(&["sh"]))
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([false, 3, "oops\n"]), res.payload());
+    }
+
+    #[test]
+    fn shell_exec_stdin() {
+        let script = r#"
+        return require('@lmb/shell'):exec('cat', nil, { stdin = 'piped' }).stdout
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions(&["cat"]))
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!("piped"), res.payload());
+    }
+
+    #[test]
+    fn shell_exec_timeout() {
+        let script = r#"
+        local res = require('@lmb/shell'):exec('sleep', { '10' }, { timeout = 0.1 })
+        return { res.ok, res.timed_out }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions(&["sleep"]))
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([false, true]), res.payload());
+        assert!(res.duration().as_secs() < 10);
+    }
+
+    #[test]
+    fn shell_exec_negative_timeout() {
+        let script = "return require('@lmb/shell'):exec('true', {}, { timeout = -1 })";
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions(&["true"]))
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("negative"), "{err}");
+    }
+
+    #[test]
+    fn shell_exec_evaluation_timeout() {
+        let script = "return require('@lmb/shell'):exec('sleep', { '10' })";
+        let e = EvaluationBuilder::new(script, empty())
+            .permissions(permissions(&["sleep"]))
+            .timeout(Some(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("timeout"));
+        assert!(start.elapsed().as_secs() < 10);
+    }
+}
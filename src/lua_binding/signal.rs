@@ -0,0 +1,93 @@
+use mlua::prelude::*;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::bound_timeout;
+use crate::{listen_signals, signal_received, Signal};
+
+/// Interval to check whether the signal is received.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Signal module, which lets long-running scripts observe SIGINT and SIGTERM and exit cleanly.
+/// Signals are listened to on first use, see [`listen_signals`].
+pub struct LuaModSignal {}
+
+fn parse_signal(name: &str) -> LuaResult<Signal> {
+    let signal = name.parse().into_lua_err()?;
+    listen_signals().into_lua_err()?;
+    Ok(signal)
+}
+
+impl LuaUserData for LuaModSignal {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("received", |_, _, name: String| {
+            Ok(signal_received(parse_signal(&name)?))
+        });
+        // wait until the signal is received, or return false after the timeout in seconds
+        methods.add_method("wait", |vm, _, (name, timeout): (String, Option<f64>)| {
+            let signal = parse_signal(&name)?;
+            let timeout = timeout
+                .map(Duration::try_from_secs_f64)
+                .transpose()
+                .into_lua_err()?;
+            let timeout = bound_timeout(vm, timeout)?;
+            let start = Instant::now();
+            loop {
+                if signal_received(signal) {
+                    return Ok(true);
+                }
+                if timeout.is_some_and(|t| start.elapsed() >= t) {
+                    // the wait may time out because of the deadline of the evaluation
+                    bound_timeout(vm, None)?;
+                    return Ok(false);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{io::empty, time::Duration};
+
+    use crate::EvaluationBuilder;
+
+    #[test]
+    fn signal() {
+        let script = r#"
+        local m = require('@lmb/signal')
+        return { m:received('term'), m:wait('int', 0.05) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        assert_eq!(&json!([false, false]), e.evaluate().unwrap().payload());
+    }
+
+    #[test]
+    fn signal_invalid() {
+        let script = "return require('@lmb/signal'):received('kill')";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        assert!(e.evaluate().is_err());
+    }
+
+    #[test]
+    fn signal_wait_negative_timeout() {
+        let script = "return require('@lmb/signal'):wait('term', -1)";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("negative"), "{err}");
+    }
+
+    #[test]
+    fn signal_wait_timeout() {
+        let script = "return require('@lmb/signal'):wait('term')";
+        let e = EvaluationBuilder::new(script, empty())
+            .timeout(Some(Duration::from_millis(50)))
+            .build()
+            .unwrap();
+        assert!(e.evaluate().is_err());
+    }
+}
@@ -0,0 +1,399 @@
+use mlua::prelude::*;
+use std::{
+    io::{self, BufRead as _, BufReader, Read as _, Write as _},
+    net::{SocketAddr, TcpStream, ToSocketAddrs as _, UdpSocket},
+    time::Duration,
+};
+use tracing::{debug, trace_span};
+
+use super::bound_timeout;
+use crate::NetPermissions;
+
+/// Default timeout of connecting, sending and receiving.
+const DEFAULT_SOCKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Max size of a UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// Max size of a TCP read with a number of bytes, so scripts can't allocate arbitrarily.
+const MAX_READ_SIZE: usize = 65_536;
+
+/// TCP module
+#[derive(Clone, Debug)]
+pub struct LuaModTCP {
+    permissions: NetPermissions,
+}
+
+impl LuaModTCP {
+    /// Create TCP module with permissions.
+    pub fn new(permissions: NetPermissions) -> Self {
+        Self { permissions }
+    }
+}
+
+/// UDP module
+#[derive(Clone, Debug)]
+pub struct LuaModUDP {
+    permissions: NetPermissions,
+}
+
+impl LuaModUDP {
+    /// Create UDP module with permissions.
+    pub fn new(permissions: NetPermissions) -> Self {
+        Self { permissions }
+    }
+}
+
+fn resolve(
+    vm: &Lua,
+    permissions: &NetPermissions,
+    host: &str,
+    port: u16,
+    options: Option<LuaTable<'_>>,
+) -> LuaResult<(Vec<SocketAddr>, Duration)> {
+    if !permissions.is_allowed(host, port) {
+        return Err(LuaError::runtime(format!(
+            "{host}:{port} is not allowed to connect"
+        )));
+    }
+    let timeout = match options {
+        Some(options) => options.get::<_, Option<f64>>("timeout")?,
+        None => None,
+    };
+    let timeout = match timeout {
+        Some(timeout) => Duration::try_from_secs_f64(timeout).into_lua_err()?,
+        None => DEFAULT_SOCKET_TIMEOUT,
+    };
+    let timeout = bound_timeout(vm, Some(timeout))?.unwrap_or(timeout);
+    let addrs = (host, port).to_socket_addrs()?.collect::<Vec<_>>();
+    Ok((addrs, timeout))
+}
+
+fn lua_tcp_connect(
+    vm: &Lua,
+    this: &LuaModTCP,
+    (host, port, options): (String, u16, Option<LuaTable<'_>>),
+) -> LuaResult<LuaTCPStream> {
+    let (addrs, timeout) = resolve(vm, &this.permissions, &host, port, options)?;
+    let _s = trace_span!("tcp_connect", host, port).entered();
+    let mut last_err = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                debug!(%addr, "connected");
+                return Ok(LuaTCPStream {
+                    stream: Some(BufReader::new(stream)),
+                    timeout,
+                });
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(match last_err {
+        Some(e) => map_io_err(vm, e),
+        None => LuaError::runtime(format!("failed to resolve {host}")),
+    })
+}
+
+fn lua_udp_connect(
+    vm: &Lua,
+    this: &LuaModUDP,
+    (host, port, options): (String, u16, Option<LuaTable<'_>>),
+) -> LuaResult<LuaUDPSocket> {
+    let (addrs, timeout) = resolve(vm, &this.permissions, &host, port, options)?;
+    let _s = trace_span!("udp_connect", host, port).entered();
+    let Some(addr) = addrs.first() else {
+        return Err(LuaError::runtime(format!("failed to resolve {host}")));
+    };
+    let local: SocketAddr = if addr.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(addr)?;
+    debug!(%addr, "connected");
+    Ok(LuaUDPSocket {
+        socket: Some(socket),
+        timeout,
+    })
+}
+
+impl LuaUserData for LuaModTCP {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("connect", lua_tcp_connect);
+    }
+}
+
+impl LuaUserData for LuaModUDP {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("connect", lua_udp_connect);
+    }
+}
+
+/// TCP connection
+#[derive(Debug)]
+pub struct LuaTCPStream {
+    stream: Option<BufReader<TcpStream>>,
+    timeout: Duration,
+}
+
+impl LuaTCPStream {
+    fn stream(&mut self, vm: &Lua) -> LuaResult<&mut BufReader<TcpStream>> {
+        let timeout = bound_timeout(vm, Some(self.timeout))?;
+        let stream = self.stream.as_mut().ok_or_else(closed)?;
+        stream.get_ref().set_read_timeout(timeout)?;
+        stream.get_ref().set_write_timeout(timeout)?;
+        Ok(stream)
+    }
+}
+
+fn closed() -> LuaError {
+    LuaError::runtime("socket is closed")
+}
+
+fn map_io_err(vm: &Lua, e: io::Error) -> LuaError {
+    match e.kind() {
+        // the operation may time out because of the deadline of the evaluation
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => match bound_timeout(vm, None) {
+            Ok(_) => LuaError::runtime("socket timed out"),
+            Err(e) => e,
+        },
+        _ => e.into_lua_err(),
+    }
+}
+
+fn lua_tcp_receive<'lua>(
+    vm: &'lua Lua,
+    this: &mut LuaTCPStream,
+    f: Option<LuaValue<'lua>>,
+) -> LuaResult<LuaValue<'lua>> {
+    let stream = this.stream(vm)?;
+    let mut buf = vec![];
+    match f {
+        Some(LuaValue::Integer(n)) => {
+            let n = usize::try_from(n).into_lua_err()?;
+            buf.resize(n.min(MAX_READ_SIZE), 0);
+            let read = stream.read(&mut buf).map_err(|e| map_io_err(vm, e))?;
+            buf.truncate(read);
+        }
+        Some(LuaValue::String(s)) if s.to_str()? == "*a" => {
+            stream
+                .read_to_end(&mut buf)
+                .map_err(|e| map_io_err(vm, e))?;
+        }
+        None | Some(LuaValue::String(_)) => {
+            let read = stream
+                .read_until(b'\n', &mut buf)
+                .map_err(|e| map_io_err(vm, e))?;
+            if read == 0 {
+                return Ok(LuaNil);
+            }
+            let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            return vm.create_string(line).map(LuaValue::String);
+        }
+        Some(_) => return Err(LuaError::runtime("unexpected format")),
+    }
+    if buf.is_empty() {
+        return Ok(LuaNil);
+    }
+    vm.create_string(&buf).map(LuaValue::String)
+}
+
+impl LuaUserData for LuaTCPStream {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("close", |_, this, ()| {
+            this.stream.take();
+            Ok(())
+        });
+        methods.add_method_mut("receive", lua_tcp_receive);
+        methods.add_method_mut("send", |vm, this, data: LuaString<'lua>| {
+            let stream = this.stream(vm)?;
+            stream
+                .get_mut()
+                .write_all(data.as_bytes())
+                .map_err(|e| map_io_err(vm, e))?;
+            Ok(data.as_bytes().len())
+        });
+    }
+}
+
+/// UDP socket connected to a remote address
+#[derive(Debug)]
+pub struct LuaUDPSocket {
+    socket: Option<UdpSocket>,
+    timeout: Duration,
+}
+
+impl LuaUDPSocket {
+    fn socket(&self, vm: &Lua) -> LuaResult<&UdpSocket> {
+        let timeout = bound_timeout(vm, Some(self.timeout))?;
+        let socket = self.socket.as_ref().ok_or_else(closed)?;
+        socket.set_read_timeout(timeout)?;
+        socket.set_write_timeout(timeout)?;
+        Ok(socket)
+    }
+}
+
+impl LuaUserData for LuaUDPSocket {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("close", |_, this, ()| {
+            this.socket.take();
+            Ok(())
+        });
+        methods.add_method("receive", |vm, this, size: Option<usize>| {
+            let socket = this.socket(vm)?;
+            let mut buf = vec![0; size.map_or(MAX_DATAGRAM_SIZE, |s| s.min(MAX_DATAGRAM_SIZE))];
+            let read = socket.recv(&mut buf).map_err(|e| map_io_err(vm, e))?;
+            vm.create_string(&buf[..read])
+        });
+        methods.add_method("send", |vm, this, data: LuaString<'lua>| {
+            let socket = this.socket(vm)?;
+            socket.send(data.as_bytes()).map_err(|e| map_io_err(vm, e))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{
+        io::{BufRead as _, BufReader, Write as _},
+        net::{TcpListener, UdpSocket},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use test_case::test_case;
+
+    use crate::{EvaluationBuilder, NetPermissions, Permissions};
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"220 ready\r\n").unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            stream.write_all(line.to_uppercase().as_bytes()).unwrap();
+        });
+
+        let script = format!(
+            r#"
+            local conn = require('@lmb/tcp'):connect('127.0.0.1', {port}, {{ timeout = 1 }})
+            local banner = conn:receive('*l')
+            conn:send('quit\n')
+            local reply = conn:receive('*a')
+            conn:close()
+            return {{ banner, reply }}
+            "#
+        );
+        let e = EvaluationBuilder::new(script, std::io::empty())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(["220 ready", "QUIT\n"]), res.payload());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn tcp_receive_large_size() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"hello").unwrap();
+        });
+
+        // the read is clamped instead of allocating the size requested by the script
+        let script = format!(
+            r#"
+            local conn = require('@lmb/tcp'):connect('127.0.0.1', {port}, {{ timeout = 1 }})
+            return conn:receive(2147483647)
+            "#
+        );
+        let e = EvaluationBuilder::new(script, std::io::empty())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!("hello"), res.payload());
+        server.join().unwrap();
+    }
+
+    #[test_case("tcp", "-1")]
+    #[test_case("udp", "0 / 0")]
+    fn invalid_timeout(module: &str, timeout: &str) {
+        let script = format!(
+            "return require('@lmb/{module}'):connect('127.0.0.1', 9, {{ timeout = {timeout} }})"
+        );
+        let e = EvaluationBuilder::new(script, std::io::empty())
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("cannot convert float"), "{err}");
+    }
+
+    #[test]
+    fn tcp_not_allowed() {
+        let script = "return require('@lmb/tcp'):connect('127.0.0.1', 25)";
+        let mut permissions = Permissions::default();
+        permissions.set_net(NetPermissions::new(["127.0.0.1:587"]));
+        let e = EvaluationBuilder::new(script, std::io::empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("127.0.0.1:25 is not allowed to connect"));
+    }
+
+    #[test]
+    fn udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut buf = [0; 64];
+            let (read, addr) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..read], addr).unwrap();
+        });
+
+        let script = format!(
+            r#"
+            local socket = require('@lmb/udp'):connect('127.0.0.1', {port}, {{ timeout = 1 }})
+            local sent = socket:send('counter:1|c')
+            local echoed = socket:receive()
+            socket:close()
+            return {{ sent, echoed }}
+            "#
+        );
+        let mut permissions = Permissions::default();
+        permissions.set_net(NetPermissions::new([format!("127.0.0.1:{port}")]));
+        let e = EvaluationBuilder::new(script, std::io::empty())
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([11, "counter:1|c"]), res.payload());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn tcp_evaluation_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let script = format!("return require('@lmb/tcp'):connect('127.0.0.1', {port}):receive()");
+        let e = EvaluationBuilder::new(script, std::io::empty())
+            .timeout(Some(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("timeout"));
+        assert!(start.elapsed().as_secs() < 10);
+        drop(listener);
+    }
+}
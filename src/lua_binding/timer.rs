@@ -0,0 +1,185 @@
+use chrono::Utc;
+use mlua::prelude::*;
+use rand::Rng as _;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::Deadline;
+use crate::is_interrupted;
+
+/// Interval to check whether the signal is received while sleeping.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Timer of `@lmb`, which repeats functions and sleeps within the timeout of the evaluation.
+/// Timers stop early once SIGINT or SIGTERM is received by scripts listening to signals.
+#[derive(Clone)]
+pub struct LuaTimer {}
+
+struct TimerOptions {
+    jitter: Duration,
+    times: Option<usize>,
+}
+
+impl TimerOptions {
+    fn from_lua(options: Option<LuaTable<'_>>) -> LuaResult<Self> {
+        let Some(options) = options else {
+            return Ok(Self {
+                jitter: Duration::ZERO,
+                times: None,
+            });
+        };
+        let jitter = options.get::<_, Option<f64>>("jitter")?.unwrap_or_default();
+        Ok(Self {
+            jitter: millis(jitter)?,
+            times: options.get("times")?,
+        })
+    }
+
+    /// Random delay added to each wake-up, so timers of many processes don't fire at once.
+    fn jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+    }
+}
+
+fn millis(ms: f64) -> LuaResult<Duration> {
+    Duration::try_from_secs_f64(ms / 1000.0).into_lua_err()
+}
+
+/// Add the delay to the instant, or fail if the sum can't be represented by the platform.
+fn after(instant: Instant, delay: Duration) -> LuaResult<Instant> {
+    instant
+        .checked_add(delay)
+        .ok_or_else(|| LuaError::runtime(format!("{delay:?} is too long to wait")))
+}
+
+/// Whether waking up at the instant is still within the deadline of the evaluation.
+fn within_deadline(vm: &Lua, wake: Instant) -> bool {
+    vm.app_data_ref::<Deadline>()
+        .map_or(true, |deadline| wake <= deadline.0)
+}
+
+// return false if interrupted by a signal
+fn sleep_until(wake: Instant) -> bool {
+    loop {
+        let now = Instant::now();
+        if now >= wake {
+            return true;
+        }
+        if is_interrupted() {
+            return false;
+        }
+        thread::sleep((wake - now).min(POLL_INTERVAL));
+    }
+}
+
+impl LuaUserData for LuaTimer {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // call the function every interval in milliseconds until it returns false,
+        // and return the number of calls. Stop before the tick after the deadline
+        methods.add_function(
+            "every",
+            |vm, (interval, f, options): (f64, LuaFunction<'lua>, Option<LuaTable<'lua>>)| {
+                let interval = millis(interval)?;
+                if interval.is_zero() {
+                    return Err(LuaError::runtime("interval must be positive"));
+                }
+                let options = TimerOptions::from_lua(options)?;
+                let mut calls = 0usize;
+                let mut tick = Instant::now();
+                while options.times.map_or(true, |times| calls < times) {
+                    // ticks missed by a slow function are skipped
+                    tick = after(tick, interval)?.max(Instant::now());
+                    let wake = after(tick, options.jitter())?;
+                    if !within_deadline(vm, wake) || !sleep_until(wake) {
+                        break;
+                    }
+                    calls += 1;
+                    if let LuaValue::Boolean(false) = f.call::<_, LuaValue<'_>>(())? {
+                        break;
+                    }
+                }
+                Ok(calls)
+            },
+        );
+        // sleep until the timestamp in seconds since the Unix epoch, comparable with `os.time()`.
+        // Return false without sleeping if it's after the deadline
+        methods.add_function(
+            "sleep_until",
+            |vm, (timestamp, options): (f64, Option<LuaTable<'lua>>)| {
+                let options = TimerOptions::from_lua(options)?;
+                let now = Utc::now().timestamp_micros() as f64 / 1_000_000.0;
+                let remaining =
+                    Duration::try_from_secs_f64((timestamp - now).max(0.0)).into_lua_err()?;
+                let wake = after(after(Instant::now(), remaining)?, options.jitter())?;
+                Ok(within_deadline(vm, wake) && sleep_until(wake))
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{io::empty, time::Duration};
+    use test_case::test_case;
+
+    use crate::EvaluationBuilder;
+
+    #[test]
+    fn timer_every() {
+        let script = r#"
+        local m = require('@lmb')
+        local n = 0
+        local calls = m.timer.every(10, function()
+          n = n + 1
+          return n < 3
+        end, { jitter = 5 })
+        return { calls, m.timer.every(10, function() end, { times = 2 }) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        assert_eq!(&json!([3, 2]), e.evaluate().unwrap().payload());
+    }
+
+    #[test]
+    fn timer_every_deadline() {
+        let script = r#"
+        local m = require('@lmb')
+        return m.timer.every(30, function() end)
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .timeout(Some(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        // the timer stops before the tick after the deadline, so the evaluation succeeds
+        let res = e.evaluate().unwrap();
+        assert!(res.payload().as_u64().is_some_and(|n| n <= 3));
+    }
+
+    #[test]
+    fn timer_sleep_until() {
+        let script = r#"
+        local m = require('@lmb')
+        local now = os.time()
+        return { m.timer.sleep_until(now - 1), m.timer.sleep_until(now + 60) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty())
+            .timeout(Some(Duration::from_secs(1)))
+            .build()
+            .unwrap();
+        assert_eq!(&json!([true, false]), e.evaluate().unwrap().payload());
+    }
+
+    #[test_case("m.timer.every(1e22, function() end)")]
+    #[test_case("m.timer.sleep_until(1e19)")]
+    fn timer_too_long(call: &str) {
+        let script = format!("local m = require('@lmb')\nreturn {call}");
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("too long to wait"), "{err}");
+    }
+}
@@ -0,0 +1,319 @@
+use mlua::prelude::*;
+use serde_json::{Map, Number, Value as JsonValue};
+use toml_edit::{ArrayOfTables, DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+fn table_to_json(table: &dyn TableLike) -> JsonValue {
+    JsonValue::Object(
+        table
+            .iter()
+            .map(|(k, v)| (k.to_string(), item_to_json(v)))
+            .collect::<Map<_, _>>(),
+    )
+}
+
+/// Convert the item into JSON. Date-times are converted into strings.
+fn item_to_json(item: &Item) -> JsonValue {
+    match item {
+        Item::None => JsonValue::Null,
+        Item::Value(v) => value_to_json(v),
+        Item::Table(t) => table_to_json(t),
+        Item::ArrayOfTables(a) => JsonValue::Array(a.iter().map(|t| table_to_json(t)).collect()),
+    }
+}
+
+fn value_to_json(value: &Value) -> JsonValue {
+    match value {
+        Value::String(s) => s.value().clone().into(),
+        Value::Integer(n) => (*n.value()).into(),
+        Value::Float(f) => Number::from_f64(*f.value()).map_or(JsonValue::Null, JsonValue::Number),
+        Value::Boolean(b) => (*b.value()).into(),
+        Value::Datetime(d) => d.value().to_string().into(),
+        Value::Array(a) => JsonValue::Array(a.iter().map(value_to_json).collect()),
+        Value::InlineTable(t) => table_to_json(t),
+    }
+}
+
+/// Convert JSON into a value of TOML. Numbers without fractions are integers, since numbers
+/// of Luau are always floats.
+fn json_to_value(value: &JsonValue) -> LuaResult<Value> {
+    Ok(match value {
+        JsonValue::Null => return Err(LuaError::runtime("TOML has no null")),
+        JsonValue::Bool(b) => (*b).into(),
+        JsonValue::Number(n) => {
+            #[allow(clippy::cast_possible_truncation)]
+            let integer = n.as_i64().or_else(|| {
+                n.as_f64()
+                    .filter(|f| f.fract() == 0.0 && f.abs() < 2f64.powi(53))
+                    .map(|f| f as i64)
+            });
+            match integer {
+                Some(i) => i.into(),
+                None => n.as_f64().unwrap_or_default().into(),
+            }
+        }
+        JsonValue::String(s) => s.as_str().into(),
+        JsonValue::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(json_to_value)
+                .collect::<LuaResult<Vec<_>>>()?
+                .into_iter()
+                .collect(),
+        ),
+        JsonValue::Object(entries) => {
+            let mut table = InlineTable::new();
+            for (k, v) in entries {
+                table.insert(k, json_to_value(v)?);
+            }
+            Value::InlineTable(table)
+        }
+    })
+}
+
+/// Convert inline tables into standard tables, and arrays of them into arrays of tables.
+fn into_item(value: Value) -> Item {
+    match value {
+        Value::InlineTable(t) => {
+            let mut table = Table::new();
+            for (k, v) in t {
+                table.insert(&k, into_item(v));
+            }
+            Item::Table(table)
+        }
+        Value::Array(a) if !a.is_empty() && a.iter().all(Value::is_inline_table) => {
+            let mut tables = ArrayOfTables::new();
+            for v in a {
+                if let Item::Table(t) = into_item(v) {
+                    tables.push(t);
+                }
+            }
+            Item::ArrayOfTables(tables)
+        }
+        v => Item::Value(v),
+    }
+}
+
+/// Keys of the path, either dotted e.g. `package.version` or an array of keys.
+fn parse_path(path: LuaValue<'_>) -> LuaResult<Vec<String>> {
+    let keys: Vec<String> = match path {
+        LuaValue::String(s) => s.to_str()?.split('.').map(String::from).collect(),
+        LuaValue::Table(t) => t.sequence_values::<String>().collect::<LuaResult<_>>()?,
+        _ => {
+            return Err(LuaError::runtime(
+                "path should be a string or an array of keys",
+            ))
+        }
+    };
+    if keys.is_empty() {
+        return Err(LuaError::runtime("path should not be empty"));
+    }
+    Ok(keys)
+}
+
+/// TOML document which preserves comments and formatting when edited.
+pub struct LuaTomlDocument(DocumentMut);
+
+impl LuaTomlDocument {
+    fn get(&self, path: &[String]) -> Option<&Item> {
+        let mut item = self.0.as_item();
+        for key in path {
+            item = item.as_table_like()?.get(key)?;
+        }
+        Some(item)
+    }
+
+    /// Set the value, creating missing tables along the path.
+    fn set(&mut self, path: &[String], value: Value) -> LuaResult<()> {
+        let (last, parents) = path
+            .split_last()
+            .ok_or_else(|| LuaError::runtime("path should not be empty"))?;
+        let mut table: &mut dyn TableLike = self.0.as_table_mut();
+        let mut inline = false;
+        for key in parents {
+            let item = table.entry(key).or_insert_with(|| {
+                if inline {
+                    Item::Value(Value::InlineTable(InlineTable::new()))
+                } else {
+                    let mut t = Table::new();
+                    t.set_implicit(true);
+                    Item::Table(t)
+                }
+            });
+            inline = inline || item.is_inline_table();
+            table = item
+                .as_table_like_mut()
+                .ok_or_else(|| LuaError::runtime(format!("{key} is not a table")))?;
+        }
+        match table.get_mut(last) {
+            // the standard table is kept e.g. [package]
+            Some(Item::Table(_)) if value.is_inline_table() => {
+                table.insert(last, into_item(value));
+            }
+            // comments around the value are kept
+            Some(Item::Value(existing)) => {
+                let decor = existing.decor().clone();
+                *existing = value;
+                *existing.decor_mut() = decor;
+            }
+            _ => {
+                table.insert(last, Item::Value(value));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &[String]) -> Option<Item> {
+        let (last, parents) = path.split_last()?;
+        let mut table = self.0.as_item_mut().as_table_like_mut()?;
+        for key in parents {
+            table = table.get_mut(key)?.as_table_like_mut()?;
+        }
+        table.remove(last)
+    }
+}
+
+impl LuaUserData for LuaTomlDocument {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("get", |vm, this, path: LuaValue<'lua>| {
+            match this.get(&parse_path(path)?) {
+                Some(item) => vm.to_value(&item_to_json(item)),
+                None => Ok(LuaNil),
+            }
+        });
+        methods.add_method_mut(
+            "set",
+            |vm, this, (path, value): (LuaValue<'lua>, LuaValue<'lua>)| {
+                let value = json_to_value(&vm.from_value(value)?)?;
+                this.set(&parse_path(path)?, value)
+            },
+        );
+        methods.add_method_mut("remove", |vm, this, path: LuaValue<'lua>| {
+            match this.remove(&parse_path(path)?) {
+                Some(item) => vm.to_value(&item_to_json(&item)),
+                None => Ok(LuaNil),
+            }
+        });
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(this.0.to_string())
+        });
+    }
+}
+
+/// TOML module
+pub struct LuaModTOML {}
+
+impl LuaUserData for LuaModTOML {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("decode", |vm, _, text: String| {
+            let doc = text.parse::<DocumentMut>().into_lua_err()?;
+            vm.to_value(&item_to_json(doc.as_item()))
+        });
+        methods.add_method("edit", |_, _, text: String| {
+            Ok(LuaTomlDocument(text.parse().into_lua_err()?))
+        });
+        methods.add_method("encode", |vm, _, value: LuaValue<'lua>| {
+            let Item::Table(table) = into_item(json_to_value(&vm.from_value(value)?)?) else {
+                return Err(LuaError::runtime("only tables can be encoded as TOML"));
+            };
+            Ok(DocumentMut::from(table).to_string())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::io::empty;
+    use test_case::test_case;
+
+    use crate::EvaluationBuilder;
+
+    const CARGO_TOML: &str = r#"# the package
+[package]
+name = "lmb" # the name
+version = "0.1.0"
+
+[dependencies]
+serde = "1"
+"#;
+
+    #[test]
+    fn toml_edit_preserves_comments() {
+        let script = r#"
+        local m = require('@lmb/toml')
+        local doc = m:edit(io.read('*a'))
+        doc:set('package.version', '0.2.0')
+        doc:set('package.name', 'lmb2')
+        doc:set({ 'dependencies', 'serde_json' }, { version = '1', features = { 'std' } })
+        doc:set('profile.release.lto', true)
+        doc:remove('dependencies.serde')
+        return tostring(doc)
+        "#;
+        let e = EvaluationBuilder::new(script, CARGO_TOML.as_bytes())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = r#"# the package
+[package]
+name = "lmb2" # the name
+version = "0.2.0"
+
+[dependencies]
+serde_json = { features = ["std"], version = "1" }
+
+[profile.release]
+lto = true
+"#;
+        assert_eq!(&json!(expected), res.payload());
+    }
+
+    #[test_case("package.name", json!("lmb"))]
+    #[test_case("package", json!({ "name": "lmb", "version": "0.1.0" }))]
+    #[test_case("package.missing", json!(null))]
+    #[test_case("package.name.missing", json!(null))]
+    fn toml_edit_get(path: &str, expected: serde_json::Value) {
+        let script = format!("return require('@lmb/toml'):edit(io.read('*a')):get('{path}')");
+        let e = EvaluationBuilder::new(script, CARGO_TOML.as_bytes())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test]
+    fn toml_edit_set_not_table() {
+        let script = r#"
+        local doc = require('@lmb/toml'):edit(io.read('*a'))
+        doc:set('package.name.first', 'l')
+        "#;
+        let e = EvaluationBuilder::new(script, CARGO_TOML.as_bytes())
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        assert!(err.to_string().contains("name is not a table"));
+    }
+
+    #[test]
+    fn toml_decode_encode() {
+        let script = r#"
+        local m = require('@lmb/toml')
+        local text = m:encode({ a = 1, b = { c = 1.5 }, d = { { e = 'f' } } })
+        return { text = text, value = m:decode(text) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = json!({
+            "text": "a = 1\n\n[b]\nc = 1.5\n\n[[d]]\ne = \"f\"\n",
+            "value": { "a": 1, "b": { "c": 1.5 }, "d": [{ "e": "f" }] },
+        });
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test]
+    fn toml_encode_not_table() {
+        let e = EvaluationBuilder::new("return require('@lmb/toml'):encode(1)", empty())
+            .build()
+            .unwrap();
+        assert!(e.evaluate().is_err());
+    }
+}
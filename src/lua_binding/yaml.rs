@@ -0,0 +1,136 @@
+use mlua::prelude::*;
+use serde::Deserialize as _;
+use serde_yaml::Value;
+
+/// Options of decoding. Merge keys i.e. `<<` are applied unless `merge` is false.
+fn merge_keys(options: Option<&LuaTable<'_>>) -> LuaResult<bool> {
+    Ok(match options {
+        Some(options) => options.get::<_, Option<bool>>("merge")?.unwrap_or(true),
+        None => true,
+    })
+}
+
+fn decode_value<'lua>(vm: &'lua Lua, mut value: Value, merge: bool) -> LuaResult<LuaValue<'lua>> {
+    if merge {
+        value.apply_merge().into_lua_err()?;
+    }
+    vm.to_value(&value)
+}
+
+/// YAML module
+pub struct LuaModYAML {}
+
+impl LuaUserData for LuaModYAML {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "decode",
+            |vm, _, (text, options): (String, Option<LuaTable<'lua>>)| {
+                let value = serde_yaml::from_str::<Value>(&text).into_lua_err()?;
+                decode_value(vm, value, merge_keys(options.as_ref())?)
+            },
+        );
+        // Decode every document of a stream separated by `---`.
+        methods.add_method(
+            "decode_all",
+            |vm, _, (text, options): (String, Option<LuaTable<'lua>>)| {
+                let merge = merge_keys(options.as_ref())?;
+                let docs = vm.create_table()?;
+                for document in serde_yaml::Deserializer::from_str(&text) {
+                    let value = Value::deserialize(document).into_lua_err()?;
+                    docs.raw_push(decode_value(vm, value, merge)?)?;
+                }
+                docs.set_metatable(Some(vm.array_metatable()));
+                Ok(docs)
+            },
+        );
+        methods.add_method("encode", |_, _, value: LuaValue<'lua>| {
+            serde_yaml::to_string(&value).into_lua_err()
+        });
+        methods.add_method("encode_all", |_, _, docs: Vec<LuaValue<'lua>>| {
+            let mut encoded = String::new();
+            for doc in docs {
+                encoded.push_str("---\n");
+                encoded.push_str(&serde_yaml::to_string(&doc).into_lua_err()?);
+            }
+            Ok(encoded)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use std::io::empty;
+    use test_case::test_case;
+
+    use crate::EvaluationBuilder;
+
+    #[test]
+    fn yaml_decode_all() {
+        let script = r#"
+        local m = require('@lmb/yaml')
+        return m:decode_all(io.read('*a'))
+        "#;
+        let input = "kind: Service\n---\nkind: Deployment\nspec:\n  replicas: 2\n";
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = json!([
+            { "kind": "Service" },
+            { "kind": "Deployment", "spec": { "replicas": 2 } },
+        ]);
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test]
+    fn yaml_encode_all() {
+        let script = r#"
+        local m = require('@lmb/yaml')
+        local text = m:encode_all({ { a = 1 }, { 'b' } })
+        return { text = text, docs = m:decode_all(text) }
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = json!({ "text": "---\na: 1\n---\n- b\n", "docs": [{ "a": 1 }, ["b"]] });
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test_case("nil", json!({ "a": 1, "b": 2, "c": 3 }))]
+    #[test_case("{ merge = false }", json!({ "c": 3 }))]
+    fn yaml_merge_keys(options: &str, expected: Value) {
+        let script = format!(
+            r#"
+            local m = require('@lmb/yaml')
+            local docs = m:decode_all(io.read('*a'), {options})
+            local v = docs[2]
+            return {{ a = v.a, b = v.b, c = v.c }}
+            "#
+        );
+        let input =
+            "base: &base\n  a: 1\n  b: 2\n---\nbase: &base\n  a: 1\n  b: 2\n<<: *base\nc: 3\n";
+        let e = EvaluationBuilder::new(script, input.as_bytes())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&expected, res.payload());
+    }
+
+    #[test]
+    fn yaml_decode_encode() {
+        let script = r#"
+        local m = require('@lmb/yaml')
+        return m:decode(m:encode({ a = { 1, 'two', true } }))
+        "#;
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!({ "a": [1, "two", true] }), res.payload());
+    }
+
+    #[test]
+    fn yaml_decode_invalid() {
+        let script = "return require('@lmb/yaml'):decode_all('a: 1\\n---\\n[')";
+        let e = EvaluationBuilder::new(script, empty()).build().unwrap();
+        assert!(e.evaluate().is_err());
+    }
+}
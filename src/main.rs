@@ -1,35 +1,145 @@
 use anyhow::bail;
-use clap::{Parser, Subcommand};
+use bench::BenchOptions;
+use chrono::DateTime;
+use clap::{
+    parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
 use clio::*;
 use comfy_table::{presets, Table};
-use cron::Schedule;
+use completion::{write_completions, Shell};
+use config::{apply_config, Config, Manifest};
+use doctor::{diagnose, CheckStatus};
+#[cfg(feature = "http")]
+use lmb::Cassette;
+#[cfg(feature = "protobuf")]
+use lmb::ProtoDescriptors;
 use lmb::{
-    Error, EvaluationBuilder, LuaCheck, PrintOptions, ScheduleOptions, Store, StoreOptions,
-    DEFAULT_TIMEOUT, EXAMPLES, GUIDES,
+    compile_with_source_map, locale_from_env, Catalog, Debugger, DryRun, DryRunFixtures, Error,
+    EvaluationBuilder, EvictionPolicy, GcOptions, InputCopy, Invocation, InvocationState, LuaCheck,
+    Metrics, MissedRunPolicy, NetPermissions, PrintOptions, Profiler, ScheduleOptions,
+    ScheduleTimezone, Scheduler, Snapshots, SourceMap, Store, StoreBackend, StoreOptions,
+    StoreQuota, StoreStats, Trigger, DEFAULT_TIMEOUT, EXAMPLES, GUIDES, MAX_HISTORY_INPUT_SIZE,
+    TYPE_DEFINITIONS,
 };
+use maintenance::DEFAULT_MAINTENANCE_INTERVAL;
+use man::write_man;
 use mlua::prelude::*;
-use serde_json::json;
-use serve::ServeOptions;
+use serde_json::{json, Value};
+use serve::{BindAddress, CacheRule, ServeOptions};
+use session::{SessionOptions, DEFAULT_SESSION_TTL, MAX_SESSION_TTL};
 use std::{
     fmt::Display,
-    io::{self, Read},
+    fs,
+    io::{self, IsTerminal as _, Read, Write as _},
     path::PathBuf,
     process::ExitCode,
     str::FromStr,
+    sync::Arc,
     time::Duration,
 };
 use termimad::MadSkin;
-use tracing::Level;
+use tracing::{warn, Level};
 use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
+mod bench;
+mod completion;
+mod config;
+mod doctor;
+mod maintenance;
+mod man;
 mod serve;
+mod session;
 
 static VERSION: &str = env!("APP_VERSION");
 
+/// Name of the script specified by `-e`, shown in error reports.
+const COMMAND_LINE_NAME: &str = "(command line)";
+
 /// lmb is a Lua function runner.
 #[derive(Parser)]
-#[command(about, author, version=VERSION)]
+#[command(
+    about,
+    author,
+    version = VERSION,
+    arg_required_else_help = true
+)]
 struct Cli {
+    /// Environment variable which scripts are allowed to read via `env` of `@lmb`.
+    /// Specify multiple times to allow more variables. No variable is allowed by default
+    #[arg(long, env = "LMB_ALLOW_ENV", value_delimiter = ',')]
+    allow_env: Vec<String>,
+
+    /// Allow `@lmb/http` to skip verification of server certificates
+    /// when a request sets `insecure_skip_verify` in its `tls` option
+    #[arg(long, env = "LMB_ALLOW_INSECURE_TLS")]
+    allow_insecure_tls: bool,
+
+    /// Host which `@lmb/http`, `@lmb/tcp` and `@lmb/udp` are allowed to connect to, and `@lmb/dns` to resolve,
+    /// e.g. `example.com` or `127.0.0.1:25`. Specify multiple times to allow more hosts.
+    /// Append a path pattern e.g. `api.github.com/repos/*` to only allow HTTP requests to matching paths.
+    /// Any host is allowed by default
+    #[arg(long, env = "LMB_ALLOW_NET", value_delimiter = ',')]
+    allow_net: Vec<String>,
+
+    /// Binary which `@lmb/shell` is allowed to run, by name e.g. `git` resolved through `PATH`,
+    /// or by the exact path.
+    /// Specify multiple times to allow more binaries. No binary is allowed by default
+    #[arg(long, env = "LMB_ALLOW_RUN", value_delimiter = ',')]
+    allow_run: Vec<String>,
+
+    /// Module which is not registered, e.g. `http` for `@lmb/http` or a custom module,
+    /// so `require` fails with "disabled by policy". Specify multiple times to disable more modules
+    #[arg(long, env = "LMB_DISABLE_MODULE", value_delimiter = ',')]
+    disable_module: Vec<String>,
+
+    /// Load allowed environment variables from a `.env` file.
+    /// Variables already set in the environment take precedence
+    #[arg(long, env = "LMB_ENV_FILE")]
+    env_file: Option<PathBuf>,
+
+    /// CA certificates in PEM which `@lmb/http` verifies servers with, instead of the default roots
+    #[arg(long, env = "LMB_HTTP_CA_BUNDLE")]
+    http_ca_bundle: Option<PathBuf>,
+
+    /// Client certificate chain in PEM which `@lmb/http` presents for mutual TLS,
+    /// requires `--http-client-key`
+    #[arg(long, env = "LMB_HTTP_CLIENT_CERT", requires = "http_client_key")]
+    http_client_cert: Option<PathBuf>,
+
+    /// Private key in PEM of the client certificate, requires `--http-client-cert`
+    #[arg(long, env = "LMB_HTTP_CLIENT_KEY", requires = "http_client_cert")]
+    http_client_key: Option<PathBuf>,
+
+    /// Timeout in milliseconds of `@lmb/http` connecting to servers
+    #[arg(long, env = "LMB_HTTP_CONNECT_TIMEOUT")]
+    http_connect_timeout: Option<u64>,
+
+    /// Max bytes of response bodies which `@lmb/http` downloads in each evaluation
+    #[arg(long, env = "LMB_HTTP_MAX_BYTES")]
+    http_max_bytes: Option<u64>,
+
+    /// Max requests which `@lmb/http` sends at the same time, across requests of the server
+    #[arg(long, env = "LMB_HTTP_MAX_CONCURRENCY")]
+    http_max_concurrency: Option<usize>,
+
+    /// Max requests which `@lmb/http` sends in each evaluation
+    #[arg(long, env = "LMB_HTTP_MAX_REQUESTS")]
+    http_max_requests: Option<u64>,
+
+    /// Timeout in milliseconds of each read of `@lmb/http` from servers
+    #[arg(long, env = "LMB_HTTP_READ_TIMEOUT")]
+    http_read_timeout: Option<u64>,
+
+    /// Timeout in milliseconds of each request of `@lmb/http`, including retries and the body
+    #[arg(long, env = "LMB_HTTP_TIMEOUT")]
+    http_timeout: Option<u64>,
+
+    /// Config file in TOML, or YAML by the extension, holding options e.g. `store_path = "db.sqlite3"`.
+    /// Options on the command line take precedence over environment variables and then the file.
+    /// The server reloads permissions, store path and timeout on SIGHUP
+    #[arg(long, env = "LMB_CONFIG")]
+    config: Option<PathBuf>,
+
     /// Checks the syntax of the function before evaluation or serving,
     /// disabled by default for startup performance
     #[arg(long, env = "LMB_CHECK_SYNTAX")]
@@ -39,12 +149,71 @@ struct Cli {
     #[arg(long, short = 'd', env = "DEBUG")]
     debug: bool,
 
+    /// Make evaluations deterministic: seed `math.random`, freeze `os.time` to the start
+    /// of each evaluation, and disable network, process and filesystem access
+    #[arg(long, env = "LMB_DETERMINISTIC")]
+    deterministic: bool,
+
+    /// Run a full garbage-collection cycle after each evaluation
+    #[arg(long, env = "LMB_GC_FULL_COLLECT")]
+    gc_full_collect: bool,
+
+    /// Heap size goal of the garbage collector in percent
+    #[arg(long, env = "LMB_GC_PAUSE")]
+    gc_pause: Option<u32>,
+
+    /// Step multiplier of the garbage collector in percent
+    #[arg(long, env = "LMB_GC_STEP_MULTIPLIER")]
+    gc_step_multiplier: Option<u32>,
+
+    /// Step size of the garbage collector in kilobytes
+    #[arg(long, env = "LMB_GC_STEP_SIZE")]
+    gc_step_size: Option<u32>,
+
+    /// Record each evaluation of `evaluate`, `run-at` and `schedule` in the history of the store,
+    /// with its duration, used memory, and the result or the error. Requires a store path
+    #[arg(long, env = "LMB_HISTORY")]
+    history: bool,
+
+    /// Directory of message catalogs of `@lmb/i18n` in JSON named after their locales,
+    /// e.g. `zh-TW.json`
+    #[arg(long, env = "LMB_I18N_DIR")]
+    i18n_dir: Option<PathBuf>,
+
     /// Enable JSON mode.
     /// When evaluating, output the solution in JSON format.
-    /// When serving, always respond with the solution as a JSON value
+    /// When serving, always respond with the solution as a JSON value.
+    /// When evaluating or checking fails, report errors in JSON format
     #[arg(long)]
     json: bool,
 
+    /// File descriptor set of protobuf messages which `@lmb/protobuf` encodes and decodes,
+    /// e.g. compiled by `protoc --include_imports --descriptor_set_out=events.pb events.proto`.
+    /// Specify multiple times to load multiple sets
+    #[cfg(feature = "protobuf")]
+    #[arg(long, env = "LMB_PROTO_DESCRIPTOR", value_delimiter = ',')]
+    proto_descriptor: Vec<PathBuf>,
+
+    /// Locale of messages e.g. `zh-TW`, for both errors of lmb and `@lmb/i18n`.
+    /// By default, the locale is detected from `LC_ALL`, `LC_MESSAGES` and `LANG`
+    #[arg(long, env = "LMB_LOCALE")]
+    locale: Option<String>,
+
+    /// Max bytes of a single read of the input e.g. `io.read('*a')`, which bounds memory
+    /// of scripts reading huge inputs. When serving, the request is responded with 413
+    #[arg(long, env = "LMB_MAX_INPUT_BYTES")]
+    max_input_bytes: Option<usize>,
+
+    /// Max instructions of each evaluation, which bounds scripts without relying on the clock.
+    /// Instructions are counted on function calls, returns and loop iterations
+    #[arg(long, env = "LMB_MAX_INSTRUCTIONS")]
+    max_instructions: Option<u64>,
+
+    /// Max bytes allocated by the Lua virtual machine of each evaluation.
+    /// When serving, the request is responded with 503
+    #[arg(long, env = "LMB_MEMORY_LIMIT")]
+    memory_limit: Option<usize>,
+
     /// No color <https://no-color.org/>
     #[arg(long, env = "NO_COLOR")]
     no_color: bool,
@@ -55,6 +224,44 @@ struct Cli {
     #[arg(long, env = "LMB_STORE_PATH")]
     store_path: Option<PathBuf>,
 
+    /// Size in bytes above which values of the store are split into chunks in a separate table,
+    /// defaults to 1 MiB. Specify 0 to keep all values in the store table
+    #[arg(long, env = "LMB_STORE_BLOB_THRESHOLD")]
+    store_blob_threshold: Option<usize>,
+
+    /// Size in bytes above which values of the store are compressed with Zstandard.
+    /// By default, or when 0 is specified, values are not compressed
+    #[arg(long, env = "LMB_STORE_COMPRESS_THRESHOLD")]
+    store_compress_threshold: Option<usize>,
+
+    /// Key to encrypt values of the store at rest, in `<id>:<base64 encoded 32 bytes>` format.
+    /// Specify multiple times to rotate keys, the first one encrypts new values
+    /// and the others only decrypt existing values
+    #[arg(long, env = "LMB_STORE_ENCRYPTION_KEY", value_delimiter = ',')]
+    store_encryption_key: Vec<String>,
+
+    /// Max total size of values in the store in bytes
+    #[arg(long, env = "LMB_STORE_MAX_SIZE")]
+    store_max_size: Option<usize>,
+
+    /// Max size of a single value in the store in bytes
+    #[arg(long, env = "LMB_STORE_MAX_VALUE_SIZE")]
+    store_max_value_size: Option<usize>,
+
+    /// Policy when the store is full, "reject" or "lru" to evict the least recently used values
+    #[arg(long, env = "LMB_STORE_EVICTION", default_value = "reject")]
+    store_eviction: EvictionPolicy,
+
+    /// Idle read-only connections of the store kept for concurrent reads,
+    /// e.g. of HTTP handlers. Specify 0 to read with the connection for writes
+    #[arg(long, env = "LMB_STORE_READERS", default_value_t = 4)]
+    store_readers: usize,
+
+    /// Store URL e.g. `redis://127.0.0.1/`, which takes precedence over the store path.
+    /// Requires the "redis" feature, and can't be combined with encryption or quota
+    #[arg(long, env = "LMB_STORE_URL")]
+    store_url: Option<String>,
+
     /// Migrate the store before startup.
     /// If the store path is not specified and the store is in-memory,
     /// it will be automatically migrated
@@ -65,27 +272,166 @@ struct Cli {
     #[arg(long, env = "LMB_THEME")]
     theme: Option<String>,
 
+    /// Show the full stack traceback when evaluation fails
+    #[arg(long, env = "LMB_TRACEBACK")]
+    traceback: bool,
+
+    /// Script to evaluate without a subcommand, so scripts can be executable with
+    /// `#!/usr/bin/env lmb`. Same as `eval --file <SCRIPT> -- [ARGS]...`
+    script: Option<PathBuf>,
+
+    /// Arguments of the script, which are read by `args` of `@lmb`
+    #[arg(
+        requires = "script",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    args: Vec<String>,
+
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Evaluate the script repeatedly and report latency, throughput and peak memory.
+    /// Standard input is read once and rewound before each iteration
+    Bench {
+        /// Number of concurrent workers
+        #[arg(long, default_value_t = 1)]
+        concurrency: usize,
+        /// Script path, or precompiled script path with --precompiled
+        #[arg(long, value_parser)]
+        file: Input,
+        /// Number of iterations
+        #[arg(long, default_value_t = 100)]
+        iterations: usize,
+        /// Load the script as bytecode precompiled by `compile`,
+        /// which must come from a trusted source
+        #[arg(long)]
+        precompiled: bool,
+        /// Timeout of each iteration in seconds
+        #[arg(long, default_value_t = DEFAULT_TIMEOUT.as_secs())]
+        timeout: u64,
+    },
     /// Check syntax of script
     Check {
         /// Script path. Specify "-" or omit to load the script from standard input
         #[arg(long, value_parser, default_value = "-")]
         file: Input,
+        /// Also check usages of modules provided by lmb against their type definitions
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Compile a script into bytecode, which can be evaluated without parsing
+    Compile {
+        /// Script path. Specify "-" or omit to load the script from standard input
+        #[arg(long, value_parser, default_value = "-")]
+        file: Input,
+        /// Output path. Specify "-" or omit to write the bytecode to standard output
+        #[arg(long, value_parser, default_value = "-")]
+        out: Output,
+    },
+    /// Print the completion script of the shell, e.g. `lmb completions bash > /etc/bash_completion.d/lmb`
+    Completions {
+        /// Shell
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Config file commands
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    /// Evaluate a script step by step with breakpoints. Commands are read from standard input,
+    /// type "help" at the prompt for available commands
+    Debug {
+        /// Line to pause the script at. Specify multiple times to set multiple breakpoints.
+        /// Without breakpoints, the script is paused at the first line
+        #[arg(long = "break")]
+        breakpoints: Vec<usize>,
+        /// Script path
+        #[arg(long, value_parser)]
+        file: Input,
+        /// Read input from a file path or an HTTP(S) URL, since standard input is for commands.
+        /// Specify multiple times to concatenate inputs. URLs are checked against `--allow-net`
+        #[arg(long)]
+        input: Vec<String>,
     },
-    /// Evaluate a script file
+    /// Write Luau type definitions of modules provided by lmb for editor tooling
+    Defs {
+        /// Output directory, where `lmb.d.luau` is written
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Diagnose the environment: versions, enabled bindings, write-ahead logging, the store
+    /// and reachability of hosts allowed by `--allow-net`
+    Doctor,
+    /// Evaluate a script file or a precompiled script
     #[command(alias = "eval")]
     Evaluate {
+        /// Output every value returned by the script on its own line instead of the first one
+        #[arg(long)]
+        all_results: bool,
+        /// Format of the result. By default, tables are pretty-printed when standard output
+        /// is a terminal, and the result is in JSON with --json
+        #[arg(long, value_enum)]
+        output: Option<OutputFormat>,
+        /// Named argument of the script e.g. `name=lmb`, which is read by `args.name` of `@lmb`.
+        /// Specify multiple times to set more arguments
+        #[arg(long = "arg", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        named_args: Vec<(String, String)>,
+        /// Arguments of the script after `--`, which are read by `args` of `@lmb`
+        #[arg(last = true)]
+        args: Vec<String>,
+        /// Record HTTP requests, store writes, environment variable reads, subprocesses
+        /// and socket connections instead of executing them, and report them at the end
+        #[arg(long)]
+        dry_run: bool,
+        /// Fixtures in JSON providing environment variables and HTTP responses in dry run
+        #[arg(long, requires = "dry_run")]
+        dry_run_fixtures: Option<PathBuf>,
+        /// Script on the command line e.g. `return io.read('*n') * 2`, so standard input
+        /// remains the input of the script. Specify multiple times to concatenate lines
+        #[arg(short = 'e', long, conflicts_with = "file")]
+        execute: Vec<String>,
         /// Script path. Specify "-" or omit to load the script from standard input
         #[arg(long, value_parser, default_value = "-")]
         file: Input,
+        /// Read input from a file path or an HTTP(S) URL instead of standard input.
+        /// Specify multiple times to concatenate inputs. URLs are checked against `--allow-net`
+        #[arg(long)]
+        input: Vec<String>,
+        /// Load the script as bytecode precompiled by `compile`,
+        /// which must come from a trusted source
+        #[arg(long, conflicts_with = "execute")]
+        precompiled: bool,
+        /// Sample the Lua call stack and write folded stacks to the path,
+        /// which can be rendered into a flamegraph by e.g. `inferno-flamegraph`
+        #[arg(long)]
+        profile: Option<PathBuf>,
+        /// URL of a Prometheus Pushgateway e.g. `http://127.0.0.1:9091`, where metrics defined
+        /// by the script with `@lmb/prometheus` are pushed after the evaluation
+        #[arg(long, env = "LMB_PUSHGATEWAY")]
+        pushgateway: Option<String>,
+        /// Job of metrics pushed to the Pushgateway, whose metrics are replaced on each push
+        #[arg(long, env = "LMB_PUSHGATEWAY_JOB", default_value = "lmb")]
+        pushgateway_job: String,
+        /// Record HTTP interactions of the script to a cassette in YAML
+        #[cfg(feature = "http")]
+        #[arg(long, conflicts_with = "replay")]
+        record: Option<PathBuf>,
+        /// Replay HTTP interactions from a cassette in YAML without network access
+        #[cfg(feature = "http")]
+        #[arg(long)]
+        replay: Option<PathBuf>,
+        /// Directory of snapshots written and compared by `snapshot` of `@lmb/assert`
+        #[arg(long, env = "LMB_SNAPSHOT_DIR")]
+        snapshot_dir: Option<PathBuf>,
         /// Timeout in seconds
         #[arg(long, default_value_t = DEFAULT_TIMEOUT.as_secs())]
         timeout: u64,
+        /// Overwrite mismatched snapshots instead of failing
+        #[arg(long, requires = "snapshot_dir", env = "LMB_UPDATE_SNAPSHOTS")]
+        update_snapshots: bool,
     },
     /// Check out examples and evaluate or serve them
     #[command(subcommand)]
@@ -93,31 +439,105 @@ enum Commands {
     /// Guide commands
     #[command(subcommand)]
     Guide(GuideCommands),
+    /// Invocation history commands
+    #[command(subcommand)]
+    History(HistoryCommands),
     /// List available themes
     ListThemes,
+    /// Print the manual page in roff format, e.g. `lmb man > lmb.1`
+    Man,
+    /// Replay an invocation recorded in the history with its script, input, state and
+    /// environment variables. Side effects are recorded instead of executed like a dry run,
+    /// and reported to standard error
+    Replay {
+        /// Invocation ID, see `history list`
+        id: i64,
+        /// Timeout in seconds
+        #[arg(long, default_value_t = DEFAULT_TIMEOUT.as_secs())]
+        timeout: u64,
+    },
+    /// Run the script once at a time. Pending runs are persisted in the store,
+    /// so running again without --when resumes them after a restart
+    RunAt {
+        /// Time to run in RFC 3339 e.g. "2025-01-01T00:00:00Z"
+        #[arg(long)]
+        when: Option<String>,
+        /// Script path. Specify "-" or omit to load the script from standard input
+        #[arg(long, value_parser, default_value = "-")]
+        file: Input,
+    },
     /// Schedule the script as a cron job
     Schedule {
         /// Exit immediately upon N number of errors. 0 to disable.
         #[arg(long, default_value_t = 1)]
         bail: usize,
-        /// Cron
+        /// Cron expression with seconds, or an interval e.g. "@every 5m"
         #[arg(long)]
         cron: String,
+        /// Timezone of the cron expression e.g. "utc", "local", "+08:00" or "Europe/Berlin"
+        #[arg(long, default_value = "utc")]
+        cron_timezone: String,
         /// Run the script at startup even if the next execution is not due
         #[arg(long)]
         initial_run: bool,
+        /// Run once for runs missed while the process was suspended, or skip them
+        #[arg(long, default_value = "coalesce", value_parser = ["coalesce", "skip"])]
+        missed_runs: String,
         /// Script path. Specify "-" or omit to load the script from standard input
         #[arg(long, value_parser, default_value = "-")]
         file: Input,
     },
     /// Handle HTTP requests with the script
     Serve {
-        /// Bind the server to a specific host and port
+        /// Bind the server to a host and port, or a Unix domain socket e.g. `unix:/run/lmb.sock`.
+        /// Repeat to listen on multiple addresses
         #[arg(long, default_value = "127.0.0.1:3000")]
-        bind: String,
+        bind: Vec<BindAddress>,
+        /// Cache responses of a method in the store for a while, e.g. `GET:60s`.
+        /// The handler can override the duration with `cache_ttl` of the response.
+        /// Requests with `Authorization` or `Cookie` headers are never cached
+        #[arg(long, env = "LMB_CACHE", value_delimiter = ',')]
+        cache: Vec<CacheRule>,
+        /// Do not decode request bodies into `request.body` by content type.
+        /// Raw bodies are always available via `io.read`
+        #[arg(long)]
+        no_decode_body: bool,
+        /// Do not compute entity tags of responses. Conditional requests are still evaluated
+        /// against `ETag` and `Last-Modified` headers set by the handler
+        #[arg(long)]
+        no_etag: bool,
         /// Script path. Specify "-" or omit to load the script from standard input
         #[arg(long, value_parser, default_value = "-")]
         file: Input,
+        /// Interval in seconds of maintenance passes, which delete expired cached responses
+        /// and sessions and checkpoint the store. Specify 0 to disable them
+        #[arg(long, env = "LMB_MAINTENANCE_INTERVAL", default_value_t = DEFAULT_MAINTENANCE_INTERVAL.as_secs())]
+        maintenance_interval: u64,
+        /// Serve scripts of the manifest under their path prefixes instead,
+        /// each with its own permissions, store namespace and timeout
+        #[arg(long, conflicts_with = "file")]
+        manifest: Option<PathBuf>,
+        /// Expose metrics defined by scripts with `@lmb/prometheus` at /metrics
+        /// for Prometheus to scrape, which takes precedence over the script
+        #[arg(long, env = "LMB_METRICS")]
+        metrics: bool,
+        /// Secret to sign session IDs. Sessions are enabled when the secret is specified
+        #[arg(long, env = "LMB_SESSION_SECRET")]
+        session_secret: Option<String>,
+        /// Time-to-live of sessions in seconds, up to 400 days
+        #[arg(
+            long,
+            default_value_t = DEFAULT_SESSION_TTL.as_secs(),
+            value_parser = clap::value_parser!(u64).range(..=MAX_SESSION_TTL.as_secs()),
+        )]
+        session_ttl: u64,
+        /// Permissions of Unix domain sockets in octal e.g. 660
+        #[arg(long, value_parser = parse_socket_mode)]
+        socket_mode: Option<u32>,
+        /// Application state in JSON e.g. `{"greeting":"hello"}`, shared read-only by requests
+        /// as `app_state` of `@lmb`. Each request gets its own copy as `state` to mutate
+        #[arg(long, env = "LMB_STATE", value_parser = parse_json)]
+        state: Option<Value>,
         /// Timeout in seconds
         #[arg(long)]
         timeout: Option<u64>,
@@ -127,6 +547,23 @@ enum Commands {
     Store(StoreCommands),
 }
 
+/// Format of the result of evaluation.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// Strings as they are, and other values in JSON
+    Raw,
+    /// JSON
+    Json,
+    /// Indented and colored JSON
+    Pretty,
+}
+
+#[derive(Parser)]
+enum ConfigCommands {
+    /// Validate the config file specified by --config
+    Validate,
+}
+
 #[derive(Parser)]
 enum ExampleCommands {
     /// Print script of example
@@ -144,9 +581,10 @@ enum ExampleCommands {
     },
     /// Handle HTTP requests with the example
     Serve {
-        /// Bind the server to a specific host and port
+        /// Bind the server to a host and port, or a Unix domain socket e.g. `unix:/run/lmb.sock`.
+        /// Repeat to listen on multiple addresses
         #[arg(long, default_value = "127.0.0.1:3000")]
-        bind: String,
+        bind: Vec<BindAddress>,
         /// Example name
         #[arg(long)]
         name: String,
@@ -171,6 +609,22 @@ enum GuideCommands {
     List,
 }
 
+#[derive(Parser)]
+enum HistoryCommands {
+    /// List the latest invocations
+    List {
+        /// Max number of invocations
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Show an invocation with its result or error
+    Show {
+        /// Invocation ID
+        #[arg(long)]
+        id: i64,
+    },
+}
+
 #[derive(Parser)]
 enum StoreCommands {
     /// Delete a value
@@ -205,16 +659,57 @@ enum StoreCommands {
         #[arg(long, value_parser, default_value = "-")]
         value: Input,
     },
+    /// Compress values above the compression threshold and decompress the others
+    Recompress,
+    /// Encrypt all values with the first encryption key
+    Reencrypt,
+    /// Reclaim free space of the store and truncate its write-ahead log
+    Vacuum,
+    /// Check integrity of the store, and show statistics of size and fragmentation
+    Verify,
     /// Show current version
     Version,
 }
 
-fn do_check_syntax<S>(no_color: bool, name: S, script: S) -> anyhow::Result<()>
+// statistics of the store in rows
+fn store_stats_rows(stats: &StoreStats) -> [(&'static str, String); 5] {
+    [
+        ("size", format!("{} bytes", stats.size())),
+        ("wal size", format!("{} bytes", stats.wal_size())),
+        ("page size", format!("{} bytes", stats.page_size())),
+        ("pages", stats.page_count().to_string()),
+        (
+            "free pages",
+            format!(
+                "{} ({:.2}%)",
+                stats.freelist_count(),
+                stats.fragmentation() * 100.0
+            ),
+        ),
+    ]
+}
+
+fn invocation_json(invocation: &Invocation) -> Value {
+    json!({
+        "id": invocation.id(),
+        "name": invocation.name(),
+        "started_at": invocation.started_at().to_rfc3339(),
+        "duration_ms": invocation.duration().as_secs_f64() * 1000.0,
+        "used_memory": invocation.used_memory(),
+        "result": invocation.result(),
+        "error": invocation.error(),
+    })
+}
+
+fn do_check_syntax<S>(no_color: bool, json: bool, name: S, script: S) -> anyhow::Result<()>
 where
     S: Display,
 {
     let check = LuaCheck::new(name, script);
     if let Err(err) = check.check() {
+        if json {
+            bail!(serde_json::to_string(&check.report(&err))?);
+        }
         let mut buf = Vec::new();
         check.write_error(&mut buf, err, no_color)?;
         bail!(String::from_utf8_lossy(&buf).trim().to_string());
@@ -222,6 +717,26 @@ where
     Ok(())
 }
 
+fn do_check_types<S>(no_color: bool, json: bool, name: S, script: S) -> anyhow::Result<()>
+where
+    S: Display,
+{
+    let check = LuaCheck::new(name, script);
+    let errors = check.check_types()?;
+    if errors.is_empty() {
+        return Ok(());
+    }
+    if json {
+        let reports: Vec<_> = errors.iter().map(|e| check.type_error_report(e)).collect();
+        bail!(serde_json::to_string(&reports)?);
+    }
+    let mut buf = Vec::new();
+    for err in &errors {
+        check.write_type_error(&mut buf, err, no_color)?;
+    }
+    bail!(String::from_utf8_lossy(&buf).trim().to_string());
+}
+
 fn read_script(input: &mut Input) -> anyhow::Result<(String, String)> {
     let name = input.path().to_string_lossy().to_string();
     let mut script = String::new();
@@ -229,8 +744,190 @@ fn read_script(input: &mut Input) -> anyhow::Result<(String, String)> {
     Ok((name, script))
 }
 
-fn prepare_store(options: &StoreOptions) -> anyhow::Result<Store> {
-    let store = if let Some(store_path) = options.store_path() {
+/// Parse the timezone of the schedule. An IANA name e.g. `Europe/Berlin` becomes
+/// the local timezone of the process, so its daylight saving time is observed.
+fn parse_timezone(timezone: &str) -> anyhow::Result<ScheduleTimezone> {
+    if let Ok(timezone) = ScheduleTimezone::from_str(timezone) {
+        return Ok(timezone);
+    }
+    let zoneinfo = PathBuf::from("/usr/share/zoneinfo").join(timezone);
+    if timezone.contains("..") || !zoneinfo.is_file() {
+        bail!("unknown timezone {timezone}");
+    }
+    std::env::set_var("TZ", timezone);
+    Ok(ScheduleTimezone::Local)
+}
+
+/// Keep options specified on the command line or by environment variables,
+/// which take precedence when the config file is reloaded.
+fn explicit_options(matches: &ArgMatches, config: Config) -> Config {
+    let is_explicit = |matches: &ArgMatches, id: &str| {
+        matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+    let serve = matches.subcommand_matches("serve").unwrap_or(matches);
+    let keep = |id: &str, values: Vec<String>| {
+        if is_explicit(matches, id) {
+            values
+        } else {
+            vec![]
+        }
+    };
+    Config {
+        allow_env: keep("allow_env", config.allow_env),
+        allow_insecure_tls: config.allow_insecure_tls && is_explicit(matches, "allow_insecure_tls"),
+        allow_net: keep("allow_net", config.allow_net),
+        allow_run: keep("allow_run", config.allow_run),
+        disable_module: keep("disable_module", config.disable_module),
+        env_file: config.env_file.filter(|_| is_explicit(matches, "env_file")),
+        http_ca_bundle: config
+            .http_ca_bundle
+            .filter(|_| is_explicit(matches, "http_ca_bundle")),
+        http_client_cert: config
+            .http_client_cert
+            .filter(|_| is_explicit(matches, "http_client_cert")),
+        http_client_key: config
+            .http_client_key
+            .filter(|_| is_explicit(matches, "http_client_key")),
+        http_connect_timeout: config
+            .http_connect_timeout
+            .filter(|_| is_explicit(matches, "http_connect_timeout")),
+        http_max_bytes: config
+            .http_max_bytes
+            .filter(|_| is_explicit(matches, "http_max_bytes")),
+        http_max_concurrency: config
+            .http_max_concurrency
+            .filter(|_| is_explicit(matches, "http_max_concurrency")),
+        http_max_requests: config
+            .http_max_requests
+            .filter(|_| is_explicit(matches, "http_max_requests")),
+        http_read_timeout: config
+            .http_read_timeout
+            .filter(|_| is_explicit(matches, "http_read_timeout")),
+        http_timeout: config
+            .http_timeout
+            .filter(|_| is_explicit(matches, "http_timeout")),
+        store_path: config
+            .store_path
+            .filter(|_| is_explicit(matches, "store_path")),
+        timeout: config.timeout.filter(|_| is_explicit(serve, "timeout")),
+    }
+}
+
+fn parse_key_value(s: &str) -> anyhow::Result<(String, String)> {
+    let Some((key, value)) = s.split_once('=') else {
+        bail!("{s} should be in key=value format");
+    };
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn parse_json(s: &str) -> anyhow::Result<Value> {
+    Ok(serde_json::from_str(s)?)
+}
+
+fn parse_socket_mode(mode: &str) -> anyhow::Result<u32> {
+    let mode = u32::from_str_radix(mode, 8)?;
+    if mode > 0o777 {
+        bail!("socket mode {mode:o} is out of range");
+    }
+    Ok(mode)
+}
+
+type InputReader = Box<dyn Read + Send>;
+
+#[cfg(feature = "http")]
+fn open_url(url: &str, permissions: &NetPermissions) -> anyhow::Result<InputReader> {
+    let parsed = url::Url::parse(url)?;
+    let host = parsed.host_str().unwrap_or_default();
+    let port = parsed.port_or_known_default().unwrap_or_default();
+    if !permissions.is_url_allowed(&parsed) {
+        let path = parsed.path().trim_start_matches('/');
+        if path.is_empty() {
+            bail!("{host}:{port} is not allowed to connect");
+        }
+        bail!("{host}:{port}/{path} is not allowed to connect");
+    }
+    // redirects may lead to hosts which are not allowed
+    let agent = if permissions.is_restricted() {
+        ureq::AgentBuilder::new().redirects(0).build()
+    } else {
+        ureq::agent()
+    };
+    Ok(Box::new(agent.get(url).call()?.into_reader()))
+}
+
+#[cfg(not(feature = "http"))]
+fn open_url(_url: &str, _permissions: &NetPermissions) -> anyhow::Result<InputReader> {
+    bail!("input URL is not supported, please rebuild with the http feature");
+}
+
+/// Push metrics to the Pushgateway, replacing metrics of the job.
+#[cfg(feature = "http")]
+fn push_metrics(url: &str, job: &str, metrics: &Metrics) -> anyhow::Result<()> {
+    let url = format!("{}/metrics/job/{job}", url.trim_end_matches('/'));
+    ureq::put(&url)
+        .set("content-type", "text/plain; version=0.0.4")
+        .send_string(&metrics.render())?;
+    Ok(())
+}
+
+#[cfg(not(feature = "http"))]
+fn push_metrics(_url: &str, _job: &str, _metrics: &Metrics) -> anyhow::Result<()> {
+    bail!("Pushgateway is not supported, please rebuild with the http feature");
+}
+
+/// Copy bytes read from the input when they are recorded in the history.
+fn tee_input(reader: InputReader, enabled: bool) -> (InputReader, Option<InputCopy>) {
+    if !enabled {
+        return (reader, None);
+    }
+    let copy = InputCopy::new();
+    (Box::new(copy.tee(reader)), Some(copy))
+}
+
+/// Open inputs of the function and concatenate them, or standard input if there is none.
+fn open_inputs(inputs: &[String], permissions: &NetPermissions) -> anyhow::Result<InputReader> {
+    if inputs.is_empty() {
+        return Ok(Box::new(io::stdin()));
+    }
+    let mut reader: InputReader = Box::new(io::empty());
+    for input in inputs {
+        let next: InputReader = if input.starts_with("http://") || input.starts_with("https://") {
+            open_url(input, permissions)?
+        } else {
+            Box::new(fs::File::open(input)?)
+        };
+        reader = Box::new(reader.chain(next));
+    }
+    Ok(reader)
+}
+
+/// Open the store with the URL, and fail with options only supported by [`Store`]
+/// instead of ignoring them.
+#[cfg(feature = "redis")]
+fn open_store_url(url: &str, options: &StoreOptions) -> anyhow::Result<Arc<dyn StoreBackend>> {
+    if !options.encryption_keys().is_empty() {
+        bail!("encryption is not supported by the store URL");
+    }
+    let quota = options.quota();
+    if quota.max_size().is_some() || quota.max_value_size().is_some() {
+        bail!("quota is not supported by the store URL");
+    }
+    Ok(Arc::new(lmb::RedisStore::new(url)?))
+}
+
+#[cfg(not(feature = "redis"))]
+fn open_store_url(_url: &str, _options: &StoreOptions) -> anyhow::Result<Arc<dyn StoreBackend>> {
+    bail!("store URL is not supported, please rebuild with the redis feature");
+}
+
+fn prepare_store(options: &StoreOptions) -> anyhow::Result<Arc<dyn StoreBackend>> {
+    if let Some(url) = options.store_url() {
+        return open_store_url(url, options);
+    }
+    let mut store = if let Some(store_path) = options.store_path() {
         let store = Store::new(store_path)?;
         if options.run_migrations() {
             store.migrate(None)?;
@@ -239,11 +936,49 @@ fn prepare_store(options: &StoreOptions) -> anyhow::Result<Store> {
     } else {
         Store::default()
     };
+    options.apply(&mut store)?;
+    Ok(Arc::new(store))
+}
+
+/// Open the store recording the history, which is persisted only with a store path.
+fn open_history(options: &StoreOptions) -> anyhow::Result<Store> {
+    if options.store_url().is_some() {
+        bail!("history is not supported by the store URL");
+    }
+    let Some(store_path) = options.store_path() else {
+        bail!("store_path is required");
+    };
+    let mut store = Store::new(store_path)?;
+    store.set_encryption(options.encryption()?);
+    if options.run_migrations() {
+        store.migrate(None)?;
+    }
     Ok(store)
 }
 
+/// Open the store recording invocations. The input and environment variables are
+/// recorded only when the store is encrypted, since they may contain secrets.
+fn open_recording_history(options: &StoreOptions) -> anyhow::Result<Store> {
+    if options.encryption_keys().is_empty() {
+        warn!("input and environment variables are not recorded in the history without store encryption keys");
+    }
+    open_history(options)
+}
+
 async fn try_main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let mut command = Cli::command();
+    // parse leniently to find the config file before the options in it become defaults
+    let config_path = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches()
+        .ok()
+        .and_then(|m| m.get_one::<PathBuf>("config").cloned());
+    if let Some(path) = &config_path {
+        command = apply_config(command, path)?;
+    }
+    let matches = command.get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
 
     let default_directive = if cli.debug {
         Level::DEBUG.into()
@@ -270,27 +1005,202 @@ async fn try_main() -> anyhow::Result<()> {
         .compact()
         .init();
 
+    let locale = cli.locale.clone().unwrap_or_else(locale_from_env);
+    let messages = Catalog::builtin(&locale);
+    let mut catalog = Catalog::new(&locale);
+    if let Some(dir) = &cli.i18n_dir {
+        catalog.load_dir(dir)?;
+    }
+    #[cfg(feature = "protobuf")]
+    let proto_descriptors = {
+        let mut descriptors = ProtoDescriptors::new();
+        for path in &cli.proto_descriptor {
+            descriptors.load(path)?;
+        }
+        descriptors
+    };
+
     let mut print_options = PrintOptions::default();
     print_options.set_no_color(cli.no_color);
     print_options.set_theme(cli.theme);
 
-    let store_options = StoreOptions::new(cli.store_path, cli.run_migrations);
-    match cli.command {
-        Commands::Check { mut file } => {
+    let mut gc = GcOptions::default();
+    gc.set_full_collect(cli.gc_full_collect)
+        .set_pause(cli.gc_pause)
+        .set_step_multiplier(cli.gc_step_multiplier)
+        .set_step_size(cli.gc_step_size);
+
+    let config = Config {
+        allow_env: cli.allow_env,
+        allow_insecure_tls: cli.allow_insecure_tls,
+        allow_net: cli.allow_net,
+        allow_run: cli.allow_run,
+        disable_module: cli.disable_module,
+        env_file: cli.env_file,
+        http_ca_bundle: cli.http_ca_bundle,
+        http_client_cert: cli.http_client_cert,
+        http_client_key: cli.http_client_key,
+        http_connect_timeout: cli.http_connect_timeout,
+        http_max_bytes: cli.http_max_bytes,
+        http_max_concurrency: cli.http_max_concurrency,
+        http_max_requests: cli.http_max_requests,
+        http_read_timeout: cli.http_read_timeout,
+        http_timeout: cli.http_timeout,
+        store_path: cli.store_path,
+        timeout: None,
+    };
+    let permissions = config.permissions()?;
+
+    let mut store_options = StoreOptions::new(config.store_path.clone(), cli.run_migrations);
+    let mut quota = StoreQuota::default();
+    quota
+        .set_eviction(cli.store_eviction)
+        .set_max_size(cli.store_max_size)
+        .set_max_value_size(cli.store_max_value_size);
+    store_options.set_blob_threshold(cli.store_blob_threshold);
+    store_options.set_compress_threshold(cli.store_compress_threshold);
+    store_options.set_encryption_keys(cli.store_encryption_key);
+    store_options.set_quota(quota);
+    store_options.set_readers(cli.store_readers);
+    store_options.set_store_url(cli.store_url);
+    let command = match (cli.command, cli.script) {
+        (Some(command), _) => command,
+        (None, Some(script)) => Commands::Evaluate {
+            all_results: false,
+            named_args: vec![],
+            output: None,
+            args: cli.args,
+            dry_run: false,
+            dry_run_fixtures: None,
+            execute: vec![],
+            file: Input::new(&script)?,
+            input: vec![],
+            precompiled: false,
+            profile: None,
+            pushgateway: None,
+            pushgateway_job: "lmb".to_string(),
+            #[cfg(feature = "http")]
+            record: None,
+            #[cfg(feature = "http")]
+            replay: None,
+            snapshot_dir: None,
+            timeout: DEFAULT_TIMEOUT.as_secs(),
+            update_snapshots: false,
+        },
+        (None, None) => bail!(messages.translate("script.required", &[])),
+    };
+    match command {
+        Commands::Bench {
+            concurrency,
+            mut file,
+            iterations,
+            precompiled,
+            timeout,
+        } => {
+            let name = file.path().to_string_lossy().to_string();
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes)?;
+            let script = if precompiled {
+                // fail early if the version header doesn't match
+                EvaluationBuilder::from_precompiled(&bytes, io::empty())?;
+                String::new()
+            } else {
+                let script = String::from_utf8(bytes.clone())?;
+                do_check_syntax(cli.no_color, cli.json, &name, &script)?;
+                script
+            };
+            let mut input = vec![];
+            io::stdin().read_to_end(&mut input)?;
+            let store = prepare_store(&store_options)?;
+            let options = BenchOptions::new(iterations, concurrency, input);
+            let report = bench::bench(&options, |input| {
+                let mut builder = if precompiled {
+                    EvaluationBuilder::from_precompiled(&bytes, input)
+                        .expect("precompiled script is verified")
+                } else {
+                    EvaluationBuilder::new(&script, input)
+                };
+                builder
+                    .gc(gc.clone())
+                    .max_instructions(cli.max_instructions)
+                    .memory_limit(cli.memory_limit)
+                    .deterministic(cli.deterministic)
+                    .name(&name)
+                    .permissions(permissions.clone())
+                    .store(store.clone())
+                    .timeout(Some(Duration::from_secs(timeout)))
+                    .build()
+            })?;
+            println!("{report}");
+            Ok(())
+        }
+        Commands::Check { mut file, strict } => {
             let (name, script) = read_script(&mut file)?;
-            do_check_syntax(cli.no_color, &name, &script)
+            do_check_syntax(cli.no_color, cli.json, &name, &script)?;
+            if strict {
+                do_check_types(cli.no_color, cli.json, &name, &script)?;
+            }
+            Ok(())
         }
-        Commands::Evaluate { mut file, timeout } => {
+        Commands::Compile { mut file, mut out } => {
             let (name, script) = read_script(&mut file)?;
-            if cli.check_syntax {
-                do_check_syntax(cli.no_color, &name, &script)?;
+            do_check_syntax(cli.no_color, cli.json, &name, &script)?;
+            // errors of the precompiled script are located in the original file
+            let mut source_map = SourceMap::new();
+            source_map.add(&name, 1, script.lines().count(), 1);
+            out.write_all(&compile_with_source_map(&script, &source_map)?)?;
+            out.finish()?;
+            Ok(())
+        }
+        Commands::Completions { shell } => {
+            write_completions(&mut Cli::command(), shell, &mut io::stdout())?;
+            Ok(())
+        }
+        Commands::Config(ConfigCommands::Validate) => {
+            let Some(path) = cli.config else {
+                bail!(messages.translate("config.not_specified", &[]));
+            };
+            // options have been validated when applied as default values
+            Config::load(&path)?;
+            let path = path.display().to_string();
+            println!("{}", messages.translate("config.valid", &[("path", &path)]));
+            Ok(())
+        }
+        Commands::Debug {
+            breakpoints,
+            mut file,
+            input,
+        } => {
+            if file.is_std() {
+                bail!(messages.translate("debug.stdin", &[]));
+            }
+            let (name, script) = read_script(&mut file)?;
+            do_check_syntax(cli.no_color, cli.json, &name, &script)?;
+            let reader = if input.is_empty() {
+                Box::new(io::empty())
+            } else {
+                open_inputs(&input, permissions.net())?
+            };
+            let debugger = Debugger::new(io::BufReader::new(io::stdin()), io::stderr());
+            for line in breakpoints {
+                debugger.add_breakpoint(line);
             }
             let store = prepare_store(&store_options)?;
-            let e = EvaluationBuilder::new(&script, io::stdin())
+            let mut builder = EvaluationBuilder::new(&script, reader);
+            #[cfg(feature = "protobuf")]
+            builder.proto_descriptors(proto_descriptors);
+            let e = builder
+                .catalog(catalog)
+                .debugger(debugger)
+                .gc(gc)
+                .max_input_bytes(cli.max_input_bytes)
+                .max_instructions(cli.max_instructions)
+                .memory_limit(cli.memory_limit)
+                .deterministic(cli.deterministic)
                 .name(&name)
+                .permissions(permissions)
                 .store(store)
-                .timeout(Some(Duration::from_secs(timeout)))
-                .build();
+                .build()?;
             let mut buf = String::new();
             match e.evaluate() {
                 Ok(s) => {
@@ -305,13 +1215,205 @@ async fn try_main() -> anyhow::Result<()> {
                 }
             }
         }
+        Commands::Defs { out } => {
+            fs::create_dir_all(&out)?;
+            let path = out.join("lmb.d.luau");
+            fs::write(&path, TYPE_DEFINITIONS.as_str())?;
+            println!("{}", path.display());
+            Ok(())
+        }
+        Commands::Doctor => {
+            let checks = diagnose(VERSION, &store_options, permissions.net());
+            if cli.json {
+                println!("{}", serde_json::to_string(&checks)?);
+            } else {
+                let mut table = Table::new();
+                table.load_preset(presets::NOTHING);
+                table.set_header(
+                    ["doctor.check", "doctor.status", "doctor.detail"]
+                        .map(|key| messages.translate(key, &[])),
+                );
+                for c in checks.iter() {
+                    table.add_row([c.name(), &c.status().to_string(), c.detail()]);
+                }
+                println!("{table}");
+            }
+            let failed = checks
+                .iter()
+                .filter(|c| c.status() == CheckStatus::Fail)
+                .count();
+            if failed > 0 {
+                let (failed, total) = (failed.to_string(), checks.len().to_string());
+                bail!(
+                    messages.translate("doctor.failed", &[("failed", &failed), ("total", &total)])
+                );
+            }
+            Ok(())
+        }
+        Commands::Evaluate {
+            all_results,
+            named_args,
+            output,
+            args,
+            dry_run,
+            dry_run_fixtures,
+            execute,
+            mut file,
+            input,
+            precompiled,
+            profile,
+            pushgateway,
+            pushgateway_job,
+            #[cfg(feature = "http")]
+            record,
+            #[cfg(feature = "http")]
+            replay,
+            snapshot_dir,
+            timeout,
+            update_snapshots,
+        } => {
+            let (name, bytes) = if execute.is_empty() {
+                let mut bytes = vec![];
+                file.read_to_end(&mut bytes)?;
+                (file.path().to_string_lossy().to_string(), bytes)
+            } else {
+                (
+                    COMMAND_LINE_NAME.to_string(),
+                    execute.join("\n").into_bytes(),
+                )
+            };
+            let reader = open_inputs(&input, permissions.net())?;
+            let history = cli
+                .history
+                .then(|| open_recording_history(&store_options))
+                .transpose()?;
+            let (reader, input_copy) = tee_input(reader, history.is_some());
+            let mut builder = if precompiled {
+                EvaluationBuilder::from_precompiled(&bytes, reader)?
+            } else {
+                let script = String::from_utf8(bytes)?;
+                if cli.check_syntax {
+                    do_check_syntax(cli.no_color, cli.json, &name, &script)?;
+                }
+                EvaluationBuilder::new(&script, reader)
+            };
+            let store = prepare_store(&store_options)?;
+            #[cfg(feature = "http")]
+            if let Some(path) = record {
+                builder.cassette(Cassette::record(path));
+            } else if let Some(path) = replay {
+                builder.cassette(Cassette::replay(path)?);
+            }
+            let dry_run = if dry_run {
+                let fixtures = match dry_run_fixtures {
+                    Some(path) => DryRunFixtures::load(&path)?,
+                    None => DryRunFixtures::default(),
+                };
+                let dry_run = DryRun::new(fixtures);
+                builder.dry_run(dry_run.clone());
+                Some(dry_run)
+            } else {
+                None
+            };
+            let profiler = profile.as_ref().map(|_| {
+                let profiler = Profiler::new();
+                builder.profiler(profiler.clone());
+                profiler
+            });
+            for (key, value) in named_args {
+                builder.arg(key, value);
+            }
+            let snapshots = snapshot_dir.map(|dir| {
+                let mut snapshots = Snapshots::new(dir);
+                snapshots.set_update(update_snapshots);
+                snapshots
+            });
+            let metrics = Metrics::new();
+            #[cfg(feature = "protobuf")]
+            builder.proto_descriptors(proto_descriptors);
+            let e = builder
+                .args(args)
+                .catalog(catalog)
+                .gc(gc)
+                .history(history)
+                .history_input(input_copy)
+                .max_instructions(cli.max_instructions)
+                .memory_limit(cli.memory_limit)
+                .metrics(metrics.clone())
+                .deterministic(cli.deterministic)
+                .name(&name)
+                .permissions(permissions)
+                .snapshots(snapshots)
+                .store(store)
+                .timeout(Some(Duration::from_secs(timeout)))
+                .build()?;
+            let result = e.evaluate();
+            // stacks and metrics are written even if the evaluation fails
+            if let (Some(path), Some(profiler)) = (profile, profiler) {
+                profiler.write_folded(fs::File::create(path)?)?;
+            }
+            if let Some(url) = pushgateway {
+                push_metrics(&url, &pushgateway_job, &metrics)?;
+            }
+            let mut buf = String::new();
+            let res = match result {
+                Ok(s) => {
+                    let output = output.unwrap_or_else(|| {
+                        let is_table = matches!(s.payload(), Value::Array(_) | Value::Object(_));
+                        if cli.json {
+                            OutputFormat::Json
+                        } else if is_table && io::stdout().is_terminal() {
+                            OutputFormat::Pretty
+                        } else {
+                            OutputFormat::Raw
+                        }
+                    });
+                    match (output, all_results) {
+                        (OutputFormat::Pretty, true) => {
+                            s.write_results_pretty(&mut buf, &print_options)?;
+                        }
+                        (OutputFormat::Pretty, false) => {
+                            s.write_pretty(&mut buf, &print_options)?;
+                        }
+                        (output, true) => {
+                            s.write_results(&mut buf, output == OutputFormat::Json)?;
+                        }
+                        (output, false) => s.write(&mut buf, output == OutputFormat::Json)?,
+                    }
+                    print!("{buf}");
+                    Ok(())
+                }
+                Err(err) if cli.json => {
+                    let mut report = err.report(&e);
+                    if cli.traceback {
+                        report.traceback = err.traceback();
+                    }
+                    bail!(serde_json::to_string(&report)?)
+                }
+                Err(err) => {
+                    err.write_lua_error(&mut buf, &e, cli.no_color)?;
+                    if cli.traceback {
+                        err.write_traceback(&mut buf)?;
+                    }
+                    eprint!("{buf}");
+                    Err(err.into())
+                }
+            };
+            // the report is written to standard error to keep the output intact
+            if let Some(dry_run) = dry_run {
+                let mut buf = String::new();
+                dry_run.write_report(&mut buf, cli.json)?;
+                eprint!("{buf}");
+            }
+            res
+        }
         Commands::Example(ExampleCommands::Cat { name }) => {
             let Some(found) = EXAMPLES.iter().find(|e| e.name() == name) else {
                 bail!("example with {name} not found");
             };
             let script = found.script().trim();
             let mut buf = String::new();
-            let e = EvaluationBuilder::new(script, io::stdin()).build();
+            let e = EvaluationBuilder::new(script, io::stdin()).build()?;
             e.write_script(&mut buf, &print_options)?;
             println!("{buf}");
             Ok(())
@@ -323,9 +1425,14 @@ async fn try_main() -> anyhow::Result<()> {
             let script = found.script().trim();
             let store = prepare_store(&store_options)?;
             let e = EvaluationBuilder::new(script, io::stdin())
+                .gc(gc)
+                .max_instructions(cli.max_instructions)
+                .memory_limit(cli.memory_limit)
+                .deterministic(cli.deterministic)
                 .name(name.as_str())
+                .permissions(permissions)
                 .store(store)
-                .build();
+                .build()?;
             let mut buf = String::new();
             match e.evaluate() {
                 Ok(s) => {
@@ -333,8 +1440,18 @@ async fn try_main() -> anyhow::Result<()> {
                     print!("{buf}");
                     Ok(())
                 }
+                Err(err) if cli.json => {
+                    let mut report = err.report(&e);
+                    if cli.traceback {
+                        report.traceback = err.traceback();
+                    }
+                    bail!(serde_json::to_string(&report)?)
+                }
                 Err(err) => {
                     err.write_lua_error(&mut buf, &e, cli.no_color)?;
+                    if cli.traceback {
+                        err.write_traceback(&mut buf)?;
+                    }
                     eprint!("{buf}");
                     Err(err.into())
                 }
@@ -359,11 +1476,17 @@ async fn try_main() -> anyhow::Result<()> {
                 bail!("example with {name} not found");
             };
             if cli.check_syntax {
-                do_check_syntax(cli.no_color, name.as_str(), found.script())?;
+                do_check_syntax(cli.no_color, cli.json, name.as_str(), found.script())?;
             }
             let timeout = timeout.map(Duration::from_secs);
             let mut options = ServeOptions::new(name.as_str(), found.script(), bind, store_options);
+            options.set_gc(gc);
+            options.set_max_input_bytes(cli.max_input_bytes);
+            options.set_deterministic(cli.deterministic);
+            options.set_max_instructions(cli.max_instructions);
+            options.set_memory_limit(cli.memory_limit);
             options.set_json(cli.json);
+            options.set_permissions(permissions);
             options.set_timeout(timeout);
             serve::serve_file(&options).await?;
             Ok(())
@@ -386,6 +1509,56 @@ async fn try_main() -> anyhow::Result<()> {
             println!("{}", skin.term_text(guide.content()));
             Ok(())
         }
+        Commands::History(c) => {
+            let store = open_history(&store_options)?;
+            match c {
+                HistoryCommands::List { limit } => {
+                    let invocations = store.invocations(limit)?;
+                    if cli.json {
+                        let values: Vec<_> = invocations.iter().map(invocation_json).collect();
+                        println!("{}", serde_json::to_string(&values)?);
+                        return Ok(());
+                    }
+                    let mut table = Table::new();
+                    table.load_preset(presets::NOTHING);
+                    table.set_header(["id", "name", "started at", "duration", "memory", "status"]);
+                    for i in invocations.iter() {
+                        table.add_row([
+                            i.id().to_string().as_str(),
+                            i.name(),
+                            &i.started_at().to_rfc3339(),
+                            &format!("{:?}", i.duration()),
+                            &i.used_memory().to_string(),
+                            if i.error().is_some() { "error" } else { "ok" },
+                        ]);
+                    }
+                    println!("{table}");
+                    Ok(())
+                }
+                HistoryCommands::Show { id } => {
+                    let Some(i) = store.invocation(id)? else {
+                        bail!("invocation {id} not found");
+                    };
+                    if cli.json {
+                        println!("{}", serde_json::to_string(&invocation_json(&i))?);
+                        return Ok(());
+                    }
+                    let mut table = Table::new();
+                    table.load_preset(presets::NOTHING);
+                    table.add_row(["id", &i.id().to_string()]);
+                    table.add_row(["name", i.name()]);
+                    table.add_row(["started at", &i.started_at().to_rfc3339()]);
+                    table.add_row(["duration", &format!("{:?}", i.duration())]);
+                    table.add_row(["memory", &format!("{} bytes", i.used_memory())]);
+                    match (i.result(), i.error()) {
+                        (_, Some(error)) => table.add_row(["error", error]),
+                        (result, None) => table.add_row(["result", result.unwrap_or_default()]),
+                    };
+                    println!("{table}");
+                    Ok(())
+                }
+            }
+        }
         Commands::ListThemes => {
             let p = bat::PrettyPrinter::new();
             for t in p.themes() {
@@ -393,38 +1566,197 @@ async fn try_main() -> anyhow::Result<()> {
             }
             Ok(())
         }
+        Commands::Man => {
+            write_man(&mut Cli::command(), &mut io::stdout())?;
+            Ok(())
+        }
+        Commands::Replay { id, timeout } => {
+            let history = open_history(&store_options)?;
+            let Some(invocation) = history.invocation(id)? else {
+                bail!("invocation {id} not found");
+            };
+            let script = match invocation.script() {
+                Some(script) if !script.is_empty() => script,
+                _ => bail!("invocation {id} was recorded without its script"),
+            };
+            let fixtures = DryRunFixtures {
+                env: invocation.env().clone(),
+                ..Default::default()
+            };
+            let dry_run = DryRun::new(fixtures);
+            let store = prepare_store(&store_options)?;
+            if invocation.input().len() >= MAX_HISTORY_INPUT_SIZE {
+                warn!(id, "input of the invocation may have been truncated");
+            }
+            let input = io::Cursor::new(invocation.input().to_vec());
+            let mut builder = EvaluationBuilder::new(script, input);
+            #[cfg(feature = "protobuf")]
+            builder.proto_descriptors(proto_descriptors);
+            let e = builder
+                .app_state(invocation.state().cloned())
+                .catalog(catalog)
+                .dry_run(dry_run.clone())
+                .gc(gc)
+                .max_instructions(cli.max_instructions)
+                .memory_limit(cli.memory_limit)
+                .deterministic(cli.deterministic)
+                .name(invocation.name())
+                .permissions(permissions)
+                .store(store)
+                .timeout(Some(Duration::from_secs(timeout)))
+                .build()?;
+            let mut buf = String::new();
+            let res = match e.evaluate() {
+                Ok(s) => {
+                    s.write(&mut buf, cli.json)?;
+                    print!("{buf}");
+                    Ok(())
+                }
+                Err(err) => {
+                    err.write_lua_error(&mut buf, &e, cli.no_color)?;
+                    eprint!("{buf}");
+                    Err(err.into())
+                }
+            };
+            let mut buf = String::new();
+            dry_run.write_report(&mut buf, cli.json)?;
+            eprint!("{buf}");
+            res
+        }
+        Commands::RunAt { when, mut file } => {
+            let (name, script) = read_script(&mut file)?;
+            let store = prepare_store(&store_options)?;
+            let history = cli
+                .history
+                .then(|| open_recording_history(&store_options))
+                .transpose()?;
+            let (reader, input_copy) = tee_input(Box::new(io::stdin()), history.is_some());
+            let mut builder = EvaluationBuilder::new(script, reader);
+            #[cfg(feature = "protobuf")]
+            builder.proto_descriptors(proto_descriptors);
+            let e = builder
+                .catalog(catalog)
+                .gc(gc)
+                .history(history)
+                .history_input(input_copy)
+                .max_instructions(cli.max_instructions)
+                .memory_limit(cli.memory_limit)
+                .deterministic(cli.deterministic)
+                .name(name)
+                .permissions(permissions)
+                .store(store.clone())
+                .build()?;
+            let scheduler = Scheduler::new(e, store);
+            if let Some(when) = when {
+                let at = DateTime::parse_from_rfc3339(&when)?;
+                scheduler.run_at(at.to_utc(), InvocationState::new())?;
+            }
+            scheduler.run_pending()?;
+            Ok(())
+        }
         Commands::Schedule {
             bail,
             cron,
+            cron_timezone,
             mut file,
             initial_run,
+            missed_runs,
         } => {
             let (name, script) = read_script(&mut file)?;
-            let schedule = Schedule::from_str(&cron)?;
+            let trigger = Trigger::from_str(&cron)?;
+            let timezone = parse_timezone(&cron_timezone)?;
             let store = prepare_store(&store_options)?;
+            let history = cli
+                .history
+                .then(|| open_recording_history(&store_options))
+                .transpose()?;
 
-            let mut options = ScheduleOptions::new(schedule);
-            options.set_bail(bail);
-            options.set_initial_run(initial_run);
+            let mut options = ScheduleOptions::new(trigger);
+            options
+                .set_bail(bail)
+                .set_initial_run(initial_run)
+                .set_missed_runs(MissedRunPolicy::from_str(&missed_runs)?)
+                .set_timezone(timezone);
 
-            let e = EvaluationBuilder::new(script, io::stdin())
+            let (reader, input_copy) = tee_input(Box::new(io::stdin()), history.is_some());
+            let mut builder = EvaluationBuilder::new(script, reader);
+            #[cfg(feature = "protobuf")]
+            builder.proto_descriptors(proto_descriptors);
+            let e = builder
+                .catalog(catalog)
+                .gc(gc)
+                .history(history)
+                .history_input(input_copy)
+                .max_instructions(cli.max_instructions)
+                .memory_limit(cli.memory_limit)
+                .deterministic(cli.deterministic)
                 .name(name)
+                .permissions(permissions)
                 .store(store)
-                .build();
+                .build()?;
             e.schedule(&options);
             Ok(())
         }
         Commands::Serve {
             bind,
+            cache,
+            no_decode_body,
+            no_etag,
             mut file,
+            maintenance_interval,
+            manifest,
+            metrics,
+            session_secret,
+            session_ttl,
+            socket_mode,
+            state,
             timeout,
         } => {
-            let (name, script) = read_script(&mut file)?;
+            let manifest = manifest.map(|path| Manifest::load(&path)).transpose()?;
+            let (name, script) = match &manifest {
+                Some(_) => (String::new(), String::new()),
+                None => read_script(&mut file)?,
+            };
             if cli.check_syntax {
-                do_check_syntax(cli.no_color, &name, &script)?;
+                match &manifest {
+                    Some(manifest) => {
+                        for app in &manifest.apps {
+                            let name = app.file.display().to_string();
+                            do_check_syntax(cli.no_color, cli.json, &name, &app.script)?;
+                        }
+                    }
+                    None => do_check_syntax(cli.no_color, cli.json, &name, &script)?,
+                }
             }
-            let timeout = timeout.map(Duration::from_secs);
             let mut options = ServeOptions::new(name, script, bind, store_options);
+            options.set_app_state(state);
+            options.set_catalog(catalog);
+            #[cfg(feature = "protobuf")]
+            options.set_proto_descriptors(proto_descriptors);
+            options.set_manifest(manifest);
+            if let Some(path) = cli.config {
+                let overrides = Config { timeout, ..config };
+                options.set_config(path, explicit_options(&matches, overrides));
+            }
+            let timeout = timeout.map(Duration::from_secs);
+            options.set_cache(cache);
+            options.set_decode_body(!no_decode_body);
+            options.set_etag(!no_etag);
+            options.set_gc(gc);
+            options.set_maintenance_interval(
+                Some(Duration::from_secs(maintenance_interval)).filter(|d| !d.is_zero()),
+            );
+            options.set_max_input_bytes(cli.max_input_bytes);
+            options.set_deterministic(cli.deterministic);
+            options.set_max_instructions(cli.max_instructions);
+            options.set_memory_limit(cli.memory_limit);
+            options.set_metrics(metrics.then(Metrics::new));
+            options.set_permissions(permissions);
+            options.set_session(
+                session_secret
+                    .map(|secret| SessionOptions::new(secret, Duration::from_secs(session_ttl))),
+            );
+            options.set_socket_mode(socket_mode);
             options.set_timeout(timeout);
             serve::serve_file(&options).await?;
             Ok(())
@@ -433,10 +1765,11 @@ async fn try_main() -> anyhow::Result<()> {
             let Some(store_path) = store_options.store_path() else {
                 bail!("store_path is required");
             };
-            let store = Store::new(store_path)?;
+            let mut store = Store::new(store_path)?;
             if store_options.run_migrations() {
                 store.migrate(None)?;
             }
+            store_options.apply(&mut store)?;
             match c {
                 StoreCommands::Delete { name } => {
                     let affected = store.delete(name)?;
@@ -486,6 +1819,48 @@ async fn try_main() -> anyhow::Result<()> {
                     print!("{affected}");
                     Ok(())
                 }
+                StoreCommands::Recompress => {
+                    let affected = store.recompress()?;
+                    print!("{affected}");
+                    Ok(())
+                }
+                StoreCommands::Reencrypt => {
+                    if store_options.encryption()?.is_none() {
+                        bail!("store_encryption_key is required");
+                    }
+                    let affected = store.reencrypt()?;
+                    print!("{affected}");
+                    Ok(())
+                }
+                StoreCommands::Vacuum => {
+                    let (before, after) = store.vacuum()?;
+                    let mut table = Table::new();
+                    table.load_preset(presets::NOTHING);
+                    table.set_header(["", "before", "after"]);
+                    let rows = store_stats_rows(&before).into_iter();
+                    for ((name, before), (_, after)) in rows.zip(store_stats_rows(&after)) {
+                        table.add_row([name, &before, &after]);
+                    }
+                    println!("{table}");
+                    Ok(())
+                }
+                StoreCommands::Verify => {
+                    let problems = store.verify()?;
+                    let stats = store.stats()?;
+                    let mut table = Table::new();
+                    table.load_preset(presets::NOTHING);
+                    for (name, value) in store_stats_rows(&stats) {
+                        table.add_row([name, &value]);
+                    }
+                    println!("{table}");
+                    if !problems.is_empty() {
+                        for problem in &problems {
+                            eprintln!("{problem}");
+                        }
+                        bail!("store is corrupted, {} problem(s) found", problems.len());
+                    }
+                    Ok(())
+                }
                 StoreCommands::Version => {
                     let version = store.current_version()?;
                     println!("{version}");
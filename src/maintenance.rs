@@ -0,0 +1,119 @@
+use chrono::Utc;
+use lmb::{expire_values, StoreBackend, CACHE_KEY_PREFIX};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::session::KEY_PREFIX as SESSION_KEY_PREFIX;
+
+/// Default interval of maintenance passes.
+pub const DEFAULT_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Outcome of a maintenance pass on a store.
+#[derive(Debug, Default, PartialEq)]
+pub struct MaintenanceReport {
+    /// Number of expired cached responses deleted
+    pub expired_cache: usize,
+    /// Number of expired sessions deleted
+    pub expired_sessions: usize,
+}
+
+/// Delete expired cached responses and sessions, and checkpoint the write-ahead log.
+pub fn run_pass(store: &dyn StoreBackend) -> anyhow::Result<MaintenanceReport> {
+    let now = Utc::now().timestamp();
+    let report = MaintenanceReport {
+        expired_cache: expire_values(store, CACHE_KEY_PREFIX, now)?,
+        expired_sessions: expire_values(store, SESSION_KEY_PREFIX, now)?,
+    };
+    store.checkpoint()?;
+    Ok(report)
+}
+
+/// Run maintenance passes periodically in background, on stores returned by the function
+/// when each pass starts, so stores replaced by reloading the config are picked up.
+pub fn spawn<F>(every: Duration, stores: F)
+where
+    F: Fn() -> Vec<Arc<dyn StoreBackend>> + Send + Sync + 'static,
+{
+    let stores = Arc::new(stores);
+    tokio::spawn(async move {
+        let mut ticker = interval(every);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // the first tick completes immediately
+        ticker.tick().await;
+        let mut passes = 0u64;
+        loop {
+            ticker.tick().await;
+            passes += 1;
+            let stores = stores.clone();
+            // store operations block, so they run off the executor
+            let res = tokio::task::spawn_blocking(move || {
+                let started = Instant::now();
+                let mut total = MaintenanceReport::default();
+                for store in stores() {
+                    match run_pass(store.as_ref()) {
+                        Ok(report) => {
+                            total.expired_cache += report.expired_cache;
+                            total.expired_sessions += report.expired_sessions;
+                        }
+                        Err(err) => warn!(?err, "maintenance pass failed"),
+                    }
+                }
+                (total, started.elapsed())
+            })
+            .await;
+            match res {
+                Ok((total, elapsed)) => info!(
+                    passes,
+                    expired_cache = total.expired_cache,
+                    expired_sessions = total.expired_sessions,
+                    ?elapsed,
+                    "maintenance pass finished"
+                ),
+                Err(err) => warn!(?err, "maintenance pass panicked"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use lmb::{cache_key, MemoryStore, StoreBackend};
+    use serde_json::json;
+
+    use super::{run_pass, MaintenanceReport, SESSION_KEY_PREFIX};
+
+    #[test]
+    fn run_pass_expires_values() {
+        let store = MemoryStore::default();
+        let expired = json!({ "expires_at": 0 });
+        let fresh = json!({ "expires_at": i64::MAX });
+        store
+            .put(&cache_key("GET", "/a", None, b""), &expired)
+            .unwrap();
+        store
+            .put(&cache_key("GET", "/b", None, b""), &fresh)
+            .unwrap();
+        store
+            .put(&format!("{SESSION_KEY_PREFIX}a"), &expired)
+            .unwrap();
+        store
+            .put(&format!("{SESSION_KEY_PREFIX}b"), &fresh)
+            .unwrap();
+        store.put("other", &expired).unwrap();
+
+        let report = run_pass(&store).unwrap();
+        assert_eq!(
+            MaintenanceReport {
+                expired_cache: 1,
+                expired_sessions: 1,
+            },
+            report
+        );
+        assert_eq!(3, store.list().unwrap().len());
+        assert_eq!(MaintenanceReport::default(), run_pass(&store).unwrap());
+    }
+}
@@ -0,0 +1,204 @@
+use clap::{Arg, Command};
+use std::io::{self, Write};
+
+/// Escape text for roff, so hyphens, backslashes and leading dots are printed as they are.
+fn escape(s: &str) -> String {
+    let s = s.replace('\\', "\\e").replace('-', "\\-");
+    if s.starts_with('.') || s.starts_with('\'') {
+        format!("\\&{s}")
+    } else {
+        s
+    }
+}
+
+fn paragraphs<W>(text: &str, w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            writeln!(w, ".PP")?;
+        } else {
+            writeln!(w, "{}", escape(line))?;
+        }
+    }
+    Ok(())
+}
+
+fn value_name(arg: &Arg) -> String {
+    arg.get_value_names()
+        .and_then(|names| names.first())
+        .map_or_else(|| arg.get_id().as_str().to_uppercase(), ToString::to_string)
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+fn write_synopsis<W>(name: &str, command: &Command, w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut line = format!("\\fB{}\\fR", escape(name));
+    if command.get_arguments().any(|a| !a.is_positional()) {
+        line.push_str(" [\\fIOPTIONS\\fR]");
+    }
+    for arg in command.get_positionals().filter(|a| !a.is_hide_set()) {
+        let value = escape(&value_name(arg));
+        if arg.is_required_set() {
+            line.push_str(&format!(" <\\fI{value}\\fR>"));
+        } else {
+            line.push_str(&format!(" [\\fI{value}\\fR]"));
+        }
+    }
+    if command.has_subcommands() {
+        line.push_str(" [\\fICOMMAND\\fR]");
+    }
+    writeln!(w, "{line}")
+}
+
+fn write_options<W>(command: &Command, w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    for arg in command.get_arguments().filter(|a| !a.is_hide_set()) {
+        writeln!(w, ".TP")?;
+        let mut flags = vec![];
+        if let Some(short) = arg.get_short() {
+            flags.push(format!("\\fB\\-{short}\\fR"));
+        }
+        if let Some(long) = arg.get_long() {
+            flags.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+        }
+        let value = escape(&value_name(arg));
+        let mut head = flags.join(", ");
+        if arg.is_positional() {
+            head = format!("[\\fI{value}\\fR]");
+        } else if takes_value(arg) {
+            head.push_str(&format!(" <\\fI{value}\\fR>"));
+        }
+        writeln!(w, "{head}")?;
+        let help = arg.get_long_help().or_else(|| arg.get_help());
+        if let Some(help) = help {
+            paragraphs(&help.to_string(), w)?;
+        }
+        let defaults = arg
+            .get_default_values()
+            .iter()
+            .map(|v| v.to_string_lossy())
+            .collect::<Vec<_>>();
+        if !defaults.is_empty() && takes_value(arg) {
+            writeln!(w, ".RS")?;
+            writeln!(w, "Default: {}", escape(&defaults.join(", ")))?;
+            writeln!(w, ".RE")?;
+        }
+        let choices = arg
+            .get_possible_values()
+            .iter()
+            .filter(|v| !v.is_hide_set())
+            .map(|v| v.get_name().to_string())
+            .collect::<Vec<_>>();
+        if !choices.is_empty() && takes_value(arg) {
+            writeln!(w, ".RS")?;
+            writeln!(w, "Possible values: {}", escape(&choices.join(", ")))?;
+            writeln!(w, ".RE")?;
+        }
+        if let Some(env) = arg.get_env() {
+            writeln!(w, ".RS")?;
+            writeln!(
+                w,
+                "May also be specified with the \\fB{}\\fR environment variable.",
+                escape(&env.to_string_lossy())
+            )?;
+            writeln!(w, ".RE")?;
+        }
+    }
+    Ok(())
+}
+
+fn write_subcommands<W>(prefix: &str, command: &Command, w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    // the help subcommand generated by clap only repeats the commands
+    for sub in command
+        .get_subcommands()
+        .filter(|c| !c.is_hide_set() && c.get_name() != "help")
+    {
+        let name = format!("{prefix} {}", sub.get_name());
+        writeln!(w, ".SS \"{}\"", escape(&name))?;
+        write_synopsis(&name, sub, w)?;
+        writeln!(w, ".PP")?;
+        if let Some(about) = sub.get_long_about().or_else(|| sub.get_about()) {
+            paragraphs(&about.to_string(), w)?;
+        }
+        let aliases = sub.get_visible_aliases().collect::<Vec<_>>();
+        if !aliases.is_empty() {
+            writeln!(w, ".PP")?;
+            writeln!(w, "Alias: {}", escape(&aliases.join(", ")))?;
+        }
+        write_options(sub, w)?;
+        write_subcommands(&name, sub, w)?;
+    }
+    Ok(())
+}
+
+/// Write the manual page of the command and its subcommands in roff.
+pub fn write_man<W>(command: &mut Command, w: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    command.build();
+    let name = command.get_name().to_string();
+    let version = command.get_version().unwrap_or_default();
+    writeln!(
+        w,
+        ".TH {} 1 \"\" \"{} {}\"",
+        escape(&name.to_uppercase()),
+        escape(&name),
+        escape(version)
+    )?;
+    writeln!(w, ".SH NAME")?;
+    let about = command
+        .get_about()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    writeln!(w, "{} \\- {}", escape(&name), escape(&about))?;
+    writeln!(w, ".SH SYNOPSIS")?;
+    write_synopsis(&name, command, w)?;
+    if let Some(about) = command.get_long_about() {
+        writeln!(w, ".SH DESCRIPTION")?;
+        paragraphs(&about.to_string(), w)?;
+    }
+    writeln!(w, ".SH OPTIONS")?;
+    write_options(command, w)?;
+    if command.has_subcommands() {
+        writeln!(w, ".SH COMMANDS")?;
+        write_subcommands(&name, command, w)?;
+    }
+    if let Some(author) = command.get_author().filter(|a| !a.is_empty()) {
+        writeln!(w, ".SH AUTHORS")?;
+        writeln!(w, "{}", escape(author))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::write_man;
+    use crate::Cli;
+
+    #[test]
+    fn man() {
+        let mut buf = Vec::new();
+        write_man(&mut Cli::command(), &mut buf).unwrap();
+        let page = String::from_utf8(buf).unwrap();
+        assert!(page.starts_with(".TH LMB 1"));
+        assert!(page.contains("\\fB\\-\\-allow\\-env\\fR <\\fIALLOW_ENV\\fR>"));
+        assert!(page.contains("with the \\fBLMB_ALLOW_ENV\\fR environment variable."));
+        assert!(page.contains(".SS \"lmb store get\""));
+        assert!(page.contains("Possible values: raw, json, pretty"));
+    }
+}
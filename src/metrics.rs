@@ -0,0 +1,263 @@
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fmt::Write as _, sync::Arc};
+
+/// Buckets of histograms when omitted, the same as client libraries of Prometheus.
+pub(crate) const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Kind of a metric, with upper bounds of buckets of histograms.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram(Vec<f64>),
+}
+
+impl MetricKind {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram(_) => "histogram",
+        }
+    }
+}
+
+/// Label names and values sorted by names.
+pub(crate) type Labels = Vec<(String, String)>;
+
+/// Value of counters and gauges, or the sum of histograms with cumulative counts of buckets.
+#[derive(Debug, Default)]
+struct Series {
+    buckets: Vec<u64>,
+    count: u64,
+    value: f64,
+}
+
+#[derive(Debug)]
+struct Family {
+    help: Option<String>,
+    kind: MetricKind,
+    series: BTreeMap<Labels, Series>,
+}
+
+/// Metrics defined by scripts with `@lmb/prometheus`, rendered in the text exposition
+/// format of Prometheus. Clones share metrics, so evaluations e.g. of a server accumulate
+/// into the same metrics.
+///
+/// ```rust
+/// # use std::io::empty;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let metrics = Metrics::new();
+/// let script = "require('@lmb/prometheus'):counter('jobs_done'):inc()";
+/// let e = EvaluationBuilder::new(script, empty()).metrics(metrics.clone()).build()?;
+/// e.evaluate()?;
+/// e.evaluate()?;
+/// assert_eq!("# TYPE jobs_done counter\njobs_done 2\n", metrics.render());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+impl Metrics {
+    /// Create empty metrics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define the metric, or check that the defined one is of the same kind.
+    pub(crate) fn define(
+        &self,
+        name: &str,
+        kind: MetricKind,
+        help: Option<String>,
+    ) -> Result<(), String> {
+        if !is_valid_name(name, true) {
+            return Err(format!("invalid metric name {name:?}"));
+        }
+        if let MetricKind::Histogram(bounds) = &kind {
+            if bounds.is_empty() || bounds.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(format!("buckets of {name} must be in increasing order"));
+            }
+        }
+        let mut families = self.families.lock();
+        match families.get(name) {
+            Some(family) if family.kind == kind => Ok(()),
+            Some(family) => Err(format!(
+                "metric {name} is already defined as a {}",
+                family.kind.name()
+            )),
+            None => {
+                let series = BTreeMap::new();
+                families.insert(name.to_string(), Family { help, kind, series });
+                Ok(())
+            }
+        }
+    }
+
+    fn update<F>(&self, name: &str, labels: Labels, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&MetricKind, &mut Series),
+    {
+        if let Some((label, _)) = labels.iter().find(|(l, _)| !is_valid_name(l, false)) {
+            return Err(format!("invalid label name {label:?}"));
+        }
+        let mut families = self.families.lock();
+        let Some(family) = families.get_mut(name) else {
+            return Err(format!("metric {name} is not defined"));
+        };
+        let series = family.series.entry(labels).or_default();
+        f(&family.kind, series);
+        Ok(())
+    }
+
+    /// Add the delta to the value of a counter or a gauge.
+    pub(crate) fn add(&self, name: &str, labels: Labels, delta: f64) -> Result<(), String> {
+        self.update(name, labels, |_, series| series.value += delta)
+    }
+
+    /// Set the value of a gauge.
+    pub(crate) fn set(&self, name: &str, labels: Labels, value: f64) -> Result<(), String> {
+        self.update(name, labels, |_, series| series.value = value)
+    }
+
+    /// Observe the value with a histogram.
+    pub(crate) fn observe(&self, name: &str, labels: Labels, value: f64) -> Result<(), String> {
+        self.update(name, labels, |kind, series| {
+            let MetricKind::Histogram(bounds) = kind else {
+                return;
+            };
+            series.buckets.resize(bounds.len(), 0);
+            for (bound, count) in bounds.iter().zip(series.buckets.iter_mut()) {
+                if value <= *bound {
+                    *count += 1;
+                }
+            }
+            series.count += 1;
+            series.value += value;
+        })
+    }
+
+    /// Render metrics in the text exposition format, which is served at `/metrics`
+    /// and pushed to a Pushgateway.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, family) in self.families.lock().iter() {
+            if let Some(help) = &family.help {
+                let help = help.replace('\\', r"\\").replace('\n', r"\n");
+                let _ = writeln!(out, "# HELP {name} {help}");
+            }
+            let _ = writeln!(out, "# TYPE {name} {}", family.kind.name());
+            for (labels, series) in &family.series {
+                let MetricKind::Histogram(bounds) = &family.kind else {
+                    let labels = format_labels(labels, None);
+                    let _ = writeln!(out, "{name}{labels} {}", format_value(series.value));
+                    continue;
+                };
+                let buckets = bounds.iter().map(|b| format_value(*b));
+                let counts = series.buckets.iter().copied();
+                let infinity = std::iter::once(("+Inf".to_string(), series.count));
+                for (le, count) in buckets.zip(counts).chain(infinity) {
+                    let labels = format_labels(labels, Some(&le));
+                    let _ = writeln!(out, "{name}_bucket{labels} {count}");
+                }
+                let labels = format_labels(labels, None);
+                let _ = writeln!(out, "{name}_sum{labels} {}", format_value(series.value));
+                let _ = writeln!(out, "{name}_count{labels} {}", series.count);
+            }
+        }
+        out
+    }
+}
+
+// metric names may contain colons, which are reserved for recording rules
+fn is_valid_name(name: &str, colon: bool) -> bool {
+    let valid = |c: char| c.is_ascii_alphabetic() || c == '_' || (colon && c == ':');
+    let mut chars = name.chars();
+    chars.next().is_some_and(valid) && chars.all(|c| valid(c) || c.is_ascii_digit())
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_infinite() {
+        return if value > 0.0 { "+Inf" } else { "-Inf" }.to_string();
+    }
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    value.to_string()
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let le = le.map(|le| ("le", le));
+    let pairs = labels
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .chain(le)
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', r"\\")
+                .replace('"', r#"\""#)
+                .replace('\n', r"\n");
+            format!(r#"{name}="{value}""#)
+        })
+        .collect::<Vec<_>>();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MetricKind, Metrics};
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics
+            .define("jobs_done", MetricKind::Counter, Some("Jobs done".into()))
+            .unwrap();
+        metrics
+            .define("job_seconds", MetricKind::Histogram(vec![0.5, 1.0]), None)
+            .unwrap();
+        let queue = vec![("queue".to_string(), "a\"b".to_string())];
+        metrics.add("jobs_done", vec![], 1.0).unwrap();
+        metrics.add("jobs_done", queue.clone(), 2.5).unwrap();
+        metrics.observe("job_seconds", vec![], 0.3).unwrap();
+        metrics.observe("job_seconds", vec![], 0.7).unwrap();
+        metrics.observe("job_seconds", vec![], 3.0).unwrap();
+        let expected = r#"# TYPE job_seconds histogram
+job_seconds_bucket{le="0.5"} 1
+job_seconds_bucket{le="1"} 2
+job_seconds_bucket{le="+Inf"} 3
+job_seconds_sum 4
+job_seconds_count 3
+# HELP jobs_done Jobs done
+# TYPE jobs_done counter
+jobs_done 1
+jobs_done{queue="a\"b"} 2.5
+"#;
+        assert_eq!(expected, metrics.render());
+    }
+
+    #[test]
+    fn define() {
+        let metrics = Metrics::new();
+        metrics.define("up", MetricKind::Gauge, None).unwrap();
+        metrics.define("up", MetricKind::Gauge, None).unwrap();
+        let err = metrics.define("up", MetricKind::Counter, None).unwrap_err();
+        assert_eq!("metric up is already defined as a gauge", err);
+        assert!(metrics.define("1up", MetricKind::Gauge, None).is_err());
+        let buckets = MetricKind::Histogram(vec![1.0, 0.5]);
+        assert!(metrics.define("latency", buckets, None).is_err());
+        let labels = vec![("1a".to_string(), String::new())];
+        assert!(metrics.set("up", labels, 1.0).is_err());
+    }
+}
@@ -0,0 +1,727 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Display,
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
+
+#[cfg(feature = "http")]
+use crate::HttpLimitError;
+
+/// Permissions of bindings with side effects beyond the store and standard I/O.
+/// Running subprocesses is denied by default, while network access is allowed
+/// to any host unless restricted.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let mut permissions = Permissions::default();
+/// assert!(permissions.net().is_allowed("example.com", 443));
+/// permissions
+///     .set_net(NetPermissions::new(["example.com:443"]))
+///     .set_run(RunPermissions::new(["echo"]));
+/// assert!(permissions.net().is_allowed("example.com", 443));
+/// assert!(!permissions.net().is_allowed("example.com", 80));
+/// assert!(permissions.run().is_allowed("echo"));
+/// assert!(!permissions.run().is_allowed("rm"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Permissions {
+    disabled_modules: Vec<String>,
+    env: EnvPermissions,
+    http_limits: HttpLimits,
+    http_timeouts: HttpTimeouts,
+    http_tls: HttpTls,
+    net: NetPermissions,
+    run: RunPermissions,
+}
+
+impl Permissions {
+    /// Get modules which are not registered, e.g. `@lmb/http`.
+    pub fn disabled_modules(&self) -> &[String] {
+        &self.disabled_modules
+    }
+
+    /// Check whether the module is disabled.
+    pub fn is_module_disabled(&self, name: &str) -> bool {
+        self.disabled_modules.iter().any(|m| m == name)
+    }
+
+    /// Disable modules, so `require` fails even before the script attempts to use them.
+    /// Names without the `@` prefix are built-in modules, e.g. `http` for `@lmb/http`.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// let mut permissions = Permissions::default();
+    /// permissions.set_disabled_modules(["crypto", "@lmb/http"]);
+    /// assert!(permissions.is_module_disabled("@lmb/crypto"));
+    /// assert!(permissions.is_module_disabled("@lmb/http"));
+    /// assert!(!permissions.is_module_disabled("@lmb/json"));
+    /// ```
+    pub fn set_disabled_modules<I, S>(&mut self, modules: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.disabled_modules = modules
+            .into_iter()
+            .map(|m| {
+                let m = m.as_ref();
+                if m.starts_with('@') {
+                    m.to_string()
+                } else {
+                    format!("@lmb/{m}")
+                }
+            })
+            .collect();
+        self
+    }
+
+    /// Get permissions of environment variables.
+    pub fn env(&self) -> &EnvPermissions {
+        &self.env
+    }
+
+    /// Get limits of `@lmb/http`.
+    pub fn http_limits(&self) -> &HttpLimits {
+        &self.http_limits
+    }
+
+    /// Get timeouts of `@lmb/http`.
+    pub fn http_timeouts(&self) -> &HttpTimeouts {
+        &self.http_timeouts
+    }
+
+    /// Get TLS options of `@lmb/http`.
+    pub fn http_tls(&self) -> &HttpTls {
+        &self.http_tls
+    }
+
+    /// Get permissions of network access.
+    pub fn net(&self) -> &NetPermissions {
+        &self.net
+    }
+
+    /// Get permissions of running subprocesses.
+    pub fn run(&self) -> &RunPermissions {
+        &self.run
+    }
+
+    /// Set permissions of environment variables.
+    pub fn set_env(&mut self, env: EnvPermissions) -> &mut Self {
+        self.env = env;
+        self
+    }
+
+    /// Set limits of `@lmb/http`.
+    pub fn set_http_limits(&mut self, http_limits: HttpLimits) -> &mut Self {
+        self.http_limits = http_limits;
+        self
+    }
+
+    /// Set timeouts of `@lmb/http`.
+    pub fn set_http_timeouts(&mut self, http_timeouts: HttpTimeouts) -> &mut Self {
+        self.http_timeouts = http_timeouts;
+        self
+    }
+
+    /// Set TLS options of `@lmb/http`.
+    pub fn set_http_tls(&mut self, http_tls: HttpTls) -> &mut Self {
+        self.http_tls = http_tls;
+        self
+    }
+
+    /// Set permissions of network access.
+    pub fn set_net(&mut self, net: NetPermissions) -> &mut Self {
+        self.net = net;
+        self
+    }
+
+    /// Set permissions of running subprocesses.
+    pub fn set_run(&mut self, run: RunPermissions) -> &mut Self {
+        self.run = run;
+        self
+    }
+}
+
+/// Allow-list of environment variables that scripts can read via `env` of `@lmb`.
+/// Values are snapshotted on creation, so evaluations see the same values
+/// even if the environment of the process changes. No variable is allowed by default.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let mut env = EnvPermissions::new(["GREETING", "NAME"]);
+/// env.load_env_file("GREETING=hello\nNAME='world'\nOTHER=1");
+/// assert_eq!(Some("hello"), env.vars().get("GREETING").map(String::as_str));
+/// assert!(env.vars().get("OTHER").is_none());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EnvPermissions {
+    allowed: Vec<String>,
+    vars: BTreeMap<String, String>,
+}
+
+impl EnvPermissions {
+    /// Allow variables by name, and snapshot their values from the environment.
+    pub fn new<I, S>(allowed: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Display,
+    {
+        let allowed: Vec<String> = allowed.into_iter().map(|s| s.to_string()).collect();
+        let vars = env::vars()
+            .filter(|(name, _)| allowed.contains(name))
+            .collect();
+        Self { allowed, vars }
+    }
+
+    /// Load allowed variables from the content of a `.env` file.
+    /// Variables already set in the environment take precedence.
+    pub fn load_env_file(&mut self, content: &str) -> &mut Self {
+        for (name, value) in parse_env_file(content) {
+            if self.is_allowed(&name) && !self.vars.contains_key(&name) {
+                self.vars.insert(name, value);
+            }
+        }
+        self
+    }
+
+    /// Check whether the variable is allowed.
+    pub fn is_allowed(&self, name: &str) -> bool {
+        self.allowed.iter().any(|a| a == name)
+    }
+
+    /// Get snapshotted values of allowed variables.
+    pub fn vars(&self) -> &BTreeMap<String, String> {
+        &self.vars
+    }
+}
+
+fn parse_env_file(content: &str) -> Vec<(String, String)> {
+    let mut vars = vec![];
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        let unquoted = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q).and_then(|v| v.strip_suffix(*q)))
+            .unwrap_or(value);
+        vars.push((name.trim().to_string(), unquoted.to_string()));
+    }
+    vars
+}
+
+/// Allow-list of hosts that `@lmb/http`, `@lmb/tcp` and `@lmb/udp` can connect to,
+/// and `@lmb/dns` can resolve. Any host is allowed by default.
+#[derive(Clone, Debug, Default)]
+pub struct NetPermissions {
+    allowed: Option<Vec<NetRule>>,
+}
+
+/// Host, optional port and optional path pattern allowed to connect.
+#[derive(Clone, Debug)]
+struct NetRule {
+    host: String,
+    port: Option<u16>,
+    path: Option<String>,
+}
+
+impl NetRule {
+    fn parse(s: &str) -> Self {
+        let (host_port, path) = match s.find('/') {
+            Some(i) => (&s[..i], Some(s[i..].to_string())),
+            None => (s, None),
+        };
+        let (host, port) = parse_host_port(host_port);
+        Self { host, port, path }
+    }
+
+    fn matches(&self, host: &str, port: u16) -> bool {
+        self.host.eq_ignore_ascii_case(trim_brackets(host)) && self.port.map_or(true, |p| p == port)
+    }
+}
+
+impl NetPermissions {
+    /// Only allow hosts e.g. `example.com`, `127.0.0.1:25` or `[::1]:8125`.
+    /// A host without port is allowed on any port.
+    /// A host followed by a path e.g. `api.github.com/repos/*` only allows HTTP requests
+    /// to matching paths, where `*` matches any characters including slashes.
+    pub fn new<I, S>(allowed: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let allowed = allowed
+            .into_iter()
+            .map(|s| NetRule::parse(s.as_ref()))
+            .collect();
+        Self {
+            allowed: Some(allowed),
+        }
+    }
+
+    /// Check whether the host is allowed to connect on the port.
+    /// Hosts only allowed on some paths are not allowed.
+    pub fn is_allowed(&self, host: &str, port: u16) -> bool {
+        let Some(allowed) = &self.allowed else {
+            return true;
+        };
+        allowed
+            .iter()
+            .any(|r| r.path.is_none() && r.matches(host, port))
+    }
+
+    /// Check whether the URL is allowed to request, by host, port and path.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// let permissions = NetPermissions::new(["api.github.com/repos/*"]);
+    /// let allowed = "https://api.github.com/repos/henry40408/lmb".parse().unwrap();
+    /// assert!(permissions.is_url_allowed(&allowed));
+    /// let denied = "https://api.github.com/user".parse().unwrap();
+    /// assert!(!permissions.is_url_allowed(&denied));
+    /// ```
+    #[cfg(feature = "http")]
+    pub fn is_url_allowed(&self, url: &url::Url) -> bool {
+        let Some(allowed) = &self.allowed else {
+            return true;
+        };
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or_default();
+        allowed.iter().any(|r| {
+            r.matches(host, port)
+                && r.path
+                    .as_ref()
+                    .map_or(true, |p| matches_pattern(p, url.path()))
+        })
+    }
+
+    /// Hosts of the allow-list with their ports, or `None` if any host is allowed.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// let permissions = NetPermissions::new(["example.com", "127.0.0.1:25", "example.com/a/*"]);
+    /// let hosts = permissions.hosts().unwrap();
+    /// assert_eq!(vec![("example.com", None), ("127.0.0.1", Some(25))], hosts);
+    /// assert!(NetPermissions::default().hosts().is_none());
+    /// ```
+    pub fn hosts(&self) -> Option<Vec<(&str, Option<u16>)>> {
+        let allowed = self.allowed.as_ref()?;
+        let mut hosts = vec![];
+        for rule in allowed {
+            let host = (rule.host.as_str(), rule.port);
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+        Some(hosts)
+    }
+
+    /// Check whether hosts are restricted.
+    pub fn is_restricted(&self) -> bool {
+        self.allowed.is_some()
+    }
+}
+
+// only `*` is special, which matches any characters
+#[cfg(feature = "http")]
+fn matches_pattern(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = s.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        let Some(i) = rest.find(part) else {
+            return false;
+        };
+        rest = &rest[i + part.len()..];
+    }
+    rest.ends_with(last)
+}
+
+fn trim_brackets(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+fn parse_host_port(s: &str) -> (String, Option<u16>) {
+    if let Some((host, port)) = s.rsplit_once(':') {
+        // a bare IPv6 address contains multiple colons without brackets
+        let is_bare_ipv6 = host.contains(':') && !host.ends_with(']');
+        if let (false, Ok(port)) = (is_bare_ipv6, port.parse()) {
+            return (trim_brackets(host).to_string(), Some(port));
+        }
+    }
+    (trim_brackets(s).to_string(), None)
+}
+
+/// Limits of `@lmb/http`, so a buggy loop cannot hammer an upstream API.
+/// Requests and downloaded bytes are counted per invocation, while requests in flight
+/// are counted across evaluations sharing the limits, e.g. requests of a server.
+/// Nothing is limited by default.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let mut limits = HttpLimits::default();
+/// limits
+///     .set_max_bytes(Some(1024 * 1024))
+///     .set_max_concurrency(Some(4))
+///     .set_max_requests(Some(10));
+/// assert_eq!(Some(10), limits.max_requests());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct HttpLimits {
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    in_flight: Arc<AtomicUsize>,
+    max_bytes: Option<u64>,
+    max_concurrency: Option<usize>,
+    max_requests: Option<u64>,
+}
+
+impl HttpLimits {
+    /// Get max bytes of response bodies downloaded by each invocation.
+    pub fn max_bytes(&self) -> Option<u64> {
+        self.max_bytes
+    }
+
+    /// Get max requests in flight.
+    pub fn max_concurrency(&self) -> Option<usize> {
+        self.max_concurrency
+    }
+
+    /// Get max requests sent by each invocation.
+    pub fn max_requests(&self) -> Option<u64> {
+        self.max_requests
+    }
+
+    /// Set or unset max bytes of response bodies downloaded by each invocation.
+    pub fn set_max_bytes(&mut self, max_bytes: Option<u64>) -> &mut Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Set or unset max requests in flight.
+    pub fn set_max_concurrency(&mut self, max_concurrency: Option<usize>) -> &mut Self {
+        self.max_concurrency = max_concurrency;
+        self
+    }
+
+    /// Set or unset max requests sent by each invocation.
+    pub fn set_max_requests(&mut self, max_requests: Option<u64>) -> &mut Self {
+        self.max_requests = max_requests;
+        self
+    }
+
+    /// Count a request in flight until the guard is dropped.
+    #[cfg(feature = "http")]
+    pub(crate) fn acquire(&self) -> Result<InFlightGuard, HttpLimitError> {
+        let count = self
+            .in_flight
+            .fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+        let guard = InFlightGuard(self.in_flight.clone());
+        match self.max_concurrency {
+            Some(limit) if count >= limit => Err(HttpLimitError::Concurrency(limit)),
+            _ => Ok(guard),
+        }
+    }
+}
+
+/// Request in flight counted by [`HttpLimits`].
+#[cfg(feature = "http")]
+#[derive(Debug)]
+pub(crate) struct InFlightGuard(Arc<AtomicUsize>);
+
+#[cfg(feature = "http")]
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, std::sync::atomic::Ordering::AcqRel);
+    }
+}
+
+/// Timeouts of `@lmb/http`. Slow connections and slow responses fail separately, and the
+/// total timeout bounds the whole request including retries and reading the body.
+/// Each request may override them with options, and all are bounded by the evaluation timeout.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// use lmb::*;
+///
+/// let mut timeouts = HttpTimeouts::default();
+/// timeouts
+///     .set_connect(Some(Duration::from_secs(1)))
+///     .set_read(Some(Duration::from_secs(5)))
+///     .set_total(Some(Duration::from_secs(10)));
+/// assert_eq!(Some(Duration::from_secs(1)), timeouts.connect());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpTimeouts {
+    connect: Option<Duration>,
+    read: Option<Duration>,
+    total: Option<Duration>,
+}
+
+impl HttpTimeouts {
+    /// Get the timeout of connecting to the server, including DNS resolution and TLS handshake.
+    pub fn connect(&self) -> Option<Duration> {
+        self.connect
+    }
+
+    /// Get the timeout of each read from the server, so a stalled response fails.
+    pub fn read(&self) -> Option<Duration> {
+        self.read
+    }
+
+    /// Get the timeout of the whole request, including retries and reading the body.
+    pub fn total(&self) -> Option<Duration> {
+        self.total
+    }
+
+    /// Set or unset the timeout of connecting to the server.
+    pub fn set_connect(&mut self, connect: Option<Duration>) -> &mut Self {
+        self.connect = connect;
+        self
+    }
+
+    /// Set or unset the timeout of each read from the server.
+    pub fn set_read(&mut self, read: Option<Duration>) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Set or unset the timeout of the whole request.
+    pub fn set_total(&mut self, total: Option<Duration>) -> &mut Self {
+        self.total = total;
+        self
+    }
+}
+
+/// TLS options of `@lmb/http`, e.g. a client certificate for mutual TLS with internal services.
+/// Certificates and keys are in PEM. Each request may override them with the `tls` option.
+/// Skipping verification of server certificates is denied unless explicitly allowed.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let mut tls = HttpTls::default();
+/// tls.set_ca_bundle(Some("-----BEGIN CERTIFICATE-----".to_string()))
+///     .set_allow_insecure(true);
+/// assert!(tls.allow_insecure());
+/// assert!(tls.client_cert().is_none());
+/// ```
+#[derive(Clone, Default)]
+pub struct HttpTls {
+    allow_insecure: bool,
+    ca_bundle: Option<String>,
+    client_cert: Option<String>,
+    client_key: Option<String>,
+}
+
+impl HttpTls {
+    /// Check whether requests are allowed to skip verification of server certificates.
+    pub fn allow_insecure(&self) -> bool {
+        self.allow_insecure
+    }
+
+    /// Get CA certificates which replace the default roots.
+    pub fn ca_bundle(&self) -> Option<&str> {
+        self.ca_bundle.as_deref()
+    }
+
+    /// Get the client certificate chain.
+    pub fn client_cert(&self) -> Option<&str> {
+        self.client_cert.as_deref()
+    }
+
+    /// Get the private key of the client certificate.
+    pub fn client_key(&self) -> Option<&str> {
+        self.client_key.as_deref()
+    }
+
+    /// Allow or deny requests to skip verification of server certificates.
+    pub fn set_allow_insecure(&mut self, allow_insecure: bool) -> &mut Self {
+        self.allow_insecure = allow_insecure;
+        self
+    }
+
+    /// Set or unset CA certificates which replace the default roots.
+    pub fn set_ca_bundle(&mut self, ca_bundle: Option<String>) -> &mut Self {
+        self.ca_bundle = ca_bundle;
+        self
+    }
+
+    /// Set or unset the client certificate chain.
+    pub fn set_client_cert(&mut self, client_cert: Option<String>) -> &mut Self {
+        self.client_cert = client_cert;
+        self
+    }
+
+    /// Set or unset the private key of the client certificate.
+    pub fn set_client_key(&mut self, client_key: Option<String>) -> &mut Self {
+        self.client_key = client_key;
+        self
+    }
+}
+
+impl std::fmt::Debug for HttpTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never print the private key
+        f.debug_struct("HttpTls")
+            .field("allow_insecure", &self.allow_insecure)
+            .field("ca_bundle", &self.ca_bundle.is_some())
+            .field("client_cert", &self.client_cert.is_some())
+            .field("client_key", &self.client_key.is_some())
+            .finish()
+    }
+}
+
+/// Allow-list of binaries that `@lmb/shell` can execute.
+#[derive(Clone, Debug, Default)]
+pub struct RunPermissions {
+    allowed: Vec<String>,
+}
+
+impl RunPermissions {
+    /// Allow binaries by name e.g. `git` or by path e.g. `/usr/bin/git`.
+    pub fn new<I, S>(allowed: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Display,
+    {
+        Self {
+            allowed: allowed.into_iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Check whether the binary is allowed. A binary allowed by name can only be executed
+    /// by name, which is resolved through `PATH`, and a binary with a path must be
+    /// allowed by the exact path, so `./echo` is not allowed by `echo`.
+    pub fn is_allowed(&self, binary: &str) -> bool {
+        self.allowed.iter().any(|allowed| allowed == binary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::{EnvPermissions, NetPermissions, RunPermissions};
+
+    use super::parse_env_file;
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn http_limits_concurrency() {
+        use crate::{HttpLimitError, HttpLimits};
+
+        let mut limits = HttpLimits::default();
+        limits.set_max_concurrency(Some(1));
+        let guard = limits.clone().acquire().unwrap();
+        let err = limits.acquire().unwrap_err();
+        assert_eq!(HttpLimitError::Concurrency(1), err);
+        drop(guard);
+        assert!(limits.acquire().is_ok());
+    }
+
+    #[test]
+    fn env_permissions() {
+        let mut env = EnvPermissions::new(["PATH", "A"]);
+        assert!(env.vars().contains_key("PATH"));
+        let path = env.vars().get("PATH").cloned();
+        env.load_env_file("PATH=/nowhere\nA=1\nB=2");
+        assert_eq!(path.as_ref(), env.vars().get("PATH"));
+        assert_eq!(Some(&"1".to_string()), env.vars().get("A"));
+        assert!(!env.is_allowed("B"));
+        assert!(!env.vars().contains_key("B"));
+    }
+
+    #[test]
+    fn env_file() {
+        let content = r#"
+        # comment
+        A=1
+        export B = "two words"
+        C='#not a comment'
+        D=
+        invalid
+        "#;
+        let expected = vec![
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "two words".to_string()),
+            ("C".to_string(), "#not a comment".to_string()),
+            ("D".to_string(), String::new()),
+        ];
+        assert_eq!(expected, parse_env_file(content));
+    }
+
+    #[test_case(&[], "example.com", 443, false)]
+    #[test_case(&["example.com"], "example.com", 443, true)]
+    #[test_case(&["example.com"], "EXAMPLE.com", 80, true)]
+    #[test_case(&["example.com:443"], "example.com", 443, true)]
+    #[test_case(&["example.com:443"], "example.com", 80, false)]
+    #[test_case(&["example.com"], "example.org", 443, false)]
+    #[test_case(&["[::1]:25"], "::1", 25, true)]
+    #[test_case(&["[::1]:25"], "[::1]", 25, true ; "bracketed")]
+    #[test_case(&["::1"], "::1", 25, true)]
+    fn net_permissions(allowed: &[&str], host: &str, port: u16, expected: bool) {
+        let permissions = NetPermissions::new(allowed);
+        assert_eq!(expected, permissions.is_allowed(host, port));
+    }
+
+    #[cfg(feature = "http")]
+    #[test_case(&["api.github.com/repos/*"], "https://api.github.com/repos/a/b", true)]
+    #[test_case(&["api.github.com/repos/*"], "https://api.github.com/repos", false)]
+    #[test_case(&["api.github.com/repos/*"], "https://api.github.com/user", false)]
+    #[test_case(&["api.github.com/repos/*/issues"], "https://api.github.com/repos/a/b/issues", true)]
+    #[test_case(&["api.github.com/repos/*/issues"], "https://api.github.com/repos/a/pulls", false)]
+    #[test_case(&["api.github.com:443/user"], "https://api.github.com/user?a=1", true)]
+    #[test_case(&["api.github.com:443/user"], "http://api.github.com/user", false)]
+    #[test_case(&["api.github.com"], "https://api.github.com/user", true)]
+    #[test_case(&["example.com/*"], "https://api.github.com/user", false)]
+    fn net_permissions_url(allowed: &[&str], url: &str, expected: bool) {
+        let permissions = NetPermissions::new(allowed);
+        assert_eq!(expected, permissions.is_url_allowed(&url.parse().unwrap()));
+    }
+
+    #[test]
+    fn net_permissions_path_denies_sockets() {
+        let permissions = NetPermissions::new(["api.github.com/repos/*"]);
+        assert!(!permissions.is_allowed("api.github.com", 443));
+    }
+
+    #[test]
+    fn net_permissions_default() {
+        let permissions = NetPermissions::default();
+        assert!(!permissions.is_restricted());
+        assert!(permissions.is_allowed("example.com", 443));
+    }
+
+    #[test_case(&["echo"], "echo", true)]
+    #[test_case(&["echo"], "/bin/echo", false)]
+    #[test_case(&["echo"], "./echo", false)]
+    #[test_case(&["/bin/echo"], "/bin/echo", true)]
+    #[test_case(&["/bin/echo"], "echo", false)]
+    #[test_case(&["echo"], "cat", false)]
+    #[test_case(&[], "echo", false)]
+    fn run_permissions(allowed: &[&str], binary: &str, expected: bool) {
+        let permissions = RunPermissions::new(allowed);
+        assert_eq!(expected, permissions.is_allowed(binary));
+    }
+}
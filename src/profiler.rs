@@ -0,0 +1,161 @@
+use mlua::prelude::*;
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, io::Write, sync::Arc, time::Instant};
+
+use crate::Result;
+
+#[derive(Debug, Default)]
+struct ProfilerState {
+    last: Option<Instant>,
+    stacks: BTreeMap<String, u64>,
+}
+
+/// Sampling profiler, which samples the Lua call stack in the interrupt callback,
+/// and attributes the time since the last sample to the stack in microseconds.
+/// Luau interrupts on function calls, returns and loop iterations.
+///
+/// ```rust
+/// # use std::io::empty;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let script = "local function f()\n  for i = 1, 1000 do end\nend\nf()";
+/// let profiler = Profiler::new();
+/// let e = EvaluationBuilder::new(script, empty())
+///     .name("a.lua")
+///     .profiler(profiler.clone())
+///     .build()?;
+/// e.evaluate()?;
+/// assert!(profiler.stacks().keys().any(|s| s == "a.lua;f (a.lua:1)"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    state: Arc<Mutex<ProfilerState>>,
+}
+
+fn frame_name(d: &mlua::Debug<'_>, outermost: bool) -> String {
+    let source = d.source();
+    let file = source.short_src.as_deref().unwrap_or("?");
+    // chunks are named like [string "a.lua"]
+    let file = file
+        .strip_prefix("[string \"")
+        .and_then(|f| f.strip_suffix("\"]"))
+        .unwrap_or(file);
+    let name = d.names().name;
+    let frame = match (source.what, name) {
+        ("C", name) => name.as_deref().unwrap_or("?").to_string(),
+        // the main chunk is the outermost function without name
+        (_, None) if outermost => file.to_string(),
+        (_, name) => {
+            let name = name.as_deref().unwrap_or("?");
+            match source.line_defined {
+                Some(line) => format!("{name} ({file}:{line})"),
+                None => format!("{name} ({file})"),
+            }
+        }
+    };
+    // semicolons separate frames in the folded format
+    frame.replace(';', ":")
+}
+
+impl Profiler {
+    /// Create a profiler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get sampled stacks from the outermost frame separated by semicolons,
+    /// and the time attributed to them in microseconds.
+    pub fn stacks(&self) -> BTreeMap<String, u64> {
+        self.state.lock().stacks.clone()
+    }
+
+    /// Write sampled stacks in the folded format, which can be rendered into a flamegraph
+    /// by e.g. `inferno-flamegraph` or `flamegraph.pl`.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let mut buf = Vec::new();
+    /// Profiler::new().write_folded(&mut buf)?;
+    /// assert!(buf.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_folded<W>(&self, mut f: W) -> Result<()>
+    where
+        W: Write,
+    {
+        for (stack, micros) in &self.state.lock().stacks {
+            writeln!(f, "{stack} {micros}")?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn start(&self) {
+        self.state.lock().last = Some(Instant::now());
+    }
+
+    pub(crate) fn sample(&self, vm: &Lua) {
+        let now = Instant::now();
+        let stack = (0..).map_while(|l| vm.inspect_stack(l)).collect::<Vec<_>>();
+        if stack.is_empty() {
+            return;
+        }
+        let frames = stack
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, d)| frame_name(d, i + 1 == stack.len()))
+            .collect::<Vec<_>>();
+        let mut state = self.state.lock();
+        let Some(last) = state.last.replace(now) else {
+            return;
+        };
+        let micros = u64::try_from(now.duration_since(last).as_micros()).unwrap_or(u64::MAX);
+        *state.stacks.entry(frames.join(";")).or_default() += micros;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::empty;
+
+    use crate::{EvaluationBuilder, Profiler};
+
+    #[test]
+    fn profile() {
+        let script = r#"
+        local function busy()
+          local s = 0
+          for i = 1, 200000 do s = s + i end
+          return s
+        end
+        local function idle() end
+        busy()
+        idle()
+        "#;
+        let profiler = Profiler::new();
+        let e = EvaluationBuilder::new(script, empty())
+            .name("a.lua")
+            .profiler(profiler.clone())
+            .build()
+            .unwrap();
+        e.evaluate().unwrap();
+        let stacks = profiler.stacks();
+        let busy = stacks.get("a.lua;busy (a.lua:2)").copied().unwrap_or(0);
+        let idle = stacks.get("a.lua;idle (a.lua:7)").copied().unwrap_or(0);
+        assert!(busy > idle, "{stacks:?}");
+
+        let mut buf = Vec::new();
+        profiler.write_folded(&mut buf).unwrap();
+        let folded = String::from_utf8(buf).unwrap();
+        assert!(
+            folded.lines().all(|l| l.rsplit_once(' ').is_some()),
+            "{folded}"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use prost_reflect::{DescriptorPool, MessageDescriptor};
+use std::{fs, path::Path};
+
+use crate::Result;
+
+/// Descriptors of protobuf messages which `@lmb/protobuf` encodes and decodes by full names,
+/// loaded from file descriptor sets e.g. compiled by
+/// `protoc --include_imports --descriptor_set_out=events.pb events.proto`.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let descriptors = ProtoDescriptors::new();
+/// assert!(descriptors.message("events.Event").is_none());
+/// assert!(descriptors.message_names().is_empty());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ProtoDescriptors {
+    pool: DescriptorPool,
+}
+
+impl ProtoDescriptors {
+    /// Create descriptors without any message.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add messages of the file descriptor set encoded in protobuf.
+    /// Files already added are skipped.
+    pub fn add(&mut self, bytes: &[u8]) -> Result<&mut Self> {
+        self.pool.decode_file_descriptor_set(bytes)?;
+        Ok(self)
+    }
+
+    /// Add messages of the file descriptor set in the file.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self> {
+        self.add(&fs::read(path)?)
+    }
+
+    /// Get the message by its full name e.g. `events.Event`.
+    pub fn message(&self, name: &str) -> Option<MessageDescriptor> {
+        self.pool.get_message_by_name(name)
+    }
+
+    /// Full names of messages, sorted.
+    pub fn message_names(&self) -> Vec<String> {
+        let mut names = self
+            .pool
+            .all_messages()
+            .map(|m| m.full_name().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
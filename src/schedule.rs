@@ -1,24 +1,183 @@
+use aes_gcm::aead::{rand_core::RngCore as _, OsRng};
+use chrono::{DateTime, FixedOffset, Local, LocalResult, Offset, TimeDelta, TimeZone, Utc};
 use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt::Write as _, io::Read, str::FromStr, sync::Arc, thread, time::Duration};
+use tracing::{debug, info, warn};
 
-use crate::Store;
+use crate::{
+    is_listening_signals, signal_received, Error, Evaluation, InvocationState, Result, Signal,
+    StateKey, Store, StoreBackend,
+};
+
+/// Prefix of store keys holding pending runs of [`Scheduler`],
+/// under [`crate::RESERVED_KEY_PREFIX`].
+pub const RUN_KEY_PREFIX: &str = "__lmb:run:";
+
+/// When a scheduled script runs, either a cron expression e.g. `0 */5 * * * *`
+/// or a fixed interval e.g. `@every 1h30m`.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let trigger: Trigger = "@every 1h30m".parse()?;
+/// assert!(matches!(trigger, Trigger::Every(d) if d == Duration::from_secs(5400)));
+/// assert!(matches!("0 */5 * * * *".parse()?, Trigger::Cron(_)));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub enum Trigger {
+    /// Cron expression with seconds.
+    Cron(Box<Schedule>),
+    /// Fixed interval.
+    Every(Duration),
+}
+
+impl From<Schedule> for Trigger {
+    fn from(schedule: Schedule) -> Self {
+        Self::Cron(Box::new(schedule))
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(every) = s.trim().strip_prefix("@every") {
+            return parse_interval(every.trim()).map(Self::Every);
+        }
+        Schedule::from_str(s)
+            .map(Self::from)
+            .map_err(|e| Error::InvalidSchedule(e.to_string()))
+    }
+}
+
+// e.g. 30s, 5m, 1h30m or 1d
+fn parse_interval(s: &str) -> Result<Duration> {
+    let invalid = || Error::InvalidSchedule(format!("invalid interval {s}"));
+    let mut secs = 0u64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let (n, tail) = rest.split_at(split);
+        let n: u64 = n
+            .parse()
+            .map_err(|e| Error::InvalidSchedule(format!("invalid interval {s}: {e}")))?;
+        let unit = tail.chars().next().ok_or_else(invalid)?;
+        let multiplier = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        secs += n * multiplier;
+        rest = &tail[unit.len_utf8()..];
+    }
+    if secs == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Timezone of cron expressions.
+///
+/// IANA names e.g. `Europe/Berlin` are supported by setting `TZ` of the process
+/// and choosing [`ScheduleTimezone::Local`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScheduleTimezone {
+    /// Coordinated universal time.
+    #[default]
+    Utc,
+    /// Timezone of the system.
+    Local,
+    /// Fixed offset e.g. `+08:00`, which never observes daylight saving time.
+    Fixed(FixedOffset),
+}
+
+impl FromStr for ScheduleTimezone {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "utc" => Ok(Self::Utc),
+            "local" => Ok(Self::Local),
+            _ => FixedOffset::from_str(s)
+                .map(Self::Fixed)
+                .map_err(|e| Error::InvalidSchedule(format!("invalid timezone {s}: {e}"))),
+        }
+    }
+}
+
+/// What to do when runs are missed e.g. the process was suspended.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MissedRunPolicy {
+    /// Run once for all missed runs.
+    #[default]
+    Coalesce,
+    /// Skip all missed runs.
+    Skip,
+}
+
+impl FromStr for MissedRunPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "coalesce" => Ok(Self::Coalesce),
+            "skip" => Ok(Self::Skip),
+            _ => Err(Error::InvalidSchedule(format!(
+                "invalid missed run policy {s}"
+            ))),
+        }
+    }
+}
 
 /// Schedule options.
+///
+/// Cron expressions are evaluated on the wall clock of the timezone.
+/// A time skipped by a daylight saving time transition is shifted forward
+/// by the length of the gap, and a time repeated by it runs once.
+///
+/// ```rust
+/// use chrono::{TimeZone, Utc};
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let mut options = ScheduleOptions::new("0 0 9 * * *".parse::<Trigger>()?);
+/// options.set_timezone("+08:00".parse()?);
+/// let after = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+/// let expected = Utc.with_ymd_and_hms(2024, 1, 1, 1, 0, 0).unwrap();
+/// assert_eq!(Some(expected), options.next_after(&after));
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug)]
 pub struct ScheduleOptions {
     bail: usize,
     initial_run: bool,
-    schedule: Schedule,
+    missed_runs: MissedRunPolicy,
     store: Option<Store>,
+    timezone: ScheduleTimezone,
+    trigger: Trigger,
 }
 
 impl ScheduleOptions {
     /// Create a new instance of schedule options.
-    pub fn new(schedule: Schedule) -> Self {
+    pub fn new<T: Into<Trigger>>(trigger: T) -> Self {
         Self {
             bail: 0,
             initial_run: false,
-            schedule,
+            missed_runs: MissedRunPolicy::default(),
             store: None,
+            timezone: ScheduleTimezone::default(),
+            trigger: trigger.into(),
         }
     }
 
@@ -27,9 +186,34 @@ impl ScheduleOptions {
         self.bail
     }
 
-    /// Get schedule.
-    pub fn schedule(&self) -> &Schedule {
-        &self.schedule
+    /// Get policy of missed runs.
+    pub fn missed_runs(&self) -> MissedRunPolicy {
+        self.missed_runs
+    }
+
+    /// Get the next run strictly after the time, or `None` when the schedule ends.
+    pub fn next_after(&self, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let schedule = match &self.trigger {
+            Trigger::Cron(schedule) => schedule,
+            Trigger::Every(interval) => {
+                return Some(*after + TimeDelta::from_std(*interval).ok()?);
+            }
+        };
+        match self.timezone {
+            ScheduleTimezone::Utc => next_in(schedule, &Utc, after),
+            ScheduleTimezone::Local => next_in(schedule, &Local, after),
+            ScheduleTimezone::Fixed(offset) => next_in(schedule, &offset, after),
+        }
+    }
+
+    /// Get timezone.
+    pub fn timezone(&self) -> ScheduleTimezone {
+        self.timezone
+    }
+
+    /// Get trigger.
+    pub fn trigger(&self) -> &Trigger {
+        &self.trigger
     }
 
     /// Set bail. 0 to disable.
@@ -44,9 +228,330 @@ impl ScheduleOptions {
         self
     }
 
+    /// Set policy of missed runs.
+    pub fn set_missed_runs(&mut self, missed_runs: MissedRunPolicy) -> &mut Self {
+        self.missed_runs = missed_runs;
+        self
+    }
+
     /// Set or unset store.
     pub fn set_store(&mut self, store: Option<Store>) -> &mut Self {
         self.store = store;
         self
     }
+
+    /// Set timezone of cron expressions.
+    pub fn set_timezone(&mut self, timezone: ScheduleTimezone) -> &mut Self {
+        self.timezone = timezone;
+        self
+    }
+}
+
+// cron drops times skipped or repeated by daylight saving time transitions,
+// so the expression is evaluated on the wall clock and resolved here instead
+fn next_in<Tz: TimeZone>(
+    schedule: &Schedule,
+    tz: &Tz,
+    after: &DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let wall = Utc.from_utc_datetime(&after.with_timezone(tz).naive_local());
+    schedule.after(&wall).find_map(|t| {
+        let naive = t.naive_utc();
+        let resolved = match tz.from_local_datetime(&naive) {
+            LocalResult::Single(t) => t.with_timezone(&Utc),
+            // the order of candidates differs between timezones
+            LocalResult::Ambiguous(a, b) => a.with_timezone(&Utc).min(b.with_timezone(&Utc)),
+            LocalResult::None => {
+                // the offset before the gap shifts the time forward by the length of the gap
+                let offset = tz
+                    .offset_from_utc_datetime(&(naive - TimeDelta::days(1)))
+                    .fix();
+                Utc.from_utc_datetime(&(naive - offset))
+            }
+        };
+        (resolved > *after).then_some(resolved)
+    })
+}
+
+// the monotonic clock stops while the process is suspended, so the wall clock
+// is checked periodically to notice missed runs. Return false if interrupted by a signal
+pub(crate) fn sleep_until(time: DateTime<Utc>) -> bool {
+    const MAX_SLEEP: Duration = Duration::from_secs(60);
+    const MAX_SLEEP_LISTENING: Duration = Duration::from_secs(1);
+    while let Ok(remaining) = (time - Utc::now()).to_std() {
+        if remaining.is_zero() {
+            break;
+        }
+        if !is_listening_signals() {
+            thread::sleep(remaining.min(MAX_SLEEP));
+            continue;
+        }
+        if is_interrupted() {
+            return false;
+        }
+        thread::sleep(remaining.min(MAX_SLEEP_LISTENING));
+    }
+    true
+}
+
+/// Whether SIGINT or SIGTERM is received, after which schedules stop.
+pub(crate) fn is_interrupted() -> bool {
+    signal_received(Signal::Interrupt) || signal_received(Signal::Terminate)
+}
+
+#[derive(Deserialize, Serialize)]
+struct PendingRun {
+    at: i64,
+    state: Vec<(StateKey, Value)>,
+}
+
+/// Run-once delayed evaluations of a script, like `at` of Unix.
+///
+/// Pending runs are persisted in the store, keyed by the name of the evaluation,
+/// so they survive restarts. Each run is claimed by removing it from the store,
+/// so it runs at most once even if processes share the store.
+///
+/// ```rust
+/// # use std::{io::empty, sync::Arc};
+/// use chrono::Utc;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let store: Arc<dyn StoreBackend> = Arc::new(MemoryStore::default());
+/// let e = EvaluationBuilder::new("require('@lmb'):put('n', 1)", empty())
+///     .store(store.clone())
+///     .build()?;
+/// let scheduler = Scheduler::new(e, store.clone());
+/// scheduler.run_at(Utc::now(), InvocationState::new())?;
+/// assert_eq!(1, scheduler.pending()?.len());
+/// assert_eq!(1, scheduler.run_due()?);
+/// assert!(scheduler.pending()?.is_empty());
+/// assert_eq!(serde_json::json!(1), store.get("n")?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Scheduler<R>
+where
+    for<'lua> R: 'lua + Read + Send,
+{
+    evaluation: Arc<Evaluation<R>>,
+    store: Arc<dyn StoreBackend>,
+}
+
+impl<R> Scheduler<R>
+where
+    for<'lua> R: 'lua + Read + Send,
+{
+    /// Create a scheduler persisting pending runs of the evaluation in the store.
+    pub fn new(evaluation: Arc<Evaluation<R>>, store: Arc<dyn StoreBackend>) -> Self {
+        Self { evaluation, store }
+    }
+
+    fn key_prefix(&self) -> String {
+        format!("{RUN_KEY_PREFIX}{}:", self.evaluation.name())
+    }
+
+    /// Persist a run at the time with the state, and return its ID.
+    pub fn run_at(&self, at: DateTime<Utc>, state: InvocationState) -> Result<String> {
+        let mut bytes = [0u8; 8];
+        OsRng.fill_bytes(&mut bytes);
+        let id = bytes
+            .iter()
+            .fold(format!("{:013}-", at.timestamp_millis()), |mut id, b| {
+                let _ = write!(id, "{b:02x}");
+                id
+            });
+        let run = PendingRun {
+            at: at.timestamp_millis(),
+            state: state.into_iter().collect(),
+        };
+        self.store.put(
+            &format!("{}{id}", self.key_prefix()),
+            &serde_json::to_value(run)?,
+        )?;
+        debug!(%at, id, "run persisted");
+        Ok(id)
+    }
+
+    /// Get IDs and times of pending runs, ordered by time.
+    pub fn pending(&self) -> Result<Vec<(String, DateTime<Utc>)>> {
+        let prefix = self.key_prefix();
+        let mut pending = vec![];
+        for metadata in self.store.list()? {
+            let Some(id) = metadata.name().strip_prefix(&prefix) else {
+                continue;
+            };
+            let run: PendingRun = serde_json::from_value(self.store.get(metadata.name())?)?;
+            let at = DateTime::from_timestamp_millis(run.at).unwrap_or_default();
+            pending.push((id.to_string(), at));
+        }
+        pending.sort_by_key(|(_, at)| *at);
+        Ok(pending)
+    }
+
+    /// Evaluate runs which are due, and return the number of them.
+    /// Errors of evaluations are logged rather than returned.
+    pub fn run_due(&self) -> Result<usize> {
+        let now = Utc::now();
+        let mut count = 0;
+        for (id, at) in self.pending()? {
+            if at > now {
+                break;
+            }
+            let key = format!("{}{id}", self.key_prefix());
+            let Ok(value) = self.store.get(&key) else {
+                continue;
+            };
+            // claimed by another process
+            if self.store.delete(&key)? == 0 {
+                continue;
+            }
+            let run: PendingRun = serde_json::from_value(value)?;
+            let state: InvocationState = run.state.into_iter().collect();
+            debug!(id, "run due");
+            if let Err(err) = self.evaluation.evaluate_with_state(Arc::new(state)) {
+                warn!(?err, id, "failed to evaluate");
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Evaluate pending runs when they are due, until none is left
+    /// or SIGINT or SIGTERM is received by scripts listening to signals.
+    pub fn run_pending(&self) -> Result<()> {
+        loop {
+            self.run_due()?;
+            let Some((_, next)) = self.pending()?.into_iter().next() else {
+                return Ok(());
+            };
+            debug!(%next, "next run");
+            if is_interrupted() || !sleep_until(next) {
+                info!("stop because signal received");
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::NamedTempFile;
+    use serde_json::json;
+    use std::{io::empty, str::FromStr, sync::Arc, time::Duration};
+
+    use chrono::{DateTime, FixedOffset, TimeDelta, TimeZone, Utc};
+    use test_case::test_case;
+
+    use crate::{
+        EvaluationBuilder, InvocationState, MissedRunPolicy, ScheduleOptions, ScheduleTimezone,
+        Scheduler, StateKey, Store, StoreBackend, Trigger,
+    };
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test_case("@every 30s", 30)]
+    #[test_case("@every 5m", 300)]
+    #[test_case("@every 1h30m", 5400)]
+    #[test_case("@every 1d", 86400)]
+    fn every(s: &str, secs: u64) {
+        let trigger = Trigger::from_str(s).unwrap();
+        assert!(matches!(trigger, Trigger::Every(d) if d == Duration::from_secs(secs)));
+    }
+
+    #[test_case("@every")]
+    #[test_case("@every 5")]
+    #[test_case("@every 0s")]
+    #[test_case("@every 5w")]
+    #[test_case("* *")]
+    fn invalid_trigger(s: &str) {
+        assert!(Trigger::from_str(s).is_err());
+    }
+
+    #[test_case("UTC", ScheduleTimezone::Utc)]
+    #[test_case("local", ScheduleTimezone::Local)]
+    #[test_case("+08:00", ScheduleTimezone::Fixed(FixedOffset::east_opt(8 * 3600).unwrap()))]
+    fn timezone(s: &str, expected: ScheduleTimezone) {
+        assert_eq!(expected, ScheduleTimezone::from_str(s).unwrap());
+    }
+
+    #[test]
+    fn missed_runs() {
+        assert_eq!(MissedRunPolicy::Skip, "skip".parse().unwrap());
+        assert_eq!(MissedRunPolicy::Coalesce, "coalesce".parse().unwrap());
+        assert!(MissedRunPolicy::from_str("all").is_err());
+    }
+
+    #[test]
+    fn next_every() {
+        let options = ScheduleOptions::new(Trigger::from_str("@every 5m").unwrap());
+        let after = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let expected = Utc.with_ymd_and_hms(2024, 1, 1, 0, 5, 0).unwrap();
+        assert_eq!(Some(expected), options.next_after(&after));
+    }
+
+    // daylight saving time of Europe/Berlin starts on 2024-03-31 and ends on 2024-10-27
+    #[test_case("2024-03-30T23:00:00Z", "2024-03-31T01:30:00Z"; "skipped time is shifted")]
+    #[test_case("2024-10-26T23:00:00Z", "2024-10-27T00:30:00Z"; "repeated time runs first")]
+    #[test_case("2024-10-27T00:30:00Z", "2024-10-28T01:30:00Z"; "repeated time runs once")]
+    fn next_across_dst(after: &str, expected: &str) {
+        // no other test depends on the local timezone
+        std::env::set_var("TZ", "Europe/Berlin");
+        let mut options = ScheduleOptions::new(Trigger::from_str("0 30 2 * * *").unwrap());
+        options.set_timezone(ScheduleTimezone::Local);
+        assert_eq!(Some(utc(expected)), options.next_after(&utc(after)));
+    }
+
+    #[test]
+    fn run_at_with_state() {
+        let store: Arc<dyn StoreBackend> = Arc::new(Store::default());
+        let script = "local m = require('@lmb'); m:put('n', m.request)";
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+        let scheduler = Scheduler::new(e, store.clone());
+        let state = InvocationState::new();
+        state.insert(StateKey::Request, json!({ "a": 1 }));
+        scheduler.run_at(Utc::now(), state).unwrap();
+        scheduler
+            .run_at(Utc::now() + TimeDelta::hours(1), InvocationState::new())
+            .unwrap();
+        assert_eq!(1, scheduler.run_due().unwrap());
+        assert_eq!(json!({ "a": 1 }), store.get("n").unwrap());
+        assert_eq!(1, scheduler.pending().unwrap().len());
+    }
+
+    #[test]
+    fn run_at_survives_restart() {
+        let store_file = NamedTempFile::new("db.sqlite3").unwrap();
+        let open = || {
+            let store = Store::new(store_file.path()).unwrap();
+            store.migrate(None).unwrap();
+            let store: Arc<dyn StoreBackend> = Arc::new(store);
+            let e = EvaluationBuilder::new("require('@lmb'):put('n', 1)", empty())
+                .name("job.lua")
+                .store(store.clone())
+                .build()
+                .unwrap();
+            (Scheduler::new(e, store.clone()), store)
+        };
+        let (scheduler, _) = open();
+        scheduler
+            .run_at(
+                Utc::now() + TimeDelta::milliseconds(100),
+                InvocationState::new(),
+            )
+            .unwrap();
+        drop(scheduler);
+
+        let (scheduler, store) = open();
+        assert_eq!(1, scheduler.pending().unwrap().len());
+        scheduler.run_pending().unwrap();
+        assert!(scheduler.pending().unwrap().is_empty());
+        assert_eq!(json!(1), store.get("n").unwrap());
+    }
 }
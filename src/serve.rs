@@ -1,67 +1,352 @@
-use crate::StoreOptions;
+use crate::{
+    config::{Config, Manifest},
+    maintenance,
+    session::SessionOptions,
+    StoreOptions,
+};
+use anyhow::anyhow;
 use axum::{
+    async_trait,
     body::Bytes,
-    extract::{Path, State as AxumState},
-    http::{HeaderMap, Method, StatusCode},
+    extract::{ConnectInfo, FromRequestParts, Path, RawQuery, Request, State as AxumState},
+    http::{request::Parts, HeaderMap, Method, StatusCode},
     response::IntoResponse,
-    routing::any,
+    routing::{any, get},
     Router,
 };
-use http::{HeaderName, HeaderValue};
-use lmb::{EvaluationBuilder, State, StateKey, Store};
-use serde_json::{Map, Value};
+use chrono::{DateTime, Utc};
+use http::{
+    header::{
+        AUTHORIZATION, CONTENT_TYPE, COOKIE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+        SET_COOKIE,
+    },
+    HeaderName, HeaderValue,
+};
+#[cfg(feature = "protobuf")]
+use lmb::ProtoDescriptors;
+use lmb::{
+    cache_key, Catalog, Error, EvaluationBuilder, GcOptions, HttpError, InvocationState, Metrics,
+    NamespacedStore, Permissions, StateKey, Store, StoreBackend, REQUEST_ID_HEADER,
+};
+use parking_lot::RwLock;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap, fmt::Display, io::Cursor, str::FromStr as _, sync::Arc, time::Duration,
+    collections::HashMap,
+    convert::Infallible,
+    fmt::{Display, Write as _},
+    io::Cursor,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{net::TcpListener, task::JoinSet};
+use tower_http::{
+    request_id::{MakeRequestId, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    trace::{self, TraceLayer},
 };
-use tokio::net::ToSocketAddrs;
-use tower_http::trace::{self, TraceLayer};
-use tracing::{error, info, warn, Level};
+use tracing::{debug, error, info, info_span, warn, Level, Span};
+use uuid::Uuid;
+
+/// Header telling whether the response is served from the cache.
+const CACHE_HEADER: &str = "x-lmb-cache";
+
+/// Rule to cache responses of a method in the store, e.g. `GET:60s`.
+/// Requests with credentials i.e. `Authorization` or `Cookie` headers are never cached.
+#[derive(Clone, Debug)]
+pub struct CacheRule {
+    method: Method,
+    ttl: Duration,
+}
+
+impl FromStr for CacheRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, ttl) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("cache rule should be <method>:<ttl>"))?;
+        let method = Method::from_str(&method.to_uppercase())?;
+        let split = ttl.find(|c: char| !c.is_ascii_digit()).unwrap_or(ttl.len());
+        let (n, unit) = ttl.split_at(split);
+        let n: u64 = n.parse()?;
+        let secs = match unit {
+            "" | "s" => Some(n),
+            "m" => n.checked_mul(60),
+            "h" => n.checked_mul(60 * 60),
+            "d" => n.checked_mul(24 * 60 * 60),
+            _ => return Err(anyhow!("unknown unit of time {unit}")),
+        }
+        .ok_or_else(|| anyhow!("ttl {ttl} is too long"))?;
+        Ok(Self {
+            method,
+            ttl: Duration::from_secs(secs),
+        })
+    }
+}
+
+/// Address to bind the server to, e.g. `127.0.0.1:3000`, `[::1]:3000`
+/// or a Unix domain socket `unix:/run/lmb.sock`.
+#[derive(Clone, Debug)]
+pub enum BindAddress {
+    /// TCP host and port
+    Tcp(String),
+    /// Path of the Unix domain socket
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err(anyhow!("path of Unix domain socket is required")),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => Ok(Self::Tcp(s.to_string())),
+        }
+    }
+}
+
+impl Display for BindAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Options applied to new requests, replaced when the config file is reloaded.
+struct LiveOptions {
+    permissions: Permissions,
+    store: Arc<dyn StoreBackend>,
+    store_path: Option<PathBuf>,
+    timeout: Option<Duration>,
+}
+
+/// Live options shared by handlers of a function.
+type SharedLiveOptions = Arc<RwLock<Arc<LiveOptions>>>;
 
 #[derive(Clone)]
 struct AppState {
+    app_state: Option<Value>,
+    cache: Arc<Vec<CacheRule>>,
+    catalog: Catalog,
+    decode_body: bool,
+    deterministic: bool,
+    etag: bool,
+    gc: GcOptions,
     json: bool,
+    live: SharedLiveOptions,
+    max_input_bytes: Option<usize>,
+    max_instructions: Option<u64>,
+    memory_limit: Option<usize>,
+    metrics: Metrics,
     name: String,
+    #[cfg(feature = "protobuf")]
+    proto_descriptors: ProtoDescriptors,
+    recycled_vms: Arc<AtomicU64>,
     script: String,
-    store: Store,
-    timeout: Option<Duration>,
+    session: Option<SessionOptions>,
 }
 
-pub struct ServeOptions<S, T>
+pub struct ServeOptions<S>
 where
     S: Display,
-    T: Display + ToSocketAddrs,
 {
-    bind: T,
+    app_state: Option<Value>,
+    bind: Vec<BindAddress>,
+    cache: Vec<CacheRule>,
+    catalog: Catalog,
+    config: Option<(PathBuf, Config)>,
+    decode_body: bool,
+    deterministic: bool,
+    etag: bool,
+    gc: GcOptions,
     json: bool,
+    maintenance_interval: Option<Duration>,
+    manifest: Option<Manifest>,
+    max_input_bytes: Option<usize>,
+    max_instructions: Option<u64>,
+    memory_limit: Option<usize>,
+    metrics: Option<Metrics>,
     name: S,
+    permissions: Permissions,
+    #[cfg(feature = "protobuf")]
+    proto_descriptors: ProtoDescriptors,
+    recycled_vms: Arc<AtomicU64>,
     script: S,
+    session: Option<SessionOptions>,
+    socket_mode: Option<u32>,
     store_options: StoreOptions,
     timeout: Option<Duration>,
 }
 
-impl<S, T> ServeOptions<S, T>
+impl<S> ServeOptions<S>
 where
     S: Display,
-    T: Display + ToSocketAddrs,
 {
-    /// Create a new instance of serve options.
-    pub fn new(name: S, script: S, bind: T, store_options: StoreOptions) -> Self {
+    /// Create a new instance of serve options, listening on all addresses.
+    pub fn new(name: S, script: S, bind: Vec<BindAddress>, store_options: StoreOptions) -> Self {
         Self {
+            app_state: None,
             bind,
+            cache: Vec::new(),
+            catalog: Catalog::default(),
+            config: None,
+            decode_body: true,
+            deterministic: false,
+            etag: true,
+            gc: GcOptions::default(),
             json: false,
+            maintenance_interval: None,
+            manifest: None,
+            max_input_bytes: None,
+            max_instructions: None,
+            memory_limit: None,
+            metrics: None,
             name,
+            permissions: Permissions::default(),
+            #[cfg(feature = "protobuf")]
+            proto_descriptors: ProtoDescriptors::default(),
+            recycled_vms: Arc::new(AtomicU64::new(0)),
             script,
+            session: None,
+            socket_mode: None,
             store_options,
             timeout: None,
         }
     }
 
+    /// Set rules to cache responses.
+    pub fn set_cache(&mut self, cache: Vec<CacheRule>) -> &mut Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Reload permissions, store path and timeout from the config file on SIGHUP.
+    /// Options specified on the command line take precedence over the file.
+    pub fn set_config(&mut self, path: PathBuf, overrides: Config) -> &mut Self {
+        self.config = Some((path, overrides));
+        self
+    }
+
+    /// Enable or disable decoding request bodies by content type.
+    pub fn set_decode_body(&mut self, yes: bool) -> &mut Self {
+        self.decode_body = yes;
+        self
+    }
+
+    /// Enable or disable computing entity tags of responses.
+    pub fn set_etag(&mut self, yes: bool) -> &mut Self {
+        self.etag = yes;
+        self
+    }
+
+    /// Set options of the garbage collector.
+    pub fn set_gc(&mut self, gc: GcOptions) -> &mut Self {
+        self.gc = gc;
+        self
+    }
+
     /// Set JSON mode.
     pub fn set_json(&mut self, yes: bool) -> &mut Self {
         self.json = yes;
         self
     }
 
+    /// Set or unset the interval of maintenance passes, which delete expired cached responses
+    /// and sessions and checkpoint the store.
+    pub fn set_maintenance_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.maintenance_interval = interval;
+        self
+    }
+
+    /// Serve functions of the manifest under their path prefixes instead of the script.
+    /// Options of the manifest are not reloaded on SIGHUP.
+    pub fn set_manifest(&mut self, manifest: Option<Manifest>) -> &mut Self {
+        self.manifest = manifest;
+        self
+    }
+
+    /// Set the application state, shared read-only by requests as `app_state` of `@lmb`.
+    /// Each request gets its own copy as `state`, so mutations never leak into other requests.
+    pub fn set_app_state(&mut self, app_state: Option<Value>) -> &mut Self {
+        self.app_state = app_state;
+        self
+    }
+
+    /// Set the catalog which `@lmb/i18n` translates messages with.
+    pub fn set_catalog(&mut self, catalog: Catalog) -> &mut Self {
+        self.catalog = catalog;
+        self
+    }
+
+    /// Set descriptors of protobuf messages which `@lmb/protobuf` encodes and decodes.
+    #[cfg(feature = "protobuf")]
+    pub fn set_proto_descriptors(&mut self, proto_descriptors: ProtoDescriptors) -> &mut Self {
+        self.proto_descriptors = proto_descriptors;
+        self
+    }
+
+    /// Set or unset the max bytes of a single read of the request body.
+    /// Requests whose script exceeds it are responded with 413 Payload Too Large.
+    pub fn set_max_input_bytes(&mut self, max_input_bytes: Option<usize>) -> &mut Self {
+        self.max_input_bytes = max_input_bytes;
+        self
+    }
+
+    /// Make evaluations of requests deterministic.
+    pub fn set_deterministic(&mut self, deterministic: bool) -> &mut Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Set or unset the instruction budget of each request.
+    pub fn set_max_instructions(&mut self, max_instructions: Option<u64>) -> &mut Self {
+        self.max_instructions = max_instructions;
+        self
+    }
+
+    /// Set or unset the max bytes allocated by the virtual machine of each request.
+    /// Requests exceeding it are responded with 503 Service Unavailable.
+    pub fn set_memory_limit(&mut self, memory_limit: Option<usize>) -> &mut Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    /// Expose metrics defined by scripts with `@lmb/prometheus` at `/metrics`,
+    /// which takes precedence over the function.
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Set permissions granted to the function.
+    pub fn set_permissions(&mut self, permissions: Permissions) -> &mut Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Enable or disable sessions.
+    pub fn set_session(&mut self, session: Option<SessionOptions>) -> &mut Self {
+        self.session = session;
+        self
+    }
+
+    /// Set permissions of Unix domain sockets e.g. `0o660`, or keep the default of the umask.
+    pub fn set_socket_mode(&mut self, mode: Option<u32>) -> &mut Self {
+        self.socket_mode = mode;
+        self
+    }
+
     /// Set or unset timeout.
     pub fn set_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
         self.timeout = timeout;
@@ -69,22 +354,285 @@ where
     }
 }
 
+fn load_cached(store: &dyn StoreBackend, key: &str) -> Option<(StatusCode, HeaderMap, String)> {
+    let value = match store.get(key) {
+        Ok(value) => value,
+        Err(err) => {
+            warn!(?err, "failed to load cached response");
+            return None;
+        }
+    };
+    let expires_at = value.get("expires_at").and_then(Value::as_i64)?;
+    if expires_at <= Utc::now().timestamp() {
+        return None;
+    }
+    let status_code = value.get("status_code").and_then(Value::as_u64)?;
+    let status_code = StatusCode::from_u16(u16::try_from(status_code).ok()?).ok()?;
+    let mut headers = HeaderMap::new();
+    for pair in value.get("headers").and_then(Value::as_array)? {
+        let (Some(name), Some(value)) = (pair.get(0)?.as_str(), pair.get(1)?.as_str()) else {
+            continue;
+        };
+        headers.append(
+            HeaderName::from_str(name).ok()?,
+            HeaderValue::from_str(value).ok()?,
+        );
+    }
+    headers.insert(CACHE_HEADER, HeaderValue::from_static("hit"));
+    let body = value.get("body").and_then(Value::as_str)?.to_string();
+    Some((status_code, headers, body))
+}
+
+fn save_cached(
+    store: &dyn StoreBackend,
+    key: &str,
+    ttl: Duration,
+    (status_code, headers, body): &(StatusCode, HeaderMap, String),
+) -> anyhow::Result<()> {
+    let headers = headers
+        .iter()
+        .filter_map(|(name, value)| Some(json!([name.as_str(), value.to_str().ok()?])))
+        .collect::<Vec<_>>();
+    // a TTL too long to be represented never expires
+    let ttl = i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX);
+    let expires_at = Utc::now().timestamp().saturating_add(ttl);
+    let value = json!({
+        "body": body,
+        "expires_at": expires_at,
+        "headers": headers,
+        "status_code": status_code.as_u16(),
+    });
+    store.put(key, &value)?;
+    Ok(())
+}
+
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    // weak comparison, ref: https://www.rfc-editor.org/rfc/rfc9110#section-13.1.2
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(|t| t.trim().trim_start_matches("W/"))
+        .any(|t| t == "*" || t == etag)
+}
+
+fn is_modified_since(if_modified_since: &str, last_modified: &str) -> bool {
+    let (Ok(since), Ok(modified)) = (
+        DateTime::parse_from_rfc2822(if_modified_since),
+        DateTime::parse_from_rfc2822(last_modified),
+    ) else {
+        return true;
+    };
+    modified > since
+}
+
+/// Add a strong entity tag to the response, and respond with 304 Not Modified
+/// when the conditions of the request are not met.
+fn evaluate_conditions(
+    method: &Method,
+    request_headers: &HeaderMap,
+    etag: bool,
+    res: (StatusCode, HeaderMap, String),
+) -> (StatusCode, HeaderMap, String) {
+    let (status_code, mut headers, body) = res;
+    if !(method == Method::GET || method == Method::HEAD) || status_code != StatusCode::OK {
+        return (status_code, headers, body);
+    }
+    if etag && !headers.contains_key(ETAG) {
+        let hash = Sha256::digest(body.as_bytes());
+        let mut value = hash.iter().fold(String::from("\""), |mut output, b| {
+            let _ = write!(output, "{b:02x}");
+            output
+        });
+        value.push('"');
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(ETAG, value);
+        }
+    }
+    let header = |headers: &HeaderMap, name| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let not_modified = match (
+        header(request_headers, IF_NONE_MATCH),
+        header(request_headers, IF_MODIFIED_SINCE),
+    ) {
+        // If-Modified-Since is ignored when If-None-Match is present
+        (Some(if_none_match), _) => {
+            header(&headers, ETAG).is_some_and(|etag| etag_matches(&if_none_match, &etag))
+        }
+        (None, Some(if_modified_since)) => header(&headers, LAST_MODIFIED)
+            .is_some_and(|modified| !is_modified_since(&if_modified_since, &modified)),
+        (None, None) => false,
+    };
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, headers, String::new());
+    }
+    (status_code, headers, body)
+}
+
+/// Information of the client connecting to the server.
+struct Client {
+    remote_addr: Option<SocketAddr>,
+    scheme: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Client
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    /// The remote address is unknown on Unix domain sockets. The scheme is HTTP unless the
+    /// request target is in absolute form.
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let remote_addr = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        let scheme = parts.uri.scheme_str().unwrap_or("http").to_string();
+        Ok(Self {
+            remote_addr,
+            scheme,
+        })
+    }
+}
+
 fn do_handle_request<S>(
     state: AppState,
+    client: Client,
     method: Method,
     path: S,
+    query: Option<String>,
     headers: HeaderMap,
     body: Bytes,
 ) -> impl IntoResponse
 where
     S: AsRef<str>,
 {
-    let e = EvaluationBuilder::new(state.script, Cursor::new(body))
+    let etag = state.etag;
+    let request_headers = headers.clone();
+    let res = do_evaluate(state, client, method.clone(), path, query, headers, body);
+    evaluate_conditions(&method, &request_headers, etag, res)
+}
+
+/// Decode the request body by the content type. JSON, URL-encoded forms, and texts are decoded.
+/// Repeated fields of a form are collected into an array.
+fn decode_body(headers: &HeaderMap, body: &[u8]) -> Option<Value> {
+    if body.is_empty() {
+        return None;
+    }
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let (kind, subtype) = mime.split_once('/')?;
+    if subtype == "json" || subtype.ends_with("+json") {
+        return match serde_json::from_slice(body) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                warn!(?err, "failed to decode JSON body");
+                None
+            }
+        };
+    }
+    if mime == "application/x-www-form-urlencoded" {
+        let mut form = Map::new();
+        for (name, value) in form_urlencoded::parse(body) {
+            let value = Value::from(value.into_owned());
+            match form.get_mut(name.as_ref()) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    form.insert(name.into_owned(), value);
+                }
+            }
+        }
+        return Some(form.into());
+    }
+    if kind == "text" {
+        return std::str::from_utf8(body).ok().map(Value::from);
+    }
+    None
+}
+
+fn do_evaluate<S>(
+    state: AppState,
+    client: Client,
+    method: Method,
+    path: S,
+    query: Option<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, HeaderMap, String)
+where
+    S: AsRef<str>,
+{
+    let live = state.live.read().clone();
+    // responses may differ by credentials, which are not part of the key
+    let credentials = headers.contains_key(AUTHORIZATION) || headers.contains_key(COOKIE);
+    let cache = state
+        .cache
+        .iter()
+        .find(|r| !credentials && r.method == method)
+        .map(|r| {
+            let key = cache_key(method.as_str(), path.as_ref(), query.as_deref(), &body);
+            (key, r.ttl)
+        });
+    if let Some((key, _)) = &cache {
+        if let Some(cached) = load_cached(live.store.as_ref(), key) {
+            return cached;
+        }
+    }
+
+    let decoded_body = if state.decode_body {
+        decode_body(&headers, &body)
+    } else {
+        None
+    };
+    let mut builder = EvaluationBuilder::new(state.script, Cursor::new(body));
+    #[cfg(feature = "protobuf")]
+    builder.proto_descriptors(state.proto_descriptors);
+    let e = builder
+        .app_state(state.app_state)
+        .catalog(state.catalog)
+        .deterministic(state.deterministic)
+        .gc(state.gc)
+        .max_input_bytes(state.max_input_bytes)
+        .max_instructions(state.max_instructions)
+        .memory_limit(state.memory_limit)
+        .metrics(state.metrics)
         .name(state.name)
-        .timeout(state.timeout)
-        .store(state.store.clone())
+        .permissions(live.permissions.clone())
+        .timeout(live.timeout)
+        .store(live.store.clone())
         .build();
+    let e = match e {
+        Ok(e) => e,
+        Err(err) => {
+            error!(%err, "failed to build the evaluation");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                HeaderMap::new(),
+                String::new(),
+            );
+        }
+    };
+
+    let session = state
+        .session
+        .as_ref()
+        .map(|s| s.load(live.store.as_ref(), &headers));
 
+    let request_id = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
     let mut headers_map: Map<_, Value> = Map::new();
     for (name, value) in headers {
         if let Some(name) = name {
@@ -94,17 +642,57 @@ where
     }
 
     let mut request_map: Map<_, Value> = Map::new();
+    if let Some(request_id) = request_id {
+        request_map.insert("id".into(), request_id.into());
+    }
     request_map.insert("method".into(), method.as_str().into());
     request_map.insert("path".into(), path.as_ref().into());
     request_map.insert("headers".into(), headers_map.into());
+    if let Some(remote_addr) = client.remote_addr {
+        request_map.insert("remote_addr".into(), remote_addr.to_string().into());
+    }
+    request_map.insert("scheme".into(), client.scheme.into());
+    if let Some(decoded_body) = decoded_body {
+        request_map.insert("body".into(), decoded_body);
+    }
 
-    let eval_state = Arc::new(State::new());
-    eval_state.insert(StateKey::Request, request_map.into());
+    let eval_state = Arc::new(InvocationState::new());
+    eval_state.set_request(request_map.into());
+    if let Some(session) = session.as_ref().filter(|s| !s.data().is_null()) {
+        eval_state.set_session(session.data().clone());
+    }
 
     let res = e.evaluate_with_state(eval_state.clone());
+    let cache_ttl = eval_state
+        .response()
+        .and_then(|res| res.get("cache_ttl").and_then(Value::as_u64))
+        .map(Duration::from_secs);
     match res {
-        Ok(res) => match build_response(state.json, eval_state, res.payload()) {
-            Ok(t) => t,
+        Ok(res) => match build_response(state.json, eval_state.clone(), res.results()) {
+            Ok((status_code, mut headers, body)) => {
+                if let (Some(options), Some(session)) = (&state.session, &session) {
+                    let data = eval_state.remove(&StateKey::Session).unwrap_or_default();
+                    match options.save(live.store.as_ref(), session, data) {
+                        Ok(Some(cookie)) => {
+                            headers.append(SET_COOKIE, cookie);
+                        }
+                        Ok(None) => {}
+                        Err(err) => error!(?err, "failed to save session"),
+                    }
+                }
+                let res = (status_code, headers, body);
+                if let Some((key, ttl)) = cache {
+                    let ttl = cache_ttl.unwrap_or(ttl);
+                    // responses setting cookies are specific to the client
+                    let cacheable = res.0.is_success() && !res.1.contains_key(SET_COOKIE);
+                    if cacheable && !ttl.is_zero() {
+                        if let Err(err) = save_cached(live.store.as_ref(), &key, ttl, &res) {
+                            error!(?err, "failed to cache response");
+                        }
+                    }
+                }
+                res
+            }
             Err(err) => {
                 error!(?err, "failed to build response");
                 (
@@ -115,6 +703,26 @@ where
             }
         },
         Err(err) => {
+            if let Some(http_error) = err.http_error() {
+                return build_error_response(http_error);
+            }
+            if let Error::MemoryLimitExceeded { limit } = err {
+                // each request has its own virtual machine, which is dropped with the evaluation
+                let recycled = state.recycled_vms.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    limit,
+                    recycled, "request exceeds the memory limit, recycle the VM"
+                );
+                return build_error_response(&HttpError::new(503, "memory limit exceeded"));
+            }
+            if let Some(limit) = err.input_limit() {
+                warn!(%limit, "request body exceeds the limit");
+                return (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    HeaderMap::new(),
+                    String::new(),
+                );
+            }
             error!(%err, "failed to run Lua script");
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -125,13 +733,24 @@ where
     }
 }
 
+fn build_error_response(http_error: &HttpError) -> (StatusCode, HeaderMap, String) {
+    let status_code =
+        StatusCode::from_u16(http_error.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let body = json!({ "status": status_code.as_u16(), "message": http_error.message() });
+    (status_code, headers, body.to_string())
+}
+
 fn build_response(
     json: bool,
-    state: Arc<State>,
-    value: &Value,
+    state: Arc<InvocationState>,
+    results: &[Value],
 ) -> anyhow::Result<(StatusCode, HeaderMap, String)> {
-    let (status_code, headers) = state
-        .view(&StateKey::Response, |_k, res| {
+    let value = results.first().unwrap_or(&Value::Null);
+    let (mut status_code, mut headers) = state.response().map_or_else(
+        || (200u64, HashMap::new()),
+        |res| {
             let status_code = res
                 .get("status_code")
                 .and_then(|s| s.as_u64())
@@ -140,17 +759,31 @@ fn build_response(
             if let Some(h) = res.get("headers").and_then(|h| h.as_object()) {
                 for (name, value) in h.iter() {
                     m.insert(
-                        name.to_string(),
+                        name.clone(),
                         match value {
-                            Value::String(s) => s.to_string(),
+                            Value::String(s) => s.clone(),
                             _ => value.to_string(),
                         },
                     );
                 }
             }
             (status_code, m)
-        })
-        .unwrap_or_else(|| (200u64, HashMap::new()));
+        },
+    );
+
+    // e.g. return body, 201, { location = '/users/1' }
+    if let Some(s) = results.get(1).and_then(Value::as_u64) {
+        status_code = s;
+    }
+    if let Some(h) = results.get(2).and_then(Value::as_object) {
+        for (name, value) in h.iter() {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                _ => value.to_string(),
+            };
+            headers.insert(name.clone(), value);
+        }
+    }
 
     let status_code = StatusCode::from_u16(u16::try_from(status_code)?)?;
     let mut header_map = HeaderMap::new();
@@ -161,7 +794,7 @@ fn build_response(
         serde_json::to_string(&value)?
     } else {
         match value {
-            Value::String(s) => s.to_string(),
+            Value::String(s) => s.clone(),
             _ => value.to_string(),
         }
     };
@@ -170,81 +803,463 @@ fn build_response(
 
 async fn index_route(
     AxumState(state): AxumState<AppState>,
+    client: Client,
     method: Method,
+    RawQuery(query): RawQuery,
     headers: HeaderMap,
     body: Bytes,
 ) -> impl IntoResponse {
-    do_handle_request(state, method, "/", headers, body)
+    do_handle_request(state, client, method, "/", query, headers, body)
 }
 
 async fn match_all_route(
     AxumState(state): AxumState<AppState>,
+    client: Client,
     method: Method,
     Path(path): Path<String>,
+    RawQuery(query): RawQuery,
     headers: HeaderMap,
     body: Bytes,
 ) -> impl IntoResponse {
     let path = format!("/{path}");
-    do_handle_request(state, method, path, headers, body)
+    do_handle_request(state, client, method, path, query, headers, body)
 }
 
-pub fn init_route<S, T>(opts: &ServeOptions<S, T>) -> anyhow::Result<Router>
-where
-    S: Display,
-    T: Display + ToSocketAddrs,
-{
-    let store = if let Some(path) = &opts.store_options.store_path() {
-        let store = Store::new(path.as_path())?;
-        if opts.store_options.run_migrations() {
+fn open_store(options: &StoreOptions) -> anyhow::Result<Arc<dyn StoreBackend>> {
+    if let Some(url) = options.store_url() {
+        let store = crate::open_store_url(url, options)?;
+        info!("open store with URL");
+        Ok(store)
+    } else if let Some(path) = &options.store_path() {
+        let mut store = Store::new(path.as_path())?;
+        if options.run_migrations() {
             store.migrate(None)?;
         }
+        options.apply(&mut store)?;
         info!(?path, "open store");
-        store
+        Ok(Arc::new(store))
     } else {
-        let store = Store::default();
+        let mut store = Store::default();
         warn!("no store path is specified, an in-memory store will be used and values will be lost when process ends");
-        store
+        options.apply(&mut store)?;
+        Ok(Arc::new(store))
+    }
+}
+
+/// Reload options from the config file, and keep the store if its path is unchanged.
+fn reload(
+    path: &std::path::Path,
+    overrides: &Config,
+    store_options: &StoreOptions,
+    live: &RwLock<Arc<LiveOptions>>,
+) -> anyhow::Result<()> {
+    let config = overrides.clone().or(Config::load(path)?);
+    let permissions = config.permissions()?;
+    let current = live.read().clone();
+    let store = if config.store_path == current.store_path {
+        current.store.clone()
+    } else {
+        let mut store_options = store_options.clone();
+        store_options.set_store_path(config.store_path.clone());
+        open_store(&store_options)?
     };
-    let app_state = AppState {
-        json: opts.json,
-        name: opts.name.to_string(),
-        script: opts.script.to_string(),
+    *live.write() = Arc::new(LiveOptions {
+        permissions,
         store,
+        store_path: config.store_path.clone(),
+        timeout: config.timeout(),
+    });
+    Ok(())
+}
+
+fn init_state<S>(opts: &ServeOptions<S>) -> anyhow::Result<AppState>
+where
+    S: Display,
+{
+    let live = LiveOptions {
+        permissions: opts.permissions.clone(),
+        store: open_store(&opts.store_options)?,
+        store_path: opts.store_options.store_path().clone(),
         timeout: opts.timeout,
     };
-    let app = Router::new()
+    Ok(app_state(
+        opts,
+        live,
+        opts.name.to_string(),
+        opts.script.to_string(),
+    ))
+}
+
+fn app_state<S>(opts: &ServeOptions<S>, live: LiveOptions, name: String, script: String) -> AppState
+where
+    S: Display,
+{
+    AppState {
+        app_state: opts.app_state.clone(),
+        cache: Arc::new(opts.cache.clone()),
+        catalog: opts.catalog.clone(),
+        decode_body: opts.decode_body,
+        deterministic: opts.deterministic,
+        etag: opts.etag,
+        gc: opts.gc.clone(),
+        json: opts.json,
+        live: Arc::new(RwLock::new(Arc::new(live))),
+        max_input_bytes: opts.max_input_bytes,
+        max_instructions: opts.max_instructions,
+        memory_limit: opts.memory_limit,
+        metrics: opts.metrics.clone().unwrap_or_default(),
+        name,
+        #[cfg(feature = "protobuf")]
+        proto_descriptors: opts.proto_descriptors.clone(),
+        recycled_vms: opts.recycled_vms.clone(),
+        script,
+        session: opts.session.clone(),
+    }
+}
+
+/// Route requests to functions of the manifest by path prefix. Functions share the store
+/// unless they specify their own store path, and values are isolated by namespace.
+/// Live options of functions are returned along with the router.
+fn manifest_router<S>(
+    opts: &ServeOptions<S>,
+    manifest: &Manifest,
+) -> anyhow::Result<(Router, Vec<SharedLiveOptions>)>
+where
+    S: Display,
+{
+    let shared = open_store(&opts.store_options)?;
+    let mut app = Router::new();
+    let mut lives = vec![];
+    for m in &manifest.apps {
+        let store = match &m.config.store_path {
+            Some(path) => {
+                let mut store_options = opts.store_options.clone();
+                store_options.set_store_path(Some(path.clone()));
+                open_store(&store_options)?
+            }
+            None => shared.clone(),
+        };
+        let live = LiveOptions {
+            permissions: m.config.permissions()?,
+            store: Arc::new(NamespacedStore::new(store, m.store_namespace())),
+            store_path: m.config.store_path.clone(),
+            timeout: m.config.timeout().or(opts.timeout),
+        };
+        let name = m.file.display().to_string();
+        let state = app_state(opts, live, name, m.script.clone());
+        lives.push(state.live.clone());
+        info!(prefix = m.prefix, file = ?m.file, "route to function");
+        // nesting at the root is not supported, so it serves unmatched paths instead
+        app = if m.prefix == "/" {
+            app.fallback_service(router(state))
+        } else {
+            app.nest(&m.prefix, router(state))
+        };
+    }
+    Ok((app, lives))
+}
+
+/// Generate IDs of requests in UUID version 7 format, which are ordered by time.
+#[derive(Clone, Copy)]
+struct MakeRequestUuidV7;
+
+impl MakeRequestId for MakeRequestUuidV7 {
+    fn make_request_id<B>(&mut self, _request: &http::Request<B>) -> Option<RequestId> {
+        let id = Uuid::now_v7().to_string();
+        HeaderValue::from_str(&id).ok().map(RequestId::new)
+    }
+}
+
+// the access log is written in the span, so it includes the request ID
+fn make_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id,
+    )
+}
+
+fn router(app_state: AppState) -> Router {
+    // requests with an ID e.g. from other services keep it, and the ID is sent back
+    let header = HeaderName::from_static(REQUEST_ID_HEADER);
+    Router::new()
         .route("/", any(index_route))
         .route("/*path", any(match_all_route))
+        .layer(PropagateRequestIdLayer::new(header.clone()))
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
+                .make_span_with(make_span)
                 .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
         )
-        .with_state(app_state);
-    Ok(app)
+        .layer(SetRequestIdLayer::new(header, MakeRequestUuidV7))
+        .with_state(app_state)
 }
 
-pub async fn serve_file<'a, S, T>(opts: &ServeOptions<S, T>) -> anyhow::Result<()>
+/// Serve metrics in the text exposition format of Prometheus,
+/// followed by the number of recycled virtual machines.
+fn metrics_router(metrics: Metrics, recycled_vms: Arc<AtomicU64>) -> Router {
+    let content_type = "text/plain; version=0.0.4; charset=utf-8";
+    Router::new().route(
+        "/metrics",
+        get(move || async move {
+            let mut body = metrics.render();
+            let recycled = recycled_vms.load(Ordering::Relaxed);
+            body.push_str(
+                "# HELP lmb_recycled_vms_total VMs recycled for exceeding the memory limit\n",
+            );
+            body.push_str("# TYPE lmb_recycled_vms_total counter\n");
+            body.push_str(&format!("lmb_recycled_vms_total {recycled}\n"));
+            ([(CONTENT_TYPE, content_type)], body)
+        }),
+    )
+}
+
+#[cfg(test)]
+pub fn init_route<S>(opts: &ServeOptions<S>) -> anyhow::Result<Router>
 where
     S: Display,
-    T: Display + ToSocketAddrs,
 {
-    let bind = &opts.bind;
-    let app = init_route(opts)?;
-    let listener = tokio::net::TcpListener::bind(&bind).await?;
-    info!(%bind, "serving lua script");
-    axum::serve(listener, app).await?;
+    Ok(router(init_state(opts)?))
+}
+
+#[cfg(unix)]
+fn watch_config<S>(opts: &ServeOptions<S>, live: SharedLiveOptions) -> anyhow::Result<()>
+where
+    S: Display,
+{
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Some((path, overrides)) = opts.config.clone() else {
+        return Ok(());
+    };
+    let store_options = opts.store_options.clone();
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match reload(&path, &overrides, &store_options, &live) {
+                Ok(()) => info!(?path, "config reloaded"),
+                Err(err) => error!(?err, "failed to reload config, keep the current one"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, mode: Option<u32>) -> anyhow::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt as _, PermissionsExt as _};
+
+    // remove the socket left by a previous process, but never a regular file
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(anyhow!("{} exists and is not a socket", path.display()));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+#[cfg(unix)]
+async fn serve_unix(listener: UnixListener, app: Router) -> anyhow::Result<()> {
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto::Builder,
+        service::TowerToHyperService,
+    };
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!(?err, "failed to accept connection");
+                continue;
+            }
+        };
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            if let Err(err) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(?err, "failed to serve connection");
+            }
+        });
+    }
+}
+
+pub async fn serve_file<S>(opts: &ServeOptions<S>) -> anyhow::Result<()>
+where
+    S: Display,
+{
+    let (app, lives) = if let Some(manifest) = &opts.manifest {
+        manifest_router(opts, manifest)?
+    } else {
+        let app_state = init_state(opts)?;
+        let live = app_state.live.clone();
+        #[cfg(unix)]
+        watch_config(opts, live.clone())?;
+        (router(app_state), vec![live])
+    };
+    let app = match &opts.metrics {
+        Some(metrics) => metrics_router(metrics.clone(), opts.recycled_vms.clone()).merge(app),
+        None => app,
+    };
+    if let Some(interval) = opts.maintenance_interval {
+        debug!(?interval, "run maintenance periodically");
+        maintenance::spawn(interval, move || {
+            lives.iter().map(|live| live.read().store.clone()).collect()
+        });
+    }
+    let mut servers = JoinSet::new();
+    for bind in &opts.bind {
+        match bind {
+            BindAddress::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                let app = app
+                    .clone()
+                    .into_make_service_with_connect_info::<SocketAddr>();
+                servers.spawn(async move { Ok(axum::serve(listener, app).await?) });
+            }
+            #[cfg(unix)]
+            BindAddress::Unix(path) => {
+                let listener = bind_unix(path, opts.socket_mode)?;
+                servers.spawn(serve_unix(listener, app.clone()));
+            }
+            #[cfg(not(unix))]
+            BindAddress::Unix(_) => return Err(anyhow!("Unix domain socket is not supported")),
+        }
+        info!(%bind, "serving lua script");
+    }
+    // servers run until any of them fails
+    while let Some(res) = servers.join_next().await {
+        res??;
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::init_route;
-    use crate::{serve::ServeOptions, Cli, StoreOptions};
+    use super::{
+        init_route, init_state, load_cached, manifest_router, metrics_router, reload, router,
+        save_cached, serve_file, BindAddress, CacheRule,
+    };
+    use crate::{
+        config::{Config, Manifest},
+        serve::ServeOptions,
+        session::{SessionOptions, DEFAULT_SESSION_TTL},
+        Cli, StoreOptions,
+    };
     use axum_test::TestServer;
     use clap::Parser;
-    use http::HeaderValue;
+    use http::{
+        header::{COOKIE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH},
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    };
+    use lmb::{MemoryStore, Metrics};
     use serde_json::{json, Value};
+    use std::{net::SocketAddr, sync::atomic::Ordering, time::Duration};
+    use test_case::test_case;
+
+    #[tokio::test]
+    async fn app_state() {
+        let script = r#"
+        local m = require('@lmb')
+        m.state.count += 1
+        table.insert(m.state.items, io.read('*a'))
+        local ok = pcall(function() m.app_state.count = 100 end)
+        return { count = m.state.count, items = m.state.items, ok = ok, shared = m.app_state.count }
+        "#;
+        let mut opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        opts.set_app_state(Some(json!({ "count": 0, "items": [] })));
+        opts.set_json(true);
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        for body in ["a", "b"] {
+            let res = server.post("/").text(body).await;
+            assert_eq!(200, res.status_code());
+            let value: Value = serde_json::from_str(&res.text()).unwrap();
+            let expected = json!({ "count": 1, "items": [body], "ok": false, "shared": 0 });
+            assert_eq!(expected, value);
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let script = r#"
+        require('@lmb/prometheus'):counter('requests'):inc(1, { path = io.read('*a') })
+        return 'ok'
+        "#;
+        let metrics = Metrics::new();
+        let mut opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        opts.set_metrics(Some(metrics.clone()));
+        let recycled = opts.recycled_vms.clone();
+        let router = metrics_router(metrics, recycled).merge(init_route(&opts).unwrap());
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        server.post("/").text("a").await;
+        server.post("/other").text("a").await;
+        let res = server.get("/metrics").await;
+        assert_eq!(200, res.status_code());
+        assert_eq!(
+            concat!(
+                "# TYPE requests counter\nrequests{path=\"a\"} 2\n",
+                "# HELP lmb_recycled_vms_total VMs recycled for exceeding the memory limit\n",
+                "# TYPE lmb_recycled_vms_total counter\nlmb_recycled_vms_total 0\n",
+            ),
+            res.text()
+        );
+    }
+
+    #[tokio::test]
+    async fn max_input_bytes() {
+        let script = "return io.read('*a')";
+        let mut opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        opts.set_max_input_bytes(Some(4));
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        let res = server.post("/").text("1234").await;
+        assert_eq!(200, res.status_code());
+        assert_eq!("1234", res.text());
+        let res = server.post("/").text("12345").await;
+        assert_eq!(413, res.status_code());
+    }
+
+    #[tokio::test]
+    async fn memory_limit() {
+        let script = r#"
+        if io.read('*a') == 'big' then
+          local t = {}
+          for i = 1, 1e7 do t[i] = string.rep('a', 64) .. i end
+        end
+        return 'ok'
+        "#;
+        let mut opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        opts.set_memory_limit(Some(4 * 1024 * 1024));
+        let recycled = opts.recycled_vms.clone();
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        let res = server.post("/").text("big").await;
+        assert_eq!(503, res.status_code());
+        let value: Value = serde_json::from_str(&res.text()).unwrap();
+        assert_eq!(
+            json!({ "status": 503, "message": "memory limit exceeded" }),
+            value
+        );
+        assert_eq!(1, recycled.load(Ordering::Relaxed));
+        let res = server.post("/").text("small").await;
+        assert_eq!(200, res.status_code());
+        assert_eq!("ok", res.text());
+    }
 
     #[tokio::test]
     async fn echo_request() {
@@ -254,7 +1269,7 @@ mod tests {
         return { request = m.request, body = io.read('*a') }
         "#;
         let store_options = StoreOptions::default();
-        let mut opts = ServeOptions::new("", script, "", store_options);
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
         opts.set_json(cli.json);
 
         let router = init_route(&opts).unwrap();
@@ -263,19 +1278,104 @@ mod tests {
         assert_eq!(200, res.status_code());
 
         let value: Value = serde_json::from_str(&res.text()).unwrap();
+        let request_id = value["request"]["id"].as_str().unwrap();
         let expected = json!({
             "body": r#"{"a":1}"#,
             "request": {
+                "body": { "a": 1 },
                 "headers": {
                     "content-type": "application/json",
+                    "x-request-id": request_id,
                 },
+                "id": request_id,
                 "method": "POST",
                 "path": "/foo/bar/baz",
+                "scheme": "http",
             },
         });
         assert_eq!(expected, value);
     }
 
+    #[tokio::test]
+    async fn request_id() {
+        let script = "return require('@lmb').request.id";
+        let opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+
+        let res = server.get("/").await;
+        let request_id = res.header("x-request-id");
+        let request_id = request_id.to_str().unwrap();
+        assert_eq!(request_id, res.text());
+        assert_eq!(
+            7,
+            uuid::Uuid::parse_str(request_id).unwrap().get_version_num()
+        );
+
+        let res = server
+            .get("/")
+            .add_header(
+                HeaderName::from_static("x-request-id"),
+                HeaderValue::from_static("upstream"),
+            )
+            .await;
+        assert_eq!("upstream", res.header("x-request-id"));
+        assert_eq!("upstream", res.text());
+    }
+
+    #[tokio::test]
+    async fn client_info() {
+        let script = r#"
+        local m = require('@lmb')
+        return { remote_addr = m.request.remote_addr, scheme = m.request.scheme }
+        "#;
+        let mut opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        opts.set_json(true);
+        let router = init_route(&opts).unwrap();
+        let server =
+            TestServer::new(router.into_make_service_with_connect_info::<SocketAddr>()).unwrap();
+        let value = server.get("/").await.json::<Value>();
+        let remote_addr = value["remote_addr"].as_str().unwrap();
+        let remote_addr = remote_addr.parse::<SocketAddr>().unwrap();
+        assert!(remote_addr.ip().is_loopback());
+        assert_eq!(json!("http"), value["scheme"]);
+    }
+
+    #[test_case("application/json", r#"{"a":1}"#, json!({ "a": 1 }))]
+    #[test_case("application/vnd.api+json; charset=utf-8", "[1]", json!([1]))]
+    #[test_case("application/json", "{", json!(null); "invalid json")]
+    #[test_case("application/x-www-form-urlencoded", "a=1&b=x+y&a=2", json!({ "a": ["1", "2"], "b": "x y" }))]
+    #[test_case("text/plain", "hello", json!("hello"))]
+    #[test_case("application/octet-stream", "hello", json!(null))]
+    #[tokio::test]
+    async fn decode_body(content_type: &str, body: &str, expected: Value) {
+        let script = r#"
+        local m = require('@lmb')
+        return { body = m.request.body, raw = io.read('*a') }
+        "#;
+        let mut opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        opts.set_json(true);
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        let res = server.post("/").text(body).content_type(content_type).await;
+        assert_eq!(200, res.status_code());
+        let value: Value = serde_json::from_str(&res.text()).unwrap();
+        assert_eq!(&expected, value.get("body").unwrap_or(&Value::Null));
+        assert_eq!(&json!(body), value.get("raw").unwrap());
+    }
+
+    #[tokio::test]
+    async fn decode_body_disabled() {
+        let script = "return require('@lmb').request.body";
+        let mut opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        opts.set_decode_body(false);
+        opts.set_json(true);
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        let res = server.post("/").json(&json!({ "a": 1 })).await;
+        assert_eq!("null", res.text());
+    }
+
     #[tokio::test]
     async fn headers_status_code() {
         let cli = Cli::parse_from(["lmb", "serve", "--file", "-"]);
@@ -290,7 +1390,7 @@ mod tests {
         return "I'm a teapot."
         "#;
         let store_options = StoreOptions::default();
-        let mut opts = ServeOptions::new("", script, "", store_options);
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
         opts.set_json(cli.json);
         let router = init_route(&opts).unwrap();
         let server = TestServer::new(router.into_make_service()).unwrap();
@@ -303,12 +1403,36 @@ mod tests {
         assert_eq!("I'm a teapot.", res.text());
     }
 
+    #[tokio::test]
+    async fn headers_status_code_returned() {
+        let script = r#"
+        local m = require('@lmb')
+        m.response = { headers = { whoami = 'a teapot' } }
+        return 'created', 201, { location = '/users/1' }
+        "#;
+        let store_options = StoreOptions::default();
+        let opts = ServeOptions::new("", script, vec![], store_options);
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        let res = server.post("/").await;
+        assert_eq!(201, res.status_code());
+        assert_eq!(
+            HeaderValue::from_static("/users/1"),
+            res.headers().get("location").unwrap()
+        );
+        assert_eq!(
+            HeaderValue::from_static("a teapot"),
+            res.headers().get("whoami").unwrap()
+        );
+        assert_eq!("created", res.text());
+    }
+
     #[tokio::test]
     async fn headers_status_code_bad_script() {
         let cli = Cli::parse_from(["lmb", "serve", "--file", "-"]);
         let script = "ret 'hello'";
         let store_options = StoreOptions::default();
-        let mut opts = ServeOptions::new("", script, "", store_options);
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
         opts.set_json(cli.json);
         let router = init_route(&opts).unwrap();
         let server = TestServer::new(router.into_make_service()).unwrap();
@@ -328,7 +1452,7 @@ mod tests {
         return "hello"
         "#;
         let store_options = StoreOptions::default();
-        let mut opts = ServeOptions::new("", script, "", store_options);
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
         opts.set_json(cli.json);
         let router = init_route(&opts).unwrap();
         let server = TestServer::new(router.into_make_service()).unwrap();
@@ -337,12 +1461,30 @@ mod tests {
         assert_eq!("", res.text());
     }
 
+    #[test_case("require('@lmb'):http_error(404, 'not found')", 404, json!({ "status": 404, "message": "not found" }))]
+    #[test_case("local m = require('@lmb'); pcall(function() m:http_error(401, 'a') end); m:http_error(403, 'b')", 403, json!({ "status": 403, "message": "b" }))]
+    #[test_case("require('@lmb'):http_error(200, 'ok')", 500, json!(null); "not error status")]
+    #[tokio::test]
+    async fn http_error(script: &'static str, status_code: u16, expected: Value) {
+        let store_options = StoreOptions::default();
+        let opts = ServeOptions::new("", script, vec![], store_options);
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        let res = server.post("/").await;
+        assert_eq!(status_code, res.status_code());
+        if expected.is_null() {
+            assert_eq!("", res.text());
+        } else {
+            assert_eq!(expected, res.json::<Value>());
+        }
+    }
+
     #[tokio::test]
     async fn json_string() {
         let cli = Cli::parse_from(["lmb", "--json", "serve", "--file", "-"]);
         let script = "return 'hello'";
         let store_options = StoreOptions::default();
-        let mut opts = ServeOptions::new("", script, "", store_options);
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
         opts.set_json(cli.json);
         let router = init_route(&opts).unwrap();
         let server = TestServer::new(router.into_make_service()).unwrap();
@@ -356,7 +1498,7 @@ mod tests {
         let cli = Cli::parse_from(["lmb", "serve", "--file", "-"]);
         let script = r#"return 1"#;
         let store_options = StoreOptions::default();
-        let mut opts = ServeOptions::new("", script, "", store_options);
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
         opts.set_json(cli.json);
         let router = init_route(&opts).unwrap();
         let server = TestServer::new(router.into_make_service()).unwrap();
@@ -370,7 +1512,7 @@ mod tests {
         let cli = Cli::parse_from(["lmb", "serve", "--file", "-"]);
         let script = "return 'hello'";
         let store_options = StoreOptions::default();
-        let mut opts = ServeOptions::new("", script, "", store_options);
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
         opts.set_json(cli.json);
         let router = init_route(&opts).unwrap();
         let server = TestServer::new(router.into_make_service()).unwrap();
@@ -384,7 +1526,7 @@ mod tests {
         let cli = Cli::parse_from(["lmb", "--json", "serve", "--file", "-"]);
         let script = "return 1";
         let store_options = StoreOptions::default();
-        let mut opts = ServeOptions::new("", script, "", store_options);
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
         opts.set_json(cli.json);
         let router = init_route(&opts).unwrap();
         let server = TestServer::new(router.into_make_service()).unwrap();
@@ -392,4 +1534,312 @@ mod tests {
         assert_eq!(200, res.status_code());
         assert_eq!("1", res.text());
     }
+
+    #[tokio::test]
+    async fn session() {
+        let script = r#"
+        local m = require('@lmb')
+        local session = m.session or {}
+        session.count = (session.count or 0) + 1
+        m.session = session
+        return session.count
+        "#;
+        let store_options = StoreOptions::default();
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
+        opts.set_session(Some(SessionOptions::new("secret", DEFAULT_SESSION_TTL)));
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+
+        let res = server.post("/").await;
+        assert_eq!("1", res.text());
+        let cookie = res.cookie("lmb_session");
+
+        let res = server.post("/").add_cookie(cookie.clone()).await;
+        assert_eq!("2", res.text());
+
+        let mut tampered = cookie.clone();
+        tampered.set_value(format!("x{}", cookie.value()));
+        let res = server.post("/").add_cookie(tampered).await;
+        assert_eq!("1", res.text());
+    }
+
+    #[tokio::test]
+    async fn cache() {
+        let script = r#"
+        local m = require('@lmb')
+        local count = m:update('count', function(v) return v + 1 end, 0)
+        if m.request.path == '/no-cache' then
+          m.response = { cache_ttl = 0 }
+        elseif m.request.path == '/invalidate' then
+          m:invalidate_cache('/')
+        end
+        return count
+        "#;
+        let store_options = StoreOptions::default();
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
+        opts.set_cache(vec!["GET:60s".parse().unwrap()]);
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+
+        let res = server.get("/").await;
+        assert_eq!("1", res.text());
+        let res = server.get("/").await;
+        assert_eq!("1", res.text());
+        assert_eq!("hit", res.header("x-lmb-cache"));
+        let res = server.get("/").add_query_param("a", "b").await;
+        assert_eq!("2", res.text());
+        let res = server.post("/").await;
+        assert_eq!("3", res.text());
+
+        let res = server.get("/no-cache").await;
+        assert_eq!("4", res.text());
+        let res = server.get("/no-cache").await;
+        assert_eq!("5", res.text());
+
+        // requests with credentials are neither served from nor saved to the cache
+        let res = server.get("/").authorization_bearer("token").await;
+        assert_eq!("6", res.text());
+        let res = server
+            .get("/")
+            .add_header(COOKIE, HeaderValue::from_static("a=b"))
+            .await;
+        assert_eq!("7", res.text());
+        let res = server.get("/").await;
+        assert_eq!("1", res.text());
+
+        let res = server.post("/invalidate").await;
+        assert_eq!("8", res.text());
+        let res = server.get("/").await;
+        assert_eq!("9", res.text());
+    }
+
+    #[test]
+    fn cache_long_ttl() {
+        let store = MemoryStore::default();
+        let res = (StatusCode::OK, HeaderMap::new(), "a".to_string());
+        save_cached(&store, "a", Duration::from_secs(u64::MAX), &res).unwrap();
+        let (status_code, _, body) = load_cached(&store, "a").unwrap();
+        assert_eq!(StatusCode::OK, status_code);
+        assert_eq!("a", body);
+    }
+
+    #[test_case("GET:60s", Method::GET, 60)]
+    #[test_case("get:5m", Method::GET, 300)]
+    #[test_case("HEAD:1h", Method::HEAD, 3600)]
+    #[test_case("GET:1d", Method::GET, 86400)]
+    #[test_case("GET:10", Method::GET, 10)]
+    fn cache_rule(s: &str, method: Method, secs: u64) {
+        let rule: CacheRule = s.parse().unwrap();
+        assert_eq!(method, rule.method);
+        assert_eq!(Duration::from_secs(secs), rule.ttl);
+    }
+
+    #[test_case("GET")]
+    #[test_case("GET:1w")]
+    #[test_case("GET:s")]
+    #[test_case("GET:18446744073709551615m")]
+    #[test_case("GET:18446744073709551615d")]
+    fn cache_rule_invalid(s: &str) {
+        assert!(s.parse::<CacheRule>().is_err());
+    }
+
+    #[tokio::test]
+    async fn etag() {
+        let script = r#"
+        local m = require('@lmb')
+        if m.request.path == '/last-modified' then
+          m.response = { headers = { ['last-modified'] = 'Wed, 21 Oct 2015 07:28:00 GMT' } }
+        end
+        return 'hello'
+        "#;
+        let store_options = StoreOptions::default();
+        let opts = ServeOptions::new("", script, vec![], store_options);
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+
+        let res = server.get("/").await;
+        assert_eq!(200, res.status_code());
+        assert_eq!("hello", res.text());
+        let etag = res.header(ETAG);
+
+        let res = server
+            .get("/")
+            .add_header(IF_NONE_MATCH, etag.clone())
+            .await;
+        assert_eq!(304, res.status_code());
+        assert_eq!("", res.text());
+
+        let stale = HeaderValue::from_static("\"stale\"");
+        let res = server.get("/").add_header(IF_NONE_MATCH, stale).await;
+        assert_eq!(200, res.status_code());
+
+        let res = server.post("/").add_header(IF_NONE_MATCH, etag).await;
+        assert_eq!(200, res.status_code());
+
+        let since = HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT");
+        let res = server
+            .get("/last-modified")
+            .add_header(IF_MODIFIED_SINCE, since)
+            .await;
+        assert_eq!(304, res.status_code());
+
+        let since = HeaderValue::from_static("Tue, 20 Oct 2015 07:28:00 GMT");
+        let res = server
+            .get("/last-modified")
+            .add_header(IF_MODIFIED_SINCE, since)
+            .await;
+        assert_eq!(200, res.status_code());
+    }
+
+    #[tokio::test]
+    async fn etag_disabled() {
+        let script = "return 'hello'";
+        let store_options = StoreOptions::default();
+        let mut opts = ServeOptions::new("", script, vec![], store_options);
+        opts.set_etag(false);
+        let router = init_route(&opts).unwrap();
+        let server = TestServer::new(router.into_make_service()).unwrap();
+        let res = server.get("/").await;
+        assert!(res.maybe_header(ETAG).is_none());
+    }
+
+    #[test_case("\"a\"", "\"a\"", true)]
+    #[test_case("\"a\", \"b\"", "\"b\"", true)]
+    #[test_case("W/\"a\"", "\"a\"", true)]
+    #[test_case("*", "\"a\"", true)]
+    #[test_case("\"b\"", "\"a\"", false)]
+    fn etag_matches(if_none_match: &str, etag: &str, expected: bool) {
+        assert_eq!(expected, super::etag_matches(if_none_match, etag));
+    }
+
+    #[test_case("127.0.0.1:3000", "127.0.0.1:3000")]
+    #[test_case("[::1]:3000", "[::1]:3000")]
+    #[test_case("unix:/run/lmb.sock", "unix:/run/lmb.sock")]
+    fn bind_address(s: &str, expected: &str) {
+        assert_eq!(expected, s.parse::<BindAddress>().unwrap().to_string());
+    }
+
+    #[test]
+    fn bind_address_without_path() {
+        assert!("unix:".parse::<BindAddress>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serve_unix_socket() {
+        use std::{
+            io::{Read as _, Write as _},
+            os::unix::{fs::PermissionsExt as _, net::UnixStream},
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lmb.sock");
+        let binds = vec![
+            BindAddress::Unix(path.clone()),
+            BindAddress::Tcp("127.0.0.1:0".to_string()),
+        ];
+        let mut opts = ServeOptions::new("", "return 'ok'", binds, StoreOptions::default());
+        opts.set_socket_mode(Some(0o600));
+        let server = tokio::spawn(async move { serve_file(&opts).await });
+
+        let res = tokio::task::spawn_blocking(move || {
+            while !path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            let mut stream = UnixStream::connect(&path).unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+                .unwrap();
+            let mut res = String::new();
+            stream.read_to_string(&mut res).unwrap();
+            (mode & 0o777, res)
+        })
+        .await
+        .unwrap();
+        server.abort();
+
+        assert_eq!(0o600, res.0);
+        assert!(res.1.starts_with("HTTP/1.1 200 OK"), "{}", res.1);
+        assert!(res.1.ends_with("ok"), "{}", res.1);
+    }
+
+    #[tokio::test]
+    async fn reload_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("lmb.toml");
+        let env_path = dir.path().join(".env");
+        let write_config = |value: &str| {
+            let config = format!(
+                "allow_env = [\"GREETING\"]\nenv_file = {:?}\ntimeout = 1",
+                env_path.to_string_lossy()
+            );
+            std::fs::write(&config_path, config).unwrap();
+            std::fs::write(&env_path, format!("GREETING={value}")).unwrap();
+        };
+        write_config("hello");
+
+        let script = "return require('@lmb').env.GREETING";
+        let overrides = Config::default();
+        let config = Config::load(&config_path).unwrap();
+        let mut opts = ServeOptions::new("", script, vec![], StoreOptions::default());
+        opts.set_permissions(config.permissions().unwrap());
+        opts.set_config(config_path.clone(), overrides.clone());
+        let state = init_state(&opts).unwrap();
+        let live = state.live.clone();
+        let server = TestServer::new(router(state).into_make_service()).unwrap();
+        assert_eq!("hello", server.get("/").await.text());
+
+        write_config("world");
+        reload(&config_path, &overrides, &StoreOptions::default(), &live).unwrap();
+        assert_eq!(Some(Duration::from_secs(1)), live.read().timeout);
+        assert_eq!("world", server.get("/").await.text());
+
+        // an invalid config keeps the current one
+        std::fs::write(&config_path, "timeout = 'invalid'").unwrap();
+        let res = reload(&config_path, &overrides, &StoreOptions::default(), &live);
+        assert!(res.is_err());
+        assert_eq!("world", server.get("/").await.text());
+    }
+
+    #[tokio::test]
+    async fn manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+        local m = require('@lmb')
+        local n = m:update('n', function(n) return n + 1 end, 0)
+        return { n = n, path = m.request.path }
+        "#;
+        std::fs::write(dir.path().join("a.lua"), script).unwrap();
+        std::fs::write(dir.path().join("b.lua"), script).unwrap();
+        let manifest_path = dir.path().join("apps.toml");
+        let manifest = r#"
+        [[app]]
+        prefix = "/a/"
+        file = "a.lua"
+
+        [[app]]
+        prefix = "/"
+        file = "b.lua"
+        "#;
+        std::fs::write(&manifest_path, manifest).unwrap();
+
+        let mut opts = ServeOptions::new("", "", vec![], StoreOptions::default());
+        opts.set_json(true);
+        opts.set_manifest(Some(Manifest::load(&manifest_path).unwrap()));
+        let server = TestServer::new(
+            manifest_router(&opts, opts.manifest.as_ref().unwrap())
+                .unwrap()
+                .0
+                .into_make_service(),
+        )
+        .unwrap();
+
+        let res = server.get("/a/foo").await;
+        assert_eq!(json!({ "n": 1, "path": "/foo" }), res.json::<Value>());
+        let res = server.get("/a").await;
+        assert_eq!(json!({ "n": 2, "path": "/" }), res.json::<Value>());
+        let res = server.get("/b/foo").await;
+        assert_eq!(json!({ "n": 1, "path": "/b/foo" }), res.json::<Value>());
+    }
 }
@@ -0,0 +1,241 @@
+use aes_gcm::aead::{rand_core::RngCore as _, OsRng};
+use anyhow::anyhow;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use http::{header::COOKIE, HeaderMap, HeaderValue};
+use lmb::StoreBackend;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::{fmt::Write as _, time::Duration};
+use tracing::{debug, warn};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Name of the cookie holding the signed session ID.
+const COOKIE_NAME: &str = "lmb_session";
+
+/// Prefix of store keys holding sessions, under [`lmb::RESERVED_KEY_PREFIX`].
+pub const KEY_PREFIX: &str = "__lmb:session:";
+
+/// Default time-to-live of sessions.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Maximum time-to-live of sessions, since browsers cap `Max-Age` of cookies at 400 days.
+pub const MAX_SESSION_TTL: Duration = Duration::from_secs(400 * 24 * 60 * 60);
+
+/// Options of cookie-based sessions persisted in the store.
+#[derive(Clone)]
+pub struct SessionOptions {
+    secret: String,
+    ttl: Duration,
+}
+
+/// Session loaded before the handler.
+#[derive(Debug)]
+pub struct Session {
+    data: Value,
+    id: String,
+    is_new: bool,
+}
+
+impl Session {
+    /// Get data of the session, which is null for a new session.
+    pub fn data(&self) -> &Value {
+        &self.data
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut output, b| {
+        let _ = write!(output, "{b:02x}");
+        output
+    })
+}
+
+fn find_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, value)| value)
+}
+
+impl SessionOptions {
+    /// Create session options with the secret to sign session IDs.
+    pub fn new<S>(secret: S, ttl: Duration) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            secret: secret.into(),
+            ttl,
+        }
+    }
+
+    fn mac(&self, id: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(id.as_bytes());
+        mac
+    }
+
+    fn sign(&self, id: &str) -> String {
+        let signature = self.mac(id).finalize().into_bytes();
+        format!("{id}.{}", to_hex(&signature))
+    }
+
+    fn verify<'a>(&self, cookie: &'a str) -> Option<&'a str> {
+        let (id, signature) = cookie.split_once('.')?;
+        let signature = (0..signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        self.mac(id).verify_slice(&signature).ok()?;
+        Some(id)
+    }
+
+    /// Load the session of the client. A new session is started when the cookie is absent,
+    /// the signature is invalid, or the session expires.
+    pub fn load(&self, store: &dyn StoreBackend, headers: &HeaderMap) -> Session {
+        let id = find_cookie(headers).and_then(|c| self.verify(c));
+        if let Some(id) = id {
+            match store.get(&format!("{KEY_PREFIX}{id}")) {
+                Ok(value) => {
+                    let expires_at = value.get("expires_at").and_then(Value::as_i64);
+                    if expires_at.is_some_and(|t| t > Utc::now().timestamp()) {
+                        return Session {
+                            data: value.get("data").cloned().unwrap_or(Value::Null),
+                            id: id.to_string(),
+                            is_new: false,
+                        };
+                    }
+                    debug!(id, "session expired or not found");
+                }
+                Err(err) => warn!(?err, "failed to load session"),
+            }
+        }
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+        Session {
+            data: Value::Null,
+            id: to_hex(&bytes),
+            is_new: true,
+        }
+    }
+
+    /// Persist the session after the handler, and return the cookie to be set if any.
+    /// The session is destroyed when the data is null.
+    pub fn save(
+        &self,
+        store: &dyn StoreBackend,
+        session: &Session,
+        data: Value,
+    ) -> anyhow::Result<Option<HeaderValue>> {
+        let key = format!("{KEY_PREFIX}{}", session.id);
+        if data.is_null() {
+            if session.is_new {
+                return Ok(None);
+            }
+            store.delete(&key)?;
+            let cookie = format!("{COOKIE_NAME}=; Max-Age=0; Path=/; HttpOnly; SameSite=Lax");
+            return Ok(Some(HeaderValue::from_str(&cookie)?));
+        }
+        let ttl = self.ttl.as_secs();
+        let expires_at = Utc::now()
+            .timestamp()
+            .checked_add(i64::try_from(ttl)?)
+            .ok_or_else(|| anyhow!("session TTL {ttl} is too long"))?;
+        store.put(&key, &json!({ "data": data, "expires_at": expires_at }))?;
+        let cookie = format!(
+            "{COOKIE_NAME}={}; Max-Age={ttl}; Path=/; HttpOnly; SameSite=Lax",
+            self.sign(&session.id)
+        );
+        Ok(Some(HeaderValue::from_str(&cookie)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header::COOKIE, HeaderMap, HeaderValue};
+    use lmb::{MemoryStore, StoreBackend};
+    use serde_json::json;
+    use std::time::Duration;
+
+    use super::{SessionOptions, DEFAULT_SESSION_TTL};
+
+    fn cookie_headers(cookie: &HeaderValue) -> HeaderMap {
+        let pair = cookie.to_str().unwrap().split(';').next().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(pair).unwrap());
+        headers
+    }
+
+    #[test]
+    fn long_ttl() {
+        let store = MemoryStore::default();
+        let options = SessionOptions::new("secret", Duration::from_secs(u64::MAX));
+        let session = options.load(&store, &HeaderMap::new());
+        assert!(options.save(&store, &session, json!(1)).is_err());
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn destroy() {
+        let store = MemoryStore::default();
+        let options = SessionOptions::new("secret", DEFAULT_SESSION_TTL);
+        let session = options.load(&store, &HeaderMap::new());
+        let cookie = options.save(&store, &session, json!(1)).unwrap().unwrap();
+
+        let session = options.load(&store, &cookie_headers(&cookie));
+        let cookie = options
+            .save(&store, &session, json!(null))
+            .unwrap()
+            .unwrap();
+        assert!(cookie.to_str().unwrap().contains("Max-Age=0"));
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired() {
+        let store = MemoryStore::default();
+        let options = SessionOptions::new("secret", Duration::ZERO);
+        let session = options.load(&store, &HeaderMap::new());
+        let cookie = options.save(&store, &session, json!(1)).unwrap().unwrap();
+
+        let session = options.load(&store, &cookie_headers(&cookie));
+        assert_eq!(&json!(null), session.data());
+    }
+
+    #[test]
+    fn load_and_save() {
+        let store = MemoryStore::default();
+        let options = SessionOptions::new("secret", DEFAULT_SESSION_TTL);
+
+        let session = options.load(&store, &HeaderMap::new());
+        assert_eq!(&json!(null), session.data());
+        let cookie = options.save(&store, &session, json!({ "a": 1 })).unwrap();
+        let cookie = cookie.unwrap();
+
+        let session = options.load(&store, &cookie_headers(&cookie));
+        assert_eq!(&json!({ "a": 1 }), session.data());
+
+        let other = SessionOptions::new("other", DEFAULT_SESSION_TTL);
+        let session = other.load(&store, &cookie_headers(&cookie));
+        assert_eq!(&json!(null), session.data());
+    }
+
+    #[test]
+    fn unchanged_new_session() {
+        let store = MemoryStore::default();
+        let options = SessionOptions::new("secret", DEFAULT_SESSION_TTL);
+        let session = options.load(&store, &HeaderMap::new());
+        assert!(options
+            .save(&store, &session, json!(null))
+            .unwrap()
+            .is_none());
+        assert!(store.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+use once_cell::sync::{Lazy, OnceCell};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{Error, Result};
+
+static INTERRUPT: Lazy<Arc<AtomicBool>> = Lazy::new(Arc::default);
+static TERMINATE: Lazy<Arc<AtomicBool>> = Lazy::new(Arc::default);
+static LISTENING: OnceCell<()> = OnceCell::new();
+
+/// Signals which scripts can observe with `@lmb/signal`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGINT, "int"
+    Interrupt,
+    /// SIGTERM, "term"
+    Terminate,
+}
+
+impl Signal {
+    fn flag(self) -> &'static Arc<AtomicBool> {
+        match self {
+            Self::Interrupt => &INTERRUPT,
+            Self::Terminate => &TERMINATE,
+        }
+    }
+
+    fn id(self) -> i32 {
+        match self {
+            Self::Interrupt => SIGINT,
+            Self::Terminate => SIGTERM,
+        }
+    }
+}
+
+impl Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Interrupt => write!(f, "int"),
+            Self::Terminate => write!(f, "term"),
+        }
+    }
+}
+
+impl FromStr for Signal {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().trim_start_matches("sig") {
+            "int" => Ok(Self::Interrupt),
+            "term" => Ok(Self::Terminate),
+            _ => Err(Error::InvalidSignal(s.to_string())),
+        }
+    }
+}
+
+/// Listen to SIGINT and SIGTERM. Once listening, the process is no longer terminated
+/// by the first signal, which is remembered for [`signal_received`] instead, so scripts
+/// can exit cleanly. The process is still terminated by the second one.
+/// It's a no-op when already listening.
+pub fn listen_signals() -> Result<()> {
+    LISTENING.get_or_try_init(|| {
+        for signal in [Signal::Interrupt, Signal::Terminate] {
+            let flag = signal.flag();
+            // terminate if the flag has been set by the first signal
+            signal_hook::flag::register_conditional_shutdown(
+                signal.id(),
+                128 + signal.id(),
+                flag.clone(),
+            )?;
+            signal_hook::flag::register(signal.id(), flag.clone())?;
+        }
+        Ok::<_, Error>(())
+    })?;
+    Ok(())
+}
+
+/// Whether signals are listened to by [`listen_signals`].
+pub fn is_listening_signals() -> bool {
+    LISTENING.get().is_some()
+}
+
+/// Whether the signal has been received since [`listen_signals`].
+///
+/// ```rust
+/// use lmb::*;
+///
+/// assert!(!signal_received(Signal::Terminate));
+/// ```
+pub fn signal_received(signal: Signal) -> bool {
+    signal.flag().load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::Signal;
+
+    #[test_case("int", Some(Signal::Interrupt))]
+    #[test_case("SIGINT", Some(Signal::Interrupt))]
+    #[test_case("term", Some(Signal::Terminate))]
+    #[test_case("sigterm", Some(Signal::Terminate))]
+    #[test_case("kill", None)]
+    fn parse(s: &str, expected: Option<Signal>) {
+        assert_eq!(expected, s.parse().ok());
+    }
+}
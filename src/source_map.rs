@@ -0,0 +1,187 @@
+use lazy_regex::{lazy_regex, Regex};
+use mlua::prelude::*;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc};
+
+static LOCATION_REGEX: Lazy<Regex> = lazy_regex!(r#"\[string "([^"]*)"\]:(\d+)"#);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Segment {
+    file: String,
+    start: usize,
+    lines: usize,
+    original: usize,
+}
+
+/// Map from lines of a wrapped or generated chunk to lines of the original files,
+/// which rewrites locations in errors and tracebacks. It's embedded in scripts precompiled
+/// by [`crate::compile_with_source_map`].
+///
+/// ```rust
+/// use lmb::*;
+///
+/// let mut map = SourceMap::new();
+/// // line 1 is generated, and lines 2-3 come from a.lua
+/// map.add("a.lua", 2, 2, 1);
+/// assert_eq!(Some(("a.lua", 2)), map.locate(3));
+/// assert_eq!(None, map.locate(1));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    segments: Vec<Segment>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sources: BTreeMap<String, String>,
+}
+
+impl SourceMap {
+    /// Create an empty source map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map the lines of the chunk starting from `start` to the lines of the file starting from `original`.
+    /// Line numbers start from 1.
+    pub fn add<S>(&mut self, file: S, start: usize, lines: usize, original: usize) -> &mut Self
+    where
+        S: Into<String>,
+    {
+        self.segments.push(Segment {
+            file: file.into(),
+            start,
+            lines,
+            original,
+        });
+        self
+    }
+
+    /// Attach the source of the file, which is rendered when the script fails.
+    pub fn add_source<S, T>(&mut self, file: S, source: T) -> &mut Self
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        self.sources.insert(file.into(), source.into());
+        self
+    }
+
+    /// Whether no line is mapped.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Locate the file and line of the line in the chunk.
+    pub fn locate(&self, line: usize) -> Option<(&str, usize)> {
+        self.segments
+            .iter()
+            .find(|s| s.start <= line && line < s.start + s.lines)
+            .map(|s| (s.file.as_str(), s.original + line - s.start))
+    }
+
+    /// Rewrite locations in the chunk e.g. `[string "chunk"]:3` in the message to the original files.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// let mut map = SourceMap::new();
+    /// map.add("a.lua", 2, 2, 1);
+    /// let message = "[string \"chunk\"]:3: boom\nstack traceback:\n\t[string \"chunk\"]:1: in ?";
+    /// assert_eq!(
+    ///     "[string \"a.lua\"]:2: boom\nstack traceback:\n\t[string \"chunk\"]:1: in ?",
+    ///     map.rewrite("chunk", message)
+    /// );
+    /// ```
+    pub fn rewrite(&self, chunk_name: &str, message: &str) -> String {
+        LOCATION_REGEX
+            .replace_all(message, |c: &lazy_regex::Captures<'_>| {
+                let location = if &c[1] == chunk_name {
+                    c[2].parse().ok().and_then(|line| self.locate(line))
+                } else {
+                    None
+                };
+                match location {
+                    Some((file, line)) => format!("[string \"{file}\"]:{line}"),
+                    None => c[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+
+    /// Source of the file attached with [`SourceMap::add_source`].
+    pub fn source(&self, file: &str) -> Option<&str> {
+        self.sources.get(file).map(String::as_str)
+    }
+
+    pub(crate) fn rewrite_error(&self, chunk_name: &str, err: LuaError) -> LuaError {
+        match err {
+            LuaError::RuntimeError(message) => {
+                LuaError::RuntimeError(self.rewrite(chunk_name, &message))
+            }
+            LuaError::SyntaxError {
+                message,
+                incomplete_input,
+            } => LuaError::SyntaxError {
+                message: self.rewrite(chunk_name, &message),
+                incomplete_input,
+            },
+            LuaError::CallbackError { traceback, cause } => LuaError::CallbackError {
+                traceback: self.rewrite(chunk_name, &traceback),
+                cause: Arc::new(self.rewrite_error(chunk_name, (*cause).clone())),
+            },
+            LuaError::WithContext { context, cause } => LuaError::WithContext {
+                context,
+                cause: Arc::new(self.rewrite_error(chunk_name, (*cause).clone())),
+            },
+            err => err,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::empty;
+
+    use crate::{Error, EvaluationBuilder, SourceMap};
+
+    #[test]
+    fn rewrite_traceback() {
+        let script = "local function f()\n  error('boom')\nend\nf()";
+        let mut map = SourceMap::new();
+        map.add("a.lua", 1, 4, 10);
+        let e = EvaluationBuilder::new(script, empty())
+            .name("chunk")
+            .source_map(map)
+            .build()
+            .unwrap();
+        let Err(Error::Lua(err)) = e.evaluate() else {
+            panic!("expect Lua error");
+        };
+        let message = err.to_string();
+        assert!(message.contains("[string \"a.lua\"]:11: boom"), "{message}");
+        assert!(message.contains("[string \"a.lua\"]:13: in ?"), "{message}");
+        assert!(!message.contains("chunk"), "{message}");
+    }
+
+    #[test]
+    fn rewrite_wrapped() {
+        let script = "return (function()\nlocal a = nil\nreturn a.b\nend)()";
+        let mut map = SourceMap::new();
+        map.add("a.lua", 2, 2, 1)
+            .add_source("a.lua", "local a = nil\nreturn a.b");
+        let e = EvaluationBuilder::new(script, empty())
+            .name("chunk")
+            .source_map(map)
+            .build()
+            .unwrap();
+        let err = e.evaluate().unwrap_err();
+        let report = err.report(&e);
+        assert_eq!("a.lua", report.file);
+        assert_eq!(Some(2), report.line);
+        assert_eq!(Some(14..24), report.span);
+
+        let mut buf = String::new();
+        err.write_lua_error(&mut buf, &e, true).unwrap();
+        assert!(buf.contains("a.lua:2:1"), "{buf}");
+        assert!(buf.contains("return a.b"), "{buf}");
+    }
+}
@@ -0,0 +1,62 @@
+use rusqlite::{Connection, OptionalExtension as _};
+use sha2::{Digest, Sha256};
+use tracing::trace;
+
+use super::stmt::*;
+use crate::{Error, Result};
+
+/// Max size of a chunk in the blob table.
+pub(crate) const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Size of the hash of a chunk. Chunks of a value are referenced by their concatenated hashes.
+const HASH_SIZE: usize = 32;
+
+/// Write the encoded value as content-addressable chunks. A chunk shared by values
+/// is stored once and counted by references. Return the concatenated hashes of chunks.
+pub(crate) fn put_chunks(conn: &Connection, value: &[u8]) -> Result<Vec<u8>> {
+    let mut stmt = conn.prepare_cached(SQL_UPSERT_BLOB)?;
+    let mut hashes = Vec::with_capacity(value.len().div_ceil(CHUNK_SIZE) * HASH_SIZE);
+    for chunk in value.chunks(CHUNK_SIZE) {
+        let hash = Sha256::digest(chunk);
+        stmt.execute((hash.as_slice(), chunk))?;
+        hashes.extend_from_slice(&hash);
+    }
+    trace!(chunks = hashes.len() / HASH_SIZE, "put chunks");
+    Ok(hashes)
+}
+
+/// Reassemble the encoded value of the name from chunks.
+pub(crate) fn get_chunks(conn: &Connection, name: &str, hashes: &[u8]) -> Result<Vec<u8>> {
+    let mut stmt = conn.prepare_cached(SQL_GET_BLOB)?;
+    let mut value = vec![];
+    for hash in hashes.chunks(HASH_SIZE) {
+        let chunk: Option<Vec<u8>> = stmt.query_row((hash,), |row| row.get(0)).optional()?;
+        let Some(chunk) = chunk else {
+            return Err(Error::MissingChunk(name.to_string()));
+        };
+        value.extend_from_slice(&chunk);
+    }
+    Ok(value)
+}
+
+/// Drop references of the current value of the name to chunks,
+/// and delete chunks no longer referenced.
+pub(crate) fn release_chunks(conn: &Connection, name: &str) -> Result<()> {
+    let hashes: Option<Vec<u8>> = conn
+        .prepare_cached(SQL_GET_CHUNKS_BY_NAME)?
+        .query_row((name,), |row| row.get(0))
+        .optional()?
+        .flatten();
+    let Some(hashes) = hashes else {
+        return Ok(());
+    };
+    let mut stmt = conn.prepare_cached(SQL_RELEASE_BLOB)?;
+    for hash in hashes.chunks(HASH_SIZE) {
+        stmt.execute((hash,))?;
+    }
+    let deleted = conn
+        .prepare_cached(SQL_DELETE_UNREFERENCED_BLOBS)?
+        .execute([])?;
+    trace!(name, deleted, "release chunks");
+    Ok(())
+}
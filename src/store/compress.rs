@@ -0,0 +1,47 @@
+use std::borrow::Cow;
+use tracing::trace;
+
+use crate::{Error, Result};
+
+/// Marker of a value prefixed with its format. `MessagePack` never uses the byte,
+/// so values written without the prefix are still decoded as `MessagePack`.
+const MARKER: u8 = 0xc1;
+
+/// Format of `MessagePack` compressed with Zstandard.
+const FORMAT_ZSTD: u8 = 1;
+
+/// Compression level of Zstandard, the default of the library.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compress the encoded value when it exceeds the threshold and compression saves space.
+pub(crate) fn compress(value: Vec<u8>, threshold: Option<usize>) -> Result<Vec<u8>> {
+    match threshold {
+        Some(threshold) if value.len() > threshold => {}
+        _ => return Ok(value),
+    }
+    let mut compressed = vec![MARKER, FORMAT_ZSTD];
+    zstd::stream::copy_encode(value.as_slice(), &mut compressed, ZSTD_LEVEL)?;
+    trace!(
+        before = value.len(),
+        after = compressed.len(),
+        "compress value"
+    );
+    if compressed.len() >= value.len() {
+        return Ok(value);
+    }
+    Ok(compressed)
+}
+
+/// Decompress the value if it is prefixed with its format.
+pub(crate) fn decompress(value: &[u8]) -> Result<Cow<'_, [u8]>> {
+    match value {
+        [MARKER, FORMAT_ZSTD, rest @ ..] => Ok(Cow::Owned(zstd::stream::decode_all(rest)?)),
+        [MARKER, format, ..] => Err(Error::UnknownValueFormat(*format)),
+        _ => Ok(Cow::Borrowed(value)),
+    }
+}
+
+/// Check whether the value is compressed.
+pub(crate) fn is_compressed(value: &[u8]) -> bool {
+    value.first() == Some(&MARKER)
+}
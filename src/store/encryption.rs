@@ -0,0 +1,136 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::{collections::HashMap, fmt, fmt::Display};
+
+use crate::{Error, Result};
+
+/// Length of nonce in bytes, which is prepended to the ciphertext.
+const NONCE_LENGTH: usize = 12;
+
+/// Keyring to encrypt values of the store at rest with AES-256-GCM.
+///
+/// The ID of the key is stored alongside the ciphertext, so keys can be rotated
+/// by adding a new primary key while keeping the old ones for decryption.
+///
+/// ```rust
+/// # use serde_json::json;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let mut store = Store::default();
+/// store.set_encryption(Some(StoreEncryption::new("k1", &[0u8; 32])?));
+/// store.put("a", &true.into())?;
+/// assert_eq!(json!(true), store.get("a")?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct StoreEncryption {
+    ciphers: HashMap<String, Aes256Gcm>,
+    primary: String,
+}
+
+impl fmt::Debug for StoreEncryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut key_ids = self.ciphers.keys().collect::<Vec<_>>();
+        key_ids.sort();
+        f.debug_struct("StoreEncryption")
+            .field("key_ids", &key_ids)
+            .field("primary", &self.primary)
+            .finish()
+    }
+}
+
+impl StoreEncryption {
+    /// Create a keyring with the primary key, which encrypts new values.
+    /// The key must be 32 bytes long.
+    pub fn new<S>(id: S, key: &[u8]) -> Result<Self>
+    where
+        S: Display,
+    {
+        let id = id.to_string();
+        let mut ciphers = HashMap::new();
+        ciphers.insert(id.clone(), Aes256Gcm::new_from_slice(key)?);
+        Ok(Self {
+            ciphers,
+            primary: id,
+        })
+    }
+
+    /// Parse keys in `<id>:<base64 encoded key>` format. The first key is the primary one,
+    /// and the others are only used to decrypt values encrypted before rotation.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// let key = "k2:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    /// let old_key = "k1:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+    /// assert!(StoreEncryption::parse(&[key, old_key]).is_ok());
+    /// assert!(StoreEncryption::parse(&["k1:invalid"]).is_err());
+    /// ```
+    pub fn parse<S>(keys: &[S]) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let mut encryption: Option<Self> = None;
+        for key in keys {
+            let Some((id, key)) = key.as_ref().split_once(':') else {
+                return Err(Error::Encryption("key should be <id>:<base64>".to_string()));
+            };
+            let key = STANDARD
+                .decode(key)
+                .map_err(|e| Error::Encryption(e.to_string()))?;
+            match encryption.as_mut() {
+                Some(e) => {
+                    e.add_key(id, &key)?;
+                }
+                None => encryption = Some(Self::new(id, &key)?),
+            }
+        }
+        encryption.ok_or_else(|| Error::Encryption("no key is specified".to_string()))
+    }
+
+    /// Add a key which is only used to decrypt values.
+    pub fn add_key<S>(&mut self, id: S, key: &[u8]) -> Result<&mut Self>
+    where
+        S: Display,
+    {
+        self.ciphers
+            .insert(id.to_string(), Aes256Gcm::new_from_slice(key)?);
+        Ok(self)
+    }
+
+    /// Get ID of the primary key.
+    pub fn primary(&self) -> &str {
+        &self.primary
+    }
+
+    /// Encrypt with the primary key. Return ID of the key and the ciphertext.
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> Result<(&str, Vec<u8>)> {
+        let cipher = &self.ciphers[&self.primary];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|e| Error::Encryption(e.to_string()))?;
+        let mut res = nonce.to_vec();
+        res.extend(ciphertext);
+        Ok((&self.primary, res))
+    }
+
+    /// Decrypt with the key the value was encrypted with.
+    pub(crate) fn decrypt(&self, key_id: &str, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let Some(cipher) = self.ciphers.get(key_id) else {
+            return Err(Error::Encryption(format!("unknown key {key_id}")));
+        };
+        if ciphertext.len() < NONCE_LENGTH {
+            return Err(Error::Encryption("ciphertext is too short".to_string()));
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(NONCE_LENGTH);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| Error::Encryption(e.to_string()))
+    }
+}
@@ -0,0 +1,524 @@
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use rusqlite::Row;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::trace_span;
+
+use super::{stmt::*, Store};
+use crate::Result;
+
+/// Max size in bytes of the result or the error kept in the history.
+pub const MAX_HISTORY_TEXT_SIZE: usize = 1024;
+
+/// Max size in bytes of the input kept in the history. Bytes read beyond it are not copied,
+/// so the invocation is replayed with the truncated input.
+pub const MAX_HISTORY_INPUT_SIZE: usize = 64 * 1024;
+
+/// Truncate the text at a character boundary, so it's kept within the size.
+fn truncate(mut text: String) -> String {
+    if text.len() <= MAX_HISTORY_TEXT_SIZE {
+        return text;
+    }
+    let mut end = MAX_HISTORY_TEXT_SIZE;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    text
+}
+
+/// Copy of bytes read from the input, recorded with each invocation in the history
+/// so the invocation can be replayed, see [`crate::EvaluationBuilder::history_input`].
+/// At most [`MAX_HISTORY_INPUT_SIZE`] bytes are copied for each invocation.
+/// The copy is recorded only when the encryption of the store is set.
+///
+/// ```rust
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let mut store = Store::default();
+/// store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32])?));
+/// let copy = InputCopy::new();
+/// let e = EvaluationBuilder::new("return io.read('*a')", copy.tee("hello".as_bytes()))
+///     .history(Some(store.clone()))
+///     .history_input(Some(copy))
+///     .build()?;
+/// e.evaluate()?;
+/// assert_eq!(b"hello", store.invocations(1)?[0].input());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InputCopy(Arc<Mutex<Vec<u8>>>);
+
+impl InputCopy {
+    /// Create an empty copy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wrap the reader, so bytes read from it are copied.
+    pub fn tee<R: Read>(&self, inner: R) -> TeeReader<R> {
+        TeeReader {
+            copy: self.clone(),
+            inner,
+        }
+    }
+
+    /// Take bytes copied so far, which are attributed to the current invocation.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock())
+    }
+}
+
+/// Reader copying bytes read from the inner reader into [`InputCopy`].
+#[derive(Debug)]
+pub struct TeeReader<R> {
+    copy: InputCopy,
+    inner: R,
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut copy = self.copy.0.lock();
+        let room = MAX_HISTORY_INPUT_SIZE.saturating_sub(copy.len());
+        copy.extend_from_slice(&buf[..read.min(room)]);
+        Ok(read)
+    }
+}
+
+/// Invocation of an evaluation recorded in the history of the store,
+/// see [`crate::EvaluationBuilder::history`].
+#[derive(Clone, Debug)]
+pub struct Invocation {
+    id: i64,
+    name: String,
+    started_at: DateTime<Utc>,
+    duration: Duration,
+    used_memory: usize,
+    result: Option<String>,
+    error: Option<String>,
+    script: Option<String>,
+    input: Vec<u8>,
+    state: Option<Value>,
+    env: BTreeMap<String, String>,
+}
+
+impl Invocation {
+    /// Create an invocation with the result in JSON, or the error if it fails.
+    /// Both are truncated to [`MAX_HISTORY_TEXT_SIZE`].
+    pub(crate) fn new(
+        name: &str,
+        started_at: DateTime<Utc>,
+        duration: Duration,
+        used_memory: usize,
+        outcome: std::result::Result<String, String>,
+    ) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(truncate(result)), None),
+            Err(error) => (None, Some(truncate(error))),
+        };
+        Self {
+            id: 0,
+            name: name.to_string(),
+            started_at,
+            duration,
+            used_memory,
+            result,
+            error,
+            script: None,
+            input: vec![],
+            state: None,
+            env: BTreeMap::new(),
+        }
+    }
+
+    /// Keep what the invocation is replayed with: the script, the input read,
+    /// the state, and the snapshot of permitted environment variables.
+    pub(crate) fn with_replay(
+        mut self,
+        script: &str,
+        input: Vec<u8>,
+        state: Option<Value>,
+        env: BTreeMap<String, String>,
+    ) -> Self {
+        self.script = Some(script.to_string());
+        self.input = input;
+        self.state = state;
+        self.env = env;
+        self
+    }
+
+    /// Get the duration.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Get the snapshot of permitted environment variables.
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    /// Get the error if the invocation failed.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Get ID, which increases with invocations.
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Get bytes read from the input, which is empty unless it's copied by [`InputCopy`].
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    /// Get name of the evaluation.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the result in JSON if the invocation succeeded.
+    pub fn result(&self) -> Option<&str> {
+        self.result.as_deref()
+    }
+
+    /// Get the script, which is absent for invocations recorded by earlier versions,
+    /// and empty for precompiled scripts.
+    pub fn script(&self) -> Option<&str> {
+        self.script.as_deref()
+    }
+
+    /// Get the timestamp that the invocation started.
+    pub fn started_at(&self) -> &DateTime<Utc> {
+        &self.started_at
+    }
+
+    /// Get the state the invocation started with, which is `state` of `@lmb`.
+    pub fn state(&self) -> Option<&Value> {
+        self.state.as_ref()
+    }
+
+    /// Get memory used by the Lua virtual machine in bytes after the invocation.
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+}
+
+impl Store {
+    /// Append the invocation to the history, and return its ID. The input, the state and
+    /// environment variables are encrypted when the encryption of the store is set.
+    /// Otherwise the input and environment variables, which may contain secrets,
+    /// are not recorded.
+    pub(crate) fn record_invocation(&self, invocation: &Invocation) -> Result<i64> {
+        let conn = self.conn.lock();
+        let _s = trace_span!("history_record", name = invocation.name).entered();
+        let duration_us = u64::try_from(invocation.duration.as_micros()).unwrap_or(u64::MAX);
+        let key_id = self.encryption.as_ref().map(|e| e.primary().to_string());
+        let (input, env) = if self.encryption.is_some() {
+            let input = self.encrypt(invocation.input.clone())?.0;
+            let env = self.encrypt(serde_json::to_vec(&invocation.env)?)?.0;
+            (Some(input), Some(env))
+        } else {
+            (None, None)
+        };
+        let state = match &invocation.state {
+            Some(state) => Some(self.encrypt(serde_json::to_vec(state)?)?.0),
+            None => None,
+        };
+        conn.prepare_cached(SQL_INSERT_INVOCATION)?.execute((
+            &invocation.name,
+            invocation.started_at,
+            duration_us,
+            invocation.used_memory,
+            &invocation.result,
+            &invocation.error,
+            &invocation.script,
+            input,
+            state,
+            env,
+            key_id,
+        ))?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// List the latest invocations in the history, the latest first.
+    ///
+    /// ```rust
+    /// # use std::io::empty;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// let e = EvaluationBuilder::new("return 1", empty())
+    ///     .history(Some(store.clone()))
+    ///     .build()?;
+    /// e.evaluate()?;
+    /// e.evaluate()?;
+    /// let invocations = store.invocations(1)?;
+    /// assert_eq!(1, invocations.len());
+    /// assert_eq!(2, invocations[0].id());
+    /// assert_eq!(Some("1"), invocations[0].result());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn invocations(&self, limit: usize) -> Result<Vec<Invocation>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare_cached(SQL_GET_LATEST_INVOCATIONS)?;
+        let mut rows = stmt.query((limit,))?;
+        let mut invocations = vec![];
+        while let Some(row) = rows.next()? {
+            invocations.push(self.invocation_from_row(row)?);
+        }
+        Ok(invocations)
+    }
+
+    /// Get the invocation in the history by ID.
+    /// `None` is returned when the invocation is absent.
+    pub fn invocation(&self, id: i64) -> Result<Option<Invocation>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare_cached(SQL_GET_INVOCATION_BY_ID)?;
+        let mut rows = stmt.query((id,))?;
+        rows.next()?
+            .map(|row| self.invocation_from_row(row))
+            .transpose()
+    }
+
+    fn invocation_from_row(&self, row: &Row<'_>) -> Result<Invocation> {
+        let duration_us: u64 = row.get("duration_us")?;
+        let key_id: Option<String> = row.get("key_id")?;
+        let decrypt = |column: &str| -> Result<Option<Vec<u8>>> {
+            let value: Option<Vec<u8>> = row.get(column)?;
+            value
+                .map(|v| Ok(self.decrypt(&v, key_id.as_deref())?.into_owned()))
+                .transpose()
+        };
+        let input = decrypt("input")?;
+        // columns are written by us in JSON, so malformed ones are treated as absent
+        let state = decrypt("state")?.and_then(|s| serde_json::from_slice(&s).ok());
+        let env = decrypt("env")?.and_then(|s| serde_json::from_slice(&s).ok());
+        Ok(Invocation {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            started_at: row.get("started_at")?,
+            duration: Duration::from_micros(duration_us),
+            used_memory: row.get("used_memory")?,
+            result: row.get("result")?,
+            error: row.get("error")?,
+            script: row.get("script")?,
+            input: input.unwrap_or_default(),
+            state,
+            env: env.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::types::ValueRef;
+    use serde_json::json;
+    use std::io::{empty, Cursor};
+
+    use super::{MAX_HISTORY_INPUT_SIZE, MAX_HISTORY_TEXT_SIZE};
+    use crate::{
+        EnvPermissions, EvaluationBuilder, InputCopy, Permissions, Store, StoreEncryption,
+    };
+
+    #[test]
+    fn history() {
+        let store = Store::default();
+        let script = r#"
+        local n = io.read('*n')
+        if n > 1 then error('too large') end
+        return string.rep('あ', 1000)
+        "#;
+        for input in ["1", "2"] {
+            let e = EvaluationBuilder::new(script, input.as_bytes())
+                .name("job")
+                .history(Some(store.clone()))
+                .build()
+                .unwrap();
+            let _ = e.evaluate();
+        }
+
+        let invocations = store.invocations(10).unwrap();
+        assert_eq!(2, invocations.len());
+        let failed = &invocations[0];
+        assert_eq!("job", failed.name());
+        assert!(failed.result().is_none());
+        assert!(failed.error().unwrap().contains("too large"));
+
+        let succeeded = store.invocation(invocations[1].id()).unwrap().unwrap();
+        let result = succeeded.result().unwrap();
+        assert!(result.len() <= MAX_HISTORY_TEXT_SIZE);
+        assert!(result.starts_with("\"あ"));
+        assert!(succeeded.error().is_none());
+        assert!(succeeded.used_memory() > 0);
+        assert!(succeeded.started_at() <= failed.started_at());
+
+        assert!(store.invocation(3).unwrap().is_none());
+        let e = EvaluationBuilder::new("return 1", empty()).build().unwrap();
+        e.evaluate().unwrap();
+        assert_eq!(2, store.invocations(10).unwrap().len());
+    }
+
+    #[test]
+    fn history_replay() {
+        let mut store = Store::default();
+        store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32]).unwrap()));
+        let mut env = EnvPermissions::new(["LMB_HISTORY_TOKEN"]);
+        env.load_env_file("LMB_HISTORY_TOKEN=secret");
+        let mut permissions = Permissions::default();
+        permissions.set_env(env);
+        let copy = InputCopy::new();
+        let script = "return io.read('*l')";
+        let e = EvaluationBuilder::new(script, copy.tee(&b"a\nb\n"[..]))
+            .app_state(Some(json!({ "n": 1 })))
+            .history(Some(store.clone()))
+            .history_input(Some(copy))
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        e.evaluate().unwrap();
+        e.evaluate_typed::<String, _>(&json!({ "n": 2 })).unwrap();
+
+        let invocations = store.invocations(2).unwrap();
+        let [second, first] = invocations.as_slice() else {
+            panic!("two invocations are expected");
+        };
+        assert_eq!(Some(script), first.script());
+        assert_eq!(b"a\nb\n", first.input());
+        assert!(second.input().is_empty());
+        assert_eq!(Some(&json!({ "n": 1 })), first.state());
+        assert_eq!(Some(&json!({ "n": 2 })), second.state());
+        assert_eq!(
+            Some(&"secret".to_string()),
+            first.env().get("LMB_HISTORY_TOKEN")
+        );
+
+        let e = EvaluationBuilder::new(script, empty())
+            .history(Some(store.clone()))
+            .build()
+            .unwrap();
+        e.evaluate().unwrap();
+        assert!(store.invocations(1).unwrap()[0].env().is_empty());
+    }
+
+    #[test]
+    fn history_encryption() {
+        let mut store = Store::default();
+        store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32]).unwrap()));
+        let mut env = EnvPermissions::new(["LMB_HISTORY_TOKEN"]);
+        env.load_env_file("LMB_HISTORY_TOKEN=secret");
+        let mut permissions = Permissions::default();
+        permissions.set_env(env);
+        let copy = InputCopy::new();
+        let e = EvaluationBuilder::new("return io.read('*a')", copy.tee(&b"password"[..]))
+            .app_state(Some(json!({ "token": "hidden" })))
+            .history(Some(store.clone()))
+            .history_input(Some(copy))
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        e.evaluate().unwrap();
+
+        let (input, state, env): (Vec<u8>, Vec<u8>, Vec<u8>) = store
+            .conn
+            .lock()
+            .query_row("SELECT input, state, env FROM history", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        let contains =
+            |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+        assert!(!contains(&input, b"password"));
+        assert!(!contains(&state, b"hidden"));
+        assert!(!contains(&env, b"secret"));
+
+        let invocation = &store.invocations(1).unwrap()[0];
+        assert_eq!(b"password", invocation.input());
+        assert_eq!(Some(&json!({ "token": "hidden" })), invocation.state());
+        assert_eq!(
+            Some(&"secret".to_string()),
+            invocation.env().get("LMB_HISTORY_TOKEN")
+        );
+
+        store.set_encryption(None);
+        assert!(store.invocations(1).is_err());
+    }
+
+    #[test]
+    fn history_without_encryption() {
+        let store = Store::default();
+        let mut env = EnvPermissions::new(["LMB_HISTORY_TOKEN"]);
+        env.load_env_file("LMB_HISTORY_TOKEN=secret");
+        let mut permissions = Permissions::default();
+        permissions.set_env(env);
+        let copy = InputCopy::new();
+        let e = EvaluationBuilder::new("return #io.read('*a')", copy.tee(&b"password"[..]))
+            .app_state(Some(json!({ "n": 1 })))
+            .history(Some(store.clone()))
+            .history_input(Some(copy))
+            .permissions(permissions)
+            .build()
+            .unwrap();
+        e.evaluate().unwrap();
+
+        let rows = store
+            .conn
+            .lock()
+            .prepare("SELECT * FROM history")
+            .unwrap()
+            .query_map([], |row| {
+                let mut bytes = vec![];
+                for i in 0..row.as_ref().column_count() {
+                    match row.get_ref(i)? {
+                        ValueRef::Text(v) | ValueRef::Blob(v) => bytes.extend_from_slice(v),
+                        _ => {}
+                    }
+                }
+                Ok(bytes)
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let contains =
+            |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+        assert_eq!(1, rows.len());
+        assert!(contains(&rows[0], b"io.read"));
+        assert!(!contains(&rows[0], b"secret"));
+        assert!(!contains(&rows[0], b"password"));
+
+        let invocation = &store.invocations(1).unwrap()[0];
+        assert!(invocation.input().is_empty());
+        assert!(invocation.env().is_empty());
+        assert_eq!(Some(&json!({ "n": 1 })), invocation.state());
+    }
+
+    #[test]
+    fn history_input_size() {
+        let mut store = Store::default();
+        store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32]).unwrap()));
+        let copy = InputCopy::new();
+        let input = vec![b'a'; MAX_HISTORY_INPUT_SIZE + 1];
+        let e = EvaluationBuilder::new("return #io.read('*a')", copy.tee(Cursor::new(input)))
+            .history(Some(store.clone()))
+            .history_input(Some(copy))
+            .build()
+            .unwrap();
+        assert_eq!(
+            &json!(MAX_HISTORY_INPUT_SIZE + 1),
+            e.evaluate().unwrap().payload()
+        );
+        let invocation = &store.invocations(1).unwrap()[0];
+        assert_eq!(MAX_HISTORY_INPUT_SIZE, invocation.input().len());
+    }
+}
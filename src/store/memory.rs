@@ -0,0 +1,235 @@
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::{trace, trace_span};
+
+use super::{
+    BufferedTransaction, Store, StoreBackend, StoreValueMetadata, TransactionFn, UpdateFn,
+};
+use crate::Result;
+
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl Entry {
+    fn new(value: Value) -> Self {
+        let now = Utc::now();
+        Self {
+            value,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Store backed by a hash map. Unlike [`Store::default`], no `SQLite` database is opened.
+/// <div class="warning">Data will be lost after the program finishes.</div>
+///
+/// ```rust
+/// # use std::io::empty;
+/// # use serde_json::json;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let store = MemoryStore::default();
+/// store.put("a", &1.into())?;
+/// let e = EvaluationBuilder::new("return require('@lmb'):get('a')", empty())
+///     .store(store)
+///     .build()?;
+/// let res = e.evaluate()?;
+/// assert_eq!(&json!(1), res.payload());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+fn write(entries: &mut HashMap<String, Entry>, name: &str, value: Value) {
+    match entries.get_mut(name) {
+        Some(entry) => {
+            entry.value = value;
+            entry.updated_at = Utc::now();
+        }
+        None => {
+            entries.insert(name.to_string(), Entry::new(value));
+        }
+    }
+}
+
+impl StoreBackend for MemoryStore {
+    fn delete(&self, name: &str) -> Result<usize> {
+        let removed = self.entries.lock().remove(name);
+        Ok(usize::from(removed.is_some()))
+    }
+
+    fn get(&self, name: &str) -> Result<Value> {
+        let _s = trace_span!("memory_store_get", name).entered();
+        let entries = self.entries.lock();
+        Ok(entries
+            .get(name)
+            .map_or(Value::Null, |entry| entry.value.clone()))
+    }
+
+    fn list(&self) -> Result<Vec<StoreValueMetadata>> {
+        let entries = self.entries.lock();
+        let mut res = entries
+            .iter()
+            .map(|(name, entry)| StoreValueMetadata {
+                name: name.clone(),
+                size: Store::get_size(&entry.value),
+                type_hint: Store::type_hint(&entry.value).to_string(),
+                created_at: entry.created_at,
+                updated_at: entry.updated_at,
+            })
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(res)
+    }
+
+    fn put(&self, name: &str, value: &Value) -> Result<usize> {
+        let _s = trace_span!("memory_store_insert", name).entered();
+        write(&mut self.entries.lock(), name, value.clone());
+        Ok(1)
+    }
+
+    fn transaction(&self, mut f: TransactionFn<'_>) -> Result<()> {
+        let _s = trace_span!("memory_store_transaction").entered();
+        let mut entries = self.entries.lock();
+        let written = {
+            let mut tx = BufferedTransaction::new(|name| {
+                Ok(entries
+                    .get(name)
+                    .map_or(Value::Null, |entry| entry.value.clone()))
+            });
+            f(&mut tx)?;
+            tx.into_written()
+        };
+        for (name, value) in written {
+            write(&mut entries, &name, value);
+        }
+        Ok(())
+    }
+
+    fn update(&self, name: &str, mut f: UpdateFn<'_>, default_v: Option<Value>) -> Result<Value> {
+        let _s = trace_span!("memory_store_update", name).entered();
+        let mut entries = self.entries.lock();
+        let mut value = entries
+            .get(name)
+            .map_or_else(|| default_v.unwrap_or(Value::Null), |e| e.value.clone());
+        if f(&mut value).is_err() {
+            // the function throws an error instead of returing a new value,
+            // return the old value instead.
+            trace!("failed");
+            return Ok(value);
+        }
+        write(&mut entries, name, value.clone());
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{io::empty, sync::Arc, thread};
+
+    use crate::{EvaluationBuilder, MemoryStore, StoreBackend};
+
+    #[test]
+    fn concurrency() {
+        let script = r#"
+        return require('@lmb'):update('a', function(v)
+            return v+1
+        end, 0)
+        "#;
+
+        let store = Arc::new(MemoryStore::default());
+
+        let mut threads = vec![];
+        for _ in 0..=100 {
+            let store = store.clone();
+            threads.push(thread::spawn(move || {
+                let e = EvaluationBuilder::new(script, empty())
+                    .store(store)
+                    .build()
+                    .unwrap();
+                e.evaluate().unwrap();
+            }));
+        }
+        for t in threads {
+            let _ = t.join();
+        }
+        assert_eq!(json!(101), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn get_put_delete() {
+        let store = MemoryStore::default();
+        assert_eq!(json!(null), store.get("a").unwrap());
+        store.put("a", &json!([1, "a"])).unwrap();
+        assert_eq!(json!([1, "a"]), store.get("a").unwrap());
+
+        let values = store.list().unwrap();
+        let value = values.first().unwrap();
+        assert_eq!("a", value.name());
+        assert_eq!("array", value.type_hint());
+        assert_eq!(8 + 1, value.size());
+
+        assert_eq!(1, store.delete("a").unwrap());
+        assert_eq!(0, store.delete("a").unwrap());
+        assert_eq!(json!(null), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn transaction() {
+        let script = r#"
+        return require('@lmb'):transaction(function(tx)
+            tx:put('a', tx:get('a') + 1)
+            pcall(tx.transaction, tx, function(sp)
+                sp:put('a', 0)
+                error('something went wrong')
+            end)
+            return tx:get('a')
+        end)
+        "#;
+
+        let store = Arc::new(MemoryStore::default());
+        store.put("a", &1.into()).unwrap();
+
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(2), res.payload());
+        assert_eq!(json!(2), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn rollback_when_error() {
+        let script = r#"
+        return require('@lmb'):update('a', function(v)
+            error('something went wrong')
+        end, 0)
+        "#;
+
+        let store = Arc::new(MemoryStore::default());
+        store.put("a", &1.into()).unwrap();
+
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(1), res.payload());
+        assert_eq!(json!(1), store.get("a").unwrap());
+    }
+}
@@ -1,24 +1,209 @@
 use chrono::{DateTime, Utc};
 use parking_lot::Mutex;
-use rusqlite::Connection;
+use rusqlite::{Connection, ErrorCode, Transaction, TransactionBehavior};
 use rusqlite_migration::SchemaVersion;
 use serde_json::Value;
 use std::{
+    borrow::Cow,
+    fmt::Debug,
     mem::size_of,
     path::{Path, PathBuf},
     sync::Arc,
+    thread,
+    time::Duration,
 };
 use stmt::*;
-use tracing::{debug, trace, trace_span};
+use tracing::{debug, trace, trace_span, warn};
 
-use crate::{Result, MIGRATIONS};
+use crate::{Error, Result, MIGRATIONS};
+use pool::ReaderPool;
 
+pub use encryption::*;
+pub use history::*;
+pub use memory::*;
+pub use namespace::*;
+pub use quota::*;
+#[cfg(feature = "redis")]
+pub use redis_store::*;
+pub use transaction::*;
+
+mod blob;
+mod compress;
+mod encryption;
+mod history;
+mod memory;
+mod namespace;
+mod pool;
+mod quota;
+#[cfg(feature = "redis")]
+mod redis_store;
 mod stmt;
+mod transaction;
+
+/// Timeout in milliseconds of waiting for locks held by other connections,
+/// e.g. other processes sharing the database file.
+const BUSY_TIMEOUT: u64 = 5000;
+
+/// Max retries of an operation still failing after the busy timeout.
+const BUSY_RETRIES: u32 = 3;
+
+/// Default size in bytes above which encoded values are stored in the blob table.
+pub const DEFAULT_BLOB_THRESHOLD: usize = 1024 * 1024;
+
+/// Prefix of store keys reserved for records of lmb e.g. locks, sessions and cached responses,
+/// which scripts can neither read nor write.
+pub const RESERVED_KEY_PREFIX: &str = "__lmb:";
+
+fn is_busy(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Retry the operation with exponential backoff while the database is busy.
+fn retry_busy<T>(mut f: impl FnMut() -> rusqlite::Result<T>) -> rusqlite::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(e) if is_busy(&e) && attempt < BUSY_RETRIES => {
+                attempt += 1;
+                let backoff = Duration::from_millis(50 << attempt);
+                warn!(attempt, ?backoff, "database is busy, retry");
+                thread::sleep(backoff);
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Begin an immediate transaction, which takes the write lock up front. Deferred transactions
+/// fail immediately without waiting when upgrading to write while other connections write.
+fn begin(conn: &Connection) -> Result<Transaction<'_>> {
+    Ok(retry_busy(|| {
+        Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
+    })?)
+}
+
+/// Function passed to [`StoreBackend::update`] to mutate the value in place.
+/// Backends with optimistic concurrency control may call it more than once.
+pub type UpdateFn<'a> = Box<dyn FnMut(&mut Value) -> mlua::Result<()> + 'a>;
+
+/// Backend that persists values of the store.
+///
+/// [`Store`] backed by `SQLite` and [`MemoryStore`] backed by a hash map are provided.
+/// Implement this trait to supply other kinds of persistence.
+pub trait StoreBackend: Debug + Send + Sync {
+    /// Checkpoint the write-ahead log into the database, so it doesn't grow unbounded in
+    /// long-running processes. Backends without such a log do nothing.
+    fn checkpoint(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Count values whose names start with the prefix. Specify an empty prefix to count all.
+    fn count(&self, prefix: &str) -> Result<usize> {
+        let values = self.list()?;
+        Ok(values.iter().filter(|m| m.name.starts_with(prefix)).count())
+    }
+
+    /// Delete value by name and return the number of deleted values.
+    fn delete(&self, name: &str) -> Result<usize>;
+
+    /// Delete values whose names start with the prefix and `expires_at` in UNIX timestamp
+    /// is not later than now. Return the number of deleted values.
+    fn expire(&self, prefix: &str, now: i64) -> Result<usize> {
+        let mut deleted = 0;
+        for metadata in self.list()? {
+            let name = metadata.name();
+            if !name.starts_with(prefix) {
+                continue;
+            }
+            let expires_at = self.get(name)?.get("expires_at").and_then(Value::as_i64);
+            if expires_at.is_some_and(|t| t <= now) {
+                deleted += self.delete(name)?;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Get value by name. [`Value::Null`] is returned when the value is absent.
+    fn get(&self, name: &str) -> Result<Value>;
+
+    /// List metadata of values.
+    fn list(&self) -> Result<Vec<StoreValueMetadata>>;
+
+    /// Put (insert or update) the value.
+    fn put(&self, name: &str, value: &Value) -> Result<usize>;
+
+    /// Get metadata of the value by name. `None` is returned when the value is absent.
+    fn stat(&self, name: &str) -> Result<Option<StoreValueMetadata>> {
+        Ok(self.list()?.into_iter().find(|m| m.name == name))
+    }
+
+    /// Run the function in a transaction, which is committed when the function succeeds
+    /// and rolled back otherwise. See [`Store::transaction`] for details.
+    fn transaction(&self, f: TransactionFn<'_>) -> Result<()>;
+
+    /// Update the value atomically. The value remains unchanged when the function fails.
+    /// See [`Store::update`] for details.
+    fn update(&self, name: &str, f: UpdateFn<'_>, default_v: Option<Value>) -> Result<Value>;
+}
+
+impl<T> StoreBackend for Arc<T>
+where
+    T: StoreBackend + ?Sized,
+{
+    fn checkpoint(&self) -> Result<()> {
+        self.as_ref().checkpoint()
+    }
+
+    fn count(&self, prefix: &str) -> Result<usize> {
+        self.as_ref().count(prefix)
+    }
+
+    fn delete(&self, name: &str) -> Result<usize> {
+        self.as_ref().delete(name)
+    }
+
+    fn expire(&self, prefix: &str, now: i64) -> Result<usize> {
+        self.as_ref().expire(prefix, now)
+    }
+
+    fn get(&self, name: &str) -> Result<Value> {
+        self.as_ref().get(name)
+    }
+
+    fn list(&self) -> Result<Vec<StoreValueMetadata>> {
+        self.as_ref().list()
+    }
+
+    fn put(&self, name: &str, value: &Value) -> Result<usize> {
+        self.as_ref().put(name, value)
+    }
+
+    fn stat(&self, name: &str) -> Result<Option<StoreValueMetadata>> {
+        self.as_ref().stat(name)
+    }
+
+    fn transaction(&self, f: TransactionFn<'_>) -> Result<()> {
+        self.as_ref().transaction(f)
+    }
+
+    fn update(&self, name: &str, f: UpdateFn<'_>, default_v: Option<Value>) -> Result<Value> {
+        self.as_ref().update(name, f, default_v)
+    }
+}
 
 /// Store options for command line.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct StoreOptions {
+    blob_threshold: Option<usize>,
+    compress_threshold: Option<usize>,
+    encryption_keys: Vec<String>,
+    quota: StoreQuota,
+    readers: usize,
     store_path: Option<PathBuf>,
+    store_url: Option<String>,
     run_migrations: bool,
 }
 
@@ -26,16 +211,120 @@ impl StoreOptions {
     /// Create a new instance of store options.
     pub fn new(store_path: Option<PathBuf>, run_migrations: bool) -> Self {
         Self {
+            blob_threshold: None,
+            compress_threshold: None,
+            encryption_keys: vec![],
+            quota: StoreQuota::default(),
+            readers: 0,
             store_path,
+            store_url: None,
             run_migrations,
         }
     }
 
+    /// Apply blob threshold, compression threshold, encryption, quota and readers to the store.
+    pub fn apply(&self, store: &mut Store) -> Result<()> {
+        if let Some(threshold) = self.blob_threshold {
+            store.set_blob_threshold((threshold > 0).then_some(threshold));
+        }
+        if let Some(threshold) = self.compress_threshold {
+            store.set_compress_threshold((threshold > 0).then_some(threshold));
+        }
+        store.set_encryption(self.encryption()?);
+        store.set_quota(self.quota.clone());
+        store.set_readers(self.readers);
+        Ok(())
+    }
+
+    /// Get the size above which values are stored in the blob table,
+    /// see [`Store::set_blob_threshold`].
+    pub fn blob_threshold(&self) -> Option<usize> {
+        self.blob_threshold
+    }
+
+    /// Set the size above which values are stored in the blob table. Specify 0 to keep
+    /// all values in the store table, or `None` for [`DEFAULT_BLOB_THRESHOLD`].
+    pub fn set_blob_threshold(&mut self, blob_threshold: Option<usize>) -> &mut Self {
+        self.blob_threshold = blob_threshold;
+        self
+    }
+
+    /// Get the size above which values are compressed,
+    /// see [`Store::set_compress_threshold`].
+    pub fn compress_threshold(&self) -> Option<usize> {
+        self.compress_threshold
+    }
+
+    /// Set the size above which values are compressed. Specify 0 or `None`
+    /// to leave values uncompressed, which is the default.
+    pub fn set_compress_threshold(&mut self, compress_threshold: Option<usize>) -> &mut Self {
+        self.compress_threshold = compress_threshold;
+        self
+    }
+
+    /// Get encryption of the store, parsed from keys.
+    pub fn encryption(&self) -> Result<Option<StoreEncryption>> {
+        if self.encryption_keys.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(StoreEncryption::parse(&self.encryption_keys)?))
+    }
+
+    /// Get encryption keys.
+    pub fn encryption_keys(&self) -> &[String] {
+        &self.encryption_keys
+    }
+
+    /// Set encryption keys in `<id>:<base64 encoded key>` format, see [`StoreEncryption::parse`].
+    pub fn set_encryption_keys(&mut self, keys: Vec<String>) -> &mut Self {
+        self.encryption_keys = keys;
+        self
+    }
+
+    /// Get quota.
+    pub fn quota(&self) -> &StoreQuota {
+        &self.quota
+    }
+
+    /// Set quota.
+    pub fn set_quota(&mut self, quota: StoreQuota) -> &mut Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Get the number of read-only connections, see [`Store::set_readers`].
+    pub fn readers(&self) -> usize {
+        self.readers
+    }
+
+    /// Set the number of read-only connections, see [`Store::set_readers`].
+    pub fn set_readers(&mut self, readers: usize) -> &mut Self {
+        self.readers = readers;
+        self
+    }
+
     /// Get store path.
     pub fn store_path(&self) -> &Option<PathBuf> {
         &self.store_path
     }
 
+    /// Set or unset store path.
+    pub fn set_store_path(&mut self, store_path: Option<PathBuf>) -> &mut Self {
+        self.store_path = store_path;
+        self
+    }
+
+    /// Get store URL e.g. `redis://127.0.0.1/`.
+    pub fn store_url(&self) -> Option<&str> {
+        self.store_url.as_deref()
+    }
+
+    /// Set or unset store URL. It takes precedence over store path.
+    pub fn set_store_url(&mut self, store_url: Option<String>) -> &mut Self {
+        self.store_url = store_url;
+        self
+    }
+
     /// Get the option indicating whether migrations should be run.
     pub fn run_migrations(&self) -> bool {
         self.run_migrations
@@ -45,7 +334,12 @@ impl StoreOptions {
 /// Store that persists data across executions.
 #[derive(Clone, Debug)]
 pub struct Store {
+    blob_threshold: Option<usize>,
+    compress_threshold: Option<usize>,
     conn: Arc<Mutex<Connection>>,
+    encryption: Option<Arc<StoreEncryption>>,
+    quota: StoreQuota,
+    readers: Option<Arc<ReaderPool>>,
 }
 
 impl Store {
@@ -64,15 +358,342 @@ impl Store {
     pub fn new(path: &Path) -> Result<Self> {
         debug!(?path, "open store");
         let conn = Connection::open(path)?;
-        conn.pragma_update(None, "busy_timeout", 5000)?;
+        conn.pragma_update(None, "busy_timeout", BUSY_TIMEOUT)?;
         conn.pragma_update(None, "foreign_keys", "OFF")?;
-        conn.pragma_update(None, "journal_mode", "wal")?;
+        // switching to WAL requires an exclusive lock
+        retry_busy(|| conn.pragma_update(None, "journal_mode", "wal"))?;
         conn.pragma_update(None, "synchronous", "NORMAL")?;
         Ok(Self {
+            blob_threshold: Some(DEFAULT_BLOB_THRESHOLD),
+            compress_threshold: None,
             conn: Arc::new(Mutex::new(conn)),
+            encryption: None,
+            quota: StoreQuota::default(),
+            readers: None,
         })
     }
 
+    /// Set the number of idle read-only connections kept for reuse. Values are read
+    /// with them instead of the connection for writes, so reads e.g. of concurrent
+    /// HTTP handlers are not serialized behind each other and writes.
+    /// Specify 0 to read with the connection for writes, which is the default.
+    /// The store in memory is always read with the connection for writes.
+    ///
+    /// ```rust
+    /// # use assert_fs::NamedTempFile;
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let store_file = NamedTempFile::new("db.sqlite3")?;
+    /// let mut store = Store::new(store_file.path())?;
+    /// store.migrate(None)?;
+    /// store.set_readers(4);
+    /// store.put("a", &1.into())?;
+    /// assert_eq!(json!(1), store.get("a")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_readers(&mut self, readers: usize) -> &mut Self {
+        let path = self.conn.lock().path().map(PathBuf::from);
+        self.readers = match path {
+            // the path is empty when the database is in memory
+            Some(path) if readers > 0 && !path.as_os_str().is_empty() => {
+                Some(Arc::new(ReaderPool::new(&path, readers)))
+            }
+            _ => None,
+        };
+        self
+    }
+
+    /// Set the size in bytes above which encoded values are split into content-addressable
+    /// chunks in the blob table, so large values don't bloat the store table. Chunks shared
+    /// by values are stored once, and deleted when no value references them.
+    /// Specify `None` to keep all values in the store table.
+    /// Defaults to [`DEFAULT_BLOB_THRESHOLD`].
+    ///
+    /// ```rust
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let mut store = Store::default();
+    /// store.set_blob_threshold(Some(16));
+    /// let value = json!("a".repeat(1024));
+    /// store.put("a", &value)?;
+    /// assert_eq!(value, store.get("a")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_blob_threshold(&mut self, blob_threshold: Option<usize>) -> &mut Self {
+        self.blob_threshold = blob_threshold;
+        self
+    }
+
+    /// Set the size in bytes above which encoded values are compressed with Zstandard before
+    /// they are encrypted. Values are kept uncompressed if compression doesn't save space.
+    /// Values written before are compressed or decompressed when they are written again,
+    /// or all at once with [`Store::recompress`], and values in either form remain readable.
+    /// Specify `None` to leave values uncompressed, which is the default.
+    ///
+    /// ```rust
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let mut store = Store::default();
+    /// store.set_compress_threshold(Some(64));
+    /// let value = json!("a".repeat(1024));
+    /// store.put("a", &value)?;
+    /// assert_eq!(value, store.get("a")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_compress_threshold(&mut self, compress_threshold: Option<usize>) -> &mut Self {
+        self.compress_threshold = compress_threshold;
+        self
+    }
+
+    /// Compress values above the compression threshold and decompress other values,
+    /// which are otherwise left as they are until they are written again.
+    /// Return the number of rewritten values.
+    ///
+    /// ```rust
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let mut store = Store::default();
+    /// store.put("a", &json!("a".repeat(1024)))?;
+    /// store.set_compress_threshold(Some(64));
+    /// assert_eq!(1, store.recompress()?);
+    /// assert_eq!(0, store.recompress()?);
+    /// store.set_compress_threshold(None);
+    /// assert_eq!(1, store.recompress()?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn recompress(&self) -> Result<usize> {
+        let conn = self.conn.lock();
+        let tx = begin(&conn)?;
+        let _s = trace_span!("store_recompress", threshold = self.compress_threshold).entered();
+        let rows = {
+            let mut stmt = tx.prepare_cached(SQL_GET_ALL_ENCRYPTED_VALUES)?;
+            let rows = stmt.query_map([], |row| {
+                let name: String = row.get_unwrap("name");
+                let value: Vec<u8> = row.get_unwrap("value");
+                let key_id: Option<String> = row.get_unwrap("key_id");
+                let chunks: Option<Vec<u8>> = row.get_unwrap("chunks");
+                Ok((name, value, key_id, chunks))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let mut count = 0;
+        for (name, value, key_id, chunks) in rows {
+            let value = Self::load(&tx, &name, value, chunks)?;
+            let current = self.decrypt(&value, key_id.as_deref())?;
+            let encoded = compress::decompress(&current)?.into_owned();
+            let encoded = compress::compress(encoded, self.compress_threshold)?;
+            if compress::is_compressed(&encoded) == compress::is_compressed(&current) {
+                continue;
+            }
+            let (value, key_id) = self.encrypt(encoded)?;
+            let (value, chunks) = self.store_encoded(&tx, &name, value)?;
+            let mut stmt = tx.prepare_cached(SQL_UPDATE_ENCRYPTED_VALUE)?;
+            count += stmt.execute((name, value, key_id, chunks))?;
+        }
+        tx.commit()?;
+        trace!(count, "recompressed");
+        Ok(count)
+    }
+
+    /// Set quota. Values written before are not checked until they are written again.
+    pub fn set_quota(&mut self, quota: StoreQuota) -> &mut Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Check the value against the quota, evicting values if the policy allows.
+    fn enforce_quota(&self, conn: &Connection, name: &str, size: usize) -> Result<()> {
+        if let Some(limit) = self.quota.max_value_size() {
+            if size > limit {
+                return Err(Error::ValueTooLarge {
+                    name: name.to_string(),
+                    size,
+                    limit,
+                });
+            }
+        }
+        let Some(limit) = self.quota.max_size() else {
+            return Ok(());
+        };
+        let others: usize = conn
+            .prepare_cached(SQL_GET_TOTAL_SIZE)?
+            .query_row((name,), |row| row.get(0))?;
+        let mut total = others + size;
+        if total <= limit {
+            return Ok(());
+        }
+        if self.quota.eviction() == EvictionPolicy::Reject {
+            return Err(Error::StoreFull { size: total, limit });
+        }
+        let candidates = {
+            let mut stmt = conn.prepare_cached(SQL_GET_LEAST_RECENTLY_USED)?;
+            let rows = stmt.query_map((name,), |row| {
+                let name: String = row.get_unwrap("name");
+                let size: usize = row.get_unwrap("size");
+                Ok((name, size))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let mut delete_stmt = conn.prepare_cached(SQL_DELETE_VALUE_BY_NAME)?;
+        for (evicted, evicted_size) in candidates {
+            if total <= limit {
+                break;
+            }
+            blob::release_chunks(conn, &evicted)?;
+            delete_stmt.execute((&evicted,))?;
+            total -= evicted_size;
+            debug!(name = evicted, size = evicted_size, "evict value");
+        }
+        if total > limit {
+            return Err(Error::StoreFull { size: total, limit });
+        }
+        Ok(())
+    }
+
+    fn now() -> String {
+        Utc::now().format("%Y-%m-%d %H:%M:%S%.9f").to_string()
+    }
+
+    /// Set or unset encryption. Values are encrypted when they are written,
+    /// and values written without encryption remain readable.
+    pub fn set_encryption(&mut self, encryption: Option<StoreEncryption>) -> &mut Self {
+        self.encryption = encryption.map(Arc::new);
+        self
+    }
+
+    /// Encrypt all values with the primary key, including values written without encryption
+    /// or encrypted with other keys. Return the number of re-encrypted values.
+    ///
+    /// ```rust
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let mut store = Store::default();
+    /// store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32])?));
+    /// store.put("a", &true.into())?;
+    ///
+    /// let mut encryption = StoreEncryption::new("k2", &[2u8; 32])?;
+    /// encryption.add_key("k1", &[1u8; 32])?;
+    /// store.set_encryption(Some(encryption));
+    /// assert_eq!(1, store.reencrypt()?);
+    /// assert_eq!(0, store.reencrypt()?);
+    /// assert_eq!(json!(true), store.get("a")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reencrypt(&self) -> Result<usize> {
+        let Some(encryption) = &self.encryption else {
+            return Ok(0);
+        };
+        let conn = self.conn.lock();
+        let tx = begin(&conn)?;
+        let _s = trace_span!("store_reencrypt", primary = encryption.primary()).entered();
+        let rows = {
+            let mut stmt = tx.prepare_cached(SQL_GET_ALL_ENCRYPTED_VALUES)?;
+            let rows = stmt.query_map([], |row| {
+                let name: String = row.get_unwrap("name");
+                let value: Vec<u8> = row.get_unwrap("value");
+                let key_id: Option<String> = row.get_unwrap("key_id");
+                let chunks: Option<Vec<u8>> = row.get_unwrap("chunks");
+                Ok((name, value, key_id, chunks))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let mut count = 0;
+        for (name, value, key_id, chunks) in rows {
+            if key_id.as_deref() == Some(encryption.primary()) {
+                continue;
+            }
+            let value = Self::load(&tx, &name, value, chunks)?;
+            let value = self.decode(&value, key_id.as_deref())?;
+            let (value, key_id) = self.encode(&value)?;
+            let (value, chunks) = self.store_encoded(&tx, &name, value)?;
+            let mut stmt = tx.prepare_cached(SQL_UPDATE_ENCRYPTED_VALUE)?;
+            count += stmt.execute((name, value, key_id, chunks))?;
+        }
+        tx.commit()?;
+        trace!(count, "reencrypted");
+        Ok(count)
+    }
+
+    fn encode(&self, value: &Value) -> Result<(Vec<u8>, Option<String>)> {
+        let value = rmp_serde::to_vec(value)?;
+        let value = compress::compress(value, self.compress_threshold)?;
+        self.encrypt(value)
+    }
+
+    fn encrypt(&self, value: Vec<u8>) -> Result<(Vec<u8>, Option<String>)> {
+        let Some(encryption) = &self.encryption else {
+            return Ok((value, None));
+        };
+        let (key_id, value) = encryption.encrypt(&value)?;
+        Ok((value, Some(key_id.to_string())))
+    }
+
+    /// Split the encoded value into chunks when it exceeds the threshold, and release chunks
+    /// of the current value. Return the value kept in the store table and hashes of chunks.
+    fn store_encoded(
+        &self,
+        conn: &Connection,
+        name: &str,
+        value: Vec<u8>,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let chunks = match self.blob_threshold {
+            Some(threshold) if value.len() > threshold => Some(blob::put_chunks(conn, &value)?),
+            _ => None,
+        };
+        // chunks shared by the current and the new value are referenced again before released
+        blob::release_chunks(conn, name)?;
+        match chunks {
+            Some(chunks) => Ok((vec![], Some(chunks))),
+            None => Ok((value, None)),
+        }
+    }
+
+    /// Reassemble the encoded value from chunks if it is stored in the blob table.
+    fn load(
+        conn: &Connection,
+        name: &str,
+        value: Vec<u8>,
+        chunks: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        match chunks {
+            Some(chunks) => blob::get_chunks(conn, name, &chunks),
+            None => Ok(value),
+        }
+    }
+
+    fn decode(&self, value: &[u8], key_id: Option<&str>) -> Result<Value> {
+        let value = self.decrypt(value, key_id)?;
+        let value = compress::decompress(&value)?;
+        Ok(rmp_serde::from_slice(&value)?)
+    }
+
+    fn decrypt<'a>(&self, value: &'a [u8], key_id: Option<&str>) -> Result<Cow<'a, [u8]>> {
+        let Some(key_id) = key_id else {
+            return Ok(Cow::Borrowed(value));
+        };
+        let Some(encryption) = &self.encryption else {
+            return Err(Error::Encryption(format!(
+                "value is encrypted with key {key_id} but encryption is not set"
+            )));
+        };
+        Ok(Cow::Owned(encryption.decrypt(key_id, value)?))
+    }
+
     /// Perform migration on the database. Migrations should be idempotent. If version is omitted,
     /// database will be migrated to the latest. If version is 0, all migrations will be reverted.
     ///
@@ -99,6 +720,122 @@ impl Store {
         Ok(())
     }
 
+    /// Return statistics of size and fragmentation of the database.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// let stats = store.stats()?;
+    /// assert!(stats.size() > 0);
+    /// assert_eq!(0, stats.wal_size());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stats(&self) -> Result<StoreStats> {
+        let conn = self.conn.lock();
+        Self::stats_of(&conn)
+    }
+
+    fn stats_of(conn: &Connection) -> Result<StoreStats> {
+        let page_size: u64 = conn.pragma_query_value(None, "page_size", |row| row.get(0))?;
+        let page_count: u64 = conn.pragma_query_value(None, "page_count", |row| row.get(0))?;
+        let freelist_count: u64 =
+            conn.pragma_query_value(None, "freelist_count", |row| row.get(0))?;
+        // the path is empty when the database is in memory
+        let wal_size = conn
+            .path()
+            .filter(|p| !p.is_empty())
+            .and_then(|p| std::fs::metadata(format!("{p}-wal")).ok())
+            .map_or(0, |m| m.len());
+        Ok(StoreStats {
+            page_size,
+            page_count,
+            freelist_count,
+            wal_size,
+        })
+    }
+
+    /// Rebuild the database to reclaim free pages, and truncate the write-ahead log.
+    /// Return statistics before and after.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// store.put("a", &"a".repeat(100_000).into())?;
+    /// store.delete("a")?;
+    /// let (before, after) = store.vacuum()?;
+    /// assert!(before.freelist_count() > 0);
+    /// assert_eq!(0, after.freelist_count());
+    /// assert!(after.size() < before.size());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn vacuum(&self) -> Result<(StoreStats, StoreStats)> {
+        let conn = self.conn.lock();
+        let before = Self::stats_of(&conn)?;
+        let _s = trace_span!("store_vacuum").entered();
+        conn.execute_batch("VACUUM")?;
+        // the log grows with the rebuilt database, so checkpoint after vacuum
+        conn.pragma_update(None, "wal_checkpoint", "TRUNCATE")?;
+        let after = Self::stats_of(&conn)?;
+        debug!(before = before.size(), after = after.size(), "vacuumed");
+        Ok((before, after))
+    }
+
+    /// Checkpoint the write-ahead log into the database without waiting for readers or writers,
+    /// so the log is reused instead of growing. Unlike [`Store::vacuum`], the log is not truncated.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// store.put("a", &true.into())?;
+    /// store.checkpoint()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn checkpoint(&self) -> Result<()> {
+        let conn = self.conn.lock();
+        let _s = trace_span!("store_checkpoint").entered();
+        let (busy, log, checkpointed): (i64, i64, i64) =
+            conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+        trace!(busy, log, checkpointed, "checkpointed");
+        Ok(())
+    }
+
+    /// Check integrity of the database. Return problems found, which is empty when it is intact.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// assert!(store.verify()?.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn verify(&self) -> Result<Vec<String>> {
+        let conn = self.conn.lock();
+        let _s = trace_span!("store_verify").entered();
+        let mut problems = vec![];
+        conn.pragma_query(None, "integrity_check", |row| {
+            let message: String = row.get(0)?;
+            if message != "ok" {
+                problems.push(message);
+            }
+            Ok(())
+        })?;
+        trace!(problems = problems.len(), "verified");
+        Ok(problems)
+    }
+
     /// Return current version of migrations.
     pub fn current_version(&self) -> Result<SchemaVersion> {
         let conn = self.conn.lock();
@@ -124,10 +861,50 @@ impl Store {
     /// ```
     pub fn delete<S: AsRef<str>>(&self, name: S) -> Result<usize> {
         let conn = self.conn.lock();
-        let affected = conn.execute(SQL_DELETE_VALUE_BY_NAME, (name.as_ref(),))?;
+        let tx = begin(&conn)?;
+        blob::release_chunks(&tx, name.as_ref())?;
+        let affected = tx.execute(SQL_DELETE_VALUE_BY_NAME, (name.as_ref(),))?;
+        tx.commit()?;
         Ok(affected)
     }
 
+    /// Delete values whose names start with the prefix and `expires_at` in UNIX timestamp
+    /// is not later than now, e.g. cached responses. Values without `expires_at` are kept.
+    /// Return the number of deleted values.
+    ///
+    /// ```rust
+    /// # use serde_json::json;
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// store.put("a:1", &json!({ "expires_at": 1 }))?;
+    /// store.put("a:2", &json!({ "expires_at": 3 }))?;
+    /// store.put("a:3", &json!({}))?;
+    /// store.put("b:1", &json!({ "expires_at": 1 }))?;
+    /// assert_eq!(1, store.expire("a:", 2)?);
+    /// assert_eq!(3, store.list()?.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn expire(&self, prefix: &str, now: i64) -> Result<usize> {
+        let conn = self.conn.lock();
+        let tx = begin(&conn)?;
+        let names = tx
+            .prepare_cached(SQL_GET_EXPIRED_CHUNKED_VALUES)?
+            .query_map((prefix, now), |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for name in names {
+            blob::release_chunks(&tx, &name)?;
+        }
+        let deleted = tx
+            .prepare_cached(SQL_DELETE_EXPIRED_VALUES)?
+            .execute((prefix, now))?;
+        tx.commit()?;
+        trace!(prefix, deleted, "expire");
+        Ok(deleted)
+    }
+
     /// Get value from the store. A `nil` will be returned to Lua virtual machine
     /// when the value is absent.
     ///
@@ -144,30 +921,51 @@ impl Store {
     /// # }
     /// ```
     pub fn get<S: AsRef<str>>(&self, name: S) -> Result<Value> {
+        // reading the least recently used value updates its access time
+        if let Some(readers) = self
+            .readers
+            .as_ref()
+            .filter(|_| self.quota.eviction() != EvictionPolicy::Lru)
+        {
+            let conn = readers.get()?;
+            // the value and its chunks should come from the same snapshot
+            let tx = conn.unchecked_transaction()?;
+            let value = self.get_value(&tx, name.as_ref())?;
+            tx.commit()?;
+            return Ok(value);
+        }
         let conn = self.conn.lock();
+        self.get_value(&conn, name.as_ref())
+    }
 
-        let name = name.as_ref();
-
+    fn get_value(&self, conn: &Connection, name: &str) -> Result<Value> {
         let mut cached_stmt = conn.prepare_cached(SQL_GET_VALUE_BY_NAME)?;
         let _s = trace_span!("store_get", name).entered();
         let res = cached_stmt.query_row((name,), |row| {
             let value: Vec<u8> = row.get_unwrap("value");
             let type_hint: String = row.get_unwrap("type_hint");
-            Ok((value, type_hint))
+            let key_id: Option<String> = row.get_unwrap("key_id");
+            let chunks: Option<Vec<u8>> = row.get_unwrap("chunks");
+            Ok((value, type_hint, key_id, chunks))
         });
-        let value: Vec<u8> = match res {
+        let (value, key_id) = match res {
             Err(rusqlite::Error::QueryReturnedNoRows) => {
                 trace!("no_value");
                 return Ok(Value::Null);
             }
             Err(e) => return Err(e.into()),
-            Ok((v, type_hint)) => {
+            Ok((v, type_hint, key_id, chunks)) => {
                 trace!(type_hint, "value");
-                v
+                (Self::load(conn, name, v, chunks)?, key_id)
             }
         };
 
-        Ok(rmp_serde::from_slice::<Value>(&value)?)
+        if self.quota.eviction() == EvictionPolicy::Lru {
+            let mut cached_stmt = conn.prepare_cached(SQL_TOUCH_VALUE_BY_NAME)?;
+            cached_stmt.execute((name, Self::now()))?;
+        }
+
+        self.decode(&value, key_id.as_deref())
     }
 
     /// List values.
@@ -185,25 +983,79 @@ impl Store {
     /// # }
     /// ```
     pub fn list(&self) -> Result<Vec<StoreValueMetadata>> {
-        let conn = self.conn.lock();
+        match &self.readers {
+            Some(readers) => Self::list_values(&*readers.get()?),
+            None => Self::list_values(&self.conn.lock()),
+        }
+    }
+
+    fn list_values(conn: &Connection) -> Result<Vec<StoreValueMetadata>> {
         let mut cached_stmt = conn.prepare_cached(SQL_GET_ALL_VALUES)?;
-        let mut rows = cached_stmt.query([])?;
-        let mut res = vec![];
-        while let Some(row) = rows.next()? {
-            let name: String = row.get_unwrap("name");
-            let type_hint: String = row.get_unwrap("type_hint");
-            let size: usize = row.get_unwrap("size");
-            let created_at: DateTime<Utc> = row.get_unwrap("created_at");
-            let updated_at: DateTime<Utc> = row.get_unwrap("updated_at");
-            res.push(StoreValueMetadata {
-                name,
-                size,
-                type_hint,
-                created_at,
-                updated_at,
-            });
+        let rows = cached_stmt.query_map([], Self::metadata_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    fn metadata_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<StoreValueMetadata> {
+        Ok(StoreValueMetadata {
+            name: row.get("name")?,
+            size: row.get("size")?,
+            type_hint: row.get("type_hint")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+
+    /// Get metadata of the value by name without reading the value.
+    /// `None` is returned when the value is absent.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// store.put("a", &"hello".into())?;
+    /// let metadata = store.stat("a")?.unwrap();
+    /// assert_eq!("string", metadata.type_hint());
+    /// assert!(store.stat("b")?.is_none());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stat(&self, name: &str) -> Result<Option<StoreValueMetadata>> {
+        let stat = |conn: &Connection| -> Result<Option<StoreValueMetadata>> {
+            let mut cached_stmt = conn.prepare_cached(SQL_GET_VALUE_METADATA_BY_NAME)?;
+            let mut rows = cached_stmt.query_map((name,), Self::metadata_from_row)?;
+            Ok(rows.next().transpose()?)
+        };
+        match &self.readers {
+            Some(readers) => stat(&*readers.get()?),
+            None => stat(&self.conn.lock()),
+        }
+    }
+
+    /// Count values whose names start with the prefix. Specify an empty prefix to count all.
+    ///
+    /// ```rust
+    /// use lmb::*;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// store.put("user:1", &1.into())?;
+    /// store.put("user:2", &2.into())?;
+    /// store.put("session:1", &1.into())?;
+    /// assert_eq!(2, store.count("user:")?);
+    /// assert_eq!(3, store.count("")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn count(&self, prefix: &str) -> Result<usize> {
+        let count = |conn: &Connection| -> Result<usize> {
+            let mut cached_stmt = conn.prepare_cached(SQL_COUNT_VALUES_BY_PREFIX)?;
+            Ok(cached_stmt.query_row((prefix,), |row| row.get(0))?)
+        };
+        match &self.readers {
+            Some(readers) => count(&*readers.get()?),
+            None => count(&self.conn.lock()),
         }
-        Ok(res)
     }
 
     /// Put (insert or update) the value into the store.
@@ -228,19 +1080,78 @@ impl Store {
     /// ```
     pub fn put<S: AsRef<str>>(&self, name: S, value: &Value) -> Result<usize> {
         let conn = self.conn.lock();
+        let tx = begin(&conn)?;
+        let affected = self.put_value(&tx, name.as_ref(), value)?;
+        tx.commit()?;
+        Ok(affected)
+    }
 
-        let name = name.as_ref();
+    fn put_value(&self, conn: &Connection, name: &str, value: &Value) -> Result<usize> {
         let size = Self::get_size(value);
         let type_hint = Self::type_hint(value);
-        let value = rmp_serde::to_vec(&value)?;
+        // kept in a column so expired values are found without decoding
+        let expires_at = value.get("expires_at").and_then(Value::as_i64);
+        let (value, key_id) = self.encode(value)?;
 
-        let mut cached_stmt = conn.prepare_cached(SQL_UPSERT_STORE)?;
         let _s = trace_span!("store_insert", name, type_hint).entered();
-        let affected = cached_stmt.execute((name, value, size, type_hint))?;
-
+        self.enforce_quota(conn, name, size)?;
+        let (value, chunks) = self.store_encoded(conn, name, value)?;
+        let mut cached_stmt = conn.prepare_cached(SQL_UPSERT_STORE)?;
+        let affected = cached_stmt.execute((
+            name,
+            value,
+            size,
+            type_hint,
+            key_id,
+            Self::now(),
+            chunks,
+            expires_at,
+        ))?;
         Ok(affected)
     }
 
+    /// Run the function in a transaction, which is committed when the function succeeds
+    /// and rolled back otherwise. Savepoints can be nested in the transaction with
+    /// [`StoreTransaction::savepoint`], so a failed part is rolled back alone.
+    ///
+    /// ```rust
+    /// # use serde_json::json;
+    /// use lmb::*;
+    /// use mlua::ExternalResult as _;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let store = Store::default();
+    /// store.transaction(|tx| {
+    ///     tx.put("a", &1.into()).into_lua_err()?;
+    ///     let res = tx.savepoint(Box::new(|sp| {
+    ///         sp.put("b", &2.into()).into_lua_err()?;
+    ///         Err(mlua::Error::runtime("something went wrong"))
+    ///     }));
+    ///     assert!(res.is_err());
+    ///     Ok(())
+    /// })?;
+    /// assert_eq!(json!(1), store.get("a")?);
+    /// assert_eq!(json!(null), store.get("b")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn transaction(
+        &self,
+        mut f: impl FnMut(&mut dyn StoreTransaction) -> mlua::Result<()>,
+    ) -> Result<()> {
+        let conn = self.conn.lock();
+        let tx = begin(&conn)?;
+        let _s = trace_span!("store_transaction").entered();
+        f(&mut SqliteTransaction {
+            conn: &tx,
+            depth: 0,
+            store: self,
+        })?;
+        tx.commit()?;
+        trace!("committed");
+        Ok(())
+    }
+
     /// Insert or update the value into the store.
     ///
     /// Unlike [`Store::put`], this function accepts a closure and only mutates the value in the store
@@ -300,28 +1211,34 @@ impl Store {
         f: impl FnOnce(&mut Value) -> mlua::Result<()>,
         default_v: Option<Value>,
     ) -> Result<Value> {
-        let mut conn = self.conn.lock();
-        let tx = conn.transaction()?;
+        let conn = self.conn.lock();
+        let tx = begin(&conn)?;
 
         let name = name.as_ref();
 
         let _s = trace_span!("store_update", name).entered();
-        let value: Vec<u8> = {
+        let mut value: Value = {
             let mut cached_stmt = tx.prepare_cached(SQL_GET_VALUE_BY_NAME)?;
-            match cached_stmt.query_row((name,), |row| row.get(0)) {
+            let res = cached_stmt.query_row((name,), |row| {
+                let value: Vec<u8> = row.get_unwrap("value");
+                let key_id: Option<String> = row.get_unwrap("key_id");
+                let chunks: Option<Vec<u8>> = row.get_unwrap("chunks");
+                Ok((value, key_id, chunks))
+            });
+            match res {
                 Err(rusqlite::Error::QueryReturnedNoRows) => {
                     trace!("default_value");
-                    rmp_serde::to_vec(default_v.as_ref().unwrap_or(&Value::Null))?
+                    default_v.unwrap_or(Value::Null)
                 }
                 Err(e) => return Err(e.into()),
-                Ok(v) => {
+                Ok((v, key_id, chunks)) => {
                     trace!("value");
-                    v
+                    let v = Self::load(&tx, name, v, chunks)?;
+                    self.decode(&v, key_id.as_deref())?
                 }
             }
         };
 
-        let mut value: Value = rmp_serde::from_slice(&value)?;
         {
             let _s = trace_span!("call_function").entered();
             let Ok(_) = f(&mut value) else {
@@ -331,13 +1248,8 @@ impl Store {
                 return Ok(value);
             };
         }
-        let size = Self::get_size(&value);
         let type_hint = Self::type_hint(&value);
-        {
-            let value = rmp_serde::to_vec(&value)?;
-            let mut cached_stmt = tx.prepare_cached(SQL_UPSERT_STORE)?;
-            cached_stmt.execute((name, value, size, type_hint))?;
-        }
+        self.put_value(&tx, name, &value)?;
         tx.commit()?;
         trace!(type_hint, "updated");
 
@@ -374,6 +1286,84 @@ impl Store {
     }
 }
 
+impl StoreBackend for Store {
+    fn checkpoint(&self) -> Result<()> {
+        Store::checkpoint(self)
+    }
+
+    fn count(&self, prefix: &str) -> Result<usize> {
+        Store::count(self, prefix)
+    }
+
+    fn delete(&self, name: &str) -> Result<usize> {
+        Store::delete(self, name)
+    }
+
+    fn expire(&self, prefix: &str, now: i64) -> Result<usize> {
+        Store::expire(self, prefix, now)
+    }
+
+    fn get(&self, name: &str) -> Result<Value> {
+        Store::get(self, name)
+    }
+
+    fn list(&self) -> Result<Vec<StoreValueMetadata>> {
+        Store::list(self)
+    }
+
+    fn put(&self, name: &str, value: &Value) -> Result<usize> {
+        Store::put(self, name, value)
+    }
+
+    fn stat(&self, name: &str) -> Result<Option<StoreValueMetadata>> {
+        Store::stat(self, name)
+    }
+
+    fn transaction(&self, f: TransactionFn<'_>) -> Result<()> {
+        Store::transaction(self, f)
+    }
+
+    fn update(&self, name: &str, f: UpdateFn<'_>, default_v: Option<Value>) -> Result<Value> {
+        Store::update(self, name, f, default_v)
+    }
+}
+
+/// Transaction of [`Store`], where savepoints are named after their depth.
+struct SqliteTransaction<'a> {
+    conn: &'a Connection,
+    depth: usize,
+    store: &'a Store,
+}
+
+impl StoreTransaction for SqliteTransaction<'_> {
+    fn get(&mut self, name: &str) -> Result<Value> {
+        self.store.get_value(self.conn, name)
+    }
+
+    fn put(&mut self, name: &str, value: &Value) -> Result<usize> {
+        self.store.put_value(self.conn, name, value)
+    }
+
+    fn savepoint(&mut self, mut f: TransactionFn<'_>) -> Result<()> {
+        let depth = self.depth + 1;
+        let _s = trace_span!("store_savepoint", depth).entered();
+        self.conn.execute_batch(&format!("SAVEPOINT sp{depth}"))?;
+        let res = f(&mut SqliteTransaction {
+            conn: self.conn,
+            depth,
+            store: self.store,
+        });
+        if let Err(e) = res {
+            trace!("rolled back");
+            self.conn
+                .execute_batch(&format!("ROLLBACK TO sp{depth}; RELEASE sp{depth}"))?;
+            return Err(e.into());
+        }
+        self.conn.execute_batch(&format!("RELEASE sp{depth}"))?;
+        Ok(())
+    }
+}
+
 /// Value metadata. The value itself is intentionally not included.
 #[derive(Debug)]
 pub struct StoreValueMetadata {
@@ -411,13 +1401,64 @@ impl StoreValueMetadata {
     }
 }
 
+/// Statistics of size and fragmentation of the database, see [`Store::stats`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreStats {
+    page_size: u64,
+    page_count: u64,
+    freelist_count: u64,
+    wal_size: u64,
+}
+
+impl StoreStats {
+    /// Get the number of free pages, which are reclaimed by [`Store::vacuum`].
+    pub fn freelist_count(&self) -> u64 {
+        self.freelist_count
+    }
+
+    /// Get the ratio of free pages to all pages, from 0 to 1.
+    pub fn fragmentation(&self) -> f64 {
+        if self.page_count == 0 {
+            return 0.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = self.freelist_count as f64 / self.page_count as f64;
+        ratio
+    }
+
+    /// Get the number of pages.
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    /// Get the size of a page in bytes.
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    /// Get the size of the database in bytes, excluding the write-ahead log.
+    pub fn size(&self) -> u64 {
+        self.page_size * self.page_count
+    }
+
+    /// Get the size of the write-ahead log in bytes.
+    pub fn wal_size(&self) -> u64 {
+        self.wal_size
+    }
+}
+
 impl Default for Store {
     /// Open and initialize a `SQLite` database in memory.
     fn default() -> Self {
         debug!("open store in memory");
         let conn = Connection::open_in_memory().expect("failed to open SQLite database in memory");
         let store = Self {
+            blob_threshold: Some(DEFAULT_BLOB_THRESHOLD),
+            compress_threshold: None,
             conn: Arc::new(Mutex::new(conn)),
+            encryption: None,
+            quota: StoreQuota::default(),
+            readers: None,
         };
         store
             .migrate(None)
@@ -433,7 +1474,8 @@ mod tests {
     use std::{io::empty, thread};
     use test_case::test_case;
 
-    use crate::{EvaluationBuilder, Store};
+    use super::blob::CHUNK_SIZE;
+    use crate::{Error, EvaluationBuilder, EvictionPolicy, Store, StoreEncryption, StoreQuota};
 
     #[test]
     fn concurrency() {
@@ -449,7 +1491,10 @@ mod tests {
         for _ in 0..=1000 {
             let store = store.clone();
             threads.push(thread::spawn(move || {
-                let e = EvaluationBuilder::new(script, empty()).store(store).build();
+                let e = EvaluationBuilder::new(script, empty())
+                    .store(store)
+                    .build()
+                    .unwrap();
                 e.evaluate().unwrap();
             }));
         }
@@ -471,6 +1516,356 @@ mod tests {
         assert_eq!(size, value.size());
     }
 
+    #[test]
+    fn concurrency_across_connections() {
+        let script = r#"
+        return require('@lmb'):update('a', function(v)
+            return v+1
+        end, 0)
+        "#;
+
+        let store_file = NamedTempFile::new("db.sqlite3").unwrap();
+        let store = Store::new(store_file.path()).unwrap();
+        store.migrate(None).unwrap();
+
+        // each store has its own connection, like processes sharing the file
+        let mut threads = vec![];
+        for _ in 0..8 {
+            let store = Store::new(store_file.path()).unwrap();
+            threads.push(thread::spawn(move || {
+                let e = EvaluationBuilder::new(script, empty())
+                    .store(store)
+                    .build()
+                    .unwrap();
+                for _ in 0..25 {
+                    e.evaluate().unwrap();
+                }
+            }));
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(json!(200), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn readers() {
+        let store_file = NamedTempFile::new("db.sqlite3").unwrap();
+        let mut store = Store::new(store_file.path()).unwrap();
+        store.migrate(None).unwrap();
+        store.set_readers(2);
+        store.put("a", &0.into()).unwrap();
+
+        let mut threads = vec![];
+        for i in 0..8 {
+            let store = store.clone();
+            threads.push(thread::spawn(move || {
+                for _ in 0..25 {
+                    if i % 2 == 0 {
+                        store.get("a").unwrap();
+                        assert_eq!(1, store.list().unwrap().len());
+                    } else {
+                        store
+                            .update(
+                                "a",
+                                |v| {
+                                    *v = json!(v.as_i64().unwrap() + 1);
+                                    Ok(())
+                                },
+                                None,
+                            )
+                            .unwrap();
+                    }
+                }
+            }));
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+        // reads see writes committed before
+        assert_eq!(json!(100), store.get("a").unwrap());
+        assert!(store.readers.as_ref().unwrap().idle_count() <= 2);
+    }
+
+    #[test]
+    fn readers_blobs() {
+        let store_file = NamedTempFile::new("db.sqlite3").unwrap();
+        let mut store = Store::new(store_file.path()).unwrap();
+        store.migrate(None).unwrap();
+        store.set_readers(2);
+        store.set_blob_threshold(Some(16));
+        let values = [
+            json!("a".repeat(CHUNK_SIZE * 2)),
+            json!("b".repeat(CHUNK_SIZE * 2)),
+        ];
+        store.put("a", &values[0]).unwrap();
+
+        let mut threads = vec![];
+        for i in 0..4 {
+            let store = store.clone();
+            let values = values.clone();
+            threads.push(thread::spawn(move || {
+                for j in 0..25 {
+                    if i == 0 {
+                        store.put("a", &values[j % 2]).unwrap();
+                    } else {
+                        // chunks released by a concurrent put are never missing
+                        let value = store.get("a").unwrap();
+                        assert!(values.contains(&value));
+                    }
+                }
+            }));
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn blobs() {
+        let count_blobs = |store: &Store| -> usize {
+            let conn = store.conn.lock();
+            conn.query_row("SELECT COUNT(*) FROM store_blobs", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let mut store = Store::default();
+        store.set_blob_threshold(Some(16));
+        // the chunk with the header, two identical chunks, and the rest
+        let value = json!("a".repeat(CHUNK_SIZE * 3));
+        store.put("a", &value).unwrap();
+        assert_eq!(value, store.get("a").unwrap());
+        assert_eq!(3, count_blobs(&store));
+
+        store.put("b", &value).unwrap();
+        assert_eq!(3, count_blobs(&store));
+
+        store.put("a", &json!("small")).unwrap();
+        assert_eq!(json!("small"), store.get("a").unwrap());
+        assert_eq!(3, count_blobs(&store));
+
+        let updated = store
+            .update(
+                "b",
+                |v| {
+                    *v = json!(format!("{}b", v.as_str().unwrap()));
+                    Ok(())
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(updated, store.get("b").unwrap());
+        assert_eq!(3, count_blobs(&store));
+
+        store.delete("b").unwrap();
+        assert_eq!(0, count_blobs(&store));
+    }
+
+    #[test]
+    fn expire() {
+        let count_blobs = |store: &Store| -> usize {
+            let conn = store.conn.lock();
+            conn.query_row("SELECT COUNT(*) FROM store_blobs", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let mut store = Store::default();
+        store.set_blob_threshold(Some(1024));
+        store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32]).unwrap()));
+        let body = "a".repeat(CHUNK_SIZE * 2);
+        store
+            .put("a:1", &json!({ "body": body, "expires_at": 1 }))
+            .unwrap();
+        store.put("a:2", &json!({ "expires_at": 3 })).unwrap();
+        store.put("a:3", &json!({ "expires_at": "1" })).unwrap();
+        store.put("b:1", &json!({ "expires_at": 1 })).unwrap();
+        assert!(count_blobs(&store) > 0);
+        assert_eq!(1, store.expire("a:", 2).unwrap());
+        assert_eq!(json!(null), store.get("a:1").unwrap());
+        assert_eq!(3, store.list().unwrap().len());
+        assert_eq!(0, count_blobs(&store));
+
+        // values put again without expires_at are kept
+        store.put("a:2", &json!({})).unwrap();
+        assert_eq!(0, store.expire("a:", 4).unwrap());
+    }
+
+    #[test]
+    fn blobs_reencrypt() {
+        let mut store = Store::default();
+        store.set_blob_threshold(Some(16));
+        store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32]).unwrap()));
+        let value = json!("a".repeat(1024));
+        store.put("a", &value).unwrap();
+
+        let mut encryption = StoreEncryption::new("k2", &[2u8; 32]).unwrap();
+        encryption.add_key("k1", &[1u8; 32]).unwrap();
+        store.set_encryption(Some(encryption));
+        assert_eq!(1, store.reencrypt().unwrap());
+        assert_eq!(value, store.get("a").unwrap());
+    }
+
+    #[test]
+    fn compression() {
+        let raw = |store: &Store, name: &str| -> Vec<u8> {
+            store
+                .conn
+                .lock()
+                .query_row("SELECT value FROM store WHERE name = ?1", (name,), |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        let value = json!("a".repeat(1024));
+        let mut store = Store::default();
+        store.put("legacy", &value).unwrap();
+        store.set_compress_threshold(Some(64));
+        store.put("small", &"hello".into()).unwrap();
+        store.put("large", &value).unwrap();
+        assert_eq!(
+            rmp_serde::to_vec(&json!("hello")).unwrap(),
+            raw(&store, "small")
+        );
+        assert!(raw(&store, "large").len() < 64);
+        assert_eq!(value, store.get("legacy").unwrap());
+        assert_eq!(value, store.get("large").unwrap());
+
+        // compressed values are encrypted, and rewritten without decryption failures
+        store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32]).unwrap()));
+        store.put("secret", &value).unwrap();
+        assert_eq!(1, store.recompress().unwrap());
+        assert_eq!(value, store.get("legacy").unwrap());
+        assert_eq!(value, store.get("secret").unwrap());
+
+        store.set_compress_threshold(None);
+        assert_eq!(3, store.recompress().unwrap());
+        assert_eq!(value, store.get("large").unwrap());
+        assert_eq!(value, store.get("secret").unwrap());
+
+        let mut unknown = raw(&store, "small");
+        unknown.splice(0..0, [0xc1, 0xff]);
+        store
+            .conn
+            .lock()
+            .execute(
+                "UPDATE store SET value = ?1 WHERE name = 'small'",
+                (unknown,),
+            )
+            .unwrap();
+        assert!(matches!(
+            store.get("small"),
+            Err(Error::UnknownValueFormat(0xff))
+        ));
+    }
+
+    #[test]
+    fn encryption() {
+        let mut store = Store::default();
+        store.put("plain", &"hello".into()).unwrap();
+        store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32]).unwrap()));
+        store.put("secret", &"hello".into()).unwrap();
+        assert_eq!(json!("hello"), store.get("plain").unwrap());
+        assert_eq!(json!("hello"), store.get("secret").unwrap());
+
+        let raw: Vec<u8> = store
+            .conn
+            .lock()
+            .query_row("SELECT value FROM store WHERE name = 'secret'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_ne!(rmp_serde::to_vec(&json!("hello")).unwrap(), raw);
+
+        store.set_encryption(None);
+        assert!(store.get("secret").is_err());
+
+        store.set_encryption(Some(StoreEncryption::new("k2", &[2u8; 32]).unwrap()));
+        assert!(store.get("secret").is_err());
+    }
+
+    #[test]
+    fn encryption_update() {
+        let script = r#"
+        return require('@lmb'):update('a', function(v)
+            return v+1
+        end, 0)
+        "#;
+
+        let mut store = Store::default();
+        store.set_encryption(Some(StoreEncryption::new("k1", &[1u8; 32]).unwrap()));
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+        e.evaluate().unwrap();
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!(2), res.payload());
+        assert_eq!(json!(2), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn quota_evict_least_recently_used() {
+        let mut quota = StoreQuota::default();
+        quota
+            .set_max_size(Some(16))
+            .set_eviction(EvictionPolicy::Lru);
+
+        let mut store = Store::default();
+        store.set_quota(quota);
+        store.put("a", &1.into()).unwrap();
+        store.put("b", &2.into()).unwrap();
+        store.get("a").unwrap();
+        store.put("c", &3.into()).unwrap();
+
+        assert_eq!(json!(1), store.get("a").unwrap());
+        assert_eq!(json!(null), store.get("b").unwrap());
+        assert_eq!(json!(3), store.get("c").unwrap());
+    }
+
+    #[test]
+    fn quota_reject() {
+        let mut quota = StoreQuota::default();
+        quota.set_max_size(Some(16));
+
+        let mut store = Store::default();
+        store.set_quota(quota);
+        store.put("a", &1.into()).unwrap();
+        store.put("b", &2.into()).unwrap();
+        store.put("b", &3.into()).unwrap(); // replace
+        let err = store.put("c", &4.into()).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::StoreFull {
+                size: 24,
+                limit: 16
+            }
+        ));
+        assert_eq!(json!(null), store.get("c").unwrap());
+    }
+
+    #[test]
+    fn quota_update() {
+        let script = r#"
+        return require('@lmb'):update('a', function(v)
+            return v .. 'a'
+        end, '')
+        "#;
+
+        let mut quota = StoreQuota::default();
+        quota.set_max_value_size(Some(2));
+
+        let mut store = Store::default();
+        store.set_quota(quota);
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+        e.evaluate().unwrap();
+        e.evaluate().unwrap();
+        assert!(e.evaluate().is_err());
+        assert_eq!(json!("aa"), store.get("a").unwrap());
+    }
+
     #[test]
     fn get_put() {
         let script = r#"
@@ -486,7 +1881,8 @@ mod tests {
 
         let e = EvaluationBuilder::new(script, empty())
             .store(store.clone())
-            .build();
+            .build()
+            .unwrap();
 
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(1.23), res.payload());
@@ -494,6 +1890,60 @@ mod tests {
         assert_eq!(json!(null), store.get("b").unwrap());
     }
 
+    #[test]
+    fn stat_and_count() {
+        let script = r#"
+        local m = require('@lmb')
+        local stat = m:stat('user:1')
+        assert(not m:stat('user:3'))
+        assert(os.time() - stat.updated_at < 60)
+        assert(stat.created_at <= stat.updated_at)
+        return { size = stat.size, type_hint = stat.type_hint, users = m:count('user:'), all = m:count() }
+        "#;
+
+        let store = Store::default();
+        store.put("user:1", &json!("hello")).unwrap();
+        store.put("user:2", &json!(1)).unwrap();
+        store.put("user_", &json!(1)).unwrap();
+
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+        let res = e.evaluate().unwrap();
+        let expected = json!({ "size": 5, "type_hint": "string", "users": 2, "all": 3 });
+        assert_eq!(&expected, res.payload());
+        // wildcards of LIKE are matched literally
+        assert_eq!(0, store.count("user%").unwrap());
+    }
+
+    #[test]
+    fn vacuum_and_verify() {
+        let store_file = NamedTempFile::new("db.sqlite3").unwrap();
+        let store = Store::new(store_file.path()).unwrap();
+        store.migrate(None).unwrap();
+        for i in 0..10 {
+            store
+                .put(format!("v{i}"), &"a".repeat(10_000).into())
+                .unwrap();
+        }
+        for i in 0..10 {
+            store.delete(format!("v{i}")).unwrap();
+        }
+
+        let stats = store.stats().unwrap();
+        assert!(stats.freelist_count() > 0);
+        assert!(stats.fragmentation() > 0.0);
+        assert!(stats.wal_size() > 0);
+
+        let (before, after) = store.vacuum().unwrap();
+        assert_eq!(stats, before);
+        assert_eq!(0, after.freelist_count());
+        assert_eq!(0, after.wal_size());
+        assert!(after.size() < before.size());
+        assert!(store.verify().unwrap().is_empty());
+    }
+
     #[test]
     fn migrate() {
         let store = Store::default();
@@ -539,7 +1989,8 @@ mod tests {
 
         let e = EvaluationBuilder::new(script, empty())
             .store(store.clone())
-            .build();
+            .build()
+            .unwrap();
 
         {
             let res = e.evaluate().unwrap();
@@ -567,13 +2018,123 @@ mod tests {
 
         let e = EvaluationBuilder::new(script, empty())
             .store(store.clone())
-            .build();
+            .build()
+            .unwrap();
 
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(2), res.payload());
         assert_eq!(json!(2), store.get("a").unwrap());
     }
 
+    #[test]
+    fn nested_update() {
+        let script = r#"
+        local m = require('@lmb')
+        return m:update('a', function(v)
+            m:update('b', function(w) return w+1 end, 0)
+            return v+1
+        end, 0)
+        "#;
+
+        let store = Store::default();
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+
+        let err = e.evaluate().unwrap_err();
+        assert_eq!("a", err.nested_update().unwrap().name);
+        assert_eq!(json!(null), store.get("a").unwrap());
+        assert_eq!(json!(null), store.get("b").unwrap());
+    }
+
+    #[test]
+    fn nested_call_caught() {
+        let script = r#"
+        local m = require('@lmb')
+        local updated = m:update('a', function(v)
+            assert(not pcall(function() m:get('b') end))
+            return v+1
+        end, 0)
+        return { updated, m:get('a') }
+        "#;
+
+        let store = Store::default();
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!([1, 1]), res.payload());
+    }
+
+    #[test]
+    fn transaction() {
+        let script = r#"
+        local m = require('@lmb')
+        return m:transaction(function(tx)
+            tx:put('a', 1)
+            local ok = pcall(tx.transaction, tx, function(sp)
+                sp:put('b', 2)
+                assert(2 == sp:get('b'))
+                error('something went wrong')
+            end)
+            assert(not ok)
+            tx:transaction(function(sp)
+                sp:put('c', sp:get('a') + 2)
+            end)
+            assert(not pcall(function() m:get('a') end))
+            return { a = tx:get('a'), b = tx:get('b'), c = tx:get('c') }
+        end)
+        "#;
+
+        let store = Store::default();
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+
+        let res = e.evaluate().unwrap();
+        assert_eq!(&json!({ "a": 1, "c": 3 }), res.payload());
+        assert_eq!(json!(1), store.get("a").unwrap());
+        assert_eq!(json!(null), store.get("b").unwrap());
+        assert_eq!(json!(3), store.get("c").unwrap());
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        let script = r#"
+        local m = require('@lmb')
+        local ok, err = pcall(m.transaction, m, function(tx)
+            tx:put('a', 2)
+            tx:transaction(function(sp)
+                assert(not pcall(tx.get, tx, 'a'), 'outer transaction is suspended')
+                sp:put('b', 1)
+            end)
+            error('something went wrong')
+        end)
+        assert(not ok)
+        return tostring(err)
+        "#;
+
+        let store = Store::default();
+        store.put("a", &1.into()).unwrap();
+        let e = EvaluationBuilder::new(script, empty())
+            .store(store.clone())
+            .build()
+            .unwrap();
+
+        let res = e.evaluate().unwrap();
+        assert!(res
+            .payload()
+            .as_str()
+            .unwrap()
+            .contains("something went wrong"));
+        assert_eq!(json!(1), store.get("a").unwrap());
+        assert_eq!(json!(null), store.get("b").unwrap());
+    }
+
     #[test_log::test]
     fn rollback_when_error() {
         let script = r#"
@@ -591,7 +2152,8 @@ mod tests {
 
         let e = EvaluationBuilder::new(script, empty())
             .store(store.clone())
-            .build();
+            .build()
+            .unwrap();
 
         let res = e.evaluate().unwrap();
         assert_eq!(&json!(1), res.payload());
@@ -0,0 +1,157 @@
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{StoreBackend, StoreTransaction, StoreValueMetadata, TransactionFn, UpdateFn};
+use crate::Result;
+
+/// Store that prefixes names of values with a namespace, so functions sharing
+/// a backend cannot read or overwrite values of each other.
+///
+/// ```rust
+/// # use std::sync::Arc;
+/// # use serde_json::json;
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let backend = Arc::new(MemoryStore::default());
+/// let store = NamespacedStore::new(backend.clone(), "a");
+/// store.put("x", &1.into())?;
+/// assert_eq!(json!(1), backend.get("a:x")?);
+/// assert_eq!("x", store.list()?[0].name());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NamespacedStore {
+    inner: Arc<dyn StoreBackend>,
+    prefix: String,
+}
+
+impl NamespacedStore {
+    /// Wrap the store, prefixing names with the namespace and a colon.
+    /// The namespace should not contain colons, or namespaces may overlap.
+    pub fn new<S>(inner: Arc<dyn StoreBackend>, namespace: S) -> Self
+    where
+        S: AsRef<str>,
+    {
+        Self {
+            inner,
+            prefix: format!("{}:", namespace.as_ref()),
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+}
+
+/// Transaction of the underlying store, prefixing names with the namespace.
+struct NamespacedTransaction<'a> {
+    inner: &'a mut dyn StoreTransaction,
+    prefix: &'a str,
+}
+
+impl StoreTransaction for NamespacedTransaction<'_> {
+    fn get(&mut self, name: &str) -> Result<Value> {
+        self.inner.get(&format!("{}{name}", self.prefix))
+    }
+
+    fn put(&mut self, name: &str, value: &Value) -> Result<usize> {
+        self.inner.put(&format!("{}{name}", self.prefix), value)
+    }
+
+    fn savepoint(&mut self, mut f: TransactionFn<'_>) -> Result<()> {
+        let prefix = self.prefix;
+        self.inner.savepoint(Box::new(|inner| {
+            f(&mut NamespacedTransaction { inner, prefix })
+        }))
+    }
+}
+
+impl StoreBackend for NamespacedStore {
+    fn checkpoint(&self) -> Result<()> {
+        self.inner.checkpoint()
+    }
+
+    fn count(&self, prefix: &str) -> Result<usize> {
+        self.inner.count(&self.key(prefix))
+    }
+
+    fn delete(&self, name: &str) -> Result<usize> {
+        self.inner.delete(&self.key(name))
+    }
+
+    fn expire(&self, prefix: &str, now: i64) -> Result<usize> {
+        self.inner.expire(&self.key(prefix), now)
+    }
+
+    fn get(&self, name: &str) -> Result<Value> {
+        self.inner.get(&self.key(name))
+    }
+
+    fn list(&self) -> Result<Vec<StoreValueMetadata>> {
+        Ok(self
+            .inner
+            .list()?
+            .into_iter()
+            .filter_map(|mut m| {
+                m.name = m.name.strip_prefix(&self.prefix)?.to_string();
+                Some(m)
+            })
+            .collect())
+    }
+
+    fn put(&self, name: &str, value: &Value) -> Result<usize> {
+        self.inner.put(&self.key(name), value)
+    }
+
+    fn stat(&self, name: &str) -> Result<Option<StoreValueMetadata>> {
+        let metadata = self.inner.stat(&self.key(name))?;
+        Ok(metadata.map(|mut m| {
+            m.name = name.to_string();
+            m
+        }))
+    }
+
+    fn transaction(&self, mut f: TransactionFn<'_>) -> Result<()> {
+        let prefix = self.prefix.as_str();
+        self.inner.transaction(Box::new(|inner| {
+            f(&mut NamespacedTransaction { inner, prefix })
+        }))
+    }
+
+    fn update(&self, name: &str, f: UpdateFn<'_>, default_v: Option<Value>) -> Result<Value> {
+        self.inner.update(&self.key(name), f, default_v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{io::empty, sync::Arc};
+
+    use crate::{EvaluationBuilder, MemoryStore, NamespacedStore, StoreBackend};
+
+    #[test]
+    fn isolation() {
+        let backend = Arc::new(MemoryStore::default());
+        let a = NamespacedStore::new(backend.clone(), "a");
+        let b = NamespacedStore::new(backend.clone(), "b");
+        a.put("x", &json!(1)).unwrap();
+        assert_eq!(json!(null), b.get("x").unwrap());
+        assert!(b.list().unwrap().is_empty());
+        assert_eq!("x", a.stat("x").unwrap().unwrap().name());
+        assert!(b.stat("x").unwrap().is_none());
+        assert_eq!((1, 0), (a.count("").unwrap(), b.count("").unwrap()));
+
+        let script = "return require('@lmb'):update('x', function(v) return v + 1 end, 0)";
+        let e = EvaluationBuilder::new(script, empty())
+            .store(b)
+            .build()
+            .unwrap();
+        assert_eq!(&json!(1), e.evaluate().unwrap().payload());
+        assert_eq!(json!(1), a.get("x").unwrap());
+        assert_eq!(1, a.delete("x").unwrap());
+        assert_eq!(json!(1), backend.get("b:x").unwrap());
+    }
+}
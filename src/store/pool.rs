@@ -0,0 +1,79 @@
+use parking_lot::Mutex;
+use rusqlite::{Connection, OpenFlags};
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+use tracing::trace;
+
+use super::BUSY_TIMEOUT;
+use crate::Result;
+
+/// Pool of read-only connections to the database, so reads run concurrently with each other
+/// and with the single writer. Connections are opened on demand, and at most `size` idle
+/// connections are kept for reuse.
+#[derive(Debug)]
+pub(crate) struct ReaderPool {
+    idle: Mutex<Vec<Connection>>,
+    path: PathBuf,
+    size: usize,
+}
+
+impl ReaderPool {
+    pub(crate) fn new(path: &Path, size: usize) -> Self {
+        Self {
+            idle: Mutex::new(Vec::with_capacity(size)),
+            path: path.to_path_buf(),
+            size,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn idle_count(&self) -> usize {
+        self.idle.lock().len()
+    }
+
+    /// Take an idle connection, or open one when none is idle.
+    pub(crate) fn get(&self) -> Result<PooledConnection<'_>> {
+        let idle = self.idle.lock().pop();
+        let conn = if let Some(conn) = idle {
+            conn
+        } else {
+            trace!(path = ?self.path, "open reader");
+            let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+            let conn = Connection::open_with_flags(&self.path, flags)?;
+            conn.pragma_update(None, "busy_timeout", BUSY_TIMEOUT)?;
+            conn
+        };
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: self,
+        })
+    }
+}
+
+/// Connection taken from [`ReaderPool`], which is returned to the pool when dropped.
+pub(crate) struct PooledConnection<'a> {
+    conn: Option<Connection>,
+    pool: &'a ReaderPool,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().expect("connection is returned")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        let mut idle = self.pool.idle.lock();
+        if idle.len() < self.pool.size {
+            idle.push(conn);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::str::FromStr;
+
+use crate::Error;
+
+/// Policy applied when the store is full.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum EvictionPolicy {
+    /// Reject the value with [`Error::StoreFull`].
+    #[default]
+    Reject,
+    /// Evict the least recently used values until the value fits.
+    Lru,
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "lru" => Ok(Self::Lru),
+            _ => Err(Error::InvalidEvictionPolicy(s.to_string())),
+        }
+    }
+}
+
+/// Limits of the store, so a runaway script cannot fill the disk.
+/// Sizes are measured the same way as [`crate::StoreValueMetadata::size`].
+///
+/// ```rust
+/// use lmb::*;
+///
+/// # fn main() -> Result<()> {
+/// let mut quota = StoreQuota::default();
+/// quota.set_max_value_size(Some(4));
+///
+/// let mut store = Store::default();
+/// store.set_quota(quota);
+/// store.put("a", &"abcd".into())?;
+/// assert!(matches!(
+///     store.put("a", &"abcde".into()),
+///     Err(Error::ValueTooLarge { .. })
+/// ));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StoreQuota {
+    eviction: EvictionPolicy,
+    max_size: Option<usize>,
+    max_value_size: Option<usize>,
+}
+
+impl StoreQuota {
+    /// Get eviction policy.
+    pub fn eviction(&self) -> EvictionPolicy {
+        self.eviction
+    }
+
+    /// Get max total size of values in bytes.
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    /// Get max size of a single value in bytes.
+    pub fn max_value_size(&self) -> Option<usize> {
+        self.max_value_size
+    }
+
+    /// Set eviction policy when the store is full.
+    pub fn set_eviction(&mut self, eviction: EvictionPolicy) -> &mut Self {
+        self.eviction = eviction;
+        self
+    }
+
+    /// Set or unset max total size of values in bytes.
+    pub fn set_max_size(&mut self, max_size: Option<usize>) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Set or unset max size of a single value in bytes.
+    pub fn set_max_value_size(&mut self, max_value_size: Option<usize>) -> &mut Self {
+        self.max_value_size = max_value_size;
+        self
+    }
+}
@@ -0,0 +1,258 @@
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use redis::{Client, Commands as _, Connection};
+use serde_json::Value;
+use std::fmt;
+use tracing::{debug, trace, trace_span};
+
+use super::{
+    BufferedTransaction, Store, StoreBackend, StoreValueMetadata, TransactionFn, UpdateFn,
+};
+use crate::Result;
+
+/// Prefix of Redis keys holding values of the store.
+const KEY_PREFIX: &str = "lmb:store:";
+
+/// Store backed by Redis, which can be shared by multiple instances.
+///
+/// Each value is kept in a Redis hash along with its metadata, and [`StoreBackend::update`]
+/// and [`StoreBackend::transaction`] are guarded with `WATCH`/`MULTI`, so concurrent updates
+/// are retried instead of lost.
+pub struct RedisStore {
+    client: Client,
+    conn: Mutex<Connection>,
+}
+
+impl fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("client", &self.client)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisStore {
+    /// Connect to Redis with URL e.g. `redis://127.0.0.1/`.
+    pub fn new(url: &str) -> Result<Self> {
+        debug!(url, "open redis store");
+        let client = Client::open(url)?;
+        let conn = client.get_connection()?;
+        Ok(Self {
+            client,
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn key(name: &str) -> String {
+        format!("{KEY_PREFIX}{name}")
+    }
+
+    fn write_pipeline(pipe: &mut redis::Pipeline, key: &str, value: &Value) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        pipe.hset_multiple(
+            key,
+            &[
+                ("value", rmp_serde::to_vec(value)?),
+                ("size", Store::get_size(value).to_string().into_bytes()),
+                ("type_hint", Store::type_hint(value).as_bytes().to_vec()),
+                ("updated_at", now.clone().into_bytes()),
+            ],
+        )
+        .ignore()
+        .hset_nx(key, "created_at", now)
+        .ignore();
+        Ok(())
+    }
+}
+
+fn parse_time(value: Option<&str>) -> DateTime<Utc> {
+    value
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        .map_or_else(Utc::now, |t| t.with_timezone(&Utc))
+}
+
+impl StoreBackend for RedisStore {
+    fn delete(&self, name: &str) -> Result<usize> {
+        let mut conn = self.conn.lock();
+        Ok(conn.del(Self::key(name))?)
+    }
+
+    fn get(&self, name: &str) -> Result<Value> {
+        let mut conn = self.conn.lock();
+        let _s = trace_span!("redis_store_get", name).entered();
+        let value: Option<Vec<u8>> = conn.hget(Self::key(name), "value")?;
+        match value {
+            None => {
+                trace!("no_value");
+                Ok(Value::Null)
+            }
+            Some(v) => Ok(rmp_serde::from_slice::<Value>(&v)?),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<StoreValueMetadata>> {
+        let mut conn = self.conn.lock();
+        let keys: Vec<String> = conn.scan_match(format!("{KEY_PREFIX}*"))?.collect();
+        let mut res = vec![];
+        for key in keys {
+            let (size, type_hint, created_at, updated_at): (
+                Option<usize>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+            ) = conn.hget(&key, &["size", "type_hint", "created_at", "updated_at"])?;
+            res.push(StoreValueMetadata {
+                name: key.trim_start_matches(KEY_PREFIX).to_string(),
+                size: size.unwrap_or_default(),
+                type_hint: type_hint.unwrap_or_default(),
+                created_at: parse_time(created_at.as_deref()),
+                updated_at: parse_time(updated_at.as_deref()),
+            });
+        }
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(res)
+    }
+
+    fn put(&self, name: &str, value: &Value) -> Result<usize> {
+        let mut conn = self.conn.lock();
+        let _s = trace_span!("redis_store_insert", name).entered();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        Self::write_pipeline(&mut pipe, &Self::key(name), value)?;
+        pipe.query::<()>(&mut *conn)?;
+        Ok(1)
+    }
+
+    fn transaction(&self, mut f: TransactionFn<'_>) -> Result<()> {
+        let mut conn = self.conn.lock();
+        let _s = trace_span!("redis_store_transaction").entered();
+        loop {
+            let res = {
+                // keys are watched as they are read
+                let mut tx = BufferedTransaction::new(|name| {
+                    let key = Self::key(name);
+                    redis::cmd("WATCH").arg(&key).query::<()>(&mut *conn)?;
+                    let value: Option<Vec<u8>> = conn.hget(&key, "value")?;
+                    match value {
+                        Some(v) => Ok(rmp_serde::from_slice(&v)?),
+                        None => Ok(Value::Null),
+                    }
+                });
+                f(&mut tx).map(|()| tx.into_written())
+            };
+            let written = match res {
+                Ok(written) => written,
+                Err(e) => {
+                    trace!("failed");
+                    redis::cmd("UNWATCH").query::<()>(&mut *conn)?;
+                    return Err(e.into());
+                }
+            };
+            if written.is_empty() {
+                redis::cmd("UNWATCH").query::<()>(&mut *conn)?;
+                return Ok(());
+            }
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for (name, value) in &written {
+                Self::write_pipeline(&mut pipe, &Self::key(name), value)?;
+            }
+            // EXEC replies nil when a watched key is modified by others
+            if pipe.query::<Option<()>>(&mut *conn)?.is_some() {
+                trace!("committed");
+                return Ok(());
+            }
+            trace!("conflict");
+        }
+    }
+
+    fn update(&self, name: &str, mut f: UpdateFn<'_>, default_v: Option<Value>) -> Result<Value> {
+        let mut conn = self.conn.lock();
+        let key = Self::key(name);
+        let _s = trace_span!("redis_store_update", name).entered();
+        loop {
+            redis::cmd("WATCH").arg(&key).query::<()>(&mut *conn)?;
+            let value: Option<Vec<u8>> = conn.hget(&key, "value")?;
+            let mut value = match value {
+                Some(v) => rmp_serde::from_slice(&v)?,
+                None => default_v.clone().unwrap_or(Value::Null),
+            };
+            if f(&mut value).is_err() {
+                // the function throws an error instead of returing a new value,
+                // return the old value instead.
+                trace!("failed");
+                redis::cmd("UNWATCH").query::<()>(&mut *conn)?;
+                return Ok(value);
+            }
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            Self::write_pipeline(&mut pipe, &key, &value)?;
+            // EXEC replies nil when the watched key is modified by others
+            if pipe.query::<Option<()>>(&mut *conn)?.is_some() {
+                trace!("updated");
+                return Ok(value);
+            }
+            trace!("conflict");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{env, io::empty, sync::Arc, thread};
+
+    use crate::{EvaluationBuilder, RedisStore, StoreBackend};
+
+    fn open_store() -> RedisStore {
+        let url = env::var("LMB_TEST_REDIS_URL").expect("LMB_TEST_REDIS_URL is required");
+        RedisStore::new(&url).unwrap()
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at LMB_TEST_REDIS_URL"]
+    fn concurrency() {
+        let script = r#"
+        return require('@lmb'):update('redis-concurrency', function(v)
+            return v+1
+        end, 0)
+        "#;
+
+        let store = Arc::new(open_store());
+        store.delete("redis-concurrency").unwrap();
+
+        let mut threads = vec![];
+        for _ in 0..=100 {
+            let store = Arc::new(open_store());
+            threads.push(thread::spawn(move || {
+                let e = EvaluationBuilder::new(script, empty())
+                    .store(store)
+                    .build()
+                    .unwrap();
+                e.evaluate().unwrap();
+            }));
+        }
+        for t in threads {
+            let _ = t.join();
+        }
+        assert_eq!(json!(101), store.get("redis-concurrency").unwrap());
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at LMB_TEST_REDIS_URL"]
+    fn get_put_delete() {
+        let store = open_store();
+        store.delete("redis-a").unwrap();
+        assert_eq!(json!(null), store.get("redis-a").unwrap());
+        store.put("redis-a", &json!({ "a": 1 })).unwrap();
+        assert_eq!(json!({ "a": 1 }), store.get("redis-a").unwrap());
+
+        let values = store.list().unwrap();
+        let value = values.iter().find(|v| v.name() == "redis-a").unwrap();
+        assert_eq!("object", value.type_hint());
+        assert_eq!(1 + 8, value.size());
+
+        assert_eq!(1, store.delete("redis-a").unwrap());
+        assert_eq!(json!(null), store.get("redis-a").unwrap());
+    }
+}
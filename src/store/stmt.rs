@@ -1,12 +1,79 @@
+pub(crate) const SQL_COUNT_VALUES_BY_PREFIX: &str =
+    "SELECT COUNT(*) FROM store WHERE substr(name, 1, length(?1)) = ?1";
+
+pub(crate) const SQL_DELETE_EXPIRED_VALUES: &str = "
+    DELETE FROM store WHERE substr(name, 1, length(?1)) = ?1 AND expires_at <= ?2
+";
+
+pub(crate) const SQL_DELETE_UNREFERENCED_BLOBS: &str = "DELETE FROM store_blobs WHERE refs <= 0";
+
 pub(crate) const SQL_DELETE_VALUE_BY_NAME: &str = "DELETE FROM store WHERE name = ?1";
 
 pub(crate) const SQL_GET_ALL_VALUES: &str = "
     SELECT name, size, type_hint, created_at, updated_at FROM store
 ";
 
-pub(crate) const SQL_GET_VALUE_BY_NAME: &str = "SELECT value, type_hint FROM store WHERE name = ?1";
+pub(crate) const SQL_GET_VALUE_METADATA_BY_NAME: &str = "
+    SELECT name, size, type_hint, created_at, updated_at FROM store WHERE name = ?1
+";
+
+pub(crate) const SQL_GET_ALL_ENCRYPTED_VALUES: &str =
+    "SELECT name, value, key_id, chunks FROM store";
+
+pub(crate) const SQL_GET_BLOB: &str = "SELECT data FROM store_blobs WHERE hash = ?1";
+
+pub(crate) const SQL_GET_CHUNKS_BY_NAME: &str = "SELECT chunks FROM store WHERE name = ?1";
+
+pub(crate) const SQL_GET_EXPIRED_CHUNKED_VALUES: &str = "
+    SELECT name FROM store
+    WHERE substr(name, 1, length(?1)) = ?1 AND expires_at <= ?2 AND chunks IS NOT NULL
+";
+
+pub(crate) const SQL_GET_INVOCATION_BY_ID: &str = "
+    SELECT id, name, started_at, duration_us, used_memory, result, error,
+    script, input, state, env, key_id FROM history WHERE id = ?1
+";
+
+pub(crate) const SQL_GET_LATEST_INVOCATIONS: &str = "
+    SELECT id, name, started_at, duration_us, used_memory, result, error,
+    script, input, state, env, key_id FROM history ORDER BY id DESC LIMIT ?1
+";
+
+pub(crate) const SQL_GET_LEAST_RECENTLY_USED: &str = "
+    SELECT name, size FROM store WHERE name != ?1
+    ORDER BY COALESCE(accessed_at, updated_at), id
+";
+
+pub(crate) const SQL_GET_TOTAL_SIZE: &str =
+    "SELECT COALESCE(SUM(size), 0) FROM store WHERE name != ?1";
+
+pub(crate) const SQL_GET_VALUE_BY_NAME: &str =
+    "SELECT value, type_hint, key_id, chunks FROM store WHERE name = ?1";
+
+pub(crate) const SQL_INSERT_INVOCATION: &str = "
+    INSERT INTO history (
+      name, started_at, duration_us, used_memory, result, error, script, input, state, env,
+      key_id
+    )
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+";
+
+pub(crate) const SQL_RELEASE_BLOB: &str = "UPDATE store_blobs SET refs = refs - 1 WHERE hash = ?1";
+
+pub(crate) const SQL_TOUCH_VALUE_BY_NAME: &str =
+    "UPDATE store SET accessed_at = ?2 WHERE name = ?1";
+
+pub(crate) const SQL_UPDATE_ENCRYPTED_VALUE: &str =
+    "UPDATE store SET value = ?2, key_id = ?3, chunks = ?4 WHERE name = ?1";
+
+pub(crate) const SQL_UPSERT_BLOB: &str = "
+    INSERT INTO store_blobs (hash, data, refs) VALUES (?1, ?2, 1)
+    ON CONFLICT(hash) DO UPDATE SET refs = refs + 1
+";
 
 pub(crate) const SQL_UPSERT_STORE: &str = r#"
-    INSERT INTO store (name, value, size, type_hint) VALUES (?1, ?2, ?3, ?4)
-    ON CONFLICT(name) DO UPDATE SET value = ?2, size = ?3, type_hint = ?4, updated_at = CURRENT_TIMESTAMP
+    INSERT INTO store (name, value, size, type_hint, key_id, accessed_at, chunks, expires_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+    ON CONFLICT(name) DO UPDATE SET value = ?2, size = ?3, type_hint = ?4, key_id = ?5,
+    updated_at = CURRENT_TIMESTAMP, accessed_at = ?6, chunks = ?7, expires_at = ?8
 "#;
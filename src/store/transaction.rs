@@ -0,0 +1,76 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::Result;
+
+/// Function passed to [`crate::StoreBackend::transaction`] and [`StoreTransaction::savepoint`].
+/// Backends with optimistic concurrency control may call it more than once.
+pub type TransactionFn<'a> = Box<dyn FnMut(&mut dyn StoreTransaction) -> mlua::Result<()> + 'a>;
+
+/// Values of the store read and written in a transaction,
+/// see [`crate::StoreBackend::transaction`].
+pub trait StoreTransaction {
+    /// Get value by name. [`Value::Null`] is returned when the value is absent.
+    fn get(&mut self, name: &str) -> Result<Value>;
+
+    /// Put (insert or update) the value.
+    fn put(&mut self, name: &str, value: &Value) -> Result<usize>;
+
+    /// Run the function in a nested savepoint. When the function fails, only writes
+    /// in the savepoint are rolled back, and the enclosing transaction can continue.
+    fn savepoint(&mut self, f: TransactionFn<'_>) -> Result<()>;
+}
+
+/// Transaction buffering writes in memory for backends without savepoints,
+/// which apply the writes once the function succeeds.
+pub(crate) struct BufferedTransaction<F>
+where
+    F: FnMut(&str) -> Result<Value>,
+{
+    read: F,
+    written: HashMap<String, Value>,
+}
+
+impl<F> BufferedTransaction<F>
+where
+    F: FnMut(&str) -> Result<Value>,
+{
+    /// Create a transaction reading values absent from the buffer with the function.
+    pub(crate) fn new(read: F) -> Self {
+        Self {
+            read,
+            written: HashMap::new(),
+        }
+    }
+
+    /// Values written in the transaction.
+    pub(crate) fn into_written(self) -> HashMap<String, Value> {
+        self.written
+    }
+}
+
+impl<F> StoreTransaction for BufferedTransaction<F>
+where
+    F: FnMut(&str) -> Result<Value>,
+{
+    fn get(&mut self, name: &str) -> Result<Value> {
+        match self.written.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => (self.read)(name),
+        }
+    }
+
+    fn put(&mut self, name: &str, value: &Value) -> Result<usize> {
+        self.written.insert(name.to_string(), value.clone());
+        Ok(1)
+    }
+
+    fn savepoint(&mut self, mut f: TransactionFn<'_>) -> Result<()> {
+        let saved = self.written.clone();
+        if let Err(e) = f(self) {
+            self.written = saved;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! Helpers to test Lua scripts from Rust, which evaluate a script against fixtures
+//! with an in-memory store.
+//!
+//! ```rust
+//! # use serde_json::json;
+//! use lmb::testing::TestRunner;
+//!
+//! let script = r#"
+//! local m = require('@lmb')
+//! return m:get('greeting') .. ', ' .. io.read('*a')
+//! "#;
+//! TestRunner::new(script)
+//!     .with_input("world")
+//!     .with_store_kv("greeting", json!("hello"))
+//!     .expect_result(json!("hello, world"));
+//! ```
+
+use serde_json::Value;
+use std::io::Cursor;
+
+use crate::{EvaluationBuilder, Permissions, Result, Solution, Store};
+
+/// Runner evaluating a script against fixtures, see [the module](self).
+#[derive(Debug)]
+pub struct TestRunner {
+    args: Vec<String>,
+    input: Vec<u8>,
+    permissions: Permissions,
+    script: String,
+    state: Option<Value>,
+    store: Store,
+    store_kv: Vec<(String, Value)>,
+}
+
+impl TestRunner {
+    /// Create a runner of the script with empty input and an empty in-memory store.
+    pub fn new<S: Into<String>>(script: S) -> Self {
+        Self {
+            args: vec![],
+            input: vec![],
+            permissions: Permissions::default(),
+            script: script.into(),
+            state: None,
+            store: Store::default(),
+            store_kv: vec![],
+        }
+    }
+
+    /// Set positional arguments of the script.
+    pub fn with_args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the input read by `io.read`.
+    pub fn with_input<B: Into<Vec<u8>>>(&mut self, input: B) -> &mut Self {
+        self.input = input.into();
+        self
+    }
+
+    /// Set permissions granted to the script.
+    pub fn with_permissions(&mut self, permissions: Permissions) -> &mut Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Set the application state, see [`EvaluationBuilder::app_state`].
+    pub fn with_state(&mut self, state: Value) -> &mut Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Put the value into the store before each run.
+    pub fn with_store_kv<S: Into<String>>(&mut self, name: S, value: Value) -> &mut Self {
+        self.store_kv.push((name.into(), value));
+        self
+    }
+
+    /// Get the store, to check values written by the script after a run.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Put values into the store and evaluate the script.
+    ///
+    /// ```rust
+    /// # use serde_json::json;
+    /// use lmb::testing::TestRunner;
+    ///
+    /// # fn main() -> lmb::Result<()> {
+    /// let runner = TestRunner::new("return require('@lmb'):put('a', 1)");
+    /// runner.run()?;
+    /// assert_eq!(json!(1), runner.store().get("a")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn run(&self) -> Result<Solution<Cursor<Vec<u8>>>> {
+        for (name, value) in &self.store_kv {
+            self.store.put(name, value)?;
+        }
+        EvaluationBuilder::new(&self.script, Cursor::new(self.input.clone()))
+            .app_state(self.state.clone())
+            .args(self.args.clone())
+            .name("test")
+            .permissions(self.permissions.clone())
+            .store(self.store.clone())
+            .build()?
+            .evaluate()
+    }
+
+    /// Run the script and assert the payload equals the expected value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the evaluation fails or the payload differs.
+    pub fn expect_result(&self, expected: Value) -> Solution<Cursor<Vec<u8>>> {
+        let solution = match self.run() {
+            Ok(solution) => solution,
+            Err(err) => panic!("failed to evaluate the script: {err}"),
+        };
+        assert_eq!(&expected, solution.payload(), "unexpected payload");
+        solution
+    }
+
+    /// Run the script and assert it fails with an error containing the message.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the evaluation succeeds or fails with another error.
+    ///
+    /// ```rust
+    /// use lmb::testing::TestRunner;
+    ///
+    /// TestRunner::new("error('boom')").expect_error("boom");
+    /// ```
+    pub fn expect_error(&self, message: &str) {
+        match self.run() {
+            Ok(solution) => panic!("expected an error but got {}", solution.payload()),
+            Err(err) => {
+                let err = err.to_string();
+                assert!(err.contains(message), "unexpected error {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::TestRunner;
+
+    #[test]
+    fn runner() {
+        let script = r#"
+        local m = require('@lmb')
+        m:put('count', m:get('count') + 1)
+        return { arg = m.args[1], state = m.state.name, count = m:get('count') }
+        "#;
+        let mut runner = TestRunner::new(script);
+        runner
+            .with_args(["a"])
+            .with_state(json!({ "name": "lmb" }))
+            .with_store_kv("count", json!(1));
+        runner.expect_result(json!({ "arg": "a", "state": "lmb", "count": 2 }));
+        // values are put again before each run
+        runner.expect_result(json!({ "arg": "a", "state": "lmb", "count": 2 }));
+        assert_eq!(json!(2), runner.store().get("count").unwrap());
+    }
+}
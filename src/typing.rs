@@ -0,0 +1,434 @@
+use full_moon::{
+    ast::{
+        types::{TypeFieldKey, TypeInfo},
+        Ast, Call, Expression, FunctionArgs, FunctionCall, Index, LocalAssignment, Prefix, Stmt,
+        Suffix, Var, VarExpression,
+    },
+    node::Node,
+    tokenizer::{TokenReference, TokenType},
+    visitors::Visitor,
+};
+use once_cell::sync::Lazy;
+use std::{collections::HashMap, ops::Range};
+
+use crate::{generate_definitions, DECLARATIONS};
+
+/// Luau type definitions of modules provided by lmb, which can be loaded by editor tooling.
+pub static TYPE_DEFINITIONS: Lazy<String> = Lazy::new(generate_definitions);
+
+static DEFINITIONS: Lazy<Definitions> = Lazy::new(|| {
+    let ast = full_moon::parse(&TYPE_DEFINITIONS).expect("failed to parse type definitions");
+    Definitions::from_ast(&ast)
+});
+
+/// Type error found by [`crate::LuaCheck::check_types`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeError {
+    message: String,
+    span: Range<usize>,
+}
+
+impl TypeError {
+    /// Get the message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Get the span in bytes.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+}
+
+#[derive(Debug)]
+enum Member {
+    Callback {
+        max: Option<usize>,
+        min: usize,
+        method: bool,
+        returns: Option<String>,
+    },
+    Field(Option<String>),
+}
+
+#[derive(Debug, Default)]
+struct Definitions {
+    types: HashMap<String, HashMap<String, Member>>,
+}
+
+fn token_text(token: &TokenReference) -> String {
+    token.token().to_string()
+}
+
+fn token_span(token: &TokenReference) -> Range<usize> {
+    token.token().start_position().bytes()..token.token().end_position().bytes()
+}
+
+fn node_span<N: Node>(node: &N) -> Range<usize> {
+    node.range()
+        .map_or(0..0, |(start, end)| start.bytes()..end.bytes())
+}
+
+/// Name of the declared type if the type refers to one.
+fn type_name(info: &TypeInfo) -> Option<String> {
+    match info {
+        TypeInfo::Basic(name) => Some(token_text(name)),
+        TypeInfo::Optional { base, .. } => type_name(base),
+        _ => None,
+    }
+}
+
+impl Definitions {
+    fn from_ast(ast: &Ast) -> Self {
+        let mut definitions = Self::default();
+        for stmt in ast.nodes().stmts() {
+            let declaration = match stmt {
+                Stmt::ExportedTypeDeclaration(d) => d.type_declaration(),
+                Stmt::TypeDeclaration(d) => d,
+                _ => continue,
+            };
+            let TypeInfo::Table { fields, .. } = declaration.type_definition() else {
+                continue;
+            };
+            let mut members = HashMap::new();
+            for field in fields {
+                let TypeFieldKey::Name(name) = field.key() else {
+                    continue;
+                };
+                members.insert(token_text(name), Self::member(field.value()));
+            }
+            definitions
+                .types
+                .insert(token_text(declaration.type_name()), members);
+        }
+        definitions
+    }
+
+    fn member(info: &TypeInfo) -> Member {
+        let TypeInfo::Callback {
+            arguments,
+            return_type,
+            ..
+        } = info
+        else {
+            return Member::Field(type_name(info));
+        };
+        let mut method = false;
+        let mut min = 0;
+        let mut max = Some(0);
+        for (idx, argument) in arguments.iter().enumerate() {
+            if idx == 0
+                && argument
+                    .name()
+                    .is_some_and(|(n, _)| token_text(n) == "self")
+            {
+                method = true;
+                continue;
+            }
+            match argument.type_info() {
+                TypeInfo::Variadic { .. } | TypeInfo::VariadicPack { .. } => max = None,
+                TypeInfo::Optional { .. } => max = max.map(|m| m + 1),
+                _ => {
+                    max = max.map(|m| m + 1);
+                    min = max.unwrap_or(min + 1);
+                }
+            }
+        }
+        Member::Callback {
+            max,
+            min,
+            method,
+            returns: type_name(return_type),
+        }
+    }
+
+    fn module_type(&self, module: &str) -> Option<&str> {
+        DECLARATIONS
+            .iter()
+            .find(|d| d.module == Some(module))
+            .map(|d| d.name)
+            .filter(|t| self.types.contains_key(*t))
+    }
+}
+
+/// Number of arguments, or the minimum number if the last argument expands to multiple values.
+fn count_arguments(args: &FunctionArgs) -> (usize, bool) {
+    match args {
+        FunctionArgs::Parentheses { arguments, .. } => {
+            let expands = arguments.last().is_some_and(|last| {
+                matches!(last.value(), Expression::FunctionCall(_))
+                    || matches!(last.value(), Expression::Symbol(s) if token_text(s) == "...")
+            });
+            (arguments.len(), expands)
+        }
+        _ => (1, false),
+    }
+}
+
+fn string_argument(args: &FunctionArgs) -> Option<String> {
+    let token = match args {
+        FunctionArgs::String(token) => token,
+        FunctionArgs::Parentheses { arguments, .. } if arguments.len() == 1 => {
+            match arguments.iter().next() {
+                Some(Expression::String(token)) => token,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    match token.token().token_type() {
+        TokenType::StringLiteral { literal, .. } => Some(literal.to_string()),
+        _ => None,
+    }
+}
+
+/// Lightweight type analysis of values of modules provided by lmb.
+/// Local variables assigned from `require` are tracked by name without scoping.
+struct TypeChecker<'a> {
+    definitions: &'a Definitions,
+    errors: Vec<TypeError>,
+    locals: HashMap<String, String>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn check_arguments(
+        &mut self,
+        name: &str,
+        (min, max): (usize, Option<usize>),
+        args: &FunctionArgs,
+    ) {
+        let (count, expands) = count_arguments(args);
+        let too_few = count < min && !expands;
+        let too_many = max.is_some_and(|max| count > max);
+        if too_few || too_many {
+            let expected = match max {
+                Some(max) if max == min => format!("{min}"),
+                Some(max) => format!("{min} to {max}"),
+                None => format!("at least {min}"),
+            };
+            self.errors.push(TypeError {
+                message: format!("{name} expects {expected} argument(s) but got {count}"),
+                span: node_span(args),
+            });
+        }
+    }
+
+    /// Resolve the type of the expression made of the prefix and suffixes.
+    /// Errors are reported only when `report` is true.
+    fn resolve(&mut self, prefix: &Prefix, suffixes: &[&Suffix], report: bool) -> Option<String> {
+        let definitions = self.definitions;
+        let Prefix::Name(name) = prefix else {
+            return None;
+        };
+        let name = token_text(name);
+        let mut suffixes = suffixes.iter();
+        let mut current = match self.locals.get(&name) {
+            Some(t) => t.clone(),
+            None if name == "require" => {
+                let Some(Suffix::Call(Call::AnonymousCall(args))) = suffixes.next() else {
+                    return None;
+                };
+                let module = string_argument(args)?;
+                if !module.starts_with("@lmb") {
+                    return None;
+                }
+                let Some(t) = definitions.module_type(&module) else {
+                    if report {
+                        self.errors.push(TypeError {
+                            message: format!("unknown module {module}"),
+                            span: node_span(args),
+                        });
+                    }
+                    return None;
+                };
+                t.to_string()
+            }
+            None => return None,
+        };
+        while let Some(suffix) = suffixes.next() {
+            let members = definitions.types.get(&current)?;
+            let name = match suffix {
+                Suffix::Call(Call::MethodCall(m)) => m.name(),
+                Suffix::Index(Index::Dot { name, .. }) => name,
+                _ => return None,
+            };
+            let member_name = token_text(name);
+            let Some(member) = members.get(&member_name) else {
+                if report {
+                    self.errors.push(TypeError {
+                        message: format!("{member_name} is not a member of {current}"),
+                        span: token_span(name),
+                    });
+                }
+                return None;
+            };
+            current = match (suffix, member) {
+                (
+                    Suffix::Call(Call::MethodCall(m)),
+                    Member::Callback {
+                        max, min, returns, ..
+                    },
+                ) => {
+                    if report {
+                        self.check_arguments(&member_name, (*min, *max), m.args());
+                    }
+                    returns.clone()?
+                }
+                (Suffix::Call(Call::MethodCall(m)), Member::Field(_)) => {
+                    if report {
+                        self.errors.push(TypeError {
+                            message: format!("{member_name} of {current} is not callable"),
+                            span: token_span(m.name()),
+                        });
+                    }
+                    return None;
+                }
+                (
+                    _,
+                    Member::Callback {
+                        max,
+                        min,
+                        method,
+                        returns,
+                    },
+                ) => {
+                    // a dot call passes self explicitly
+                    let Some(Suffix::Call(Call::AnonymousCall(args))) = suffixes.next() else {
+                        return None;
+                    };
+                    let extra = usize::from(*method);
+                    if report {
+                        self.check_arguments(
+                            &member_name,
+                            (*min + extra, max.map(|m| m + extra)),
+                            args,
+                        );
+                    }
+                    returns.clone()?
+                }
+                (_, Member::Field(t)) => t.clone()?,
+            };
+        }
+        Some(current)
+    }
+}
+
+impl<'a> Visitor for TypeChecker<'a> {
+    fn visit_function_call(&mut self, node: &FunctionCall) {
+        let suffixes = node.suffixes().collect::<Vec<_>>();
+        self.resolve(node.prefix(), &suffixes, true);
+    }
+
+    fn visit_local_assignment(&mut self, node: &LocalAssignment) {
+        let expressions = node.expressions().iter().collect::<Vec<_>>();
+        for (idx, name) in node.names().iter().enumerate() {
+            let resolved = match expressions.get(idx) {
+                Some(Expression::FunctionCall(call)) => {
+                    let suffixes = call.suffixes().collect::<Vec<_>>();
+                    self.resolve(call.prefix(), &suffixes, false)
+                }
+                Some(Expression::Var(Var::Name(other))) => {
+                    self.locals.get(&token_text(other)).cloned()
+                }
+                _ => None,
+            };
+            match resolved {
+                Some(t) => self.locals.insert(token_text(name), t),
+                None => self.locals.remove(&token_text(name)),
+            };
+        }
+    }
+
+    fn visit_var_expression(&mut self, node: &VarExpression) {
+        let suffixes = node.suffixes().collect::<Vec<_>>();
+        self.resolve(node.prefix(), &suffixes, true);
+    }
+}
+
+/// Check the parsed script against [`TYPE_DEFINITIONS`].
+pub(crate) fn check_types(ast: &Ast) -> Vec<TypeError> {
+    let mut checker = TypeChecker {
+        definitions: &DEFINITIONS,
+        errors: vec![],
+        locals: HashMap::new(),
+    };
+    checker.visit_ast(ast);
+    checker.errors.sort_by_key(|e| e.span.start);
+    checker.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::{check_types, DEFINITIONS};
+    use crate::{DECLARATIONS, EXAMPLES};
+
+    fn messages(script: &str) -> Vec<String> {
+        let ast = full_moon::parse(script).unwrap();
+        check_types(&ast)
+            .into_iter()
+            .map(|e| e.message().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn definitions() {
+        for declaration in DECLARATIONS {
+            let members = DEFINITIONS.types.get(declaration.name).unwrap();
+            assert_eq!(declaration.members.len(), members.len());
+            if let Some(module) = declaration.module {
+                assert!(DEFINITIONS.module_type(module).is_some(), "{module}");
+            }
+        }
+    }
+
+    #[test_case("return require('@lmb'):get('a')")]
+    #[test_case("local m = require('@lmb'); return m:update('a', function(v) return v end)")]
+    #[test_case("local m = require('@lmb'); return m.request")]
+    #[test_case("local m = require('@lmb'); m.response = { status_code = 200 }")]
+    #[test_case("local h = require('@lmb/http'); local res = h:fetch('a'); return res.ok")]
+    #[test_case("local j = require('@lmb/json'); return j.encode(j, {})")]
+    #[test_case("local m = require('@lmb'); return m:put(unpack({ 'a', 1 }))")]
+    #[test_case("local m = require('not-lmb'); return m:anything()")]
+    fn valid(script: &str) {
+        assert!(messages(script).is_empty(), "{:?}", messages(script));
+    }
+
+    #[test_case("return require('@lmb/unknown')", "unknown module @lmb/unknown")]
+    #[test_case("return require('@lmb'):gett('a')", "gett is not a member of Lmb")]
+    #[test_case("return require('@lmb'):get()", "get expects 1 argument(s) but got 0")]
+    #[test_case(
+        "local m = require('@lmb'); return m:update('a')",
+        "update expects 2 to 3 argument(s) but got 1"
+    )]
+    #[test_case(
+        "local h = require('@lmb/http'); local res = h:fetch('a'); return res.status",
+        "status is not a member of HttpResponse"
+    )]
+    #[test_case(
+        "local m = require('@lmb'); return m:request()",
+        "request of Lmb is not callable"
+    )]
+    #[test_case(
+        "local j = require('@lmb/json'); return j.encode({})",
+        "encode expects 2 to 3 argument(s) but got 1"
+    )]
+    fn invalid(script: &str, expected: &str) {
+        assert_eq!(vec![expected.to_string()], messages(script));
+    }
+
+    #[test]
+    fn examples() {
+        for example in EXAMPLES.iter() {
+            let messages = messages(example.script());
+            assert!(messages.is_empty(), "{}: {messages:?}", example.name());
+        }
+    }
+
+    #[test]
+    fn shadowed() {
+        let script = "local m = require('@lmb'); local m = {}; return m:anything()";
+        assert!(messages(script).is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use assert_fs::NamedTempFile;
+use assert_fs::{prelude::*, NamedTempFile};
 use snapbox::{
     cmd::{cargo_bin, Command},
     str,
@@ -37,6 +37,376 @@ Error: unexpected character !
 "#]]);
 }
 
+#[test]
+fn check_stdin_type_error() {
+    Command::new(cargo_bin("lmb"))
+        .stdin("return require('@lmb'):gett('a')")
+        .args(["--no-color", "check", "--strict", "--file", "-"])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+Error: gett is not a member of Lmb
+   ,-[-:1:24]
+ 1 |return require('@lmb'):gett('a')
+   |                         `-- gett is not a member of Lmb
+
+"#]]);
+}
+
+#[test]
+fn compile_and_eval() {
+    let out = NamedTempFile::new("hello.luac").unwrap();
+    let out_path = out.path().to_string_lossy();
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--no-color",
+            "compile",
+            "--file",
+            "lua-examples/algebra.lua",
+            "--out",
+            &out_path,
+        ])
+        .assert()
+        .success();
+    Command::new(cargo_bin("lmb"))
+        .stdin("2")
+        .args(["--no-color", "eval", "--precompiled", "--file", &out_path])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+4
+"#]]);
+    // bytecode is never detected without the flag
+    Command::new(cargo_bin("lmb"))
+        .stdin("2")
+        .args(["--no-color", "eval", "--file", &out_path])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn compile_and_eval_error() {
+    let out = NamedTempFile::new("error.luac").unwrap();
+    let out_path = out.path().to_string_lossy();
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--no-color",
+            "compile",
+            "--file",
+            "lua-examples/error.lua",
+            "--out",
+            &out_path,
+        ])
+        .assert()
+        .success();
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--no-color",
+            "--json",
+            "eval",
+            "--precompiled",
+            "--file",
+            &out_path,
+        ])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+{"message":"something went wrong","file":"lua-examples/error.lua","line":4,"column":null,"span":null,"label":null,"severity":"error"}
+
+"#]]);
+}
+
+#[test]
+fn completions() {
+    Command::new(cargo_bin("lmb"))
+        .args(["completions", "bash"])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+_lmb() {
+...
+"#]]);
+}
+
+#[test]
+fn debug() {
+    let script = NamedTempFile::new("debug.lua").unwrap();
+    script
+        .write_str("a = 1\nlocal function f()\n  return a + 1\nend\nreturn f()")
+        .unwrap();
+    let script_path = script.path().to_string_lossy();
+    Command::new(cargo_bin("lmb"))
+        .stdin("p a\nbt\nc\n")
+        .args([
+            "--no-color",
+            "debug",
+            "--file",
+            &script_path,
+            "--break",
+            "3",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+2
+"#]])
+        .stderr_eq(str![[r#"
+stopped at line 3 in f
+>   3 |   return a + 1
+(lmb) a = 1
+(lmb) #0 [..]:3 in f
+#1 [..]:5 in ?
+(lmb) 
+"#]]);
+}
+
+#[test]
+fn doctor() {
+    Command::new(cargo_bin("lmb"))
+        .args(["--json", "doctor"])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[{"name":"lmb","status":"ok","detail":"[..]"},{"name":"luau","status":"ok","detail":"Luau [..]"},{"name":"sqlite","status":"ok","detail":"[..]"},{"name":"bindings","status":"ok","detail":"[..]"},{"name":"sqlite wal","status":"ok","detail":"journal mode is wal"},{"name":"store","status":"skip","detail":"store is in-memory"},{"name":"network","status":"skip","detail":"any host is allowed, specify --allow-net to check hosts"}]
+
+"#]]);
+}
+
+#[test]
+fn eval_profile() {
+    let out = NamedTempFile::new("out.folded").unwrap();
+    let out_path = out.path().to_string_lossy();
+    Command::new(cargo_bin("lmb"))
+        .stdin("2")
+        .args([
+            "--no-color",
+            "eval",
+            "--file",
+            "lua-examples/algebra.lua",
+            "--profile",
+            &out_path,
+        ])
+        .assert()
+        .success();
+    let folded = std::fs::read_to_string(out.path()).unwrap();
+    assert!(folded.starts_with("lua-examples/algebra.lua "), "{folded}");
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn eval_dry_run() {
+    let fixtures = NamedTempFile::new("fixtures.json").unwrap();
+    fixtures
+        .write_str(
+            r#"{"env":{"NAME":"world"},"http":[{"url":"https://example.com/","status":204}]}"#,
+        )
+        .unwrap();
+    Command::new(cargo_bin("lmb"))
+        .stdin(
+            r#"
+            local m = require('@lmb')
+            m:put('greeting', 'hello, ' .. m.env.NAME)
+            local res = require('@lmb/http'):fetch('https://example.com/')
+            return m:get('greeting') .. ' ' .. res.status_code
+            "#,
+        )
+        .args([
+            "--no-color",
+            "eval",
+            "--dry-run",
+            "--dry-run-fixtures",
+            &fixtures.path().to_string_lossy(),
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+hello, world 204
+"#]])
+        .stderr_eq(str![[r#"
+dry run: 3 side effect(s) attempted
+env	NAME
+store	greeting	"hello, world"
+http	https://example.com/	{"method":"GET"}
+
+"#]]);
+}
+
+#[test]
+fn eval_env_file() {
+    let env_file = NamedTempFile::new(".env").unwrap();
+    env_file.write_str("NAME=world\nSECRET=1").unwrap();
+    Command::new(cargo_bin("lmb"))
+        .stdin("local env = require('@lmb').env; return env.NAME .. ' ' .. tostring(env.SECRET)")
+        .args([
+            "--allow-env",
+            "NAME",
+            "--env-file",
+            &env_file.path().to_string_lossy(),
+            "--no-color",
+            "eval",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+world nil
+"#]]);
+}
+
+#[test]
+fn eval_inputs() {
+    let first = NamedTempFile::new("first.txt").unwrap();
+    first.write_str("hello, ").unwrap();
+    let second = NamedTempFile::new("second.txt").unwrap();
+    second.write_str("world").unwrap();
+    Command::new(cargo_bin("lmb"))
+        .stdin("return io.read('*a')")
+        .args([
+            "--no-color",
+            "eval",
+            "--input",
+            &first.path().to_string_lossy(),
+            "--input",
+            &second.path().to_string_lossy(),
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+hello, world
+"#]]);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn eval_input_url_not_allowed() {
+    Command::new(cargo_bin("lmb"))
+        .stdin("return io.read('*a')")
+        .args([
+            "--no-color",
+            "--allow-net",
+            "example.com",
+            "eval",
+            "--input",
+            "http://127.0.0.1:1/",
+        ])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+127.0.0.1:1 is not allowed to connect
+
+"#]]);
+}
+
+#[test]
+fn error_localized() {
+    Command::new(cargo_bin("lmb"))
+        .args(["--no-color", "--locale", "zh-TW"])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+需要腳本或子命令，用法請見 --help
+
+"#]]);
+}
+
+#[test]
+fn eval_execute() {
+    Command::new(cargo_bin("lmb"))
+        .stdin("21")
+        .args([
+            "--no-color",
+            "eval",
+            "-e",
+            "local n = io.read('*n')",
+            "-e",
+            "return n * 2",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+42
+"#]]);
+}
+
+#[test]
+fn eval_i18n() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    dir.child("zh-TW.json")
+        .write_str(r#"{"greeting":"你好，{name}"}"#)
+        .unwrap();
+    Command::new(cargo_bin("lmb"))
+        .args(["--no-color", "--locale", "zh_TW.UTF-8", "--i18n-dir"])
+        .arg(dir.path())
+        .args([
+            "eval",
+            "-e",
+            "return require('@lmb/i18n'):t('greeting', { name = 'lmb' })",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+你好，lmb
+"#]]);
+}
+
+#[test]
+fn eval_named_args() {
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--no-color",
+            "eval",
+            "--arg",
+            "name=world",
+            "-e",
+            "local m = require('@lmb'); return m.args.name .. ' ' .. tostring(m.stdin_is_tty)",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+world false
+"#]]);
+}
+
+#[test]
+fn eval_execute_error() {
+    Command::new(cargo_bin("lmb"))
+        .args(["--no-color", "eval", "-e", "return nil + 1"])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+Error: attempt to perform arithmetic (add) on nil and number
+   ,-[(command line):1:1]
+ 1 |return nil + 1
+   |       `------- attempt to perform arithmetic (add) on nil and number
+
+"#]]);
+}
+
+#[test]
+fn script_without_subcommand() {
+    let script = NamedTempFile::new("script.lua").unwrap();
+    script
+        .write_str("#!/usr/bin/env lmb\nreturn table.concat(require('@lmb').args, ' ')")
+        .unwrap();
+    Command::new(cargo_bin("lmb"))
+        .arg("--no-color")
+        .arg(script.path())
+        .args(["a", "--b"])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+a --b
+"#]]);
+}
+
 #[test]
 fn eval_file() {
     Command::new(cargo_bin("lmb"))
@@ -44,12 +414,43 @@ fn eval_file() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 nullhello, world!
 
 "#]]);
 }
 
+#[test]
+fn eval_pretty_output() {
+    Command::new(cargo_bin("lmb"))
+        .args(["--no-color", "example", "eval", "--name", "return-table"])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+{"bool":true,"num":1.23,"str":"hello"}
+"#]]);
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--no-color",
+            "eval",
+            "--output",
+            "pretty",
+            "--file",
+            "lua-examples/return-table.lua",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+{
+  "bool": true,
+  "num": 1.23,
+  "str": "hello"
+}
+"#]]);
+}
+
 #[test]
 fn eval_json_output() {
     Command::new(cargo_bin("lmb"))
@@ -64,7 +465,7 @@ fn eval_json_output() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 {"bool":true,"num":1.23,"str":"hello"}
 "#]]);
 }
@@ -77,11 +478,65 @@ fn eval_stdin() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 2
 "#]]);
 }
 
+#[test]
+fn eval_stdin_runtime_error_traceback() {
+    Command::new(cargo_bin("lmb"))
+        .stdin("local function f()\n  error('boom')\nend\nf()")
+        .args(["--no-color", "--traceback", "eval", "--file", "-"])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+Error: boom
+   ,-[-:2:1]
+ 2 |  error('boom')
+   |        `-------- boom
+stack traceback:
+  [C]: in ?
+  [C]: in function 'error'
+  -:2: in function 'f'
+  -:4: in ?
+...
+"#]]);
+}
+
+#[test]
+fn eval_max_instructions() {
+    Command::new(cargo_bin("lmb"))
+        .stdin("while true do end")
+        .args([
+            "--no-color",
+            "--max-instructions",
+            "100",
+            "eval",
+            "--file",
+            "-",
+        ])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+instruction budget of 100 exceeded
+
+"#]]);
+}
+
+#[test]
+fn eval_stdin_runtime_error_json() {
+    Command::new(cargo_bin("lmb"))
+        .stdin("print(1)\nprint(nil+1)\nprint(2)")
+        .args(["--no-color", "--json", "eval", "--file", "-"])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+{"message":"attempt to perform arithmetic (add) on nil and number","file":"-","line":2,"column":1,"span":{"start":9,"end":21},"label":"attempt to perform arithmetic (add) on nil and number","severity":"error"}
+
+"#]]);
+}
+
 #[test]
 fn eval_stdin_runtime_error() {
     Command::new(cargo_bin("lmb"))
@@ -132,7 +587,7 @@ fn eval_store_migrate() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 true
 "#]]);
 }
@@ -172,7 +627,7 @@ fn example_eval() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 3798601
 "#]]);
 }
@@ -218,7 +673,7 @@ fn example_serve() {
         ])
         .assert()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 [..]  WARN lmb::serve: no store path is specified, an in-memory store will be used and values will be lost when process ends
 [..]  INFO lmb::serve: serving lua script bind=127.0.0.1:3000
 
@@ -260,6 +715,20 @@ zenburn
 "#]]);
 }
 
+#[test]
+fn man() {
+    Command::new(cargo_bin("lmb"))
+        .arg("man")
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+.TH LMB 1 "" "lmb [..]"
+.SH NAME
+lmb [..] lmb is a Lua function runner
+...
+"#]]);
+}
+
 #[test]
 fn guide_cat() {
     Command::new(cargo_bin("lmb"))
@@ -325,6 +794,47 @@ fn schedule() {
         .stdout_eq(str!["1"]);
 }
 
+#[test]
+fn store_vacuum_verify() {
+    let store = NamedTempFile::new("db.sqlite3").unwrap();
+    let store_path = store.path().to_string_lossy();
+
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--no-color",
+            "--store-path",
+            &store_path,
+            "--run-migrations",
+            "store",
+            "vacuum",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+[..]before[..]after[..]
+ size        [..] bytes[..]
+ wal size    [..] bytes  0 bytes[..]
+ page size   [..] bytes[..]
+ pages       [..]
+ free pages  [..]
+
+"#]]);
+
+    Command::new(cargo_bin("lmb"))
+        .args(["--no-color", "--store-path", &store_path, "store", "verify"])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+ size        [..] bytes[..]
+ wal size    0 bytes[..]
+ page size   [..] bytes[..]
+ pages       [..]
+ free pages  0 (0.00%)[..]
+
+"#]]);
+}
+
 #[test]
 fn serve() {
     Command::new(cargo_bin("lmb"))
@@ -339,13 +849,69 @@ fn serve() {
         .timeout(Duration::from_secs(2))
         .assert()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 [..]  WARN lmb::serve: no store path is specified, an in-memory store will be used and values will be lost when process ends
 [..]  INFO lmb::serve: serving lua script bind=127.0.0.1:3001
 
 "#]]);
 }
 
+#[test]
+fn serve_session_ttl_too_long() {
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--no-color",
+            "serve",
+            "--file",
+            "lua-examples/hello.lua",
+            "--session-ttl",
+            "9223372036854775807",
+        ])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+error: invalid value '9223372036854775807' for '--session-ttl <SESSION_TTL>': 9223372036854775807 is not in 0..=34560000
+
+For more information, try '--help'.
+
+"#]]);
+}
+
+#[cfg(unix)]
+#[test]
+fn signal() {
+    use std::{
+        io::{BufRead as _, BufReader},
+        process::Stdio,
+    };
+
+    let script = r#"
+    local signal = require('@lmb/signal')
+    signal:received('term')
+    io.write('ready\n')
+    while not signal:wait('term', 0.1) do end
+    return 'stopped'
+    "#;
+    let mut child = std::process::Command::new(cargo_bin("lmb"))
+        .args(["--no-color", "eval", "--file", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), script.as_bytes()).unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    assert!(lines.any(|l| l.unwrap() == "ready"));
+    std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert_eq!(
+        Some("stopped".to_string()),
+        lines.next().map(Result::unwrap)
+    );
+    assert!(child.wait().unwrap().success());
+}
+
 #[test]
 fn store_delete() {
     let store = NamedTempFile::new("db.sqlite3").unwrap();
@@ -368,7 +934,7 @@ fn store_delete() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 1
 "#]]);
 
@@ -405,7 +971,7 @@ fn store_get() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 null
 "#]]);
 }
@@ -432,7 +998,7 @@ fn store_get_list_put() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 1
 "#]]);
 
@@ -485,7 +1051,7 @@ fn store_list() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
  name  type  size  created at  updated at 
 
 "#]]);
@@ -506,7 +1072,7 @@ fn store_migrate() {
         .assert()
         .success()
         .stdout_eq(str![[r#"
-[..]  INFO rusqlite_migration: Database migrated to version 1    
+[..]  INFO rusqlite_migration: Database migrated to version 8    
 
 "#]]);
 }
@@ -524,3 +1090,202 @@ fn store_version() {
 
 "#]]);
 }
+
+#[test]
+fn history() {
+    let store = NamedTempFile::new("db.sqlite3").unwrap();
+    let store_path = store.path().to_string_lossy();
+    for script in ["return 1", "error('boom')"] {
+        Command::new(cargo_bin("lmb"))
+            .stdin(script)
+            .args([
+                "--history",
+                "--run-migrations",
+                "--store-path",
+                &store_path,
+                "eval",
+            ])
+            .assert();
+    }
+    Command::new(cargo_bin("lmb"))
+        .args(["--store-path", &store_path, "history", "list"])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+ id  name  started at [..] duration  memory  status 
+ 2   -     [..]  [..]  [..]  error  
+ 1   -     [..]  [..]  [..]  ok     
+
+"#]]);
+    Command::new(cargo_bin("lmb"))
+        .args(["--store-path", &store_path, "history", "show", "--id", "1"])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+ id          1 [..]
+ name        - [..]
+ started at  [..]
+ duration    [..]
+ memory      [..] bytes [..]
+ result      1 [..]
+
+"#]]);
+    Command::new(cargo_bin("lmb"))
+        .args(["--store-path", &store_path, "history", "show", "--id", "3"])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+invocation 3 not found
+
+"#]]);
+}
+
+#[test]
+fn replay() {
+    let store = NamedTempFile::new("db.sqlite3").unwrap();
+    let store_path = store.path().to_string_lossy();
+    let script = NamedTempFile::new("greet.lua").unwrap();
+    script
+        .write_str(
+            r#"
+            local m = require('@lmb')
+            local greeting = io.read('*l') .. ', ' .. m.env.NAME
+            m:put('greeting', greeting)
+            return greeting
+            "#,
+        )
+        .unwrap();
+    let script_path = script.path().to_string_lossy();
+    Command::new(cargo_bin("lmb"))
+        .env("NAME", "world")
+        .stdin("hello")
+        .args([
+            "--no-color",
+            "--allow-env",
+            "NAME",
+            "--history",
+            "--run-migrations",
+            "--store-path",
+            &store_path,
+            "--store-encryption-key",
+            "k1:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+            "eval",
+            "--file",
+            &script_path,
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+[..]  INFO rusqlite_migration: Database migrated to version 8    
+hello, world
+"#]]);
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--store-path",
+            &store_path,
+            "store",
+            "delete",
+            "--name",
+            "greeting",
+        ])
+        .assert()
+        .success();
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--no-color",
+            "--store-path",
+            &store_path,
+            "--store-encryption-key",
+            "k1:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+            "replay",
+            "1",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str![[r#"
+hello, world
+"#]])
+        .stderr_eq(str![[r#"
+dry run: 2 side effect(s) attempted
+env	NAME
+store	greeting	"hello, world"
+
+"#]]);
+    Command::new(cargo_bin("lmb"))
+        .args([
+            "--store-path",
+            &store_path,
+            "store",
+            "get",
+            "--name",
+            "greeting",
+        ])
+        .assert()
+        .success()
+        .stdout_eq(str!["null"]);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn eval_pushgateway() {
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("PUT", "/metrics/job/batch")
+        .match_body("# TYPE jobs_done counter\njobs_done 1\n")
+        .create();
+    Command::new(cargo_bin("lmb"))
+        .stdin("require('@lmb/prometheus'):counter('jobs_done'):inc()")
+        .args([
+            "--no-color",
+            "eval",
+            "--pushgateway",
+            &server.url(),
+            "--pushgateway-job",
+            "batch",
+        ])
+        .assert()
+        .success();
+    mock.assert();
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn eval_store_url_with_encryption() {
+    Command::new(cargo_bin("lmb"))
+        .stdin("return true")
+        .args([
+            "--no-color",
+            "--store-url",
+            "redis://127.0.0.1:1/",
+            "--store-encryption-key",
+            "k1:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+            "eval",
+        ])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+encryption is not supported by the store URL
+
+"#]]);
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn eval_store_url_with_quota() {
+    Command::new(cargo_bin("lmb"))
+        .stdin("return true")
+        .args([
+            "--no-color",
+            "--store-url",
+            "redis://127.0.0.1:1/",
+            "--store-max-size",
+            "1024",
+            "eval",
+        ])
+        .assert()
+        .failure()
+        .stderr_eq(str![[r#"
+quota is not supported by the store URL
+
+"#]]);
+}